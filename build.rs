@@ -0,0 +1,26 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    generate_c_header();
+}
+
+/// Generate the C header for the `ffi` module into `OUT_DIR`; the build never writes
+/// to the source tree, so read-only and vendored builds work. The checked-in
+/// `include/metric_query.h` is refreshed with
+/// `cbindgen --config cbindgen.toml --output include/metric_query.h`.
+#[cfg(feature = "ffi")]
+fn generate_c_header() {
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").expect("cargo sets CARGO_MANIFEST_DIR");
+    let out_dir = std::env::var("OUT_DIR").expect("cargo sets OUT_DIR");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir))
+        .expect("cbindgen.toml is valid");
+
+    cbindgen::Builder::new()
+        .with_config(config)
+        .with_src(format!("{}/src/ffi.rs", crate_dir))
+        .generate()
+        .expect("C header generation failed")
+        .write_to_file(format!("{}/metric_query.h", out_dir));
+}
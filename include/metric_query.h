@@ -0,0 +1,96 @@
+#ifndef METRIC_QUERY_H
+#define METRIC_QUERY_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Status code returned by every fallible FFI function
+typedef enum MqStatus {
+  MQ_STATUS_OK = 0,
+  // A required pointer was null or a string was not valid UTF-8
+  MQ_STATUS_INVALID_ARGUMENT = 1,
+  // The engine rejected the request; see `mq_last_error`
+  MQ_STATUS_FAILED = 2,
+  // A Rust panic was caught at the boundary
+  MQ_STATUS_PANIC = 3,
+} MqStatus;
+
+// Opaque pipeline handle
+typedef struct MqPipeline MqPipeline;
+
+// Result columns of an execution, owned by Rust until `mq_result_free`
+typedef struct MqResult {
+  int64_t *values;
+  int64_t *timestamps;
+  uintptr_t len;
+} MqResult;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create an empty pipeline. Free it with `mq_pipeline_free`.
+struct MqPipeline *mq_pipeline_new(void);
+
+// Free a pipeline. Passing null is a no-op.
+//
+// # Safety
+// `pipeline` must be null or a handle from `mq_pipeline_new` that has not been freed.
+void mq_pipeline_free(struct MqPipeline *pipeline);
+
+// Add a value filter ("gt", "lt", "ge", "le" or "eq") comparing against `value`.
+//
+// # Safety
+// `pipeline` must be a live handle and `filter_type` a NUL-terminated string.
+enum MqStatus mq_pipeline_add_filter(struct MqPipeline *pipeline,
+                                     const char *filter_type,
+                                     int64_t value);
+
+// Add an aggregation ("sum", "avg", "min" or "max") over the whole stream.
+//
+// # Safety
+// `pipeline` must be a live handle and `agg_type` a NUL-terminated string.
+enum MqStatus mq_pipeline_add_aggregation(struct MqPipeline *pipeline, const char *agg_type);
+
+// Group by time ("minute", "hour" or "day") and aggregate each group.
+//
+// # Safety
+// `pipeline` must be a live handle; both names must be NUL-terminated strings.
+enum MqStatus mq_pipeline_add_time_grouping(struct MqPipeline *pipeline,
+                                            const char *time_grouping_type,
+                                            const char *agg_type);
+
+// Execute the pipeline over `len` values and timestamps.
+//
+// On success `out` receives newly allocated columns that must be released with
+// `mq_result_free`; on failure `out` is set to an empty result.
+//
+// # Safety
+// `pipeline` must be a live handle, `values` and `timestamps` must each point to
+// `len` readable elements (or may be null when `len` is 0), and `out` must be writable.
+enum MqStatus mq_pipeline_execute(const struct MqPipeline *pipeline,
+                                  const int64_t *values,
+                                  const int64_t *timestamps,
+                                  uintptr_t len,
+                                  struct MqResult *out);
+
+// Release the columns of a result and reset it to empty. Passing null is a no-op.
+//
+// # Safety
+// `result` must be null or a result filled in by `mq_pipeline_execute`.
+void mq_result_free(struct MqResult *result);
+
+// Message for the last failed call on this thread, or null if it succeeded.
+//
+// The string stays valid until the next FFI call on the same thread.
+const char *mq_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* METRIC_QUERY_H */
@@ -0,0 +1,110 @@
+//! Exact versus sketch-based aggregation.
+//!
+//! Aggregations whose exact form needs every value in memory (percentiles, distinct
+//! counts) can instead run from a sketch: a DDSketch for percentiles and a
+//! HyperLogLog for distinct counts. The choice is made per aggregation with an
+//! `Accuracy`. `Accuracy::Auto` is resolved when a run is planned, from the number of
+//! metrics fed into the pipeline, so one pipeline definition stays exact on small
+//! inputs and bounded in memory on large ones. The mode each step ran with is reported in
+//! `StepStats::accuracy`.
+
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+
+/// Step inputs larger than this make `Accuracy::Auto` approximate
+pub const AUTO_APPROXIMATE_THRESHOLD: usize = 100_000;
+
+/// Relative accuracy of the DDSketch behind approximate percentiles
+pub const PERCENTILE_RELATIVE_ACCURACY: f64 = 0.01;
+
+/// Whether an aggregation computes its result exactly or estimates it from a sketch
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Accuracy {
+    /// Exact result, holding every value of a group in memory
+    #[default]
+    Exact,
+    /// Estimate from a fixed-size sketch
+    Approximate,
+    /// Exact up to `AUTO_APPROXIMATE_THRESHOLD` input metrics, approximate above
+    Auto,
+}
+
+impl Accuracy {
+    /// Parse an accuracy mode name ("exact", "approximate" or "auto")
+    pub fn parse(mode: &str) -> MetricQueryResult<Self> {
+        match mode {
+            "exact" => Ok(Self::Exact),
+            "approximate" => Ok(Self::Approximate),
+            "auto" => Ok(Self::Auto),
+            _ => Err(MetricQueryError::InvalidAggregation {
+                reason: format!("Unknown accuracy mode: {}. Expected one of: exact, approximate, auto", mode),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Exact => "exact",
+            Self::Approximate => "approximate",
+            Self::Auto => "auto",
+        }
+    }
+
+    /// The mode to run with for a step over `input_count` metrics; never `Auto`
+    pub fn resolve(self, input_count: usize) -> Self {
+        match self {
+            Self::Auto if input_count > AUTO_APPROXIMATE_THRESHOLD => Self::Approximate,
+            Self::Auto => Self::Exact,
+            mode => mode,
+        }
+    }
+}
+
+/// Number of index bits of `HyperLogLog`; 2^14 registers give about 0.8% standard error
+const HLL_PRECISION: u32 = 14;
+
+/// A HyperLogLog distinct-value counter using 16 KiB whatever the number of values
+#[derive(Debug, Clone)]
+pub struct HyperLogLog {
+    registers: Vec<u8>,
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self { registers: vec![0; 1 << HLL_PRECISION] }
+    }
+
+    /// Count `value` as seen
+    pub fn add(&mut self, value: impl Hash) {
+        let mut hasher = DefaultHasher::new();
+        value.hash(&mut hasher);
+        let hash = hasher.finish();
+        let index = (hash >> (64 - HLL_PRECISION)) as usize;
+        // Position of the first set bit in the remaining bits, capped when they're all zero
+        let rank = ((hash << HLL_PRECISION) | (1 << (HLL_PRECISION - 1))).leading_zeros() as u8 + 1;
+        self.registers[index] = self.registers[index].max(rank);
+    }
+
+    /// Estimated number of distinct values added
+    pub fn estimate(&self) -> u64 {
+        let m = self.registers.len() as f64;
+        let alpha = 0.7213 / (1.0 + 1.079 / m);
+        let sum: f64 = self.registers.iter().map(|&r| 2f64.powi(-i32::from(r))).sum();
+        let raw = alpha * m * m / sum;
+        let zeros = self.registers.iter().filter(|&&r| r == 0).count();
+        // Small cardinalities are more accurate by linear counting of empty registers
+        if raw <= 2.5 * m && zeros > 0 {
+            (m * (m / zeros as f64).ln()).round() as u64
+        } else {
+            raw.round() as u64
+        }
+    }
+}
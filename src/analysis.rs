@@ -0,0 +1,413 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::decimal::rescale;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::plugin_impls::RoundingMode;
+use crate::settings::Settings;
+
+/// Percentiles reported by `describe` when none are requested
+pub const DEFAULT_PERCENTILES: [f64; 3] = [0.5, 0.9, 0.99];
+
+/// Summary statistics of a metric stream
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricSummary {
+    /// Number of values summarised (missing float values are not counted)
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    /// Sample standard deviation; NaN for fewer than two values
+    pub stddev: f64,
+    /// (quantile, value) pairs in the requested order
+    pub percentiles: Vec<(f64, f64)>,
+    /// Earliest and latest timestamps summarised; None when only values were given
+    pub earliest: Option<i64>,
+    pub latest: Option<i64>,
+}
+
+#[cfg_attr(feature = "python", pymethods)]
+impl MetricSummary {
+    /// Value of a requested quantile, or None if it wasn't computed
+    pub fn percentile(&self, quantile: f64) -> Option<f64> {
+        self.percentiles.iter().find(|(q, _)| *q == quantile).map(|(_, value)| *value)
+    }
+}
+
+/// Quantile `q` in [0, 1] of non-empty sorted values, interpolating between the closest ranks
+pub(crate) fn quantile_of_sorted(sorted: &[f64], q: f64) -> f64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    interpolate(sorted[lower], sorted[upper], rank - lower as f64)
+}
+
+/// `quantile_of_sorted` for integers, exact at the ranks and interpolated in i128,
+/// rounded half away from zero
+pub(crate) fn quantile_of_sorted_ints(sorted: &[i64], q: f64) -> i64 {
+    let rank = q * (sorted.len() - 1) as f64;
+    let (lower, upper) = (rank.floor() as usize, rank.ceil() as usize);
+    interpolate_ints(sorted[lower], sorted[upper], rank - lower as f64)
+}
+
+/// The value `fraction` of the way from `lower` to `upper`
+pub(crate) fn interpolate(lower: f64, upper: f64, fraction: f64) -> f64 {
+    lower + (upper - lower) * fraction
+}
+
+/// `interpolate` for integers, computed in i128 and rounded half away from zero
+pub(crate) fn interpolate_ints(lower: i64, upper: i64, fraction: f64) -> i64 {
+    let gap = i128::from(upper) - i128::from(lower);
+    // Lies between the two values, so it fits in an i64
+    (i128::from(lower) + (gap as f64 * fraction).round() as i128) as i64
+}
+
+/// Compute summary statistics, with quantiles in [0, 1] interpolated linearly between
+/// the closest ranks. Missing (NaN) values are ignored.
+pub fn describe(values: &[f64], quantiles: &[f64]) -> MetricQueryResult<MetricSummary> {
+    if let Some(quantile) = quantiles.iter().find(|q| !(0.0..=1.0).contains(*q)) {
+        return Err(MetricQueryError::OperationFailed {
+            operation: "describe".to_string(),
+            reason: format!("Percentiles must be between 0 and 1, got {}", quantile),
+        });
+    }
+
+    let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+    if sorted.is_empty() {
+        return Err(MetricQueryError::EmptyMetricStream);
+    }
+    sorted.sort_by(f64::total_cmp);
+
+    let count = sorted.len();
+    let mean = sorted.iter().sum::<f64>() / count as f64;
+    let stddev = if count < 2 {
+        f64::NAN
+    } else {
+        (sorted.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (count - 1) as f64).sqrt()
+    };
+
+    let percentiles = quantiles.iter().map(|&q| (q, quantile_of_sorted(&sorted, q))).collect();
+
+    Ok(MetricSummary {
+        count,
+        min: sorted[0],
+        max: sorted[count - 1],
+        mean,
+        stddev,
+        percentiles,
+        earliest: None,
+        latest: None,
+    })
+}
+
+/// Summary statistics of metrics, like `describe`, plus their earliest and latest
+/// timestamps. Decimal metrics are summarised by their scaled value; staleness
+/// markers are skipped.
+pub fn describe_metrics(metrics: &[Metric], quantiles: &[f64]) -> MetricQueryResult<MetricSummary> {
+    let live: Vec<&Metric> = metrics.iter().filter(|m| !m.stale).collect();
+    let values: Vec<f64> = live.iter().map(|m| m.as_f64()).collect();
+    let mut summary = describe(&values, quantiles)?;
+    summary.earliest = live.iter().map(|m| m.timestamp).min();
+    summary.latest = live.iter().map(|m| m.timestamp).max();
+    Ok(summary)
+}
+
+/// Count, min, max, mean, stddev, percentiles and earliest/latest timestamps of
+/// `metrics`, without building a pipeline
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "describe", signature = (metrics, percentiles=None))]
+pub fn py_describe(metrics: Vec<Metric>, percentiles: Option<Vec<f64>>) -> PyResult<MetricSummary> {
+    let percentiles = percentiles.unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec());
+    Ok(describe_metrics(&metrics, &percentiles)?)
+}
+
+/// Headline numbers of a pipeline result, returned in place of the per-metric output
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResultSummary {
+    /// Number of samples; staleness markers are not counted
+    pub count: usize,
+    /// Sum, min and max are whole units of `scale` decimal places, like `Metric::value`
+    pub sum: i64,
+    /// None when the result is empty
+    pub min: Option<i64>,
+    pub max: Option<i64>,
+    /// Mean of the decimal values, with the scale undone
+    pub mean: Option<f64>,
+    /// Largest scale among the summarised metrics, to which the others are brought;
+    /// None when none of them is fixed-point
+    pub scale: Option<u32>,
+}
+
+impl ResultSummary {
+    /// Summarise metric values in a single pass; an empty slice gives a zero count
+    pub fn of(metrics: &[Metric]) -> MetricQueryResult<Self> {
+        let mut builder = SummaryBuilder::default();
+        metrics.iter().try_for_each(|metric| builder.push(metric))?;
+        Ok(builder.finish())
+    }
+}
+
+/// Builds a `ResultSummary` one metric at a time
+#[derive(Debug, Default)]
+pub(crate) struct SummaryBuilder {
+    count: usize,
+    sum: i64,
+    bounds: Option<(i64, i64)>,
+    scale: Option<u32>,
+}
+
+impl SummaryBuilder {
+    /// Add a metric, skipping staleness markers. A metric with a larger scale than
+    /// those seen so far rescales the running totals, as `align_scales` would.
+    pub(crate) fn push(&mut self, metric: &Metric) -> MetricQueryResult<()> {
+        if metric.stale {
+            return Ok(());
+        }
+        let scale = self.scale.max(metric.scale);
+        let to = scale.unwrap_or(0);
+        if scale != self.scale {
+            let from = self.scale.unwrap_or(0);
+            self.sum = rescale(self.sum, from, to, RoundingMode::Truncate)?;
+            if let Some((min, max)) = self.bounds {
+                let min = rescale(min, from, to, RoundingMode::Truncate)?;
+                self.bounds = Some((min, rescale(max, from, to, RoundingMode::Truncate)?));
+            }
+            self.scale = scale;
+        }
+        let value = rescale(metric.value, metric.scale.unwrap_or(0), to, RoundingMode::Truncate)?;
+
+        self.sum = self.sum.checked_add(value).ok_or_else(|| MetricQueryError::ArithmeticOverflow {
+            operation: "summary".to_string(),
+        })?;
+        self.bounds = Some(match self.bounds {
+            Some((min, max)) => (min.min(value), max.max(value)),
+            None => (value, value),
+        });
+        self.count += 1;
+        Ok(())
+    }
+
+    pub(crate) fn finish(self) -> ResultSummary {
+        let unit = 10_f64.powi(self.scale.unwrap_or(0) as i32);
+        ResultSummary {
+            count: self.count,
+            sum: self.sum,
+            min: self.bounds.map(|(min, _)| min),
+            max: self.bounds.map(|(_, max)| max),
+            mean: (self.count > 0).then(|| self.sum as f64 / unit / self.count as f64),
+            scale: self.scale,
+        }
+    }
+}
+
+/// Correlation coefficient to compute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CorrelationMethod {
+    /// Linear correlation of the raw values
+    #[default]
+    Pearson,
+    /// Linear correlation of the value ranks, robust to monotonic non-linear relationships
+    Spearman,
+}
+
+impl CorrelationMethod {
+    /// Parse a method name ("pearson" or "spearman")
+    pub fn parse(method: &str) -> MetricQueryResult<Self> {
+        match method {
+            "pearson" => Ok(Self::Pearson),
+            "spearman" => Ok(Self::Spearman),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "correlate".to_string(),
+                reason: format!("Unknown correlation method: {}. Expected 'pearson' or 'spearman'", method),
+            }),
+        }
+    }
+
+    /// Correlation of two equally long samples; NaN when either sample is constant
+    fn coefficient(self, xs: &[f64], ys: &[f64]) -> f64 {
+        match self {
+            Self::Pearson => pearson(xs, ys),
+            Self::Spearman => pearson(&ranks(xs), &ranks(ys)),
+        }
+    }
+}
+
+fn pearson(xs: &[f64], ys: &[f64]) -> f64 {
+    let n = xs.len() as f64;
+    let mean_x = xs.iter().sum::<f64>() / n;
+    let mean_y = ys.iter().sum::<f64>() / n;
+
+    let (mut covariance, mut variance_x, mut variance_y) = (0.0, 0.0, 0.0);
+    for (x, y) in xs.iter().zip(ys) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance_x += (x - mean_x).powi(2);
+        variance_y += (y - mean_y).powi(2);
+    }
+    covariance / (variance_x * variance_y).sqrt()
+}
+
+/// 1-based ranks, with ties sharing their average rank
+fn ranks(values: &[f64]) -> Vec<f64> {
+    let mut order: Vec<usize> = (0..values.len()).collect();
+    order.sort_by(|&a, &b| values[a].total_cmp(&values[b]));
+
+    let mut ranks = vec![0.0; values.len()];
+    let mut start = 0;
+    while start < order.len() {
+        let mut end = start + 1;
+        while end < order.len() && values[order[end]] == values[order[start]] {
+            end += 1;
+        }
+        let rank = (start + end + 1) as f64 / 2.0;
+        for &index in &order[start..end] {
+            ranks[index] = rank;
+        }
+        start = end;
+    }
+    ranks
+}
+
+/// Average the values of one labeled series per bucket of `bucket_ticks` timestamp
+/// units, skipping staleness markers
+fn bucket_series(metrics: &[Metric], label: &str, bucket_ticks: i64) -> MetricQueryResult<BTreeMap<i64, f64>> {
+    let mut buckets: BTreeMap<i64, (f64, usize)> = BTreeMap::new();
+    for metric in metrics.iter().filter(|m| !m.stale && m.label.as_deref() == Some(label)) {
+        // Near i64::MIN the bucket's start can lie below the representable range
+        let bucket = metric
+            .timestamp
+            .checked_sub(metric.timestamp.rem_euclid(bucket_ticks))
+            .ok_or_else(|| MetricQueryError::ArithmeticOverflow { operation: "correlate".to_string() })?;
+        let entry = buckets.entry(bucket).or_insert((0.0, 0));
+        entry.0 += metric.as_f64();
+        entry.1 += 1;
+    }
+    Ok(buckets.into_iter().map(|(bucket, (sum, count))| (bucket, sum / count as f64)).collect())
+}
+
+/// Pair up the two labeled series on the buckets where both have data
+fn align(
+    metrics: &[Metric],
+    label_a: &str,
+    label_b: &str,
+    bucket_seconds: i64,
+) -> MetricQueryResult<(Vec<i64>, Vec<f64>, Vec<f64>)> {
+    if bucket_seconds <= 0 {
+        return Err(MetricQueryError::OperationFailed {
+            operation: "correlate".to_string(),
+            reason: format!("Bucket size must be positive, got {}", bucket_seconds),
+        });
+    }
+
+    let bucket_ticks = Settings::current().timestamp_precision.from_seconds(bucket_seconds)?;
+    let a = bucket_series(metrics, label_a, bucket_ticks)?;
+    let b = bucket_series(metrics, label_b, bucket_ticks)?;
+
+    let mut buckets = Vec::new();
+    let mut xs = Vec::new();
+    let mut ys = Vec::new();
+    for (bucket, x) in &a {
+        if let Some(y) = b.get(bucket) {
+            buckets.push(*bucket);
+            xs.push(*x);
+            ys.push(*y);
+        }
+    }
+
+    if xs.len() < 2 {
+        return Err(MetricQueryError::OperationFailed {
+            operation: "correlate".to_string(),
+            reason: format!(
+                "Series '{}' and '{}' share {} bucket(s); at least two are needed",
+                label_a,
+                label_b,
+                xs.len()
+            ),
+        });
+    }
+    Ok((buckets, xs, ys))
+}
+
+/// Correlation between two labeled series, aligned by timestamp bucket.
+///
+/// Values falling in the same bucket are averaged first; buckets missing from
+/// either series are ignored, as are staleness markers. `bucket_seconds` is
+/// converted to the active settings' timestamp precision. The result is NaN when
+/// either series is constant.
+pub fn correlation(
+    metrics: &[Metric],
+    label_a: &str,
+    label_b: &str,
+    method: CorrelationMethod,
+    bucket_seconds: i64,
+) -> MetricQueryResult<f64> {
+    let (_, xs, ys) = align(metrics, label_a, label_b, bucket_seconds)?;
+    Ok(method.coefficient(&xs, &ys))
+}
+
+/// Correlation over a sliding window of `window` aligned buckets.
+///
+/// Each output metric is stamped with the last bucket of its window and labeled
+/// "correlation".
+pub fn rolling_correlation(
+    metrics: &[Metric],
+    label_a: &str,
+    label_b: &str,
+    method: CorrelationMethod,
+    bucket_seconds: i64,
+    window: usize,
+) -> MetricQueryResult<Vec<FloatMetric>> {
+    if window < 2 {
+        return Err(MetricQueryError::OperationFailed {
+            operation: "correlate".to_string(),
+            reason: format!("Window must cover at least 2 buckets, got {}", window),
+        });
+    }
+
+    let (buckets, xs, ys) = align(metrics, label_a, label_b, bucket_seconds)?;
+    Ok((window..=xs.len())
+        .map(|end| FloatMetric {
+            value: method.coefficient(&xs[end - window..end], &ys[end - window..end]),
+            timestamp: buckets[end - 1],
+            label: Some("correlation".to_string()),
+            unit: None,
+        })
+        .collect())
+}
+
+/// Correlation between the series labeled `label_a` and `label_b`
+///
+/// Metrics are aligned into `bucket_seconds` buckets (1 = exact timestamps) and
+/// `method` is "pearson" or "spearman".
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "correlate", signature = (metrics, label_a, label_b, method="pearson", bucket_seconds=1))]
+pub fn py_correlate(
+    metrics: Vec<Metric>,
+    label_a: &str,
+    label_b: &str,
+    method: &str,
+    bucket_seconds: i64,
+) -> PyResult<f64> {
+    Ok(correlation(&metrics, label_a, label_b, CorrelationMethod::parse(method)?, bucket_seconds)?)
+}
+
+/// Rolling correlation between two labeled series over `window` aligned buckets
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "rolling_correlation", signature = (metrics, label_a, label_b, window, method="pearson", bucket_seconds=1))]
+pub fn py_rolling_correlation(
+    metrics: Vec<Metric>,
+    label_a: &str,
+    label_b: &str,
+    window: usize,
+    method: &str,
+    bucket_seconds: i64,
+) -> PyResult<Vec<FloatMetric>> {
+    let method = CorrelationMethod::parse(method)?;
+    Ok(rolling_correlation(&metrics, label_a, label_b, method, bucket_seconds, window)?)
+}
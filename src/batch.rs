@@ -0,0 +1,150 @@
+//! Running many pipelines over the same input in one pass.
+//!
+//! A dashboard page typically fires a set of related queries over identical data.
+//! `BatchExecutor` takes the input once (from Python, it is converted once rather than
+//! per query), optionally runs a shared stage of steps common to every query, such as
+//! the filters selecting the data, and feeds its output to each pipeline. With the
+//! `rayon` feature and `Settings::parallel`, the pipelines run concurrently, except
+//! those with steps that call into Python, which stay on the calling thread.
+//!
+//! Each pipeline's result is reported separately, so one failing query doesn't
+//! discard the others; only a failure of the shared stage fails the whole batch.
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use pyo3::types::PyList;
+
+use crate::cache::RunOutput;
+use crate::compiled::CompiledPipeline;
+use crate::errors::MetricQueryResult;
+use crate::models::Metric;
+#[cfg(feature = "rayon")]
+use crate::settings::Settings;
+use crate::transformations::MetricPipeline;
+#[cfg(feature = "python")]
+use crate::warnings::emit_python_warnings;
+
+/// A set of pipelines executed together over one input
+#[cfg_attr(feature = "python", pyclass(frozen))]
+#[derive(Clone)]
+pub struct BatchExecutor {
+    shared: Option<CompiledPipeline>,
+    pipelines: Vec<CompiledPipeline>,
+}
+
+impl BatchExecutor {
+    /// Execute `pipelines` over each batch; their own input metrics are ignored
+    pub fn new(pipelines: Vec<CompiledPipeline>) -> Self {
+        Self { shared: None, pipelines }
+    }
+
+    /// Run the steps of `shared` once per batch and give its output to every pipeline
+    /// instead of the raw input; its own input metrics are ignored
+    pub fn with_shared_steps(mut self, shared: &MetricPipeline) -> Self {
+        self.shared = Some(CompiledPipeline::new(shared));
+        self
+    }
+
+    /// Number of pipelines in the batch
+    pub fn len(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pipelines.is_empty()
+    }
+
+    /// Execute every pipeline over `metrics`, returning one result per pipeline in order.
+    ///
+    /// Warnings from the shared stage are included in every pipeline's output. Fails
+    /// only when the shared stage does. Thread-bound pipelines run on the calling thread
+    /// after the others.
+    pub fn run(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<MetricQueryResult<RunOutput>>> {
+        let shared = match &self.shared {
+            Some(shared) => Some(shared.run_metrics_with_warnings(metrics)?),
+            None => None,
+        };
+        let (input, shared_warnings) = match &shared {
+            Some((output, warnings)) => (output.as_slice(), warnings.as_slice()),
+            None => (metrics, &[][..]),
+        };
+
+        let run_one = |pipeline: &CompiledPipeline| {
+            pipeline.run_metrics_with_warnings(input).map(|(result, warnings)| {
+                (result, shared_warnings.iter().cloned().chain(warnings).collect())
+            })
+        };
+
+        #[cfg(feature = "rayon")]
+        if self.pipelines.len() > 1 && Settings::current().parallel {
+            use rayon::prelude::*;
+            let parallel: Vec<Option<MetricQueryResult<RunOutput>>> = self
+                .pipelines
+                .par_iter()
+                .map(|pipeline| (!pipeline.thread_bound()).then(|| run_one(pipeline)))
+                .collect();
+            return Ok(self
+                .pipelines
+                .iter()
+                .zip(parallel)
+                .map(|(pipeline, result)| result.unwrap_or_else(|| run_one(pipeline)))
+                .collect());
+        }
+
+        Ok(self.pipelines.iter().map(run_one).collect())
+    }
+}
+
+#[cfg(feature = "python")]
+fn compile_any(pipeline: &Bound<'_, PyAny>) -> PyResult<CompiledPipeline> {
+    match pipeline.downcast::<CompiledPipeline>() {
+        Ok(compiled) => Ok(compiled.get().clone()),
+        Err(_) => Ok(CompiledPipeline::new(&*pipeline.extract::<PyRef<MetricPipeline>>()?)),
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl BatchExecutor {
+    /// Batch `pipelines` (`MetricPipeline` or `CompiledPipeline`), snapshotting their
+    /// steps; `shared` is a pipeline whose steps run once before all of them
+    #[new]
+    #[pyo3(signature = (pipelines, shared=None))]
+    fn py_new(pipelines: Vec<Bound<'_, PyAny>>, shared: Option<PyRef<'_, MetricPipeline>>) -> PyResult<Self> {
+        let pipelines = pipelines.iter().map(compile_any).collect::<PyResult<_>>()?;
+        let executor = Self::new(pipelines);
+        Ok(match shared {
+            Some(shared) => executor.with_shared_steps(&shared),
+            None => executor,
+        })
+    }
+
+    /// Execute every pipeline over `metrics` and return their results as a list, in order.
+    ///
+    /// The GIL is released while the pipelines run. A failing pipeline raises its error,
+    /// or with `return_exceptions=True` appears in the list as the exception object, like
+    /// `asyncio.gather`. Non-fatal issues are reported as `MetricQueryWarning`.
+    #[pyo3(signature = (metrics, return_exceptions=false))]
+    fn execute<'py>(&self, py: Python<'py>, metrics: Vec<Metric>, return_exceptions: bool) -> PyResult<Bound<'py, PyList>> {
+        let results = py.allow_threads(|| self.run(&metrics))?;
+        let list = PyList::empty(py);
+        for result in results {
+            match result {
+                Ok((result, warnings)) => {
+                    if !warnings.is_empty() {
+                        emit_python_warnings(py, &warnings)?;
+                    }
+                    list.append(result)?;
+                }
+                Err(e) if return_exceptions => list.append(PyErr::from(e).into_value(py))?,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(list)
+    }
+
+    fn __len__(&self) -> usize {
+        self.len()
+    }
+}
@@ -0,0 +1,37 @@
+//! `metric-query-server`: serve `POST /query` over HTTP.
+//!
+//! ```text
+//! metric-query-server --addr 0.0.0.0:8080
+//! ```
+
+use std::process::ExitCode;
+
+use clap::Parser;
+use metric_query_library::server::serve;
+
+#[derive(Parser)]
+#[command(name = "metric-query-server", version, about = "Serve metric pipeline queries over HTTP")]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+    /// Number of worker threads; defaults to the number of CPUs
+    #[arg(long)]
+    workers: Option<usize>,
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let workers = args
+        .workers
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+
+    eprintln!("metric-query-server: listening on {}", args.addr);
+    match serve(&args.addr, workers) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("metric-query-server: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
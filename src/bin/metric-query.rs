@@ -0,0 +1,111 @@
+//! `metric-query`: run a JSON pipeline spec over a metrics file.
+//!
+//! ```text
+//! metric-query --spec pipeline.json --input metrics.csv --output result.ndjson
+//! cat metrics.ndjson | metric-query --spec pipeline.json --input-format ndjson
+//! ```
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::Parser;
+use metric_query_library::errors::{MetricQueryError, MetricQueryResult};
+use metric_query_library::io::{read_csv, read_ndjson, write_csv, write_ndjson, Format};
+use metric_query_library::models::FloatMetric;
+use metric_query_library::spec::PipelineSpec;
+
+#[derive(Parser)]
+#[command(name = "metric-query", version, about = "Run a metric pipeline spec over CSV, NDJSON or Parquet files")]
+struct Args {
+    /// JSON pipeline spec
+    #[arg(long)]
+    spec: PathBuf,
+    /// Input file; reads stdin when omitted
+    #[arg(long, short)]
+    input: Option<PathBuf>,
+    /// Input format (csv, ndjson, parquet); inferred from the input extension, else csv
+    #[arg(long)]
+    input_format: Option<String>,
+    /// Output file; writes stdout when omitted
+    #[arg(long, short)]
+    output: Option<PathBuf>,
+    /// Output format (csv, ndjson); inferred from the output extension, else csv
+    #[arg(long)]
+    output_format: Option<String>,
+}
+
+fn main() -> ExitCode {
+    match run(Args::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("metric-query: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn resolve_format(explicit: Option<&str>, path: Option<&PathBuf>) -> MetricQueryResult<Format> {
+    match explicit {
+        Some(format) => Format::parse(format),
+        None => Ok(path.and_then(|p| Format::from_path(p)).unwrap_or(Format::Csv)),
+    }
+}
+
+fn io_error(path: &std::path::Path, e: io::Error) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: "open".to_string(), reason: format!("{}: {}", path.display(), e) }
+}
+
+fn read_input(args: &Args) -> MetricQueryResult<Vec<FloatMetric>> {
+    let format = resolve_format(args.input_format.as_deref(), args.input.as_ref())?;
+    let Some(path) = &args.input else {
+        return match format {
+            Format::Csv => read_csv(io::stdin().lock()),
+            Format::Ndjson => read_ndjson(io::stdin().lock()),
+            Format::Parquet => Err(MetricQueryError::OperationFailed {
+                operation: "read_parquet".to_string(),
+                reason: "Parquet input must be a file, not stdin".to_string(),
+            }),
+        };
+    };
+
+    let file = File::open(path).map_err(|e| io_error(path, e))?;
+    match format {
+        Format::Csv => read_csv(BufReader::new(file)),
+        Format::Ndjson => read_ndjson(BufReader::new(file)),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => metric_query_library::io::read_parquet(file),
+        #[cfg(not(feature = "parquet"))]
+        Format::Parquet => Err(MetricQueryError::OperationFailed {
+            operation: "read_parquet".to_string(),
+            reason: "Parquet support requires building with the `parquet` feature".to_string(),
+        }),
+    }
+}
+
+fn write_output(args: &Args, metrics: &[FloatMetric]) -> MetricQueryResult<()> {
+    let format = resolve_format(args.output_format.as_deref(), args.output.as_ref())?;
+    let writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(File::create(path).map_err(|e| io_error(path, e))?),
+        None => Box::new(io::stdout().lock()),
+    };
+    let writer = BufWriter::new(writer);
+
+    match format {
+        Format::Csv => write_csv(writer, metrics),
+        Format::Ndjson => write_ndjson(writer, metrics),
+        Format::Parquet => Err(MetricQueryError::OperationFailed {
+            operation: "write".to_string(),
+            reason: "Parquet is supported for input only".to_string(),
+        }),
+    }
+}
+
+fn run(args: Args) -> MetricQueryResult<()> {
+    let spec = std::fs::read_to_string(&args.spec).map_err(|e| io_error(&args.spec, e))?;
+    let pipeline = PipelineSpec::from_json(&spec)?.build(Vec::new())?;
+    let metrics = read_input(&args)?;
+    let result = pipeline.execute_float_metrics(&metrics)?;
+    write_output(&args, &result)
+}
@@ -0,0 +1,187 @@
+//! Memoized pipeline results for callers that repeat identical queries.
+//!
+//! Entries are keyed by a compiled pipeline's id and a fingerprint of the input
+//! metrics, and expire after a fixed time to live. Step configuration isn't
+//! hashable in general (plugins and callbacks are opaque), so the id identifies
+//! one `CompiledPipeline`: keep the compiled pipeline around between refreshes
+//! rather than compiling an identical one each time. A compiled pipeline also
+//! freezes the settings it runs with, so the id covers those too.
+//!
+//! Each entry keeps its input, and a hit only counts when the input is equal, so
+//! a fingerprint collision runs the pipeline instead of returning another input's
+//! result.
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::errors::MetricQueryResult;
+use crate::models::{Exemplar, Metric};
+use crate::warnings::PipelineWarning;
+
+/// Default number of results a cache keeps
+pub const DEFAULT_MAX_ENTRIES: usize = 128;
+
+/// Result of a run: output metrics and non-fatal warnings
+pub type RunOutput = (Vec<Metric>, Vec<PipelineWarning>);
+
+/// Hash of every field of every metric, in order, identifying an input batch.
+///
+/// The metrics are destructured without `..`, so a field added to `Metric` or
+/// `Exemplar` doesn't compile until it is hashed here too.
+pub fn fingerprint(metrics: &[Metric]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    metrics.len().hash(&mut hasher);
+    for metric in metrics {
+        let Metric { value, timestamp, label, exemplar, fields, unit, metric_type, scale, source, description, stale, tags } =
+            metric;
+        (value, timestamp, label, fields, unit, metric_type, scale).hash(&mut hasher);
+        (source, description, stale, tags).hash(&mut hasher);
+        match exemplar {
+            Some(Exemplar { trace_id, value, timestamp }) => {
+                true.hash(&mut hasher);
+                (trace_id, value.to_bits(), timestamp).hash(&mut hasher);
+            }
+            None => false.hash(&mut hasher),
+        }
+    }
+    hasher.finish()
+}
+
+struct CacheEntry {
+    stored_at: Instant,
+    input: Arc<[Metric]>,
+    output: RunOutput,
+}
+
+/// Results of recent runs, keyed by (pipeline id, input fingerprint), expiring after `ttl`.
+///
+/// Safe to share between threads. The lock isn't held while a pipeline runs, so two
+/// concurrent misses on the same key both compute the result.
+#[cfg_attr(feature = "python", pyclass(frozen))]
+pub struct ResultCache {
+    ttl: Duration,
+    max_entries: usize,
+    entries: Mutex<HashMap<(u64, u64), CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResultCache {
+    /// Create a cache keeping at most `max_entries` results for `ttl` each
+    pub fn new(ttl: Duration, max_entries: usize) -> Self {
+        Self {
+            ttl,
+            max_entries,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<(u64, u64), CacheEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The memoized output for `key` if it was computed from `input`, or the output of
+    /// `run`, which is stored if it succeeds
+    pub fn get_or_run(
+        &self,
+        key: (u64, u64),
+        input: &[Metric],
+        run: impl FnOnce() -> MetricQueryResult<RunOutput>,
+    ) -> MetricQueryResult<RunOutput> {
+        {
+            let mut entries = self.entries();
+            match entries.get(&key) {
+                Some(entry) if entry.stored_at.elapsed() < self.ttl && *entry.input == *input => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(entry.output.clone());
+                }
+                Some(_) => {
+                    entries.remove(&key);
+                }
+                None => {}
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let output = run()?;
+        if self.max_entries > 0 {
+            let mut entries = self.entries();
+            entries.retain(|_, entry| entry.stored_at.elapsed() < self.ttl);
+            if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+                let oldest = entries.iter().min_by_key(|(_, entry)| entry.stored_at).map(|(&key, _)| key);
+                if let Some(oldest) = oldest {
+                    entries.remove(&oldest);
+                }
+            }
+            entries.insert(key, CacheEntry { stored_at: Instant::now(), input: input.into(), output: output.clone() });
+        }
+        Ok(output)
+    }
+
+    /// Number of stored results, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.entries().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().is_empty()
+    }
+
+    /// Drop every stored result; hit and miss counts are kept
+    pub fn clear(&self) {
+        self.entries().clear();
+    }
+
+    /// Lookups answered from the cache
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Lookups that ran the pipeline
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl ResultCache {
+    /// Create a cache keeping at most `max_entries` results for `ttl_seconds` each
+    #[new]
+    #[pyo3(signature = (ttl_seconds, max_entries=DEFAULT_MAX_ENTRIES))]
+    fn py_new(ttl_seconds: f64, max_entries: usize) -> PyResult<Self> {
+        let ttl = Duration::try_from_secs_f64(ttl_seconds)
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("Invalid ttl_seconds: {}", e)))?;
+        Ok(Self::new(ttl, max_entries))
+    }
+
+    /// Lookups answered from the cache
+    #[getter(hits)]
+    fn py_hits(&self) -> u64 {
+        self.hits()
+    }
+
+    /// Lookups that ran the pipeline
+    #[getter(misses)]
+    fn py_misses(&self) -> u64 {
+        self.misses()
+    }
+
+    /// Drop every stored result
+    #[pyo3(name = "clear")]
+    fn py_clear(&self) {
+        self.clear();
+    }
+
+    fn __len__(&self) -> usize {
+        self.len()
+    }
+}
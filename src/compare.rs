@@ -0,0 +1,169 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+use crate::models::FloatMetric;
+#[cfg(feature = "python")]
+use crate::models::Metric;
+
+/// A point present in both result sets whose values differ by more than the tolerance
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValueMismatch {
+    pub label: Option<String>,
+    /// Timestamp in the expected result
+    pub timestamp: i64,
+    /// Timestamp of the matched point in the actual result
+    pub actual_timestamp: i64,
+    pub expected: f64,
+    pub actual: f64,
+}
+
+/// Differences between an expected and an actual result set
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResultDiff {
+    /// Expected points with no counterpart in the actual result
+    pub missing: Vec<FloatMetric>,
+    /// Actual points with no counterpart in the expected result
+    pub unexpected: Vec<FloatMetric>,
+    /// Matched points whose values differ
+    pub mismatched: Vec<ValueMismatch>,
+}
+
+impl ResultDiff {
+    /// Whether the result sets matched within the tolerances
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Whether two values agree within `tolerance`; two missing values agree
+fn values_match(expected: f64, actual: f64, tolerance: f64) -> bool {
+    (expected.is_nan() && actual.is_nan()) || (expected - actual).abs() <= tolerance
+}
+
+/// Compare an expected result set with an actual one, ignoring order.
+///
+/// Points are matched per label: each expected point pairs with the closest unmatched
+/// actual point whose timestamp is within `ts_tolerance` seconds. Matched points whose
+/// values differ by more than `value_tolerance` are reported as mismatches.
+pub fn compare_results(
+    expected: &[FloatMetric],
+    actual: &[FloatMetric],
+    value_tolerance: f64,
+    ts_tolerance: i64,
+) -> ResultDiff {
+    // Actual points per label, sorted by timestamp: (timestamp, index, matched)
+    let mut candidates: HashMap<Option<&str>, Vec<(i64, usize, bool)>> = HashMap::new();
+    for (index, metric) in actual.iter().enumerate() {
+        candidates.entry(metric.label.as_deref()).or_default().push((metric.timestamp, index, false));
+    }
+    for series in candidates.values_mut() {
+        series.sort_unstable();
+    }
+
+    let mut order: Vec<&FloatMetric> = expected.iter().collect();
+    order.sort_by(|a, b| (&a.label, a.timestamp).cmp(&(&b.label, b.timestamp)));
+
+    let mut diff = ResultDiff::default();
+    for metric in order {
+        let series = candidates.get_mut(&metric.label.as_deref());
+        let closest = series.as_ref().and_then(|series| {
+            let lower = metric.timestamp.saturating_sub(ts_tolerance);
+            let upper = metric.timestamp.saturating_add(ts_tolerance);
+            let start = series.partition_point(|&(timestamp, _, _)| timestamp < lower);
+            series[start..]
+                .iter()
+                .enumerate()
+                .take_while(|(_, &(timestamp, _, _))| timestamp <= upper)
+                .filter(|(_, &(_, _, matched))| !matched)
+                .min_by_key(|(_, &(timestamp, _, _))| (i128::from(timestamp) - i128::from(metric.timestamp)).abs())
+                .map(|(offset, _)| start + offset)
+        });
+
+        match (series, closest) {
+            (Some(series), Some(position)) => {
+                series[position].2 = true;
+                let other = &actual[series[position].1];
+                if !values_match(metric.value, other.value, value_tolerance) {
+                    diff.mismatched.push(ValueMismatch {
+                        label: metric.label.clone(),
+                        timestamp: metric.timestamp,
+                        actual_timestamp: other.timestamp,
+                        expected: metric.value,
+                        actual: other.value,
+                    });
+                }
+            }
+            _ => diff.missing.push(metric.clone()),
+        }
+    }
+
+    let mut unexpected: Vec<usize> = candidates
+        .values()
+        .flatten()
+        .filter(|&&(_, _, matched)| !matched)
+        .map(|&(_, index, _)| index)
+        .collect();
+    unexpected.sort_unstable();
+    diff.unexpected = unexpected.into_iter().map(|index| actual[index].clone()).collect();
+    diff
+}
+
+/// Read a list of `Metric` or `FloatMetric` objects as float metrics
+#[cfg(feature = "python")]
+pub(crate) fn extract_result_set(metrics: &Bound<'_, PyAny>) -> PyResult<Vec<FloatMetric>> {
+    metrics
+        .try_iter()?
+        .map(|item| {
+            let item = item?;
+            match item.extract::<FloatMetric>() {
+                Ok(metric) => Ok(metric),
+                Err(_) => Ok(FloatMetric::from(&item.extract::<Metric>()?)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl ResultDiff {
+    /// Whether the result sets matched within the tolerances
+    #[pyo3(name = "is_empty")]
+    fn py_is_empty(&self) -> bool {
+        self.is_empty()
+    }
+
+    fn __bool__(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ResultDiff(missing={}, unexpected={}, mismatched={})",
+            self.missing.len(),
+            self.unexpected.len(),
+            self.mismatched.len()
+        )
+    }
+}
+
+/// Compare two result sets (lists of `Metric` or `FloatMetric`) and return a `ResultDiff`;
+/// the diff is falsy when they match
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "compare_results", signature = (expected, actual, value_tolerance=0.0, ts_tolerance=0))]
+pub fn py_compare_results(
+    expected: &Bound<'_, PyAny>,
+    actual: &Bound<'_, PyAny>,
+    value_tolerance: f64,
+    ts_tolerance: i64,
+) -> PyResult<ResultDiff> {
+    Ok(compare_results(
+        &extract_result_set(expected)?,
+        &extract_result_set(actual)?,
+        value_tolerance,
+        ts_tolerance,
+    ))
+}
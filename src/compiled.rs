@@ -0,0 +1,143 @@
+//! Immutable, shareable form of a configured pipeline.
+//!
+//! `MetricPipeline` is built up step by step through `&mut self` methods, so a
+//! Python object of it can't be used from several threads at once: a second
+//! thread executing while another adds a step fails with "Already borrowed".
+//! `MetricPipeline.compile()` snapshots the input metrics and steps into a
+//! `CompiledPipeline`, which has no mutating methods and releases the GIL while
+//! it runs, so a worker pool can execute one configured pipeline concurrently.
+//! The effective settings are part of the snapshot.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+use crate::cache::{fingerprint, ResultCache, RunOutput};
+use crate::errors::MetricQueryResult;
+use crate::models::{FloatMetric, Metric};
+use crate::settings::Settings;
+use crate::stats::RunStats;
+use crate::transformations::MetricPipeline;
+use crate::warnings::PipelineWarning;
+#[cfg(feature = "python")]
+use crate::warnings::emit_python_warnings;
+
+/// Source of compiled pipeline ids
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A frozen snapshot of a pipeline's input metrics and steps.
+///
+/// Cloning is cheap and clones share the snapshot, including its id and run stats.
+#[cfg_attr(feature = "python", pyclass(frozen))]
+#[derive(Clone)]
+pub struct CompiledPipeline {
+    pipeline: Arc<MetricPipeline>,
+    id: u64,
+    // Fingerprint of the snapshot's own metrics, computed on the first cached run
+    input_fingerprint: Arc<OnceLock<u64>>,
+}
+
+impl CompiledPipeline {
+    /// Snapshot a pipeline; later changes to it don't affect the compiled form.
+    ///
+    /// The settings in effect now are frozen with it: a pipeline following the
+    /// process-wide settings keeps the ones current at compile time.
+    pub fn new(pipeline: &MetricPipeline) -> Self {
+        let mut snapshot = pipeline.clone();
+        snapshot.freeze_input();
+        snapshot.set_settings(Some(pipeline.settings()));
+        Self {
+            pipeline: Arc::new(snapshot),
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            input_fingerprint: Arc::new(OnceLock::new()),
+        }
+    }
+
+    /// Identifier unique to this snapshot (and its clones) within the process; part of the cache key
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Like `run_metrics_with_warnings`, but answered from `cache` when the same input was
+    /// run recently; `None` runs over the snapshot's own metrics
+    pub fn run_cached(&self, metrics: Option<&[Metric]>, cache: &ResultCache) -> MetricQueryResult<RunOutput> {
+        let own = self.pipeline.shared_metrics();
+        let (input, fingerprint) = match metrics {
+            Some(metrics) => (metrics, fingerprint(metrics)),
+            None => (&*own, *self.input_fingerprint.get_or_init(|| fingerprint(&own))),
+        };
+        cache.get_or_run((self.id, fingerprint), input, || self.pipeline.run_metrics_with_warnings(input))
+    }
+
+    /// The settings frozen into the snapshot
+    pub fn settings(&self) -> Settings {
+        self.pipeline.settings()
+    }
+
+    /// Execute the steps over the snapshot's own metrics
+    pub fn run(&self) -> MetricQueryResult<Vec<Metric>> {
+        self.pipeline.run()
+    }
+
+    /// Execute the steps over other metrics, also returning non-fatal warnings
+    pub fn run_metrics_with_warnings(&self, metrics: &[Metric]) -> MetricQueryResult<(Vec<Metric>, Vec<PipelineWarning>)> {
+        self.pipeline.run_metrics_with_warnings(metrics)
+    }
+
+    /// Execute the steps over float metrics
+    pub fn run_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        self.pipeline.execute_float_metrics(metrics)
+    }
+
+    /// Counts and timings from the most recent successful run on any thread, if any
+    pub fn last_run_stats(&self) -> Option<RunStats> {
+        self.pipeline.last_run_stats()
+    }
+
+    /// Whether any step must run on the calling thread (e.g. it calls into Python)
+    pub fn thread_bound(&self) -> bool {
+        self.pipeline.thread_bound()
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl CompiledPipeline {
+    /// Execute the pipeline over `metrics`, or over the metrics it was compiled with
+    ///
+    /// The GIL is released while the steps run. With a `ResultCache`, a recent result for
+    /// the same input is returned without running the steps. Non-fatal issues are reported
+    /// through Python's `warnings` module as `MetricQueryWarning`.
+    #[pyo3(signature = (metrics=None, cache=None))]
+    fn execute(&self, py: Python<'_>, metrics: Option<Vec<Metric>>, cache: Option<&ResultCache>) -> PyResult<Vec<Metric>> {
+        let (result, warnings) = py.allow_threads(|| match (cache, &metrics) {
+            (Some(cache), metrics) => self.run_cached(metrics.as_deref(), cache),
+            (None, Some(metrics)) => self.pipeline.run_metrics_with_warnings(metrics),
+            (None, None) => self.pipeline.run_with_warnings(),
+        })?;
+        if !warnings.is_empty() {
+            emit_python_warnings(py, &warnings)?;
+        }
+        Ok(result)
+    }
+
+    /// Execute the pipeline's steps over float metrics, releasing the GIL while they run
+    fn execute_float(&self, py: Python<'_>, metrics: Vec<FloatMetric>) -> PyResult<Vec<FloatMetric>> {
+        Ok(py.allow_threads(|| self.run_float(&metrics))?)
+    }
+
+    /// Identifier unique to this compiled pipeline within the process
+    #[getter(id)]
+    fn py_id(&self) -> u64 {
+        self.id
+    }
+
+    /// Input/output counts, per-step counts and wall time of the most recent
+    /// successful execution on any thread, or `None` if it hasn't run yet
+    #[pyo3(name = "last_run_stats")]
+    fn py_last_run_stats(&self) -> Option<RunStats> {
+        self.last_run_stats()
+    }
+}
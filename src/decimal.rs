@@ -0,0 +1,96 @@
+//! Fixed-point decimal values for metrics that must add up exactly, such as money.
+//!
+//! A metric with a `scale` stores its value as a whole number of `10^-scale`
+//! units: 12.34 is value 1234 with scale 2. Aggregations bring their inputs to
+//! the largest scale among them before combining, so sums stay exact and an
+//! `avg` is rounded only at the last decimal place. Add a `rescale` step before
+//! an `avg` to keep more digits of the mean.
+
+use std::borrow::Cow;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::Metric;
+use crate::plugin_impls::RoundingMode;
+
+/// Largest supported scale; 10^18 is the largest power of ten that fits in an i64
+pub const MAX_SCALE: u32 = 18;
+
+fn decimal_error(reason: String) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: "decimal".to_string(), reason }
+}
+
+/// Fail for scales beyond `MAX_SCALE`
+pub fn check_scale(scale: u32) -> MetricQueryResult<()> {
+    if scale > MAX_SCALE {
+        return Err(decimal_error(format!("scale {} exceeds the maximum of {}", scale, MAX_SCALE)));
+    }
+    Ok(())
+}
+
+/// Parse a decimal string such as "12.34" or "-0.5" into its unscaled value and scale
+pub fn parse_decimal(text: &str) -> MetricQueryResult<(i64, u32)> {
+    let invalid = || decimal_error(format!("Invalid decimal: {:?}", text));
+    let trimmed = text.trim();
+    let (negative, digits) = match trimmed.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, trimmed.strip_prefix('+').unwrap_or(trimmed)),
+    };
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+    if (whole.is_empty() && fraction.is_empty()) || !whole.chars().chain(fraction.chars()).all(|c| c.is_ascii_digit()) {
+        return Err(invalid());
+    }
+    let scale = fraction.len() as u32;
+    check_scale(scale)?;
+
+    let mut value: i64 = 0;
+    for digit in whole.bytes().chain(fraction.bytes()) {
+        value = value
+            .checked_mul(10)
+            .and_then(|v| v.checked_add(i64::from(digit - b'0')))
+            .ok_or_else(|| MetricQueryError::ArithmeticOverflow { operation: "decimal".to_string() })?;
+    }
+    Ok((if negative { -value } else { value }, scale))
+}
+
+/// Format an unscaled value with `scale` decimal places, e.g. (1234, 2) as "12.34"
+pub fn format_decimal(value: i64, scale: u32) -> String {
+    if scale == 0 {
+        return value.to_string();
+    }
+    let digits = value.unsigned_abs().to_string();
+    let digits = format!("{:0>width$}", digits, width = scale as usize + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - scale as usize);
+    format!("{}{}.{}", if value < 0 { "-" } else { "" }, whole, fraction)
+}
+
+/// Convert an unscaled value from scale `from` to scale `to`, rounding with `rounding`
+/// when digits are dropped
+pub fn rescale(value: i64, from: u32, to: u32, rounding: RoundingMode) -> MetricQueryResult<i64> {
+    check_scale(to)?;
+    let overflow = || MetricQueryError::ArithmeticOverflow { operation: "rescale".to_string() };
+    if to >= from {
+        value.checked_mul(10_i64.pow(to - from)).ok_or_else(overflow)
+    } else {
+        let divided = rounding.divide(i128::from(value), 10_i128.pow(from - to));
+        i64::try_from(divided).map_err(|_| overflow())
+    }
+}
+
+/// Bring every metric to the largest scale among them, treating unscaled metrics as
+/// scale 0. Returns the metrics unchanged, and no scale, when none of them has one.
+pub fn align_scales(metrics: &[Metric]) -> MetricQueryResult<(Cow<'_, [Metric]>, Option<u32>)> {
+    let Some(scale) = metrics.iter().filter_map(|m| m.scale).max() else {
+        return Ok((Cow::Borrowed(metrics), None));
+    };
+    if metrics.iter().all(|m| m.scale == Some(scale)) {
+        return Ok((Cow::Borrowed(metrics), Some(scale)));
+    }
+    let aligned = metrics
+        .iter()
+        .map(|metric| {
+            let value = rescale(metric.value, metric.scale.unwrap_or(0), scale, RoundingMode::Truncate)?;
+            Ok(Metric { value, scale: Some(scale), ..metric.clone() })
+        })
+        .collect::<MetricQueryResult<Vec<_>>>()?;
+    Ok((Cow::Owned(aligned), Some(scale)))
+}
@@ -1,8 +1,17 @@
-use pyo3::exceptions::PyValueError;
+#[cfg(feature = "python")]
+use pyo3::exceptions::{PyOverflowError, PyPermissionError, PyTimeoutError, PyValueError};
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
 use pyo3::PyErr;
+use std::time::Duration;
 
 /// Custom error types for the metric query library
+///
+/// With the `serde` feature an error serializes as an object keyed by its variant name,
+/// e.g. `{"InvalidFilter": {"reason": "..."}}`.
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum MetricQueryError {
     /// Error when an invalid filter is provided
     InvalidFilter { reason: String },
@@ -14,6 +23,87 @@ pub enum MetricQueryError {
     EmptyMetricStream,
     /// Error when a transformation operation fails
     OperationFailed { operation: String, reason: String },
+    /// Error when an arithmetic result does not fit in the value type
+    ArithmeticOverflow { operation: String },
+    /// Error when timestamp validation finds implausible timestamps
+    InvalidTimestamp { index: usize, timestamp: i64, reason: String, offenders: usize },
+    /// Error when a metric's value or label fails validation at construction
+    InvalidMetric { index: usize, reason: String },
+    /// Error when two metrics share a timestamp and label under the "error" duplicate strategy
+    DuplicateTimestamp { index: usize, timestamp: i64, label: Option<String> },
+    /// Error when an `assert_that` step finds the stream violating its assertion
+    AssertionFailed { index: usize, message: String },
+    /// Error when a plugin defined in Python raises; `reason` names the exception and where it was raised
+    PluginFailed { plugin: String, reason: String },
+    /// Error when a call into a Python plugin outlives the settings' `plugin_timeout`
+    PluginTimeout { plugin: String, timeout: Duration },
+    /// Error when a Python plugin is about to run under trusted-only settings
+    UntrustedPlugin { plugin: String },
+    /// Error raised by a pipeline step, annotated with the step's position and plugin
+    StepFailed { step: usize, plugin: String, source: Box<MetricQueryError> },
+}
+
+impl MetricQueryError {
+    /// Stable machine-readable error code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::InvalidFilter { .. } => "invalid_filter",
+            Self::InvalidAggregation { .. } => "invalid_aggregation",
+            Self::InvalidTimeGrouping { .. } => "invalid_time_grouping",
+            Self::EmptyMetricStream => "empty_metric_stream",
+            Self::OperationFailed { .. } => "operation_failed",
+            Self::ArithmeticOverflow { .. } => "arithmetic_overflow",
+            Self::InvalidTimestamp { .. } => "invalid_timestamp",
+            Self::InvalidMetric { .. } => "invalid_metric",
+            Self::DuplicateTimestamp { .. } => "duplicate_timestamp",
+            Self::AssertionFailed { .. } => "assertion_failed",
+            Self::PluginFailed { .. } => "plugin_failed",
+            Self::PluginTimeout { .. } => "plugin_timeout",
+            Self::UntrustedPlugin { .. } => "untrusted_plugin",
+            Self::StepFailed { source, .. } => source.code(),
+        }
+    }
+
+    /// Index of the pipeline step that failed, if known
+    pub fn step_index(&self) -> Option<usize> {
+        match self {
+            Self::StepFailed { step, .. } => Some(*step),
+            _ => None,
+        }
+    }
+
+    /// Name of the plugin behind the failing step, if known
+    pub fn plugin_name(&self) -> Option<&str> {
+        match self {
+            Self::StepFailed { plugin, .. } => Some(plugin),
+            _ => None,
+        }
+    }
+
+    /// Index of the offending metric within the step's input, if known
+    pub fn metric_index(&self) -> Option<usize> {
+        match self {
+            Self::InvalidTimestamp { index, .. } => Some(*index),
+            Self::InvalidMetric { index, .. } => Some(*index),
+            Self::DuplicateTimestamp { index, .. } => Some(*index),
+            Self::AssertionFailed { index, .. } => Some(*index),
+            Self::StepFailed { source, .. } => source.metric_index(),
+            _ => None,
+        }
+    }
+
+    /// The underlying error, with any step context removed
+    pub fn root(&self) -> &MetricQueryError {
+        match self {
+            Self::StepFailed { source, .. } => source.root(),
+            other => other,
+        }
+    }
+
+    /// Attach step context to an error raised while executing a pipeline
+    pub fn at_step(self, step: usize, plugin: impl Into<String>) -> Self {
+        Self::StepFailed { step, plugin: plugin.into(), source: Box::new(self) }
+    }
 }
 
 impl std::fmt::Display for MetricQueryError {
@@ -26,33 +116,68 @@ impl std::fmt::Display for MetricQueryError {
             Self::OperationFailed { operation, reason } => {
                 write!(f, "Operation '{}' failed: {}", operation, reason)
             }
+            Self::ArithmeticOverflow { operation } => {
+                write!(f, "Arithmetic overflow in '{}'", operation)
+            }
+            Self::InvalidTimestamp { index, timestamp, reason, offenders } => write!(
+                f,
+                "Invalid timestamp {} ({}) at index {}; {} metric(s) failed validation",
+                timestamp, reason, index, offenders
+            ),
+            Self::InvalidMetric { index, reason } => write!(f, "Invalid metric at index {}: {}", index, reason),
+            Self::DuplicateTimestamp { index, timestamp, label } => write!(
+                f,
+                "Duplicate timestamp {} for label {:?} at index {}",
+                timestamp, label, index
+            ),
+            Self::AssertionFailed { index, message } => {
+                write!(f, "Assertion failed at index {}: {}", index, message)
+            }
+            Self::PluginFailed { plugin, reason } => write!(f, "Plugin '{}' raised {}", plugin, reason),
+            Self::PluginTimeout { plugin, timeout } => {
+                write!(f, "Plugin '{}' did not return within {:?}", plugin, timeout)
+            }
+            Self::UntrustedPlugin { plugin } => {
+                write!(f, "Plugin '{}' is defined in Python, which trusted-only settings forbid", plugin)
+            }
+            Self::StepFailed { step, plugin, source } => {
+                write!(f, "Step {} ('{}') failed: {}", step, plugin, source)
+            }
         }
     }
 }
 
 impl std::error::Error for MetricQueryError {}
 
+/// Converts to `OverflowError` for overflows, `TimeoutError` for plugin timeouts,
+/// `PermissionError` for plugins forbidden by trusted-only settings and `ValueError`
+/// otherwise. The raised exception carries `code`, `step_index`, `plugin` and
+/// `metric_index` attributes (`None` when not applicable) so callers don't have to
+/// parse the message.
+#[cfg(feature = "python")]
 impl From<MetricQueryError> for PyErr {
     fn from(err: MetricQueryError) -> PyErr {
-        match err {
-            MetricQueryError::InvalidFilter { reason } => {
-                PyValueError::new_err(format!("Invalid filter: {}", reason))
-            }
-            MetricQueryError::InvalidAggregation { reason } => {
-                PyValueError::new_err(format!("Invalid aggregation: {}", reason))
-            }
-            MetricQueryError::InvalidTimeGrouping { reason } => {
-                PyValueError::new_err(format!("Invalid time grouping: {}", reason))
-            }
-            MetricQueryError::EmptyMetricStream => {
-                PyValueError::new_err("Operation on empty metric stream")
-            }
-            MetricQueryError::OperationFailed { operation, reason } => {
-                PyValueError::new_err(format!("Operation '{}' failed: {}", operation, reason))
-            }
-        }
+        let message = err.to_string();
+        let py_err = match err.root() {
+            MetricQueryError::ArithmeticOverflow { .. } => PyOverflowError::new_err(message),
+            MetricQueryError::PluginTimeout { .. } => PyTimeoutError::new_err(message),
+            MetricQueryError::UntrustedPlugin { .. } => PyPermissionError::new_err(message),
+            _ => PyValueError::new_err(message),
+        };
+
+        Python::with_gil(|py| {
+            let value = py_err.value(py);
+            // Attribute assignment on a fresh exception instance cannot fail in practice;
+            // if it ever does, the exception is still raised with its message
+            let _ = value.setattr("code", err.code());
+            let _ = value.setattr("step_index", err.step_index());
+            let _ = value.setattr("plugin", err.plugin_name());
+            let _ = value.setattr("metric_index", err.metric_index());
+        });
+
+        py_err
     }
 }
 
 /// Result type for metric query operations
-pub type MetricQueryResult<T> = Result<T, MetricQueryError>;
\ No newline at end of file
+pub type MetricQueryResult<T> = Result<T, MetricQueryError>;
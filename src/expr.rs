@@ -0,0 +1,521 @@
+//! Filter expressions such as `value > 10 and value <= 100 and label == 'cpu'`.
+//!
+//! An expression compares metric attributes with literals or with each other and
+//! combines the comparisons with `and`, `or`, `not` and parentheses:
+//!
+//! - attributes: `value`, `timestamp`, `label`, `unit`, `source`, `tags.<key>` and
+//!   `fields.<name>`;
+//! - literals: integers, decimals, 'single' or "double" quoted strings and `None`;
+//! - comparisons: `==`, `!=`, `<`, `<=`, `>`, `>=`, plus `in` and `not in` against a
+//!   parenthesised list of literals.
+//!
+//! Comparisons are type checked when the expression is parsed, so `label > 3` fails
+//! up front, as does nesting parentheses or `not` deeper than `MAX_EXPR_DEPTH`. An
+//! attribute the metric doesn't have is `None`: it equals only `None` and fails
+//! every ordering comparison.
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+
+fn syntax_error(reason: String) -> MetricQueryError {
+    MetricQueryError::InvalidFilter { reason }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+    Op(&'static str),
+    Open,
+    Close,
+    Comma,
+}
+
+/// Split `source` into tokens, each with its byte offset for error messages
+fn tokenize(source: &str) -> MetricQueryResult<Vec<(usize, Token)>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        let token = match c {
+            '(' => {
+                chars.next();
+                Token::Open
+            }
+            ')' => {
+                chars.next();
+                Token::Close
+            }
+            ',' => {
+                chars.next();
+                Token::Comma
+            }
+            '\'' | '"' => {
+                chars.next();
+                let mut text = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, ch)) if ch == c => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, escaped)) => text.push(escaped),
+                            None => return Err(syntax_error(format!("Unterminated string at position {}", start))),
+                        },
+                        Some((_, ch)) => text.push(ch),
+                        None => return Err(syntax_error(format!("Unterminated string at position {}", start))),
+                    }
+                }
+                Token::Str(text)
+            }
+            '=' | '!' | '<' | '>' => {
+                chars.next();
+                let followed_by_eq = chars.next_if(|&(_, next)| next == '=').is_some();
+                Token::Op(match (c, followed_by_eq) {
+                    ('=', true) => "==",
+                    ('!', true) => "!=",
+                    ('<', true) => "<=",
+                    ('>', true) => ">=",
+                    ('<', false) => "<",
+                    ('>', false) => ">",
+                    _ => return Err(syntax_error(format!("Unexpected '{}' at position {}; use '==' or '!='", c, start))),
+                })
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut text = String::new();
+                while let Some((_, ch)) = chars.next_if(|&(i, ch)| ch.is_ascii_digit() || ch == '.' || (i == start && ch == '-')) {
+                    text.push(ch);
+                }
+                match text.parse::<i64>() {
+                    Ok(value) => Token::Int(value),
+                    Err(_) => text.parse::<f64>().map(Token::Float).map_err(|_| {
+                        syntax_error(format!("Invalid number {:?} at position {}", text, start))
+                    })?,
+                }
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut text = String::new();
+                while let Some((_, ch)) = chars.next_if(|&(_, ch)| ch.is_alphanumeric() || ch == '_' || ch == '.') {
+                    text.push(ch);
+                }
+                Token::Ident(text)
+            }
+            _ => return Err(syntax_error(format!("Unexpected '{}' at position {}", c, start))),
+        };
+        tokens.push((start, token));
+    }
+    Ok(tokens)
+}
+
+/// A metric attribute an expression can read
+#[derive(Debug, Clone, PartialEq)]
+enum Attribute {
+    Value,
+    Timestamp,
+    Label,
+    Unit,
+    Source,
+    Tag(String),
+    Field(String),
+}
+
+/// A value an expression compares
+#[derive(Debug, Clone, PartialEq)]
+enum Scalar {
+    Int(i64),
+    Float(f64),
+    Str(String),
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Number,
+    Text,
+    None,
+}
+
+impl Scalar {
+    fn kind(&self) -> Kind {
+        match self {
+            Self::Int(_) | Self::Float(_) => Kind::Number,
+            Self::Str(_) => Kind::Text,
+            Self::None => Kind::None,
+        }
+    }
+
+    /// Order of two values of the same kind; None when either is `None`
+    fn compare(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        match (self, other) {
+            (Self::Int(a), Self::Int(b)) => Some(a.cmp(b)),
+            (Self::Int(a), Self::Float(b)) => (*a as f64).partial_cmp(b),
+            (Self::Float(a), Self::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (Self::Float(a), Self::Float(b)) => a.partial_cmp(b),
+            (Self::Str(a), Self::Str(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl Attribute {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "value" => Some(Self::Value),
+            "timestamp" => Some(Self::Timestamp),
+            "label" => Some(Self::Label),
+            "unit" => Some(Self::Unit),
+            "source" => Some(Self::Source),
+            _ => match name.split_once('.') {
+                Some(("tags", key)) if !key.is_empty() => Some(Self::Tag(key.to_string())),
+                Some(("fields", field)) if !field.is_empty() => Some(Self::Field(field.to_string())),
+                _ => None,
+            },
+        }
+    }
+
+    fn kind(&self) -> Kind {
+        match self {
+            Self::Value | Self::Timestamp | Self::Field(_) => Kind::Number,
+            Self::Label | Self::Unit | Self::Source | Self::Tag(_) => Kind::Text,
+        }
+    }
+
+    fn read(&self, metric: &Metric) -> Scalar {
+        let text = |text: Option<&String>| text.map_or(Scalar::None, |text| Scalar::Str(text.clone()));
+        match self {
+            Self::Value if metric.scale.is_some() => Scalar::Float(metric.as_f64()),
+            Self::Value => Scalar::Int(metric.value),
+            Self::Timestamp => Scalar::Int(metric.timestamp),
+            Self::Label => text(metric.label.as_ref()),
+            Self::Unit => text(metric.unit.as_ref()),
+            Self::Source => text(metric.source.as_ref()),
+            Self::Tag(key) => text(metric.tags.get(key)),
+            Self::Field(field) => metric.fields.get(field).map_or(Scalar::None, Scalar::Int),
+        }
+    }
+
+    /// Float metrics have no tags, fields or source; a missing value is `None`
+    fn read_float(&self, metric: &FloatMetric) -> Scalar {
+        match self {
+            Self::Value if metric.is_missing() => Scalar::None,
+            Self::Value => Scalar::Float(metric.value),
+            Self::Timestamp => Scalar::Int(metric.timestamp),
+            Self::Label => metric.label.clone().map_or(Scalar::None, Scalar::Str),
+            Self::Unit => metric.unit.clone().map_or(Scalar::None, Scalar::Str),
+            Self::Source | Self::Tag(_) | Self::Field(_) => Scalar::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Attribute(Attribute),
+    Literal(Scalar),
+}
+
+impl Operand {
+    fn kind(&self) -> Kind {
+        match self {
+            Self::Attribute(attribute) => attribute.kind(),
+            Self::Literal(literal) => literal.kind(),
+        }
+    }
+}
+
+/// Deepest nesting of parentheses and `not` an expression may use. The parser
+/// recurses once per level, so unbounded nesting would overflow the stack.
+pub const MAX_EXPR_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Or(Vec<Node>),
+    And(Vec<Node>),
+    Not(Box<Node>),
+    Compare(Operand, &'static str, Operand),
+    In(Operand, Vec<Scalar>),
+}
+
+impl Node {
+    fn eval(&self, read: &impl Fn(&Attribute) -> Scalar) -> bool {
+        let value = |operand: &Operand| match operand {
+            Operand::Attribute(attribute) => read(attribute),
+            Operand::Literal(literal) => literal.clone(),
+        };
+        match self {
+            Self::Or(nodes) => nodes.iter().any(|node| node.eval(read)),
+            Self::And(nodes) => nodes.iter().all(|node| node.eval(read)),
+            Self::Not(inner) => !inner.eval(read),
+            Self::Compare(left, op, right) => {
+                let (left, right) = (value(left), value(right));
+                let equal = left == right || left.compare(&right) == Some(std::cmp::Ordering::Equal);
+                match *op {
+                    "==" => equal,
+                    "!=" => !equal,
+                    _ => left.compare(&right).is_some_and(|ordering| match *op {
+                        "<" => ordering.is_lt(),
+                        "<=" => ordering.is_le(),
+                        ">" => ordering.is_gt(),
+                        _ => ordering.is_ge(),
+                    }),
+                }
+            }
+            Self::In(operand, list) => {
+                let value = value(operand);
+                list.iter().any(|item| *item == value || item.compare(&value) == Some(std::cmp::Ordering::Equal))
+            }
+        }
+    }
+}
+
+/// What a possibly parenthesized term parses to: a condition, or an operand such as
+/// `(value)` still waiting for its comparison
+enum Term {
+    Condition(Node),
+    Operand(Operand),
+}
+
+/// Recursive-descent parser over the token list
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    position: usize,
+    end: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(_, token)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.position).map_or(self.end, |&(offset, _)| offset)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(_, token)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(word)) if word == keyword)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        let found = self.at_keyword(keyword);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expected(&self, what: &str) -> MetricQueryError {
+        match self.tokens.get(self.position) {
+            Some((offset, token)) => syntax_error(format!("Expected {} at position {}, found {:?}", what, offset, token)),
+            None => syntax_error(format!("Expected {} at the end of the expression", what)),
+        }
+    }
+
+    /// Enter a parenthesis or `not`, failing past `MAX_EXPR_DEPTH`
+    fn descend(&mut self) -> MetricQueryResult<()> {
+        self.depth += 1;
+        if self.depth > MAX_EXPR_DEPTH {
+            return Err(syntax_error(format!(
+                "Expression nests deeper than {} levels at position {}",
+                MAX_EXPR_DEPTH,
+                self.offset()
+            )));
+        }
+        Ok(())
+    }
+
+    /// The condition `term` parsed to; a bare operand is missing its comparison
+    fn condition(&self, term: Term) -> MetricQueryResult<Node> {
+        match term {
+            Term::Condition(node) => Ok(node),
+            Term::Operand(_) => Err(self.expected("a comparison operator")),
+        }
+    }
+
+    fn operand_of(&self, term: Term) -> MetricQueryResult<Operand> {
+        match term {
+            Term::Operand(operand) => Ok(operand),
+            Term::Condition(_) => Err(self.expected("an attribute or literal, not a condition,")),
+        }
+    }
+
+    fn or(&mut self) -> MetricQueryResult<Term> {
+        let first = self.and()?;
+        if !self.at_keyword("or") {
+            return Ok(first);
+        }
+        let mut nodes = vec![self.condition(first)?];
+        while self.eat_keyword("or") {
+            let next = self.and()?;
+            nodes.push(self.condition(next)?);
+        }
+        Ok(Term::Condition(Node::Or(nodes)))
+    }
+
+    fn and(&mut self) -> MetricQueryResult<Term> {
+        let first = self.not()?;
+        if !self.at_keyword("and") {
+            return Ok(first);
+        }
+        let mut nodes = vec![self.condition(first)?];
+        while self.eat_keyword("and") {
+            let next = self.not()?;
+            nodes.push(self.condition(next)?);
+        }
+        Ok(Term::Condition(Node::And(nodes)))
+    }
+
+    fn not(&mut self) -> MetricQueryResult<Term> {
+        if self.eat_keyword("not") {
+            self.descend()?;
+            let inner = self.not()?;
+            self.depth -= 1;
+            return Ok(Term::Condition(Node::Not(Box::new(self.condition(inner)?))));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> MetricQueryResult<Term> {
+        let offset = self.offset();
+        let left = self.term()?;
+        if self.eat_keyword("in") {
+            let left = self.operand_of(left)?;
+            return self.membership(offset, left, false).map(Term::Condition);
+        }
+        if self.eat_keyword("not") {
+            if !self.eat_keyword("in") {
+                return Err(self.expected("'in'"));
+            }
+            let left = self.operand_of(left)?;
+            return self.membership(offset, left, true).map(Term::Condition);
+        }
+        let op = match self.peek() {
+            Some(&Token::Op(op)) => op,
+            _ => return Ok(left),
+        };
+        let left = self.operand_of(left)?;
+        self.position += 1;
+        let right = self.term()?;
+        let right = self.operand_of(right)?;
+        let kinds = (left.kind(), right.kind());
+        let ordering = !matches!(op, "==" | "!=");
+        if kinds.0 != kinds.1 && (ordering || (kinds.0 != Kind::None && kinds.1 != Kind::None)) {
+            return Err(syntax_error(format!(
+                "Cannot compare {:?} with {:?} using '{}' at position {}",
+                kinds.0, kinds.1, op, offset
+            )));
+        }
+        Ok(Term::Condition(Node::Compare(left, op, right)))
+    }
+
+    /// An operand, or a parenthesized condition or operand
+    fn term(&mut self) -> MetricQueryResult<Term> {
+        if self.peek() != Some(&Token::Open) {
+            return self.operand().map(Term::Operand);
+        }
+        self.position += 1;
+        self.descend()?;
+        let inner = self.or()?;
+        if self.peek() != Some(&Token::Close) {
+            return Err(self.expected("')'"));
+        }
+        self.position += 1;
+        self.depth -= 1;
+        Ok(inner)
+    }
+
+    fn membership(&mut self, offset: usize, operand: Operand, negate: bool) -> MetricQueryResult<Node> {
+        if self.next() != Some(Token::Open) {
+            self.position -= 1;
+            return Err(self.expected("'(' starting a list"));
+        }
+        let mut list = Vec::new();
+        loop {
+            match self.operand()? {
+                Operand::Literal(literal) if literal.kind() == operand.kind() || literal == Scalar::None => {
+                    list.push(literal)
+                }
+                _ => {
+                    return Err(syntax_error(format!(
+                        "List items must be {:?} literals, at position {}",
+                        operand.kind(),
+                        offset
+                    )))
+                }
+            }
+            match self.next() {
+                Some(Token::Comma) => continue,
+                Some(Token::Close) => break,
+                _ => {
+                    self.position -= 1;
+                    return Err(self.expected("',' or ')'"));
+                }
+            }
+        }
+        let node = Node::In(operand, list);
+        Ok(if negate { Node::Not(Box::new(node)) } else { node })
+    }
+
+    fn operand(&mut self) -> MetricQueryResult<Operand> {
+        let offset = self.offset();
+        match self.next() {
+            Some(Token::Int(value)) => Ok(Operand::Literal(Scalar::Int(value))),
+            Some(Token::Float(value)) => Ok(Operand::Literal(Scalar::Float(value))),
+            Some(Token::Str(text)) => Ok(Operand::Literal(Scalar::Str(text))),
+            Some(Token::Ident(name)) if name == "None" || name == "null" => Ok(Operand::Literal(Scalar::None)),
+            Some(Token::Ident(name)) => Attribute::parse(&name).map(Operand::Attribute).ok_or_else(|| {
+                syntax_error(format!(
+                    "Unknown attribute '{}' at position {}. Expected value, timestamp, label, unit, source, tags.<key> or fields.<name>",
+                    name, offset
+                ))
+            }),
+            _ => {
+                self.position -= 1;
+                Err(self.expected("an attribute or literal"))
+            }
+        }
+    }
+}
+
+/// A parsed filter expression
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    source: String,
+    root: Node,
+}
+
+impl Expr {
+    /// Parse `source`, failing with `InvalidFilter` on syntax or type errors
+    pub fn parse(source: &str) -> MetricQueryResult<Self> {
+        let mut parser = Parser { tokens: tokenize(source)?, position: 0, end: source.len(), depth: 0 };
+        let root = parser.or()?;
+        let root = parser.condition(root)?;
+        if parser.position < parser.tokens.len() {
+            return Err(parser.expected("'and', 'or' or the end of the expression"));
+        }
+        Ok(Self { source: source.to_string(), root })
+    }
+
+    /// The expression as written
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether `metric` satisfies the expression
+    pub fn matches(&self, metric: &Metric) -> bool {
+        self.root.eval(&|attribute| attribute.read(metric))
+    }
+
+    /// Whether the float `metric` satisfies the expression
+    pub fn matches_float(&self, metric: &FloatMetric) -> bool {
+        self.root.eval(&|attribute| attribute.read_float(metric))
+    }
+}
@@ -0,0 +1,226 @@
+//! C ABI over the Rust core, enabled with the `ffi` feature.
+//!
+//! Pipelines are opaque handles built up step by step and executed over
+//! value/timestamp arrays. Every fallible function returns an `MqStatus`; on
+//! failure the message is available from `mq_last_error` on the same thread.
+//! The build generates a C header from this file into `OUT_DIR`; the checked-in
+//! `include/metric_query.h` is refreshed from it with cbindgen (see `build.rs`).
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::plugin_impls::{create_aggregation, create_filter, create_time_grouping};
+use crate::transformations::MetricPipeline;
+
+/// Status code returned by every fallible FFI function
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MqStatus {
+    Ok = 0,
+    /// A required pointer was null or a string was not valid UTF-8
+    InvalidArgument = 1,
+    /// The engine rejected the request; see `mq_last_error`
+    Failed = 2,
+    /// A Rust panic was caught at the boundary
+    Panic = 3,
+}
+
+/// Opaque pipeline handle
+pub struct MqPipeline {
+    pipeline: MetricPipeline,
+}
+
+/// Result columns of an execution, owned by Rust until `mq_result_free`
+#[repr(C)]
+pub struct MqResult {
+    pub values: *mut i64,
+    pub timestamps: *mut i64,
+    pub len: usize,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "")).expect("NUL bytes were removed");
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = Some(message));
+}
+
+/// Run `f`, converting errors and panics into a status code
+fn guard(f: impl FnOnce() -> Result<(), MqStatus>) -> MqStatus {
+    LAST_ERROR.with(|slot| *slot.borrow_mut() = None);
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => MqStatus::Ok,
+        Ok(Err(status)) => status,
+        Err(_) => {
+            set_last_error("internal panic in metric query engine".to_string());
+            MqStatus::Panic
+        }
+    }
+}
+
+fn engine<T>(result: MetricQueryResult<T>) -> Result<T, MqStatus> {
+    result.map_err(|e: MetricQueryError| {
+        set_last_error(e.to_string());
+        MqStatus::Failed
+    })
+}
+
+fn invalid(message: &str) -> MqStatus {
+    set_last_error(message.to_string());
+    MqStatus::InvalidArgument
+}
+
+/// # Safety
+/// `s` must be null or a valid NUL-terminated string.
+unsafe fn read_str<'a>(s: *const c_char, what: &str) -> Result<&'a str, MqStatus> {
+    if s.is_null() {
+        return Err(invalid(&format!("{} must not be null", what)));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| invalid(&format!("{} is not valid UTF-8", what)))
+}
+
+/// # Safety
+/// `pipeline` must be null or a handle from `mq_pipeline_new` that has not been freed.
+unsafe fn pipeline_mut<'a>(pipeline: *mut MqPipeline) -> Result<&'a mut MetricPipeline, MqStatus> {
+    pipeline.as_mut().map(|p| &mut p.pipeline).ok_or_else(|| invalid("pipeline must not be null"))
+}
+
+/// Create an empty pipeline. Free it with `mq_pipeline_free`.
+#[no_mangle]
+pub extern "C" fn mq_pipeline_new() -> *mut MqPipeline {
+    Box::into_raw(Box::new(MqPipeline { pipeline: MetricPipeline::new(Vec::new()) }))
+}
+
+/// Free a pipeline. Passing null is a no-op.
+///
+/// # Safety
+/// `pipeline` must be null or a handle from `mq_pipeline_new` that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mq_pipeline_free(pipeline: *mut MqPipeline) {
+    if !pipeline.is_null() {
+        drop(Box::from_raw(pipeline));
+    }
+}
+
+/// Add a value filter ("gt", "lt", "ge", "le" or "eq") comparing against `value`.
+///
+/// # Safety
+/// `pipeline` must be a live handle and `filter_type` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mq_pipeline_add_filter(
+    pipeline: *mut MqPipeline,
+    filter_type: *const c_char,
+    value: i64,
+) -> MqStatus {
+    guard(|| {
+        let pipeline = pipeline_mut(pipeline)?;
+        let filter = engine(create_filter(read_str(filter_type, "filter_type")?, value))?;
+        pipeline.add_filter(filter);
+        Ok(())
+    })
+}
+
+/// Add an aggregation ("sum", "avg", "min" or "max") over the whole stream.
+///
+/// # Safety
+/// `pipeline` must be a live handle and `agg_type` a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mq_pipeline_add_aggregation(pipeline: *mut MqPipeline, agg_type: *const c_char) -> MqStatus {
+    guard(|| {
+        let pipeline = pipeline_mut(pipeline)?;
+        let aggregation = engine(create_aggregation(read_str(agg_type, "agg_type")?))?;
+        pipeline.add_aggregation(aggregation);
+        Ok(())
+    })
+}
+
+/// Group by time ("minute", "hour" or "day") and aggregate each group.
+///
+/// # Safety
+/// `pipeline` must be a live handle; both names must be NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn mq_pipeline_add_time_grouping(
+    pipeline: *mut MqPipeline,
+    time_grouping_type: *const c_char,
+    agg_type: *const c_char,
+) -> MqStatus {
+    guard(|| {
+        let pipeline = pipeline_mut(pipeline)?;
+        let time_grouping = engine(create_time_grouping(read_str(time_grouping_type, "time_grouping_type")?))?;
+        let aggregation = engine(create_aggregation(read_str(agg_type, "agg_type")?))?;
+        pipeline.add_time_grouping(time_grouping, aggregation);
+        Ok(())
+    })
+}
+
+/// Execute the pipeline over `len` values and timestamps.
+///
+/// On success `out` receives newly allocated columns that must be released with
+/// `mq_result_free`; on failure `out` is set to an empty result.
+///
+/// # Safety
+/// `pipeline` must be a live handle, `values` and `timestamps` must each point to
+/// `len` readable elements (or may be null when `len` is 0), and `out` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn mq_pipeline_execute(
+    pipeline: *const MqPipeline,
+    values: *const i64,
+    timestamps: *const i64,
+    len: usize,
+    out: *mut MqResult,
+) -> MqStatus {
+    guard(|| {
+        let out = out.as_mut().ok_or_else(|| invalid("out must not be null"))?;
+        *out = MqResult { values: ptr::null_mut(), timestamps: ptr::null_mut(), len: 0 };
+
+        let pipeline = pipeline.as_ref().ok_or_else(|| invalid("pipeline must not be null"))?;
+        let (values, timestamps) = if len == 0 {
+            (&[][..], &[][..])
+        } else {
+            if values.is_null() || timestamps.is_null() {
+                return Err(invalid("values and timestamps must not be null"));
+            }
+            (std::slice::from_raw_parts(values, len), std::slice::from_raw_parts(timestamps, len))
+        };
+
+        let (values, timestamps) = engine(pipeline.pipeline.execute_columns(values, timestamps))?;
+        let len = values.len();
+        *out = MqResult {
+            values: Box::into_raw(values.into_boxed_slice()) as *mut i64,
+            timestamps: Box::into_raw(timestamps.into_boxed_slice()) as *mut i64,
+            len,
+        };
+        Ok(())
+    })
+}
+
+/// Release the columns of a result and reset it to empty. Passing null is a no-op.
+///
+/// # Safety
+/// `result` must be null or a result filled in by `mq_pipeline_execute`.
+#[no_mangle]
+pub unsafe extern "C" fn mq_result_free(result: *mut MqResult) {
+    let Some(result) = result.as_mut() else {
+        return;
+    };
+    if !result.values.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(result.values, result.len)));
+    }
+    if !result.timestamps.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(result.timestamps, result.len)));
+    }
+    *result = MqResult { values: ptr::null_mut(), timestamps: ptr::null_mut(), len: 0 };
+}
+
+/// Message for the last failed call on this thread, or null if it succeeded.
+///
+/// The string stays valid until the next FFI call on the same thread.
+#[no_mangle]
+pub extern "C" fn mq_last_error() -> *const c_char {
+    LAST_ERROR.with(|slot| slot.borrow().as_ref().map_or(ptr::null(), |message| message.as_ptr()))
+}
@@ -0,0 +1,268 @@
+//! Golden-result regression testing for shared pipelines.
+//!
+//! A golden file pins a pipeline's output: record it once, commit it, and have the
+//! test suite compare every later run against it, so numerical changes across crate
+//! upgrades show up as test failures rather than silently shifted dashboards.
+//!
+//! The file format is plain text, one point per line sorted by label then timestamp,
+//! so diffs read well in review. After a `# metric-query golden v1` header, each
+//! line holds tab-separated fields: timestamp, value (`NaN` when missing), and the
+//! label if the point has one.
+//! Backslash, tab and newline in labels are escaped as `\\`, `\t` and `\n`.
+
+#[cfg(feature = "python")]
+use pyo3::exceptions::PyAssertionError;
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::compare::{compare_results, ResultDiff};
+#[cfg(feature = "python")]
+use crate::compare::extract_result_set;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::FloatMetric;
+use crate::transformations::MetricPipeline;
+
+/// First line of every golden file
+const HEADER: &str = "# metric-query golden v1";
+
+/// Environment variable that makes `assert_golden` re-record instead of comparing
+pub const UPDATE_ENV: &str = "METRIC_QUERY_UPDATE_GOLDEN";
+
+/// Most differences listed in a failure message
+const MAX_REPORTED: usize = 10;
+
+fn golden_error(reason: String) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: "golden".to_string(), reason }
+}
+
+fn escape_label(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r")
+}
+
+fn unescape_label(field: &str) -> Result<String, String> {
+    let mut label = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            label.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => label.push('\\'),
+            Some('t') => label.push('\t'),
+            Some('n') => label.push('\n'),
+            Some('r') => label.push('\r'),
+            other => return Err(format!("invalid escape '\\{}'", other.map(String::from).unwrap_or_default())),
+        }
+    }
+    Ok(label)
+}
+
+/// Write metrics in the golden format, sorted by label then timestamp
+pub fn write_golden<W: Write>(mut writer: W, metrics: &[FloatMetric]) -> MetricQueryResult<()> {
+    let mut sorted: Vec<&FloatMetric> = metrics.iter().collect();
+    sorted.sort_by(|a, b| (&a.label, a.timestamp).cmp(&(&b.label, b.timestamp)));
+
+    let mut text = String::new();
+    text.push_str(HEADER);
+    text.push('\n');
+    for metric in sorted {
+        // Debug formatting of f64 is the shortest string that parses back to the same value
+        let _ = write!(text, "{}\t{:?}", metric.timestamp, metric.value);
+        if let Some(label) = &metric.label {
+            let _ = write!(text, "\t{}", escape_label(label));
+        }
+        text.push('\n');
+    }
+    writer
+        .write_all(text.as_bytes())
+        .and_then(|_| writer.flush())
+        .map_err(|e| golden_error(format!("Failed to write golden results: {}", e)))
+}
+
+/// Read metrics written by `write_golden`
+pub fn read_golden<R: BufRead>(reader: R) -> MetricQueryResult<Vec<FloatMetric>> {
+    let mut lines = reader.lines();
+    match lines.next().transpose() {
+        Ok(Some(header)) if header == HEADER => {}
+        Ok(_) => return Err(golden_error(format!("Not a golden file: expected '{}' on the first line", HEADER))),
+        Err(e) => return Err(golden_error(format!("Failed to read golden results: {}", e))),
+    }
+
+    let mut metrics = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line.map_err(|e| golden_error(format!("Failed to read golden results: {}", e)))?;
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let invalid = |reason: String| golden_error(format!("Line {}: {}", index + 2, reason));
+        let mut fields = line.splitn(3, '\t');
+        let timestamp = fields.next().unwrap_or_default();
+        let timestamp: i64 = timestamp.parse().map_err(|_| invalid(format!("invalid timestamp '{}'", timestamp)))?;
+        let value = fields.next().ok_or_else(|| invalid("missing value".to_string()))?;
+        let value: f64 = value.parse().map_err(|_| invalid(format!("invalid value '{}'", value)))?;
+        let label = fields.next().map(unescape_label).transpose().map_err(invalid)?;
+        metrics.push(FloatMetric::new(Some(value), timestamp, label));
+    }
+    Ok(metrics)
+}
+
+impl ResultDiff {
+    /// Human-readable listing of the differences, truncated after the first few of each kind
+    pub fn describe(&self) -> String {
+        fn point(label: &Option<String>, timestamp: i64) -> String {
+            match label {
+                Some(label) => format!("{}@{}", label, timestamp),
+                None => format!("@{}", timestamp),
+            }
+        }
+        fn section<T>(text: &mut String, title: &str, items: &[T], line: impl Fn(&T) -> String) {
+            if items.is_empty() {
+                return;
+            }
+            let _ = writeln!(text, "{} ({}):", title, items.len());
+            for item in items.iter().take(MAX_REPORTED) {
+                let _ = writeln!(text, "  {}", line(item));
+            }
+            if items.len() > MAX_REPORTED {
+                let _ = writeln!(text, "  ... and {} more", items.len() - MAX_REPORTED);
+            }
+        }
+
+        let mut text = String::new();
+        section(&mut text, "mismatched", &self.mismatched, |m| {
+            format!("{}: expected {}, got {}", point(&m.label, m.timestamp), m.expected, m.actual)
+        });
+        section(&mut text, "missing", &self.missing, |m| format!("{} = {}", point(&m.label, m.timestamp), m.value));
+        section(&mut text, "unexpected", &self.unexpected, |m| format!("{} = {}", point(&m.label, m.timestamp), m.value));
+        text
+    }
+}
+
+/// Run `pipeline` and return its output as float metrics
+fn pipeline_output(pipeline: &MetricPipeline) -> MetricQueryResult<Vec<FloatMetric>> {
+    Ok(pipeline.run()?.iter().map(FloatMetric::from).collect())
+}
+
+/// Run `pipeline` and compare its output with `expected`, ignoring order; values may
+/// differ by up to `tolerance`
+pub fn check_pipeline_result(pipeline: &MetricPipeline, expected: &[FloatMetric], tolerance: f64) -> MetricQueryResult<ResultDiff> {
+    Ok(compare_results(expected, &pipeline_output(pipeline)?, tolerance, 0))
+}
+
+/// Panic unless `pipeline` runs and produces `expected` within `tolerance`
+pub fn assert_pipeline_result(pipeline: &MetricPipeline, expected: &[FloatMetric], tolerance: f64) {
+    match check_pipeline_result(pipeline, expected, tolerance) {
+        Ok(diff) if diff.is_empty() => {}
+        Ok(diff) => panic!("pipeline result differs from expected:\n{}", diff.describe()),
+        Err(e) => panic!("pipeline failed: {}", e),
+    }
+}
+
+/// Run `pipeline` and write its output to the golden file at `path`
+pub fn record_golden(pipeline: &MetricPipeline, path: impl AsRef<Path>) -> MetricQueryResult<()> {
+    let path = path.as_ref();
+    let output = pipeline_output(pipeline)?;
+    let file = File::create(path)
+        .map_err(|e| golden_error(format!("Failed to create golden file {}: {}", path.display(), e)))?;
+    write_golden(BufWriter::new(file), &output)
+}
+
+/// Run `pipeline` and compare its output with the golden file at `path`
+pub fn check_golden(pipeline: &MetricPipeline, path: impl AsRef<Path>, tolerance: f64) -> MetricQueryResult<ResultDiff> {
+    let path = path.as_ref();
+    let file = File::open(path).map_err(|e| {
+        golden_error(format!(
+            "Failed to open golden file {}: {}. Record it by setting {}=1",
+            path.display(),
+            e,
+            UPDATE_ENV
+        ))
+    })?;
+    check_pipeline_result(pipeline, &read_golden(BufReader::new(file))?, tolerance)
+}
+
+/// Whether `UPDATE_ENV` asks for golden files to be re-recorded
+fn update_requested() -> bool {
+    std::env::var_os(UPDATE_ENV).is_some_and(|value| !value.is_empty() && value != "0")
+}
+
+/// Panic unless `pipeline` reproduces the golden file at `path` within `tolerance`.
+///
+/// With `METRIC_QUERY_UPDATE_GOLDEN` set (to anything but "" or "0") the file is
+/// re-recorded from the current output instead.
+pub fn assert_golden(pipeline: &MetricPipeline, path: impl AsRef<Path>, tolerance: f64) {
+    let path = path.as_ref();
+    if update_requested() {
+        if let Err(e) = record_golden(pipeline, path) {
+            panic!("{}", e);
+        }
+        return;
+    }
+    match check_golden(pipeline, path, tolerance) {
+        Ok(diff) if diff.is_empty() => {}
+        Ok(diff) => panic!("pipeline result differs from {}:\n{}", path.display(), diff.describe()),
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Raise `AssertionError` unless the pipeline produces `expected` (a list of `Metric`
+/// or `FloatMetric`) within `tolerance`, in any order
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "assert_pipeline_result", signature = (pipeline, expected, tolerance=0.0))]
+pub fn py_assert_pipeline_result(
+    py: Python<'_>,
+    pipeline: &MetricPipeline,
+    expected: &Bound<'_, PyAny>,
+    tolerance: f64,
+) -> PyResult<()> {
+    let expected = extract_result_set(expected)?;
+    let diff = py.allow_threads(|| check_pipeline_result(pipeline, &expected, tolerance))?;
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(PyAssertionError::new_err(format!("pipeline result differs from expected:\n{}", diff.describe())))
+    }
+}
+
+/// Run the pipeline and write its output to the golden file at `path`
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "record_golden")]
+pub fn py_record_golden(py: Python<'_>, pipeline: &MetricPipeline, path: std::path::PathBuf) -> PyResult<()> {
+    Ok(py.allow_threads(|| record_golden(pipeline, path))?)
+}
+
+/// Raise `AssertionError` unless the pipeline reproduces the golden file at `path`.
+///
+/// With `update=True`, or when `update` is `None` and `METRIC_QUERY_UPDATE_GOLDEN` is
+/// set, the file is re-recorded from the current output instead.
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "assert_golden", signature = (pipeline, path, tolerance=0.0, update=None))]
+pub fn py_assert_golden(
+    py: Python<'_>,
+    pipeline: &MetricPipeline,
+    path: std::path::PathBuf,
+    tolerance: f64,
+    update: Option<bool>,
+) -> PyResult<()> {
+    if update.unwrap_or_else(update_requested) {
+        return Ok(py.allow_threads(|| record_golden(pipeline, &path))?);
+    }
+    let diff = py.allow_threads(|| check_golden(pipeline, &path, tolerance))?;
+    if diff.is_empty() {
+        Ok(())
+    } else {
+        Err(PyAssertionError::new_err(format!(
+            "pipeline result differs from {}:\n{}",
+            path.display(),
+            diff.describe()
+        )))
+    }
+}
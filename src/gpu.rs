@@ -0,0 +1,375 @@
+//! GPU backend for large group-by aggregations (`gpu` feature).
+//!
+//! `TimeGroupingTransformation` hands inputs of at least `GPU_GROUPING_THRESHOLD`
+//! metrics to this module when its aggregation has a `Reduction` (the built-in sum,
+//! avg, min and max). Group keys are still computed on the CPU; the values are then
+//! laid out contiguously by group and cut into segments of at most `SEGMENT_LEN`, and
+//! a compute shader reduces every segment to a `Partial` holding an exact 128-bit sum,
+//! the minimum and the maximum. The CPU merges each group's partials and finishes them.
+//!
+//! The device is opened once per process. Without a usable adapter, or when a
+//! dispatch fails, the same partials are computed on the CPU, so results never depend
+//! on the hardware. As on the CPU, sums under `OverflowPolicy::Error` check the group
+//! total rather than every running total, so grouping gives the same result on either
+//! side of `GPU_GROUPING_THRESHOLD`.
+
+use std::ops::Range;
+use std::sync::{mpsc, OnceLock};
+
+use wgpu::util::DeviceExt;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::plugin_impls::{OverflowPolicy, RoundingMode};
+
+/// Group-by-time inputs smaller than this stay on the CPU
+pub const GPU_GROUPING_THRESHOLD: usize = 1 << 20;
+
+/// Most values a single shader invocation reduces
+pub const SEGMENT_LEN: usize = 4096;
+
+/// Invocations per workgroup, matching `@workgroup_size` in the shader
+const WORKGROUP_SIZE: usize = 64;
+
+/// Size of one partial in the shader's output buffer
+const PARTIAL_BYTES: usize = 32;
+
+// WGSL has no 64-bit integers, so each value is a (low, high) pair of u32 words and the
+// sum a four-word two's complement integer.
+const SHADER: &str = r#"
+struct Partial {
+    sum: vec4<u32>,
+    min: vec2<u32>,
+    max: vec2<u32>,
+}
+
+@group(0) @binding(0) var<storage, read> values: array<vec2<u32>>;
+@group(0) @binding(1) var<storage, read> segments: array<vec2<u32>>;
+@group(0) @binding(2) var<storage, read_write> partials: array<Partial>;
+
+fn less(a: vec2<u32>, b: vec2<u32>) -> bool {
+    let ah = bitcast<i32>(a.y);
+    let bh = bitcast<i32>(b.y);
+    return ah < bh || (ah == bh && a.x < b.x);
+}
+
+fn add_carry(a: u32, b: u32, carry: u32) -> vec2<u32> {
+    let t = a + b;
+    let s = t + carry;
+    return vec2<u32>(s, select(0u, 1u, t < b) + select(0u, 1u, s < t));
+}
+
+@compute @workgroup_size(64)
+fn reduce(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let index = id.x + id.y * groups.x * 64u;
+    if (index >= arrayLength(&segments)) {
+        return;
+    }
+    let range = segments[index];
+    var sum = vec4<u32>(0u);
+    var lo = values[range.x];
+    var hi = values[range.x];
+    for (var i = range.x; i < range.y; i++) {
+        let v = values[i];
+        let ext = select(0u, 0xffffffffu, bitcast<i32>(v.y) < 0);
+        let r0 = add_carry(sum.x, v.x, 0u);
+        let r1 = add_carry(sum.y, v.y, r0.y);
+        let r2 = add_carry(sum.z, ext, r1.y);
+        let r3 = add_carry(sum.w, ext, r2.y);
+        sum = vec4<u32>(r0.x, r1.x, r2.x, r3.x);
+        if (less(v, lo)) {
+            lo = v;
+        }
+        if (less(hi, v)) {
+            hi = v;
+        }
+    }
+    partials[index] = Partial(sum, lo, hi);
+}
+"#;
+
+/// Summary of a non-empty run of values, enough to finish any `Reduction`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partial {
+    pub sum: i128,
+    pub count: u64,
+    pub min: i64,
+    pub max: i64,
+}
+
+impl Partial {
+    /// Summarize `values` on the CPU; `None` when empty
+    pub fn of(values: &[i64]) -> Option<Self> {
+        let (&first, _) = values.split_first()?;
+        let mut partial = Self { sum: 0, count: values.len() as u64, min: first, max: first };
+        for &value in values {
+            partial.sum += i128::from(value);
+            partial.min = partial.min.min(value);
+            partial.max = partial.max.max(value);
+        }
+        Some(partial)
+    }
+
+    /// Fold another run of the same group into this one
+    pub fn merge(&mut self, other: &Partial) {
+        self.sum += other.sum;
+        self.count += other.count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+    }
+}
+
+/// An aggregation the GPU backend can compute from group partials
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reduction {
+    Sum(OverflowPolicy),
+    Avg(RoundingMode),
+    Min,
+    Max,
+}
+
+impl Reduction {
+    /// The aggregate of a group from its merged partial
+    pub fn finish(self, partial: &Partial) -> MetricQueryResult<i64> {
+        match self {
+            Self::Sum(OverflowPolicy::Saturate) => Ok(partial.sum.clamp(i64::MIN as i128, i64::MAX as i128) as i64),
+            Self::Sum(OverflowPolicy::Error) => i64::try_from(partial.sum)
+                .map_err(|_| MetricQueryError::ArithmeticOverflow { operation: "sum".to_string() }),
+            Self::Avg(rounding) => Ok(rounding.divide(partial.sum, i128::from(partial.count)) as i64),
+            Self::Min => Ok(partial.min),
+            Self::Max => Ok(partial.max),
+        }
+    }
+}
+
+/// Reduce `values` per group, where `values[i]` belongs to group `groups[i]` and every
+/// group below `group_count` has at least one value. Runs on the GPU when one is
+/// available and on the CPU otherwise.
+pub fn reduce_groups(values: &[i64], groups: &[usize], group_count: usize) -> Vec<Partial> {
+    // Counting sort: lay each group's values out contiguously
+    let mut offsets = vec![0usize; group_count + 1];
+    for &group in groups {
+        offsets[group + 1] += 1;
+    }
+    for group in 0..group_count {
+        offsets[group + 1] += offsets[group];
+    }
+    let mut next = offsets.clone();
+    let mut sorted = vec![0i64; values.len()];
+    for (&value, &group) in values.iter().zip(groups) {
+        sorted[next[group]] = value;
+        next[group] += 1;
+    }
+
+    // Cut groups into segments so one huge group doesn't serialize on a single invocation
+    let mut segments = Vec::with_capacity(group_count + values.len() / SEGMENT_LEN);
+    let mut segment_groups = Vec::with_capacity(segments.capacity());
+    for group in 0..group_count {
+        for start in (offsets[group]..offsets[group + 1]).step_by(SEGMENT_LEN) {
+            segments.push(start..(start + SEGMENT_LEN).min(offsets[group + 1]));
+            segment_groups.push(group);
+        }
+    }
+
+    let partials = match device() {
+        Some(gpu) => gpu.reduce(&sorted, &segments).unwrap_or_else(|e| {
+            log::warn!("GPU reduction failed ({}); reducing on the CPU", e);
+            reduce_on_cpu(&sorted, &segments)
+        }),
+        None => reduce_on_cpu(&sorted, &segments),
+    };
+
+    let mut merged: Vec<Option<Partial>> = vec![None; group_count];
+    for (partial, group) in partials.iter().zip(segment_groups) {
+        match &mut merged[group] {
+            Some(total) => total.merge(partial),
+            slot => *slot = Some(*partial),
+        }
+    }
+    merged.into_iter().map(|partial| partial.expect("every group has a value")).collect()
+}
+
+fn reduce_on_cpu(values: &[i64], segments: &[Range<usize>]) -> Vec<Partial> {
+    segments
+        .iter()
+        .map(|segment| Partial::of(&values[segment.clone()]).expect("segments are not empty"))
+        .collect()
+}
+
+/// Whether a GPU adapter was found; opens the device on first use
+pub fn available() -> bool {
+    device().is_some()
+}
+
+/// The process-wide device, if an adapter could be opened
+fn device() -> Option<&'static Gpu> {
+    static DEVICE: OnceLock<Option<Gpu>> = OnceLock::new();
+    DEVICE
+        .get_or_init(|| match pollster::block_on(Gpu::open()) {
+            Ok(gpu) => Some(gpu),
+            Err(e) => {
+                log::info!("GPU backend unavailable ({}); group-by aggregations stay on the CPU", e);
+                None
+            }
+        })
+        .as_ref()
+}
+
+struct Gpu {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    /// Largest storage buffer a binding may address, in bytes
+    max_binding: usize,
+    max_workgroups: usize,
+}
+
+impl Gpu {
+    async fn open() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+        let limits = adapter.limits();
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("metric-query"),
+                required_limits: limits.clone(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("metric-query reduce"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("metric-query reduce"),
+            layout: None,
+            module: &module,
+            entry_point: Some("reduce"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+        log::info!("GPU backend using {}", adapter.get_info().name);
+
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            max_binding: limits.max_storage_buffer_binding_size as usize,
+            max_workgroups: limits.max_compute_workgroups_per_dimension as usize,
+        })
+    }
+
+    /// Reduce every segment of `values`, splitting the work into dispatches that fit the
+    /// device's buffer limits
+    fn reduce(&self, values: &[i64], segments: &[Range<usize>]) -> Result<Vec<Partial>, String> {
+        let max_values = self.max_binding / std::mem::size_of::<i64>();
+        let max_segments = (self.max_binding / PARTIAL_BYTES).min(self.max_workgroups * self.max_workgroups * WORKGROUP_SIZE);
+
+        let mut partials = Vec::with_capacity(segments.len());
+        let mut first = 0;
+        while first < segments.len() {
+            let base = segments[first].start;
+            let mut last = first + 1;
+            while last < segments.len() && last - first < max_segments && segments[last].end - base <= max_values {
+                last += 1;
+            }
+            partials.extend(self.dispatch(&values[base..segments[last - 1].end], &segments[first..last], base)?);
+            first = last;
+        }
+        Ok(partials)
+    }
+
+    /// Run the shader over one batch of segments whose offsets are relative to `base`
+    fn dispatch(&self, values: &[i64], segments: &[Range<usize>], base: usize) -> Result<Vec<Partial>, String> {
+        let bounds: Vec<[u32; 2]> =
+            segments.iter().map(|s| [(s.start - base) as u32, (s.end - base) as u32]).collect();
+        let output_size = (segments.len() * PARTIAL_BYTES) as u64;
+
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let storage = |label, contents: &[u8]| {
+            self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(label),
+                contents,
+                usage: wgpu::BufferUsages::STORAGE,
+            })
+        };
+        let values_buffer = storage("values", bytemuck::cast_slice(values));
+        let segments_buffer = storage("segments", bytemuck::cast_slice(&bounds));
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("partials"),
+            size: output_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("partials readback"),
+            size: output_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: values_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: segments_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: output.as_entire_binding() },
+            ],
+        });
+
+        // Spread the workgroups over two dimensions once they exceed the per-dimension limit
+        let workgroups = segments.len().div_ceil(WORKGROUP_SIZE);
+        let columns = workgroups.min(self.max_workgroups);
+        let rows = workgroups.div_ceil(columns);
+
+        let mut encoder = self.device.create_command_encoder(&Default::default());
+        {
+            let mut pass = encoder.begin_compute_pass(&Default::default());
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(columns as u32, rows as u32, 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &readback, 0, output_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        for scope in ["validation", "out of memory"] {
+            if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+                return Err(format!("{} error: {}", scope, error));
+            }
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        readback.slice(..).map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::Wait).map_err(|e| e.to_string())?;
+        receiver
+            .recv()
+            .map_err(|e| e.to_string())?
+            .map_err(|e| e.to_string())?;
+
+        let words: Vec<u32> = bytemuck::cast_slice(&readback.slice(..).get_mapped_range()).to_vec();
+        readback.unmap();
+
+        let pair = |lo: u32, hi: u32| ((u64::from(hi) << 32) | u64::from(lo)) as i64;
+        Ok(words
+            .chunks_exact(PARTIAL_BYTES / 4)
+            .zip(segments)
+            .map(|(w, segment)| Partial {
+                sum: ((u128::from(w[3]) << 96) | (u128::from(w[2]) << 64) | (u128::from(w[1]) << 32) | u128::from(w[0])) as i128,
+                count: segment.len() as u64,
+                min: pair(w[4], w[5]),
+                max: pair(w[6], w[7]),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,108 @@
+//! Graph description of a pipeline for documentation and debugging tools.
+//!
+//! A pipeline is a chain today: input, then each step in order, then output.
+//! Nodes and edges are kept general so branching pipelines can describe forks
+//! and joins the same way.
+
+use std::fmt::Write;
+
+/// What a graph node stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "snake_case"))]
+pub enum NodeKind {
+    /// The pipeline's input metrics
+    Input,
+    /// A transformation step
+    Step,
+    /// The result returned to the caller
+    Output,
+}
+
+impl NodeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Input => "input",
+            Self::Step => "step",
+            Self::Output => "output",
+        }
+    }
+}
+
+/// One node of a pipeline graph
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GraphNode {
+    /// Identifier unique within the graph, e.g. "input" or "step0"
+    pub id: String,
+    /// Human-readable description, e.g. the step name
+    pub label: String,
+    pub kind: NodeKind,
+}
+
+/// A directed edge along which metrics flow
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+}
+
+/// Nodes and edges describing how metrics flow through a pipeline
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct PipelineGraph {
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl PipelineGraph {
+    /// Graph of a chain of steps fed by `input_count` metrics
+    pub fn linear(input_count: usize, steps: &[String]) -> Self {
+        let mut nodes = vec![GraphNode {
+            id: "input".to_string(),
+            label: format!("input ({} metrics)", input_count),
+            kind: NodeKind::Input,
+        }];
+        nodes.extend(steps.iter().enumerate().map(|(index, name)| GraphNode {
+            id: format!("step{}", index),
+            label: name.clone(),
+            kind: NodeKind::Step,
+        }));
+        nodes.push(GraphNode { id: "output".to_string(), label: "output".to_string(), kind: NodeKind::Output });
+
+        let edges = nodes
+            .windows(2)
+            .map(|pair| GraphEdge { from: pair[0].id.clone(), to: pair[1].id.clone() })
+            .collect();
+        Self { nodes, edges }
+    }
+
+    /// Graphviz DOT source, laid out left to right
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph pipeline {\n    rankdir=LR;\n");
+        for node in &self.nodes {
+            let shape = match node.kind {
+                NodeKind::Input | NodeKind::Output => "ellipse",
+                NodeKind::Step => "box",
+            };
+            // Writing to a String can't fail
+            let _ = writeln!(dot, "    {} [label=\"{}\", shape={}];", node.id, escape_dot(&node.label), shape);
+        }
+        for edge in &self.edges {
+            let _ = writeln!(dot, "    {} -> {};", edge.from, edge.to);
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The graph as JSON: `{"nodes": [{"id", "label", "kind"}], "edges": [{"from", "to"}]}`
+    #[cfg(feature = "spec")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("graph nodes and edges always serialize")
+    }
+}
+
+/// Escape a label for use inside a double-quoted DOT string
+fn escape_dot(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
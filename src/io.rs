@@ -0,0 +1,130 @@
+//! File formats for the `metric-query` command-line tool, enabled with the `cli` feature.
+//!
+//! Every format carries `value`, `timestamp` and an optional `label` column. Values
+//! are read as floats so the tool can run integer and fractional data alike; an
+//! empty or null value is read as missing (NaN) and written back the same way.
+
+use std::io::{BufRead, Read, Write};
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::FloatMetric;
+use crate::spec::MetricRecord;
+
+/// Supported input and output formats
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+    /// Input only
+    Parquet,
+}
+
+impl Format {
+    /// Parse a format name: "csv", "ndjson" (or "jsonl") or "parquet"
+    pub fn parse(format: &str) -> MetricQueryResult<Self> {
+        match format {
+            "csv" => Ok(Self::Csv),
+            "ndjson" | "jsonl" => Ok(Self::Ndjson),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "format".to_string(),
+                reason: format!(
+                    "Unknown format '{}'. Expected 'csv', 'ndjson' or 'parquet'",
+                    format
+                ),
+            }),
+        }
+    }
+
+    /// Infer the format from a file extension
+    pub fn from_path(path: &std::path::Path) -> Option<Self> {
+        path.extension().and_then(|ext| ext.to_str()).and_then(|ext| Self::parse(ext).ok())
+    }
+}
+
+fn io_error(operation: &str, reason: impl std::fmt::Display) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: operation.to_string(), reason: reason.to_string() }
+}
+
+/// Read CSV with a header row naming the `value`, `timestamp` and optional `label` columns
+pub fn read_csv<R: Read>(reader: R) -> MetricQueryResult<Vec<FloatMetric>> {
+    csv::Reader::from_reader(reader)
+        .deserialize::<MetricRecord>()
+        .map(|record| record.map(FloatMetric::from).map_err(|e| io_error("read_csv", e)))
+        .collect()
+}
+
+/// Read newline-delimited JSON objects; blank lines are skipped
+pub fn read_ndjson<R: BufRead>(reader: R) -> MetricQueryResult<Vec<FloatMetric>> {
+    let mut metrics = Vec::new();
+    for (index, line) in reader.lines().enumerate() {
+        let line = line.map_err(|e| io_error("read_ndjson", e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: MetricRecord = serde_json::from_str(&line)
+            .map_err(|e| io_error("read_ndjson", format!("line {}: {}", index + 1, e)))?;
+        metrics.push(record.into());
+    }
+    Ok(metrics)
+}
+
+/// Read a Parquet file with an integer or float `value` column, an integer
+/// `timestamp` column and an optional string `label` column
+#[cfg(feature = "parquet")]
+pub fn read_parquet(file: std::fs::File) -> MetricQueryResult<Vec<FloatMetric>> {
+    use arrow_array::{Array, Float64Array, Int64Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)
+        .and_then(|builder| builder.build())
+        .map_err(|e| io_error("read_parquet", e))?;
+
+    let mut metrics = Vec::new();
+    for batch in reader {
+        let batch = batch.map_err(|e| io_error("read_parquet", e))?;
+        let column = |name: &str| {
+            batch.column_by_name(name).ok_or_else(|| io_error("read_parquet", format!("missing '{}' column", name)))
+        };
+
+        let values = column("value")?;
+        let timestamps = column("timestamp")?
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .ok_or_else(|| io_error("read_parquet", "'timestamp' must be a 64-bit integer column"))?;
+        let labels = batch.column_by_name("label").and_then(|c| c.as_any().downcast_ref::<StringArray>());
+
+        let value_at: Box<dyn Fn(usize) -> Option<f64>> =
+            if let Some(floats) = values.as_any().downcast_ref::<Float64Array>() {
+                Box::new(move |i| floats.is_valid(i).then(|| floats.value(i)))
+            } else if let Some(ints) = values.as_any().downcast_ref::<Int64Array>() {
+                Box::new(move |i| ints.is_valid(i).then(|| ints.value(i) as f64))
+            } else {
+                return Err(io_error("read_parquet", "'value' must be a 64-bit integer or float column"));
+            };
+
+        for i in 0..batch.num_rows() {
+            let label = labels.filter(|l| l.is_valid(i)).map(|l| l.value(i).to_string());
+            metrics.push(FloatMetric::new(value_at(i), timestamps.value(i), label));
+        }
+    }
+    Ok(metrics)
+}
+
+/// Write CSV with a `value,timestamp,label` header
+pub fn write_csv<W: Write>(writer: W, metrics: &[FloatMetric]) -> MetricQueryResult<()> {
+    let mut writer = csv::Writer::from_writer(writer);
+    for metric in metrics {
+        writer.serialize(MetricRecord::from(metric)).map_err(|e| io_error("write_csv", e))?;
+    }
+    writer.flush().map_err(|e| io_error("write_csv", e))
+}
+
+/// Write one JSON object per line
+pub fn write_ndjson<W: Write>(mut writer: W, metrics: &[FloatMetric]) -> MetricQueryResult<()> {
+    for metric in metrics {
+        serde_json::to_writer(&mut writer, &MetricRecord::from(metric)).map_err(|e| io_error("write_ndjson", e))?;
+        writeln!(writer).map_err(|e| io_error("write_ndjson", e))?;
+    }
+    writer.flush().map_err(|e| io_error("write_ndjson", e))
+}
@@ -0,0 +1,113 @@
+//! Bridge from the `log` crate to Python's `logging` module.
+//!
+//! Internal events (per-step counts, dropped metrics, grouping strategy, plugin
+//! registration) are logged with the `log` macros. Once the extension module is
+//! imported they reach the `metric_query_library` logger hierarchy, named after
+//! the Rust module (e.g. `metric_query_library.transformations`), so the usual
+//! handlers and levels apply. Only warnings are forwarded until `set_log_level`
+//! lowers the threshold.
+//!
+//! Records are only forwarded from the thread running the pipeline; worker
+//! threads must not log, since they cannot take the GIL while the caller holds it.
+//!
+//! Each Python logger's effective level is read once and cached, so records it
+//! would discard are dropped without taking the GIL. `set_log_level` clears the
+//! cache, so call it after changing Python logger levels.
+
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use pyo3::prelude::*;
+
+/// Effective level of each Python logger records were sent to
+static PYTHON_LEVELS: RwLock<BTreeMap<String, u32>> = RwLock::new(BTreeMap::new());
+
+/// The cached effective level of the Python logger `name`, if read already
+fn cached_level(name: &str) -> Option<u32> {
+    PYTHON_LEVELS.read().ok()?.get(name).copied()
+}
+
+/// Forwards `log` records to Python loggers
+pub struct PythonLogger;
+
+/// Python `logging` level for a `log` level
+fn python_level(level: Level) -> u32 {
+    match level {
+        Level::Error => 40,
+        Level::Warn => 30,
+        Level::Info => 20,
+        Level::Debug => 10,
+        Level::Trace => 5,
+    }
+}
+
+/// Python logger name for a record target such as `metric_query_library::transformations`
+fn logger_name(target: &str) -> String {
+    let module = target.split("::").skip(1).collect::<Vec<_>>().join(".");
+    if module.is_empty() {
+        "metric_query_library".to_string()
+    } else {
+        format!("metric_query_library.{}", module)
+    }
+}
+
+impl Log for PythonLogger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let name = logger_name(record.target());
+        let level = python_level(record.level());
+        if cached_level(&name).is_some_and(|threshold| level < threshold) {
+            return;
+        }
+        Python::with_gil(|py| {
+            let result = py.import("logging").and_then(|logging| {
+                let logger = logging.call_method1("getLogger", (name.as_str(),))?;
+                let threshold: u32 = logger.call_method0("getEffectiveLevel")?.extract()?;
+                if let Ok(mut levels) = PYTHON_LEVELS.write() {
+                    levels.insert(name.clone(), threshold);
+                }
+                if level >= threshold {
+                    logger.call_method1("log", (level, record.args().to_string()))?;
+                }
+                Ok(())
+            });
+            // A failing handler must not take the pipeline down; Python prints it instead
+            if let Err(e) = result {
+                e.print(py);
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+/// Install the bridge as the process-wide logger, forwarding warnings and errors.
+/// Does nothing if another `log` logger is already installed.
+pub fn install() {
+    if log::set_logger(&PythonLogger).is_ok() {
+        log::set_max_level(LevelFilter::Warn);
+    }
+}
+
+/// Forward records at `level` or more severe to Python: "trace", "debug", "info",
+/// "warn", "error" or "off". Python logger levels still filter what is printed, and
+/// are read again after this call.
+#[pyfunction]
+#[pyo3(name = "set_log_level")]
+pub fn py_set_log_level(level: &str) -> PyResult<()> {
+    let level: LevelFilter = level
+        .parse()
+        .map_err(|_| pyo3::exceptions::PyValueError::new_err(format!("Unknown log level: {}", level)))?;
+    log::set_max_level(level);
+    if let Ok(mut levels) = PYTHON_LEVELS.write() {
+        levels.clear();
+    }
+    Ok(())
+}
@@ -0,0 +1,93 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::sync::Arc;
+
+use crate::models::Metric;
+#[cfg(feature = "python")]
+use crate::models::metric::MetricInput;
+#[cfg(feature = "python")]
+use crate::models::MetricSeries;
+use crate::transformations::MetricPipeline;
+
+/// An immutable batch of metrics that several pipelines can read without copying.
+///
+/// Converting a Python list into metrics happens once, when the dataset is built;
+/// every pipeline created from it shares the same `Arc<[Metric]>`.
+#[cfg_attr(feature = "python", pyclass(frozen))]
+#[derive(Debug, Clone)]
+pub struct MetricDataset {
+    metrics: Arc<[Metric]>,
+}
+
+impl MetricDataset {
+    /// Wrap metrics so pipelines can share them
+    pub fn new(metrics: impl Into<Arc<[Metric]>>) -> Self {
+        Self { metrics: metrics.into() }
+    }
+
+    /// The shared metrics
+    pub fn shared(&self) -> Arc<[Metric]> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Start a pipeline over the shared metrics
+    pub fn pipeline(&self) -> MetricPipeline {
+        MetricPipeline::from_shared(self.shared())
+    }
+
+    pub fn len(&self) -> usize {
+        self.metrics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.metrics.is_empty()
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl MetricDataset {
+    /// Build from metrics given as `Metric`s, tuples or dicts
+    #[new]
+    fn py_new(metrics: Vec<MetricInput>) -> Self {
+        Self::new(metrics.into_iter().map(|MetricInput(metric)| metric).collect::<Vec<_>>())
+    }
+
+    /// A copy of the metrics as a Python list
+    #[getter]
+    fn metrics(&self) -> Vec<Metric> {
+        self.metrics.to_vec()
+    }
+
+    /// Start a pipeline that reads this dataset without copying it
+    #[pyo3(name = "pipeline")]
+    fn py_pipeline(&self) -> MetricPipeline {
+        self.pipeline()
+    }
+
+    fn __len__(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Pipeline input from Python: a shared dataset, a `MetricSeries` to flatten, or a
+/// list of metrics to copy in, each a `Metric`, a tuple or a dict (see `MetricInput`)
+#[cfg(feature = "python")]
+#[derive(FromPyObject)]
+pub enum PipelineInput {
+    Dataset(MetricDataset),
+    Series(MetricSeries),
+    Metrics(Vec<MetricInput>),
+}
+
+#[cfg(feature = "python")]
+impl PipelineInput {
+    /// The input as shared metrics; a dataset is shared as is, a list is converted once
+    pub fn into_shared(self) -> Arc<[Metric]> {
+        match self {
+            Self::Dataset(dataset) => dataset.metrics,
+            Self::Series(series) => series.to_metrics().into(),
+            Self::Metrics(metrics) => metrics.into_iter().map(|MetricInput(metric)| metric).collect(),
+        }
+    }
+}
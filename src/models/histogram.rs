@@ -0,0 +1,174 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::plugins::{GroupPoint, TimeGroupingPlugin};
+
+/// A Prometheus-style histogram observed at one point in time.
+///
+/// # Properties
+///
+/// * `bounds` - Ascending upper bounds of the finite buckets.
+/// * `counts` - Observations per bucket (not cumulative), with one extra
+///   trailing entry for the `+Inf` bucket.
+/// * `sum` - Sum of all observed values.
+///
+/// Histograms with identical bounds can be merged, so per-second histograms can
+/// be rolled up into hourly ones and queried for quantiles afterwards.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramMetric {
+    pub bounds: Vec<f64>,
+    pub counts: Vec<u64>,
+    pub sum: f64,
+    /// The time at which the histogram was collected.
+    pub timestamp: i64,
+    pub label: Option<String>,
+}
+
+fn histogram_error(reason: impl Into<String>) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: "histogram".to_string(), reason: reason.into() }
+}
+
+impl HistogramMetric {
+    /// Create a histogram, checking that the bounds ascend and that there is
+    /// one count per bound plus the `+Inf` bucket
+    pub fn new(
+        bounds: Vec<f64>,
+        counts: Vec<u64>,
+        sum: f64,
+        timestamp: i64,
+        label: Option<String>,
+    ) -> MetricQueryResult<Self> {
+        if bounds.iter().any(|b| !b.is_finite()) || bounds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(histogram_error("bucket bounds must be finite and strictly ascending"));
+        }
+        if counts.len() != bounds.len() + 1 {
+            return Err(histogram_error(format!(
+                "expected {} counts ({} bounds plus +Inf), got {}",
+                bounds.len() + 1,
+                bounds.len(),
+                counts.len()
+            )));
+        }
+        Ok(Self { bounds, counts, sum, timestamp, label })
+    }
+
+    /// Total number of observations
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Add another histogram's counts and sum to this one; the bounds must match
+    pub fn merge(&mut self, other: &HistogramMetric) -> MetricQueryResult<()> {
+        if self.bounds != other.bounds {
+            return Err(histogram_error("cannot merge histograms with different bucket bounds"));
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count = count.checked_add(*other).ok_or_else(|| MetricQueryError::ArithmeticOverflow {
+                operation: "histogram merge".to_string(),
+            })?;
+        }
+        self.sum += other.sum;
+        Ok(())
+    }
+
+    /// Estimate quantile `q` in [0, 1] by interpolating linearly inside the bucket
+    /// that holds it, as Prometheus' `histogram_quantile` does. The first bucket
+    /// starts at 0 (or at its bound, if that is negative), and a quantile that falls
+    /// in the `+Inf` bucket is reported as the highest finite bound.
+    pub fn quantile(&self, q: f64) -> MetricQueryResult<f64> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(histogram_error(format!("quantile must be between 0 and 1, got {}", q)));
+        }
+        let total = self.count();
+        if total == 0 {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+
+        let rank = q * total as f64;
+        let mut below = 0u64;
+        for (index, &count) in self.counts.iter().enumerate() {
+            if count == 0 || ((below + count) as f64) < rank {
+                below += count;
+                continue;
+            }
+            let Some(&upper) = self.bounds.get(index) else {
+                // The +Inf bucket has no upper edge to interpolate towards
+                return Ok(self.bounds.last().copied().unwrap_or(f64::NAN));
+            };
+            let lower = match index {
+                0 => upper.min(0.0),
+                _ => self.bounds[index - 1],
+            };
+            return Ok(lower + (upper - lower) * (rank - below as f64) / count as f64);
+        }
+        Ok(self.bounds.last().copied().unwrap_or(f64::NAN))
+    }
+
+    /// Merge histograms that share a label and time group.
+    ///
+    /// Each result carries its group's timestamp; results are ordered by label,
+    /// then timestamp.
+    pub fn group_by_time(
+        histograms: &[HistogramMetric],
+        grouping: &dyn TimeGroupingPlugin,
+    ) -> MetricQueryResult<Vec<HistogramMetric>> {
+        let mut groups: BTreeMap<(Option<&str>, i64), HistogramMetric> = BTreeMap::new();
+        for histogram in histograms {
+            let point = GroupPoint { timestamp: histogram.timestamp, label: histogram.label.as_deref(), value: None };
+            let timestamp = grouping.get_point_group_timestamp(&point)?;
+            match groups.get_mut(&(histogram.label.as_deref(), timestamp)) {
+                Some(group) => group.merge(histogram)?,
+                None => {
+                    let group = HistogramMetric { timestamp, ..histogram.clone() };
+                    groups.insert((histogram.label.as_deref(), timestamp), group);
+                }
+            }
+        }
+        Ok(groups.into_values().collect())
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl HistogramMetric {
+    #[new]
+    #[pyo3(signature = (bounds, counts, timestamp, label=None, sum=0.0))]
+    fn py_new(
+        bounds: Vec<f64>,
+        counts: Vec<u64>,
+        timestamp: i64,
+        label: Option<String>,
+        sum: f64,
+    ) -> PyResult<Self> {
+        Ok(Self::new(bounds, counts, sum, timestamp, label)?)
+    }
+
+    /// Total number of observations
+    #[pyo3(name = "count")]
+    fn py_count(&self) -> u64 {
+        self.count()
+    }
+
+    /// Add another histogram's counts and sum to this one
+    #[pyo3(name = "merge")]
+    fn py_merge(&mut self, other: HistogramMetric) -> PyResult<()> {
+        Ok(self.merge(&other)?)
+    }
+
+    /// Estimate quantile `q` in [0, 1] from the buckets
+    #[pyo3(name = "quantile")]
+    fn py_quantile(&self, q: f64) -> PyResult<f64> {
+        Ok(self.quantile(q)?)
+    }
+
+    /// Merge histograms per label into "minute", "hour" or "day" groups
+    #[staticmethod]
+    #[pyo3(name = "group_by_time")]
+    fn py_group_by_time(histograms: Vec<HistogramMetric>, grouping: &str) -> PyResult<Vec<HistogramMetric>> {
+        let grouping = crate::plugin_impls::create_time_grouping(grouping)?;
+        Ok(Self::group_by_time(&histograms, grouping.as_ref())?)
+    }
+}
@@ -1,4 +1,240 @@
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use pyo3::types::{PyDict, PyString};
+use std::collections::BTreeMap;
+#[cfg(feature = "python")]
+use std::collections::hash_map::DefaultHasher;
+#[cfg(feature = "python")]
+use std::convert::Infallible;
+#[cfg(feature = "python")]
+use std::hash::{Hash, Hasher};
+
+use crate::decimal::{format_decimal, parse_decimal};
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::time_range::{format_iso_timestamp, parse_iso_timestamp};
+
+/// A sample observation linked to a trace, so an aggregate can point at a
+/// concrete request (e.g. the slowest one behind a p99 bucket).
+#[cfg_attr(feature = "python", pyclass(get_all, set_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: String,
+    /// The observed value of the traced request
+    pub value: f64,
+    pub timestamp: i64,
+}
+
+impl Exemplar {
+    /// Create a new Exemplar
+    pub fn new(trace_id: String, value: f64, timestamp: i64) -> Self {
+        Self { trace_id, value, timestamp }
+    }
+
+    /// The exemplar with the highest value, so a merged point links to its worst trace
+    pub fn worst<'a>(exemplars: impl IntoIterator<Item = Option<&'a Exemplar>>) -> Option<&'a Exemplar> {
+        exemplars.into_iter().flatten().max_by(|a, b| a.value.total_cmp(&b.value))
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Exemplar {
+    #[new]
+    fn py_new(trace_id: String, value: f64, timestamp: i64) -> Self {
+        Self::new(trace_id, value, timestamp)
+    }
+}
+
+/// Kind of quantity a metric measures, which decides how its samples may be combined
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(rename_all = "snake_case"))]
+pub enum MetricType {
+    /// Cumulative total that only grows, apart from resets to zero (e.g. requests served)
+    Counter,
+    /// Reading that moves freely between samples (e.g. memory in use)
+    Gauge,
+    /// Count of observations in one bucket of a distribution
+    Histogram,
+}
+
+impl MetricType {
+    /// Parse a metric type name ("counter", "gauge" or "histogram")
+    pub fn parse(metric_type: &str) -> MetricQueryResult<Self> {
+        match metric_type {
+            "counter" => Ok(Self::Counter),
+            "gauge" => Ok(Self::Gauge),
+            "histogram" => Ok(Self::Histogram),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "metric_type".to_string(),
+                reason: format!("Unknown metric type: {}. Expected one of: counter, gauge, histogram", metric_type),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Counter => "counter",
+            Self::Gauge => "gauge",
+            Self::Histogram => "histogram",
+        }
+    }
+
+    /// The type shared by a set of metrics, ignoring untyped ones; `Err` holds the
+    /// first two types that differ
+    pub fn common(types: impl IntoIterator<Item = Option<Self>>) -> Result<Option<Self>, (Self, Self)> {
+        let mut common = None;
+        for metric_type in types.into_iter().flatten() {
+            match common {
+                None => common = Some(metric_type),
+                Some(seen) if seen != metric_type => return Err((seen, metric_type)),
+                Some(_) => {}
+            }
+        }
+        Ok(common)
+    }
+}
+
+// Python sees metric types as their names
+#[cfg(feature = "python")]
+impl<'py> IntoPyObject<'py> for MetricType {
+    type Target = PyString;
+    type Output = Bound<'py, PyString>;
+    type Error = Infallible;
+
+    fn into_pyobject(self, py: Python<'py>) -> Result<Self::Output, Self::Error> {
+        Ok(PyString::new(py, self.as_str()))
+    }
+}
+
+#[cfg(feature = "python")]
+impl<'py> FromPyObject<'py> for MetricType {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        Ok(Self::parse(&ob.extract::<String>()?)?)
+    }
+}
+
+/// Extra named values of a metric. Most metrics have none, so the map is only
+/// allocated once a field is set, and a metric without fields pays for one pointer.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[allow(clippy::box_collection)] // Boxed so the empty case is one pointer, not a whole map
+pub struct Fields(Option<Box<BTreeMap<String, i64>>>);
+
+impl Fields {
+    /// Value of the field `name`, if set
+    pub fn get(&self, name: &str) -> Option<i64> {
+        self.0.as_ref()?.get(name).copied()
+    }
+
+    /// Set the field `name`
+    pub fn insert(&mut self, name: impl Into<String>, value: i64) {
+        self.0.get_or_insert_with(Default::default).insert(name.into(), value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    /// Fields in name order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, i64)> {
+        self.0.iter().flat_map(|fields| fields.iter().map(|(name, &value)| (name.as_str(), value)))
+    }
+
+    /// The fields as a map
+    pub fn to_map(&self) -> BTreeMap<String, i64> {
+        self.0.as_deref().cloned().unwrap_or_default()
+    }
+}
+
+impl From<BTreeMap<String, i64>> for Fields {
+    fn from(fields: BTreeMap<String, i64>) -> Self {
+        // Empty maps stay unallocated, so equal field sets compare and hash equal
+        Self((!fields.is_empty()).then(|| Box::new(fields)))
+    }
+}
+
+// Fields serialize as a plain map, empty when there are none
+#[cfg(feature = "serde")]
+impl serde::Serialize for Fields {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Fields {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        BTreeMap::deserialize(deserializer).map(Self::from)
+    }
+}
+
+// Python sees fields as a dict
+#[cfg(feature = "python")]
+impl<'py> IntoPyObject<'py> for Fields {
+    type Target = PyDict;
+    type Output = Bound<'py, PyDict>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> Result<Self::Output, Self::Error> {
+        self.to_map().into_pyobject(py)
+    }
+}
+
+#[cfg(feature = "python")]
+impl<'py> FromPyObject<'py> for Fields {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        Ok(ob.extract::<BTreeMap<String, i64>>()?.into())
+    }
+}
+
+/// How aggregation combines the `source` and `description` of the metrics it merges
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MetadataMerge {
+    /// Keep the first value found
+    #[default]
+    First,
+    /// Join the distinct values with "; ", in input order
+    Concat,
+}
+
+impl MetadataMerge {
+    /// Parse a merge mode name ("first" or "concat")
+    pub fn parse(mode: &str) -> MetricQueryResult<Self> {
+        match mode {
+            "first" => Ok(Self::First),
+            "concat" => Ok(Self::Concat),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "metadata".to_string(),
+                reason: format!("Unknown metadata merge mode: {}. Expected 'first' or 'concat'", mode),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::First => "first",
+            Self::Concat => "concat",
+        }
+    }
+
+    /// Combine metadata values, ignoring metrics without one
+    pub fn merge<'a>(&self, values: impl IntoIterator<Item = Option<&'a str>>) -> Option<String> {
+        let mut values = values.into_iter().flatten();
+        match self {
+            Self::First => values.next().map(str::to_string),
+            Self::Concat => {
+                let mut distinct: Vec<&str> = Vec::new();
+                for value in values {
+                    if !distinct.contains(&value) {
+                        distinct.push(value);
+                    }
+                }
+                (!distinct.is_empty()).then(|| distinct.join("; "))
+            }
+        }
+    }
+}
 
 /// A metric is a single data point that is collected at a specific time.
 ///
@@ -6,48 +242,794 @@ use pyo3::prelude::*;
 ///
 /// * `value` - The value of the metric.
 /// * `timestamp` - The time at which the metric was collected.
-#[pyclass]
-#[derive(Debug, Clone)]
+///
+/// With the `serde` feature a metric round-trips through any serde format; only
+/// `value` and `timestamp` are required when deserializing.
+#[cfg_attr(feature = "python", pyclass(get_all, set_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Metric {
     /// The value of the metric.
-    #[pyo3(get, set)]
     pub value: i64,
     /// The time at which the metric was collected.
-    #[pyo3(get, set)]
     pub timestamp: i64,
-    #[pyo3(get, set)]
+    #[cfg_attr(feature = "serde", serde(default))]
     pub label: Option<String>, // Add optional label
+    /// Trace sample behind this point; time grouping and aggregation keep the
+    /// exemplar with the highest value
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub exemplar: Option<Exemplar>,
+    /// Extra named values measured at the same point (e.g. bytes_in and bytes_out);
+    /// `select_field` makes one of them the value that steps operate on
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fields: Fields,
+    /// Unit of the value (e.g. "ms" or "bytes"); `convert_unit` rescales it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unit: Option<String>,
+    /// Whether the value is a counter, gauge or histogram bucket; steps warn about
+    /// combinations that don't make sense for the type, such as summing counter samples
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub metric_type: Option<MetricType>,
+    /// Decimal places of a fixed-point value: value 1234 with scale 2 is 12.34.
+    /// `None` is a plain integer. Aggregations keep decimal results exact.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub scale: Option<u32>,
+    /// Where the metric came from (e.g. a host or exporter); filters keep it and
+    /// aggregation merges it according to the `Settings` metadata mode
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub source: Option<String>,
+    /// Free-text note about the metric, kept and merged like `source`
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub description: Option<String>,
+    /// Marks the point where the series stopped reporting, like a Prometheus staleness
+    /// marker; its value means nothing and aggregations skip it
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub stale: bool,
+    /// Dimensions beyond the label, such as Kubernetes "namespace" and "pod";
+    /// `filter_by_tag` selects on them
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub tags: BTreeMap<String, String>,
 }
 
-#[pymethods]
 impl Metric {
     /// Create a new Metric
-    #[new]
     pub fn new(value: i64, timestamp: i64, label: Option<String>) -> Self {
-        Self { value, timestamp, label }
+        Self {
+            value,
+            timestamp,
+            label,
+            exemplar: None,
+            fields: Fields::default(),
+            unit: None,
+            metric_type: None,
+            scale: None,
+            source: None,
+            description: None,
+            stale: false,
+            tags: BTreeMap::new(),
+        }
+    }
+
+    /// A staleness marker: `label`'s series stopped reporting at `timestamp`
+    pub fn stale_marker(timestamp: i64, label: Option<String>) -> Self {
+        Self { stale: true, ..Self::new(0, timestamp, label) }
+    }
+
+    /// Attach an exemplar to the metric
+    pub fn with_exemplar(mut self, exemplar: Exemplar) -> Self {
+        self.exemplar = Some(exemplar);
+        self
+    }
+
+    /// Set a named field on the metric
+    pub fn with_field(mut self, name: impl Into<String>, value: i64) -> Self {
+        self.fields.insert(name.into(), value);
+        self
+    }
+
+    /// Set a tag on the metric
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.insert(key.into(), value.into());
+        self
+    }
+
+    /// Set the unit of the metric's value
+    pub fn with_unit(mut self, unit: impl Into<String>) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+
+    /// Set the type of the metric
+    pub fn with_type(mut self, metric_type: MetricType) -> Self {
+        self.metric_type = Some(metric_type);
+        self
+    }
+
+    /// Set where the metric came from
+    pub fn with_source(mut self, source: impl Into<String>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+
+    /// Set the metric's description
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Make the value a fixed-point decimal with `scale` decimal places
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = Some(scale);
+        self
+    }
+
+    /// Create a fixed-point metric from a decimal string such as "12.34", keeping
+    /// as many decimal places as the string has
+    pub fn from_decimal(decimal: &str, timestamp: i64, label: Option<String>) -> MetricQueryResult<Self> {
+        let (value, scale) = parse_decimal(decimal)?;
+        Ok(Self::new(value, timestamp, label).with_scale(scale))
+    }
+
+    /// The value as a decimal string, e.g. "12.34" for value 1234 with scale 2
+    pub fn decimal_string(&self) -> String {
+        format_decimal(self.value, self.scale.unwrap_or(0))
+    }
+
+    /// The value as a float, undoing the scale of a fixed-point value
+    pub fn as_f64(&self) -> f64 {
+        match self.scale {
+            Some(scale) => self.value as f64 / 10_f64.powi(scale as i32),
+            None => self.value as f64,
+        }
+    }
+
+    /// Value of a named field, if the metric has it
+    pub fn field(&self, name: &str) -> Option<i64> {
+        self.fields.get(name)
+    }
+
+    /// Create a metric timestamped by an ISO-8601 string such as "2024-03-01T10:15:30Z"
+    pub fn from_iso(value: i64, iso: &str, label: Option<String>) -> MetricQueryResult<Self> {
+        Ok(Self::new(value, parse_iso_timestamp(iso)?, label))
+    }
+
+    /// The timestamp as an RFC 3339 string in UTC
+    pub fn timestamp_iso(&self) -> MetricQueryResult<String> {
+        format_iso_timestamp(self.timestamp)
+    }
+
+    /// Build metrics from parallel value, timestamp and (optionally) label columns
+    pub fn from_arrays(
+        values: &[i64],
+        timestamps: &[i64],
+        labels: Option<Vec<Option<String>>>,
+    ) -> MetricQueryResult<Vec<Self>> {
+        let mismatch = |column: &str, len: usize| MetricQueryError::OperationFailed {
+            operation: "from_arrays".to_string(),
+            reason: format!("{} has {} entries but values has {}", column, len, values.len()),
+        };
+        if timestamps.len() != values.len() {
+            return Err(mismatch("timestamps", timestamps.len()));
+        }
+        let labels = match labels {
+            Some(labels) if labels.len() != values.len() => return Err(mismatch("labels", labels.len())),
+            Some(labels) => labels,
+            None => vec![None; values.len()],
+        };
+        Ok(values
+            .iter()
+            .zip(timestamps)
+            .zip(labels)
+            .map(|((&value, &timestamp), label)| Self::new(value, timestamp, label))
+            .collect())
+    }
+
+    /// Order of metrics in Python comparisons: by timestamp, then label, then value
+    pub fn sort_key(&self) -> (i64, Option<&str>, i64) {
+        (self.timestamp, self.label.as_deref(), self.value)
+    }
+
+    /// Split metrics into value, timestamp and label columns, the reverse of `from_arrays`
+    pub fn to_arrays(metrics: &[Self]) -> (Vec<i64>, Vec<i64>, Vec<Option<String>>) {
+        let mut values = Vec::with_capacity(metrics.len());
+        let mut timestamps = Vec::with_capacity(metrics.len());
+        let mut labels = Vec::with_capacity(metrics.len());
+        for metric in metrics {
+            values.push(metric.value);
+            timestamps.push(metric.timestamp);
+            labels.push(metric.label.clone());
+        }
+        (values, timestamps, labels)
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Metric {
+    #[new]
+    #[pyo3(signature = (
+        value,
+        timestamp,
+        label=None,
+        exemplar=None,
+        fields=None,
+        unit=None,
+        metric_type=None,
+        scale=None,
+        source=None,
+        description=None,
+        stale=false,
+        tags=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        value: i64,
+        timestamp: i64,
+        label: Option<String>,
+        exemplar: Option<Exemplar>,
+        fields: Option<BTreeMap<String, i64>>,
+        unit: Option<String>,
+        metric_type: Option<MetricType>,
+        scale: Option<u32>,
+        source: Option<String>,
+        description: Option<String>,
+        stale: bool,
+        tags: Option<BTreeMap<String, String>>,
+    ) -> PyResult<Self> {
+        if let Some(scale) = scale {
+            crate::decimal::check_scale(scale)?;
+        }
+        Ok(Self {
+            value,
+            timestamp,
+            label,
+            exemplar,
+            fields: fields.map(Fields::from).unwrap_or_default(),
+            unit,
+            metric_type,
+            scale,
+            source,
+            description,
+            stale,
+            tags: tags.unwrap_or_default(),
+        })
+    }
+
+    /// A staleness marker saying the series of `label` stopped reporting at `timestamp`
+    #[staticmethod]
+    #[pyo3(name = "stale_marker", signature = (timestamp, label=None))]
+    fn py_stale_marker(timestamp: i64, label: Option<String>) -> Self {
+        Self::stale_marker(timestamp, label)
+    }
+
+    /// Create a fixed-point metric from a `decimal.Decimal` or a decimal string such
+    /// as "12.34"; the scale is the number of decimal places given
+    #[staticmethod]
+    #[pyo3(name = "from_decimal", signature = (value, timestamp, label=None))]
+    fn py_from_decimal(value: &Bound<'_, PyAny>, timestamp: i64, label: Option<String>) -> PyResult<Self> {
+        Ok(Self::from_decimal(&value.str()?.to_cow()?, timestamp, label)?)
+    }
+
+    /// The value as a `decimal.Decimal`, e.g. Decimal("12.34") for value 1234 with scale 2
+    #[getter(decimal)]
+    fn py_decimal<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        py.import("decimal")?.getattr("Decimal")?.call1((self.decimal_string(),))
+    }
+
+    /// Create a metric timestamped by an ISO-8601 string such as "2024-03-01T10:15:30Z";
+    /// without an offset the time is UTC
+    #[staticmethod]
+    #[pyo3(name = "from_iso", signature = (value, timestamp, label=None))]
+    fn py_from_iso(value: i64, timestamp: &str, label: Option<String>) -> PyResult<Self> {
+        Ok(Self::from_iso(value, timestamp, label)?)
+    }
+
+    /// The timestamp as an RFC 3339 string in UTC, e.g. "2024-03-01T10:15:30Z"
+    #[getter(timestamp_iso)]
+    fn py_timestamp_iso(&self) -> PyResult<String> {
+        Ok(self.timestamp_iso()?)
+    }
+
+    /// Build a list of metrics from parallel `values`, `timestamps` and optional
+    /// `labels` lists in one call, much faster than constructing each `Metric`
+    #[staticmethod]
+    #[pyo3(name = "from_arrays", signature = (values, timestamps, labels=None))]
+    fn py_from_arrays(values: Vec<i64>, timestamps: Vec<i64>, labels: Option<Vec<Option<String>>>) -> PyResult<Vec<Self>> {
+        Ok(Self::from_arrays(&values, &timestamps, labels)?)
+    }
+
+    /// Split `metrics` into `(values, timestamps, labels)` lists
+    #[staticmethod]
+    #[pyo3(name = "to_arrays")]
+    fn py_to_arrays(metrics: Vec<Self>) -> (Vec<i64>, Vec<i64>, Vec<Option<String>>) {
+        Self::to_arrays(&metrics)
+    }
+
+    /// The metric as a dict with the keys a dict input takes, plus "exemplar"
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("value", self.value)?;
+        dict.set_item("timestamp", self.timestamp)?;
+        dict.set_item("label", &self.label)?;
+        dict.set_item("unit", &self.unit)?;
+        dict.set_item("metric_type", self.metric_type)?;
+        dict.set_item("fields", self.fields.to_map())?;
+        dict.set_item("scale", self.scale)?;
+        dict.set_item("source", &self.source)?;
+        dict.set_item("description", &self.description)?;
+        dict.set_item("stale", self.stale)?;
+        dict.set_item("tags", &self.tags)?;
+        let exemplar = match &self.exemplar {
+            Some(exemplar) => {
+                let item = PyDict::new(py);
+                item.set_item("trace_id", &exemplar.trace_id)?;
+                item.set_item("value", exemplar.value)?;
+                item.set_item("timestamp", exemplar.timestamp)?;
+                Some(item)
+            }
+            None => None,
+        };
+        dict.set_item("exemplar", exemplar)?;
+        Ok(dict)
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn __lt__(&self, other: &Self) -> bool {
+        self.sort_key() < other.sort_key()
+    }
+
+    fn __le__(&self, other: &Self) -> bool {
+        self.sort_key() <= other.sort_key()
+    }
+
+    fn __gt__(&self, other: &Self) -> bool {
+        self.sort_key() > other.sort_key()
+    }
+
+    fn __ge__(&self, other: &Self) -> bool {
+        self.sort_key() >= other.sort_key()
+    }
+
+    /// Hash of everything but the exemplar; don't mutate a metric while it's in a set
+    /// (or use a `FrozenMetric`, which can't be mutated)
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.value, self.timestamp, &self.label, &self.unit, self.metric_type, &self.fields, self.scale, &self.tags)
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// An immutable, hashable copy of the metric
+    fn freeze(&self) -> FrozenMetric {
+        FrozenMetric::new(self.clone())
+    }
+
+    fn __repr__(&self) -> String {
+        let mut repr = format!("Metric(value={}, timestamp={}", self.value, self.timestamp);
+        if let Some(label) = &self.label {
+            repr.push_str(&format!(", label='{}'", label));
+        }
+        if let Some(unit) = &self.unit {
+            repr.push_str(&format!(", unit='{}'", unit));
+        }
+        if let Some(metric_type) = self.metric_type {
+            repr.push_str(&format!(", metric_type='{}'", metric_type.as_str()));
+        }
+        if let Some(scale) = self.scale {
+            repr.push_str(&format!(", scale={}", scale));
+        }
+        if let Some(source) = &self.source {
+            repr.push_str(&format!(", source='{}'", source));
+        }
+        if self.stale {
+            repr.push_str(", stale=True");
+        }
+        if !self.tags.is_empty() {
+            let tags: Vec<String> = self.tags.iter().map(|(key, value)| format!("'{}': '{}'", key, value)).collect();
+            repr.push_str(&format!(", tags={{{}}}", tags.join(", ")));
+        }
+        if !self.fields.is_empty() {
+            let fields: Vec<String> = self.fields.iter().map(|(name, value)| format!("'{}': {}", name, value)).collect();
+            repr.push_str(&format!(", fields={{{}}}", fields.join(", ")));
+        }
+        if let Some(exemplar) = &self.exemplar {
+            repr.push_str(&format!(", exemplar='{}'", exemplar.trace_id));
+        }
+        repr.push(')');
+        repr
+    }
+}
+
+/// A `Metric` that can't be changed once created.
+///
+/// Every attribute is read-only from Python, so a frozen metric is safe to keep in
+/// sets, use as a dict key, cache, or share between threads without copying.
+/// Pipelines accept frozen metrics anywhere they accept metrics; `thaw` gives back an
+/// editable `Metric`.
+#[cfg_attr(feature = "python", pyclass(frozen))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrozenMetric {
+    metric: Metric,
+}
+
+impl FrozenMetric {
+    /// Freeze `metric`
+    pub fn new(metric: Metric) -> Self {
+        Self { metric }
+    }
+
+    /// The frozen metric
+    pub fn metric(&self) -> &Metric {
+        &self.metric
+    }
+}
+
+impl From<FrozenMetric> for Metric {
+    fn from(frozen: FrozenMetric) -> Self {
+        frozen.metric
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl FrozenMetric {
+    /// Takes the same arguments as `Metric`
+    #[new]
+    #[pyo3(signature = (
+        value,
+        timestamp,
+        label=None,
+        exemplar=None,
+        fields=None,
+        unit=None,
+        metric_type=None,
+        scale=None,
+        source=None,
+        description=None,
+        stale=false,
+        tags=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        value: i64,
+        timestamp: i64,
+        label: Option<String>,
+        exemplar: Option<Exemplar>,
+        fields: Option<BTreeMap<String, i64>>,
+        unit: Option<String>,
+        metric_type: Option<MetricType>,
+        scale: Option<u32>,
+        source: Option<String>,
+        description: Option<String>,
+        stale: bool,
+        tags: Option<BTreeMap<String, String>>,
+    ) -> PyResult<Self> {
+        let metric = Metric::py_new(
+            value, timestamp, label, exemplar, fields, unit, metric_type, scale, source, description, stale, tags,
+        )?;
+        Ok(Self::new(metric))
+    }
+
+    #[getter]
+    fn value(&self) -> i64 {
+        self.metric.value
+    }
+
+    #[getter]
+    fn timestamp(&self) -> i64 {
+        self.metric.timestamp
+    }
+
+    #[getter]
+    fn label(&self) -> Option<String> {
+        self.metric.label.clone()
+    }
+
+    /// A copy of the exemplar; changing it doesn't change the frozen metric
+    #[getter]
+    fn exemplar(&self) -> Option<Exemplar> {
+        self.metric.exemplar.clone()
+    }
+
+    #[getter]
+    fn fields(&self) -> BTreeMap<String, i64> {
+        self.metric.fields.to_map()
+    }
+
+    #[getter]
+    fn unit(&self) -> Option<String> {
+        self.metric.unit.clone()
+    }
+
+    #[getter]
+    fn metric_type(&self) -> Option<MetricType> {
+        self.metric.metric_type
+    }
+
+    #[getter]
+    fn scale(&self) -> Option<u32> {
+        self.metric.scale
+    }
+
+    #[getter]
+    fn source(&self) -> Option<String> {
+        self.metric.source.clone()
+    }
+
+    #[getter]
+    fn description(&self) -> Option<String> {
+        self.metric.description.clone()
+    }
+
+    #[getter]
+    fn stale(&self) -> bool {
+        self.metric.stale
+    }
+
+    #[getter]
+    fn tags(&self) -> BTreeMap<String, String> {
+        self.metric.tags.clone()
+    }
+
+    /// An editable copy of the metric
+    fn thaw(&self) -> Metric {
+        self.metric.clone()
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        self.metric.to_dict(py)
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn __lt__(&self, other: &Self) -> bool {
+        self.metric.sort_key() < other.metric.sort_key()
+    }
+
+    fn __le__(&self, other: &Self) -> bool {
+        self.metric.sort_key() <= other.metric.sort_key()
+    }
+
+    fn __gt__(&self, other: &Self) -> bool {
+        self.metric.sort_key() > other.metric.sort_key()
+    }
+
+    fn __ge__(&self, other: &Self) -> bool {
+        self.metric.sort_key() >= other.metric.sort_key()
+    }
+
+    /// Same hash as the equal `Metric`
+    fn __hash__(&self) -> u64 {
+        self.metric.__hash__()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Frozen{}", self.metric.__repr__())
+    }
+}
+
+/// A metric given from Python as a `Metric`, a `FrozenMetric`, a `LabeledMetric`, a `(value, timestamp)` or
+/// `(value, timestamp, label)` tuple, or a dict with "value" and "timestamp" and
+/// optionally "label", "unit", "metric_type", "fields", "scale", "source", "description",
+/// "stale" and "tags", as decoded from JSON
+#[cfg(feature = "python")]
+pub struct MetricInput(pub Metric);
+
+#[cfg(feature = "python")]
+impl<'py> FromPyObject<'py> for MetricInput {
+    fn extract_bound(ob: &Bound<'py, PyAny>) -> PyResult<Self> {
+        if let Ok(metric) = ob.downcast::<Metric>() {
+            return Ok(Self(metric.borrow().clone()));
+        }
+        if let Ok(metric) = ob.downcast::<LabeledMetric>() {
+            return Ok(Self(metric.borrow().clone().into()));
+        }
+        if let Ok(metric) = ob.downcast::<FrozenMetric>() {
+            return Ok(Self(metric.get().metric().clone()));
+        }
+        if let Ok(dict) = ob.downcast::<PyDict>() {
+            let required = |key: &str| {
+                dict.get_item(key)?
+                    .ok_or_else(|| pyo3::exceptions::PyValueError::new_err(format!("metric dict has no '{}'", key)))
+            };
+            let optional = |key: &str| dict.get_item(key).map(|item| item.filter(|item| !item.is_none()));
+            return Ok(Self(Metric {
+                unit: optional("unit")?.map(|unit| unit.extract()).transpose()?,
+                metric_type: optional("metric_type")?.map(|metric_type| metric_type.extract()).transpose()?,
+                fields: optional("fields")?.map(|fields| fields.extract()).transpose()?.unwrap_or_default(),
+                scale: optional("scale")?.map(|scale| scale.extract()).transpose()?,
+                source: optional("source")?.map(|source| source.extract()).transpose()?,
+                description: optional("description")?.map(|description| description.extract()).transpose()?,
+                stale: optional("stale")?.map(|stale| stale.extract()).transpose()?.unwrap_or(false),
+                tags: optional("tags")?.map(|tags| tags.extract()).transpose()?.unwrap_or_default(),
+                ..Metric::new(
+                    required("value")?.extract()?,
+                    required("timestamp")?.extract()?,
+                    optional("label")?.map(|label| label.extract()).transpose()?,
+                )
+            }));
+        }
+        if let Ok((value, timestamp)) = ob.extract::<(i64, i64)>() {
+            return Ok(Self(Metric::new(value, timestamp, None)));
+        }
+        if let Ok((value, timestamp, label)) = ob.extract::<(i64, i64, Option<String>)>() {
+            return Ok(Self(Metric::new(value, timestamp, label)));
+        }
+        Err(pyo3::exceptions::PyTypeError::new_err(format!(
+            "expected a Metric, a (value, timestamp[, label]) tuple or a dict, got {}",
+            ob.get_type().name()?
+        )))
+    }
+}
+
+/// A metric whose value is a floating point number.
+///
+/// Used for ratios, temperatures, percentages and anything else that loses
+/// meaning when forced into an integer. Runs through the same pipeline steps
+/// as `Metric` via `MetricPipeline.execute_float`. A missing value is stored
+/// as NaN; passing `None` from Python creates one.
+#[cfg_attr(feature = "python", pyclass(get_all, set_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct FloatMetric {
+    /// The value of the metric.
+    pub value: f64,
+    /// The time at which the metric was collected.
+    pub timestamp: i64,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub label: Option<String>,
+    /// Unit of the value (e.g. "ms" or "bytes")
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub unit: Option<String>,
+}
+
+impl FloatMetric {
+    /// Create a new FloatMetric; a `None` value is stored as NaN
+    pub fn new(value: Option<f64>, timestamp: i64, label: Option<String>) -> Self {
+        Self { value: value.unwrap_or(f64::NAN), timestamp, label, unit: None }
+    }
+    
+    /// Set the unit of the metric's value
+    pub fn with_unit(mut self, unit: impl Into<String>) -> Self {
+        self.unit = Some(unit.into());
+        self
+    }
+    
+    /// Whether the value is missing (NaN)
+    pub fn is_missing(&self) -> bool {
+        self.value.is_nan()
+    }
+    
+    /// Convert to an integer metric, rounding the value to the nearest integer
+    pub fn to_metric(&self) -> Metric {
+        Metric { unit: self.unit.clone(), ..Metric::new(self.value.round() as i64, self.timestamp, self.label.clone()) }
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl FloatMetric {
+    #[new]
+    #[pyo3(signature = (value, timestamp, label=None, unit=None))]
+    fn py_new(value: Option<f64>, timestamp: i64, label: Option<String>, unit: Option<String>) -> Self {
+        Self { unit, ..Self::new(value, timestamp, label) }
+    }
+    
+    /// Whether the value is missing (NaN)
+    #[pyo3(name = "is_missing")]
+    fn py_is_missing(&self) -> bool {
+        self.is_missing()
+    }
+}
+
+impl From<&Metric> for FloatMetric {
+    fn from(metric: &Metric) -> Self {
+        Self {
+            value: metric.as_f64(),
+            timestamp: metric.timestamp,
+            label: metric.label.clone(),
+            unit: metric.unit.clone(),
+        }
     }
 }
 
 /// Extended Metric struct (for multiple metric types)
-#[pyclass]
-#[derive(Debug, Clone)]
+#[cfg_attr(feature = "python", pyclass(get_all, set_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct LabeledMetric {
     /// The label of the metric (e.g., "cpu", "memory").
-    #[pyo3(get, set)]
     pub label: String,
     /// The value of the metric.
-    #[pyo3(get, set)]
     pub value: i64,
     /// The time at which the metric was collected.
-    #[pyo3(get, set)]
     pub timestamp: i64,
 }
 
-#[pymethods]
 impl LabeledMetric {
     /// Create a new LabeledMetric
-    #[new]
     pub fn new(label: String, value: i64, timestamp: i64) -> Self {
         Self { label, value, timestamp }
     }
+
+    /// Order of metrics in Python comparisons: by timestamp, then label, then value
+    pub fn sort_key(&self) -> (i64, &str, i64) {
+        (self.timestamp, &self.label, self.value)
+    }
+
+    /// The labeled form of a pipeline metric, or `None` if it has no label
+    pub fn from_metric(metric: &Metric) -> Option<Self> {
+        let label = metric.label.clone()?;
+        Some(Self::new(label, metric.value, metric.timestamp))
+    }
+}
+
+impl From<LabeledMetric> for Metric {
+    fn from(metric: LabeledMetric) -> Self {
+        Metric::new(metric.value, metric.timestamp, Some(metric.label))
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl LabeledMetric {
+    #[new]
+    fn py_new(label: String, value: i64, timestamp: i64) -> Self {
+        Self::new(label, value, timestamp)
+    }
+
+    /// The `Metric` pipelines operate on, carrying this label
+    fn to_metric(&self) -> Metric {
+        self.clone().into()
+    }
+
+    /// The labeled form of `metric`; raises ValueError if it has no label
+    #[staticmethod]
+    #[pyo3(name = "from_metric")]
+    fn py_from_metric(metric: &Metric) -> PyResult<Self> {
+        Self::from_metric(metric).ok_or_else(|| pyo3::exceptions::PyValueError::new_err("metric has no label"))
+    }
+
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let dict = PyDict::new(py);
+        dict.set_item("label", &self.label)?;
+        dict.set_item("value", self.value)?;
+        dict.set_item("timestamp", self.timestamp)?;
+        Ok(dict)
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn __lt__(&self, other: &Self) -> bool {
+        self.sort_key() < other.sort_key()
+    }
+
+    fn __le__(&self, other: &Self) -> bool {
+        self.sort_key() <= other.sort_key()
+    }
+
+    fn __gt__(&self, other: &Self) -> bool {
+        self.sort_key() > other.sort_key()
+    }
+
+    fn __ge__(&self, other: &Self) -> bool {
+        self.sort_key() >= other.sort_key()
+    }
+
+    fn __hash__(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("LabeledMetric(label='{}', value={}, timestamp={})", self.label, self.value, self.timestamp)
+    }
 }
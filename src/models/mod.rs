@@ -1,4 +1,18 @@
 pub mod metric;
+pub mod histogram;
+pub mod sketch;
+pub mod dataset;
+pub mod series;
 
 pub use metric::Metric;
 pub use metric::LabeledMetric;
+pub use metric::FrozenMetric;
+pub use metric::FloatMetric;
+pub use metric::Exemplar;
+pub use metric::Fields;
+pub use metric::MetricType;
+pub use metric::MetadataMerge;
+pub use histogram::HistogramMetric;
+pub use sketch::SketchMetric;
+pub use dataset::MetricDataset;
+pub use series::MetricSeries;
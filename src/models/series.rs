@@ -0,0 +1,178 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::compiled::CompiledPipeline;
+use crate::errors::MetricQueryResult;
+use crate::models::Metric;
+use crate::transformations::MetricPipeline;
+use crate::warnings::PipelineWarning;
+#[cfg(feature = "python")]
+use crate::warnings::emit_python_warnings;
+
+/// Metrics kept as separate series, keyed by label.
+///
+/// Every metric of a series carries the series' label, so flattening the
+/// collection into one pipeline input still groups and aggregates each series on
+/// its own. `apply` goes further and runs a pipeline over each series separately,
+/// keeping the results under the same keys.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Debug, Clone, Default)]
+pub struct MetricSeries {
+    series: BTreeMap<Option<String>, Vec<Metric>>,
+}
+
+impl MetricSeries {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Split a flat list of metrics into one series per label, keeping their order
+    pub fn from_metrics(metrics: impl IntoIterator<Item = Metric>) -> Self {
+        let mut series: BTreeMap<Option<String>, Vec<Metric>> = BTreeMap::new();
+        for metric in metrics {
+            series.entry(metric.label.clone()).or_default().push(metric);
+        }
+        Self { series }
+    }
+
+    /// Store `metrics` as the series `name`, relabeling them to it and replacing any
+    /// series already stored under that name
+    pub fn insert(&mut self, name: Option<String>, metrics: impl IntoIterator<Item = Metric>) {
+        let metrics = metrics.into_iter().map(|metric| Metric { label: name.clone(), ..metric }).collect();
+        self.series.insert(name, metrics);
+    }
+
+    /// The series stored under `name`
+    pub fn get(&self, name: Option<&str>) -> Option<&[Metric]> {
+        self.series.get(&name.map(str::to_string)).map(Vec::as_slice)
+    }
+
+    /// Remove and return the series stored under `name`
+    pub fn remove(&mut self, name: Option<&str>) -> Option<Vec<Metric>> {
+        self.series.remove(&name.map(str::to_string))
+    }
+
+    /// Series names in sorted order, `None` (unlabeled) first
+    pub fn names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.series.keys().map(Option::as_deref)
+    }
+
+    /// Each series with its name, in name order
+    pub fn iter(&self) -> impl Iterator<Item = (Option<&str>, &[Metric])> {
+        self.series.iter().map(|(name, metrics)| (name.as_deref(), metrics.as_slice()))
+    }
+
+    /// Number of series
+    pub fn len(&self) -> usize {
+        self.series.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.series.is_empty()
+    }
+
+    /// Every metric of every series, series by series in name order
+    pub fn to_metrics(&self) -> Vec<Metric> {
+        self.series.values().flatten().cloned().collect()
+    }
+
+    /// Start a pipeline over all series at once
+    pub fn pipeline(&self) -> MetricPipeline {
+        MetricPipeline::from_shared(Arc::from(self.to_metrics()))
+    }
+
+    /// Run `pipeline` over each series separately, storing each result under its
+    /// series' name as the pipeline left it; also returns the warnings of every run
+    pub fn apply(&self, pipeline: &CompiledPipeline) -> MetricQueryResult<(Self, Vec<PipelineWarning>)> {
+        let mut series = BTreeMap::new();
+        let mut warnings = Vec::new();
+        for (name, metrics) in &self.series {
+            let (result, run_warnings) = pipeline.run_metrics_with_warnings(metrics)?;
+            series.insert(name.clone(), result);
+            warnings.extend(run_warnings);
+        }
+        Ok((Self { series }, warnings))
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl MetricSeries {
+    /// Build from a dict of label to metrics, or empty
+    #[new]
+    #[pyo3(signature = (series=None))]
+    fn py_new(series: Option<BTreeMap<Option<String>, Vec<Metric>>>) -> Self {
+        let mut collection = Self::new();
+        for (name, metrics) in series.unwrap_or_default() {
+            collection.insert(name, metrics);
+        }
+        collection
+    }
+
+    /// Split a flat list of metrics into one series per label
+    #[staticmethod]
+    #[pyo3(name = "from_metrics")]
+    fn py_from_metrics(metrics: Vec<Metric>) -> Self {
+        Self::from_metrics(metrics)
+    }
+
+    /// Series names in sorted order, `None` (unlabeled) first
+    #[pyo3(name = "names")]
+    fn py_names(&self) -> Vec<Option<String>> {
+        self.series.keys().cloned().collect()
+    }
+
+    /// Every metric of every series as one list
+    #[pyo3(name = "to_metrics")]
+    fn py_to_metrics(&self) -> Vec<Metric> {
+        self.to_metrics()
+    }
+
+    /// Start a pipeline over all series at once
+    #[pyo3(name = "pipeline")]
+    fn py_pipeline(&self) -> MetricPipeline {
+        self.pipeline()
+    }
+
+    /// Run `pipeline` (`MetricPipeline` or `CompiledPipeline`) over each series
+    /// separately and return the results as a new `MetricSeries`
+    #[pyo3(name = "apply")]
+    fn py_apply(&self, py: Python<'_>, pipeline: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let pipeline = match pipeline.downcast::<CompiledPipeline>() {
+            Ok(compiled) => compiled.get().clone(),
+            Err(_) => CompiledPipeline::new(&*pipeline.extract::<PyRef<MetricPipeline>>()?),
+        };
+        let (result, warnings) = self.apply(&pipeline)?;
+        emit_python_warnings(py, &warnings)?;
+        Ok(result)
+    }
+
+    fn __getitem__(&self, name: Option<String>) -> PyResult<Vec<Metric>> {
+        self.series
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| pyo3::exceptions::PyKeyError::new_err(name))
+    }
+
+    fn __setitem__(&mut self, name: Option<String>, metrics: Vec<Metric>) {
+        self.insert(name, metrics);
+    }
+
+    fn __delitem__(&mut self, name: Option<String>) -> PyResult<()> {
+        self.series.remove(&name).map(|_| ()).ok_or_else(|| pyo3::exceptions::PyKeyError::new_err(name))
+    }
+
+    fn __contains__(&self, name: Option<String>) -> bool {
+        self.series.contains_key(&name)
+    }
+
+    fn __len__(&self) -> usize {
+        self.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("MetricSeries(series={}, metrics={})", self.len(), self.series.values().map(Vec::len).sum::<usize>())
+    }
+}
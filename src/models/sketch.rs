@@ -0,0 +1,236 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::FloatMetric;
+use crate::plugins::{GroupPoint, TimeGroupingPlugin};
+
+/// Magnitudes below this are counted as zero, since their log index is unbounded
+const MIN_INDEXABLE: f64 = 1e-9;
+
+/// A mergeable quantile sketch (DDSketch) observed at one point in time.
+///
+/// Every quantile estimate is within `relative_accuracy` of the true value
+/// (e.g. 0.01 for 1%), however many values were added and however the sketch
+/// was merged. Edge nodes can ship sketches instead of raw samples and the
+/// percentiles stay accurate after aggregation.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct SketchMetric {
+    pub relative_accuracy: f64,
+    /// Number of values added (missing values are skipped)
+    pub count: u64,
+    pub sum: f64,
+    /// NaN while the sketch is empty
+    pub min: f64,
+    pub max: f64,
+    /// The time at which the sketch was collected.
+    pub timestamp: i64,
+    pub label: Option<String>,
+    gamma: f64,
+    positive: BTreeMap<i32, u64>,
+    negative: BTreeMap<i32, u64>,
+    zero_count: u64,
+}
+
+fn sketch_error(reason: impl Into<String>) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: "sketch".to_string(), reason: reason.into() }
+}
+
+impl SketchMetric {
+    /// Create an empty sketch with the given relative accuracy, in (0, 1)
+    pub fn new(relative_accuracy: f64, timestamp: i64, label: Option<String>) -> MetricQueryResult<Self> {
+        if !(relative_accuracy > 0.0 && relative_accuracy < 1.0) {
+            return Err(sketch_error(format!(
+                "relative accuracy must be between 0 and 1, got {}",
+                relative_accuracy
+            )));
+        }
+        Ok(Self {
+            relative_accuracy,
+            count: 0,
+            sum: 0.0,
+            min: f64::NAN,
+            max: f64::NAN,
+            timestamp,
+            label,
+            gamma: (1.0 + relative_accuracy) / (1.0 - relative_accuracy),
+            positive: BTreeMap::new(),
+            negative: BTreeMap::new(),
+            zero_count: 0,
+        })
+    }
+
+    fn index(&self, magnitude: f64) -> i32 {
+        (magnitude.ln() / self.gamma.ln()).ceil() as i32
+    }
+
+    /// Representative value of bucket `index`, within the relative accuracy of everything in it
+    fn bucket_value(&self, index: i32) -> f64 {
+        2.0 * self.gamma.powi(index) / (self.gamma + 1.0)
+    }
+
+    /// Add one value; NaN is skipped
+    pub fn add(&mut self, value: f64) {
+        if value.is_nan() {
+            return;
+        }
+        if value.abs() < MIN_INDEXABLE {
+            self.zero_count += 1;
+        } else {
+            let index = self.index(value.abs());
+            let store = if value > 0.0 { &mut self.positive } else { &mut self.negative };
+            *store.entry(index).or_insert(0) += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Add another sketch's values to this one; the relative accuracy must match.
+    ///
+    /// A count that would overflow fails the merge and leaves this sketch unchanged.
+    pub fn merge(&mut self, other: &SketchMetric) -> MetricQueryResult<()> {
+        if self.relative_accuracy != other.relative_accuracy {
+            return Err(sketch_error("cannot merge sketches with different relative accuracy"));
+        }
+        let overflow = || MetricQueryError::ArithmeticOverflow { operation: "sketch merge".to_string() };
+        let fits = |own: &BTreeMap<i32, u64>, theirs: &BTreeMap<i32, u64>| {
+            theirs.iter().all(|(index, count)| own.get(index).copied().unwrap_or(0).checked_add(*count).is_some())
+        };
+        let count = self.count.checked_add(other.count).ok_or_else(overflow)?;
+        let zero_count = self.zero_count.checked_add(other.zero_count).ok_or_else(overflow)?;
+        if !fits(&self.positive, &other.positive) || !fits(&self.negative, &other.negative) {
+            return Err(overflow());
+        }
+
+        for (index, count) in &other.positive {
+            *self.positive.entry(*index).or_insert(0) += count;
+        }
+        for (index, count) in &other.negative {
+            *self.negative.entry(*index).or_insert(0) += count;
+        }
+        self.zero_count = zero_count;
+        self.count = count;
+        self.sum += other.sum;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        Ok(())
+    }
+
+    /// Estimate quantile `q` in [0, 1]
+    pub fn quantile(&self, q: f64) -> MetricQueryResult<f64> {
+        if !(0.0..=1.0).contains(&q) {
+            return Err(sketch_error(format!("quantile must be between 0 and 1, got {}", q)));
+        }
+        if self.count == 0 {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+
+        let rank = (q * (self.count - 1) as f64).round() as u64;
+        let mut seen = 0u64;
+        // Walk from the most negative value up: large negative magnitudes first
+        let negative = self.negative.iter().rev().map(|(&i, &c)| (-self.bucket_value(i), c));
+        let zero = std::iter::once((0.0, self.zero_count));
+        let positive = self.positive.iter().map(|(&i, &c)| (self.bucket_value(i), c));
+        for (value, count) in negative.chain(zero).chain(positive) {
+            seen += count;
+            if seen > rank {
+                return Ok(value.clamp(self.min, self.max));
+            }
+        }
+        Ok(self.max)
+    }
+
+    /// Build one sketch per label and time group from raw float metrics
+    pub fn from_metrics(
+        metrics: &[FloatMetric],
+        grouping: &dyn TimeGroupingPlugin,
+        relative_accuracy: f64,
+    ) -> MetricQueryResult<Vec<SketchMetric>> {
+        // Checks the accuracy once, so an empty input still reports a bad value
+        let empty = SketchMetric::new(relative_accuracy, 0, None)?;
+        let mut groups: BTreeMap<(Option<&str>, i64), SketchMetric> = BTreeMap::new();
+        for metric in metrics {
+            let timestamp = grouping.get_point_group_timestamp(&GroupPoint::from(metric))?;
+            groups
+                .entry((metric.label.as_deref(), timestamp))
+                .or_insert_with(|| SketchMetric { timestamp, label: metric.label.clone(), ..empty.clone() })
+                .add(metric.value);
+        }
+        Ok(groups.into_values().collect())
+    }
+
+    /// Merge sketches that share a label and time group, ordered by label, then timestamp
+    pub fn group_by_time(
+        sketches: &[SketchMetric],
+        grouping: &dyn TimeGroupingPlugin,
+    ) -> MetricQueryResult<Vec<SketchMetric>> {
+        let mut groups: BTreeMap<(Option<&str>, i64), SketchMetric> = BTreeMap::new();
+        for sketch in sketches {
+            let point = GroupPoint { timestamp: sketch.timestamp, label: sketch.label.as_deref(), value: None };
+            let timestamp = grouping.get_point_group_timestamp(&point)?;
+            match groups.get_mut(&(sketch.label.as_deref(), timestamp)) {
+                Some(group) => group.merge(sketch)?,
+                None => {
+                    let group = SketchMetric { timestamp, ..sketch.clone() };
+                    groups.insert((sketch.label.as_deref(), timestamp), group);
+                }
+            }
+        }
+        Ok(groups.into_values().collect())
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl SketchMetric {
+    #[new]
+    #[pyo3(signature = (timestamp, label=None, relative_accuracy=0.01))]
+    fn py_new(timestamp: i64, label: Option<String>, relative_accuracy: f64) -> PyResult<Self> {
+        Ok(Self::new(relative_accuracy, timestamp, label)?)
+    }
+
+    /// Add one value, or every value of an iterable
+    #[pyo3(name = "add")]
+    fn py_add(&mut self, values: &Bound<'_, PyAny>) -> PyResult<()> {
+        if let Ok(value) = values.extract::<f64>() {
+            self.add(value);
+            return Ok(());
+        }
+        for value in values.try_iter()? {
+            self.add(value?.extract()?);
+        }
+        Ok(())
+    }
+
+    /// Add another sketch's values to this one
+    #[pyo3(name = "merge")]
+    fn py_merge(&mut self, other: SketchMetric) -> PyResult<()> {
+        Ok(self.merge(&other)?)
+    }
+
+    /// Estimate quantile `q` in [0, 1]
+    #[pyo3(name = "quantile")]
+    fn py_quantile(&self, q: f64) -> PyResult<f64> {
+        Ok(self.quantile(q)?)
+    }
+
+    /// Build one sketch per label and "minute", "hour" or "day" group from float metrics
+    #[staticmethod]
+    #[pyo3(name = "from_metrics", signature = (metrics, grouping, relative_accuracy=0.01))]
+    fn py_from_metrics(metrics: Vec<FloatMetric>, grouping: &str, relative_accuracy: f64) -> PyResult<Vec<SketchMetric>> {
+        let grouping = crate::plugin_impls::create_time_grouping(grouping)?;
+        Ok(Self::from_metrics(&metrics, grouping.as_ref(), relative_accuracy)?)
+    }
+
+    /// Merge sketches per label into "minute", "hour" or "day" groups
+    #[staticmethod]
+    #[pyo3(name = "group_by_time")]
+    fn py_group_by_time(sketches: Vec<SketchMetric>, grouping: &str) -> PyResult<Vec<SketchMetric>> {
+        let grouping = crate::plugin_impls::create_time_grouping(grouping)?;
+        Ok(Self::group_by_time(&sketches, grouping.as_ref())?)
+    }
+}
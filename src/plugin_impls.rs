@@ -1,8 +1,20 @@
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
-use chrono::{DateTime, Timelike, Utc};
+use chrono::{DateTime, Datelike, NaiveDateTime, Offset, TimeZone, Timelike, Utc, Weekday};
+#[cfg(feature = "regex")]
+use regex::Regex;
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::sync::Arc;
 
+use crate::accuracy::{Accuracy, HyperLogLog, PERCENTILE_RELATIVE_ACCURACY};
+use crate::analysis::{quantile_of_sorted, quantile_of_sorted_ints};
 use crate::errors::{MetricQueryError, MetricQueryResult};
-use crate::models::Metric;
+use crate::expr::Expr;
+use crate::models::{FloatMetric, Metric, SketchMetric};
+use crate::settings::Settings;
+use crate::time_range::TimeRange;
 use crate::plugins::{
     FilterPlugin, AggregationPlugin, TimeGroupingPlugin, 
     with_registry_mut
@@ -10,311 +22,1481 @@ use crate::plugins::{
 
 // ----- Filter Plugin Implementations -----
 
+/// What a value filter compares against: a whole number, or a float such as 2.5 for
+/// a cut between integers
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(untagged))]
+#[cfg_attr(feature = "python", derive(FromPyObject))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Threshold {
+    Int(i64),
+    Float(f64),
+}
+
+impl Threshold {
+    /// How an integer value compares with the threshold, exactly for every i64;
+    /// `None` for a NaN threshold
+    fn compare_int(self, value: i64) -> Option<Ordering> {
+        match self {
+            Self::Int(threshold) => Some(value.cmp(&threshold)),
+            Self::Float(threshold) if threshold.is_nan() => None,
+            // 2^63 is exact as a float, and every float at or beyond it is out of i64 range
+            Self::Float(threshold) if threshold >= 9_223_372_036_854_775_808.0 => Some(Ordering::Less),
+            Self::Float(threshold) if threshold < -9_223_372_036_854_775_808.0 => Some(Ordering::Greater),
+            Self::Float(threshold) => {
+                let floor = threshold.floor();
+                match value.cmp(&(floor as i64)) {
+                    Ordering::Equal if floor != threshold => Some(Ordering::Less),
+                    ordering => Some(ordering),
+                }
+            }
+        }
+    }
+
+    /// How a float value compares with the threshold; `None` when either is NaN
+    fn compare_float(self, value: f64) -> Option<Ordering> {
+        match self {
+            Self::Int(threshold) => value.partial_cmp(&(threshold as f64)),
+            Self::Float(threshold) => value.partial_cmp(&threshold),
+        }
+    }
+
+    /// How a metric's value compares with the threshold, reading a fixed-point value at
+    /// its scale (value 1234 with scale 2 is 12.34); `None` for a NaN threshold
+    fn compare_metric(self, metric: &Metric) -> Option<Ordering> {
+        match (self, metric.scale) {
+            (_, None | Some(0)) => self.compare_int(metric.value),
+            (Self::Int(threshold), Some(scale)) => {
+                match 10_i128.checked_pow(scale).and_then(|unit| i128::from(threshold).checked_mul(unit)) {
+                    Some(scaled) => Some(i128::from(metric.value).cmp(&scaled)),
+                    // Beyond every i64 value at this scale
+                    None => Some(if threshold > 0 { Ordering::Less } else { Ordering::Greater }),
+                }
+            }
+            // Dividing out the scale gives the float nearest the decimal, as parsing it would
+            (Self::Float(_), Some(_)) => self.compare_float(metric.as_f64()),
+        }
+    }
+
+    /// How the threshold compares with `other`; `None` when either is NaN
+    fn compare(self, other: Self) -> Option<Ordering> {
+        match (self, other) {
+            (Self::Int(value), _) => other.compare_int(value),
+            (Self::Float(value), Self::Int(other)) => Self::Float(value).compare_int(other).map(Ordering::reverse),
+            (Self::Float(value), Self::Float(other)) => value.partial_cmp(&other),
+        }
+    }
+}
+
+impl std::fmt::Display for Threshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Int(value) => write!(f, "{}", value),
+            Self::Float(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+impl From<i64> for Threshold {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
+impl From<f64> for Threshold {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
 /// Greater than filter
 #[derive(Clone)]
 pub struct GreaterThanFilter {
-    value: i64,
+    threshold: Threshold,
 }
 
 impl GreaterThanFilter {
     pub fn new(value: i64) -> Self {
-        Self { value }
+        Self { threshold: Threshold::Int(value) }
+    }
+
+    /// Keep values above `threshold`, which may lie between integers
+    pub fn from_threshold(threshold: Threshold) -> Self {
+        Self { threshold }
     }
 }
 
 impl FilterPlugin for GreaterThanFilter {
     fn name(&self) -> &str {
-        "gt"
+        "gt"
+    }
+    
+    fn apply(&self, metric: &Metric) -> bool {
+        self.threshold.compare_metric(metric).is_some_and(Ordering::is_gt)
+    }
+    
+    fn apply_parts(&self, value: i64, _timestamp: i64) -> bool {
+        self.threshold.compare_int(value).is_some_and(Ordering::is_gt)
+    }
+    
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.threshold.compare_metric(metric).is_some_and(Ordering::is_gt)));
+    }
+    
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.threshold.compare_float(metric.value).is_some_and(Ordering::is_gt)
+    }
+}
+
+/// Less than filter
+#[derive(Clone)]
+pub struct LessThanFilter {
+    threshold: Threshold,
+}
+
+impl LessThanFilter {
+    pub fn new(value: i64) -> Self {
+        Self { threshold: Threshold::Int(value) }
+    }
+
+    /// Keep values below `threshold`, which may lie between integers
+    pub fn from_threshold(threshold: Threshold) -> Self {
+        Self { threshold }
+    }
+}
+
+impl FilterPlugin for LessThanFilter {
+    fn name(&self) -> &str {
+        "lt"
+    }
+    
+    fn apply(&self, metric: &Metric) -> bool {
+        self.threshold.compare_metric(metric).is_some_and(Ordering::is_lt)
+    }
+    
+    fn apply_parts(&self, value: i64, _timestamp: i64) -> bool {
+        self.threshold.compare_int(value).is_some_and(Ordering::is_lt)
+    }
+    
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.threshold.compare_metric(metric).is_some_and(Ordering::is_lt)));
+    }
+    
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.threshold.compare_float(metric.value).is_some_and(Ordering::is_lt)
+    }
+}
+
+/// Greater than or equal filter
+#[derive(Clone)]
+pub struct GreaterThanOrEqualFilter {
+    threshold: Threshold,
+}
+
+impl GreaterThanOrEqualFilter {
+    pub fn new(value: i64) -> Self {
+        Self { threshold: Threshold::Int(value) }
+    }
+
+    /// Keep values at or above `threshold`, which may lie between integers
+    pub fn from_threshold(threshold: Threshold) -> Self {
+        Self { threshold }
+    }
+}
+
+impl FilterPlugin for GreaterThanOrEqualFilter {
+    fn name(&self) -> &str {
+        "ge"
+    }
+    
+    fn apply(&self, metric: &Metric) -> bool {
+        self.threshold.compare_metric(metric).is_some_and(Ordering::is_ge)
+    }
+    
+    fn apply_parts(&self, value: i64, _timestamp: i64) -> bool {
+        self.threshold.compare_int(value).is_some_and(Ordering::is_ge)
+    }
+    
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.threshold.compare_metric(metric).is_some_and(Ordering::is_ge)));
+    }
+    
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.threshold.compare_float(metric.value).is_some_and(Ordering::is_ge)
+    }
+}
+
+/// Less than or equal filter
+#[derive(Clone)]
+pub struct LessThanOrEqualFilter {
+    threshold: Threshold,
+}
+
+impl LessThanOrEqualFilter {
+    pub fn new(value: i64) -> Self {
+        Self { threshold: Threshold::Int(value) }
+    }
+
+    /// Keep values at or below `threshold`, which may lie between integers
+    pub fn from_threshold(threshold: Threshold) -> Self {
+        Self { threshold }
+    }
+}
+
+impl FilterPlugin for LessThanOrEqualFilter {
+    fn name(&self) -> &str {
+        "le"
+    }
+    
+    fn apply(&self, metric: &Metric) -> bool {
+        self.threshold.compare_metric(metric).is_some_and(Ordering::is_le)
+    }
+    
+    fn apply_parts(&self, value: i64, _timestamp: i64) -> bool {
+        self.threshold.compare_int(value).is_some_and(Ordering::is_le)
+    }
+    
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.threshold.compare_metric(metric).is_some_and(Ordering::is_le)));
+    }
+    
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.threshold.compare_float(metric.value).is_some_and(Ordering::is_le)
+    }
+}
+
+/// Equal filter
+#[derive(Clone)]
+pub struct EqualFilter {
+    threshold: Threshold,
+}
+
+impl EqualFilter {
+    pub fn new(value: i64) -> Self {
+        Self { threshold: Threshold::Int(value) }
+    }
+
+    /// Keep values equal to `threshold`, which may lie between integers
+    pub fn from_threshold(threshold: Threshold) -> Self {
+        Self { threshold }
+    }
+}
+
+impl FilterPlugin for EqualFilter {
+    fn name(&self) -> &str {
+        "eq"
+    }
+    
+    fn apply(&self, metric: &Metric) -> bool {
+        self.threshold.compare_metric(metric).is_some_and(Ordering::is_eq)
+    }
+    
+    fn apply_parts(&self, value: i64, _timestamp: i64) -> bool {
+        self.threshold.compare_int(value).is_some_and(Ordering::is_eq)
+    }
+    
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.threshold.compare_metric(metric).is_some_and(Ordering::is_eq)));
+    }
+    
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.threshold.compare_float(metric.value).is_some_and(Ordering::is_eq)
+    }
+}
+
+/// Keeps values between `lo` and `hi`, including both bounds unless `inclusive` is off.
+///
+/// Bounds may lie between integers, and compare with fixed-point values at their scale,
+/// as the "gt" and "lt" filters do.
+#[derive(Clone)]
+pub struct BetweenFilter {
+    lo: Threshold,
+    hi: Threshold,
+    inclusive: bool,
+}
+
+impl BetweenFilter {
+    pub fn new(lo: impl Into<Threshold>, hi: impl Into<Threshold>, inclusive: bool) -> MetricQueryResult<Self> {
+        let (lo, hi) = (lo.into(), hi.into());
+        match lo.compare(hi) {
+            Some(Ordering::Greater) => Err(MetricQueryError::InvalidFilter {
+                reason: format!("between lower bound {} is above its upper bound {}", lo, hi),
+            }),
+            None => Err(MetricQueryError::InvalidFilter { reason: "between bounds must not be NaN".to_string() }),
+            _ => Ok(Self { lo, hi, inclusive }),
+        }
+    }
+
+    /// Whether a value is kept, given how it compares with the lower and upper bound
+    fn contains(&self, to_lo: Option<Ordering>, to_hi: Option<Ordering>) -> bool {
+        match (to_lo, to_hi) {
+            (Some(to_lo), Some(to_hi)) if self.inclusive => to_lo.is_ge() && to_hi.is_le(),
+            (Some(to_lo), Some(to_hi)) => to_lo.is_gt() && to_hi.is_lt(),
+            _ => false,
+        }
+    }
+}
+
+impl FilterPlugin for BetweenFilter {
+    fn name(&self) -> &str {
+        "between"
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.contains(self.lo.compare_metric(metric), self.hi.compare_metric(metric))
+    }
+
+    fn apply_parts(&self, value: i64, _timestamp: i64) -> bool {
+        self.contains(self.lo.compare_int(value), self.hi.compare_int(value))
+    }
+
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.apply(metric)));
+    }
+
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.contains(self.lo.compare_float(metric.value), self.hi.compare_float(metric.value))
+    }
+}
+
+/// Keeps metrics whose value is one of a set, such as the HTTP status codes of interest
+#[derive(Clone)]
+pub struct ValueInFilter {
+    values: HashSet<i64>,
+}
+
+impl ValueInFilter {
+    pub fn new(values: impl IntoIterator<Item = i64>) -> Self {
+        Self { values: values.into_iter().collect() }
+    }
+}
+
+impl FilterPlugin for ValueInFilter {
+    fn name(&self) -> &str {
+        "value_in"
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.values.contains(&metric.value)
+    }
+
+    fn apply_parts(&self, value: i64, _timestamp: i64) -> bool {
+        self.values.contains(&value)
+    }
+
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.values.contains(&metric.value)));
+    }
+
+    /// Only whole float values can be in the set
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        metric.value.fract() == 0.0 && self.values.contains(&(metric.value as i64))
+    }
+}
+
+/// Keeps metrics with `start <= timestamp < end`, in the pipeline's timestamp units
+#[derive(Clone)]
+pub struct TimestampRangeFilter {
+    start: i64,
+    end: i64,
+}
+
+impl TimestampRangeFilter {
+    pub fn new(start: i64, end: i64) -> MetricQueryResult<Self> {
+        if start > end {
+            return Err(MetricQueryError::InvalidFilter {
+                reason: format!("time range start {} is after its end {}", start, end),
+            });
+        }
+        Ok(Self { start, end })
+    }
+
+    fn contains(&self, timestamp: i64) -> bool {
+        self.start <= timestamp && timestamp < self.end
+    }
+}
+
+impl FilterPlugin for TimestampRangeFilter {
+    fn name(&self) -> &str {
+        "time_range"
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.contains(metric.timestamp)
+    }
+
+    fn apply_parts(&self, _value: i64, timestamp: i64) -> bool {
+        self.contains(timestamp)
+    }
+
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.contains(metric.timestamp)));
+    }
+
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.contains(metric.timestamp)
+    }
+
+    fn reads_value(&self) -> bool {
+        false
+    }
+}
+
+/// How `TimestampFilter` compares timestamps with its instant
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampComparison {
+    Before,
+    After,
+    At,
+}
+
+impl TimestampComparison {
+    /// Parse a comparison name ("before", "after" or "at")
+    pub fn parse(comparison: &str) -> MetricQueryResult<Self> {
+        match comparison {
+            "before" => Ok(Self::Before),
+            "after" => Ok(Self::After),
+            "at" => Ok(Self::At),
+            _ => Err(MetricQueryError::InvalidFilter {
+                reason: format!(
+                    "Unknown timestamp comparison: {}. Expected one of: before, after, at",
+                    comparison
+                ),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Before => "before",
+            Self::After => "after",
+            Self::At => "at",
+        }
+    }
+}
+
+/// Keeps metrics timestamped strictly before, strictly after or exactly at an
+/// instant, in the pipeline's timestamp units
+#[derive(Clone)]
+pub struct TimestampFilter {
+    comparison: TimestampComparison,
+    instant: i64,
+}
+
+impl TimestampFilter {
+    pub fn new(comparison: TimestampComparison, instant: i64) -> Self {
+        Self { comparison, instant }
+    }
+
+    fn accepts(&self, timestamp: i64) -> bool {
+        match self.comparison {
+            TimestampComparison::Before => timestamp < self.instant,
+            TimestampComparison::After => timestamp > self.instant,
+            TimestampComparison::At => timestamp == self.instant,
+        }
+    }
+}
+
+impl FilterPlugin for TimestampFilter {
+    fn name(&self) -> &str {
+        self.comparison.as_str()
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.accepts(metric.timestamp)
+    }
+
+    fn apply_parts(&self, _value: i64, timestamp: i64) -> bool {
+        self.accepts(timestamp)
+    }
+
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.accepts(metric.timestamp)
+    }
+
+    fn reads_value(&self) -> bool {
+        false
+    }
+}
+
+/// Wall-clock time of `timestamp` in the `Settings` timezone (UTC by default); None
+/// when it is outside chrono's range
+fn local_time(timestamp: i64) -> Option<NaiveDateTime> {
+    let settings = Settings::current();
+    let (seconds, _) = settings.timestamp_precision.split(timestamp);
+    let instant = DateTime::from_timestamp(seconds, 0)?;
+    Some(match settings.timezone {
+        Some(tz) => instant.with_timezone(&tz).naive_local(),
+        None => instant.naive_utc(),
+    })
+}
+
+/// Keeps metrics taken on the given days of the week, in the `Settings` timezone
+#[derive(Clone)]
+pub struct WeekdayFilter {
+    days: HashSet<Weekday>,
+}
+
+impl WeekdayFilter {
+    pub fn new(days: impl IntoIterator<Item = Weekday>) -> Self {
+        Self { days: days.into_iter().collect() }
+    }
+
+    /// Parse day names such as "mon" or "Monday"
+    pub fn parse<S: AsRef<str>>(days: &[S]) -> MetricQueryResult<Self> {
+        let days = days
+            .iter()
+            .map(|day| {
+                day.as_ref().parse::<Weekday>().map_err(|_| MetricQueryError::InvalidFilter {
+                    reason: format!("Unknown day of the week: {}. Expected a name such as 'mon' or 'monday'", day.as_ref()),
+                })
+            })
+            .collect::<MetricQueryResult<Vec<_>>>()?;
+        Ok(Self::new(days))
+    }
+
+    fn accepts(&self, timestamp: i64) -> bool {
+        local_time(timestamp).is_some_and(|time| self.days.contains(&time.weekday()))
+    }
+}
+
+impl FilterPlugin for WeekdayFilter {
+    fn name(&self) -> &str {
+        "weekday"
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.accepts(metric.timestamp)
+    }
+
+    fn apply_parts(&self, _value: i64, timestamp: i64) -> bool {
+        self.accepts(timestamp)
+    }
+
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.accepts(metric.timestamp)
+    }
+
+    fn reads_value(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps metrics taken from hour `start` up to hour `end` of the day, in the
+/// `Settings` timezone; a `start` after `end` wraps past midnight, so 22..6 is the
+/// night shift
+#[derive(Clone)]
+pub struct HourOfDayFilter {
+    start: u32,
+    end: u32,
+}
+
+impl HourOfDayFilter {
+    pub fn new(start: u32, end: u32) -> MetricQueryResult<Self> {
+        if start > 23 || end > 24 {
+            return Err(MetricQueryError::InvalidFilter {
+                reason: format!("hours {}..{} are outside the day; start must be 0-23 and end 0-24", start, end),
+            });
+        }
+        Ok(Self { start, end })
+    }
+
+    fn accepts(&self, timestamp: i64) -> bool {
+        local_time(timestamp).is_some_and(|time| {
+            let hour = time.hour();
+            if self.start <= self.end {
+                self.start <= hour && hour < self.end
+            } else {
+                hour >= self.start || hour < self.end
+            }
+        })
+    }
+}
+
+impl FilterPlugin for HourOfDayFilter {
+    fn name(&self) -> &str {
+        "hour_of_day"
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.accepts(metric.timestamp)
+    }
+
+    fn apply_parts(&self, _value: i64, timestamp: i64) -> bool {
+        self.accepts(timestamp)
+    }
+
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.accepts(metric.timestamp)
+    }
+
+    fn reads_value(&self) -> bool {
+        false
+    }
+}
+
+// ----- Label-Specific Filter Implementations -----
+
+/// Keeps metrics labeled `label` ("label_eq"), or with `not_equal`, labeled anything
+/// else ("label_ne")
+#[derive(Clone)]
+pub struct LabelFilter {
+    label: String,
+    negate: bool,
+    keep_unlabeled: bool,
+}
+
+impl LabelFilter {
+    pub fn new(label: String) -> Self {
+        Self { label, negate: false, keep_unlabeled: false }
+    }
+
+    /// Keep metrics not labeled `label`; unlabeled metrics are kept unless
+    /// `keep_unlabeled(false)` says otherwise
+    pub fn not_equal(label: String) -> Self {
+        Self { label, negate: true, keep_unlabeled: true }
+    }
+
+    /// Whether metrics without a label pass the filter
+    pub fn keep_unlabeled(mut self, keep: bool) -> Self {
+        self.keep_unlabeled = keep;
+        self
+    }
+}
+
+impl FilterPlugin for LabelFilter {
+    fn name(&self) -> &str {
+        if self.negate { "label_ne" } else { "label_eq" } // Use a different name than the Python one
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        match &metric.label {
+            Some(l) => (l == &self.label) != self.negate,
+            None => self.keep_unlabeled,
+        }
+    }
+
+    fn reads_value(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps metrics whose label is in a given set ("label_in"), or with `not_in`, whose
+/// label isn't ("label_not_in")
+#[derive(Clone)]
+pub struct LabelInFilter {
+    labels: Vec<String>,
+    negate: bool,
+    keep_unlabeled: bool,
+}
+
+impl LabelInFilter {
+    pub fn new(labels: Vec<String>) -> Self {
+        Self { labels, negate: false, keep_unlabeled: false }
+    }
+
+    /// Keep metrics whose label is none of `labels`; unlabeled metrics are kept unless
+    /// `keep_unlabeled(false)` says otherwise
+    pub fn not_in(labels: Vec<String>) -> Self {
+        Self { labels, negate: true, keep_unlabeled: true }
+    }
+
+    /// Whether metrics without a label pass the filter
+    pub fn keep_unlabeled(mut self, keep: bool) -> Self {
+        self.keep_unlabeled = keep;
+        self
+    }
+}
+
+impl FilterPlugin for LabelInFilter{
+    fn name(&self) -> &str {
+        if self.negate { "label_not_in" } else { "label_in" }
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        match &metric.label {
+            Some(l) => self.labels.contains(l) != self.negate,
+            None => self.keep_unlabeled,
+        }
+    }
+
+    fn reads_value(&self) -> bool {
+        false
+    }
+}
+
+// ----- Tag Filter Implementations -----
+
+/// The value(s) a tag is compared against: one string, or a list for "in"
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(untagged))]
+#[cfg_attr(feature = "python", derive(FromPyObject))]
+#[derive(Debug, Clone, PartialEq)]
+pub enum TagValue {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// How `TagFilter` compares a tag's value
+#[derive(Debug, Clone)]
+pub enum TagMatch {
+    Eq(String),
+    Ne(String),
+    /// Matches the whole value, like Prometheus' `=~`
+    #[cfg(feature = "regex")]
+    Regex(Regex),
+    In(HashSet<String>),
+}
+
+impl TagMatch {
+    /// Parse an operator ("eq", "ne", "regex" or "in") and the value it compares against;
+    /// only "in" takes a list
+    pub fn parse(op: &str, value: TagValue) -> MetricQueryResult<Self> {
+        let invalid = |reason: String| MetricQueryError::InvalidFilter { reason };
+        match (op, value) {
+            ("eq", TagValue::One(value)) => Ok(Self::Eq(value)),
+            ("ne", TagValue::One(value)) => Ok(Self::Ne(value)),
+            #[cfg(feature = "regex")]
+            ("regex", TagValue::One(pattern)) => Regex::new(&format!("^(?:{})$", pattern))
+                .map(Self::Regex)
+                .map_err(|e| invalid(format!("Invalid tag regex {:?}: {}", pattern, e))),
+            #[cfg(not(feature = "regex"))]
+            ("regex", TagValue::One(_)) => Err(invalid("Tag operator 'regex' needs the `regex` feature".to_string())),
+            ("in", TagValue::One(value)) => Ok(Self::In(HashSet::from([value]))),
+            ("in", TagValue::Many(values)) => Ok(Self::In(values.into_iter().collect())),
+            ("eq" | "ne" | "regex", TagValue::Many(_)) => Err(invalid(format!("Tag operator '{}' takes a single value", op))),
+            _ => Err(invalid(format!("Unknown tag operator: {}. Expected one of: eq, ne, regex, in", op))),
+        }
+    }
+
+    /// Whether a metric whose tag is `value` (None when it lacks the tag) matches.
+    /// A missing tag equals nothing, so only "ne" matches it.
+    fn matches(&self, value: Option<&str>) -> bool {
+        match (self, value) {
+            (Self::Ne(expected), value) => value != Some(expected.as_str()),
+            (_, None) => false,
+            (Self::Eq(expected), Some(value)) => value == expected,
+            #[cfg(feature = "regex")]
+            (Self::Regex(regex), Some(value)) => regex.is_match(value),
+            (Self::In(values), Some(value)) => values.contains(value),
+        }
+    }
+}
+
+/// Keeps metrics whose tag `key` matches, for dimensional metrics such as Kubernetes'
+/// namespace/pod/container
+#[derive(Clone)]
+pub struct TagFilter {
+    key: String,
+    matcher: TagMatch,
+}
+
+impl TagFilter {
+    pub fn new(key: impl Into<String>, matcher: TagMatch) -> Self {
+        Self { key: key.into(), matcher }
+    }
+}
+
+impl FilterPlugin for TagFilter {
+    fn name(&self) -> &str {
+        "tag"
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.matcher.matches(metric.tags.get(&self.key).map(String::as_str))
+    }
+
+    /// Bare values carry no tags
+    fn apply_parts(&self, _value: i64, _timestamp: i64) -> bool {
+        self.matcher.matches(None)
+    }
+
+    /// Float metrics carry no tags
+    fn apply_float(&self, _metric: &FloatMetric) -> bool {
+        self.matcher.matches(None)
+    }
+
+    fn reads_value(&self) -> bool {
+        false
+    }
+}
+
+/// Keeps metrics satisfying an `Expr`, e.g. `value > 10 and label == 'cpu'`
+#[derive(Clone)]
+pub struct ExprFilter {
+    expr: Expr,
+}
+
+impl ExprFilter {
+    /// Parse `source`; syntax and type errors fail here rather than per metric
+    pub fn new(source: &str) -> MetricQueryResult<Self> {
+        Ok(Self { expr: Expr::parse(source)? })
+    }
+}
+
+impl FilterPlugin for ExprFilter {
+    fn name(&self) -> &str {
+        "expr"
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.expr.matches(metric)
+    }
+
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.expr.matches_float(metric)
+    }
+}
+
+// ----- Aggregation Plugin Implementations -----
+
+/// What `SumAggregation` does when a total does not fit in an i64
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Fail the step with an overflow error when the total doesn't fit. Sums run in
+    /// i128, so a running total that overflows on the way to one that fits is fine,
+    /// the same on the CPU and on the GPU.
+    #[default]
+    Error,
+    /// Clamp the total to `i64::MIN`/`i64::MAX`
+    Saturate,
+}
+
+impl OverflowPolicy {
+    /// Parse a policy name ("error" or "saturate"); use `execute_float` for totals
+    /// beyond the i64 range
+    pub fn parse(policy: &str) -> MetricQueryResult<Self> {
+        match policy {
+            "error" => Ok(Self::Error),
+            "saturate" => Ok(Self::Saturate),
+            _ => Err(MetricQueryError::InvalidAggregation {
+                reason: format!(
+                    "Unknown overflow policy: {}. Expected one of: error, saturate",
+                    policy
+                ),
+            }),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Saturate => "saturate",
+        }
+    }
+}
+
+/// Sum aggregation
+///
+/// `SumAggregation::default()` takes its overflow policy from the `Settings` in
+/// effect when it runs; `new` fixes one.
+#[derive(Clone, Default)]
+pub struct SumAggregation {
+    overflow_policy: Option<OverflowPolicy>,
+}
+
+impl SumAggregation {
+    pub fn new(overflow_policy: OverflowPolicy) -> Self {
+        Self { overflow_policy: Some(overflow_policy) }
+    }
+    
+    /// Sum values according to the configured overflow policy
+    fn sum<I: Iterator<Item = i64>>(&self, values: I) -> MetricQueryResult<i64> {
+        // Even 2^64 values of i64::MAX fit in an i128
+        let total: i128 = values.map(i128::from).sum();
+        match self.overflow_policy.unwrap_or_else(|| Settings::current().overflow) {
+            OverflowPolicy::Error => {
+                i64::try_from(total).map_err(|_| MetricQueryError::ArithmeticOverflow { operation: "sum".to_string() })
+            }
+            OverflowPolicy::Saturate => Ok(total.clamp(i64::MIN as i128, i64::MAX as i128) as i64),
+        }
+    }
+}
+
+impl AggregationPlugin for SumAggregation {
+    fn name(&self) -> &str {
+        "sum"
+    }
+    
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        if metrics.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        
+        self.sum(metrics.iter().map(|m| m.value))
+    }
+    
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        
+        self.sum(values.iter().copied())
+    }
+    
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        
+        Ok(values.iter().sum())
+    }
+    
+    fn keeps_scale(&self) -> bool {
+        true
+    }
+    
+    #[cfg(feature = "gpu")]
+    fn gpu_reduction(&self) -> Option<crate::gpu::Reduction> {
+        Some(crate::gpu::Reduction::Sum(self.overflow_policy.unwrap_or_else(|| Settings::current().overflow)))
+    }
+}
+
+/// How `AvgAggregation` turns an exact mean into an integer value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RoundingMode {
+    /// Round toward zero (integer division)
+    #[default]
+    Truncate,
+    /// Round to the nearest integer, halves away from zero
+    Round,
+    /// Round toward negative infinity
+    Floor,
+    /// Round toward positive infinity
+    Ceil,
+}
+
+impl RoundingMode {
+    /// Parse a rounding mode name ("truncate", "round", "floor" or "ceil")
+    pub fn parse(mode: &str) -> MetricQueryResult<Self> {
+        match mode {
+            "truncate" => Ok(Self::Truncate),
+            "round" => Ok(Self::Round),
+            "floor" => Ok(Self::Floor),
+            "ceil" => Ok(Self::Ceil),
+            _ => Err(MetricQueryError::InvalidAggregation {
+                reason: format!(
+                    "Unknown rounding mode: {}. Expected one of: truncate, round, floor, ceil",
+                    mode
+                ),
+            }),
+        }
+    }
+    
+    /// Divide `sum` by a positive `count` using this rounding mode
+    pub(crate) fn divide(self, sum: i128, count: i128) -> i128 {
+        match self {
+            Self::Truncate => sum / count,
+            Self::Floor => sum.div_euclid(count),
+            Self::Ceil => -(-sum).div_euclid(count),
+            Self::Round => {
+                if sum >= 0 {
+                    (2 * sum + count) / (2 * count)
+                } else {
+                    (2 * sum - count) / (2 * count)
+                }
+            }
+        }
+    }
+}
+
+/// Average aggregation
+///
+/// Integer output is rounded with the configured `RoundingMode`. Float execution
+/// (`execute_float` / `execute_as_float`) always returns the exact mean.
+#[derive(Clone, Default)]
+pub struct AvgAggregation {
+    rounding: RoundingMode,
+}
+
+impl AvgAggregation {
+    pub fn new(rounding: RoundingMode) -> Self {
+        Self { rounding }
+    }
+}
+
+impl AggregationPlugin for AvgAggregation {
+    fn name(&self) -> &str {
+        "avg"
+    }
+    
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        if metrics.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        
+        // Accumulate in i128 so the intermediate sum cannot overflow; the mean always fits
+        let sum: i128 = metrics.iter().map(|m| i128::from(m.value)).sum();
+        Ok(self.rounding.divide(sum, metrics.len() as i128) as i64)
+    }
+    
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        
+        let sum: i128 = values.iter().copied().map(i128::from).sum();
+        Ok(self.rounding.divide(sum, values.len() as i128) as i64)
+    }
+    
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        
+        let sum: f64 = values.iter().sum();
+        Ok(sum / values.len() as f64)
+    }
+    
+    fn keeps_scale(&self) -> bool {
+        true
+    }
+    
+    #[cfg(feature = "gpu")]
+    fn gpu_reduction(&self) -> Option<crate::gpu::Reduction> {
+        Some(crate::gpu::Reduction::Avg(self.rounding))
+    }
+}
+
+/// Minimum aggregation
+#[derive(Clone)]
+pub struct MinAggregation;
+
+impl AggregationPlugin for MinAggregation {
+    fn name(&self) -> &str {
+        "min"
+    }
+    
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        metrics.iter().map(|m| m.value).min().ok_or(MetricQueryError::EmptyMetricStream)
+    }
+    
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        values.iter().copied().min().ok_or(MetricQueryError::EmptyMetricStream)
+    }
+    
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        values.iter().copied().reduce(f64::min).ok_or(MetricQueryError::EmptyMetricStream)
     }
     
-    fn apply(&self, metric: &Metric) -> bool { // Updated signature
-        metric.value > self.value
+    fn keeps_scale(&self) -> bool {
+        true
     }
     
-    fn clone_box(&self) -> Box<dyn FilterPlugin> {
-        Box::new(self.clone())
+    #[cfg(feature = "gpu")]
+    fn gpu_reduction(&self) -> Option<crate::gpu::Reduction> {
+        Some(crate::gpu::Reduction::Min)
     }
 }
 
-/// Less than filter
+/// Maximum aggregation
 #[derive(Clone)]
-pub struct LessThanFilter {
-    value: i64,
-}
-
-impl LessThanFilter {
-    pub fn new(value: i64) -> Self {
-        Self { value }
-    }
-}
+pub struct MaxAggregation;
 
-impl FilterPlugin for LessThanFilter {
+impl AggregationPlugin for MaxAggregation {
     fn name(&self) -> &str {
-        "lt"
+        "max"
+    }
+    
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        metrics.iter().map(|m| m.value).max().ok_or(MetricQueryError::EmptyMetricStream)
+    }
+    
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        values.iter().copied().max().ok_or(MetricQueryError::EmptyMetricStream)
+    }
+    
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        values.iter().copied().reduce(f64::max).ok_or(MetricQueryError::EmptyMetricStream)
     }
     
-    fn apply(&self, metric: &Metric) -> bool { // Updated signature
-        metric.value < self.value
+    fn keeps_scale(&self) -> bool {
+        true
     }
     
-    fn clone_box(&self) -> Box<dyn FilterPlugin> {
-        Box::new(self.clone())
+    #[cfg(feature = "gpu")]
+    fn gpu_reduction(&self) -> Option<crate::gpu::Reduction> {
+        Some(crate::gpu::Reduction::Max)
     }
 }
 
-/// Greater than or equal filter
+/// Percentile aggregation, e.g. "p99" or "median"
+///
+/// Exact percentiles interpolate linearly between the closest ranks, like `describe`;
+/// approximate ones come from a DDSketch within `PERCENTILE_RELATIVE_ACCURACY` of the
+/// true value. Exact integer percentiles interpolate in i128, so they stay exact past
+/// 2^53; integer results are rounded to the nearest integer.
 #[derive(Clone)]
-pub struct GreaterThanOrEqualFilter {
-    value: i64,
+pub struct PercentileAggregation {
+    name: String,
+    quantile: f64,
+    accuracy: Accuracy,
 }
 
-impl GreaterThanOrEqualFilter {
-    pub fn new(value: i64) -> Self {
-        Self { value }
+impl PercentileAggregation {
+    /// Percentile at `quantile` in [0, 1], named "median" at 0.5 and e.g. "p99" otherwise
+    pub fn new(quantile: f64, accuracy: Accuracy) -> MetricQueryResult<Self> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(MetricQueryError::InvalidAggregation {
+                reason: format!("Percentile must be between 0 and 1, got {}", quantile),
+            });
+        }
+        let name = match quantile {
+            0.5 => "median".to_string(),
+            // Round away float noise so 0.999 is named "p99.9"
+            _ => format!("p{}", (quantile * 1e6).round() / 1e4),
+        };
+        Ok(Self { name, quantile, accuracy })
+    }
+    
+    /// Parse a percentile name: "median" or "p" followed by a percentage such as "p99" or "p99.9"
+    pub fn parse(name: &str) -> Option<Self> {
+        let percent = match name {
+            "median" => 50.0,
+            _ => name.strip_prefix('p')?.parse::<f64>().ok()?,
+        };
+        Self::new(percent / 100.0, Accuracy::default()).ok()
+    }
+    
+    /// Estimate from a DDSketch; NaN when every value is missing, like the exact path
+    fn approximate(&self, values: impl Iterator<Item = f64>) -> MetricQueryResult<f64> {
+        let mut sketch = SketchMetric::new(PERCENTILE_RELATIVE_ACCURACY, 0, None)?;
+        values.for_each(|value| sketch.add(value));
+        if sketch.count == 0 {
+            return Ok(f64::NAN);
+        }
+        sketch.quantile(self.quantile)
     }
 }
 
-impl FilterPlugin for GreaterThanOrEqualFilter {
+impl AggregationPlugin for PercentileAggregation {
     fn name(&self) -> &str {
-        "ge"
+        &self.name
     }
     
-    fn apply(&self, metric: &Metric) -> bool { // Updated signature
-        metric.value >= self.value
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        self.apply_values(&metrics.iter().map(|m| m.value).collect::<Vec<_>>())
     }
     
-    fn clone_box(&self) -> Box<dyn FilterPlugin> {
-        Box::new(self.clone())
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        match self.accuracy.resolve(values.len()) {
+            Accuracy::Approximate => Ok(self.approximate(values.iter().map(|&v| v as f64))?.round() as i64),
+            _ => {
+                let mut sorted = values.to_vec();
+                sorted.sort_unstable();
+                Ok(quantile_of_sorted_ints(&sorted, self.quantile))
+            }
+        }
     }
-}
-
-/// Less than or equal filter
-#[derive(Clone)]
-pub struct LessThanOrEqualFilter {
-    value: i64,
-}
-
-impl LessThanOrEqualFilter {
-    pub fn new(value: i64) -> Self {
-        Self { value }
+    
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        match self.accuracy.resolve(values.len()) {
+            Accuracy::Approximate => self.approximate(values.iter().copied()),
+            _ => {
+                let mut sorted: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+                if sorted.is_empty() {
+                    return Ok(f64::NAN);
+                }
+                sorted.sort_by(f64::total_cmp);
+                Ok(quantile_of_sorted(&sorted, self.quantile))
+            }
+        }
     }
-}
-
-impl FilterPlugin for LessThanOrEqualFilter {
-    fn name(&self) -> &str {
-        "le"
+    
+    fn accuracy(&self) -> Option<Accuracy> {
+        Some(self.accuracy)
     }
     
-    fn apply(&self, metric: &Metric) -> bool { // Updated signature
-        metric.value <= self.value
+    fn with_accuracy(&self, accuracy: Accuracy) -> Option<Box<dyn AggregationPlugin>> {
+        Some(Box::new(Self { accuracy, ..self.clone() }))
     }
     
-    fn clone_box(&self) -> Box<dyn FilterPlugin> {
-        Box::new(self.clone())
+    fn keeps_scale(&self) -> bool {
+        true
     }
 }
 
-/// Equal filter
-#[derive(Clone)]
-pub struct EqualFilter {
-    value: i64,
+/// Number of distinct values; approximate counts come from a HyperLogLog
+#[derive(Clone, Default)]
+pub struct DistinctCountAggregation {
+    accuracy: Accuracy,
 }
 
-impl EqualFilter {
-    pub fn new(value: i64) -> Self {
-        Self { value }
+impl DistinctCountAggregation {
+    pub fn new(accuracy: Accuracy) -> Self {
+        Self { accuracy }
+    }
+    
+    /// Count distinct keys; float values are keyed by their bits, with NaN skipped
+    fn count<K: Hash + Eq>(&self, keys: impl ExactSizeIterator<Item = K>) -> MetricQueryResult<i64> {
+        if keys.len() == 0 {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        let count = match self.accuracy.resolve(keys.len()) {
+            Accuracy::Approximate => {
+                let mut counter = HyperLogLog::new();
+                keys.for_each(|key| counter.add(key));
+                counter.estimate() as usize
+            }
+            _ => keys.collect::<HashSet<K>>().len(),
+        };
+        Ok(count as i64)
     }
 }
 
-impl FilterPlugin for EqualFilter {
+impl AggregationPlugin for DistinctCountAggregation {
     fn name(&self) -> &str {
-        "eq"
+        "count_distinct"
+    }
+    
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        self.count(metrics.iter().map(|m| m.value))
+    }
+    
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        self.count(values.iter().copied())
+    }
+    
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        // +0.0 and -0.0 count as one value
+        let present: Vec<u64> = values.iter().filter(|v| !v.is_nan()).map(|&v| (v + 0.0).to_bits()).collect();
+        if present.is_empty() {
+            return Ok(0.0);
+        }
+        self.count(present.into_iter()).map(|count| count as f64)
     }
     
-    fn apply(&self, metric: &Metric) -> bool { // Updated signature
-        metric.value == self.value
+    fn accuracy(&self) -> Option<Accuracy> {
+        Some(self.accuracy)
     }
     
-    fn clone_box(&self) -> Box<dyn FilterPlugin> {
-        Box::new(self.clone())
+    fn with_accuracy(&self, accuracy: Accuracy) -> Option<Box<dyn AggregationPlugin>> {
+        Some(Box::new(Self { accuracy }))
     }
 }
 
-// ----- Label-Specific Filter Implementations -----
-
-#[derive(Clone)]
-pub struct LabelFilter {
-    label: String,
+/// Give `aggregation` an accuracy mode; fails for aggregations that are always exact
+pub fn with_accuracy(aggregation: Box<dyn AggregationPlugin>, accuracy: Accuracy) -> MetricQueryResult<Box<dyn AggregationPlugin>> {
+    aggregation.with_accuracy(accuracy).ok_or_else(|| MetricQueryError::InvalidAggregation {
+        reason: format!(
+            "Accuracy only applies to percentile and count_distinct aggregations, not '{}'",
+            aggregation.name()
+        ),
+    })
 }
 
-impl LabelFilter {
-    pub fn new(label: String) -> Self {
-        Self { label }
-    }
+/// How an aggregation treats missing (NaN) float values
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissingValuePolicy {
+    /// Ignore missing values; a group with only missing values stays missing
+    Skip,
+    /// Any missing value makes the aggregate missing
+    Propagate,
+    /// Replace missing values with a constant before aggregating
+    Substitute(f64),
 }
 
-impl FilterPlugin for LabelFilter {
-    fn name(&self) -> &str {
-        "label_eq" // Use a different name than the Python one
+impl MissingValuePolicy {
+    /// Parse a policy name ("skip", "propagate" or "substitute"); "substitute" needs a fill value
+    pub fn parse(policy: &str, fill_value: Option<f64>) -> MetricQueryResult<Self> {
+        match (policy, fill_value) {
+            ("skip", _) => Ok(Self::Skip),
+            ("propagate", _) => Ok(Self::Propagate),
+            ("substitute", Some(value)) => Ok(Self::Substitute(value)),
+            ("substitute", None) => Err(MetricQueryError::InvalidAggregation {
+                reason: "Missing-value policy 'substitute' requires a fill value".to_string(),
+            }),
+            _ => Err(MetricQueryError::InvalidAggregation {
+                reason: format!(
+                    "Unknown missing-value policy: {}. Expected one of: skip, propagate, substitute",
+                    policy
+                ),
+            }),
+        }
     }
-
-    fn apply(&self, metric: &Metric) -> bool {
-        match &metric.label {
-            Some(l) => l == &self.label,
-            None => false, // Don't include unlabeled metrics
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Skip => "skip",
+            Self::Propagate => "propagate",
+            Self::Substitute(_) => "substitute",
         }
     }
+    
+    /// Aggregate `values` with `aggregation` after applying the policy to the missing ones
+    pub fn aggregate(&self, aggregation: &dyn AggregationPlugin, values: &[f64]) -> MetricQueryResult<f64> {
+        match self {
+            Self::Skip => {
+                let present: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+                if present.is_empty() {
+                    return Ok(f64::NAN);
+                }
+                aggregation.apply_float_values(&present)
+            }
+            Self::Propagate => {
+                if values.iter().any(|v| v.is_nan()) {
+                    return Ok(f64::NAN);
+                }
+                aggregation.apply_float_values(values)
+            }
+            Self::Substitute(fill) => {
+                let filled: Vec<f64> = values
+                    .iter()
+                    .map(|&v| if v.is_nan() { *fill } else { v })
+                    .collect();
+                aggregation.apply_float_values(&filled)
+            }
+        }
+    }
+}
 
-    fn clone_box(&self) -> Box<dyn FilterPlugin> {
-        Box::new(self.clone())
+/// Aggregate float values, first applying the `Settings` missing-value policy unless
+/// the aggregation has its own. Every float aggregation in a pipeline goes through here.
+pub fn aggregate_float_values(aggregation: &dyn AggregationPlugin, values: &[f64]) -> MetricQueryResult<f64> {
+    match Settings::current().missing {
+        Some(policy) if aggregation.missing_policy().is_none() && !values.is_empty() => {
+            policy.aggregate(aggregation, values)
+        }
+        _ => aggregation.apply_float_values(values),
     }
 }
 
-// Example: Filter for metrics where the label is in a given set
+/// Wraps an aggregation and applies a missing-value policy before it sees float values.
+/// Integer values can't be missing, so the integer paths delegate unchanged.
 #[derive(Clone)]
-pub struct LabelInFilter {
-    labels: Vec<String>,
+pub struct MissingValueAggregation {
+    inner: Box<dyn AggregationPlugin>,
+    policy: MissingValuePolicy,
 }
 
-impl LabelInFilter {
-    pub fn new(labels: Vec<String>) -> Self {
-        Self { labels }
+impl MissingValueAggregation {
+    pub fn new(inner: Box<dyn AggregationPlugin>, policy: MissingValuePolicy) -> Self {
+        Self { inner, policy }
     }
 }
 
-impl FilterPlugin for LabelInFilter{
+impl AggregationPlugin for MissingValueAggregation {
     fn name(&self) -> &str {
-        "label_in"
+        self.inner.name()
     }
-
-    fn apply(&self, metric: &Metric) -> bool {
-        match &metric.label {
-            Some(l) => self.labels.contains(l),
-            None => false
-        }
+    
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        self.inner.apply(metrics)
     }
-    fn clone_box(&self) -> Box<dyn FilterPlugin> {
-        Box::new(self.clone())
+    
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        self.inner.apply_values(values)
     }
-}
-
-// ----- Aggregation Plugin Implementations -----
-
-/// Sum aggregation
-#[derive(Clone)]
-pub struct SumAggregation;
-
-impl AggregationPlugin for SumAggregation {
-    fn name(&self) -> &str {
-        "sum"
+    
+    fn thread_bound(&self) -> bool {
+        self.inner.thread_bound()
     }
     
-    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
-        if metrics.is_empty() {
-            return Err(MetricQueryError::EmptyMetricStream);
-        }
-        
-        Ok(metrics.iter().map(|m| m.value).sum())
+    fn keeps_scale(&self) -> bool {
+        self.inner.keeps_scale()
     }
     
-    fn clone_box(&self) -> Box<dyn AggregationPlugin> {
-        Box::new(self.clone())
+    fn accuracy(&self) -> Option<Accuracy> {
+        self.inner.accuracy()
     }
-}
-
-/// Average aggregation
-#[derive(Clone)]
-pub struct AvgAggregation;
-
-impl AggregationPlugin for AvgAggregation {
-    fn name(&self) -> &str {
-        "avg"
+    
+    fn with_accuracy(&self, accuracy: Accuracy) -> Option<Box<dyn AggregationPlugin>> {
+        let inner = self.inner.with_accuracy(accuracy)?;
+        Some(Box::new(Self { inner, policy: self.policy }))
     }
     
-    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
-        if metrics.is_empty() {
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        if values.is_empty() {
             return Err(MetricQueryError::EmptyMetricStream);
         }
         
-        let sum: i64 = metrics.iter().map(|m| m.value).sum();
-        Ok(sum / metrics.len() as i64)
+        self.policy.aggregate(&*self.inner, values)
     }
     
-    fn clone_box(&self) -> Box<dyn AggregationPlugin> {
-        Box::new(self.clone())
+    fn missing_policy(&self) -> Option<MissingValuePolicy> {
+        Some(self.policy)
     }
 }
 
-/// Minimum aggregation
-#[derive(Clone)]
-pub struct MinAggregation;
+// ----- Time Grouping Plugin Implementations -----
 
-impl AggregationPlugin for MinAggregation {
-    fn name(&self) -> &str {
-        "min"
-    }
-    
-    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
-        metrics.iter().map(|m| m.value).min().ok_or(MetricQueryError::EmptyMetricStream)
-    }
-    
-    fn clone_box(&self) -> Box<dyn AggregationPlugin> {
-        Box::new(self.clone())
-    }
+/// The UTC instant of `timestamp`, which may be negative (before 1970); outside the
+/// years chrono can represent (about ±262,000) grouping fails rather than guess
+fn grouping_datetime(timestamp: i64) -> MetricQueryResult<DateTime<Utc>> {
+    DateTime::<Utc>::from_timestamp(timestamp, 0).ok_or_else(|| MetricQueryError::InvalidTimeGrouping {
+        reason: format!(
+            "Timestamp {} is outside the supported range {} to {}",
+            timestamp,
+            DateTime::<Utc>::MIN_UTC.timestamp(),
+            DateTime::<Utc>::MAX_UTC.timestamp()
+        ),
+    })
 }
 
-/// Maximum aggregation
+/// Start of the `span`-second bucket holding `timestamp` on the wall clock of the
+/// `Settings` timezone (UTC by default), in the timestamp precision.
+///
+/// Zones whose offset isn't a whole number of spans, like India's +5:30 for hours or
+/// the local mean times zones kept before standard time for minutes, start their
+/// buckets off the UTC boundaries.
+fn wall_clock_bucket(timestamp: i64, span: i64) -> MetricQueryResult<i64> {
+    let settings = Settings::current();
+    let precision = settings.timestamp_precision;
+    let (timestamp, _) = precision.split(timestamp);
+    let dt = grouping_datetime(timestamp)?;
+    let offset = settings
+        .timezone
+        .map_or(0, |tz| i64::from(tz.offset_from_utc_datetime(&dt.naive_utc()).fix().local_minus_utc()));
+    let local = timestamp + offset;
+    precision.from_seconds(local - local.rem_euclid(span) - offset)
+}
+
+/// Hour time grouping, on the clock of the `Settings` timezone (UTC by default)
 #[derive(Clone)]
-pub struct MaxAggregation;
+pub struct HourGrouping;
 
-impl AggregationPlugin for MaxAggregation {
+impl TimeGroupingPlugin for HourGrouping {
     fn name(&self) -> &str {
-        "max"
-    }
-    
-    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
-        metrics.iter().map(|m| m.value).max().ok_or(MetricQueryError::EmptyMetricStream)
+        "hour"
     }
     
-    fn clone_box(&self) -> Box<dyn AggregationPlugin> {
-        Box::new(self.clone())
+    fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64> {
+        wall_clock_bucket(timestamp, 3_600)
     }
 }
 
-// ----- Time Grouping Plugin Implementations -----
-
-/// Hour time grouping
+/// Second time grouping, for timestamps with sub-second precision; at the default
+/// precision of seconds every timestamp is already its own group
 #[derive(Clone)]
-pub struct HourGrouping;
+pub struct SecondGrouping;
 
-impl TimeGroupingPlugin for HourGrouping {
+impl TimeGroupingPlugin for SecondGrouping {
     fn name(&self) -> &str {
-        "hour"
+        "second"
     }
     
     fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64> {
-        let dt = DateTime::<Utc>::from_timestamp(timestamp, 0)
-            .ok_or_else(|| MetricQueryError::InvalidTimeGrouping {
-                reason: format!("Invalid timestamp: {}", timestamp),
-            })?;
-        
-        let grouped_dt = dt
-            .with_minute(0)
-            .and_then(|dt| dt.with_second(0))
-            .ok_or_else(|| MetricQueryError::OperationFailed {
-                operation: "hour grouping".to_string(),
-                reason: "Failed to set minute/second to 0".to_string(),
-            })?;
-        
-        Ok(grouped_dt.timestamp())
-    }
-    
-    fn clone_box(&self) -> Box<dyn TimeGroupingPlugin> {
-        Box::new(self.clone())
+        let precision = Settings::current().timestamp_precision;
+        precision.from_seconds(precision.split(timestamp).0)
     }
 }
 
-/// Minute time grouping
+/// Minute time grouping, on the clock of the `Settings` timezone (UTC by default)
 #[derive(Clone)]
 pub struct MinuteGrouping;
 
@@ -324,27 +1506,11 @@ impl TimeGroupingPlugin for MinuteGrouping {
     }
     
     fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64> {
-        let dt = DateTime::<Utc>::from_timestamp(timestamp, 0)
-            .ok_or_else(|| MetricQueryError::InvalidTimeGrouping {
-                reason: format!("Invalid timestamp: {}", timestamp),
-            })?;
-        
-        let grouped_dt = dt
-            .with_second(0)
-            .ok_or_else(|| MetricQueryError::OperationFailed {
-                operation: "minute grouping".to_string(),
-                reason: "Failed to set second to 0".to_string(),
-            })?;
-        
-        Ok(grouped_dt.timestamp())
-    }
-    
-    fn clone_box(&self) -> Box<dyn TimeGroupingPlugin> {
-        Box::new(self.clone())
+        wall_clock_bucket(timestamp, 60)
     }
 }
 
-/// Day time grouping
+/// Day time grouping, from midnight to midnight in the `Settings` timezone (UTC by default)
 #[derive(Clone)]
 pub struct DayGrouping;
 
@@ -354,10 +1520,14 @@ impl TimeGroupingPlugin for DayGrouping {
     }
     
     fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64> {
-        let dt = DateTime::<Utc>::from_timestamp(timestamp, 0)
-            .ok_or_else(|| MetricQueryError::InvalidTimeGrouping {
-                reason: format!("Invalid timestamp: {}", timestamp),
-            })?;
+        let settings = Settings::current();
+        let precision = settings.timestamp_precision;
+        let (timestamp, _) = precision.split(timestamp);
+        let dt = grouping_datetime(timestamp)?;
+        
+        if let Some(tz) = settings.timezone {
+            return precision.from_seconds(TimeRange::day_containing(timestamp, tz)?.start);
+        }
         
         let grouped_dt = dt
             .with_hour(0)
@@ -368,24 +1538,119 @@ impl TimeGroupingPlugin for DayGrouping {
                 reason: "Failed to set hour/minute/second to 0".to_string(),
             })?;
         
-        Ok(grouped_dt.timestamp())
+        precision.from_seconds(grouped_dt.timestamp())
+    }
+}
+
+// ----- Closure Adapters -----
+
+/// Closure wrapped by `FnFilter`
+pub type MetricPredicate = Arc<dyn Fn(&Metric) -> bool + Send + Sync>;
+
+/// Closure wrapped by `FnAggregation`
+pub type MetricReducer = Arc<dyn Fn(&[Metric]) -> i64 + Send + Sync>;
+
+/// Closure wrapped by `FnTimeGrouping`
+pub type TimestampGrouper = Arc<dyn Fn(i64) -> i64 + Send + Sync>;
+
+/// Filter plugin wrapping a closure, for one-off Rust filters without a dedicated struct
+#[derive(Clone)]
+pub struct FnFilter {
+    name: String,
+    predicate: MetricPredicate,
+}
+
+impl FnFilter {
+    pub fn new(name: impl Into<String>, predicate: impl Fn(&Metric) -> bool + Send + Sync + 'static) -> Self {
+        Self { name: name.into(), predicate: Arc::new(predicate) }
+    }
+}
+
+impl FilterPlugin for FnFilter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn apply(&self, metric: &Metric) -> bool {
+        (self.predicate)(metric)
+    }
+}
+
+/// Aggregation plugin wrapping a closure that reduces a group of metrics to one value.
+/// Empty groups are rejected before the closure sees them. Results are plain integers
+/// unless `keeping_scale` says they are in the inputs' units.
+#[derive(Clone)]
+pub struct FnAggregation {
+    name: String,
+    reduce: MetricReducer,
+    keeps_scale: bool,
+}
+
+impl FnAggregation {
+    pub fn new(name: impl Into<String>, reduce: impl Fn(&[Metric]) -> i64 + Send + Sync + 'static) -> Self {
+        Self { name: name.into(), reduce: Arc::new(reduce), keeps_scale: false }
+    }
+
+    /// Mark results as fixed-point values at the inputs' scale, as for a sum
+    pub fn keeping_scale(mut self) -> Self {
+        self.keeps_scale = true;
+        self
+    }
+}
+
+impl AggregationPlugin for FnAggregation {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        if metrics.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+        Ok((self.reduce)(metrics))
+    }
+    
+    fn keeps_scale(&self) -> bool {
+        self.keeps_scale
+    }
+}
+
+/// Time grouping plugin wrapping a closure that maps a timestamp to its group's timestamp.
+///
+/// The closure sees raw UTC timestamps; the `Settings` timezone is up to it to apply.
+#[derive(Clone)]
+pub struct FnTimeGrouping {
+    name: String,
+    group: TimestampGrouper,
+}
+
+impl FnTimeGrouping {
+    pub fn new(name: impl Into<String>, group: impl Fn(i64) -> i64 + Send + Sync + 'static) -> Self {
+        Self { name: name.into(), group: Arc::new(group) }
+    }
+}
+
+impl TimeGroupingPlugin for FnTimeGrouping {
+    fn name(&self) -> &str {
+        &self.name
     }
     
-    fn clone_box(&self) -> Box<dyn TimeGroupingPlugin> {
-        Box::new(self.clone())
+    fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64> {
+        Ok((self.group)(timestamp))
     }
 }
 
 // ----- Factory Functions -----
 
-/// Create a filter from type and value
-pub fn create_filter(filter_type: &str, value: i64) -> MetricQueryResult<Box<dyn FilterPlugin>> {
+/// Create a filter from type and value; the value may be an integer or a float
+pub fn create_filter(filter_type: &str, value: impl Into<Threshold>) -> MetricQueryResult<Box<dyn FilterPlugin>> {
+    let value = value.into();
     match filter_type {
-        "gt" => Ok(Box::new(GreaterThanFilter::new(value))),
-        "lt" => Ok(Box::new(LessThanFilter::new(value))),
-        "ge" => Ok(Box::new(GreaterThanOrEqualFilter::new(value))),
-        "le" => Ok(Box::new(LessThanOrEqualFilter::new(value))),
-        "eq" => Ok(Box::new(EqualFilter::new(value))),
+        "gt" => Ok(Box::new(GreaterThanFilter::from_threshold(value))),
+        "lt" => Ok(Box::new(LessThanFilter::from_threshold(value))),
+        "ge" => Ok(Box::new(GreaterThanOrEqualFilter::from_threshold(value))),
+        "le" => Ok(Box::new(LessThanOrEqualFilter::from_threshold(value))),
+        "eq" => Ok(Box::new(EqualFilter::from_threshold(value))),
         _ => Err(MetricQueryError::InvalidFilter {
             reason: format!("Unknown filter type: {}", filter_type),
         }),
@@ -395,13 +1660,17 @@ pub fn create_filter(filter_type: &str, value: i64) -> MetricQueryResult<Box<dyn
 /// Create an aggregation from type
 pub fn create_aggregation(agg_type: &str) -> MetricQueryResult<Box<dyn AggregationPlugin>> {
     match agg_type {
-        "sum" => Ok(Box::new(SumAggregation)),
-        "avg" => Ok(Box::new(AvgAggregation)),
+        "sum" => Ok(Box::new(SumAggregation::default())),
+        "avg" => Ok(Box::new(AvgAggregation::default())),
         "min" => Ok(Box::new(MinAggregation)),
         "max" => Ok(Box::new(MaxAggregation)),
-        _ => Err(MetricQueryError::InvalidAggregation {
-            reason: format!("Unknown aggregation type: {}", agg_type),
-        }),
+        "count_distinct" => Ok(Box::new(DistinctCountAggregation::default())),
+        _ => match PercentileAggregation::parse(agg_type) {
+            Some(percentile) => Ok(Box::new(percentile)),
+            None => Err(MetricQueryError::InvalidAggregation {
+                reason: format!("Unknown aggregation type: {}", agg_type),
+            }),
+        },
     }
 }
 
@@ -409,6 +1678,7 @@ pub fn create_aggregation(agg_type: &str) -> MetricQueryResult<Box<dyn Aggregati
 pub fn create_time_grouping(grouping_type: &str) -> MetricQueryResult<Box<dyn TimeGroupingPlugin>> {
     match grouping_type {
         "hour" => Ok(Box::new(HourGrouping)),
+        "second" => Ok(Box::new(SecondGrouping)),
         "minute" => Ok(Box::new(MinuteGrouping)),
         "day" => Ok(Box::new(DayGrouping)),
         _ => Err(MetricQueryError::InvalidTimeGrouping {
@@ -421,6 +1691,7 @@ pub fn create_time_grouping(grouping_type: &str) -> MetricQueryResult<Box<dyn Ti
 pub fn create_label_filter(filter_type: &str, label: String) -> MetricQueryResult<Box<dyn FilterPlugin>> {
     match filter_type {
         "label_eq" => Ok(Box::new(LabelFilter::new(label))),
+        "label_ne" => Ok(Box::new(LabelFilter::not_equal(label))),
         _ => Err(MetricQueryError::InvalidFilter {
             reason: format!("Unknown label filter type: {}", filter_type),
         }),
@@ -431,6 +1702,7 @@ pub fn create_label_filter(filter_type: &str, label: String) -> MetricQueryResul
 pub fn create_label_in_filter(filter_type: &str, labels: Vec<String>) -> MetricQueryResult<Box<dyn FilterPlugin>> {
     match filter_type {
         "label_in" => Ok(Box::new(LabelInFilter::new(labels))),
+        "label_not_in" => Ok(Box::new(LabelInFilter::not_in(labels))),
         _ => Err(MetricQueryError::InvalidFilter {
             reason: format!("Unknown label filter type: {}", filter_type),
         }),
@@ -448,57 +1720,76 @@ pub fn init_registry() {
         registry.register_filter(Box::new(EqualFilter::new(0)));
         registry.register_filter(Box::new(LabelFilter::new("".to_string())));
         registry.register_filter(Box::new(LabelInFilter::new(vec![])));
+        for name in ["gt", "lt", "ge", "le", "eq"] {
+            registry.register_filter_factory(name, Arc::new(move |value| create_filter(name, value)));
+        }
         
         // Register aggregations
-        registry.register_aggregation(Box::new(SumAggregation));
-        registry.register_aggregation(Box::new(AvgAggregation));
+        registry.register_aggregation(Box::new(SumAggregation::default()));
+        registry.register_aggregation(Box::new(AvgAggregation::default()));
         registry.register_aggregation(Box::new(MinAggregation));
         registry.register_aggregation(Box::new(MaxAggregation));
+        registry.register_aggregation(Box::new(DistinctCountAggregation::default()));
+        for quantile in [0.5, 0.9, 0.95, 0.99] {
+            if let Ok(percentile) = PercentileAggregation::new(quantile, Accuracy::default()) {
+                registry.register_aggregation(Box::new(percentile));
+            }
+        }
         
         // Register time groupings
         registry.register_time_grouping(Box::new(HourGrouping));
+        registry.register_time_grouping(Box::new(SecondGrouping));
         registry.register_time_grouping(Box::new(MinuteGrouping));
         registry.register_time_grouping(Box::new(DayGrouping));
     });
 }
 
 // Python wrapper functions for creating plugins
+#[cfg(feature = "python")]
 #[pyfunction]
-pub fn py_create_filter(filter_type: &str, value: i64) -> PyResult<String> {
+pub fn py_create_filter(filter_type: &str, value: Threshold) -> PyResult<String> {
     match create_filter(filter_type, value) {
         Ok(filter) => Ok(filter.name().to_string()),
-        Err(e) => Err(pyo3::exceptions::PyValueError::new_err(format!("{:?}", e))),
+        Err(e) => Err(e.into()),
     }
 }
 
+#[cfg(feature = "python")]
+
 #[pyfunction]
 pub fn py_create_aggregation(agg_type: &str) -> PyResult<String> {
     match create_aggregation(agg_type) {
         Ok(agg) => Ok(agg.name().to_string()),
-        Err(e) => Err(pyo3::exceptions::PyValueError::new_err(format!("{:?}", e))),
+        Err(e) => Err(e.into()),
     }
 }
 
+#[cfg(feature = "python")]
+
 #[pyfunction]
 pub fn py_create_time_grouping(grouping_type: &str) -> PyResult<String> {
     match create_time_grouping(grouping_type) {
         Ok(group) => Ok(group.name().to_string()),
-        Err(e) => Err(pyo3::exceptions::PyValueError::new_err(format!("{:?}", e))),
+        Err(e) => Err(e.into()),
     }
 }
 
+#[cfg(feature = "python")]
+
 #[pyfunction]
 pub fn py_create_label_filter(filter_type: &str, label: String) -> PyResult<String> {
     match create_label_filter(filter_type, label) {
         Ok(filter) => Ok(filter.name().to_string()),
-        Err(e) => Err(pyo3::exceptions::PyValueError::new_err(format!("{:?}", e))),
+        Err(e) => Err(e.into()),
     }
 }
 
+#[cfg(feature = "python")]
+
 #[pyfunction]
 pub fn py_create_label_in_filter(filter_type: &str, labels: Vec<String>) -> PyResult<String> {
     match create_label_in_filter(filter_type, labels) {
         Ok(filter) => Ok(filter.name().to_string()),
-        Err(e) => Err(pyo3::exceptions::PyValueError::new_err(format!("{:?}", e))),
+        Err(e) => Err(e.into()),
     }
 }
\ No newline at end of file
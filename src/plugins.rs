@@ -1,18 +1,85 @@
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
-use crate::errors::MetricQueryResult;
-use crate::models::Metric;
+use crate::accuracy::Accuracy;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::plugin_impls::{MissingValuePolicy, Threshold};
+#[cfg(feature = "python")]
+use crate::sandbox::{call_plugin, describe_exception};
+#[cfg(feature = "python")]
+use crate::settings::Settings;
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, PoisonError, RwLock};
+
+/// Clones a filter behind a `Box<dyn FilterPlugin>`. Implemented for every `Clone`
+/// filter, so plugins only need `#[derive(Clone)]`; the aggregation and time grouping
+/// traits below work the same way.
+pub trait FilterPluginClone {
+    fn clone_box(&self) -> Box<dyn FilterPlugin>;
+}
+
+impl<T: FilterPlugin + Clone + 'static> FilterPluginClone for T {
+    fn clone_box(&self) -> Box<dyn FilterPlugin> {
+        Box::new(self.clone())
+    }
+}
 
 /// Trait for filter plugins
-pub trait FilterPlugin: Send + Sync {
+pub trait FilterPlugin: FilterPluginClone + Send + Sync {
     /// Get the name of the filter plugin
     fn name(&self) -> &str;
     
     /// Apply the filter to a metric
     fn apply(&self, metric: &Metric) -> bool; // Update parameter type
     
-    /// Clone the plugin (required for trait objects)
-    fn clone_box(&self) -> Box<dyn FilterPlugin>;
+    /// Evaluate the filter over a whole batch, replacing the contents of `out` with one
+    /// keep flag per metric. The default calls `apply` per metric; built-in value
+    /// filters override it with a tight loop that avoids a virtual call per metric.
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.apply(metric)));
+    }
+    
+    /// Apply the filter to a bare value/timestamp pair (used by columnar execution).
+    /// The default wraps the pair in an unlabeled metric; value-only filters override it.
+    fn apply_parts(&self, value: i64, timestamp: i64) -> bool {
+        self.apply(&Metric::new(value, timestamp, None))
+    }
+    
+    /// Apply the filter to a float metric.
+    ///
+    /// Rounding would change comparisons (0.4 > 0 would fail), so the default only
+    /// serves filters that don't read values and reports the others as unsupported
+    /// on floats; value filters override it.
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        if self.reads_value() {
+            fail_filter(MetricQueryError::InvalidFilter {
+                reason: format!("filter '{}' does not support float metrics", self.name()),
+            });
+            return false;
+        }
+        self.apply(&metric.to_metric())
+    }
+    
+    /// `apply_batch` for float metrics. The default calls `apply_float` per metric.
+    fn apply_float_batch(&self, metrics: &[FloatMetric], out: &mut Vec<bool>) {
+        out.clear();
+        out.extend(metrics.iter().map(|metric| self.apply_float(metric)));
+    }
+    
+    /// Whether the filter looks at values. Staleness markers have none, so value
+    /// filters keep them without asking; filters on labels, tags or time override
+    /// this to judge markers like samples.
+    fn reads_value(&self) -> bool {
+        true
+    }
+    
+    /// Whether the plugin must run on the calling thread (e.g. it calls into Python),
+    /// which keeps batches from running its pipeline on worker threads
+    fn thread_bound(&self) -> bool {
+        false
+    }
 }
 
 // Enable cloning of BoxedFilterPlugin
@@ -22,16 +89,102 @@ impl Clone for Box<dyn FilterPlugin> {
     }
 }
 
+thread_local! {
+    /// First error a filter hit during the current filter step on this thread
+    static FILTER_ERROR: RefCell<Option<MetricQueryError>> = const { RefCell::new(None) };
+}
+
+/// Report an error from a filter, whose `apply` can only answer yes or no; the filter
+/// step running it fails with the first error reported
+pub fn fail_filter(error: MetricQueryError) {
+    FILTER_ERROR.with(|slot| {
+        slot.borrow_mut().get_or_insert(error);
+    });
+}
+
+/// Whether a filter has reported an error that its step hasn't collected yet
+pub fn filter_failed() -> bool {
+    FILTER_ERROR.with(|slot| slot.borrow().is_some())
+}
+
+/// Collect the error reported by a filter on this thread, if any
+pub fn take_filter_error() -> Option<MetricQueryError> {
+    FILTER_ERROR.with(|slot| slot.borrow_mut().take())
+}
+
+/// Clones an aggregation behind a `Box<dyn AggregationPlugin>`
+pub trait AggregationPluginClone {
+    fn clone_box(&self) -> Box<dyn AggregationPlugin>;
+}
+
+impl<T: AggregationPlugin + Clone + 'static> AggregationPluginClone for T {
+    fn clone_box(&self) -> Box<dyn AggregationPlugin> {
+        Box::new(self.clone())
+    }
+}
+
 /// Trait for aggregation plugins
-pub trait AggregationPlugin: Send + Sync {
+pub trait AggregationPlugin: AggregationPluginClone + Send + Sync {
     /// Get the name of the aggregation plugin
     fn name(&self) -> &str;
     
     /// Apply the aggregation to a collection of metrics
     fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64>;
     
-    /// Clone the plugin (required for trait objects)
-    fn clone_box(&self) -> Box<dyn AggregationPlugin>;
+    /// Apply the aggregation to bare values (used by grouping and columnar execution).
+    /// The default wraps the values in unlabeled metrics; built-in plugins override it.
+    fn apply_values(&self, values: &[i64]) -> MetricQueryResult<i64> {
+        let metrics: Vec<Metric> = values
+            .iter()
+            .map(|&value| Metric::new(value, 0, None))
+            .collect();
+        self.apply(&metrics)
+    }
+    
+    /// Apply the aggregation to float values.
+    /// The default rounds the values and uses the integer aggregation; built-in plugins override it.
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        let rounded: Vec<i64> = values.iter().map(|v| v.round() as i64).collect();
+        self.apply_values(&rounded).map(|v| v as f64)
+    }
+    
+    /// Whether the plugin must run on the calling thread (e.g. it calls into Python),
+    /// which keeps grouping from fanning it out to worker threads
+    fn thread_bound(&self) -> bool {
+        false
+    }
+    
+    /// Whether the result is in the inputs' units, so a fixed-point result keeps their
+    /// scale. The default is a plain integer, which is right for counts and anything
+    /// else that doesn't carry the inputs' units; sums, means and extremes override it.
+    fn keeps_scale(&self) -> bool {
+        false
+    }
+    
+    /// Whether the aggregation is computed exactly or from a sketch; `None` for
+    /// aggregations that are always exact
+    fn accuracy(&self) -> Option<Accuracy> {
+        None
+    }
+    
+    /// A copy of the aggregation running with `accuracy`, or `None` when it has no
+    /// approximate form
+    fn with_accuracy(&self, _accuracy: Accuracy) -> Option<Box<dyn AggregationPlugin>> {
+        None
+    }
+    
+    /// The missing-value policy the aggregation was given, which takes precedence over
+    /// the pipeline's; `None` for aggregations that follow `Settings::missing`
+    fn missing_policy(&self) -> Option<MissingValuePolicy> {
+        None
+    }
+    
+    /// How the GPU backend can compute this aggregation from group partials, if at all;
+    /// `None` keeps grouping on the CPU
+    #[cfg(feature = "gpu")]
+    fn gpu_reduction(&self) -> Option<crate::gpu::Reduction> {
+        None
+    }
 }
 
 // Enable cloning of BoxedAggregationPlugin
@@ -41,16 +194,63 @@ impl Clone for Box<dyn AggregationPlugin> {
     }
 }
 
+/// What a time grouping sees of a point, the same whichever execution path (metrics,
+/// floats or columns) groups it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GroupPoint<'a> {
+    pub timestamp: i64,
+    /// `None` in columnar execution, which carries no labels
+    pub label: Option<&'a str>,
+    /// The value as a float; `None` when missing, for staleness markers, and for
+    /// histograms and sketches
+    pub value: Option<f64>,
+}
+
+impl<'a> From<&'a Metric> for GroupPoint<'a> {
+    fn from(metric: &'a Metric) -> Self {
+        let value = (!metric.stale).then(|| metric.as_f64());
+        Self { timestamp: metric.timestamp, label: metric.label.as_deref(), value }
+    }
+}
+
+impl<'a> From<&'a FloatMetric> for GroupPoint<'a> {
+    fn from(metric: &'a FloatMetric) -> Self {
+        let value = (!metric.is_missing()).then_some(metric.value);
+        Self { timestamp: metric.timestamp, label: metric.label.as_deref(), value }
+    }
+}
+
+/// Clones a time grouping behind a `Box<dyn TimeGroupingPlugin>`
+pub trait TimeGroupingPluginClone {
+    fn clone_box(&self) -> Box<dyn TimeGroupingPlugin>;
+}
+
+impl<T: TimeGroupingPlugin + Clone + 'static> TimeGroupingPluginClone for T {
+    fn clone_box(&self) -> Box<dyn TimeGroupingPlugin> {
+        Box::new(self.clone())
+    }
+}
+
 /// Trait for time grouping plugins
-pub trait TimeGroupingPlugin: Send + Sync {
+pub trait TimeGroupingPlugin: TimeGroupingPluginClone + Send + Sync {
     /// Get the name of the time grouping plugin
     fn name(&self) -> &str;
     
     /// Get the timestamp for the group that a metric belongs to
     fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64>;
     
-    /// Clone the plugin (required for trait objects)
-    fn clone_box(&self) -> Box<dyn TimeGroupingPlugin>;
+    /// Get the group timestamp for a point, so the grouping can depend on its label or
+    /// value (e.g. a per-tenant calendar). Every execution path calls this one hook;
+    /// it defaults to grouping by timestamp alone.
+    fn get_point_group_timestamp(&self, point: &GroupPoint<'_>) -> MetricQueryResult<i64> {
+        self.get_group_timestamp(point.timestamp)
+    }
+    
+    /// Whether the plugin must run on the calling thread (e.g. it calls into Python),
+    /// which keeps grouping from fanning it out to worker threads
+    fn thread_bound(&self) -> bool {
+        false
+    }
 }
 
 // Enable cloning of BoxedTimeGroupingPlugin
@@ -61,37 +261,36 @@ impl Clone for Box<dyn TimeGroupingPlugin> {
 }
 
 // Python-friendly wrappers for the plugin registry
-#[pyclass]
+#[cfg_attr(feature = "python", pyclass(get_all))]
 #[derive(Clone)]
 pub struct PyFilterPluginRef {
-    #[pyo3(get)]
     pub name: String,
 }
 
-#[pyclass]
+#[cfg_attr(feature = "python", pyclass(get_all))]
 #[derive(Clone)]
 pub struct PyAggregationPluginRef {
-    #[pyo3(get)]
     pub name: String,
 }
 
-#[pyclass]
+#[cfg_attr(feature = "python", pyclass(get_all))]
 #[derive(Clone)]
 pub struct PyTimeGroupingPluginRef {
-    #[pyo3(get)]
     pub name: String,
 }
 
-// Global registry
-// We're using thread-local storage to maintain a reference to the registry
-thread_local! {
-    static GLOBAL_REGISTRY: std::cell::RefCell<PluginRegistry> = std::cell::RefCell::new(PluginRegistry::new());
-}
+// Global registry, shared by every thread so pipelines on worker threads see the
+// plugins registered from Python
+static GLOBAL_REGISTRY: LazyLock<RwLock<PluginRegistry>> = LazyLock::new(|| RwLock::new(PluginRegistry::new()));
+
+/// Builds a filter from the value it compares against, e.g. `gt` with 25 or 2.5
+pub type FilterFactory = Arc<dyn Fn(Threshold) -> MetricQueryResult<Box<dyn FilterPlugin>> + Send + Sync>;
 
 // Registry for transformation plugins
 #[derive(Default)]
 pub struct PluginRegistry {
     filters: HashMap<String, Box<dyn FilterPlugin>>,
+    filter_factories: HashMap<String, FilterFactory>,
     aggregations: HashMap<String, Box<dyn AggregationPlugin>>,
     time_groupings: HashMap<String, Box<dyn TimeGroupingPlugin>>,
 }
@@ -101,6 +300,7 @@ impl PluginRegistry {
     pub fn new() -> Self {
         Self {
             filters: HashMap::new(),
+            filter_factories: HashMap::new(),
             aggregations: HashMap::new(),
             time_groupings: HashMap::new(),
         }
@@ -108,32 +308,64 @@ impl PluginRegistry {
     
     /// Register a new filter plugin
     pub fn register_filter(&mut self, filter: Box<dyn FilterPlugin>) {
+        log::debug!("registered filter plugin '{}'", filter.name());
         self.filters.insert(filter.name().to_string(), filter);
     }
     
+    /// Register a factory building parameterized filters named `name`; `create_filter`
+    /// prefers it over a plain filter of the same name
+    pub fn register_filter_factory(&mut self, name: impl Into<String>, factory: FilterFactory) {
+        let name = name.into();
+        log::debug!("registered filter factory '{}'", name);
+        self.filter_factories.insert(name, factory);
+    }
+    
+    /// Instantiate the filter `name` comparing against `value`. Filters with a factory
+    /// need a value; plain registered filters take none, and passing one is an error
+    /// rather than silently ignored.
+    pub fn create_filter(&self, name: &str, value: Option<Threshold>) -> MetricQueryResult<Box<dyn FilterPlugin>> {
+        if let Some(factory) = self.filter_factories.get(name) {
+            return factory(factory_value(name, value)?);
+        }
+        match (self.filters.get(name), value) {
+            (Some(filter), None) => Ok(filter.clone_box()),
+            (Some(_), Some(_)) => Err(MetricQueryError::InvalidFilter {
+                reason: format!("Filter '{}' takes no value", name),
+            }),
+            (None, _) => Err(MetricQueryError::InvalidFilter { reason: format!("Unknown filter type: {}", name) }),
+        }
+    }
+    
+    /// The factory registered as `name`, if any
+    pub fn get_filter_factory(&self, name: &str) -> Option<FilterFactory> {
+        self.filter_factories.get(name).cloned()
+    }
+    
     /// Register a new aggregation plugin
     pub fn register_aggregation(&mut self, aggregation: Box<dyn AggregationPlugin>) {
+        log::debug!("registered aggregation plugin '{}'", aggregation.name());
         self.aggregations.insert(aggregation.name().to_string(), aggregation);
     }
     
     /// Register a new time grouping plugin
     pub fn register_time_grouping(&mut self, time_grouping: Box<dyn TimeGroupingPlugin>) {
+        log::debug!("registered time grouping plugin '{}'", time_grouping.name());
         self.time_groupings.insert(time_grouping.name().to_string(), time_grouping);
     }
     
     /// Get a filter plugin by name
-    pub fn get_filter(&self, name: &str) -> Option<&Box<dyn FilterPlugin>> {
-        self.filters.get(name)
+    pub fn get_filter(&self, name: &str) -> Option<&dyn FilterPlugin> {
+        self.filters.get(name).map(|p| p.as_ref())
     }
     
     /// Get an aggregation plugin by name
-    pub fn get_aggregation(&self, name: &str) -> Option<&Box<dyn AggregationPlugin>> {
-        self.aggregations.get(name)
+    pub fn get_aggregation(&self, name: &str) -> Option<&dyn AggregationPlugin> {
+        self.aggregations.get(name).map(|p| p.as_ref())
     }
     
     /// Get a time grouping plugin by name
-    pub fn get_time_grouping(&self, name: &str) -> Option<&Box<dyn TimeGroupingPlugin>> {
-        self.time_groupings.get(name)
+    pub fn get_time_grouping(&self, name: &str) -> Option<&dyn TimeGroupingPlugin> {
+        self.time_groupings.get(name).map(|p| p.as_ref())
     }
     
     /// Get list of available filter names
@@ -141,6 +373,11 @@ impl PluginRegistry {
         self.filters.keys().cloned().collect()
     }
     
+    /// Whether `create_filter` knows `name`, as a factory or a plain filter
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filter_factories.contains_key(name) || self.filters.contains_key(name)
+    }
+    
     /// Get list of available aggregation names
     pub fn get_aggregation_names(&self) -> Vec<String> {
         self.aggregations.keys().cloned().collect()
@@ -167,15 +404,21 @@ impl PluginRegistry {
     }
 }
 
+fn factory_value(name: &str, value: Option<Threshold>) -> MetricQueryResult<Threshold> {
+    value.ok_or_else(|| MetricQueryError::InvalidFilter {
+        reason: format!("Filter '{}' needs a value to compare against", name),
+    })
+}
+
 /// Helper function to access the global registry
+///
+/// The registry stays locked while `f` runs, so `f` must not call into plugins that
+/// may use the registry themselves; clone what it needs out instead.
 pub fn with_registry<F, R>(f: F) -> R
 where
     F: FnOnce(&PluginRegistry) -> R,
 {
-    GLOBAL_REGISTRY.with(|registry| {
-        let registry = registry.borrow();
-        f(&registry)
-    })
+    f(&GLOBAL_REGISTRY.read().unwrap_or_else(PoisonError::into_inner))
 }
 
 /// Helper function to mutate the global registry
@@ -183,57 +426,392 @@ pub fn with_registry_mut<F, R>(f: F) -> R
 where
     F: FnOnce(&mut PluginRegistry) -> R,
 {
-    GLOBAL_REGISTRY.with(|registry| {
-        let mut registry = registry.borrow_mut();
-        f(&mut registry)
-    })
+    f(&mut GLOBAL_REGISTRY.write().unwrap_or_else(PoisonError::into_inner))
+}
+
+/// `PluginRegistry::create_filter` on the global registry, calling a filter factory
+/// only once the registry is unlocked, since Python factories may register plugins
+pub fn create_registered_filter(name: &str, value: Option<Threshold>) -> MetricQueryResult<Box<dyn FilterPlugin>> {
+    match with_registry(|registry| registry.get_filter_factory(name)) {
+        Some(factory) => factory(factory_value(name, value)?),
+        None => with_registry(|registry| registry.create_filter(name, value)),
+    }
 }
 
-/// Python wrapper for the plugin registry
-#[pyclass]
-pub struct TransformationRegistry {
-    #[pyo3(get)]
-    pub filters: Vec<PyFilterPluginRef>,
-    #[pyo3(get)]
-    pub aggregations: Vec<PyAggregationPluginRef>,
-    #[pyo3(get)]
-    pub time_groupings: Vec<PyTimeGroupingPluginRef>,
+/// Metrics a Python filter evaluates per GIL acquisition when no plugin timeout is set
+#[cfg(feature = "python")]
+const PY_FILTER_BATCH: usize = 1024;
+
+/// Filter plugin backed by a Python callable that takes a `Metric` (or a `FloatMetric` in
+/// float pipelines) and returns a truthy value.
+///
+/// By default a callable that raises is logged and treated as not matching; with
+/// `raise_errors` the exception fails the step instead. Timeouts and trusted-only
+/// settings always fail the step, and once a call has failed the rest of the batch
+/// isn't evaluated.
+///
+/// Batches take the GIL once per `PY_FILTER_BATCH` metrics rather than once per
+/// metric. With a `plugin_timeout` each metric is still a separately timed call.
+#[cfg(feature = "python")]
+#[derive(Clone)]
+pub struct PyCallableFilter {
+    name: String,
+    callable: Arc<Py<PyAny>>,
+    raise_errors: bool,
 }
 
+#[cfg(feature = "python")]
+impl PyCallableFilter {
+    pub fn new(name: impl Into<String>, callable: Py<PyAny>) -> Self {
+        Self { name: name.into(), callable: Arc::new(callable), raise_errors: false }
+    }
+
+    /// Fail the step when the callable raises, instead of skipping the metric
+    pub fn raise_errors(mut self, raise_errors: bool) -> Self {
+        self.raise_errors = raise_errors;
+        self
+    }
+
+    /// Whether the callable keeps `metric`, taking the GIL for this one call
+    fn call<T>(&self, metric: T) -> bool
+    where
+        T: for<'py> IntoPyObject<'py> + Send + 'static,
+    {
+        if filter_failed() {
+            return false;
+        }
+        let callable = Arc::clone(&self.callable);
+        match call_plugin(&self.name, move |py| callable.call1(py, (metric,))?.is_truthy(py)) {
+            Ok(keep) => keep,
+            Err(MetricQueryError::PluginFailed { reason, .. }) if !self.raise_errors => {
+                log::warn!("filter '{}' raised {}; treating the metric as not matching", self.name, reason);
+                false
+            }
+            Err(error) => {
+                fail_filter(error);
+                false
+            }
+        }
+    }
+
+    /// Keep flags for `metrics`, taking the GIL once per `PY_FILTER_BATCH` of them
+    fn call_batch<T>(&self, metrics: &[T], out: &mut Vec<bool>)
+    where
+        T: for<'py> IntoPyObject<'py> + Clone + Send + 'static,
+    {
+        out.clear();
+        if Settings::current().plugin_timeout.is_some() {
+            out.extend(metrics.iter().map(|metric| self.call(metric.clone())));
+            return;
+        }
+        for chunk in metrics.chunks(PY_FILTER_BATCH) {
+            if filter_failed() {
+                break;
+            }
+            let (callable, chunk) = (Arc::clone(&self.callable), chunk.to_vec());
+            let (name, raise_errors) = (self.name.clone(), self.raise_errors);
+            let evaluated = call_plugin(&self.name, move |py| {
+                let mut keep = Vec::with_capacity(chunk.len());
+                for metric in chunk {
+                    match callable.call1(py, (metric,)).and_then(|result| result.is_truthy(py)) {
+                        Ok(matches) => keep.push(matches),
+                        Err(error) if !raise_errors => {
+                            let reason = describe_exception(py, &error);
+                            log::warn!("filter '{}' raised {}; treating the metric as not matching", name, reason);
+                            keep.push(false);
+                        }
+                        Err(error) => return Err(error),
+                    }
+                }
+                Ok(keep)
+            });
+            match evaluated {
+                Ok(keep) => out.extend(keep),
+                Err(error) => fail_filter(error),
+            }
+        }
+        out.resize(metrics.len(), false);
+    }
+}
+
+#[cfg(feature = "python")]
+impl FilterPlugin for PyCallableFilter {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        self.call(metric.clone())
+    }
+
+    fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+        self.call_batch(metrics, out);
+    }
+
+    fn apply_float(&self, metric: &FloatMetric) -> bool {
+        self.call(metric.clone())
+    }
+
+    fn apply_float_batch(&self, metrics: &[FloatMetric], out: &mut Vec<bool>) {
+        self.call_batch(metrics, out);
+    }
+
+    fn thread_bound(&self) -> bool {
+        true
+    }
+}
+
+/// Aggregation plugin backed by a Python callable that takes a list of values and returns one
+#[cfg(feature = "python")]
+#[derive(Clone)]
+pub struct PyCallableAggregation {
+    name: String,
+    callable: Arc<Py<PyAny>>,
+}
+
+#[cfg(feature = "python")]
+impl PyCallableAggregation {
+    pub fn new(name: impl Into<String>, callable: Py<PyAny>) -> Self {
+        Self { name: name.into(), callable: Arc::new(callable) }
+    }
+
+    fn call<T>(&self, values: Vec<T>) -> MetricQueryResult<T>
+    where
+        T: for<'py> IntoPyObject<'py> + for<'py> FromPyObject<'py> + Send + 'static,
+    {
+        let callable = Arc::clone(&self.callable);
+        call_plugin(&self.name, move |py| callable.call1(py, (values,))?.extract(py))
+    }
+}
+
+#[cfg(feature = "python")]
+impl AggregationPlugin for PyCallableAggregation {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<i64> {
+        self.call(metrics.iter().map(|m| m.value).collect())
+    }
+
+    fn apply_float_values(&self, values: &[f64]) -> MetricQueryResult<f64> {
+        self.call(values.to_vec())
+    }
+
+    fn thread_bound(&self) -> bool {
+        true
+    }
+}
+
+/// Time grouping plugin backed by a Python callable that maps a timestamp to its group's timestamp
+///
+/// The callable sees raw UTC timestamps; the `Settings` timezone is up to it to apply.
+#[cfg(feature = "python")]
+#[derive(Clone)]
+pub struct PyCallableTimeGrouping {
+    name: String,
+    callable: Arc<Py<PyAny>>,
+}
+
+#[cfg(feature = "python")]
+impl PyCallableTimeGrouping {
+    pub fn new(name: impl Into<String>, callable: Py<PyAny>) -> Self {
+        Self { name: name.into(), callable: Arc::new(callable) }
+    }
+}
+
+#[cfg(feature = "python")]
+impl TimeGroupingPlugin for PyCallableTimeGrouping {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64> {
+        let callable = Arc::clone(&self.callable);
+        call_plugin(&self.name, move |py| callable.call1(py, (timestamp,))?.extract(py))
+    }
+
+    fn thread_bound(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(feature = "python")]
+pub(crate) fn require_callable(callable: &Bound<'_, PyAny>) -> PyResult<Py<PyAny>> {
+    if callable.is_callable() {
+        Ok(callable.clone().unbind())
+    } else {
+        Err(pyo3::exceptions::PyTypeError::new_err("plugin must be a callable"))
+    }
+}
+
+/// Whether a Python filter's `on_error` ("skip" or "raise") asks for exceptions to
+/// fail the step
+#[cfg(feature = "python")]
+pub(crate) fn raise_on_error(on_error: &str) -> PyResult<bool> {
+    match on_error {
+        "skip" => Ok(false),
+        "raise" => Ok(true),
+        _ => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "Unknown on_error: {}. Expected 'skip' or 'raise'",
+            on_error
+        ))),
+    }
+}
+
+/// A filter instantiated with its parameters, as returned by
+/// `TransformationRegistry.create_filter`; `MetricPipeline.filter` takes it in place of
+/// a filter name
+#[cfg_attr(feature = "python", pyclass(frozen))]
+#[derive(Clone)]
+pub struct BoundFilter {
+    pub filter: Box<dyn FilterPlugin>,
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl BoundFilter {
+    #[getter]
+    fn name(&self) -> &str {
+        self.filter.name()
+    }
+
+    /// Whether the filter keeps `metric`
+    fn __call__(&self, metric: crate::models::metric::MetricInput) -> PyResult<bool> {
+        let keep = self.filter.apply(&metric.0);
+        match take_filter_error() {
+            Some(error) => Err(error.into()),
+            None => Ok(keep),
+        }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("BoundFilter('{}')", self.filter.name())
+    }
+}
+
+/// How `MetricPipeline.filter` is told which filter to run: a registered filter's name,
+/// instantiated with the value passed alongside, or an already instantiated filter
+#[cfg(feature = "python")]
+#[derive(FromPyObject)]
+pub enum FilterArg {
+    Name(String),
+    Bound(BoundFilter),
+}
+
+#[cfg(feature = "python")]
+impl From<&str> for FilterArg {
+    fn from(name: &str) -> Self {
+        Self::Name(name.to_string())
+    }
+}
+
+/// Python view of the plugin registry.
+///
+/// Reads go straight to the registry, so plugins registered anywhere are visible
+/// immediately, and plugins registered here are usable by name in pipelines.
+#[cfg_attr(feature = "python", pyclass)]
+pub struct TransformationRegistry;
+
+#[cfg(feature = "python")]
 #[pymethods]
 impl TransformationRegistry {
     #[new]
     pub fn new(_py: Python) -> PyResult<Self> {
-        Ok(Self {
-            filters: Vec::new(),
-            aggregations: Vec::new(),
-            time_groupings: Vec::new(),
-        })
+        Ok(Self)
     }
     
-    /// Update the references to match the current plugins
+    /// Kept for compatibility; the view is always current
     pub fn refresh(&mut self, _py: Python) -> PyResult<()> {
-        with_registry(|registry| {
-            self.filters = registry.get_py_filters();
-            self.aggregations = registry.get_py_aggregations();
-            self.time_groupings = registry.get_py_time_groupings();
-        });
-        
         Ok(())
     }
     
+    /// References to the registered filters
+    #[getter]
+    pub fn filters(&self) -> Vec<PyFilterPluginRef> {
+        with_registry(|registry| registry.get_py_filters())
+    }
+    
+    /// References to the registered aggregations
+    #[getter]
+    pub fn aggregations(&self) -> Vec<PyAggregationPluginRef> {
+        with_registry(|registry| registry.get_py_aggregations())
+    }
+    
+    /// References to the registered time groupings
+    #[getter]
+    pub fn time_groupings(&self) -> Vec<PyTimeGroupingPluginRef> {
+        with_registry(|registry| registry.get_py_time_groupings())
+    }
+    
     /// Check if a filter exists
     pub fn has_filter(&self, name: &str) -> bool {
-        self.filters.iter().any(|f| f.name == name)
+        with_registry(|registry| registry.has_filter(name))
+    }
+    
+    /// Instantiate a registered filter, e.g. `create_filter("gt", 25)`, for
+    /// `MetricPipeline.filter`. Filters built by a factory need `value`, an int or a
+    /// float; other filters take none.
+    #[pyo3(signature = (name, value=None))]
+    pub fn create_filter(&self, name: &str, value: Option<Threshold>) -> PyResult<BoundFilter> {
+        Ok(BoundFilter { filter: create_registered_filter(name, value)? })
+    }
+    
+    /// Register a parameterized filter: `factory(value)` returns a predicate that takes
+    /// a metric and returns whether to keep it. Replaces any factory with the same name.
+    pub fn register_filter_factory(&self, name: String, factory: &Bound<'_, PyAny>) -> PyResult<()> {
+        let factory = Arc::new(require_callable(factory)?);
+        let plugin = name.clone();
+        let build: FilterFactory = Arc::new(move |value| {
+            let factory = Arc::clone(&factory);
+            let predicate = call_plugin(&plugin, move |py| {
+                let predicate = match value {
+                    Threshold::Int(value) => factory.call1(py, (value,))?,
+                    Threshold::Float(value) => factory.call1(py, (value,))?,
+                };
+                require_callable(predicate.bind(py))
+            })?;
+            Ok(Box::new(PyCallableFilter::new(plugin.clone(), predicate)) as Box<dyn FilterPlugin>)
+        });
+        with_registry_mut(|registry| registry.register_filter_factory(name, build));
+        Ok(())
     }
     
     /// Check if an aggregation exists
     pub fn has_aggregation(&self, name: &str) -> bool {
-        self.aggregations.iter().any(|a| a.name == name)
+        with_registry(|registry| registry.get_aggregation(name).is_some())
     }
     
     /// Check if a time grouping exists
     pub fn has_time_grouping(&self, name: &str) -> bool {
-        self.time_groupings.iter().any(|t| t.name == name)
+        with_registry(|registry| registry.get_time_grouping(name).is_some())
+    }
+    
+    /// Register a filter: `predicate(metric)` returns whether to keep the metric.
+    /// Replaces any filter with the same name.
+    ///
+    /// `on_error` decides what an exception from the predicate does: "skip" logs it and
+    /// drops the metric, "raise" fails the pipeline step.
+    #[pyo3(signature = (name, predicate, on_error="skip"))]
+    pub fn register_filter(&self, name: String, predicate: &Bound<'_, PyAny>, on_error: &str) -> PyResult<()> {
+        let filter = PyCallableFilter::new(name, require_callable(predicate)?).raise_errors(raise_on_error(on_error)?);
+        with_registry_mut(|registry| registry.register_filter(Box::new(filter)));
+        Ok(())
+    }
+    
+    /// Register an aggregation: `function(values)` reduces a list of values to one.
+    /// Replaces any aggregation with the same name.
+    pub fn register_aggregation(&self, name: String, function: &Bound<'_, PyAny>) -> PyResult<()> {
+        let aggregation = PyCallableAggregation::new(name, require_callable(function)?);
+        with_registry_mut(|registry| registry.register_aggregation(Box::new(aggregation)));
+        Ok(())
+    }
+    
+    /// Register a time grouping: `function(timestamp)` returns the group's timestamp.
+    /// Replaces any time grouping with the same name.
+    pub fn register_time_grouping(&self, name: String, function: &Bound<'_, PyAny>) -> PyResult<()> {
+        let grouping = PyCallableTimeGrouping::new(name, require_callable(function)?);
+        with_registry_mut(|registry| registry.register_time_grouping(Box::new(grouping)));
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,342 @@
+//! Python bindings: the `metric_query_library` extension module and its legacy API
+
+use crate::models::metric::{Metric, FrozenMetric, LabeledMetric, FloatMetric};
+use crate::models::{Exemplar, HistogramMetric, MetricSeries, SketchMetric};
+use crate::plugins::{BoundFilter, TransformationRegistry};
+use crate::transformations::MetricPipeline;
+use crate::compiled::CompiledPipeline;
+use crate::batch::BatchExecutor;
+use crate::streaming::{SeriesChange, SeriesId, StreamingProcessor, WindowUpdate};
+use crate::cache::ResultCache;
+use crate::settings::{get_settings, set_settings, Settings};
+use crate::models::dataset::{MetricDataset, PipelineInput};
+use crate::plugin_impls::{
+    create_filter, init_registry,
+    py_create_filter, py_create_aggregation, py_create_time_grouping,
+    py_create_label_filter, py_create_label_in_filter
+};
+use crate::analysis::{py_correlate, py_describe, py_rolling_correlation, MetricSummary, ResultSummary};
+use crate::slo::{py_burn_rate, py_burn_rate_alerts, BurnRateAlert, BurnRateRule};
+use crate::compare::{py_compare_results, ResultDiff, ValueMismatch};
+use crate::golden::{py_assert_golden, py_assert_pipeline_result, py_record_golden};
+use crate::logging::py_set_log_level;
+use crate::stats::{RunStats, StepStats};
+#[cfg(feature = "tracing")]
+use crate::stats::py_init_tracing;
+use crate::time_range::TimeRange;
+use crate::validation::{
+    check_timestamps, py_validate_metrics, MetricBuilder, StreamIssue, StreamReport, TimestampIssue, TimestampReport,
+};
+use crate::warnings::MetricQueryWarning;
+use pyo3::prelude::*;
+
+// Legacy filter enum for backward compatibility
+#[pyclass]
+#[derive(Debug, Clone)]
+pub enum Filter {
+    GreaterThan { value: i64 },
+    LessThan { value: i64 },
+    GreaterThanOrEqual { value: i64 },
+    LessThanOrEqual { value: i64 },
+    Equal { value: i64 },
+}
+
+#[pymethods]
+impl Filter {
+    #[new]
+    pub fn new(filter_type: &str, value: i64) -> PyResult<Self> {
+        match filter_type {
+            "gt" => Ok(Filter::GreaterThan { value }),
+            "lt" => Ok(Filter::LessThan { value }),
+            "ge" => Ok(Filter::GreaterThanOrEqual { value }),
+            "le" => Ok(Filter::LessThanOrEqual { value }),
+            "eq" => Ok(Filter::Equal { value }),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(
+                "Invalid filter type. Expected one of: gt, lt, ge, le, eq",
+            )),
+        }
+    }
+}
+
+// Legacy aggregation enum for backward compatibility
+#[pyclass]
+#[derive(Debug, Clone)]
+pub enum Aggregation {
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+#[pymethods]
+impl Aggregation {
+    #[new]
+    pub fn new(agg_type: &str) -> PyResult<Self> {
+        match agg_type {
+            "sum" => Ok(Aggregation::Sum),
+            "avg" => Ok(Aggregation::Avg),
+            "min" => Ok(Aggregation::Min),
+            "max" => Ok(Aggregation::Max),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(
+                "Invalid aggregation type. Expected one of: sum, avg, min, max",
+            )),
+        }
+    }
+}
+
+// Legacy time grouping enum for backward compatibility
+#[pyclass]
+#[derive(Debug, Clone)]
+pub enum TimeGrouping {
+    Hour,
+    Minute,
+    Day,
+}
+
+#[pymethods]
+impl TimeGrouping {
+    #[new]
+    pub fn new(time_group_type: &str) -> PyResult<Self> {
+        match time_group_type {
+            "hour" => Ok(TimeGrouping::Hour),
+            "minute" => Ok(TimeGrouping::Minute),
+            "day" => Ok(TimeGrouping::Day),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(
+                "Invalid time grouping. Expected one of: hour, minute, day",
+            )),
+        }
+    }
+}
+
+// Legacy transformation struct for backward compatibility
+#[pyclass]
+#[derive(Debug, Clone)]
+pub struct Transformation {
+    #[pyo3(get, set)]
+    pub filter: Option<Filter>,
+    #[pyo3(get, set)]
+    pub aggregation: Option<Aggregation>,
+    #[pyo3(get, set)]
+    pub time_grouping: Option<TimeGrouping>,
+}
+
+impl Default for Transformation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[pymethods]
+impl Transformation {
+    #[new]
+    pub fn new() -> Self {
+        Self {
+            filter: None,
+            aggregation: None,
+            time_grouping: None,
+        }
+    }
+}
+
+/// Convert legacy Filter to a filter type string
+fn filter_to_string(filter: &Filter) -> &'static str {
+    match filter {
+        Filter::GreaterThan { .. } => "gt",
+        Filter::LessThan { .. } => "lt",
+        Filter::GreaterThanOrEqual { .. } => "ge",
+        Filter::LessThanOrEqual { .. } => "le",
+        Filter::Equal { .. } => "eq",
+    }
+}
+
+/// Extract value from legacy Filter
+fn filter_to_value(filter: &Filter) -> i64 {
+    match filter {
+        Filter::GreaterThan { value } => *value,
+        Filter::LessThan { value } => *value,
+        Filter::GreaterThanOrEqual { value } => *value,
+        Filter::LessThanOrEqual { value } => *value,
+        Filter::Equal { value } => *value,
+    }
+}
+
+/// Convert legacy Aggregation to an aggregation type string
+fn aggregation_to_string(agg: &Aggregation) -> &'static str {
+    match agg {
+        Aggregation::Sum => "sum",
+        Aggregation::Avg => "avg",
+        Aggregation::Min => "min",
+        Aggregation::Max => "max",
+    }
+}
+
+/// Convert legacy TimeGrouping to a time grouping type string
+fn time_grouping_to_string(time_grouping: &TimeGrouping) -> &'static str {
+    match time_grouping {
+        TimeGrouping::Hour => "hour",
+        TimeGrouping::Minute => "minute",
+        TimeGrouping::Day => "day",
+    }
+}
+
+/// Helper function to apply transformations using our new architecture
+fn apply_transformations(py: Python<'_>, metrics: &[Metric], transformations: &[Transformation]) -> PyResult<Vec<Metric>> {
+    // Create a pipeline
+    let mut pipeline = MetricPipeline::new(metrics.to_vec());
+    
+    // Apply each transformation
+    for t in transformations {
+        // Apply filter if present
+        if let Some(filter) = &t.filter {
+            let filter_type = filter_to_string(filter);
+            let value = filter_to_value(filter);
+            pipeline.add_filter(create_filter(filter_type, value)?);
+        }
+        
+        // Check if we have both aggregation and time grouping
+        if let (Some(agg), Some(time_group)) = (&t.aggregation, &t.time_grouping) {
+            let agg_type = aggregation_to_string(agg);
+            let time_group_type = time_grouping_to_string(time_group);
+            pipeline.group_by_time(py, time_group_type, agg_type, None, None, None, None, None, None, None)?;
+        } else if let Some(agg) = &t.aggregation {
+            // Only aggregation
+            let agg_type = aggregation_to_string(agg);
+            pipeline.aggregate(py, agg_type, None, None, None, None, "first", None, None)?;
+        }
+    }
+    
+    // Execute the pipeline
+    pipeline.execute()
+}
+
+/// Transforms a slice of Metrics according to a series of Transformations.
+/// This function is intended to be exposed to other languages via FFI.
+///
+/// # Arguments
+///
+/// * `metrics` - A slice of `Metric` objects.
+/// * `transformations` - A slice of `Transformation` structs, defining the transformations to be applied.
+///
+/// # Returns
+///
+/// A `Vec<Metric>` containing the transformed metrics.
+#[pyfunction]
+pub fn transform(py: Python<'_>, metrics: Vec<Metric>, transformations: Vec<Transformation>) -> PyResult<Vec<Metric>> {
+    apply_transformations(py, &metrics, &transformations)
+}
+
+/// Creates a new metric pipeline with the given metrics.
+/// This is part of the new fluent API.
+///
+/// Pass a `MetricDataset` instead of a list to share one copy of the metrics
+/// between several pipelines. List entries may also be `LabeledMetric`s,
+/// `(value, timestamp)` or `(value, timestamp, label)` tuples, or dicts with
+/// "value", "timestamp" and optionally "label", "unit", "metric_type" and "fields".
+#[pyfunction]
+pub fn create_pipeline(metrics: PipelineInput) -> MetricPipeline {
+    MetricPipeline::from_shared(metrics.into_shared())
+}
+
+/// Creates a new metric pipeline over `LabeledMetric`s, each becoming a `Metric`
+/// with its label so the pipeline can filter and group by it.
+/// `LabeledMetric.from_metric` converts results back.
+#[pyfunction]
+pub fn create_labeled_pipeline(metrics: Vec<LabeledMetric>) -> MetricPipeline {
+    MetricPipeline::new(metrics.into_iter().map(Metric::from).collect())
+}
+
+/// Initializes and returns the transformation registry with built-in plugins
+#[pyfunction]
+pub fn get_registry(py: Python<'_>) -> PyResult<TransformationRegistry> {
+    TransformationRegistry::new(py)
+}
+
+// Use the correct PyO3 module signature for newer versions
+#[pymodule]
+fn metric_query_library(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    // Route internal log records to Python's `logging` before anything logs
+    crate::logging::install();
+    m.add_function(wrap_pyfunction!(py_set_log_level, m)?)?;
+    
+    // Initialize plugin registry
+    init_registry();
+    
+    // Register legacy functions and types for backward compatibility
+    m.add_function(wrap_pyfunction!(transform, m)?)?;
+    m.add_class::<Metric>()?;
+    m.add_class::<LabeledMetric>()?;
+    m.add_class::<FrozenMetric>()?;
+    m.add_class::<FloatMetric>()?;
+    m.add_class::<Exemplar>()?;
+    m.add_class::<HistogramMetric>()?;
+    m.add_class::<SketchMetric>()?;
+    m.add_class::<Filter>()?;
+    m.add_class::<Aggregation>()?;
+    m.add_class::<TimeGrouping>()?;
+    m.add_class::<Transformation>()?;
+    
+    // Register new fluent API components
+    m.add_function(wrap_pyfunction!(create_pipeline, m)?)?;
+    m.add_function(wrap_pyfunction!(create_labeled_pipeline, m)?)?;
+    m.add_function(wrap_pyfunction!(get_registry, m)?)?;
+    m.add_class::<MetricPipeline>()?;
+    m.add_class::<CompiledPipeline>()?;
+    m.add_class::<BatchExecutor>()?;
+    m.add_class::<StreamingProcessor>()?;
+    m.add_class::<WindowUpdate>()?;
+    m.add_class::<SeriesChange>()?;
+    m.add_class::<SeriesId>()?;
+    m.add_class::<ResultCache>()?;
+    m.add_class::<Settings>()?;
+    m.add_function(wrap_pyfunction!(get_settings, m)?)?;
+    m.add_function(wrap_pyfunction!(set_settings, m)?)?;
+    m.add_class::<MetricDataset>()?;
+    m.add_class::<MetricSeries>()?;
+    m.add_class::<TransformationRegistry>()?;
+    m.add_class::<BoundFilter>()?;
+    
+    // Register validation helpers
+    m.add_function(wrap_pyfunction!(check_timestamps, m)?)?;
+    m.add_class::<TimestampReport>()?;
+    m.add_class::<TimestampIssue>()?;
+    m.add_function(wrap_pyfunction!(py_validate_metrics, m)?)?;
+    m.add_class::<StreamReport>()?;
+    m.add_class::<StreamIssue>()?;
+    m.add_class::<MetricBuilder>()?;
+    m.add_class::<TimeRange>()?;
+    m.add_class::<RunStats>()?;
+    m.add_class::<StepStats>()?;
+    #[cfg(feature = "tracing")]
+    m.add_function(wrap_pyfunction!(py_init_tracing, m)?)?;
+    
+    // Register analysis helpers
+    m.add_function(wrap_pyfunction!(py_correlate, m)?)?;
+    m.add_function(wrap_pyfunction!(py_rolling_correlation, m)?)?;
+    m.add_function(wrap_pyfunction!(py_describe, m)?)?;
+    m.add_class::<MetricSummary>()?;
+    m.add_class::<ResultSummary>()?;
+    m.add_function(wrap_pyfunction!(py_compare_results, m)?)?;
+    m.add_class::<ResultDiff>()?;
+    m.add_class::<ValueMismatch>()?;
+    m.add_function(wrap_pyfunction!(py_assert_pipeline_result, m)?)?;
+    m.add_function(wrap_pyfunction!(py_record_golden, m)?)?;
+    m.add_function(wrap_pyfunction!(py_assert_golden, m)?)?;
+    
+    // Register SLO helpers
+    m.add_function(wrap_pyfunction!(py_burn_rate, m)?)?;
+    m.add_function(wrap_pyfunction!(py_burn_rate_alerts, m)?)?;
+    m.add_class::<BurnRateRule>()?;
+    m.add_class::<BurnRateAlert>()?;
+    
+    // Register the warning category used for non-fatal pipeline issues
+    m.add("MetricQueryWarning", m.py().get_type::<MetricQueryWarning>())?;
+    
+    // Register helper functions for plugin creation
+    m.add_function(wrap_pyfunction!(py_create_filter, m)?)?;
+    m.add_function(wrap_pyfunction!(py_create_aggregation, m)?)?;
+    m.add_function(wrap_pyfunction!(py_create_time_grouping, m)?)?;
+    m.add_function(wrap_pyfunction!(py_create_label_filter, m)?)?;
+    m.add_function(wrap_pyfunction!(py_create_label_in_filter, m)?)?;
+    
+    Ok(())
+}
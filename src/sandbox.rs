@@ -0,0 +1,135 @@
+//! Guarded calls into plugins defined in Python.
+//!
+//! Every call to a registered Python filter, aggregation or time grouping, and to
+//! the callables given to `tap`, `assert_that` and stream subscriptions, goes
+//! through `call_plugin`, which applies the `Settings` in effect:
+//!
+//! - with `trusted_only` in either the effective or the process-wide settings, the
+//!   call is refused with `UntrustedPlugin`;
+//! - with a `plugin_timeout`, the callable runs on a helper thread while the caller
+//!   waits without holding the GIL. A call that overruns fails its step with
+//!   `PluginTimeout` straight away, and the abandoned call is sent a `TimeoutError`,
+//!   which it raises at its next Python instruction (a blocking C call such as
+//!   `time.sleep` finishes first). Spawning the helper costs tens of microseconds
+//!   per call, so the limit is opt-in;
+//! - exceptions become `PluginFailed`, naming the exception and the file and line
+//!   that raised it. Pipelines add the failing step's index and name on top.
+
+use pyo3::prelude::*;
+use std::os::raw::c_long;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::settings::Settings;
+
+/// Run `call` as plugin `plugin` under the current settings' sandbox rules
+pub(crate) fn call_plugin<T: Send + 'static>(
+    plugin: &str,
+    call: impl FnOnce(Python<'_>) -> PyResult<T> + Send + 'static,
+) -> MetricQueryResult<T> {
+    let settings = Settings::current();
+    // A pipeline's own settings may add the restriction but never lift the process-wide one
+    if settings.trusted_only || Settings::global().trusted_only {
+        return Err(MetricQueryError::UntrustedPlugin { plugin: plugin.to_string() });
+    }
+    let result = match settings.plugin_timeout {
+        None => Python::with_gil(|py| call(py).map_err(|e| describe_exception(py, &e))),
+        Some(timeout) => call_with_timeout(plugin, timeout, call)?,
+    };
+    result.map_err(|reason| MetricQueryError::PluginFailed { plugin: plugin.to_string(), reason })
+}
+
+/// "ZeroDivisionError: division by zero (at pipeline.py:12)", locating the innermost frame
+pub(crate) fn describe_exception(py: Python<'_>, error: &PyErr) -> String {
+    let location = error.traceback(py).and_then(|traceback| {
+        let mut frame = traceback.into_any();
+        while let Ok(next) = frame.getattr("tb_next") {
+            if next.is_none() {
+                break;
+            }
+            frame = next;
+        }
+        let line: u32 = frame.getattr("tb_lineno").ok()?.extract().ok()?;
+        let code = frame.getattr("tb_frame").ok()?.getattr("f_code").ok()?;
+        let file: String = code.getattr("co_filename").ok()?.extract().ok()?;
+        Some(format!("{}:{}", file, line))
+    });
+    match location {
+        Some(location) => format!("{} (at {})", error, location),
+        None => error.to_string(),
+    }
+}
+
+/// Progress of a call on a helper thread; only read or written while holding the GIL
+#[derive(Default)]
+struct CallState {
+    /// Python thread id of the helper once the call has started
+    thread: Option<c_long>,
+    finished: bool,
+    abandoned: bool,
+}
+
+fn call_with_timeout<T: Send + 'static>(
+    plugin: &str,
+    timeout: Duration,
+    call: impl FnOnce(Python<'_>) -> PyResult<T> + Send + 'static,
+) -> MetricQueryResult<Result<T, String>> {
+    let state = Arc::new(Mutex::new(CallState::default()));
+    let (sender, receiver) = mpsc::sync_channel(1);
+
+    let helper_state = Arc::clone(&state);
+    thread::Builder::new()
+        .name(format!("plugin {}", plugin))
+        .spawn(move || {
+            let outcome = Python::with_gil(|py| {
+                let thread = py.import("threading")?.call_method0("get_ident")?.extract::<u64>()? as c_long;
+                {
+                    let mut state = helper_state.lock().unwrap_or_else(PoisonError::into_inner);
+                    if state.abandoned {
+                        return Ok(None);
+                    }
+                    state.thread = Some(thread);
+                }
+                let result = call(py).map_err(|e| describe_exception(py, &e));
+                helper_state.lock().unwrap_or_else(PoisonError::into_inner).finished = true;
+                Ok::<_, PyErr>(Some(result))
+            });
+            match outcome {
+                Ok(Some(result)) => {
+                    let _ = sender.send(result);
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    let _ = sender.send(Err(e.to_string()));
+                }
+            }
+        })
+        .map_err(|e| MetricQueryError::OperationFailed {
+            operation: plugin.to_string(),
+            reason: format!("failed to start a thread for the plugin call: {}", e),
+        })?;
+
+    let received = Python::with_gil(|py| py.allow_threads(move || receiver.recv_timeout(timeout)));
+    match received {
+        Ok(result) => Ok(result),
+        Err(RecvTimeoutError::Timeout) => {
+            Python::with_gil(|_py| {
+                // Holding the GIL, the helper is either yet to start, inside the call, or done
+                let mut state = state.lock().unwrap_or_else(PoisonError::into_inner);
+                state.abandoned = true;
+                if let (Some(thread), false) = (state.thread, state.finished) {
+                    // SAFETY: the GIL is held, and an id whose thread has exited is ignored
+                    unsafe { pyo3::ffi::PyThreadState_SetAsyncExc(thread, pyo3::ffi::PyExc_TimeoutError) };
+                }
+            });
+            Err(MetricQueryError::PluginTimeout { plugin: plugin.to_string(), timeout })
+        }
+        Err(RecvTimeoutError::Disconnected) => Err(MetricQueryError::PluginFailed {
+            plugin: plugin.to_string(),
+            reason: "the plugin call ended without a result".to_string(),
+        }),
+    }
+}
@@ -0,0 +1,148 @@
+//! Embedded HTTP server, enabled with the `server` feature.
+//!
+//! `POST /query` takes a pipeline spec and inline metrics and returns the result:
+//!
+//! ```json
+//! {"pipeline": {"steps": [{"op": "group_by_time", "grouping": "hour", "aggregation": "avg"}]},
+//!  "metrics": [{"value": 1.5, "timestamp": 0, "label": "cpu"}]}
+//! ```
+//!
+//! A successful response is `{"metrics": [...]}`; failures return a 4xx status with
+//! `{"error": {"code": ..., "message": ..., "step": ...}}`, using the codes of
+//! `MetricQueryError::code`. Metrics run through the float path, so values keep
+//! their fractional part. Metrics are always sent inline; there is no server-side
+//! store to reference. Bodies over `MAX_BODY` bytes are refused with 413, and a
+//! request whose handling panics gets a 500 without taking its worker down.
+
+use std::io::Read;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+use std::thread;
+
+use serde::Deserialize;
+use serde_json::json;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::errors::MetricQueryError;
+use crate::models::FloatMetric;
+use crate::spec::{MetricRecord, PipelineSpec};
+
+/// Largest request body accepted, in bytes
+pub const MAX_BODY: u64 = 16 * 1024 * 1024;
+
+/// Body of a `POST /query` request
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QueryRequest {
+    pub pipeline: PipelineSpec,
+    pub metrics: Vec<MetricRecord>,
+}
+
+fn error_body(error: &MetricQueryError) -> String {
+    json!({
+        "error": {
+            "code": error.code(),
+            "message": error.to_string(),
+            "step": error.step_index(),
+        }
+    })
+    .to_string()
+}
+
+/// Run a query body and return the HTTP status and JSON response body
+pub fn handle_query(body: &str) -> (u16, String) {
+    let request: QueryRequest = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = MetricQueryError::OperationFailed { operation: "parse_query".to_string(), reason: e.to_string() };
+            return (400, error_body(&error));
+        }
+    };
+
+    let metrics: Vec<FloatMetric> = request.metrics.into_iter().map(FloatMetric::from).collect();
+    let result = request
+        .pipeline
+        .build(Vec::new())
+        .and_then(|pipeline| pipeline.execute_float_metrics(&metrics));
+
+    match result {
+        Ok(result) => {
+            let records: Vec<MetricRecord> = result.iter().map(MetricRecord::from).collect();
+            (200, json!({ "metrics": records }).to_string())
+        }
+        Err(e) => (422, error_body(&e)),
+    }
+}
+
+/// Read a body of at most `MAX_BODY` bytes, or the error response to send instead
+fn read_body(reader: impl Read) -> Result<String, (u16, String)> {
+    let read_error = |reason: String| {
+        let error = MetricQueryError::OperationFailed { operation: "read_body".to_string(), reason };
+        (400, error_body(&error))
+    };
+    let mut body = Vec::new();
+    // One byte past the limit tells a body of exactly `MAX_BODY` from a longer one
+    reader.take(MAX_BODY + 1).read_to_end(&mut body).map_err(|e| read_error(e.to_string()))?;
+    if body.len() as u64 > MAX_BODY {
+        let message = format!("Request body is larger than {} bytes", MAX_BODY);
+        return Err((413, json!({ "error": { "code": "payload_too_large", "message": message } }).to_string()));
+    }
+    String::from_utf8(body).map_err(|e| read_error(e.to_string()))
+}
+
+/// Answer a request for `url`, ignoring its query string, and return the HTTP status
+/// and JSON response body
+pub fn route(method: &Method, url: &str, body: impl Read) -> (u16, String) {
+    let path = url.split_once('?').map_or(url, |(path, _)| path);
+    match (method, path == "/query") {
+        (Method::Post, true) => read_body(body).map_or_else(|response| response, |body| handle_query(&body)),
+        (_, true) => (405, json!({ "error": { "code": "method_not_allowed", "message": "Use POST" } }).to_string()),
+        _ => (404, json!({ "error": { "code": "not_found", "message": "Unknown path" } }).to_string()),
+    }
+}
+
+/// `route`, answering 500 instead of unwinding when handling the request panics
+pub fn route_guarded(method: &Method, url: &str, body: impl Read) -> (u16, String) {
+    panic::catch_unwind(AssertUnwindSafe(|| route(method, url, body))).unwrap_or_else(|_| {
+        log::error!("panicked while handling {} {}", method, url);
+        let message = "The server failed while handling the request";
+        (500, json!({ "error": { "code": "internal_error", "message": message } }).to_string())
+    })
+}
+
+fn respond(mut request: Request) {
+    let method = request.method().clone();
+    let url = request.url().to_string();
+    let (status, body) = route_guarded(&method, &url, request.as_reader());
+    send(request, status, body);
+}
+
+fn send(request: Request, status: u16, body: String) {
+    let header = Header::from_bytes("Content-Type", "application/json").expect("static header is valid");
+    let response = Response::from_string(body).with_status_code(status).with_header(header);
+    // The client may have gone away; there is nobody left to report the failure to
+    let _ = request.respond(response);
+}
+
+/// Serve `POST /query` on `addr` (e.g. "0.0.0.0:8080") with `workers` threads.
+///
+/// Blocks for as long as the server runs.
+pub fn serve(addr: &str, workers: usize) -> std::io::Result<()> {
+    let server = Arc::new(Server::http(addr).map_err(std::io::Error::other)?);
+    let handles: Vec<_> = (0..workers.max(1))
+        .map(|_| {
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    respond(request);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        // Workers only stop when the server does; request panics are answered with 500
+        let _ = handle.join();
+    }
+    Ok(())
+}
@@ -0,0 +1,451 @@
+//! Behaviour shared by every step: default timezone, timestamp precision,
+//! empty-stream policy, output ordering, overflow and missing-value policies,
+//! metadata merging, parallelism, and the sandbox rules for plugins defined in Python.
+//!
+//! Settings are set process-wide with `Settings::set_global`, or per pipeline with
+//! `MetricPipeline::set_settings`, which takes precedence. A pipeline run installs
+//! its effective settings for the calling thread (and for the worker threads it
+//! spawns), and steps read them with `Settings::current()` when they execute.
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use chrono_tz::Tz;
+use std::cell::Cell;
+use std::sync::{PoisonError, RwLock};
+use std::time::Duration;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, MetadataMerge, Metric};
+use crate::plugin_impls::{MissingValuePolicy, OverflowPolicy};
+#[cfg(feature = "python")]
+use crate::time_range::parse_timezone;
+
+/// What aggregation and time grouping do with an empty input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EmptyStreamPolicy {
+    /// Fail the step with `EmptyMetricStream`
+    #[default]
+    Error,
+    /// Return no metrics
+    Empty,
+}
+
+impl EmptyStreamPolicy {
+    /// Parse a policy name ("error" or "empty")
+    pub fn parse(policy: &str) -> MetricQueryResult<Self> {
+        match policy {
+            "error" => Ok(Self::Error),
+            "empty" => Ok(Self::Empty),
+            _ => Err(invalid_setting(format!("Unknown empty stream policy: {}. Expected 'error' or 'empty'", policy))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Empty => "empty",
+        }
+    }
+}
+
+/// Order of a pipeline's final output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputOrdering {
+    /// Whatever order the last step produced; grouping steps don't promise one
+    #[default]
+    Unordered,
+    /// Sorted by timestamp, then label
+    Sorted,
+}
+
+impl OutputOrdering {
+    /// Parse an ordering name ("unordered" or "sorted")
+    pub fn parse(ordering: &str) -> MetricQueryResult<Self> {
+        match ordering {
+            "unordered" => Ok(Self::Unordered),
+            "sorted" => Ok(Self::Sorted),
+            _ => Err(invalid_setting(format!("Unknown ordering: {}. Expected 'unordered' or 'sorted'", ordering))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unordered => "unordered",
+            Self::Sorted => "sorted",
+        }
+    }
+}
+
+/// Largest distance from the epoch, in seconds, that a real timestamp is expected to lie
+/// (about the year 5138); anything further was almost certainly read in the wrong unit
+pub const MAX_PLAUSIBLE_SECONDS: u64 = 100_000_000_000;
+
+/// Smallest distance from the epoch, in seconds, expected of a sub-second timestamp
+/// (early 1973). Epoch seconds read as milliseconds land in January 1970, so anything
+/// closer was almost certainly read in too fine a unit.
+pub const MIN_PLAUSIBLE_SECONDS: u64 = 100_000_000;
+
+/// Unit of metric timestamps. Sub-second precisions keep high-frequency metrics apart;
+/// time groupings, rates and duration windows convert to seconds where they need to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampPrecision {
+    #[default]
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl TimestampPrecision {
+    /// Parse a precision name ("s", "ms", "us" or "ns")
+    pub fn parse(precision: &str) -> MetricQueryResult<Self> {
+        match precision {
+            "s" => Ok(Self::Seconds),
+            "ms" => Ok(Self::Milliseconds),
+            "us" => Ok(Self::Microseconds),
+            "ns" => Ok(Self::Nanoseconds),
+            _ => Err(invalid_setting(format!(
+                "Unknown timestamp precision: {}. Expected one of: s, ms, us, ns",
+                precision
+            ))),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Seconds => "s",
+            Self::Milliseconds => "ms",
+            Self::Microseconds => "us",
+            Self::Nanoseconds => "ns",
+        }
+    }
+
+    /// Timestamp units in one second
+    pub fn ticks_per_second(&self) -> i64 {
+        match self {
+            Self::Seconds => 1,
+            Self::Milliseconds => 1_000,
+            Self::Microseconds => 1_000_000,
+            Self::Nanoseconds => 1_000_000_000,
+        }
+    }
+
+    /// The whole second containing `timestamp` and the ticks past it
+    pub fn split(&self, timestamp: i64) -> (i64, i64) {
+        let ticks = self.ticks_per_second();
+        (timestamp.div_euclid(ticks), timestamp.rem_euclid(ticks))
+    }
+
+    /// `timestamp` in this precision re-expressed in `to`; coarser units round down
+    pub fn convert(&self, timestamp: i64, to: TimestampPrecision) -> MetricQueryResult<i64> {
+        let (from_ticks, to_ticks) = (self.ticks_per_second(), to.ticks_per_second());
+        if to_ticks >= from_ticks {
+            timestamp.checked_mul(to_ticks / from_ticks).ok_or_else(|| MetricQueryError::ArithmeticOverflow {
+                operation: format!("timestamp {}->{}", self.as_str(), to.as_str()),
+            })
+        } else {
+            Ok(timestamp.div_euclid(from_ticks / to_ticks))
+        }
+    }
+
+    /// Whether `timestamp`, read in this precision, lies within `MAX_PLAUSIBLE_SECONDS`
+    /// of the epoch and, for sub-second precisions, at least `MIN_PLAUSIBLE_SECONDS` from it
+    pub fn plausible(&self, timestamp: i64) -> bool {
+        let seconds = (timestamp / self.ticks_per_second()).unsigned_abs();
+        let lowest = if *self == Self::Seconds { 0 } else { MIN_PLAUSIBLE_SECONDS };
+        (lowest..=MAX_PLAUSIBLE_SECONDS).contains(&seconds)
+    }
+
+    /// The coarsest precision in which `timestamp` is plausible, to suggest when a
+    /// declared precision isn't
+    pub fn guess(timestamp: i64) -> Option<Self> {
+        [Self::Seconds, Self::Milliseconds, Self::Microseconds, Self::Nanoseconds]
+            .into_iter()
+            .find(|precision| precision.plausible(timestamp))
+    }
+
+    /// `seconds` as a timestamp in this precision, failing if it doesn't fit in i64
+    pub fn from_seconds(&self, seconds: i64) -> MetricQueryResult<i64> {
+        seconds.checked_mul(self.ticks_per_second()).ok_or_else(|| MetricQueryError::InvalidTimeGrouping {
+            reason: format!("Timestamp of second {} overflows at precision '{}'", seconds, self.as_str()),
+        })
+    }
+}
+
+fn invalid_setting(reason: String) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: "settings".to_string(), reason }
+}
+
+/// Pipeline-wide behaviour that individual steps don't configure themselves
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// Zone whose wall clock the "minute", "hour" and "day" groupings follow; `None` is
+    /// UTC. Custom groupings get UTC timestamps and apply a zone themselves.
+    pub timezone: Option<Tz>,
+    /// Unit of metric timestamps, seconds unless metrics carry sub-second times
+    pub timestamp_precision: TimestampPrecision,
+    pub empty_stream: EmptyStreamPolicy,
+    pub ordering: OutputOrdering,
+    /// Overflow policy of "sum" aggregations created without an explicit one
+    pub overflow: OverflowPolicy,
+    /// How float aggregations created without their own policy treat missing (NaN)
+    /// values; `None` leaves them to each aggregation
+    pub missing: Option<MissingValuePolicy>,
+    /// How aggregation combines the `source` and `description` of merged metrics
+    pub metadata: MetadataMerge,
+    /// Allow steps to spread large inputs over worker threads (with the `rayon` feature)
+    pub parallel: bool,
+    /// Longest a single call into a Python-defined plugin may take before its step fails
+    pub plugin_timeout: Option<Duration>,
+    /// Refuse to run plugins defined in Python; only built-in and Rust plugins execute.
+    /// Once the process-wide settings turn this on, a pipeline's own settings can't
+    /// turn it off.
+    pub trusted_only: bool,
+}
+
+impl Settings {
+    /// The built-in behaviour: UTC, timestamps in seconds, errors on empty input and
+    /// overflow, unordered, first metadata wins, parallel, and Python plugins allowed
+    /// without a time limit
+    pub const DEFAULT: Self = Self {
+        timezone: None,
+        timestamp_precision: TimestampPrecision::Seconds,
+        empty_stream: EmptyStreamPolicy::Error,
+        ordering: OutputOrdering::Unordered,
+        overflow: OverflowPolicy::Error,
+        missing: None,
+        metadata: MetadataMerge::First,
+        parallel: true,
+        plugin_timeout: None,
+        trusted_only: false,
+    };
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+static GLOBAL: RwLock<Settings> = RwLock::new(Settings::DEFAULT);
+
+thread_local! {
+    static CURRENT: Cell<Option<Settings>> = const { Cell::new(None) };
+}
+
+/// Restores the previously installed settings when a scope ends, even by panic
+struct ScopeGuard(Option<Settings>);
+
+impl Drop for ScopeGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| current.set(self.0));
+    }
+}
+
+impl Settings {
+    /// The process-wide settings
+    pub fn global() -> Self {
+        *GLOBAL.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Replace the process-wide settings; pipelines with their own settings are unaffected
+    pub fn set_global(settings: Self) {
+        *GLOBAL.write().unwrap_or_else(PoisonError::into_inner) = settings;
+    }
+
+    /// The settings in effect on this thread: those of the running pipeline, else the global ones
+    pub fn current() -> Self {
+        CURRENT.with(Cell::get).unwrap_or_else(Self::global)
+    }
+
+    /// Run `f` with these settings in effect on the calling thread
+    pub fn scope<R>(&self, f: impl FnOnce() -> R) -> R {
+        let _guard = ScopeGuard(CURRENT.with(|current| current.replace(Some(*self))));
+        f()
+    }
+
+    /// Output of a step given no metrics: an error or nothing, per `empty_stream`
+    pub fn empty_stream_result<T>(&self) -> MetricQueryResult<Vec<T>> {
+        match self.empty_stream {
+            EmptyStreamPolicy::Error => Err(MetricQueryError::EmptyMetricStream),
+            EmptyStreamPolicy::Empty => Ok(Vec::new()),
+        }
+    }
+
+    /// Sort a run's output if `ordering` asks for it
+    pub fn order_metrics(&self, metrics: &mut [Metric]) {
+        if self.ordering == OutputOrdering::Sorted {
+            metrics.sort_by(|a, b| (a.timestamp, &a.label).cmp(&(b.timestamp, &b.label)));
+        }
+    }
+
+    /// Sort a float run's output if `ordering` asks for it
+    pub fn order_float_metrics(&self, metrics: &mut [FloatMetric]) {
+        if self.ordering == OutputOrdering::Sorted {
+            metrics.sort_by(|a, b| (a.timestamp, &a.label).cmp(&(b.timestamp, &b.label)));
+        }
+    }
+
+    /// Sort value/timestamp columns by timestamp if `ordering` asks for it
+    pub fn order_columns(&self, columns: (Vec<i64>, Vec<i64>)) -> (Vec<i64>, Vec<i64>) {
+        if self.ordering == OutputOrdering::Unordered {
+            return columns;
+        }
+        let mut pairs: Vec<(i64, i64)> = columns.1.into_iter().zip(columns.0).collect();
+        pairs.sort_by_key(|&(timestamp, _)| timestamp);
+        let (timestamps, values) = pairs.into_iter().unzip();
+        (values, timestamps)
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl Settings {
+    #[new]
+    #[pyo3(signature = (
+        timezone=None,
+        empty_stream="error",
+        ordering="unordered",
+        overflow="error",
+        parallel=true,
+        plugin_timeout=None,
+        trusted_only=false,
+        timestamp_precision="s",
+        missing=None,
+        fill_value=None,
+        metadata="first",
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        timezone: Option<&str>,
+        empty_stream: &str,
+        ordering: &str,
+        overflow: &str,
+        parallel: bool,
+        plugin_timeout: Option<f64>,
+        trusted_only: bool,
+        timestamp_precision: &str,
+        missing: Option<&str>,
+        fill_value: Option<f64>,
+        metadata: &str,
+    ) -> PyResult<Self> {
+        let plugin_timeout = plugin_timeout
+            .map(Duration::try_from_secs_f64)
+            .transpose()
+            .map_err(|e| pyo3::exceptions::PyValueError::new_err(format!("Invalid plugin_timeout: {}", e)))?;
+        Ok(Self {
+            timezone: timezone.map(parse_timezone).transpose()?,
+            timestamp_precision: TimestampPrecision::parse(timestamp_precision)?,
+            empty_stream: EmptyStreamPolicy::parse(empty_stream)?,
+            ordering: OutputOrdering::parse(ordering)?,
+            overflow: OverflowPolicy::parse(overflow)?,
+            missing: missing.map(|policy| MissingValuePolicy::parse(policy, fill_value)).transpose()?,
+            metadata: MetadataMerge::parse(metadata)?,
+            parallel,
+            plugin_timeout,
+            trusted_only,
+        })
+    }
+
+    /// IANA name of the grouping timezone, or `None` for UTC
+    #[getter(timezone)]
+    fn py_timezone(&self) -> Option<&'static str> {
+        self.timezone.map(|tz| tz.name())
+    }
+
+    /// "s", "ms", "us" or "ns"
+    #[getter(timestamp_precision)]
+    fn py_timestamp_precision(&self) -> &'static str {
+        self.timestamp_precision.as_str()
+    }
+
+    /// "error" or "empty"
+    #[getter(empty_stream)]
+    fn py_empty_stream(&self) -> &'static str {
+        self.empty_stream.as_str()
+    }
+
+    /// "unordered" or "sorted"
+    #[getter(ordering)]
+    fn py_ordering(&self) -> &'static str {
+        self.ordering.as_str()
+    }
+
+    /// "error" or "saturate"
+    #[getter(overflow)]
+    fn py_overflow(&self) -> &'static str {
+        self.overflow.as_str()
+    }
+
+    /// "skip", "propagate", "substitute", or `None` to leave missing values to each aggregation
+    #[getter(missing)]
+    fn py_missing(&self) -> Option<&'static str> {
+        self.missing.map(|policy| policy.as_str())
+    }
+
+    /// Fill value of the "substitute" missing-value policy
+    #[getter(fill_value)]
+    fn py_fill_value(&self) -> Option<f64> {
+        match self.missing {
+            Some(MissingValuePolicy::Substitute(fill)) => Some(fill),
+            _ => None,
+        }
+    }
+
+    /// "first" or "concat"
+    #[getter(metadata)]
+    fn py_metadata(&self) -> &'static str {
+        self.metadata.as_str()
+    }
+
+    #[getter(parallel)]
+    fn py_parallel(&self) -> bool {
+        self.parallel
+    }
+
+    /// Per-call time limit for Python plugins in seconds, or `None` for no limit
+    #[getter(plugin_timeout)]
+    fn py_plugin_timeout(&self) -> Option<f64> {
+        self.plugin_timeout.map(|timeout| timeout.as_secs_f64())
+    }
+
+    #[getter(trusted_only)]
+    fn py_trusted_only(&self) -> bool {
+        self.trusted_only
+    }
+
+    fn __eq__(&self, other: &Self) -> bool {
+        self == other
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Settings(timezone={}, timestamp_precision='{}', empty_stream='{}', ordering='{}', overflow='{}', missing={}, metadata='{}', parallel={}, plugin_timeout={}, trusted_only={})",
+            self.timezone.map_or("None".to_string(), |tz| format!("'{}'", tz.name())),
+            self.timestamp_precision.as_str(),
+            self.empty_stream.as_str(),
+            self.ordering.as_str(),
+            self.overflow.as_str(),
+            self.missing.map_or("None".to_string(), |policy| format!("'{}'", policy.as_str())),
+            self.metadata.as_str(),
+            if self.parallel { "True" } else { "False" },
+            self.plugin_timeout.map_or("None".to_string(), |timeout| timeout.as_secs_f64().to_string()),
+            if self.trusted_only { "True" } else { "False" },
+        )
+    }
+}
+
+/// The process-wide settings
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn get_settings() -> Settings {
+    Settings::global()
+}
+
+/// Replace the process-wide settings; pipelines given their own settings keep them
+#[cfg(feature = "python")]
+#[pyfunction]
+pub fn set_settings(settings: Settings) {
+    Settings::set_global(settings);
+}
@@ -0,0 +1,178 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+
+/// One multi-window burn-rate alerting rule, e.g. 14.4× over 1h confirmed by 5m
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BurnRateRule {
+    /// Long window length in seconds
+    pub long_window: i64,
+    /// Short window length in seconds, guarding against alerts on already-recovered spikes
+    pub short_window: i64,
+    /// Burn rate both windows must reach for the rule to fire
+    pub threshold: f64,
+}
+
+impl BurnRateRule {
+    /// Create a rule firing when both windows burn at `threshold` or faster
+    pub fn new(long_window: i64, short_window: i64, threshold: f64) -> Self {
+        Self { long_window, short_window, threshold }
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl BurnRateRule {
+    #[new]
+    fn py_new(long_window: i64, short_window: i64, threshold: f64) -> Self {
+        Self::new(long_window, short_window, threshold)
+    }
+}
+
+/// Outcome of evaluating a `BurnRateRule`
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct BurnRateAlert {
+    pub rule: BurnRateRule,
+    /// Burn rate over the long window
+    pub long_burn_rate: f64,
+    /// Burn rate over the short window
+    pub short_burn_rate: f64,
+    /// Whether both burn rates reached the threshold
+    pub firing: bool,
+}
+
+/// Error-budget burn rate over the window `(end - window, end]`.
+///
+/// The burn rate is the observed error ratio (`1 - good / total`) divided by the
+/// allowed error ratio (`1 - target`), so 1.0 spends the budget exactly on schedule.
+/// A window without any events burns nothing.
+fn window_burn_rate(
+    metrics: &[Metric],
+    good_label: &str,
+    total_label: &str,
+    target: f64,
+    window: i64,
+    end: i64,
+) -> f64 {
+    let start = end.saturating_sub(window);
+    let (mut good, mut total) = (0.0, 0.0);
+    for metric in metrics.iter().filter(|m| m.timestamp > start && m.timestamp <= end) {
+        match metric.label.as_deref() {
+            Some(label) if label == good_label => good += metric.value as f64,
+            Some(label) if label == total_label => total += metric.value as f64,
+            _ => {}
+        }
+    }
+
+    if total == 0.0 {
+        return 0.0;
+    }
+    (1.0 - good / total) / (1.0 - target)
+}
+
+fn check_inputs(metrics: &[Metric], target: f64, windows: &[i64]) -> MetricQueryResult<i64> {
+    if !(target > 0.0 && target < 1.0) {
+        return Err(MetricQueryError::OperationFailed {
+            operation: "burn_rate".to_string(),
+            reason: format!("SLO target must be between 0 and 1 (exclusive), got {}", target),
+        });
+    }
+    if let Some(window) = windows.iter().find(|&&w| w <= 0) {
+        return Err(MetricQueryError::OperationFailed {
+            operation: "burn_rate".to_string(),
+            reason: format!("Windows must be positive, got {}", window),
+        });
+    }
+    metrics.iter().map(|m| m.timestamp).max().ok_or(MetricQueryError::EmptyMetricStream)
+}
+
+/// Burn rate for each window, ending at `end` (default: the latest timestamp).
+///
+/// `good_label` and `total_label` select per-interval event counts. Each result is
+/// labeled "burn_rate_<window>s" and stamped with the end of the window.
+pub fn burn_rates(
+    metrics: &[Metric],
+    good_label: &str,
+    total_label: &str,
+    target: f64,
+    windows: &[i64],
+    end: Option<i64>,
+) -> MetricQueryResult<Vec<FloatMetric>> {
+    let latest = check_inputs(metrics, target, windows)?;
+    let end = end.unwrap_or(latest);
+
+    Ok(windows
+        .iter()
+        .map(|&window| FloatMetric {
+            value: window_burn_rate(metrics, good_label, total_label, target, window, end),
+            timestamp: end,
+            label: Some(format!("burn_rate_{}s", window)),
+            unit: None,
+        })
+        .collect())
+}
+
+/// Evaluate multi-window multi-burn-rate rules at `end` (default: the latest timestamp)
+pub fn evaluate_burn_rate_rules(
+    metrics: &[Metric],
+    good_label: &str,
+    total_label: &str,
+    target: f64,
+    rules: &[BurnRateRule],
+    end: Option<i64>,
+) -> MetricQueryResult<Vec<BurnRateAlert>> {
+    let windows: Vec<i64> = rules.iter().flat_map(|r| [r.long_window, r.short_window]).collect();
+    let latest = check_inputs(metrics, target, &windows)?;
+    let end = end.unwrap_or(latest);
+
+    Ok(rules
+        .iter()
+        .map(|&rule| {
+            let long_burn_rate = window_burn_rate(metrics, good_label, total_label, target, rule.long_window, end);
+            let short_burn_rate = window_burn_rate(metrics, good_label, total_label, target, rule.short_window, end);
+            BurnRateAlert {
+                rule,
+                long_burn_rate,
+                short_burn_rate,
+                firing: long_burn_rate >= rule.threshold && short_burn_rate >= rule.threshold,
+            }
+        })
+        .collect())
+}
+
+/// Error-budget burn rate of an SLO over each of `windows` (in seconds)
+///
+/// `good_label` and `total_label` pick out the good-event and total-event counts and
+/// `target` is the SLO as a ratio (e.g. 0.999).
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "burn_rate", signature = (metrics, good_label, total_label, target, windows, end=None))]
+pub fn py_burn_rate(
+    metrics: Vec<Metric>,
+    good_label: &str,
+    total_label: &str,
+    target: f64,
+    windows: Vec<i64>,
+    end: Option<i64>,
+) -> PyResult<Vec<FloatMetric>> {
+    Ok(burn_rates(&metrics, good_label, total_label, target, &windows, end)?)
+}
+
+/// Evaluate multi-window burn-rate alerting rules for an SLO
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "burn_rate_alerts", signature = (metrics, good_label, total_label, target, rules, end=None))]
+pub fn py_burn_rate_alerts(
+    metrics: Vec<Metric>,
+    good_label: &str,
+    total_label: &str,
+    target: f64,
+    rules: Vec<BurnRateRule>,
+    end: Option<i64>,
+) -> PyResult<Vec<BurnRateAlert>> {
+    Ok(evaluate_burn_rate_rules(&metrics, good_label, total_label, target, &rules, end)?)
+}
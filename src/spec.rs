@@ -0,0 +1,657 @@
+//! JSON pipeline specs, enabled with the `spec` feature.
+//!
+//! A spec is a list of steps, each tagged with the name of the matching
+//! `MetricPipeline` Python method and taking the same parameters and defaults:
+//!
+//! ```json
+//! {"version": 1, "steps": [
+//!     {"op": "filter", "type": "gt", "value": 10},
+//!     {"op": "group_by_time", "grouping": "hour", "aggregation": "avg"}
+//! ]}
+//! ```
+//!
+//! `version` is the format version, `SPEC_VERSION` for specs written today. Specs
+//! stored by older releases are upgraded step by step through `MIGRATIONS` when they
+//! load, so a step's schema can change without breaking persisted queries. A spec
+//! without `version` predates the field and is read as version 1.
+
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::accuracy::Accuracy;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::plugin_impls::{
+    create_aggregation, create_filter, create_label_filter, create_label_in_filter, create_time_grouping,
+    with_accuracy, BetweenFilter, ExprFilter, HourOfDayFilter, LabelFilter, LabelInFilter, RoundingMode, TagFilter,
+    TagMatch, TagValue, Threshold, TimestampComparison, TimestampFilter, TimestampRangeFilter, ValueInFilter, WeekdayFilter,
+};
+use crate::plugins::{create_registered_filter, with_registry, AggregationPlugin, FilterPlugin};
+use crate::settings::TimestampPrecision;
+use crate::steps::{
+    AnomalyDetectionTransformation, AnomalyMethod, AnomalyOutput, Assertion, AssertionTransformation,
+    ConvertUnitTransformation, CounterIncreaseTransformation, CrossingDirection, CrossingTransformation,
+    DeduplicateTransformation, DerivativeTransformation, DuplicateStrategy, ForecastMethod, ForecastTransformation,
+    GapAction, GapDetectionTransformation, HistogramBuckets, HistogramTransformation, LatestTransformation, NormalizeMethod, NormalizeTransformation, PercentChangeTransformation,
+    resample_aggregation_name, RescaleTransformation, ResampleFill, ResampleTransformation, RollingPercentileTransformation, RollingReduceTransformation,
+    RollingReduction, RollingWindow, SampleEveryTransformation, SampleFractionTransformation,
+    SeasonalDecompositionTransformation, SmoothingParams, TimestampUnitTransformation, TopKSeriesTransformation,
+    TimezoneDirection,
+    TimezoneShiftTransformation,
+    TrendOutput, TrendTransformation,
+};
+use crate::time_range::{parse_duration, parse_timezone};
+use crate::transformations::{
+    AggregationTransformation, FilterTransformation, MetricPipeline, TimeGroupingTransformation, TimestampPolicy,
+    TransformationStrategy,
+};
+
+/// One metric as it appears in JSON, CSV or NDJSON; a missing value is `null`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetricRecord {
+    pub value: Option<f64>,
+    pub timestamp: i64,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+impl From<MetricRecord> for FloatMetric {
+    fn from(record: MetricRecord) -> Self {
+        FloatMetric::new(record.value, record.timestamp, record.label)
+    }
+}
+
+impl From<&FloatMetric> for MetricRecord {
+    fn from(metric: &FloatMetric) -> Self {
+        Self {
+            value: (!metric.is_missing()).then_some(metric.value),
+            timestamp: metric.timestamp,
+            label: metric.label.clone(),
+        }
+    }
+}
+
+/// Version of the spec format this release reads and writes
+pub const SPEC_VERSION: u64 = 1;
+
+/// Version assumed for specs without a `version` field, written before it existed
+const UNVERSIONED: u64 = 1;
+
+/// Rewrites a spec object from one format version to the next, in place
+pub(crate) type Migration = fn(&mut Map<String, Value>) -> MetricQueryResult<()>;
+
+/// `MIGRATIONS[n - 1]` upgrades a version `n` spec to version `n + 1`.
+///
+/// When a step's schema changes, bump `SPEC_VERSION` and append a migration that
+/// rewrites the old form of the step into the new one.
+const MIGRATIONS: [Migration; (SPEC_VERSION - 1) as usize] = [];
+
+fn spec_error(reason: impl Into<String>) -> MetricQueryError {
+    MetricQueryError::OperationFailed { operation: "parse_spec".to_string(), reason: reason.into() }
+}
+
+/// Upgrade a spec to the newest version `migrations` lead to, stamping that version on it
+pub(crate) fn migrate(spec: &mut Value, migrations: &[Migration]) -> MetricQueryResult<()> {
+    let latest = migrations.len() as u64 + 1;
+    let object = spec.as_object_mut().ok_or_else(|| spec_error("A spec must be a JSON object"))?;
+    let version = match object.get("version") {
+        None => UNVERSIONED,
+        Some(version) => version
+            .as_u64()
+            .filter(|&version| version >= 1)
+            .ok_or_else(|| spec_error(format!("Spec version must be a positive integer, got {}", version)))?,
+    };
+    if version > latest {
+        return Err(spec_error(format!(
+            "Spec version {} is newer than the supported version {}; upgrade metric-query to load it",
+            version, latest
+        )));
+    }
+    for migration in &migrations[(version - 1) as usize..] {
+        migration(object)?;
+    }
+    object.insert("version".to_string(), latest.into());
+    Ok(())
+}
+
+/// An ordered list of pipeline steps.
+///
+/// Deserializing migrates older spec versions, so a loaded spec is always at `SPEC_VERSION`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "Value")]
+pub struct PipelineSpec {
+    pub version: u64,
+    pub steps: Vec<StepSpec>,
+}
+
+/// A spec's fields once migrated to the current version
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CurrentSpec {
+    version: u64,
+    steps: Vec<StepSpec>,
+}
+
+impl TryFrom<Value> for PipelineSpec {
+    type Error = MetricQueryError;
+
+    fn try_from(mut spec: Value) -> MetricQueryResult<Self> {
+        migrate(&mut spec, &MIGRATIONS)?;
+        let spec: CurrentSpec = serde_json::from_value(spec).map_err(|e| spec_error(e.to_string()))?;
+        Ok(Self { version: spec.version, steps: spec.steps })
+    }
+}
+
+/// One pipeline step, tagged by `op`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum StepSpec {
+    Filter {
+        #[serde(rename = "type")]
+        filter_type: String,
+        value: Threshold,
+    },
+    Between {
+        lo: Threshold,
+        hi: Threshold,
+        #[serde(default = "default_inclusive")]
+        inclusive: bool,
+    },
+    ValueIn {
+        values: Vec<i64>,
+    },
+    FilterTimeRange {
+        start_ts: i64,
+        end_ts: i64,
+    },
+    /// `operator` is the Python method's `op`, as for `FilterByTag`
+    FilterTimestamp {
+        operator: String,
+        instant: i64,
+    },
+    FilterWeekdays {
+        days: Vec<String>,
+    },
+    FilterHours {
+        start: u32,
+        end: u32,
+    },
+    FilterByLabel {
+        label: String,
+    },
+    FilterByLabels {
+        labels: Vec<String>,
+    },
+    /// `operator` is the Python method's `op`, which would clash with the step tag
+    FilterByTag {
+        key: String,
+        operator: String,
+        value: TagValue,
+    },
+    FilterExpr {
+        expr: String,
+    },
+    LabelNe {
+        label: String,
+        #[serde(default = "default_keep_unlabeled")]
+        keep_unlabeled: bool,
+    },
+    LabelNotIn {
+        labels: Vec<String>,
+        #[serde(default = "default_keep_unlabeled")]
+        keep_unlabeled: bool,
+    },
+    Aggregate {
+        #[serde(rename = "type")]
+        agg_type: String,
+        #[serde(default = "default_timestamp_policy")]
+        timestamp: String,
+        #[serde(default)]
+        accuracy: Option<String>,
+    },
+    GroupByTime {
+        grouping: String,
+        aggregation: String,
+        #[serde(default)]
+        deduplicate: Option<String>,
+        #[serde(default)]
+        accuracy: Option<String>,
+    },
+    Deduplicate {
+        #[serde(default = "default_duplicate_strategy")]
+        strategy: String,
+    },
+    DetectAnomalies {
+        #[serde(default = "default_anomaly_method")]
+        method: String,
+        #[serde(default = "default_anomaly_threshold")]
+        threshold: f64,
+        #[serde(default)]
+        window: Option<usize>,
+        #[serde(default = "default_anomaly_output")]
+        output: String,
+    },
+    Trend {
+        #[serde(default = "default_trend_output")]
+        output: String,
+        #[serde(default = "default_per_seconds")]
+        per_seconds: i64,
+    },
+    Histogram {
+        #[serde(default = "default_histogram_buckets")]
+        buckets: usize,
+        #[serde(default)]
+        bounds: Option<Vec<f64>>,
+    },
+    Crossings {
+        threshold: f64,
+        #[serde(default = "default_crossing_direction")]
+        direction: String,
+    },
+    PctChange {
+        period: i64,
+    },
+    RollingPercentile {
+        quantile: f64,
+        #[serde(default)]
+        count: Option<usize>,
+        #[serde(default)]
+        seconds: Option<i64>,
+    },
+    Rolling {
+        how: String,
+        #[serde(default)]
+        window: Option<String>,
+        #[serde(default)]
+        count: Option<usize>,
+    },
+    CounterIncrease {
+        #[serde(default)]
+        cumulative: bool,
+    },
+    TimestampUnit {
+        unit: String,
+    },
+    DetectGaps {
+        max_gap_seconds: i64,
+        #[serde(default = "default_gap_action")]
+        on_gap: String,
+        #[serde(default)]
+        now: Option<i64>,
+    },
+    Acceleration {
+        #[serde(default = "default_per_seconds")]
+        per_seconds: i64,
+    },
+    Normalize {
+        #[serde(default = "default_normalize_method")]
+        method: String,
+        #[serde(default)]
+        per_label: bool,
+    },
+    AssertThat {
+        predicate: String,
+        #[serde(default)]
+        message: Option<String>,
+        #[serde(default)]
+        min: Option<f64>,
+        #[serde(default)]
+        max: Option<f64>,
+    },
+    ConvertUnit {
+        from: String,
+        to: String,
+        #[serde(default)]
+        assume_unit: bool,
+    },
+    Rescale {
+        scale: u32,
+        #[serde(default = "default_rescale_rounding")]
+        rounding: String,
+    },
+    Latest,
+    Resample {
+        interval: i64,
+        #[serde(default = "default_resample_how")]
+        how: String,
+        #[serde(default = "default_resample_fill")]
+        fill: String,
+    },
+    ConvertTimezone {
+        tz: String,
+        #[serde(default = "default_timezone_direction")]
+        direction: String,
+    },
+    TopkSeries {
+        k: usize,
+        #[serde(default = "default_topk_agg")]
+        agg: String,
+    },
+    SampleEvery {
+        n: usize,
+    },
+    SampleFraction {
+        p: f64,
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    Decompose {
+        period: usize,
+    },
+    Forecast {
+        horizon: usize,
+        #[serde(default = "default_forecast_method")]
+        method: String,
+        #[serde(default)]
+        period: Option<usize>,
+        #[serde(default = "default_alpha")]
+        alpha: f64,
+        #[serde(default = "default_beta")]
+        beta: f64,
+        #[serde(default = "default_gamma")]
+        gamma: f64,
+    },
+}
+
+fn default_forecast_method() -> String {
+    "holt_winters".to_string()
+}
+
+fn default_alpha() -> f64 {
+    SmoothingParams::default().alpha
+}
+
+fn default_beta() -> f64 {
+    SmoothingParams::default().beta
+}
+
+fn default_gamma() -> f64 {
+    SmoothingParams::default().gamma
+}
+
+fn default_timestamp_policy() -> String {
+    "first".to_string()
+}
+
+fn default_resample_how() -> String {
+    "mean".to_string()
+}
+
+fn default_resample_fill() -> String {
+    "previous".to_string()
+}
+
+fn default_topk_agg() -> String {
+    "sum".to_string()
+}
+
+fn default_timezone_direction() -> String {
+    "to_local".to_string()
+}
+
+fn default_inclusive() -> bool {
+    true
+}
+
+fn default_keep_unlabeled() -> bool {
+    true
+}
+
+fn default_gap_action() -> String {
+    "mark".to_string()
+}
+
+fn default_rescale_rounding() -> String {
+    "round".to_string()
+}
+
+fn default_duplicate_strategy() -> String {
+    "keep_last".to_string()
+}
+
+fn default_anomaly_method() -> String {
+    "zscore".to_string()
+}
+
+fn default_anomaly_threshold() -> f64 {
+    3.0
+}
+
+fn default_anomaly_output() -> String {
+    "flag".to_string()
+}
+
+fn default_trend_output() -> String {
+    "line".to_string()
+}
+
+fn default_per_seconds() -> i64 {
+    1
+}
+
+fn default_histogram_buckets() -> usize {
+    10
+}
+
+fn default_crossing_direction() -> String {
+    "both".to_string()
+}
+
+fn default_normalize_method() -> String {
+    "minmax".to_string()
+}
+
+impl PipelineSpec {
+    /// A spec of the current version with these steps
+    pub fn new(steps: Vec<StepSpec>) -> Self {
+        Self { version: SPEC_VERSION, steps }
+    }
+
+    /// Parse a spec from JSON, migrating it from an older version if needed
+    pub fn from_json(json: &str) -> MetricQueryResult<Self> {
+        let spec: Value = serde_json::from_str(json).map_err(|e| spec_error(e.to_string()))?;
+        Self::try_from(spec)
+    }
+
+    /// The spec as JSON in the current format, for storing alongside queries
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("specs always serialize")
+    }
+
+    /// Build a pipeline over `metrics` with the spec's steps
+    pub fn build(&self, metrics: Vec<Metric>) -> MetricQueryResult<MetricPipeline> {
+        let mut pipeline = MetricPipeline::new(metrics);
+        for step in &self.steps {
+            for strategy in step.strategies()? {
+                pipeline.add_strategy(strategy);
+            }
+        }
+        Ok(pipeline)
+    }
+}
+
+/// Create an aggregation, with an accuracy mode when one is given
+fn aggregation_with_accuracy(agg_type: &str, accuracy: Option<&str>) -> MetricQueryResult<Box<dyn AggregationPlugin>> {
+    let aggregation = create_aggregation(agg_type)?;
+    match accuracy {
+        Some(mode) => with_accuracy(aggregation, Accuracy::parse(mode)?),
+        None => Ok(aggregation),
+    }
+}
+
+/// A value filter by name: filters registered at runtime (e.g. Python factories) take
+/// precedence, then the built-ins
+fn filter_from_registry(filter_type: &str, value: Threshold) -> MetricQueryResult<Box<dyn FilterPlugin>> {
+    if with_registry(|registry| registry.has_filter(filter_type)) {
+        create_registered_filter(filter_type, Some(value))
+    } else {
+        create_filter(filter_type, value)
+    }
+}
+
+impl StepSpec {
+    /// The transformations this step adds, in order
+    fn strategies(&self) -> MetricQueryResult<Vec<Box<dyn TransformationStrategy>>> {
+        let strategy: Box<dyn TransformationStrategy> = match self {
+            Self::Filter { filter_type, value } => {
+                Box::new(FilterTransformation::new(filter_from_registry(filter_type, *value)?))
+            }
+            Self::Between { lo, hi, inclusive } => {
+                Box::new(FilterTransformation::new(Box::new(BetweenFilter::new(*lo, *hi, *inclusive)?)))
+            }
+            Self::ValueIn { values } => {
+                Box::new(FilterTransformation::new(Box::new(ValueInFilter::new(values.iter().copied()))))
+            }
+            Self::FilterTimeRange { start_ts, end_ts } => {
+                Box::new(FilterTransformation::new(Box::new(TimestampRangeFilter::new(*start_ts, *end_ts)?)))
+            }
+            Self::FilterTimestamp { operator, instant } => Box::new(FilterTransformation::new(Box::new(
+                TimestampFilter::new(TimestampComparison::parse(operator)?, *instant),
+            ))),
+            Self::FilterWeekdays { days } => Box::new(FilterTransformation::new(Box::new(WeekdayFilter::parse(days)?))),
+            Self::FilterHours { start, end } => {
+                Box::new(FilterTransformation::new(Box::new(HourOfDayFilter::new(*start, *end)?)))
+            }
+            Self::FilterByLabel { label } => {
+                Box::new(FilterTransformation::new(create_label_filter("label_eq", label.clone())?))
+            }
+            Self::FilterByLabels { labels } => {
+                Box::new(FilterTransformation::new(create_label_in_filter("label_in", labels.clone())?))
+            }
+            Self::FilterByTag { key, operator, value } => {
+                let filter = TagFilter::new(key.clone(), TagMatch::parse(operator, value.clone())?);
+                Box::new(FilterTransformation::new(Box::new(filter)))
+            }
+            Self::FilterExpr { expr } => Box::new(FilterTransformation::new(Box::new(ExprFilter::new(expr)?))),
+            Self::LabelNe { label, keep_unlabeled } => Box::new(FilterTransformation::new(Box::new(
+                LabelFilter::not_equal(label.clone()).keep_unlabeled(*keep_unlabeled),
+            ))),
+            Self::LabelNotIn { labels, keep_unlabeled } => Box::new(FilterTransformation::new(Box::new(
+                LabelInFilter::not_in(labels.clone()).keep_unlabeled(*keep_unlabeled),
+            ))),
+            Self::Aggregate { agg_type, timestamp, accuracy } => Box::new(
+                AggregationTransformation::new(aggregation_with_accuracy(agg_type, accuracy.as_deref())?)
+                    .with_timestamp_policy(TimestampPolicy::parse(timestamp)?),
+            ),
+            Self::GroupByTime { grouping, aggregation, deduplicate, accuracy } => {
+                let grouping = Box::new(TimeGroupingTransformation::new(
+                    create_time_grouping(grouping)?,
+                    aggregation_with_accuracy(aggregation, accuracy.as_deref())?,
+                ));
+                return Ok(match deduplicate {
+                    Some(strategy) => vec![
+                        Box::new(DeduplicateTransformation::new(DuplicateStrategy::parse(strategy)?)),
+                        grouping,
+                    ],
+                    None => vec![grouping],
+                });
+            }
+            Self::Deduplicate { strategy } => {
+                Box::new(DeduplicateTransformation::new(DuplicateStrategy::parse(strategy)?))
+            }
+            Self::DetectAnomalies { method, threshold, window, output } => {
+                let mut step = AnomalyDetectionTransformation::new(AnomalyMethod::parse(method)?, *threshold)?
+                    .with_output(AnomalyOutput::parse(output)?);
+                if let Some(window) = window {
+                    step = step.with_window(*window)?;
+                }
+                Box::new(step)
+            }
+            Self::Trend { output, per_seconds } => {
+                Box::new(TrendTransformation::new(TrendOutput::parse(output)?).with_slope_unit(*per_seconds)?)
+            }
+            Self::Histogram { buckets, bounds } => {
+                let buckets = match bounds {
+                    Some(bounds) => HistogramBuckets::Bounds(bounds.clone()),
+                    None => HistogramBuckets::Auto(*buckets),
+                };
+                Box::new(HistogramTransformation::new(buckets)?)
+            }
+            Self::Crossings { threshold, direction } => {
+                Box::new(CrossingTransformation::new(*threshold, CrossingDirection::parse(direction)?))
+            }
+            Self::PctChange { period } => Box::new(PercentChangeTransformation::new(*period)?),
+            Self::RollingPercentile { quantile, count, seconds } => {
+                let window = match (count, seconds) {
+                    (Some(count), None) => RollingWindow::Count(*count),
+                    (None, Some(seconds)) => RollingWindow::Duration(*seconds),
+                    _ => {
+                        return Err(MetricQueryError::OperationFailed {
+                            operation: "rolling_percentile".to_string(),
+                            reason: "Specify exactly one of 'count' or 'seconds' for the rolling window".to_string(),
+                        })
+                    }
+                };
+                Box::new(RollingPercentileTransformation::new(*quantile, window)?)
+            }
+            Self::Rolling { how, window, count } => {
+                let window = match (window, count) {
+                    (Some(window), None) => RollingWindow::Duration(parse_duration(window)?),
+                    (None, Some(count)) => RollingWindow::Count(*count),
+                    _ => {
+                        return Err(MetricQueryError::OperationFailed {
+                            operation: "rolling".to_string(),
+                            reason: "Specify exactly one of 'window' or 'count' for the rolling window".to_string(),
+                        })
+                    }
+                };
+                Box::new(RollingReduceTransformation::new(RollingReduction::parse(how)?, window)?)
+            }
+            Self::DetectGaps { max_gap_seconds, on_gap, now } => {
+                let step = GapDetectionTransformation::new(*max_gap_seconds, GapAction::parse(on_gap)?)?;
+                Box::new(match now {
+                    Some(now) => step.with_heartbeat(*now),
+                    None => step,
+                })
+            }
+            Self::CounterIncrease { cumulative } => {
+                Box::new(CounterIncreaseTransformation::new().cumulative(*cumulative))
+            }
+            Self::TimestampUnit { unit } => {
+                Box::new(TimestampUnitTransformation::new(TimestampPrecision::parse(unit)?))
+            }
+            Self::Acceleration { per_seconds } => {
+                Box::new(DerivativeTransformation::new(2)?.with_time_unit(*per_seconds)?)
+            }
+            Self::Normalize { method, per_label } => {
+                Box::new(NormalizeTransformation::new(NormalizeMethod::parse(method)?).per_label(*per_label))
+            }
+            Self::AssertThat { predicate, message, min, max } => {
+                let step = AssertionTransformation::new(Assertion::parse(predicate, *min, *max)?);
+                Box::new(match message {
+                    Some(message) => step.with_message(message.clone()),
+                    None => step,
+                })
+            }
+            Self::Latest => Box::new(LatestTransformation::new()),
+            Self::Resample { interval, how, fill } => Box::new(ResampleTransformation::new(
+                *interval,
+                create_aggregation(resample_aggregation_name(how))?,
+                ResampleFill::parse(fill)?,
+            )),
+            Self::ConvertUnit { from, to, assume_unit } => {
+                Box::new(ConvertUnitTransformation::new(from.as_str(), to.as_str())?.with_assume_unit(*assume_unit))
+            }
+            Self::Rescale { scale, rounding } => {
+                Box::new(RescaleTransformation::new(*scale, RoundingMode::parse(rounding)?)?)
+            }
+            Self::ConvertTimezone { tz, direction } => Box::new(TimezoneShiftTransformation::new(
+                parse_timezone(tz)?,
+                TimezoneDirection::parse(direction)?,
+            )),
+            Self::TopkSeries { k, agg } => Box::new(TopKSeriesTransformation::new(*k, create_aggregation(agg)?)?),
+            Self::SampleEvery { n } => Box::new(SampleEveryTransformation::new(*n)?),
+            Self::SampleFraction { p, seed } => Box::new(SampleFractionTransformation::new(*p, *seed)?),
+            Self::Decompose { period } => Box::new(SeasonalDecompositionTransformation::new(*period)?),
+            Self::Forecast { horizon, method, period, alpha, beta, gamma } => {
+                let mut step = ForecastTransformation::new(ForecastMethod::parse(method)?, *horizon)
+                    .with_params(SmoothingParams { alpha: *alpha, beta: *beta, gamma: *gamma })?;
+                if let Some(period) = period {
+                    step = step.with_period(*period)?;
+                }
+                Box::new(step.checked()?)
+            }
+        };
+        Ok(vec![strategy])
+    }
+}
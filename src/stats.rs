@@ -0,0 +1,187 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::time::Instant;
+
+use crate::accuracy::Accuracy;
+
+/// Row counts and timing for one pipeline step
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepStats {
+    /// Position of the step in the pipeline
+    pub index: usize,
+    /// Name of the step
+    pub name: String,
+    /// Metrics the step received
+    pub input_count: usize,
+    /// Metrics the step produced
+    pub output_count: usize,
+    /// Metrics the step removed or collapsed (input minus output, never negative)
+    pub dropped: usize,
+    /// Time spent in the step
+    pub elapsed_seconds: f64,
+    /// "exact" or "approximate" for aggregations that offer the choice, as resolved for this run
+    pub accuracy: Option<String>,
+}
+
+/// Execution metadata for the most recent successful pipeline run
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunStats {
+    /// Metrics fed into the first step
+    pub input_count: usize,
+    /// Metrics returned by the last step
+    pub output_count: usize,
+    /// Total metrics removed or collapsed across all steps
+    pub dropped: usize,
+    /// Wall time of the whole run
+    pub wall_time_seconds: f64,
+    /// Per-step counts, in pipeline order
+    pub steps: Vec<StepStats>,
+}
+
+/// Collects step statistics while a pipeline runs.
+///
+/// With the `tracing` feature, the run is also a `pipeline_run` span with one
+/// `pipeline_step` child span per step, carrying the same counts and durations.
+pub(crate) struct StatsRecorder {
+    started: Instant,
+    input_count: usize,
+    steps: Vec<StepStats>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+/// A step in progress, from `StatsRecorder::begin_step` until `record_step`
+pub(crate) struct StepTimer {
+    started: Instant,
+    #[cfg(feature = "tracing")]
+    span: tracing::span::EnteredSpan,
+}
+
+impl StatsRecorder {
+    /// Start timing a run over `input_count` metrics
+    pub(crate) fn start(input_count: usize) -> Self {
+        Self {
+            started: Instant::now(),
+            input_count,
+            steps: Vec::new(),
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                "pipeline_run",
+                input_count,
+                output_count = tracing::field::Empty,
+                dropped = tracing::field::Empty,
+                wall_time_seconds = tracing::field::Empty,
+            ),
+        }
+    }
+
+    /// Start timing step `index`
+    #[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+    pub(crate) fn begin_step(&self, index: usize, name: &str, input_count: usize) -> StepTimer {
+        StepTimer {
+            started: Instant::now(),
+            #[cfg(feature = "tracing")]
+            span: tracing::info_span!(
+                parent: &self.span,
+                "pipeline_step",
+                index,
+                name,
+                input_count,
+                output_count = tracing::field::Empty,
+                dropped = tracing::field::Empty,
+                elapsed_seconds = tracing::field::Empty,
+            )
+            .entered(),
+        }
+    }
+
+    /// Record a finished step
+    pub(crate) fn record_step(
+        &mut self,
+        index: usize,
+        name: String,
+        input_count: usize,
+        output_count: usize,
+        accuracy: Option<Accuracy>,
+        timer: StepTimer,
+    ) {
+        let step = StepStats {
+            index,
+            name,
+            input_count,
+            output_count,
+            dropped: input_count.saturating_sub(output_count),
+            elapsed_seconds: timer.started.elapsed().as_secs_f64(),
+            accuracy: accuracy.map(|accuracy| accuracy.as_str().to_string()),
+        };
+        log::debug!(
+            "step {} ({}): {} -> {} metrics, {} dropped in {:.6}s",
+            step.index,
+            step.name,
+            step.input_count,
+            step.output_count,
+            step.dropped,
+            step.elapsed_seconds
+        );
+        #[cfg(feature = "tracing")]
+        {
+            timer.span.record("output_count", step.output_count);
+            timer.span.record("dropped", step.dropped);
+            timer.span.record("elapsed_seconds", step.elapsed_seconds);
+        }
+        self.steps.push(step);
+    }
+
+    /// Finish the run with `output_count` result metrics
+    pub(crate) fn finish(self, output_count: usize) -> RunStats {
+        let stats = RunStats {
+            input_count: self.input_count,
+            output_count,
+            dropped: self.steps.iter().map(|step| step.dropped).sum(),
+            wall_time_seconds: self.started.elapsed().as_secs_f64(),
+            steps: self.steps,
+        };
+        #[cfg(feature = "tracing")]
+        {
+            self.span.record("output_count", stats.output_count);
+            self.span.record("dropped", stats.dropped);
+            self.span.record("wall_time_seconds", stats.wall_time_seconds);
+        }
+        stats
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl RunStats {
+    fn __repr__(&self) -> String {
+        format!(
+            "RunStats(input_count={}, output_count={}, dropped={}, wall_time_seconds={:.6})",
+            self.input_count, self.output_count, self.dropped, self.wall_time_seconds
+        )
+    }
+}
+
+/// Print pipeline spans to stderr as they close, at `level` or more severe
+/// ("trace", "debug", "info", "warn" or "error"). Only the first call installs
+/// the subscriber; later calls, or a subscriber installed elsewhere, raise.
+#[cfg(all(feature = "python", feature = "tracing"))]
+#[pyfunction]
+#[pyo3(name = "init_tracing", signature = (level="info"))]
+pub fn py_init_tracing(level: &str) -> PyResult<()> {
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    let level: tracing::Level = level
+        .parse()
+        .map_err(|_| pyo3::exceptions::PyValueError::new_err(format!("Unknown tracing level: {}", level)))?;
+    tracing_subscriber::fmt()
+        .with_max_level(level)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(std::io::stderr)
+        .try_init()
+        .map_err(|e| pyo3::exceptions::PyRuntimeError::new_err(e.to_string()))
+}
@@ -0,0 +1,219 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// Label given to anomalous metrics; labeled inputs become "<label>:anomaly"
+pub const ANOMALY_LABEL: &str = "anomaly";
+
+/// Scale factor that makes the MAD a consistent estimator of the standard deviation
+const MAD_SCALE: f64 = 1.4826;
+
+/// How deviation from the baseline is scored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyMethod {
+    /// Distance from the mean in standard deviations
+    ZScore,
+    /// Distance from the median in scaled median absolute deviations
+    Mad,
+}
+
+impl AnomalyMethod {
+    /// Parse a method name ("zscore" or "mad")
+    pub fn parse(method: &str) -> MetricQueryResult<Self> {
+        match method {
+            "zscore" => Ok(Self::ZScore),
+            "mad" => Ok(Self::Mad),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "detect_anomalies".to_string(),
+                reason: format!("Unknown anomaly method: {}. Expected 'zscore' or 'mad'", method),
+            }),
+        }
+    }
+
+    /// Score `value` against a baseline of at least two points
+    fn score(self, value: f64, baseline: &[f64]) -> f64 {
+        let (center, spread) = match self {
+            Self::ZScore => {
+                let n = baseline.len() as f64;
+                let mean = baseline.iter().sum::<f64>() / n;
+                let variance = baseline.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+                (mean, variance.sqrt())
+            }
+            Self::Mad => {
+                let center = median(baseline.to_vec());
+                let deviations = baseline.iter().map(|v| (v - center).abs()).collect();
+                (center, MAD_SCALE * median(deviations))
+            }
+        };
+
+        let distance = (value - center).abs();
+        if spread == 0.0 {
+            // A flat baseline makes any deviation infinitely unlikely
+            return if distance == 0.0 { 0.0 } else { f64::INFINITY };
+        }
+        distance / spread
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// What the detection step returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyOutput {
+    /// Return every metric, relabeling the anomalous ones
+    Flag,
+    /// Return only the anomalous metrics
+    Extract,
+}
+
+impl AnomalyOutput {
+    /// Parse an output mode ("flag" or "extract")
+    pub fn parse(mode: &str) -> MetricQueryResult<Self> {
+        match mode {
+            "flag" => Ok(Self::Flag),
+            "extract" => Ok(Self::Extract),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "detect_anomalies".to_string(),
+                reason: format!("Unknown anomaly output mode: {}. Expected 'flag' or 'extract'", mode),
+            }),
+        }
+    }
+}
+
+/// Flags metrics that deviate strongly from their baseline.
+///
+/// The baseline of each metric is the `window` metrics before it in input order, or
+/// the whole series when no window is set. Metrics with fewer than two baseline points
+/// are never flagged. NaN values are ignored when building baselines and never flagged.
+#[derive(Clone)]
+pub struct AnomalyDetectionTransformation {
+    method: AnomalyMethod,
+    threshold: f64,
+    window: Option<usize>,
+    output: AnomalyOutput,
+}
+
+impl AnomalyDetectionTransformation {
+    /// Create a new anomaly detection step scoring against the whole series, failing
+    /// unless `threshold` is positive
+    pub fn new(method: AnomalyMethod, threshold: f64) -> MetricQueryResult<Self> {
+        if threshold.is_nan() || threshold <= 0.0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "detect_anomalies".to_string(),
+                reason: format!("Threshold must be positive, got {}", threshold),
+            });
+        }
+        Ok(Self { method, threshold, window: None, output: AnomalyOutput::Flag })
+    }
+
+    /// Score each metric against the preceding `window` metrics only; fails for an
+    /// empty window
+    pub fn with_window(mut self, window: usize) -> MetricQueryResult<Self> {
+        if window == 0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "detect_anomalies".to_string(),
+                reason: "Window must be at least 1".to_string(),
+            });
+        }
+        self.window = Some(window);
+        Ok(self)
+    }
+
+    /// Choose whether all metrics or only the anomalies are returned
+    pub fn with_output(mut self, output: AnomalyOutput) -> Self {
+        self.output = output;
+        self
+    }
+
+    /// Positions of the anomalous values
+    fn detect(&self, values: &[f64]) -> Vec<bool> {
+        let whole: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+
+        values
+            .iter()
+            .enumerate()
+            .map(|(index, &value)| {
+                if value.is_nan() {
+                    return false;
+                }
+                let score = match self.window {
+                    Some(window) => {
+                        let start = index.saturating_sub(window);
+                        let baseline: Vec<f64> =
+                            values[start..index].iter().copied().filter(|v| !v.is_nan()).collect();
+                        if baseline.len() < 2 {
+                            return false;
+                        }
+                        self.method.score(value, &baseline)
+                    }
+                    None if whole.len() < 2 => return false,
+                    None => self.method.score(value, &whole),
+                };
+                score > self.threshold
+            })
+            .collect()
+    }
+}
+
+fn anomaly_label(label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("{}:{}", label, ANOMALY_LABEL),
+        None => ANOMALY_LABEL.to_string(),
+    }
+}
+
+impl TransformationStrategy for AnomalyDetectionTransformation {
+    fn name(&self) -> String {
+        "detect_anomalies".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let values: Vec<f64> = metrics.iter().map(|m| m.value as f64).collect();
+        let flags = self.detect(&values);
+
+        Ok(metrics
+            .iter()
+            .zip(flags)
+            .filter(|(_, anomalous)| *anomalous || self.output == AnomalyOutput::Flag)
+            .map(|(metric, anomalous)| Metric {
+                value: metric.value,
+                timestamp: metric.timestamp,
+                label: if anomalous { Some(anomaly_label(metric.label.as_deref())) } else { metric.label.clone() },
+                exemplar: metric.exemplar.clone(),
+                fields: metric.fields.clone(),
+                unit: metric.unit.clone(),
+                metric_type: metric.metric_type,
+                scale: metric.scale,
+                source: metric.source.clone(),
+                description: metric.description.clone(),
+                stale: metric.stale,
+                tags: metric.tags.clone(),
+            })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let values: Vec<f64> = metrics.iter().map(|m| m.value).collect();
+        let flags = self.detect(&values);
+
+        Ok(metrics
+            .iter()
+            .zip(flags)
+            .filter(|(_, anomalous)| *anomalous || self.output == AnomalyOutput::Flag)
+            .map(|(metric, anomalous)| FloatMetric {
+                value: metric.value,
+                timestamp: metric.timestamp,
+                label: if anomalous { Some(anomaly_label(metric.label.as_deref())) } else { metric.label.clone() },
+                unit: metric.unit.clone(),
+            })
+            .collect())
+    }
+}
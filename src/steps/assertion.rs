@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::steps::TapBatch;
+use crate::transformations::TransformationStrategy;
+
+/// Custom predicate for `Assertion::Custom`; `Ok(false)` fails the assertion
+pub type AssertionPredicate = Arc<dyn Fn(TapBatch<'_>) -> MetricQueryResult<bool> + Send + Sync>;
+
+/// A data-quality condition the stream must satisfy
+#[derive(Clone)]
+pub enum Assertion {
+    /// At least one metric
+    NonEmpty,
+    /// Timestamps never decrease
+    Sorted,
+    /// Every value lies within `min..=max` (missing values fail)
+    WithinRange { min: f64, max: f64 },
+    /// No missing (NaN) values
+    NoMissing,
+    /// A caller-supplied predicate over the whole stream
+    Custom(AssertionPredicate),
+}
+
+impl Assertion {
+    /// Parse a built-in assertion: "non_empty", "sorted", "no_missing" or
+    /// "within_range" (which needs at least one of `min` and `max`)
+    pub fn parse(name: &str, min: Option<f64>, max: Option<f64>) -> MetricQueryResult<Self> {
+        match name {
+            "non_empty" => Ok(Self::NonEmpty),
+            "sorted" => Ok(Self::Sorted),
+            "no_missing" => Ok(Self::NoMissing),
+            "within_range" if min.is_some() || max.is_some() => Ok(Self::WithinRange {
+                min: min.unwrap_or(f64::NEG_INFINITY),
+                max: max.unwrap_or(f64::INFINITY),
+            }),
+            "within_range" => Err(MetricQueryError::OperationFailed {
+                operation: "assert_that".to_string(),
+                reason: "'within_range' needs a min or max bound".to_string(),
+            }),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "assert_that".to_string(),
+                reason: format!(
+                    "Unknown assertion: {}. Expected 'non_empty', 'sorted', 'within_range' or 'no_missing'",
+                    name
+                ),
+            }),
+        }
+    }
+
+    /// Default failure message
+    fn describe(&self) -> String {
+        match self {
+            Self::NonEmpty => "stream is empty".to_string(),
+            Self::Sorted => "timestamps are not sorted".to_string(),
+            Self::WithinRange { min, max } => format!("value outside [{}, {}]", min, max),
+            Self::NoMissing => "stream has missing values".to_string(),
+            Self::Custom(_) => "custom predicate returned false".to_string(),
+        }
+    }
+
+    /// Index of the first offending metric, `Ok(None)` when the assertion holds.
+    /// Whole-stream failures (empty stream, custom predicate) report index 0.
+    fn first_violation(&self, batch: TapBatch<'_>, points: &[(i64, f64)]) -> MetricQueryResult<Option<usize>> {
+        Ok(match self {
+            Self::NonEmpty => points.is_empty().then_some(0),
+            Self::Sorted => points.windows(2).position(|pair| pair[1].0 < pair[0].0).map(|i| i + 1),
+            Self::WithinRange { min, max } => points.iter().position(|&(_, v)| !(*min..=*max).contains(&v)),
+            Self::NoMissing => points.iter().position(|&(_, v)| v.is_nan()),
+            Self::Custom(predicate) => (!predicate(batch)?).then_some(0),
+        })
+    }
+}
+
+/// Fails the pipeline when the stream violates an assertion; otherwise forwards it unchanged
+#[derive(Clone)]
+pub struct AssertionTransformation {
+    assertion: Assertion,
+    message: Option<String>,
+    thread_bound: bool,
+}
+
+impl AssertionTransformation {
+    /// Create an assertion step with the default failure message
+    pub fn new(assertion: Assertion) -> Self {
+        Self { assertion, message: None, thread_bound: false }
+    }
+
+    /// Replace the failure message
+    pub fn with_message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+
+    /// Keep the step on the calling thread, for custom predicates that call into Python
+    pub fn with_thread_bound(mut self, thread_bound: bool) -> Self {
+        self.thread_bound = thread_bound;
+        self
+    }
+
+    fn check(&self, batch: TapBatch<'_>, points: &[(i64, f64)]) -> MetricQueryResult<()> {
+        match self.assertion.first_violation(batch, points)? {
+            None => Ok(()),
+            Some(index) => Err(MetricQueryError::AssertionFailed {
+                index,
+                message: self.message.clone().unwrap_or_else(|| self.assertion.describe()),
+            }),
+        }
+    }
+}
+
+impl TransformationStrategy for AssertionTransformation {
+    fn name(&self) -> String {
+        "assert_that".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let points: Vec<(i64, f64)> = metrics.iter().map(|m| (m.timestamp, m.value as f64)).collect();
+        self.check(TapBatch::Metrics(metrics), &points)?;
+        Ok(metrics.to_vec())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points: Vec<(i64, f64)> = metrics.iter().map(|m| (m.timestamp, m.value)).collect();
+        self.check(TapBatch::Floats(metrics), &points)?;
+        Ok(metrics.to_vec())
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.thread_bound
+    }
+}
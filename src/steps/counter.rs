@@ -0,0 +1,130 @@
+use std::collections::BTreeMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric, MetricType};
+use crate::transformations::TransformationStrategy;
+use crate::warnings::WarningSink;
+
+/// Reset-aware increases of counter series, like Prometheus' `increase`.
+///
+/// Each label is walked in timestamp order. A drop in value means the process
+/// restarted and counted from zero again, so the increase across it is the value
+/// after the reset rather than a negative delta. By default every sample but the
+/// first of each series becomes the increase since the previous sample, typed as a
+/// gauge. With `cumulative`, every sample becomes the total increase since the start
+/// of its series: a monotonic counter that starts at zero and survives restarts.
+#[derive(Clone, Default)]
+pub struct CounterIncreaseTransformation {
+    cumulative: bool,
+}
+
+impl CounterIncreaseTransformation {
+    /// Create a step emitting per-sample increases
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emit running totals since the start of each series instead of per-sample increases
+    pub fn cumulative(mut self, cumulative: bool) -> Self {
+        self.cumulative = cumulative;
+        self
+    }
+
+    /// Positions of each label's metrics, in timestamp order
+    fn series<'a>(keys: impl Iterator<Item = (i64, Option<&'a str>)>) -> Vec<Vec<usize>> {
+        let mut series: BTreeMap<Option<&str>, Vec<(i64, usize)>> = BTreeMap::new();
+        for (index, (timestamp, label)) in keys.enumerate() {
+            series.entry(label).or_default().push((timestamp, index));
+        }
+        series
+            .into_values()
+            .map(|mut points| {
+                // Stable, so samples sharing a timestamp keep their input order
+                points.sort_by_key(|&(timestamp, _)| timestamp);
+                points.into_iter().map(|(_, index)| index).collect()
+            })
+            .collect()
+    }
+
+    /// (position, new value) of every output point and the number of resets found,
+    /// ordered by timestamp
+    fn increases<'a, T: Copy + PartialOrd + Default>(
+        &self,
+        keys: impl Iterator<Item = (i64, Option<&'a str>)>,
+        timestamps: impl Fn(usize) -> i64,
+        value: impl Fn(usize) -> T,
+        add: impl Fn(T, T) -> MetricQueryResult<T>,
+        sub: impl Fn(T, T) -> MetricQueryResult<T>,
+    ) -> MetricQueryResult<(Vec<(usize, T)>, usize)> {
+        let mut result = Vec::new();
+        let mut resets = 0;
+        for indices in Self::series(keys) {
+            let mut total = T::default();
+            if self.cumulative {
+                if let Some(&first) = indices.first() {
+                    result.push((first, total));
+                }
+            }
+            for pair in indices.windows(2) {
+                let (previous, current) = (value(pair[0]), value(pair[1]));
+                let increase = if current < previous {
+                    resets += 1;
+                    current
+                } else {
+                    sub(current, previous)?
+                };
+                if self.cumulative {
+                    total = add(total, increase)?;
+                    result.push((pair[1], total));
+                } else {
+                    result.push((pair[1], increase));
+                }
+            }
+        }
+        result.sort_by_key(|&(index, _)| timestamps(index));
+        Ok((result, resets))
+    }
+}
+
+fn overflow() -> MetricQueryError {
+    MetricQueryError::ArithmeticOverflow { operation: "counter_increase".to_string() }
+}
+
+impl TransformationStrategy for CounterIncreaseTransformation {
+    fn name(&self) -> String {
+        if self.cumulative { "counter_increase(cumulative)" } else { "counter_increase" }.to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        self.apply_with_warnings(metrics, &mut WarningSink::new())
+    }
+
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        let (points, resets) = self.increases(
+            metrics.iter().map(|m| (m.timestamp, m.label.as_deref())),
+            |index| metrics[index].timestamp,
+            |index| metrics[index].value,
+            |a, b| a.checked_add(b).ok_or_else(overflow),
+            |a, b| a.checked_sub(b).ok_or_else(overflow),
+        )?;
+        if resets > 0 {
+            warnings.warn("counter_reset", format!("treated {} counter decrease(s) as resets to zero", resets));
+        }
+        let metric_type = if self.cumulative { MetricType::Counter } else { MetricType::Gauge };
+        Ok(points
+            .into_iter()
+            .map(|(index, value)| Metric { value, metric_type: Some(metric_type), ..metrics[index].clone() })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let (points, _) = self.increases(
+            metrics.iter().map(|m| (m.timestamp, m.label.as_deref())),
+            |index| metrics[index].timestamp,
+            |index| metrics[index].value,
+            |a, b| Ok(a + b),
+            |a, b| Ok(a - b),
+        )?;
+        Ok(points.into_iter().map(|(index, value)| FloatMetric { value, ..metrics[index].clone() }).collect())
+    }
+}
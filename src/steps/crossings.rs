@@ -0,0 +1,130 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// Which threshold crossings are reported
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrossingDirection {
+    /// From below the threshold to at or above it
+    Up,
+    /// From at or above the threshold to below it
+    Down,
+    /// Both directions
+    Both,
+}
+
+impl CrossingDirection {
+    /// Parse a direction name ("up", "down" or "both")
+    pub fn parse(direction: &str) -> MetricQueryResult<Self> {
+        match direction {
+            "up" => Ok(Self::Up),
+            "down" => Ok(Self::Down),
+            "both" => Ok(Self::Both),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "crossings".to_string(),
+                reason: format!("Unknown crossing direction: {}. Expected 'up', 'down' or 'both'", direction),
+            }),
+        }
+    }
+}
+
+/// Emits the first metric on the far side of the threshold at every crossing.
+///
+/// The series is walked in timestamp order and a value counts as "above" when it is
+/// at or above the threshold. Emitted metrics keep their value and timestamp and are
+/// labeled "crossing_up" or "crossing_down". Missing float values are skipped.
+#[derive(Clone)]
+pub struct CrossingTransformation {
+    threshold: f64,
+    direction: CrossingDirection,
+}
+
+impl CrossingTransformation {
+    /// Create a new crossing detection step
+    pub fn new(threshold: f64, direction: CrossingDirection) -> Self {
+        Self { threshold, direction }
+    }
+
+    /// Indices of crossing points in `values` (visited in `order`) and whether each was upward
+    fn detect(&self, values: &[f64], order: &[usize]) -> Vec<(usize, bool)> {
+        let mut crossings = Vec::new();
+        let mut above: Option<bool> = None;
+
+        for &index in order {
+            let value = values[index];
+            if value.is_nan() {
+                continue;
+            }
+            let now_above = value >= self.threshold;
+            if let Some(was_above) = above {
+                let reported = match self.direction {
+                    CrossingDirection::Up => now_above && !was_above,
+                    CrossingDirection::Down => !now_above && was_above,
+                    CrossingDirection::Both => now_above != was_above,
+                };
+                if reported {
+                    crossings.push((index, now_above));
+                }
+            }
+            above = Some(now_above);
+        }
+        crossings
+    }
+}
+
+fn timestamp_order(timestamps: impl Iterator<Item = i64>) -> Vec<usize> {
+    let timestamps: Vec<i64> = timestamps.collect();
+    let mut order: Vec<usize> = (0..timestamps.len()).collect();
+    order.sort_by_key(|&index| timestamps[index]);
+    order
+}
+
+fn crossing_label(upward: bool) -> Option<String> {
+    Some(if upward { "crossing_up" } else { "crossing_down" }.to_string())
+}
+
+impl TransformationStrategy for CrossingTransformation {
+    fn name(&self) -> String {
+        "crossings".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let values: Vec<f64> = metrics.iter().map(|m| m.value as f64).collect();
+        let order = timestamp_order(metrics.iter().map(|m| m.timestamp));
+
+        Ok(self
+            .detect(&values, &order)
+            .into_iter()
+            .map(|(index, upward)| Metric {
+                value: metrics[index].value,
+                timestamp: metrics[index].timestamp,
+                label: crossing_label(upward),
+                exemplar: metrics[index].exemplar.clone(),
+                fields: metrics[index].fields.clone(),
+                unit: metrics[index].unit.clone(),
+                metric_type: metrics[index].metric_type,
+                scale: metrics[index].scale,
+                source: metrics[index].source.clone(),
+                description: metrics[index].description.clone(),
+                stale: metrics[index].stale,
+                tags: metrics[index].tags.clone(),
+            })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let values: Vec<f64> = metrics.iter().map(|m| m.value).collect();
+        let order = timestamp_order(metrics.iter().map(|m| m.timestamp));
+
+        Ok(self
+            .detect(&values, &order)
+            .into_iter()
+            .map(|(index, upward)| FloatMetric {
+                value: metrics[index].value,
+                timestamp: metrics[index].timestamp,
+                label: crossing_label(upward),
+                unit: metrics[index].unit.clone(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,123 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::steps::series::{to_metrics, Series};
+use crate::transformations::TransformationStrategy;
+
+/// Labels of the three component series, in output order
+pub const DECOMPOSITION_LABELS: [&str; 3] = ["trend", "seasonal", "residual"];
+
+/// Splits a regularly spaced series into trend, seasonal and residual components.
+///
+/// The trend is a centered moving average over one period (a 2×period average for even
+/// periods), carried flat to the ends of the series where the window doesn't fit. The
+/// seasonal component is the mean detrended value at each phase of the period, centered
+/// on zero, and the residual is what's left. The output holds the three component series
+/// one after the other, labeled "trend", "seasonal" and "residual".
+#[derive(Clone)]
+pub struct SeasonalDecompositionTransformation {
+    period: usize,
+}
+
+impl SeasonalDecompositionTransformation {
+    /// Create a new decomposition step for a season of `period` points, at least 2
+    pub fn new(period: usize) -> MetricQueryResult<Self> {
+        let step = Self { period };
+        if period < 2 {
+            return Err(step.failure(format!("Period must be at least 2, got {}", period)));
+        }
+        Ok(step)
+    }
+
+    fn decompose(&self, series: &Series) -> MetricQueryResult<Vec<FloatMetric>> {
+        let period = self.period;
+        if series.len() < 2 * period {
+            return Err(self.failure(format!(
+                "Need at least two full periods ({} metrics), got {}",
+                2 * period,
+                series.len()
+            )));
+        }
+        if series.values.iter().any(|v| v.is_nan()) {
+            return Err(self.failure("Series contains missing values; fill them first".to_string()));
+        }
+        series.regular_step("decompose")?;
+
+        let values = &series.values;
+        let trend = centered_moving_average(values, period);
+
+        let mut phase_sums = vec![0.0; period];
+        let mut phase_counts = vec![0usize; period];
+        // Only points with a full moving-average window inform the season
+        let half = period / 2;
+        for index in half..values.len() - half {
+            phase_sums[index % period] += values[index] - trend[index];
+            phase_counts[index % period] += 1;
+        }
+        let phase_means: Vec<f64> = phase_sums
+            .iter()
+            .zip(&phase_counts)
+            .map(|(sum, &count)| sum / count as f64)
+            .collect();
+        let offset = phase_means.iter().sum::<f64>() / period as f64;
+
+        let seasonal: Vec<f64> = (0..values.len()).map(|index| phase_means[index % period] - offset).collect();
+        let residual: Vec<f64> = values
+            .iter()
+            .zip(&trend)
+            .zip(&seasonal)
+            .map(|((value, trend), seasonal)| value - trend - seasonal)
+            .collect();
+
+        let mut result = Vec::with_capacity(values.len() * 3);
+        for (component, label) in [trend, seasonal, residual].iter().zip(DECOMPOSITION_LABELS) {
+            result.extend(component.iter().zip(&series.timestamps).map(|(&value, &timestamp)| FloatMetric {
+                value,
+                timestamp,
+                label: Some(label.to_string()),
+                unit: None,
+            }));
+        }
+        Ok(result)
+    }
+
+    fn failure(&self, reason: String) -> MetricQueryError {
+        MetricQueryError::OperationFailed { operation: "decompose".to_string(), reason }
+    }
+}
+
+/// Centered moving average over one period, extended flat to both ends
+fn centered_moving_average(values: &[f64], period: usize) -> Vec<f64> {
+    let half = period / 2;
+    let last = values.len() - half;
+
+    let mut trend = vec![0.0; values.len()];
+    for index in half..last {
+        let window = &values[index - half..=index + half];
+        trend[index] = if !period.is_multiple_of(2) {
+            window.iter().sum::<f64>() / period as f64
+        } else {
+            // Even periods need 2×MA so the window stays centered: end points count half
+            let inner: f64 = window[1..period].iter().sum();
+            (inner + (window[0] + window[period]) / 2.0) / period as f64
+        };
+    }
+
+    let (first_defined, last_defined) = (trend[half], trend[last - 1]);
+    trend[..half].fill(first_defined);
+    trend[last..].fill(last_defined);
+    trend
+}
+
+impl TransformationStrategy for SeasonalDecompositionTransformation {
+    fn name(&self) -> String {
+        "decompose".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        Ok(to_metrics(self.decompose(&Series::from_metrics(metrics))?))
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        self.decompose(&Series::from_float_metrics(metrics))
+    }
+}
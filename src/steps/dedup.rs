@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{Exemplar, FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+use crate::warnings::WarningSink;
+
+/// How metrics sharing a timestamp and label are collapsed into one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateStrategy {
+    /// Keep the first metric in input order
+    KeepFirst,
+    /// Keep the last metric in input order
+    KeepLast,
+    /// Replace the duplicates with the sum of their values
+    Sum,
+    /// Replace the duplicates with the (truncated) mean of their values
+    Average,
+    /// Fail on the first duplicate
+    Error,
+}
+
+impl DuplicateStrategy {
+    /// Parse a strategy name ("keep_first", "keep_last", "sum", "average" or "error")
+    pub fn parse(strategy: &str) -> MetricQueryResult<Self> {
+        match strategy {
+            "keep_first" => Ok(Self::KeepFirst),
+            "keep_last" => Ok(Self::KeepLast),
+            "sum" => Ok(Self::Sum),
+            "average" => Ok(Self::Average),
+            "error" => Ok(Self::Error),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "deduplicate".to_string(),
+                reason: format!(
+                    "Unknown duplicate strategy: {}. Expected one of: keep_first, keep_last, sum, average, error",
+                    strategy
+                ),
+            }),
+        }
+    }
+}
+
+/// Collapses metrics that share both timestamp and label.
+///
+/// Output keeps the position of the first occurrence of each (timestamp, label) key.
+#[derive(Clone)]
+pub struct DeduplicateTransformation {
+    strategy: DuplicateStrategy,
+}
+
+impl DeduplicateTransformation {
+    /// Create a new deduplication step
+    pub fn new(strategy: DuplicateStrategy) -> Self {
+        Self { strategy }
+    }
+
+    /// Group input positions by (timestamp, label), in order of first occurrence
+    fn duplicate_groups<'a, I>(&self, keys: I) -> MetricQueryResult<Vec<Vec<usize>>>
+    where
+        I: Iterator<Item = (i64, Option<&'a str>)>,
+    {
+        let mut positions: HashMap<(i64, Option<&str>), usize> = HashMap::new();
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+
+        for (index, (timestamp, label)) in keys.enumerate() {
+            match positions.get(&(timestamp, label)) {
+                Some(&group) => {
+                    if self.strategy == DuplicateStrategy::Error {
+                        return Err(MetricQueryError::DuplicateTimestamp {
+                            index,
+                            timestamp,
+                            label: label.map(str::to_string),
+                        });
+                    }
+                    groups[group].push(index);
+                }
+                None => {
+                    positions.insert((timestamp, label), groups.len());
+                    groups.push(vec![index]);
+                }
+            }
+        }
+
+        Ok(groups)
+    }
+}
+
+impl TransformationStrategy for DeduplicateTransformation {
+    fn name(&self) -> String {
+        "deduplicate".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let groups = self.duplicate_groups(metrics.iter().map(|m| (m.timestamp, m.label.as_deref())))?;
+
+        groups
+            .into_iter()
+            .map(|group| {
+                let first = &metrics[group[0]];
+                let value = match self.strategy {
+                    DuplicateStrategy::KeepFirst | DuplicateStrategy::Error => first.value,
+                    DuplicateStrategy::KeepLast => metrics[group[group.len() - 1]].value,
+                    DuplicateStrategy::Sum => {
+                        let mut total: i64 = 0;
+                        for &index in &group {
+                            total = total.checked_add(metrics[index].value).ok_or_else(|| {
+                                MetricQueryError::ArithmeticOverflow { operation: "deduplicate".to_string() }
+                            })?;
+                        }
+                        total
+                    }
+                    DuplicateStrategy::Average => {
+                        let total: i128 = group.iter().map(|&index| i128::from(metrics[index].value)).sum();
+                        (total / group.len() as i128) as i64
+                    }
+                };
+                let exemplar = Exemplar::worst(group.iter().map(|&index| metrics[index].exemplar.as_ref()));
+                Ok(Metric {
+                    exemplar: exemplar.cloned(),
+                    unit: first.unit.clone(),
+                    ..Metric::new(value, first.timestamp, first.label.clone())
+                })
+            })
+            .collect()
+    }
+
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        let result = self.apply(metrics)?;
+        let collapsed = metrics.len() - result.len();
+        if collapsed > 0 {
+            warnings.warn(
+                "dropped_duplicates",
+                format!("collapsed {} duplicate metric(s)", collapsed),
+            );
+        }
+        Ok(result)
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let groups = self.duplicate_groups(metrics.iter().map(|m| (m.timestamp, m.label.as_deref())))?;
+
+        Ok(groups
+            .into_iter()
+            .map(|group| {
+                let first = &metrics[group[0]];
+                let value = match self.strategy {
+                    DuplicateStrategy::KeepFirst | DuplicateStrategy::Error => first.value,
+                    DuplicateStrategy::KeepLast => metrics[group[group.len() - 1]].value,
+                    DuplicateStrategy::Sum => group.iter().map(|&index| metrics[index].value).sum(),
+                    DuplicateStrategy::Average => {
+                        group.iter().map(|&index| metrics[index].value).sum::<f64>() / group.len() as f64
+                    }
+                };
+                FloatMetric { value, timestamp: first.timestamp, label: first.label.clone(), unit: first.unit.clone() }
+            })
+            .collect())
+    }
+}
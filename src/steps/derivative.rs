@@ -0,0 +1,155 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::decimal::align_scales;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric, MetricType};
+use crate::steps::series::{seconds_between, SeriesKey, NO_TAGS};
+use crate::transformations::TransformationStrategy;
+use crate::warnings::WarningSink;
+
+/// Rate of change (order 1) or change of the rate (order 2) over time.
+///
+/// Each label and tag set is differenced independently in timestamp order, scaled to
+/// change per `per_seconds` seconds, and stamped at the later point of each difference,
+/// so every order drops the first point of each series. Timestamps must be unique per
+/// series. Fixed-point inputs are brought to their largest scale, which the rates keep.
+///
+/// In a counter series a drop in value is a reset to zero, so the change across it is
+/// the value after the reset rather than a negative rate; the result is a gauge.
+#[derive(Clone)]
+pub struct DerivativeTransformation {
+    order: usize,
+    per_seconds: i64,
+}
+
+/// (timestamp, series, value)
+type Point<'a> = (i64, SeriesKey<'a>, f64);
+
+impl DerivativeTransformation {
+    /// Create a new derivative step of order 1 or 2, per second
+    pub fn new(order: usize) -> MetricQueryResult<Self> {
+        if !(1..=2).contains(&order) {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "derivative".to_string(),
+                reason: format!("Derivative order must be 1 or 2, got {}", order),
+            });
+        }
+        Ok(Self { order, per_seconds: 1 })
+    }
+
+    /// Express changes per `per_seconds` seconds (e.g. 60 for "per minute"), failing
+    /// unless it is positive
+    pub fn with_time_unit(mut self, per_seconds: i64) -> MetricQueryResult<Self> {
+        if per_seconds <= 0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "derivative".to_string(),
+                reason: format!("Time unit must be positive, got {}", per_seconds),
+            });
+        }
+        self.per_seconds = per_seconds;
+        Ok(self)
+    }
+
+    /// (timestamp, series, value) of every derivative point, in timestamp order, and the
+    /// number of resets found in the `counters` series
+    fn differentiate<'a>(
+        &self,
+        points: impl Iterator<Item = Point<'a>>,
+        counters: &HashSet<SeriesKey<'a>>,
+    ) -> MetricQueryResult<(Vec<Point<'a>>, usize)> {
+        let mut series: BTreeMap<SeriesKey, Vec<(i64, f64)>> = BTreeMap::new();
+        for (timestamp, key, value) in points {
+            series.entry(key).or_default().push((timestamp, value));
+        }
+
+        let mut result = Vec::new();
+        let mut resets = 0;
+        for (key, mut points) in series {
+            points.sort_by_key(|&(timestamp, _)| timestamp);
+            for order in 0..self.order {
+                // Only the raw samples are cumulative; their rate is an ordinary series
+                let counter = order == 0 && counters.contains(&key);
+                points = self.difference(&points, counter, &mut resets)?;
+            }
+            result.extend(points.into_iter().map(|(timestamp, value)| (timestamp, key, value)));
+        }
+
+        result.sort_by_key(|&(timestamp, _, _)| timestamp);
+        Ok((result, resets))
+    }
+
+    fn difference(&self, points: &[(i64, f64)], counter: bool, resets: &mut usize) -> MetricQueryResult<Vec<(i64, f64)>> {
+        points
+            .windows(2)
+            .map(|pair| {
+                let ((t0, v0), (t1, v1)) = (pair[0], pair[1]);
+                if t1 == t0 {
+                    return Err(MetricQueryError::OperationFailed {
+                        operation: "derivative".to_string(),
+                        reason: format!("Duplicate timestamp {}; deduplicate the series first", t1),
+                    });
+                }
+                let change = if counter && v1 < v0 {
+                    *resets += 1;
+                    v1
+                } else {
+                    v1 - v0
+                };
+                Ok((t1, change / seconds_between(t0, t1) * self.per_seconds as f64))
+            })
+            .collect()
+    }
+}
+
+impl TransformationStrategy for DerivativeTransformation {
+    fn name(&self) -> String {
+        "derivative".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        self.apply_with_warnings(metrics, &mut WarningSink::new())
+    }
+
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        let (metrics, scale) = align_scales(metrics)?;
+        let mut types: HashMap<SeriesKey, MetricType> = HashMap::new();
+        for metric in metrics.iter() {
+            if let Some(metric_type) = metric.metric_type {
+                types.insert((metric.label.as_deref(), &metric.tags), metric_type);
+            }
+        }
+        let counters = types
+            .iter()
+            .filter(|(_, &metric_type)| metric_type == MetricType::Counter)
+            .map(|(&key, _)| key)
+            .collect();
+
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &m.tags), m.value as f64));
+        let (points, resets) = self.differentiate(points, &counters)?;
+        if resets > 0 {
+            warnings.warn("counter_reset", format!("treated {} counter decrease(s) as resets to zero", resets));
+        }
+        Ok(points
+            .into_iter()
+            .map(|(timestamp, key, value)| Metric {
+                metric_type: match types.get(&key) {
+                    Some(MetricType::Counter) => Some(MetricType::Gauge),
+                    metric_type => metric_type.copied(),
+                },
+                tags: key.1.clone(),
+                scale,
+                ..Metric::new(value.round() as i64, timestamp, key.0.map(str::to_string))
+            })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &NO_TAGS), m.value));
+        Ok(self
+            .differentiate(points, &HashSet::new())?
+            .0
+            .into_iter()
+            .map(|(timestamp, (label, _), value)| FloatMetric::new(Some(value), timestamp, label.map(str::to_string)))
+            .collect())
+    }
+}
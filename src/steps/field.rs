@@ -0,0 +1,82 @@
+use crate::errors::MetricQueryResult;
+use crate::models::Metric;
+use crate::plugins::FilterPlugin;
+use crate::transformations::TransformationStrategy;
+use crate::warnings::WarningSink;
+
+/// Makes one of a multi-field metric's named fields its value.
+///
+/// Later steps (aggregations, time groupings, value filters) then operate on that
+/// field. The other fields stay on the metric; metrics without the field are dropped.
+#[derive(Clone)]
+pub struct SelectFieldTransformation {
+    field: String,
+}
+
+impl SelectFieldTransformation {
+    /// Create a step selecting `field`
+    pub fn new(field: impl Into<String>) -> Self {
+        Self { field: field.into() }
+    }
+}
+
+impl TransformationStrategy for SelectFieldTransformation {
+    fn name(&self) -> String {
+        format!("select_field({})", self.field)
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        Ok(metrics
+            .iter()
+            .filter_map(|metric| {
+                let value = metric.field(&self.field)?;
+                Some(Metric { value, ..metric.clone() })
+            })
+            .collect())
+    }
+
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        let result = self.apply(metrics)?;
+        let missing = metrics.len() - result.len();
+        if missing > 0 {
+            warnings.warn(
+                "missing_field",
+                format!("dropped {} metric(s) without field '{}'", missing, self.field),
+            );
+        }
+        Ok(result)
+    }
+}
+
+/// Applies a filter to one named field, keeping the whole metric when it matches.
+///
+/// Metrics without the field never match.
+#[derive(Clone)]
+pub struct FieldFilter {
+    field: String,
+    filter: Box<dyn FilterPlugin>,
+}
+
+impl FieldFilter {
+    /// Wrap `filter` so it reads `field` instead of the metric's value
+    pub fn new(field: impl Into<String>, filter: Box<dyn FilterPlugin>) -> Self {
+        Self { field: field.into(), filter }
+    }
+}
+
+impl FilterPlugin for FieldFilter {
+    fn name(&self) -> &str {
+        self.filter.name()
+    }
+
+    fn apply(&self, metric: &Metric) -> bool {
+        match metric.field(&self.field) {
+            Some(value) => self.filter.apply(&Metric::new(value, metric.timestamp, metric.label.clone())),
+            None => false,
+        }
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.filter.thread_bound()
+    }
+}
@@ -0,0 +1,186 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::steps::series::{to_metrics, Series};
+use crate::transformations::TransformationStrategy;
+
+/// Label given to forecast points
+pub const FORECAST_LABEL: &str = "forecast";
+
+const NO_PERIOD: &str = "Holt-Winters needs a seasonal period";
+
+/// Exponential smoothing model used for forecasting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForecastMethod {
+    /// Additive level, trend and season (triple exponential smoothing)
+    HoltWinters,
+    /// Level and trend only (double exponential smoothing)
+    Holt,
+}
+
+impl ForecastMethod {
+    /// Parse a method name ("holt_winters" or "holt")
+    pub fn parse(method: &str) -> MetricQueryResult<Self> {
+        match method {
+            "holt_winters" => Ok(Self::HoltWinters),
+            "holt" => Ok(Self::Holt),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "forecast".to_string(),
+                reason: format!("Unknown forecast method: {}. Expected 'holt_winters' or 'holt'", method),
+            }),
+        }
+    }
+}
+
+/// Smoothing factors, each in (0, 1]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SmoothingParams {
+    /// Weight of the newest observation in the level
+    pub alpha: f64,
+    /// Weight of the newest level change in the trend
+    pub beta: f64,
+    /// Weight of the newest observation in the seasonal component
+    pub gamma: f64,
+}
+
+impl Default for SmoothingParams {
+    fn default() -> Self {
+        Self { alpha: 0.5, beta: 0.1, gamma: 0.1 }
+    }
+}
+
+/// Fits an exponential smoothing model to a regularly spaced series and emits
+/// `horizon` predicted points after its last timestamp, labeled "forecast".
+#[derive(Clone)]
+pub struct ForecastTransformation {
+    method: ForecastMethod,
+    horizon: usize,
+    period: Option<usize>,
+    params: SmoothingParams,
+}
+
+impl ForecastTransformation {
+    /// Create a new forecast step; Holt-Winters needs a seasonal period, set with `with_period`
+    pub fn new(method: ForecastMethod, horizon: usize) -> Self {
+        Self { method, horizon, period: None, params: SmoothingParams::default() }
+    }
+
+    /// Set the number of points in one season, at least 2
+    pub fn with_period(mut self, period: usize) -> MetricQueryResult<Self> {
+        if period < 2 {
+            return Err(self.failure(format!("Period must be at least 2, got {}", period)));
+        }
+        self.period = Some(period);
+        Ok(self)
+    }
+
+    /// Override the default smoothing factors, each of which must be in (0, 1]
+    pub fn with_params(mut self, params: SmoothingParams) -> MetricQueryResult<Self> {
+        let SmoothingParams { alpha, beta, gamma } = params;
+        for (name, factor) in [("alpha", alpha), ("beta", beta), ("gamma", gamma)] {
+            if !(factor > 0.0 && factor <= 1.0) {
+                return Err(self.failure(format!("Smoothing factor {} must be in (0, 1], got {}", name, factor)));
+            }
+        }
+        self.params = params;
+        Ok(self)
+    }
+
+    /// The step, or an error straight away when Holt-Winters was given no period
+    pub fn checked(self) -> MetricQueryResult<Self> {
+        match (self.method, self.period) {
+            (ForecastMethod::HoltWinters, None) => Err(self.failure(NO_PERIOD.to_string())),
+            _ => Ok(self),
+        }
+    }
+
+    fn forecast(&self, series: &Series) -> MetricQueryResult<Vec<FloatMetric>> {
+        if series.values.iter().any(|v| v.is_nan()) {
+            return Err(self.failure("Series contains missing values; fill them first".to_string()));
+        }
+
+        let step = series.regular_step("forecast")?;
+        let predictions = match self.method {
+            ForecastMethod::Holt => self.holt(&series.values),
+            ForecastMethod::HoltWinters => self.holt_winters(&series.values)?,
+        };
+
+        let last = series.timestamps[series.len() - 1];
+        predictions
+            .into_iter()
+            .enumerate()
+            .map(|(offset, value)| {
+                let timestamp = (offset as i64 + 1)
+                    .checked_mul(step)
+                    .and_then(|delta| last.checked_add(delta))
+                    .ok_or_else(|| MetricQueryError::ArithmeticOverflow { operation: "forecast".to_string() })?;
+                Ok(FloatMetric::new(Some(value), timestamp, Some(FORECAST_LABEL.to_string())))
+            })
+            .collect()
+    }
+
+    fn holt(&self, values: &[f64]) -> Vec<f64> {
+        let SmoothingParams { alpha, beta, .. } = self.params;
+        let mut level = values[0];
+        let mut trend = values[1] - values[0];
+
+        for &value in &values[1..] {
+            let previous = level;
+            level = alpha * value + (1.0 - alpha) * (level + trend);
+            trend = beta * (level - previous) + (1.0 - beta) * trend;
+        }
+
+        (1..=self.horizon).map(|h| level + h as f64 * trend).collect()
+    }
+
+    fn holt_winters(&self, values: &[f64]) -> MetricQueryResult<Vec<f64>> {
+        let SmoothingParams { alpha, beta, gamma } = self.params;
+        let Some(period) = self.period else {
+            return Err(self.failure(NO_PERIOD.to_string()));
+        };
+        if values.len() < 2 * period {
+            return Err(self.failure(format!(
+                "Need at least two full periods ({} metrics), got {}",
+                2 * period,
+                values.len()
+            )));
+        }
+
+        // Initialise from the first two seasons
+        let first_mean = values[..period].iter().sum::<f64>() / period as f64;
+        let second_mean = values[period..2 * period].iter().sum::<f64>() / period as f64;
+        let mut level = first_mean;
+        let mut trend = (second_mean - first_mean) / period as f64;
+        let mut season: Vec<f64> = values[..period].iter().map(|v| v - first_mean).collect();
+
+        for (index, &value) in values.iter().enumerate().skip(period) {
+            let phase = index % period;
+            let previous = level;
+            level = alpha * (value - season[phase]) + (1.0 - alpha) * (level + trend);
+            trend = beta * (level - previous) + (1.0 - beta) * trend;
+            season[phase] = gamma * (value - level) + (1.0 - gamma) * season[phase];
+        }
+
+        let n = values.len();
+        Ok((1..=self.horizon)
+            .map(|h| level + h as f64 * trend + season[(n + h - 1) % period])
+            .collect())
+    }
+
+    fn failure(&self, reason: String) -> MetricQueryError {
+        MetricQueryError::OperationFailed { operation: "forecast".to_string(), reason }
+    }
+}
+
+impl TransformationStrategy for ForecastTransformation {
+    fn name(&self) -> String {
+        "forecast".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        Ok(to_metrics(self.forecast(&Series::from_metrics(metrics))?))
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        self.forecast(&Series::from_float_metrics(metrics))
+    }
+}
@@ -0,0 +1,139 @@
+use std::collections::BTreeMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::settings::Settings;
+use crate::transformations::TransformationStrategy;
+use crate::warnings::WarningSink;
+
+/// What `detect_gaps` does when a series goes quiet for too long
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GapAction {
+    /// Insert a staleness marker where the series went stale
+    #[default]
+    Mark,
+    /// Fail the step, naming the first gap found
+    Error,
+}
+
+impl GapAction {
+    /// Parse an action name ("mark" or "error")
+    pub fn parse(action: &str) -> MetricQueryResult<Self> {
+        match action {
+            "mark" => Ok(Self::Mark),
+            "error" => Ok(Self::Error),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "detect_gaps".to_string(),
+                reason: format!("Unknown gap action: {}. Expected 'mark' or 'error'", action),
+            }),
+        }
+    }
+}
+
+/// Finds series that stopped reporting, so "not reporting" isn't mistaken for zero.
+///
+/// Each label's series is walked in timestamp order. Wherever two samples are more
+/// than `max_gap_seconds` apart, the series is stale from `max_gap_seconds` after the
+/// earlier one: `GapAction::Mark` inserts a staleness marker (`Metric::stale_marker`)
+/// at that time, and `GapAction::Error` fails the step. Float metrics have no
+/// staleness flag, and a missing value would read as a gap in the data rather than
+/// the end of the series, so marking them fails too. With a heartbeat time (`now`), a series whose last sample is more than
+/// `max_gap_seconds` before it counts as a gap too. Existing metrics pass through.
+#[derive(Clone)]
+pub struct GapDetectionTransformation {
+    max_gap_seconds: i64,
+    action: GapAction,
+    now: Option<i64>,
+}
+
+impl GapDetectionTransformation {
+    /// Create a gap detector for gaps longer than `max_gap_seconds`
+    pub fn new(max_gap_seconds: i64, action: GapAction) -> MetricQueryResult<Self> {
+        if max_gap_seconds <= 0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "detect_gaps".to_string(),
+                reason: format!("Maximum gap must be positive, got {}", max_gap_seconds),
+            });
+        }
+        Ok(Self { max_gap_seconds, action, now: None })
+    }
+
+    /// Also treat series whose last sample is too long before `now` as having gone stale
+    pub fn with_heartbeat(mut self, now: i64) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// (label, time the series went stale) of every gap, in timestamp order
+    fn gaps<'a>(&self, points: impl Iterator<Item = (i64, Option<&'a str>)>) -> MetricQueryResult<Vec<(Option<&'a str>, i64)>> {
+        let max_gap = Settings::current().timestamp_precision.from_seconds(self.max_gap_seconds)?;
+        let mut series: BTreeMap<Option<&str>, Vec<i64>> = BTreeMap::new();
+        for (timestamp, label) in points {
+            series.entry(label).or_default().push(timestamp);
+        }
+
+        let mut gaps = Vec::new();
+        for (label, mut timestamps) in series {
+            timestamps.sort_unstable();
+            timestamps.extend(self.now);
+            for pair in timestamps.windows(2) {
+                // Differences too wide for i64 are certainly gaps
+                if pair[1].checked_sub(pair[0]).is_none_or(|gap| gap > max_gap) {
+                    let stale_at = pair[0].saturating_add(max_gap);
+                    if self.action == GapAction::Error {
+                        return Err(MetricQueryError::OperationFailed {
+                            operation: "detect_gaps".to_string(),
+                            reason: format!(
+                                "series {} reported nothing between {} and {}",
+                                label.map_or("(unlabeled)".to_string(), |label| format!("'{}'", label)),
+                                pair[0],
+                                pair[1]
+                            ),
+                        });
+                    }
+                    gaps.push((label, stale_at));
+                }
+            }
+        }
+        gaps.sort_by_key(|&(_, stale_at)| stale_at);
+        Ok(gaps)
+    }
+}
+
+impl TransformationStrategy for GapDetectionTransformation {
+    fn name(&self) -> String {
+        format!("detect_gaps({})", self.max_gap_seconds)
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        self.apply_with_warnings(metrics, &mut WarningSink::new())
+    }
+
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        // Markers from an earlier run are not samples
+        let live = metrics.iter().filter(|m| !m.stale);
+        let gaps = self.gaps(live.map(|m| (m.timestamp, m.label.as_deref())))?;
+        if !gaps.is_empty() {
+            warnings.warn("series_gap", format!("marked {} gap(s) longer than {}s as stale", gaps.len(), self.max_gap_seconds));
+        }
+        let mut result = metrics.to_vec();
+        result.extend(gaps.into_iter().map(|(label, stale_at)| Metric::stale_marker(stale_at, label.map(str::to_string))));
+        Ok(result)
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let live = metrics.iter().filter(|m| !m.is_missing());
+        let gaps = self.gaps(live.map(|m| (m.timestamp, m.label.as_deref())))?;
+        if !gaps.is_empty() {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "detect_gaps".to_string(),
+                reason: "float metrics can't carry staleness markers; use on_gap='error' or integer metrics".to_string(),
+            });
+        }
+        Ok(metrics.to_vec())
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,115 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// How bucket boundaries are chosen
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistogramBuckets {
+    /// Sorted upper bounds; values above the last bound land in a final "+Inf" bucket
+    Bounds(Vec<f64>),
+    /// This many equal-width buckets spanning the observed minimum to maximum
+    Auto(usize),
+}
+
+/// Terminal step that replaces the stream with per-bucket value counts.
+///
+/// Each bucket becomes one metric whose value is the number of input values in it
+/// (not cumulative) and whose label is "le=<upper bound>", Prometheus-style. Buckets
+/// include their upper bound. Every output metric carries the latest input timestamp.
+/// Missing float values are not counted.
+#[derive(Clone)]
+pub struct HistogramTransformation {
+    buckets: HistogramBuckets,
+}
+
+impl HistogramTransformation {
+    /// Create a new histogram step
+    pub fn new(buckets: HistogramBuckets) -> MetricQueryResult<Self> {
+        match &buckets {
+            HistogramBuckets::Bounds(bounds) => {
+                if bounds.is_empty() || bounds.iter().any(|b| b.is_nan()) {
+                    return Err(Self::failure("Bucket bounds must be a non-empty list of numbers".to_string()));
+                }
+                if bounds.windows(2).any(|pair| pair[0] >= pair[1]) {
+                    return Err(Self::failure("Bucket bounds must be strictly increasing".to_string()));
+                }
+            }
+            HistogramBuckets::Auto(0) => {
+                return Err(Self::failure("At least one bucket is needed".to_string()));
+            }
+            HistogramBuckets::Auto(_) => {}
+        }
+        Ok(Self { buckets })
+    }
+
+    fn failure(reason: String) -> MetricQueryError {
+        MetricQueryError::OperationFailed { operation: "histogram".to_string(), reason }
+    }
+
+    /// Upper bounds of every bucket for the given values, the last one being +Inf for explicit bounds
+    fn upper_bounds(&self, values: &[f64]) -> Vec<f64> {
+        match &self.buckets {
+            HistogramBuckets::Bounds(bounds) => {
+                let mut upper = bounds.clone();
+                upper.push(f64::INFINITY);
+                upper
+            }
+            HistogramBuckets::Auto(count) => {
+                let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                if min == max {
+                    return vec![max];
+                }
+                let width = (max - min) / *count as f64;
+                // The top bound is the observed maximum exactly, so rounding can't push it out
+                (1..=*count)
+                    .map(|i| if i == *count { max } else { min + width * i as f64 })
+                    .collect()
+            }
+        }
+    }
+
+    fn histogram(&self, values: &[f64], timestamp: i64) -> MetricQueryResult<Vec<FloatMetric>> {
+        let values: Vec<f64> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+        if values.is_empty() {
+            return Err(MetricQueryError::EmptyMetricStream);
+        }
+
+        let upper = self.upper_bounds(&values);
+        let mut counts = vec![0usize; upper.len()];
+        for value in &values {
+            // First bucket whose upper bound is >= value; the last bucket catches the rest
+            let index = upper.partition_point(|bound| bound < value).min(upper.len() - 1);
+            counts[index] += 1;
+        }
+
+        Ok(upper
+            .iter()
+            .zip(counts)
+            .map(|(bound, count)| FloatMetric {
+                value: count as f64,
+                timestamp,
+                label: Some(if bound.is_infinite() { "le=+Inf".to_string() } else { format!("le={}", bound) }),
+                unit: None,
+            })
+            .collect())
+    }
+}
+
+impl TransformationStrategy for HistogramTransformation {
+    fn name(&self) -> String {
+        "histogram".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let values: Vec<f64> = metrics.iter().map(|m| m.value as f64).collect();
+        let timestamp = metrics.iter().map(|m| m.timestamp).max().unwrap_or_default();
+        Ok(self.histogram(&values, timestamp)?.iter().map(FloatMetric::to_metric).collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let values: Vec<f64> = metrics.iter().map(|m| m.value).collect();
+        let timestamp = metrics.iter().map(|m| m.timestamp).max().unwrap_or_default();
+        self.histogram(&values, timestamp)
+    }
+}
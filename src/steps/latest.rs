@@ -0,0 +1,50 @@
+use std::collections::HashMap;
+
+use crate::errors::MetricQueryResult;
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// Reduces each label's series to its most recent metric ("current value of every series").
+///
+/// Unlabeled metrics count as one series. When several metrics share the latest
+/// timestamp, the one that comes last in the input wins. Survivors keep their
+/// input order.
+#[derive(Clone, Default)]
+pub struct LatestTransformation;
+
+impl LatestTransformation {
+    /// Create a new keep-latest step
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Positions of the latest metric of each label, in input order
+    fn latest<'a>(points: impl Iterator<Item = (i64, Option<&'a str>)>) -> Vec<usize> {
+        let mut latest: HashMap<Option<&str>, (i64, usize)> = HashMap::new();
+        for (index, (timestamp, label)) in points.enumerate() {
+            let kept = latest.entry(label).or_insert((timestamp, index));
+            if timestamp >= kept.0 {
+                *kept = (timestamp, index);
+            }
+        }
+        let mut positions: Vec<usize> = latest.into_values().map(|(_, index)| index).collect();
+        positions.sort_unstable();
+        positions
+    }
+}
+
+impl TransformationStrategy for LatestTransformation {
+    fn name(&self) -> String {
+        "latest".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let positions = Self::latest(metrics.iter().map(|m| (m.timestamp, m.label.as_deref())));
+        Ok(positions.into_iter().map(|index| metrics[index].clone()).collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let positions = Self::latest(metrics.iter().map(|m| (m.timestamp, m.label.as_deref())));
+        Ok(positions.into_iter().map(|index| metrics[index].clone()).collect())
+    }
+}
@@ -0,0 +1,50 @@
+pub mod anomaly;
+pub mod assertion;
+pub mod counter;
+pub mod crossings;
+pub mod decompose;
+pub mod dedup;
+pub mod derivative;
+pub mod field;
+pub mod forecast;
+pub mod gaps;
+pub mod histogram;
+pub mod latest;
+pub mod normalize;
+pub mod pct_change;
+pub mod resample;
+pub mod rescale;
+pub mod rolling;
+pub mod sample;
+mod series;
+pub mod tap;
+pub mod timestamp_unit;
+pub mod timezone;
+pub mod topk;
+pub mod trend;
+pub mod unit;
+
+pub use anomaly::{AnomalyDetectionTransformation, AnomalyMethod, AnomalyOutput, ANOMALY_LABEL};
+pub use assertion::{Assertion, AssertionPredicate, AssertionTransformation};
+pub use counter::CounterIncreaseTransformation;
+pub use crossings::{CrossingDirection, CrossingTransformation};
+pub use decompose::{SeasonalDecompositionTransformation, DECOMPOSITION_LABELS};
+pub use dedup::{DeduplicateTransformation, DuplicateStrategy};
+pub use derivative::DerivativeTransformation;
+pub use field::{FieldFilter, SelectFieldTransformation};
+pub use forecast::{ForecastMethod, ForecastTransformation, SmoothingParams, FORECAST_LABEL};
+pub use gaps::{GapAction, GapDetectionTransformation};
+pub use histogram::{HistogramBuckets, HistogramTransformation};
+pub use latest::LatestTransformation;
+pub use normalize::{NormalizeMethod, NormalizeTransformation};
+pub use pct_change::PercentChangeTransformation;
+pub use resample::{resample_aggregation_name, ResampleFill, ResampleTransformation, MAX_RESAMPLE_POINTS};
+pub use rescale::RescaleTransformation;
+pub use rolling::{RollingPercentileTransformation, RollingReduceTransformation, RollingReduction, RollingWindow};
+pub use sample::{SampleEveryTransformation, SampleFractionTransformation};
+pub use tap::{TapBatch, TapCallback, TapTransformation};
+pub use timestamp_unit::TimestampUnitTransformation;
+pub use timezone::{TimezoneDirection, TimezoneShiftTransformation};
+pub use topk::TopKSeriesTransformation;
+pub use trend::{TrendOutput, TrendTransformation};
+pub use unit::ConvertUnitTransformation;
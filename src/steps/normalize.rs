@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// How values are rescaled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalizeMethod {
+    /// Map the minimum to 0 and the maximum to 1
+    MinMax,
+    /// Subtract the mean and divide by the (population) standard deviation
+    ZScore,
+}
+
+impl NormalizeMethod {
+    /// Parse a method name ("minmax" or "zscore")
+    pub fn parse(method: &str) -> MetricQueryResult<Self> {
+        match method {
+            "minmax" => Ok(Self::MinMax),
+            "zscore" => Ok(Self::ZScore),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "normalize".to_string(),
+                reason: format!("Unknown normalization method: {}. Expected 'minmax' or 'zscore'", method),
+            }),
+        }
+    }
+
+    /// (offset, scale) such that `(value - offset) / scale` normalizes the sample
+    fn parameters(self, values: &[f64]) -> (f64, f64) {
+        match self {
+            Self::MinMax => {
+                let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+                let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                (min, max - min)
+            }
+            Self::ZScore => {
+                let n = values.len() as f64;
+                let mean = values.iter().sum::<f64>() / n;
+                let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / n;
+                (mean, variance.sqrt())
+            }
+        }
+    }
+}
+
+/// Rescales values across the whole stream, or per label.
+///
+/// Constant series normalize to 0. Missing float values are ignored when computing
+/// the scale and stay missing. Order, timestamps and labels are preserved.
+#[derive(Clone)]
+pub struct NormalizeTransformation {
+    method: NormalizeMethod,
+    per_label: bool,
+}
+
+impl NormalizeTransformation {
+    /// Create a new normalization step over the whole stream
+    pub fn new(method: NormalizeMethod) -> Self {
+        Self { method, per_label: false }
+    }
+
+    /// Normalize each label's values independently
+    pub fn per_label(mut self, per_label: bool) -> Self {
+        self.per_label = per_label;
+        self
+    }
+
+    fn normalize<'a>(&self, points: &[(Option<&'a str>, f64)]) -> Vec<f64> {
+        let group_of = |label: Option<&'a str>| if self.per_label { label } else { None };
+
+        let mut groups: HashMap<Option<&str>, Vec<f64>> = HashMap::new();
+        for &(label, value) in points.iter().filter(|(_, v)| !v.is_nan()) {
+            groups.entry(group_of(label)).or_default().push(value);
+        }
+        let parameters: HashMap<Option<&str>, (f64, f64)> = groups
+            .into_iter()
+            .map(|(group, values)| (group, self.method.parameters(&values)))
+            .collect();
+
+        points
+            .iter()
+            .map(|&(label, value)| match parameters.get(&group_of(label)) {
+                _ if value.is_nan() => value,
+                Some(&(_, 0.0)) => 0.0,
+                Some(&(offset, scale)) => (value - offset) / scale,
+                None => value,
+            })
+            .collect()
+    }
+}
+
+impl TransformationStrategy for NormalizeTransformation {
+    fn name(&self) -> String {
+        "normalize".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let points: Vec<_> = metrics.iter().map(|m| (m.label.as_deref(), m.value as f64)).collect();
+        Ok(metrics
+            .iter()
+            .zip(self.normalize(&points))
+            .map(|(metric, value)| Metric {
+                value: value.round() as i64,
+                timestamp: metric.timestamp,
+                label: metric.label.clone(),
+                exemplar: metric.exemplar.clone(),
+                fields: metric.fields.clone(),
+                unit: None,
+                metric_type: None,
+                scale: None,
+                source: metric.source.clone(),
+                description: metric.description.clone(),
+                stale: metric.stale,
+                tags: metric.tags.clone(),
+            })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points: Vec<_> = metrics.iter().map(|m| (m.label.as_deref(), m.value)).collect();
+        Ok(metrics
+            .iter()
+            .zip(self.normalize(&points))
+            .map(|(metric, value)| FloatMetric::new(Some(value), metric.timestamp, metric.label.clone()))
+            .collect())
+    }
+}
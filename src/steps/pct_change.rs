@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// Compares each metric with the metric of the same label exactly one period earlier.
+///
+/// Emits `(current - previous) / previous * 100` at the current timestamp. Metrics
+/// without a predecessor, or whose predecessor is zero, are dropped. Intended to run
+/// after time grouping, so that buckets line up exactly.
+#[derive(Clone)]
+pub struct PercentChangeTransformation {
+    period: i64,
+}
+
+impl PercentChangeTransformation {
+    /// Create a new period-over-period step; `period` is in seconds and must be positive
+    pub fn new(period: i64) -> MetricQueryResult<Self> {
+        if period <= 0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "pct_change".to_string(),
+                reason: format!("Period must be positive, got {}", period),
+            });
+        }
+        Ok(Self { period })
+    }
+
+    /// (position, percent change) for every metric that has a usable predecessor
+    fn changes<'a>(
+        &self,
+        points: impl Iterator<Item = (i64, Option<&'a str>, f64)> + Clone,
+    ) -> Vec<(usize, f64)> {
+        let previous: HashMap<(Option<&str>, i64), f64> =
+            points.clone().map(|(timestamp, label, value)| ((label, timestamp), value)).collect();
+
+        points
+            .enumerate()
+            .filter_map(|(index, (timestamp, label, value))| {
+                let earlier = timestamp.checked_sub(self.period)?;
+                let base = *previous.get(&(label, earlier))?;
+                if base == 0.0 {
+                    return None;
+                }
+                Some((index, (value - base) / base * 100.0))
+            })
+            .collect()
+    }
+}
+
+impl TransformationStrategy for PercentChangeTransformation {
+    fn name(&self) -> String {
+        "pct_change".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let points = metrics.iter().map(|m| (m.timestamp, m.label.as_deref(), m.value as f64));
+        Ok(self
+            .changes(points)
+            .into_iter()
+            .map(|(index, change)| Metric::new(change.round() as i64, metrics[index].timestamp, metrics[index].label.clone()))
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points = metrics.iter().map(|m| (m.timestamp, m.label.as_deref(), m.value));
+        Ok(self
+            .changes(points)
+            .into_iter()
+            .map(|(index, change)| FloatMetric {
+                value: change,
+                timestamp: metrics[index].timestamp,
+                label: metrics[index].label.clone(),
+                unit: None,
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::decimal::align_scales;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::plugin_impls::aggregate_float_values;
+use crate::plugins::AggregationPlugin;
+use crate::settings::Settings;
+use crate::steps::series::{seconds_between, SeriesKey, NO_TAGS};
+use crate::transformations::TransformationStrategy;
+use crate::units::common_unit;
+
+/// Grids longer than this are refused rather than filling memory with interpolated points
+pub const MAX_RESAMPLE_POINTS: i64 = 10_000_000;
+
+/// How grid steps without any input metric get their value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResampleFill {
+    /// Carry the previous step's value forward
+    Previous,
+    /// Interpolate linearly between the surrounding steps that have data
+    Linear,
+    /// Leave the step missing: NaN in float output, a staleness marker in integer output
+    Null,
+}
+
+impl ResampleFill {
+    /// Parse a fill name ("previous", "linear" or "null")
+    pub fn parse(fill: &str) -> MetricQueryResult<Self> {
+        match fill {
+            "previous" => Ok(Self::Previous),
+            "linear" => Ok(Self::Linear),
+            "null" => Ok(Self::Null),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "resample".to_string(),
+                reason: format!("Unknown fill: {}. Expected 'previous', 'linear' or 'null'", fill),
+            }),
+        }
+    }
+}
+
+/// Aggregation name for a `how` argument; "mean" is accepted as an alias of "avg"
+pub fn resample_aggregation_name(how: &str) -> &str {
+    if how == "mean" {
+        "avg"
+    } else {
+        how
+    }
+}
+
+/// Re-projects each series onto a fixed grid of `interval` seconds.
+///
+/// A series is a label and tag set. Grid steps start at multiples of the interval and
+/// run from the step holding the series' first metric to the one holding its last, so
+/// every series gets exactly one point per step. Steps with metrics are aggregated
+/// with `how`; empty steps are filled according to `fill`. Series are emitted in order
+/// of first appearance. Fixed-point inputs are brought to their largest scale, which
+/// the output keeps unless `how` counts.
+#[derive(Clone)]
+pub struct ResampleTransformation {
+    interval: i64,
+    aggregation: Box<dyn AggregationPlugin>,
+    fill: ResampleFill,
+}
+
+/// One resampled point: (series, grid timestamp, value, unit)
+type GridPoint<'a> = (SeriesKey<'a>, i64, f64, Option<&'a str>);
+
+/// One series' input: metric values per grid step, and the units seen
+struct LabelSeries<'a, T> {
+    buckets: BTreeMap<i64, Vec<T>>,
+    units: Vec<Option<&'a str>>,
+}
+
+impl ResampleTransformation {
+    /// Create a new resample step
+    pub fn new(interval: i64, aggregation: Box<dyn AggregationPlugin>, fill: ResampleFill) -> Self {
+        Self { interval, aggregation, fill }
+    }
+
+    fn failure(&self, reason: String) -> MetricQueryError {
+        MetricQueryError::OperationFailed { operation: "resample".to_string(), reason }
+    }
+
+    fn resample<'a, T: Copy>(
+        &self,
+        points: impl Iterator<Item = (i64, SeriesKey<'a>, T, Option<&'a str>)>,
+        aggregate: impl Fn(&[T]) -> MetricQueryResult<f64>,
+    ) -> MetricQueryResult<Vec<GridPoint<'a>>> {
+        if self.interval <= 0 {
+            return Err(self.failure(format!("Interval must be positive, got {}", self.interval)));
+        }
+        let interval = Settings::current().timestamp_precision.from_seconds(self.interval)?;
+
+        // Series in order of first appearance
+        let mut order: Vec<SeriesKey> = Vec::new();
+        let mut series: HashMap<SeriesKey, LabelSeries<'a, T>> = HashMap::new();
+        for (timestamp, key, value, unit) in points {
+            let step = timestamp
+                .div_euclid(interval)
+                .checked_mul(interval)
+                .ok_or_else(|| MetricQueryError::ArithmeticOverflow { operation: "resample".to_string() })?;
+            let input = series.entry(key).or_insert_with(|| {
+                order.push(key);
+                LabelSeries { buckets: BTreeMap::new(), units: Vec::new() }
+            });
+            input.buckets.entry(step).or_default().push(value);
+            input.units.push(unit);
+        }
+
+        let mut result = Vec::new();
+        for key in order {
+            let input = &series[&key];
+            let unit = common_unit(input.units.iter().copied(), "resample")?;
+            let known: Vec<(i64, f64)> = input
+                .buckets
+                .iter()
+                .map(|(&step, values)| Ok((step, aggregate(values)?)))
+                .collect::<MetricQueryResult<_>>()?;
+
+            let (first, last) = (known[0].0, known[known.len() - 1].0);
+            let steps = (i128::from(last) - i128::from(first)) / i128::from(interval) + 1;
+            if steps > i128::from(MAX_RESAMPLE_POINTS) {
+                return Err(self.failure(format!(
+                    "{} grid steps exceed the limit of {}; use a larger interval",
+                    steps, MAX_RESAMPLE_POINTS
+                )));
+            }
+
+            let mut next_known = 0;
+            for index in 0..steps as i64 {
+                // Each step lies between `first` and `last`, but the product alone can overflow
+                let step = (i128::from(first) + i128::from(index) * i128::from(interval)) as i64;
+                if known[next_known].0 == step {
+                    result.push((key, step, known[next_known].1, unit));
+                    next_known += 1;
+                    continue;
+                }
+                // Steps before the first and after the last known one don't exist, so both neighbours do
+                let (before, after) = (known[next_known - 1], known[next_known]);
+                let value = match self.fill {
+                    ResampleFill::Previous => before.1,
+                    ResampleFill::Linear => {
+                        let position = seconds_between(before.0, step) / seconds_between(before.0, after.0);
+                        before.1 + (after.1 - before.1) * position
+                    }
+                    ResampleFill::Null => f64::NAN,
+                };
+                result.push((key, step, value, unit));
+            }
+        }
+        Ok(result)
+    }
+}
+
+impl TransformationStrategy for ResampleTransformation {
+    fn name(&self) -> String {
+        format!("resample({}s/{})", self.interval, self.aggregation.name())
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let (metrics, scale) = align_scales(metrics)?;
+        let scale = scale.filter(|_| self.aggregation.keeps_scale());
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &m.tags), m.value, m.unit.as_deref()));
+        let grid = self.resample(points, |values| self.aggregation.apply_values(values).map(|v| v as f64))?;
+        Ok(grid
+            .into_iter()
+            .map(|((label, tags), timestamp, value, unit)| {
+                let label = label.map(str::to_string);
+                Metric {
+                    unit: unit.map(str::to_string),
+                    tags: tags.clone(),
+                    scale,
+                    // Integers can't be missing, so a null step is marked stale instead
+                    ..if value.is_nan() {
+                        Metric::stale_marker(timestamp, label)
+                    } else {
+                        Metric::new(value.round() as i64, timestamp, label)
+                    }
+                }
+            })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &NO_TAGS), m.value, m.unit.as_deref()));
+        let grid = self.resample(points, |values| aggregate_float_values(&*self.aggregation, values))?;
+        Ok(grid
+            .into_iter()
+            .map(|((label, _), timestamp, value, unit)| FloatMetric {
+                value,
+                timestamp,
+                label: label.map(str::to_string),
+                unit: unit.map(str::to_string),
+            })
+            .collect())
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.aggregation.thread_bound()
+    }
+}
@@ -0,0 +1,45 @@
+use crate::decimal::{check_scale, rescale};
+use crate::errors::MetricQueryResult;
+use crate::models::{FloatMetric, Metric};
+use crate::plugin_impls::RoundingMode;
+use crate::transformations::TransformationStrategy;
+
+/// Brings fixed-point values to a given number of decimal places.
+///
+/// Raising the scale is exact, so rescaling to more places before an `avg` keeps
+/// more digits of the mean; lowering it rounds with the configured `RoundingMode`.
+/// Plain integer metrics are treated as scale 0 and become fixed-point. Float
+/// values already carry their fraction and pass through.
+#[derive(Clone)]
+pub struct RescaleTransformation {
+    scale: u32,
+    rounding: RoundingMode,
+}
+
+impl RescaleTransformation {
+    /// Create a rescaling step, failing for scales beyond `decimal::MAX_SCALE`
+    pub fn new(scale: u32, rounding: RoundingMode) -> MetricQueryResult<Self> {
+        check_scale(scale)?;
+        Ok(Self { scale, rounding })
+    }
+}
+
+impl TransformationStrategy for RescaleTransformation {
+    fn name(&self) -> String {
+        format!("rescale({})", self.scale)
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        metrics
+            .iter()
+            .map(|metric| {
+                let value = rescale(metric.value, metric.scale.unwrap_or(0), self.scale, self.rounding)?;
+                Ok(Metric { value, scale: Some(self.scale), ..metric.clone() })
+            })
+            .collect()
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        Ok(metrics.to_vec())
+    }
+}
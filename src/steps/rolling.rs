@@ -0,0 +1,585 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, VecDeque};
+
+use crate::analysis::{interpolate, interpolate_ints};
+use crate::decimal::align_scales;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::plugin_impls::RoundingMode;
+use crate::settings::Settings;
+use crate::steps::series::{SeriesKey, NO_TAGS};
+use crate::transformations::TransformationStrategy;
+
+/// Extent of a rolling window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollingWindow {
+    /// The last N metrics, including the current one
+    Count(usize),
+    /// Metrics within the last N seconds, i.e. timestamps in `(t - N, t]`
+    Duration(i64),
+}
+
+impl RollingWindow {
+    fn validate(self, operation: &str) -> MetricQueryResult<Self> {
+        let valid = match self {
+            Self::Count(count) => count > 0,
+            Self::Duration(seconds) => seconds > 0,
+        };
+        if !valid {
+            return Err(MetricQueryError::OperationFailed {
+                operation: operation.to_string(),
+                reason: "Rolling window must be positive".to_string(),
+            });
+        }
+        Ok(self)
+    }
+
+    /// The window with a duration converted to the `Settings` timestamp precision
+    fn in_ticks(self) -> Self {
+        match self {
+            Self::Duration(seconds) => {
+                Self::Duration(seconds.saturating_mul(Settings::current().timestamp_precision.ticks_per_second()))
+            }
+            window => window,
+        }
+    }
+
+    /// Whether the oldest point (at `oldest`) has left a window that ends at `newest`,
+    /// given the window currently holds `len` points including the newest one
+    fn evicts(self, len: usize, oldest: i64, newest: i64) -> bool {
+        match self {
+            Self::Count(count) => len > count,
+            // In i128 so windows near i64::MIN don't saturate and evict the newest point too
+            Self::Duration(seconds) => i128::from(newest) - i128::from(oldest) >= i128::from(seconds),
+        }
+    }
+}
+
+/// A value rolling windows run over: floats, or integers, which stay exact past 2^53
+/// because they are never converted to floats
+trait RollingValue: Copy + PartialOrd {
+    /// Running total of a window
+    type Sum: Default;
+
+    fn is_missing(self) -> bool;
+
+    fn order(&self, other: &Self) -> Ordering;
+
+    fn add(sum: &mut Self::Sum, value: Self);
+
+    fn subtract(sum: &mut Self::Sum, value: Self);
+
+    /// The sum of a window of `len` values, or with `mean` their mean
+    fn total(sum: &Self::Sum, len: usize, mean: bool) -> MetricQueryResult<Self>;
+
+    /// The value `fraction` of the way from `lower` to `upper`, for quantiles that
+    /// fall between two ranks
+    fn interpolate(lower: Self, upper: Self, fraction: f64) -> Self;
+}
+
+impl RollingValue for f64 {
+    type Sum = CompensatedSum;
+
+    fn is_missing(self) -> bool {
+        self.is_nan()
+    }
+
+    fn order(&self, other: &Self) -> Ordering {
+        self.total_cmp(other)
+    }
+
+    fn add(sum: &mut CompensatedSum, value: f64) {
+        sum.add(value);
+    }
+
+    fn subtract(sum: &mut CompensatedSum, value: f64) {
+        sum.add(-value);
+    }
+
+    fn total(sum: &CompensatedSum, len: usize, mean: bool) -> MetricQueryResult<f64> {
+        Ok(if mean { sum.value() / len as f64 } else { sum.value() })
+    }
+
+    fn interpolate(lower: f64, upper: f64, fraction: f64) -> f64 {
+        interpolate(lower, upper, fraction)
+    }
+}
+
+impl RollingValue for i64 {
+    // Even 2^64 values of i64::MAX fit in an i128
+    type Sum = i128;
+
+    fn is_missing(self) -> bool {
+        false
+    }
+
+    fn order(&self, other: &Self) -> Ordering {
+        self.cmp(other)
+    }
+
+    fn add(sum: &mut i128, value: i64) {
+        *sum += i128::from(value);
+    }
+
+    fn subtract(sum: &mut i128, value: i64) {
+        *sum -= i128::from(value);
+    }
+
+    fn total(sum: &i128, len: usize, mean: bool) -> MetricQueryResult<i64> {
+        if mean {
+            // A mean always fits
+            return Ok(RoundingMode::Round.divide(*sum, len as i128) as i64);
+        }
+        i64::try_from(*sum).map_err(|_| MetricQueryError::ArithmeticOverflow { operation: "rolling".to_string() })
+    }
+
+    fn interpolate(lower: i64, upper: i64, fraction: f64) -> i64 {
+        interpolate_ints(lower, upper, fraction)
+    }
+}
+
+/// A window value ordered by `RollingValue::order`, so floats can go in heaps
+#[derive(Clone, Copy)]
+struct Ranked<V>(V);
+
+impl<V: RollingValue> Ord for Ranked<V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.order(&other.0)
+    }
+}
+
+impl<V: RollingValue> PartialOrd for Ranked<V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<V: RollingValue> PartialEq for Ranked<V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other).is_eq()
+    }
+}
+
+impl<V: RollingValue> Eq for Ranked<V> {}
+
+/// Values of the current window split around the quantile's rank: a max-heap of the
+/// lower values and a min-heap of the rest.
+///
+/// Removed values are only recorded, and dropped from a heap once they reach its top,
+/// so inserting, removing and reading the quantile cost O(log w) for a window of w
+/// values. The heaps are rebuilt when removed values come to outnumber live ones.
+struct QuantileWindow<V> {
+    lower: BinaryHeap<Ranked<V>>,
+    upper: BinaryHeap<Reverse<Ranked<V>>>,
+    /// Live values in each heap
+    lower_len: usize,
+    upper_len: usize,
+    /// Values removed from the window but still in a heap, with their multiplicity
+    removed: BTreeMap<Ranked<V>, usize>,
+}
+
+impl<V: RollingValue> QuantileWindow<V> {
+    fn new() -> Self {
+        Self {
+            lower: BinaryHeap::new(),
+            upper: BinaryHeap::new(),
+            lower_len: 0,
+            upper_len: 0,
+            removed: BTreeMap::new(),
+        }
+    }
+
+    fn insert(&mut self, value: V) {
+        let value = Ranked(value);
+        if self.lower.peek().is_some_and(|top| value <= *top) {
+            self.lower.push(value);
+            self.lower_len += 1;
+        } else {
+            self.upper.push(Reverse(value));
+            self.upper_len += 1;
+        }
+    }
+
+    /// Remove one occurrence of `value`, which must be in the window
+    fn remove(&mut self, value: V) {
+        let value = Ranked(value);
+        *self.removed.entry(value).or_default() += 1;
+        // Both tops are live, so a value no greater than the lower top is in the lower heap
+        if self.lower.peek().is_some_and(|top| value <= *top) {
+            self.lower_len -= 1;
+        } else {
+            self.upper_len -= 1;
+        }
+        self.prune();
+        if self.lower.len() + self.upper.len() > 2 * (self.lower_len + self.upper_len) + 64 {
+            self.compact();
+        }
+    }
+
+    /// Quantile `q` of a non-empty window, interpolating between the closest ranks
+    fn quantile(&mut self, q: f64) -> V {
+        let rank = q * (self.lower_len + self.upper_len - 1) as f64;
+        let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+        // Move values across until the lower heap holds exactly the ranks up to `below`
+        while self.lower_len > below + 1 {
+            if let Some(value) = self.lower.pop() {
+                self.upper.push(Reverse(value));
+                self.lower_len -= 1;
+                self.upper_len += 1;
+                self.prune();
+            }
+        }
+        while self.lower_len < below + 1 {
+            if let Some(Reverse(value)) = self.upper.pop() {
+                self.lower.push(value);
+                self.upper_len -= 1;
+                self.lower_len += 1;
+                self.prune();
+            }
+        }
+
+        let lower = self.lower.peek().expect("the window is not empty").0;
+        let upper = match self.upper.peek() {
+            Some(Reverse(top)) if above > below => top.0,
+            _ => lower,
+        };
+        V::interpolate(lower, upper, rank - below as f64)
+    }
+
+    /// Drop removed values from the top of both heaps, so the tops are live
+    fn prune(&mut self) {
+        while let Some(&top) = self.lower.peek() {
+            if !self.take_removed(top) {
+                break;
+            }
+            self.lower.pop();
+        }
+        while let Some(&Reverse(top)) = self.upper.peek() {
+            if !self.take_removed(top) {
+                break;
+            }
+            self.upper.pop();
+        }
+    }
+
+    /// Whether `value` was removed, consuming one removal if so
+    fn take_removed(&mut self, value: Ranked<V>) -> bool {
+        match self.removed.get_mut(&value) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.removed.remove(&value);
+                }
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Rebuild both heaps from their live values
+    fn compact(&mut self) {
+        let mut values: Vec<Ranked<V>> = self.lower.drain().chain(self.upper.drain().map(|Reverse(v)| v)).collect();
+        values.sort_unstable();
+        values.retain(|&value| !self.take_removed(value));
+        let upper = values.split_off(self.lower_len);
+        self.lower = values.into();
+        self.upper = upper.into_iter().map(Reverse).collect();
+    }
+}
+
+/// Replaces each metric with a quantile of its series' rolling window.
+///
+/// Each label and tag set is treated as its own series, walked in timestamp order; the
+/// output keeps the input's labels, tags and timestamps, sorted by timestamp, at the
+/// largest scale among fixed-point inputs. Missing float values
+/// are skipped.
+#[derive(Clone)]
+pub struct RollingPercentileTransformation {
+    quantile: f64,
+    window: RollingWindow,
+}
+
+impl RollingPercentileTransformation {
+    /// Create a new rolling percentile step; `quantile` is in [0, 1] (0.95 for p95)
+    pub fn new(quantile: f64, window: RollingWindow) -> MetricQueryResult<Self> {
+        if !(0.0..=1.0).contains(&quantile) {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "rolling_percentile".to_string(),
+                reason: format!("Quantile must be between 0 and 1, got {}", quantile),
+            });
+        }
+        Ok(Self { quantile, window: window.validate("rolling_percentile")? })
+    }
+
+    /// (position, rolling quantile) for every non-missing point, in timestamp order
+    fn rolling<'a, V: RollingValue>(&self, points: impl Iterator<Item = (i64, SeriesKey<'a>, V)>) -> Vec<(usize, V)> {
+        let mut series: BTreeMap<SeriesKey, Vec<(usize, i64, V)>> = BTreeMap::new();
+        for (index, (timestamp, key, value)) in points.enumerate() {
+            if !value.is_missing() {
+                series.entry(key).or_default().push((index, timestamp, value));
+            }
+        }
+
+        let extent = self.window.in_ticks();
+        let mut result = Vec::new();
+        for mut points in series.into_values() {
+            points.sort_by_key(|&(_, timestamp, _)| timestamp);
+
+            let mut window = QuantileWindow::new();
+            let mut members: VecDeque<(i64, V)> = VecDeque::new();
+            for (index, timestamp, value) in points {
+                window.insert(value);
+                members.push_back((timestamp, value));
+                while let Some(&(oldest, old_value)) = members.front() {
+                    if !extent.evicts(members.len(), oldest, timestamp) {
+                        break;
+                    }
+                    members.pop_front();
+                    window.remove(old_value);
+                }
+                result.push((index, timestamp, window.quantile(self.quantile)));
+            }
+        }
+
+        result.sort_by_key(|&(_, timestamp, _)| timestamp);
+        result.into_iter().map(|(index, _, value)| (index, value)).collect()
+    }
+}
+
+impl TransformationStrategy for RollingPercentileTransformation {
+    fn name(&self) -> String {
+        "rolling_percentile".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let (metrics, scale) = align_scales(metrics)?;
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &m.tags), m.value));
+        Ok(self
+            .rolling(points)
+            .into_iter()
+            .map(|(index, value)| Metric {
+                unit: metrics[index].unit.clone(),
+                tags: metrics[index].tags.clone(),
+                scale,
+                ..Metric::new(value, metrics[index].timestamp, metrics[index].label.clone())
+            })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &NO_TAGS), m.value));
+        Ok(self
+            .rolling(points)
+            .into_iter()
+            .map(|(index, value)| FloatMetric {
+                value,
+                timestamp: metrics[index].timestamp,
+                label: metrics[index].label.clone(),
+                unit: metrics[index].unit.clone(),
+            })
+            .collect())
+    }
+}
+
+/// Reduction applied to each rolling window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RollingReduction {
+    Sum,
+    Mean,
+    Min,
+    Max,
+}
+
+impl RollingReduction {
+    /// Parse a reduction name ("sum", "mean" or "avg", "min" or "max")
+    pub fn parse(how: &str) -> MetricQueryResult<Self> {
+        match how {
+            "sum" => Ok(Self::Sum),
+            "mean" | "avg" => Ok(Self::Mean),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "rolling".to_string(),
+                reason: format!("Unknown rolling reduction: {}. Expected 'sum', 'mean', 'min' or 'max'", how),
+            }),
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Sum => "sum",
+            Self::Mean => "mean",
+            Self::Min => "min",
+            Self::Max => "max",
+        }
+    }
+}
+
+/// Running sum with Neumaier compensation, so values leaving the window don't leave
+/// rounding error behind
+#[derive(Default)]
+struct CompensatedSum {
+    sum: f64,
+    compensation: f64,
+}
+
+impl CompensatedSum {
+    fn add(&mut self, value: f64) {
+        let total = self.sum + value;
+        self.compensation += if self.sum.abs() >= value.abs() {
+            (self.sum - total) + value
+        } else {
+            (value - total) + self.sum
+        };
+        self.sum = total;
+    }
+
+    fn value(&self) -> f64 {
+        self.sum + self.compensation
+    }
+}
+
+/// Positions of window candidates for the minimum or maximum; values along the deque
+/// are monotonic, so the front is always the current extreme
+struct MonotonicDeque {
+    positions: VecDeque<usize>,
+    keeps_max: bool,
+}
+
+impl MonotonicDeque {
+    fn new(keeps_max: bool) -> Self {
+        Self { positions: VecDeque::new(), keeps_max }
+    }
+
+    /// Whether a newer value makes an older candidate irrelevant: it leaves the window later
+    /// and is at least as extreme
+    fn supersedes<V: PartialOrd>(&self, newer: V, kept: V) -> bool {
+        if self.keeps_max {
+            newer >= kept
+        } else {
+            newer <= kept
+        }
+    }
+
+    fn push<V: Copy + PartialOrd>(&mut self, position: usize, values: &[V]) {
+        while let Some(&back) = self.positions.back() {
+            if !self.supersedes(values[position], values[back]) {
+                break;
+            }
+            self.positions.pop_back();
+        }
+        self.positions.push_back(position);
+    }
+
+    /// Drop candidates that left the window, which now starts at `start`
+    fn evict_before(&mut self, start: usize) {
+        while self.positions.front().is_some_and(|&front| front < start) {
+            self.positions.pop_front();
+        }
+    }
+
+    /// The current extreme; the window always holds at least the newest value
+    fn extreme<V: Copy>(&self, values: &[V]) -> V {
+        values[self.positions[0]]
+    }
+}
+
+/// Replaces each metric with the sum, mean, minimum or maximum of its series' rolling window.
+///
+/// Unlike bucketed grouping, every metric gets its own window ending at it. Each label
+/// and tag set is walked in timestamp order in O(n): sums are maintained incrementally
+/// and min/max use a monotonic deque. The output keeps the input's labels, tags,
+/// timestamps and units, sorted by timestamp, at the largest scale among fixed-point
+/// inputs. Integer sums are exact, and means rounded half away from zero. Missing
+/// float values are skipped.
+#[derive(Clone)]
+pub struct RollingReduceTransformation {
+    reduction: RollingReduction,
+    window: RollingWindow,
+}
+
+impl RollingReduceTransformation {
+    /// Create a new rolling reduction step
+    pub fn new(reduction: RollingReduction, window: RollingWindow) -> MetricQueryResult<Self> {
+        Ok(Self { reduction, window: window.validate("rolling")? })
+    }
+
+    /// (position, rolling value) for every non-missing point, in timestamp order
+    fn rolling<'a, V: RollingValue>(
+        &self,
+        points: impl Iterator<Item = (i64, SeriesKey<'a>, V)>,
+    ) -> MetricQueryResult<Vec<(usize, V)>> {
+        let mut series: BTreeMap<SeriesKey, Vec<(usize, i64, V)>> = BTreeMap::new();
+        for (index, (timestamp, key, value)) in points.enumerate() {
+            if !value.is_missing() {
+                series.entry(key).or_default().push((index, timestamp, value));
+            }
+        }
+
+        let extent = self.window.in_ticks();
+        let mut result = Vec::new();
+        for mut points in series.into_values() {
+            points.sort_by_key(|&(_, timestamp, _)| timestamp);
+            let values: Vec<V> = points.iter().map(|&(_, _, value)| value).collect();
+
+            let mut sum = V::Sum::default();
+            let mut extremes = MonotonicDeque::new(self.reduction == RollingReduction::Max);
+            let mut start = 0;
+            for (position, &(index, timestamp, value)) in points.iter().enumerate() {
+                V::add(&mut sum, value);
+                extremes.push(position, &values);
+                while extent.evicts(position - start + 1, points[start].1, timestamp) {
+                    V::subtract(&mut sum, values[start]);
+                    start += 1;
+                }
+                extremes.evict_before(start);
+
+                let len = position - start + 1;
+                let rolled = match self.reduction {
+                    RollingReduction::Sum => V::total(&sum, len, false)?,
+                    RollingReduction::Mean => V::total(&sum, len, true)?,
+                    RollingReduction::Min | RollingReduction::Max => extremes.extreme(&values),
+                };
+                result.push((index, timestamp, rolled));
+            }
+        }
+
+        result.sort_by_key(|&(_, timestamp, _)| timestamp);
+        Ok(result.into_iter().map(|(index, _, value)| (index, value)).collect())
+    }
+}
+
+impl TransformationStrategy for RollingReduceTransformation {
+    fn name(&self) -> String {
+        format!("rolling({})", self.reduction.as_str())
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let (metrics, scale) = align_scales(metrics)?;
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &m.tags), m.value));
+        Ok(self
+            .rolling(points)?
+            .into_iter()
+            .map(|(index, value)| Metric {
+                unit: metrics[index].unit.clone(),
+                tags: metrics[index].tags.clone(),
+                scale,
+                ..Metric::new(value, metrics[index].timestamp, metrics[index].label.clone())
+            })
+            .collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points = metrics.iter().map(|m| (m.timestamp, (m.label.as_deref(), &NO_TAGS), m.value));
+        Ok(self
+            .rolling(points)?
+            .into_iter()
+            .map(|(index, value)| FloatMetric {
+                value,
+                timestamp: metrics[index].timestamp,
+                label: metrics[index].label.clone(),
+                unit: metrics[index].unit.clone(),
+            })
+            .collect())
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// Keeps the first metric and every `n`th one after it, to cut a huge stream down
+/// before expensive steps.
+///
+/// Positions count across the whole input, not per label series, so sort or group
+/// first when each series should be thinned evenly. The same input always yields
+/// the same sample.
+#[derive(Clone)]
+pub struct SampleEveryTransformation {
+    n: usize,
+}
+
+impl SampleEveryTransformation {
+    /// Create a new every-nth sampling step
+    pub fn new(n: usize) -> MetricQueryResult<Self> {
+        if n == 0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "sample_every".to_string(),
+                reason: "n must be at least 1".to_string(),
+            });
+        }
+        Ok(Self { n })
+    }
+}
+
+impl TransformationStrategy for SampleEveryTransformation {
+    fn name(&self) -> String {
+        format!("sample_every({})", self.n)
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        Ok(metrics.iter().step_by(self.n).cloned().collect())
+    }
+
+    fn apply_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        Ok((
+            values.iter().step_by(self.n).copied().collect(),
+            timestamps.iter().step_by(self.n).copied().collect(),
+        ))
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        Ok(metrics.iter().step_by(self.n).cloned().collect())
+    }
+}
+
+/// SplitMix64, small and fast; kept in-crate so a seed picks the same sample in
+/// every release
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in [0, 1)
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Keeps each metric with probability `p`, independently.
+///
+/// With a seed every run keeps the same positions, whichever execution mode runs
+/// it, so samples are reproducible in tests and analyses; without one each run
+/// draws a fresh sample.
+#[derive(Clone)]
+pub struct SampleFractionTransformation {
+    p: f64,
+    seed: Option<u64>,
+}
+
+impl SampleFractionTransformation {
+    /// Create a new random sampling step, failing unless `p` is between 0 and 1
+    pub fn new(p: f64, seed: Option<u64>) -> MetricQueryResult<Self> {
+        if !(0.0..=1.0).contains(&p) {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "sample_fraction".to_string(),
+                reason: format!("p must be between 0 and 1, got {}", p),
+            });
+        }
+        Ok(Self { p, seed })
+    }
+
+    /// Whether to keep each of `len` positions
+    fn keep(&self, len: usize) -> Vec<bool> {
+        let seed = self.seed.unwrap_or_else(|| RandomState::new().build_hasher().finish());
+        let mut rng = SplitMix64(seed);
+        (0..len).map(|_| rng.next_f64() < self.p).collect()
+    }
+
+    fn sample<T: Clone>(&self, items: &[T]) -> Vec<T> {
+        items.iter().zip(self.keep(items.len())).filter(|(_, keep)| *keep).map(|(item, _)| item.clone()).collect()
+    }
+}
+
+impl TransformationStrategy for SampleFractionTransformation {
+    fn name(&self) -> String {
+        format!("sample_fraction({})", self.p)
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        Ok(self.sample(metrics))
+    }
+
+    fn apply_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        let keep = self.keep(values.len());
+        let kept = |column: &[i64]| column.iter().zip(&keep).filter(|(_, &keep)| keep).map(|(&x, _)| x).collect();
+        Ok((kept(values), kept(timestamps)))
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        Ok(self.sample(metrics))
+    }
+}
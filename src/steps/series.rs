@@ -0,0 +1,75 @@
+//! Helpers shared by steps that treat the input as a single time-ordered series
+
+use std::collections::BTreeMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::settings::Settings;
+
+/// Tags of float metrics, which carry none
+pub(crate) static NO_TAGS: BTreeMap<String, String> = BTreeMap::new();
+
+/// What tells series apart in steps that work per series: the label and the tag set,
+/// so series that differ only in tags aren't merged
+pub(crate) type SeriesKey<'a> = (Option<&'a str>, &'a BTreeMap<String, String>);
+
+/// Timestamps and values of a series, sorted by timestamp
+pub(crate) struct Series {
+    pub timestamps: Vec<i64>,
+    pub values: Vec<f64>,
+}
+
+impl Series {
+    /// Build a sorted series from integer metrics
+    pub(crate) fn from_metrics(metrics: &[Metric]) -> Self {
+        Self::sorted(metrics.iter().map(|m| (m.timestamp, m.value as f64)).collect())
+    }
+
+    /// Build a sorted series from float metrics
+    pub(crate) fn from_float_metrics(metrics: &[FloatMetric]) -> Self {
+        Self::sorted(metrics.iter().map(|m| (m.timestamp, m.value)).collect())
+    }
+
+    fn sorted(mut points: Vec<(i64, f64)>) -> Self {
+        // Stable, so points sharing a timestamp keep their input order
+        points.sort_by_key(|&(timestamp, _)| timestamp);
+        let (timestamps, values) = points.into_iter().unzip();
+        Self { timestamps, values }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    /// The constant spacing between timestamps, failing if the series is irregular
+    pub(crate) fn regular_step(&self, operation: &str) -> MetricQueryResult<i64> {
+        if self.timestamps.len() < 2 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: operation.to_string(),
+                reason: "At least two metrics are needed to determine the series spacing".to_string(),
+            });
+        }
+
+        // A spacing too wide for i64 can't be regular either
+        let step = self.timestamps[1].checked_sub(self.timestamps[0]);
+        match step {
+            Some(step) if step > 0 && self.timestamps.windows(2).all(|pair| pair[1].checked_sub(pair[0]) == Some(step)) => Ok(step),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: operation.to_string(),
+                reason: "Series must be regularly spaced; group it by time first".to_string(),
+            }),
+        }
+    }
+}
+
+/// Seconds from `from` to `to` at the `Settings` timestamp precision, exact even when
+/// the difference overflows i64
+pub(crate) fn seconds_between(from: i64, to: i64) -> f64 {
+    let ticks = Settings::current().timestamp_precision.ticks_per_second();
+    (i128::from(to) - i128::from(from)) as f64 / ticks as f64
+}
+
+/// Convert float results back into integer metrics, rounding to the nearest value
+pub(crate) fn to_metrics(metrics: Vec<FloatMetric>) -> Vec<Metric> {
+    metrics.iter().map(FloatMetric::to_metric).collect()
+}
@@ -0,0 +1,73 @@
+use std::sync::Arc;
+
+use crate::errors::MetricQueryResult;
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// The slice of the stream handed to a tap callback
+#[derive(Debug, Clone, Copy)]
+pub enum TapBatch<'a> {
+    /// Sample of an integer metric stream
+    Metrics(&'a [Metric]),
+    /// Sample of a float metric stream
+    Floats(&'a [FloatMetric]),
+}
+
+/// Callback invoked by `TapTransformation`; returning an error fails the pipeline
+pub type TapCallback = Arc<dyn Fn(TapBatch<'_>) -> MetricQueryResult<()> + Send + Sync>;
+
+/// Passes the first `sample` metrics of the stream to a callback and forwards the
+/// stream unchanged, for inspecting what a later step receives.
+#[derive(Clone)]
+pub struct TapTransformation {
+    callback: TapCallback,
+    sample: Option<usize>,
+    thread_bound: bool,
+}
+
+impl TapTransformation {
+    /// Create a tap that hands the whole stream to `callback`
+    pub fn new(callback: impl Fn(TapBatch<'_>) -> MetricQueryResult<()> + Send + Sync + 'static) -> Self {
+        Self { callback: Arc::new(callback), sample: None, thread_bound: false }
+    }
+
+    /// Only hand the first `sample` metrics to the callback (`None` for all of them)
+    pub fn with_sample(mut self, sample: Option<usize>) -> Self {
+        self.sample = sample;
+        self
+    }
+
+    /// Keep the step on the calling thread, for callbacks that call into Python
+    pub fn with_thread_bound(mut self, thread_bound: bool) -> Self {
+        self.thread_bound = thread_bound;
+        self
+    }
+
+    fn sample_of<'a, T>(&self, metrics: &'a [T]) -> &'a [T] {
+        &metrics[..self.sample.unwrap_or(metrics.len()).min(metrics.len())]
+    }
+}
+
+impl TransformationStrategy for TapTransformation {
+    fn name(&self) -> String {
+        "tap".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        (self.callback)(TapBatch::Metrics(self.sample_of(metrics)))?;
+        Ok(metrics.to_vec())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        (self.callback)(TapBatch::Floats(self.sample_of(metrics)))?;
+        Ok(metrics.to_vec())
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.thread_bound
+    }
+}
@@ -0,0 +1,67 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::settings::{Settings, TimestampPrecision};
+use crate::transformations::TransformationStrategy;
+
+/// Converts timestamps read in `unit` to the `Settings` timestamp precision.
+///
+/// Feeding epoch milliseconds to a pipeline that counts seconds puts every point
+/// tens of thousands of years in the future, so bucket boundaries come out as
+/// nonsense. This step declares the unit the input actually uses. A timestamp that
+/// is implausible in that unit (more than `MAX_PLAUSIBLE_SECONDS` from the epoch, or
+/// for a sub-second unit less than `MIN_PLAUSIBLE_SECONDS`) fails the step with the
+/// unit it more likely is; converting to a coarser precision rounds down.
+#[derive(Clone)]
+pub struct TimestampUnitTransformation {
+    unit: TimestampPrecision,
+}
+
+impl TimestampUnitTransformation {
+    /// Create a step reading timestamps as `unit`
+    pub fn new(unit: TimestampPrecision) -> Self {
+        Self { unit }
+    }
+
+    /// Check and convert every timestamp, reporting the first implausible one and how
+    /// many there are
+    fn convert(&self, timestamps: impl Iterator<Item = i64> + Clone) -> MetricQueryResult<Vec<i64>> {
+        let mut offenders = timestamps.clone().enumerate().filter(|&(_, timestamp)| !self.unit.plausible(timestamp));
+        if let Some((index, timestamp)) = offenders.next() {
+            let reason = match TimestampPrecision::guess(timestamp) {
+                Some(guess) => format!(
+                    "implausible as '{}'; it looks like '{}'",
+                    self.unit.as_str(),
+                    guess.as_str()
+                ),
+                None => format!("implausible in any unit, read as '{}'", self.unit.as_str()),
+            };
+            return Err(MetricQueryError::InvalidTimestamp { index, timestamp, reason, offenders: offenders.count() + 1 });
+        }
+        let precision = Settings::current().timestamp_precision;
+        timestamps.map(|timestamp| self.unit.convert(timestamp, precision)).collect()
+    }
+}
+
+impl TransformationStrategy for TimestampUnitTransformation {
+    fn name(&self) -> String {
+        format!("timestamp_unit({})", self.unit.as_str())
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let timestamps = self.convert(metrics.iter().map(|m| m.timestamp))?;
+        Ok(metrics.iter().zip(timestamps).map(|(metric, timestamp)| Metric { timestamp, ..metric.clone() }).collect())
+    }
+
+    fn apply_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        Ok((values.to_vec(), self.convert(timestamps.iter().copied())?))
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let timestamps = self.convert(metrics.iter().map(|m| m.timestamp))?;
+        Ok(metrics.iter().zip(timestamps).map(|(metric, timestamp)| FloatMetric { timestamp, ..metric.clone() }).collect())
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+}
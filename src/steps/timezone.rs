@@ -0,0 +1,92 @@
+use chrono::{DateTime, Duration, Offset, TimeZone};
+use chrono_tz::Tz;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+
+/// Which way timestamps are shifted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimezoneDirection {
+    /// UTC instants become the target zone's wall-clock time, for export
+    ToLocal,
+    /// Wall-clock times in the target zone become UTC instants, for ingest
+    FromLocal,
+}
+
+impl TimezoneDirection {
+    /// Parse a direction name ("to_local" or "from_local")
+    pub fn parse(direction: &str) -> MetricQueryResult<Self> {
+        match direction {
+            "to_local" => Ok(Self::ToLocal),
+            "from_local" => Ok(Self::FromLocal),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "convert_timezone".to_string(),
+                reason: format!("Unknown direction: {}. Expected 'to_local' or 'from_local'", direction),
+            }),
+        }
+    }
+}
+
+/// Shifts timestamps between UTC and a timezone's local clock.
+///
+/// Unlike tz-aware grouping, which only moves bucket boundaries, this rewrites every
+/// timestamp by the zone's UTC offset at that moment, so exported results read as
+/// local time. On ingest, a wall-clock time repeated by a DST change maps to its
+/// earlier instant, and one skipped by a DST change uses the offset from before it.
+#[derive(Clone)]
+pub struct TimezoneShiftTransformation {
+    tz: Tz,
+    direction: TimezoneDirection,
+}
+
+impl TimezoneShiftTransformation {
+    /// Create a new timezone shift step
+    pub fn new(tz: Tz, direction: TimezoneDirection) -> Self {
+        Self { tz, direction }
+    }
+
+    fn shift(&self, timestamp: i64) -> MetricQueryResult<i64> {
+        let clock = DateTime::from_timestamp(timestamp, 0)
+            .ok_or_else(|| MetricQueryError::OperationFailed {
+                operation: "convert_timezone".to_string(),
+                reason: format!("timestamp {} is out of range", timestamp),
+            })?
+            .naive_utc();
+        let offset = |at| i64::from(self.tz.offset_from_utc_datetime(&at).fix().local_minus_utc());
+
+        let shifted = match self.direction {
+            TimezoneDirection::ToLocal => timestamp.checked_add(offset(clock)),
+            TimezoneDirection::FromLocal => match self.tz.from_local_datetime(&clock).earliest() {
+                Some(instant) => Some(instant.timestamp()),
+                // DST changes are months apart, so a day earlier is safely before the gap
+                None => timestamp.checked_sub(offset(clock - Duration::days(1))),
+            },
+        };
+        shifted.ok_or_else(|| MetricQueryError::ArithmeticOverflow { operation: "convert_timezone".to_string() })
+    }
+}
+
+impl TransformationStrategy for TimezoneShiftTransformation {
+    fn name(&self) -> String {
+        format!("convert_timezone({})", self.tz.name())
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        metrics
+            .iter()
+            .map(|metric| Ok(Metric { timestamp: self.shift(metric.timestamp)?, ..metric.clone() }))
+            .collect()
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        metrics
+            .iter()
+            .map(|metric| Ok(FloatMetric { timestamp: self.shift(metric.timestamp)?, ..metric.clone() }))
+            .collect()
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashMap;
+
+use crate::decimal::align_scales;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::plugin_impls::aggregate_float_values;
+use crate::plugins::AggregationPlugin;
+use crate::transformations::TransformationStrategy;
+
+/// Keeps the `k` label series ranking highest by an aggregate of their values
+/// ("the 5 noisiest hosts").
+///
+/// Every point of a selected series is kept, in input order; unlabeled metrics count
+/// as one series. Series tied on the aggregate rank in order of first appearance.
+/// Fixed-point values are ranked at their largest scale, so mixed scales compare
+/// correctly; the kept points are unchanged.
+/// In float execution missing values follow the `Settings` missing-value policy like
+/// any other aggregation, and a series whose aggregate comes out missing ranks last.
+#[derive(Clone)]
+pub struct TopKSeriesTransformation {
+    k: usize,
+    aggregation: Box<dyn AggregationPlugin>,
+}
+
+impl TopKSeriesTransformation {
+    /// Create a new top-k series step
+    pub fn new(k: usize, aggregation: Box<dyn AggregationPlugin>) -> MetricQueryResult<Self> {
+        if k == 0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "topk_series".to_string(),
+                reason: "k must be at least 1".to_string(),
+            });
+        }
+        Ok(Self { k, aggregation })
+    }
+
+    /// Labels of the selected series, given each metric's label and value
+    fn select<'a, T>(
+        &self,
+        points: impl Iterator<Item = (Option<&'a str>, T)>,
+        score: impl Fn(&[T]) -> MetricQueryResult<f64>,
+    ) -> MetricQueryResult<Vec<Option<&'a str>>> {
+        let mut order: Vec<Option<&str>> = Vec::new();
+        let mut series: HashMap<Option<&str>, Vec<T>> = HashMap::new();
+        for (label, value) in points {
+            series
+                .entry(label)
+                .or_insert_with(|| {
+                    order.push(label);
+                    Vec::new()
+                })
+                .push(value);
+        }
+
+        let mut ranked = order
+            .into_iter()
+            .map(|label| Ok((label, score(&series[&label])?)))
+            .collect::<MetricQueryResult<Vec<_>>>()?;
+        // Stable, so ties keep their order of first appearance
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(self.k);
+        Ok(ranked.into_iter().map(|(label, _)| label).collect())
+    }
+}
+
+impl TransformationStrategy for TopKSeriesTransformation {
+    fn name(&self) -> String {
+        format!("topk_series({}/{})", self.k, self.aggregation.name())
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let (aligned, _) = align_scales(metrics)?;
+        let points = aligned.iter().map(|m| (m.label.as_deref(), m.value));
+        let selected = self.select(points, |values| self.aggregation.apply_values(values).map(|v| v as f64))?;
+        Ok(metrics.iter().filter(|m| selected.contains(&m.label.as_deref())).cloned().collect())
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let points = metrics.iter().map(|m| (m.label.as_deref(), m.value));
+        let selected = self.select(points, |values| {
+            let score = aggregate_float_values(&*self.aggregation, values)?;
+            Ok(if score.is_nan() { f64::NEG_INFINITY } else { score })
+        })?;
+        Ok(metrics.iter().filter(|m| selected.contains(&m.label.as_deref())).cloned().collect())
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.aggregation.thread_bound()
+    }
+}
@@ -0,0 +1,126 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::steps::series::{seconds_between, to_metrics, Series};
+use crate::transformations::TransformationStrategy;
+
+/// What the trend step emits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrendOutput {
+    /// The fitted value at every input timestamp, labeled "trend"
+    Line,
+    /// Two metrics labeled "slope" and "intercept", stamped with the last timestamp
+    Summary,
+}
+
+impl TrendOutput {
+    /// Parse an output mode ("line" or "summary")
+    pub fn parse(output: &str) -> MetricQueryResult<Self> {
+        match output {
+            "line" => Ok(Self::Line),
+            "summary" => Ok(Self::Summary),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "trend".to_string(),
+                reason: format!("Unknown trend output: {}. Expected 'line' or 'summary'", output),
+            }),
+        }
+    }
+}
+
+/// Least-squares linear trend of value over timestamp.
+///
+/// The slope is reported per `per_seconds` seconds and the intercept is the fitted
+/// value at the first timestamp, since the value at the Unix epoch is rarely useful.
+#[derive(Clone)]
+pub struct TrendTransformation {
+    output: TrendOutput,
+    per_seconds: i64,
+}
+
+impl TrendTransformation {
+    /// Create a new trend step with the slope expressed per second
+    pub fn new(output: TrendOutput) -> Self {
+        Self { output, per_seconds: 1 }
+    }
+
+    /// Express the slope per `per_seconds` seconds (e.g. 3600 for "per hour"), failing
+    /// unless it is positive
+    pub fn with_slope_unit(mut self, per_seconds: i64) -> MetricQueryResult<Self> {
+        if per_seconds <= 0 {
+            return Err(self.failure(format!("Slope unit must be positive, got {}", per_seconds)));
+        }
+        self.per_seconds = per_seconds;
+        Ok(self)
+    }
+
+    fn fit(&self, series: &Series) -> MetricQueryResult<Vec<FloatMetric>> {
+        if series.values.iter().any(|v| v.is_nan()) {
+            return Err(self.failure("Series contains missing values; fill them first".to_string()));
+        }
+        if series.len() < 2 {
+            return Err(self.failure("At least two metrics are needed to fit a trend".to_string()));
+        }
+
+        // Measure time from the first point to keep the sums well conditioned
+        let origin = series.timestamps[0];
+        let xs: Vec<f64> = series.timestamps.iter().map(|&t| seconds_between(origin, t)).collect();
+        let n = xs.len() as f64;
+        let mean_x = xs.iter().sum::<f64>() / n;
+        let mean_y = series.values.iter().sum::<f64>() / n;
+
+        let mut covariance = 0.0;
+        let mut variance = 0.0;
+        for (x, y) in xs.iter().zip(&series.values) {
+            covariance += (x - mean_x) * (y - mean_y);
+            variance += (x - mean_x).powi(2);
+        }
+        if variance == 0.0 {
+            return Err(self.failure("All metrics share one timestamp; the trend is undefined".to_string()));
+        }
+
+        let slope = covariance / variance;
+        let intercept = mean_y - slope * mean_x;
+
+        Ok(match self.output {
+            TrendOutput::Line => xs
+                .iter()
+                .zip(&series.timestamps)
+                .map(|(x, &timestamp)| FloatMetric {
+                    value: intercept + slope * x,
+                    timestamp,
+                    label: Some("trend".to_string()),
+                    unit: None,
+                })
+                .collect(),
+            TrendOutput::Summary => {
+                let last = series.timestamps[series.len() - 1];
+                vec![
+                    FloatMetric {
+                        value: slope * self.per_seconds as f64,
+                        timestamp: last,
+                        label: Some("slope".to_string()),
+                        unit: None,
+                    },
+                    FloatMetric::new(Some(intercept), last, Some("intercept".to_string())),
+                ]
+            }
+        })
+    }
+
+    fn failure(&self, reason: String) -> MetricQueryError {
+        MetricQueryError::OperationFailed { operation: "trend".to_string(), reason }
+    }
+}
+
+impl TransformationStrategy for TrendTransformation {
+    fn name(&self) -> String {
+        "trend".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        Ok(to_metrics(self.fit(&Series::from_metrics(metrics))?))
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        self.fit(&Series::from_float_metrics(metrics))
+    }
+}
@@ -0,0 +1,85 @@
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::{FloatMetric, Metric};
+use crate::transformations::TransformationStrategy;
+use crate::units::conversion_factor;
+
+/// Rescales values from one unit to another, e.g. "ms" to "s" or "bytes" to "MiB".
+///
+/// Metrics in `from` are converted and tagged with `to`; metrics already in `to` pass
+/// through. Any other unit is an error rather than a silent mix of scales, and so is
+/// a metric without a unit unless `with_assume_unit` says it is in `from`. Integer
+/// values are rounded to the nearest whole unit.
+#[derive(Clone)]
+pub struct ConvertUnitTransformation {
+    from: String,
+    to: String,
+    factor: f64,
+    assume_unit: bool,
+}
+
+impl ConvertUnitTransformation {
+    /// Create a conversion step, failing if the units don't convert into each other
+    pub fn new(from: impl Into<String>, to: impl Into<String>) -> MetricQueryResult<Self> {
+        let (from, to) = (from.into(), to.into());
+        let factor = conversion_factor(&from, &to)?;
+        Ok(Self { from, to, factor, assume_unit: false })
+    }
+
+    /// Treat metrics without a unit as being in `from` instead of failing on them
+    pub fn with_assume_unit(mut self, assume_unit: bool) -> Self {
+        self.assume_unit = assume_unit;
+        self
+    }
+
+    /// Whether a metric in `unit` is converted (true) or passed through (false)
+    fn converts(&self, unit: Option<&str>) -> MetricQueryResult<bool> {
+        match unit {
+            None if self.assume_unit => Ok(true),
+            None => Err(MetricQueryError::OperationFailed {
+                operation: "convert_unit".to_string(),
+                reason: format!("found a metric without a unit; tag it or assume it is in '{}'", self.from),
+            }),
+            Some(unit) if unit == self.from => Ok(true),
+            Some(unit) if unit == self.to => Ok(false),
+            Some(unit) => Err(MetricQueryError::OperationFailed {
+                operation: "convert_unit".to_string(),
+                reason: format!("expected metrics in '{}', found '{}'", self.from, unit),
+            }),
+        }
+    }
+}
+
+impl TransformationStrategy for ConvertUnitTransformation {
+    fn name(&self) -> String {
+        format!("convert_unit({}->{})", self.from, self.to)
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        metrics
+            .iter()
+            .map(|metric| {
+                if !self.converts(metric.unit.as_deref())? {
+                    return Ok(metric.clone());
+                }
+                let value = (metric.value as f64 * self.factor).round();
+                // `as` saturates, so check the range before converting back
+                if !(i64::MIN as f64..=i64::MAX as f64).contains(&value) {
+                    return Err(MetricQueryError::ArithmeticOverflow { operation: "convert_unit".to_string() });
+                }
+                Ok(Metric { value: value as i64, unit: Some(self.to.clone()), ..metric.clone() })
+            })
+            .collect()
+    }
+
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        metrics
+            .iter()
+            .map(|metric| {
+                if !self.converts(metric.unit.as_deref())? {
+                    return Ok(metric.clone());
+                }
+                Ok(FloatMetric { value: metric.value * self.factor, unit: Some(self.to.clone()), ..metric.clone() })
+            })
+            .collect()
+    }
+}
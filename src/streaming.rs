@@ -0,0 +1,312 @@
+//! Incremental window results pushed to subscribers as metrics arrive.
+//!
+//! `StreamingProcessor` buffers incoming metrics into tumbling windows of a fixed
+//! number of seconds, aligned to the epoch; the window is converted to the
+//! pipeline's timestamp precision, so a millisecond stream gets windows of the
+//! same length as a seconds one. A window closes once a metric at or past
+//! its end has been pushed; its metrics then run through a compiled pipeline and the
+//! result is handed to every subscriber, so a live dashboard can subscribe rather
+//! than poll `execute()`. Metrics arriving for a window that already closed are
+//! dropped and counted. A window only closes once its pipeline run and every
+//! subscriber succeed; after a failure it stays open and is retried by the next
+//! `push` or `flush`, which also returns the updates of windows closed before it.
+//!
+//! Updates are delta encoded against the previous window, per series (label and
+//! tags): only
+//! series whose latest value changed are reported, each with the difference, and
+//! series with no output in the new window are listed as removed. The JSON form
+//! (`spec` feature) is meant to be written as-is to a WebSocket or event stream.
+
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::compiled::CompiledPipeline;
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::Metric;
+#[cfg(feature = "python")]
+use crate::sandbox::call_plugin;
+#[cfg(feature = "python")]
+use crate::time_range::parse_duration;
+#[cfg(feature = "python")]
+use crate::transformations::MetricPipeline;
+
+/// A series of the stream: its label and tags
+#[cfg_attr(feature = "python", pyclass(get_all, frozen))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SeriesId {
+    pub label: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeMap::is_empty"))]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// The latest value of one series in a closed window
+#[cfg_attr(feature = "python", pyclass(get_all, frozen))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeriesChange {
+    pub label: Option<String>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "BTreeMap::is_empty"))]
+    pub tags: BTreeMap<String, String>,
+    pub value: i64,
+    pub timestamp: i64,
+    /// `value` minus the series' value in the previous update, or `None` for a new series
+    pub delta: Option<i64>,
+}
+
+/// The result of one closed window, pushed to subscribers
+#[cfg_attr(feature = "python", pyclass(get_all, frozen))]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowUpdate {
+    /// First timestamp of the window (inclusive)
+    pub start: i64,
+    /// End of the window (exclusive)
+    pub end: i64,
+    /// Series whose value changed, or every series when delta encoding is off
+    pub changed: Vec<SeriesChange>,
+    /// Series reported before that have no output in this window
+    pub removed: Vec<SeriesId>,
+}
+
+impl WindowUpdate {
+    /// Whether the window changed nothing since the previous update
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.removed.is_empty()
+    }
+
+    /// The update as JSON: `{"start", "end", "changed": [{"label", "tags", "value", "timestamp", "delta"}],
+    /// "removed": [{"label", "tags"}]}`, leaving out empty tags
+    #[cfg(feature = "spec")]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("window updates always serialize")
+    }
+}
+
+/// Callback receiving every window update; an error is returned from the `push` that closed the window
+pub type UpdateCallback = Arc<dyn Fn(&WindowUpdate) -> MetricQueryResult<()> + Send + Sync>;
+
+/// Runs a pipeline over tumbling windows of a metric stream and pushes each result
+#[cfg_attr(feature = "python", pyclass)]
+pub struct StreamingProcessor {
+    pipeline: CompiledPipeline,
+    window: i64,
+    // The window in the stream's timestamp units
+    span: i64,
+    delta_encoding: bool,
+    subscribers: Vec<UpdateCallback>,
+    // Buffered metrics of the windows still open, by window start
+    open: BTreeMap<i64, Vec<Metric>>,
+    // End of the latest closed window; earlier metrics are late
+    closed_until: Option<i64>,
+    // Latest value of each series in the previous update
+    previous: BTreeMap<SeriesId, i64>,
+    // Updates of windows closed by a call that then failed on a later window
+    pending: Vec<WindowUpdate>,
+    late: usize,
+}
+
+impl StreamingProcessor {
+    /// Run `pipeline` over windows of `window` seconds, in timestamps of the precision
+    /// in its settings; its own input metrics are ignored
+    pub fn new(pipeline: CompiledPipeline, window: i64) -> MetricQueryResult<Self> {
+        if window <= 0 {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "streaming".to_string(),
+                reason: format!("Window must be a positive number of seconds, got {}", window),
+            });
+        }
+        let span = pipeline.settings().timestamp_precision.from_seconds(window)?;
+        Ok(Self {
+            pipeline,
+            window,
+            span,
+            delta_encoding: true,
+            subscribers: Vec::new(),
+            open: BTreeMap::new(),
+            closed_until: None,
+            previous: BTreeMap::new(),
+            pending: Vec::new(),
+            late: 0,
+        })
+    }
+
+    /// List only the series that changed in each update (the default), or every series when off
+    pub fn with_delta_encoding(mut self, delta_encoding: bool) -> Self {
+        self.delta_encoding = delta_encoding;
+        self
+    }
+
+    /// Call `callback` with every update from now on
+    pub fn subscribe(&mut self, callback: impl Fn(&WindowUpdate) -> MetricQueryResult<()> + Send + Sync + 'static) {
+        self.subscribers.push(Arc::new(callback));
+    }
+
+    /// Window length in seconds
+    pub fn window(&self) -> i64 {
+        self.window
+    }
+
+    /// Number of metrics dropped because their window had already closed
+    pub fn late_metrics(&self) -> usize {
+        self.late
+    }
+
+    /// Start of the window containing `timestamp`
+    fn window_start(&self, timestamp: i64) -> i64 {
+        timestamp.saturating_sub(timestamp.rem_euclid(self.span))
+    }
+
+    /// Buffer `metrics` and close every window that ended before the latest of them,
+    /// returning the updates pushed to subscribers, oldest window first
+    pub fn push(&mut self, metrics: impl IntoIterator<Item = Metric>) -> MetricQueryResult<Vec<WindowUpdate>> {
+        let mut watermark = None;
+        for metric in metrics {
+            if self.closed_until.is_some_and(|closed| metric.timestamp < closed) {
+                self.late += 1;
+                continue;
+            }
+            watermark = watermark.max(Some(metric.timestamp));
+            self.open.entry(self.window_start(metric.timestamp)).or_default().push(metric);
+        }
+        match watermark {
+            Some(watermark) => self.close_through(self.window_start(watermark)),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Close every open window, e.g. when the stream ends
+    pub fn flush(&mut self) -> MetricQueryResult<Vec<WindowUpdate>> {
+        self.close_through(i64::MAX)
+    }
+
+    /// Close the open windows starting before `until`, along with returning any
+    /// updates a failed call left behind.
+    ///
+    /// A window is only removed once its run and every subscriber succeed, so a
+    /// failure leaves it open to be retried; subscribers that already took its update
+    /// then see it again.
+    fn close_through(&mut self, until: i64) -> MetricQueryResult<Vec<WindowUpdate>> {
+        let settings = self.pipeline.settings();
+        while let Some((&start, metrics)) = self.open.first_key_value() {
+            if start >= until {
+                break;
+            }
+            let end = start.saturating_add(self.span);
+            let (result, _) = self.pipeline.run_metrics_with_warnings(metrics)?;
+            let (update, latest) = self.encode(start, end, &result);
+            settings.scope(|| self.subscribers.iter().try_for_each(|subscriber| subscriber(&update)))?;
+            self.open.remove(&start);
+            self.closed_until = Some(end);
+            self.previous = latest;
+            self.pending.push(update);
+        }
+        Ok(std::mem::take(&mut self.pending))
+    }
+
+    /// Delta-encode a window's result against the previous update, also returning
+    /// the latest value of each series for the next one
+    fn encode(&self, start: i64, end: i64, result: &[Metric]) -> (WindowUpdate, BTreeMap<SeriesId, i64>) {
+        let mut latest: BTreeMap<SeriesId, &Metric> = BTreeMap::new();
+        for metric in result {
+            latest.insert(SeriesId { label: metric.label.clone(), tags: metric.tags.clone() }, metric);
+        }
+        let removed = self.previous.keys().filter(|series| !latest.contains_key(*series)).cloned().collect();
+        let changed = latest
+            .iter()
+            .filter_map(|(series, metric)| {
+                let previous = self.previous.get(series).copied();
+                if self.delta_encoding && previous == Some(metric.value) {
+                    return None;
+                }
+                Some(SeriesChange {
+                    label: series.label.clone(),
+                    tags: series.tags.clone(),
+                    value: metric.value,
+                    timestamp: metric.timestamp,
+                    delta: previous.map(|previous| metric.value.saturating_sub(previous)),
+                })
+            })
+            .collect();
+        let latest = latest.into_iter().map(|(series, metric)| (series, metric.value)).collect();
+        (WindowUpdate { start, end, changed, removed }, latest)
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl WindowUpdate {
+    #[cfg(feature = "spec")]
+    #[pyo3(name = "to_json")]
+    fn py_to_json(&self) -> String {
+        self.to_json()
+    }
+
+    fn __bool__(&self) -> bool {
+        !self.is_empty()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "WindowUpdate(start={}, end={}, changed={}, removed={})",
+            self.start,
+            self.end,
+            self.changed.len(),
+            self.removed.len()
+        )
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl StreamingProcessor {
+    /// Run `pipeline` (`MetricPipeline` or `CompiledPipeline`) over tumbling windows of
+    /// `window`, a duration such as "1m" or a number of seconds; with `delta=False`
+    /// every update lists all series instead of only the changed ones
+    #[new]
+    #[pyo3(signature = (pipeline, window, delta=true))]
+    fn py_new(pipeline: &Bound<'_, PyAny>, window: &Bound<'_, PyAny>, delta: bool) -> PyResult<Self> {
+        let pipeline = match pipeline.downcast::<CompiledPipeline>() {
+            Ok(compiled) => compiled.get().clone(),
+            Err(_) => CompiledPipeline::new(&*pipeline.extract::<PyRef<MetricPipeline>>()?),
+        };
+        let window = match window.extract::<i64>() {
+            Ok(seconds) => seconds,
+            Err(_) => parse_duration(window.extract()?)?,
+        };
+        Ok(Self::new(pipeline, window)?.with_delta_encoding(delta))
+    }
+
+    /// Call `callback` with a `WindowUpdate` each time a window closes. Like a Python
+    /// plugin, it runs under the pipeline's `plugin_timeout` and `trusted_only`
+    /// settings, and an exception it raises fails the `push` or `flush` that closed
+    /// the window, which then stays open
+    #[pyo3(name = "subscribe")]
+    fn py_subscribe(&mut self, callback: PyObject) {
+        let callback = Arc::new(callback);
+        self.subscribe(move |update| {
+            let (callback, update) = (Arc::clone(&callback), update.clone());
+            call_plugin("subscriber", move |py| callback.call1(py, (update,)).map(|_| ()))
+        });
+    }
+
+    /// Add metrics to the stream and return the updates of the windows this closed
+    #[pyo3(name = "push")]
+    fn py_push(&mut self, metrics: Vec<Metric>) -> PyResult<Vec<WindowUpdate>> {
+        Ok(self.push(metrics)?)
+    }
+
+    /// Close every open window and return their updates
+    #[pyo3(name = "flush")]
+    fn py_flush(&mut self) -> PyResult<Vec<WindowUpdate>> {
+        Ok(self.flush()?)
+    }
+
+    /// Number of metrics dropped because their window had already closed
+    #[getter(late_metrics)]
+    fn py_late_metrics(&self) -> usize {
+        self.late
+    }
+}
@@ -1,16 +1,14 @@
-use crate::errors::MetricQueryResult;
 use crate::models::Metric;
 use crate::plugin_impls::{
     create_aggregation, create_filter, create_time_grouping,
     AvgAggregation, DayGrouping, EqualFilter, GreaterThanFilter, HourGrouping, MaxAggregation,
-    MinAggregation, MinuteGrouping, SumAggregation,
+    MinAggregation, SumAggregation,
 };
-use crate::plugins::{AggregationPlugin, FilterPlugin, TimeGroupingPlugin};
 use crate::transformations::{
     AggregationTransformation, FilterTransformation, MetricPipeline, TimeGroupingTransformation,
     TransformationStrategy,
 };
-use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use chrono::{TimeZone, Utc};
 
 #[cfg(test)]
 mod test_filters {
@@ -18,11 +16,11 @@ mod test_filters {
 
     fn create_test_metrics() -> Vec<Metric> {
         vec![
-            Metric::new(10, 1000),
-            Metric::new(20, 2000),
-            Metric::new(30, 3000),
-            Metric::new(15, 4000),
-            Metric::new(25, 5000),
+            Metric::new(10, 1000, None),
+            Metric::new(20, 2000, None),
+            Metric::new(30, 3000, None),
+            Metric::new(15, 4000, None),
+            Metric::new(25, 5000, None),
         ]
     }
 
@@ -81,17 +79,17 @@ mod test_aggregations {
     
     fn create_test_metrics() -> Vec<Metric> {
         vec![
-            Metric::new(10, 1000),
-            Metric::new(20, 2000),
-            Metric::new(30, 3000),
-            Metric::new(40, 4000),
+            Metric::new(10, 1000, None),
+            Metric::new(20, 2000, None),
+            Metric::new(30, 3000, None),
+            Metric::new(40, 4000, None),
         ]
     }
     
     #[test]
     fn test_sum_aggregation() {
         let metrics = create_test_metrics();
-        let aggregation = SumAggregation;
+        let aggregation = SumAggregation::default();
         let transformer = AggregationTransformation::new(Box::new(aggregation));
         
         let result = transformer.apply(&metrics).unwrap();
@@ -103,7 +101,7 @@ mod test_aggregations {
     #[test]
     fn test_avg_aggregation() {
         let metrics = create_test_metrics();
-        let aggregation = AvgAggregation;
+        let aggregation = AvgAggregation::default();
         let transformer = AggregationTransformation::new(Box::new(aggregation));
         
         let result = transformer.apply(&metrics).unwrap();
@@ -167,13 +165,13 @@ mod test_time_groupings {
     fn create_test_metrics() -> Vec<Metric> {
         vec![
             // 2023-01-01 10:15:30
-            Metric::new(10, timestamp(2023, 1, 1, 10, 15, 30)),
+            Metric::new(10, timestamp(2023, 1, 1, 10, 15, 30), None),
             // 2023-01-01 10:30:45
-            Metric::new(20, timestamp(2023, 1, 1, 10, 30, 45)),
+            Metric::new(20, timestamp(2023, 1, 1, 10, 30, 45), None),
             // 2023-01-01 11:15:30
-            Metric::new(30, timestamp(2023, 1, 1, 11, 15, 30)),
+            Metric::new(30, timestamp(2023, 1, 1, 11, 15, 30), None),
             // 2023-01-02 10:15:30
-            Metric::new(40, timestamp(2023, 1, 2, 10, 15, 30)),
+            Metric::new(40, timestamp(2023, 1, 2, 10, 15, 30), None),
         ]
     }
     
@@ -181,7 +179,7 @@ mod test_time_groupings {
     fn test_hour_grouping() {
         let metrics = create_test_metrics();
         let time_grouping = HourGrouping;
-        let aggregation = SumAggregation;
+        let aggregation = SumAggregation::default();
         let transformer = TimeGroupingTransformation::new(
             Box::new(time_grouping),
             Box::new(aggregation),
@@ -216,7 +214,7 @@ mod test_time_groupings {
     fn test_day_grouping() {
         let metrics = create_test_metrics();
         let time_grouping = DayGrouping;
-        let aggregation = SumAggregation;
+        let aggregation = SumAggregation::default();
         let transformer = TimeGroupingTransformation::new(
             Box::new(time_grouping),
             Box::new(aggregation),
@@ -245,7 +243,7 @@ mod test_time_groupings {
     #[test]
     fn test_time_grouping_factory() {
         let metrics = create_test_metrics();
-        let aggregation = SumAggregation;
+        let aggregation = SumAggregation::default();
         
         // Test hour grouping
         let hour_group = create_time_grouping("hour").unwrap();
@@ -265,9 +263,102 @@ mod test_time_groupings {
         let day_result = day_transformer.apply(&metrics).unwrap();
         assert_eq!(day_result.len(), 2);
     }
+
+    #[test]
+    fn test_grouping_keeps_labels_apart() {
+        let hour = timestamp(2023, 1, 1, 10, 0, 0);
+        let metrics = vec![
+            Metric::new(10, hour + 60, Some("cpu".to_string())),
+            Metric::new(20, hour + 120, Some("cpu".to_string())),
+            Metric::new(100, hour + 60, Some("memory".to_string())),
+            Metric::new(5, hour + 180, None),
+        ];
+        let transformer = TimeGroupingTransformation::new(
+            Box::new(HourGrouping),
+            Box::new(SumAggregation::default()),
+        );
+
+        let mut result = transformer.apply(&metrics).unwrap();
+        result.sort_by(|a, b| a.label.cmp(&b.label));
+        let groups: Vec<(Option<&str>, i64, i64)> =
+            result.iter().map(|m| (m.label.as_deref(), m.timestamp, m.value)).collect();
+        assert_eq!(groups, vec![(None, hour, 5), (Some("cpu"), hour, 30), (Some("memory"), hour, 100)]);
+
+        let floats: Vec<crate::models::FloatMetric> = metrics.iter().map(Into::into).collect();
+        let mut float_result = transformer.apply_float(&floats).unwrap();
+        float_result.sort_by(|a, b| a.label.cmp(&b.label));
+        assert_eq!(float_result[1].label.as_deref(), Some("cpu"));
+        assert_eq!(float_result[1].value, 30.0);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_sharded_grouping_matches_sequential() {
+        use crate::transformations::PARALLEL_GROUPING_THRESHOLD;
+        use std::collections::HashMap;
+
+        // Large enough to take the sharded path, spread over many minutes
+        let count = PARALLEL_GROUPING_THRESHOLD as i64 * 4;
+        let labels = [Some("a".to_string()), Some("b".to_string()), None];
+        let metrics: Vec<Metric> = (0..count)
+            .map(|i| Metric::new(i % 97, timestamp(2023, 1, 1, 0, 0, 0) + i * 7, labels[i as usize % 3].clone()))
+            .collect();
+
+        let mut expected: HashMap<(Option<String>, i64), i64> = HashMap::new();
+        for m in &metrics {
+            *expected.entry((m.label.clone(), m.timestamp - m.timestamp.rem_euclid(60))).or_default() += m.value;
+        }
+
+        let transformer = TimeGroupingTransformation::new(
+            Box::new(crate::plugin_impls::MinuteGrouping),
+            Box::new(SumAggregation::default()),
+        );
+        let result = transformer.apply(&metrics).unwrap();
+
+        assert_eq!(result.len(), expected.len());
+        for m in result {
+            assert_eq!(expected[&(m.label, m.timestamp)], m.value);
+        }
+    }
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn test_gpu_grouping_matches_cpu() {
+        use crate::gpu::GPU_GROUPING_THRESHOLD;
+        use crate::plugin_impls::{AvgAggregation, FnAggregation, MaxAggregation, MinAggregation, RoundingMode};
+        use crate::plugins::AggregationPlugin;
+
+        // Large enough to take the GPU path, with values of both signs and one group per label and hour
+        let count = GPU_GROUPING_THRESHOLD as i64 + 1000;
+        let labels = [Some("a".to_string()), Some("b".to_string()), None];
+        let metrics: Vec<Metric> = (0..count)
+            .map(|i| Metric::new((i * 7919) % 20011 - 10000, timestamp(2023, 1, 1, 0, 0, 0) + i, labels[i as usize % 3].clone()))
+            .collect();
+
+        let builtins: Vec<(Box<dyn AggregationPlugin>, FnAggregation)> = vec![
+            (Box::new(SumAggregation::default()), FnAggregation::new("cpu_sum", |m| m.iter().map(|m| m.value).sum())),
+            (
+                Box::new(AvgAggregation::new(RoundingMode::Floor)),
+                FnAggregation::new("cpu_avg", |m| m.iter().map(|m| m.value).sum::<i64>().div_euclid(m.len() as i64)),
+            ),
+            (Box::new(MinAggregation), FnAggregation::new("cpu_min", |m| m.iter().map(|m| m.value).min().unwrap())),
+            (Box::new(MaxAggregation), FnAggregation::new("cpu_max", |m| m.iter().map(|m| m.value).max().unwrap())),
+        ];
+        for (builtin, reference) in builtins {
+            let grouped = |aggregation: Box<dyn AggregationPlugin>| {
+                let transformer = TimeGroupingTransformation::new(Box::new(crate::plugin_impls::HourGrouping), aggregation);
+                let mut result: Vec<(Option<String>, i64, i64)> =
+                    transformer.apply(&metrics).unwrap().into_iter().map(|m| (m.label, m.timestamp, m.value)).collect();
+                result.sort_unstable();
+                result
+            };
+            assert_eq!(grouped(builtin), grouped(Box::new(reference)));
+        }
+    }
 }
 
-#[cfg(test)]
+// The pipeline's `execute` entry point is part of the Python API
+#[cfg(all(test, feature = "python"))]
 mod test_pipeline {
     use super::*;
     
@@ -280,17 +371,17 @@ mod test_pipeline {
     fn create_test_metrics() -> Vec<Metric> {
         vec![
             // 2023-01-01 10:15:30
-            Metric::new(10, timestamp(2023, 1, 1, 10, 15, 30)),
+            Metric::new(10, timestamp(2023, 1, 1, 10, 15, 30), None),
             // 2023-01-01 10:30:45
-            Metric::new(20, timestamp(2023, 1, 1, 10, 30, 45)),
+            Metric::new(20, timestamp(2023, 1, 1, 10, 30, 45), None),
             // 2023-01-01 11:15:30
-            Metric::new(5, timestamp(2023, 1, 1, 11, 15, 30)),
+            Metric::new(5, timestamp(2023, 1, 1, 11, 15, 30), None),
             // 2023-01-01 11:45:00
-            Metric::new(15, timestamp(2023, 1, 1, 11, 45, 0)),
+            Metric::new(15, timestamp(2023, 1, 1, 11, 45, 0), None),
             // 2023-01-02 10:15:30
-            Metric::new(40, timestamp(2023, 1, 2, 10, 15, 30)),
+            Metric::new(40, timestamp(2023, 1, 2, 10, 15, 30), None),
             // 2023-01-02 10:45:30
-            Metric::new(50, timestamp(2023, 1, 2, 10, 45, 30)),
+            Metric::new(50, timestamp(2023, 1, 2, 10, 45, 30), None),
         ]
     }
     
@@ -300,10 +391,10 @@ mod test_pipeline {
         let mut pipeline = MetricPipeline::new(metrics);
         
         // Add filter for values > 15
-        pipeline.filter(Box::new(GreaterThanFilter::new(15))).unwrap();
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(15)));
         
         // Add sum aggregation
-        pipeline.aggregate(Box::new(SumAggregation)).unwrap();
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
         
         let result = pipeline.execute().unwrap();
         
@@ -317,13 +408,13 @@ mod test_pipeline {
         let mut pipeline = MetricPipeline::new(metrics);
         
         // Add filter for values > 10
-        pipeline.filter(Box::new(GreaterThanFilter::new(10))).unwrap();
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(10)));
         
         // Add time grouping by day with sum aggregation
-        pipeline.group_by_time(
+        pipeline.add_time_grouping(
             Box::new(DayGrouping),
-            Box::new(SumAggregation),
-        ).unwrap();
+            Box::new(SumAggregation::default()),
+        );
         
         let result = pipeline.execute().unwrap();
         
@@ -351,16 +442,16 @@ mod test_pipeline {
         let mut pipeline = MetricPipeline::new(metrics);
         
         // First filter values > 10
-        pipeline.filter(Box::new(GreaterThanFilter::new(10))).unwrap();
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(10)));
         
         // Group by hour with sum aggregation
-        pipeline.group_by_time(
+        pipeline.add_time_grouping(
             Box::new(HourGrouping),
-            Box::new(SumAggregation),
-        ).unwrap();
+            Box::new(SumAggregation::default()),
+        );
         
         // Then filter aggregated values > 30
-        pipeline.filter(Box::new(GreaterThanFilter::new(30))).unwrap();
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(30)));
         
         let result = pipeline.execute().unwrap();
         
@@ -372,4 +463,7216 @@ mod test_pipeline {
         assert_eq!(result[0].timestamp, hour_ts);
         assert_eq!(result[0].value, 90);
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod test_columnar {
+    use super::*;
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_execute_columns_matches_execute() {
+        let base = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap().timestamp();
+        let values: Vec<i64> = (0..500).map(|i| i % 40).collect();
+        let timestamps: Vec<i64> = (0..500).map(|i| base + i * 37).collect();
+        let metrics: Vec<Metric> = values
+            .iter()
+            .zip(&timestamps)
+            .map(|(&v, &t)| Metric::new(v, t, None))
+            .collect();
+
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(10)));
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+
+        let mut expected: Vec<(i64, i64)> = pipeline
+            .execute()
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.timestamp, m.value))
+            .collect();
+        expected.sort();
+
+        let (out_values, out_timestamps) = pipeline.execute_columns(&values, &timestamps).unwrap();
+        let mut actual: Vec<(i64, i64)> = out_timestamps.into_iter().zip(out_values).collect();
+        actual.sort();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn test_execute_columns_rejects_mismatched_lengths() {
+        let pipeline = MetricPipeline::new(vec![]);
+        assert!(pipeline.execute_columns(&[1, 2], &[1]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_float_metrics {
+    use super::*;
+    use crate::models::FloatMetric;
+
+    fn create_test_metrics() -> Vec<FloatMetric> {
+        vec![
+            FloatMetric::new(Some(0.25), 1000, None),
+            FloatMetric::new(Some(0.5), 2000, None),
+            FloatMetric::new(Some(0.75), 3000, None),
+            FloatMetric::new(Some(1.5), 4000, None),
+        ]
+    }
+
+    #[test]
+    fn test_float_filter_keeps_fractional_values() {
+        let mut pipeline = MetricPipeline::new(vec![]);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(0)));
+
+        let result = pipeline.execute_float_metrics(&create_test_metrics()).unwrap();
+
+        // Rounding 0.25 down to 0 would have dropped it
+        assert_eq!(result.len(), 4);
+    }
+
+    #[test]
+    fn test_float_aggregations() {
+        let metrics = create_test_metrics();
+
+        let avg = AggregationTransformation::new(Box::new(AvgAggregation::default()));
+        assert_eq!(avg.apply_float(&metrics).unwrap()[0].value, 0.75);
+
+        let sum = AggregationTransformation::new(Box::new(SumAggregation::default()));
+        assert_eq!(sum.apply_float(&metrics).unwrap()[0].value, 3.0);
+
+        let min = AggregationTransformation::new(Box::new(MinAggregation));
+        assert_eq!(min.apply_float(&metrics).unwrap()[0].value, 0.25);
+
+        let max = AggregationTransformation::new(Box::new(MaxAggregation));
+        assert_eq!(max.apply_float(&metrics).unwrap()[0].value, 1.5);
+    }
+
+    #[test]
+    fn test_float_time_grouping() {
+        let metrics = vec![
+            FloatMetric::new(Some(1.5), 0, None),
+            FloatMetric::new(Some(2.25), 30, None),
+            FloatMetric::new(Some(4.0), 3600, None),
+        ];
+        let transformer = TimeGroupingTransformation::new(
+            Box::new(HourGrouping),
+            Box::new(SumAggregation::default()),
+        );
+
+        let mut result = transformer.apply_float(&metrics).unwrap();
+        result.sort_by_key(|m| m.timestamp);
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].value, 3.75);
+        assert_eq!(result[1].value, 4.0);
+    }
+}
+
+#[cfg(test)]
+mod test_overflow_policy {
+    use super::*;
+    use crate::errors::MetricQueryError;
+    use crate::models::FloatMetric;
+    use crate::plugin_impls::OverflowPolicy;
+    use crate::plugins::AggregationPlugin;
+
+    #[test]
+    fn test_sum_overflow_errors_by_default() {
+        let result = SumAggregation::default().apply_values(&[i64::MAX, 1]);
+        assert!(matches!(result, Err(MetricQueryError::ArithmeticOverflow { .. })));
+    }
+
+    #[test]
+    fn test_sum_overflow_checks_the_total() {
+        // The running total overflows but the final sum fits
+        let sum = SumAggregation::new(OverflowPolicy::Error);
+        assert_eq!(sum.apply_values(&[i64::MAX, 10, -20]).unwrap(), i64::MAX - 10);
+        assert!(sum.apply_values(&[i64::MIN, -1, 1, -1]).is_err());
+    }
+
+    #[test]
+    fn test_sum_overflow_saturates() {
+        let sum = SumAggregation::new(OverflowPolicy::Saturate);
+        assert_eq!(sum.apply_values(&[i64::MAX, 1]).unwrap(), i64::MAX);
+        assert_eq!(sum.apply_values(&[i64::MIN, -1]).unwrap(), i64::MIN);
+    }
+
+    #[test]
+    fn test_totals_past_i64_need_the_float_path() {
+        // An integer result can't hold the total, so there is no policy promising one
+        assert!(OverflowPolicy::parse("promote").is_err());
+        let metrics = vec![FloatMetric::new(Some(i64::MAX as f64), 0, None); 2];
+        let total = AggregationTransformation::new(Box::new(SumAggregation::default())).apply_float(&metrics).unwrap();
+        assert_eq!(total[0].value, 2.0 * i64::MAX as f64);
+    }
+
+    #[test]
+    fn test_avg_does_not_overflow() {
+        assert_eq!(AvgAggregation::default().apply_values(&[i64::MAX, i64::MAX]).unwrap(), i64::MAX);
+    }
+}
+
+#[cfg(test)]
+mod test_avg_rounding {
+    use super::*;
+    use crate::plugin_impls::RoundingMode;
+    use crate::plugins::AggregationPlugin;
+
+    fn avg(mode: RoundingMode, values: &[i64]) -> i64 {
+        AvgAggregation::new(mode).apply_values(values).unwrap()
+    }
+
+    #[test]
+    fn test_avg_rounding_modes_positive() {
+        // 5 / 2 = 2.5
+        assert_eq!(avg(RoundingMode::Truncate, &[2, 3]), 2);
+        assert_eq!(avg(RoundingMode::Round, &[2, 3]), 3);
+        assert_eq!(avg(RoundingMode::Floor, &[2, 3]), 2);
+        assert_eq!(avg(RoundingMode::Ceil, &[2, 3]), 3);
+        // 4 / 3 = 1.33
+        assert_eq!(avg(RoundingMode::Round, &[1, 1, 2]), 1);
+    }
+
+    #[test]
+    fn test_avg_rounding_modes_negative() {
+        // -5 / 2 = -2.5
+        assert_eq!(avg(RoundingMode::Truncate, &[-2, -3]), -2);
+        assert_eq!(avg(RoundingMode::Round, &[-2, -3]), -3);
+        assert_eq!(avg(RoundingMode::Floor, &[-2, -3]), -3);
+        assert_eq!(avg(RoundingMode::Ceil, &[-2, -3]), -2);
+    }
+
+    #[test]
+    fn test_avg_float_output_is_exact() {
+        let avg = AvgAggregation::new(RoundingMode::Round);
+        assert_eq!(avg.apply_float_values(&[2.0, 3.0]).unwrap(), 2.5);
+    }
+}
+
+#[cfg(test)]
+mod test_timestamp_validation {
+    use super::*;
+    use crate::errors::MetricQueryError;
+    use crate::validation::{
+        validate_timestamps, TimestampRules, TimestampValidationMode,
+        TimestampValidationTransformation,
+    };
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn create_test_metrics() -> Vec<Metric> {
+        vec![
+            Metric::new(1, NOW - 60, None),
+            Metric::new(2, -5, None),
+            Metric::new(3, 0, None),
+            Metric::new(4, NOW + 100 * 365 * 24 * 3600, None),
+            Metric::new(5, NOW, None),
+        ]
+    }
+
+    #[test]
+    fn test_report_lists_offenders() {
+        let report = validate_timestamps(&create_test_metrics(), &TimestampRules::default(), NOW);
+
+        assert_eq!(report.checked, 5);
+        let found: Vec<(usize, &str)> = report
+            .issues
+            .iter()
+            .map(|issue| (issue.index, issue.reason.as_str()))
+            .collect();
+        assert_eq!(found, vec![(1, "negative"), (2, "zero"), (3, "far_future")]);
+    }
+
+    #[test]
+    fn test_rules_can_allow_zero_and_negative() {
+        let rules = TimestampRules {
+            allow_negative: true,
+            allow_zero: true,
+            max_future_seconds: None,
+        };
+        assert!(validate_timestamps(&create_test_metrics(), &rules, NOW).is_valid());
+    }
+
+    #[test]
+    fn test_reject_mode_fails_with_first_offender() {
+        let step = TimestampValidationTransformation::new(
+            TimestampRules::default(),
+            TimestampValidationMode::Reject,
+        );
+        match step.apply(&create_test_metrics()) {
+            Err(MetricQueryError::InvalidTimestamp { index, offenders, .. }) => {
+                assert_eq!(index, 1);
+                assert_eq!(offenders, 3);
+            }
+            other => panic!("expected InvalidTimestamp, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_drop_mode_removes_offenders() {
+        let step = TimestampValidationTransformation::new(
+            TimestampRules { max_future_seconds: None, ..TimestampRules::default() },
+            TimestampValidationMode::Drop,
+        );
+        let result = step.apply(&create_test_metrics()).unwrap();
+        let values: Vec<i64> = result.iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![1, 4, 5]);
+    }
+}
+
+#[cfg(test)]
+mod test_missing_values {
+    use super::*;
+    use crate::plugin_impls::{MissingValueAggregation, MissingValuePolicy};
+    use crate::plugins::AggregationPlugin;
+
+    fn sum_with(policy: MissingValuePolicy) -> MissingValueAggregation {
+        MissingValueAggregation::new(Box::new(SumAggregation::default()), policy)
+    }
+
+    #[test]
+    fn test_skip_ignores_missing_values() {
+        let sum = sum_with(MissingValuePolicy::Skip);
+        assert_eq!(sum.apply_float_values(&[1.0, f64::NAN, 2.0]).unwrap(), 3.0);
+        assert!(sum.apply_float_values(&[f64::NAN]).unwrap().is_nan());
+    }
+
+    #[test]
+    fn test_propagate_makes_result_missing() {
+        let sum = sum_with(MissingValuePolicy::Propagate);
+        assert!(sum.apply_float_values(&[1.0, f64::NAN]).unwrap().is_nan());
+        assert_eq!(sum.apply_float_values(&[1.0, 2.0]).unwrap(), 3.0);
+    }
+
+    #[test]
+    fn test_substitute_fills_missing_values() {
+        let avg = MissingValueAggregation::new(
+            Box::new(AvgAggregation::default()),
+            MissingValuePolicy::Substitute(0.0),
+        );
+        assert_eq!(avg.apply_float_values(&[3.0, f64::NAN]).unwrap(), 1.5);
+    }
+
+    #[test]
+    fn test_missing_policy_requires_fill_value_for_substitute() {
+        assert!(MissingValuePolicy::parse("substitute", None).is_err());
+        assert_eq!(
+            MissingValuePolicy::parse("substitute", Some(1.0)).unwrap(),
+            MissingValuePolicy::Substitute(1.0)
+        );
+    }
+
+    #[test]
+    fn test_pipeline_missing_policy_applies_to_every_aggregation() {
+        use crate::models::FloatMetric;
+        use crate::plugin_impls::{FnAggregation, HourGrouping};
+        use crate::settings::Settings;
+
+        let metrics = vec![
+            FloatMetric::new(Some(4.0), 0, None),
+            FloatMetric::new(None, 60, None),
+            FloatMetric::new(Some(2.0), 120, None),
+        ];
+        let summed = |missing: Option<MissingValuePolicy>, aggregation: Box<dyn AggregationPlugin>| {
+            let mut pipeline = MetricPipeline::new(Vec::new());
+            pipeline.add_time_grouping(Box::new(HourGrouping), aggregation);
+            pipeline.set_settings(Some(Settings { missing, ..Settings::DEFAULT }));
+            pipeline.execute_float_metrics(&metrics).unwrap()[0].value
+        };
+
+        assert!(summed(None, Box::<SumAggregation>::default()).is_nan());
+        assert_eq!(summed(Some(MissingValuePolicy::Skip), Box::<SumAggregation>::default()), 6.0);
+        assert_eq!(summed(Some(MissingValuePolicy::Substitute(1.0)), Box::<AvgAggregation>::default()), 7.0 / 3.0);
+        assert!(summed(Some(MissingValuePolicy::Propagate), Box::new(MaxAggregation)).is_nan());
+
+        // Plugins without float support would otherwise see the missing value as 0
+        let count = || Box::new(FnAggregation::new("count", |metrics| metrics.len() as i64)) as Box<dyn AggregationPlugin>;
+        assert_eq!(summed(None, count()), 3.0);
+        assert_eq!(summed(Some(MissingValuePolicy::Skip), count()), 2.0);
+
+        // An aggregation's own policy wins over the pipeline's
+        assert!(summed(Some(MissingValuePolicy::Skip), Box::new(sum_with(MissingValuePolicy::Propagate))).is_nan());
+    }
+}
+
+#[cfg(test)]
+mod test_structured_errors {
+    use super::*;
+    use crate::errors::MetricQueryError;
+    use crate::validation::{TimestampRules, TimestampValidationMode, TimestampValidationTransformation};
+    #[cfg(feature = "python")]
+    use pyo3::prelude::*;
+    #[cfg(feature = "python")]
+    use pyo3::PyErr;
+
+    fn failing_pipeline() -> MetricPipeline {
+        let mut pipeline = MetricPipeline::new(vec![
+            Metric::new(1, 1_700_000_000, None),
+            Metric::new(2, -1, None),
+        ]);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(0)));
+        pipeline.add_strategy(Box::new(TimestampValidationTransformation::new(
+            TimestampRules::default(),
+            TimestampValidationMode::Reject,
+        )));
+        pipeline
+    }
+
+    #[test]
+    fn test_run_annotates_failing_step() {
+        let err = failing_pipeline().run().unwrap_err();
+
+        assert_eq!(err.code(), "invalid_timestamp");
+        assert_eq!(err.step_index(), Some(1));
+        assert_eq!(err.plugin_name(), Some("validate_timestamps"));
+        assert_eq!(err.metric_index(), Some(1));
+        assert!(matches!(err.root(), MetricQueryError::InvalidTimestamp { .. }));
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_python_exception_carries_fields() {
+        pyo3::prepare_freethreaded_python();
+        let py_err: PyErr = failing_pipeline().run().unwrap_err().into();
+
+        Python::with_gil(|py| {
+            let value = py_err.value(py);
+            let code: String = value.getattr("code").unwrap().extract().unwrap();
+            let step: Option<usize> = value.getattr("step_index").unwrap().extract().unwrap();
+            let plugin: Option<String> = value.getattr("plugin").unwrap().extract().unwrap();
+            let metric: Option<usize> = value.getattr("metric_index").unwrap().extract().unwrap();
+
+            assert!(py_err.is_instance_of::<pyo3::exceptions::PyValueError>(py));
+            assert_eq!(code, "invalid_timestamp");
+            assert_eq!(step, Some(1));
+            assert_eq!(plugin.as_deref(), Some("validate_timestamps"));
+            assert_eq!(metric, Some(1));
+        });
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_overflow_maps_to_overflow_error() {
+        pyo3::prepare_freethreaded_python();
+        let err = MetricQueryError::ArithmeticOverflow { operation: "sum".to_string() }.at_step(2, "sum");
+        let py_err: PyErr = err.into();
+
+        Python::with_gil(|py| {
+            assert!(py_err.is_instance_of::<pyo3::exceptions::PyOverflowError>(py));
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_warnings {
+    use super::*;
+    use crate::validation::{TimestampRules, TimestampValidationMode, TimestampValidationTransformation};
+
+    #[test]
+    fn test_dropped_metrics_are_reported() {
+        let mut pipeline = MetricPipeline::new(vec![
+            Metric::new(1, 1_700_000_000, None),
+            Metric::new(2, 0, None),
+            Metric::new(3, -10, None),
+        ]);
+        pipeline.add_strategy(Box::new(TimestampValidationTransformation::new(
+            TimestampRules::default(),
+            TimestampValidationMode::Drop,
+        )));
+
+        let (result, warnings) = pipeline.run_with_warnings().unwrap();
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].step, 0);
+        assert_eq!(warnings[0].plugin, "validate_timestamps");
+        assert_eq!(warnings[0].code, "dropped_invalid_timestamps");
+    }
+
+    #[test]
+    fn test_filter_to_empty_is_reported() {
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 1000, None)]);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(0)));
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(100)));
+
+        let (result, warnings) = pipeline.run_with_warnings().unwrap();
+
+        assert!(result.is_empty());
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].step, 1);
+        assert_eq!(warnings[0].code, "filtered_to_empty");
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_warnings_reach_python_warnings_module() {
+        use crate::warnings::{emit_python_warnings, PipelineWarning};
+        use pyo3::prelude::*;
+        use pyo3::types::{IntoPyDict, PyList};
+
+        pyo3::prepare_freethreaded_python();
+        let warning = PipelineWarning {
+            step: 1,
+            plugin: "gt".to_string(),
+            code: "filtered_to_empty",
+            message: "filter removed all 3 metrics".to_string(),
+        };
+
+        Python::with_gil(|py| {
+            let warnings_mod = py.import("warnings").unwrap();
+            let recorder = warnings_mod
+                .call_method("catch_warnings", (), Some(&[("record", true)].into_py_dict(py).unwrap()))
+                .unwrap();
+            let caught = recorder.call_method0("__enter__").unwrap();
+            warnings_mod.call_method1("simplefilter", ("always",)).unwrap();
+
+            emit_python_warnings(py, &[warning]).unwrap();
+
+            recorder.call_method1("__exit__", (py.None(), py.None(), py.None())).unwrap();
+            let caught = caught.downcast::<PyList>().unwrap();
+            assert_eq!(caught.len(), 1);
+            let message: String = caught.get_item(0).unwrap().getattr("message").unwrap().str().unwrap().extract().unwrap();
+            assert!(message.contains("filtered_to_empty"));
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_timestamp_policy {
+    use super::*;
+    use crate::transformations::TimestampPolicy;
+
+    fn create_test_metrics() -> Vec<Metric> {
+        vec![
+            Metric::new(1, 3000, None),
+            Metric::new(2, 1000, None),
+            Metric::new(3, 5000, None),
+            Metric::new(4, 2000, None),
+        ]
+    }
+
+    fn aggregate_timestamp(policy: TimestampPolicy) -> i64 {
+        let transformer = AggregationTransformation::new(Box::new(SumAggregation::default()))
+            .with_timestamp_policy(policy);
+        transformer.apply(&create_test_metrics()).unwrap()[0].timestamp
+    }
+
+    #[test]
+    fn test_timestamp_policies() {
+        assert_eq!(aggregate_timestamp(TimestampPolicy::First), 3000);
+        assert_eq!(aggregate_timestamp(TimestampPolicy::Last), 2000);
+        assert_eq!(aggregate_timestamp(TimestampPolicy::Min), 1000);
+        assert_eq!(aggregate_timestamp(TimestampPolicy::Max), 5000);
+        assert_eq!(aggregate_timestamp(TimestampPolicy::Midpoint), 3000);
+    }
+
+    #[test]
+    fn test_midpoint_handles_extreme_timestamps() {
+        let midpoint = TimestampPolicy::Midpoint.select([i64::MAX, i64::MAX - 2]);
+        assert_eq!(midpoint, Some(i64::MAX - 1));
+    }
+
+    #[test]
+    fn test_columnar_aggregation_uses_policy() {
+        let transformer = AggregationTransformation::new(Box::new(SumAggregation::default()))
+            .with_timestamp_policy(TimestampPolicy::Max);
+        let (values, timestamps) = transformer.apply_columns(&[1, 2], &[20, 10]).unwrap();
+        assert_eq!(values, vec![3]);
+        assert_eq!(timestamps, vec![20]);
+    }
+}
+
+#[cfg(test)]
+mod test_deduplicate {
+    use super::*;
+    use crate::steps::{DeduplicateTransformation, DuplicateStrategy};
+
+    fn create_test_metrics() -> Vec<Metric> {
+        vec![
+            Metric::new(10, 1000, Some("a".to_string())),
+            Metric::new(20, 1000, Some("b".to_string())),
+            Metric::new(30, 1000, Some("a".to_string())),
+            Metric::new(40, 2000, Some("a".to_string())),
+        ]
+    }
+
+    fn deduplicated_values(strategy: DuplicateStrategy) -> Vec<i64> {
+        DeduplicateTransformation::new(strategy)
+            .apply(&create_test_metrics())
+            .unwrap()
+            .iter()
+            .map(|m| m.value)
+            .collect()
+    }
+
+    #[test]
+    fn test_duplicate_strategies() {
+        assert_eq!(deduplicated_values(DuplicateStrategy::KeepFirst), vec![10, 20, 40]);
+        assert_eq!(deduplicated_values(DuplicateStrategy::KeepLast), vec![30, 20, 40]);
+        assert_eq!(deduplicated_values(DuplicateStrategy::Sum), vec![40, 20, 40]);
+        assert_eq!(deduplicated_values(DuplicateStrategy::Average), vec![20, 20, 40]);
+    }
+
+    #[test]
+    fn test_error_strategy_reports_duplicate() {
+        let err = DeduplicateTransformation::new(DuplicateStrategy::Error)
+            .apply(&create_test_metrics())
+            .unwrap_err();
+        assert_eq!(err.code(), "duplicate_timestamp");
+        assert_eq!(err.metric_index(), Some(2));
+    }
+
+    #[test]
+    fn test_deduplicate_before_grouping() {
+        let mut pipeline = MetricPipeline::new(create_test_metrics());
+        pipeline.add_strategy(Box::new(DeduplicateTransformation::new(DuplicateStrategy::KeepLast)));
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
+
+        let (result, warnings) = pipeline.run_with_warnings().unwrap();
+        assert_eq!(result[0].value, 90);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].code, "dropped_duplicates");
+    }
+
+    #[test]
+    fn test_unknown_strategy() {
+        assert!(DuplicateStrategy::parse("median").is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_anomalies {
+    use super::*;
+    use crate::models::FloatMetric;
+    use crate::steps::{AnomalyDetectionTransformation, AnomalyMethod, AnomalyOutput};
+
+    fn create_test_metrics() -> Vec<Metric> {
+        let values = [10, 11, 9, 10, 12, 10, 95, 11, 10, 9];
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| Metric::new(v, 1000 + i as i64 * 60, Some("cpu".to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_zscore_flags_spike() {
+        let step = AnomalyDetectionTransformation::new(AnomalyMethod::ZScore, 2.5).unwrap();
+        let result = step.apply(&create_test_metrics()).unwrap();
+        assert_eq!(result.len(), 10);
+        let flagged: Vec<i64> = result
+            .iter()
+            .filter(|m| m.label.as_deref() == Some("cpu:anomaly"))
+            .map(|m| m.value)
+            .collect();
+        assert_eq!(flagged, vec![95]);
+    }
+
+    #[test]
+    fn test_mad_extract_with_window() {
+        let step = AnomalyDetectionTransformation::new(AnomalyMethod::Mad, 3.5)
+            .unwrap()
+            .with_window(5)
+            .unwrap()
+            .with_output(AnomalyOutput::Extract);
+        let result = step.apply(&create_test_metrics()).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].value, 95);
+        assert_eq!(result[0].timestamp, 1360);
+    }
+
+    #[test]
+    fn test_float_ignores_missing_values() {
+        let metrics = vec![
+            FloatMetric::new(Some(1.0), 0, None),
+            FloatMetric::new(None, 60, None),
+            FloatMetric::new(Some(1.0), 120, None),
+            FloatMetric::new(Some(50.0), 180, None),
+        ];
+        let step = AnomalyDetectionTransformation::new(AnomalyMethod::Mad, 3.0)
+            .unwrap()
+            .with_output(AnomalyOutput::Extract);
+        let result = step.apply_float(&metrics).unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].label.as_deref(), Some("anomaly"));
+    }
+
+    #[test]
+    fn test_invalid_parameters_fail_at_construction() {
+        assert!(AnomalyDetectionTransformation::new(AnomalyMethod::ZScore, 0.0).is_err());
+        assert!(AnomalyDetectionTransformation::new(AnomalyMethod::ZScore, f64::NAN).is_err());
+        let step = AnomalyDetectionTransformation::new(AnomalyMethod::ZScore, 2.5).unwrap();
+        assert!(step.with_window(0).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_decomposition {
+    use super::*;
+    use crate::models::FloatMetric;
+    use crate::steps::SeasonalDecompositionTransformation;
+
+    fn component<'a>(result: &'a [FloatMetric], label: &str) -> Vec<&'a FloatMetric> {
+        result.iter().filter(|m| m.label.as_deref() == Some(label)).collect()
+    }
+
+    #[test]
+    fn test_recovers_trend_and_season() {
+        // Linear trend plus a period-4 season of [3, -1, -3, 1]
+        let season = [3.0, -1.0, -3.0, 1.0];
+        let metrics: Vec<FloatMetric> = (0..16)
+            .map(|i| FloatMetric::new(Some(2.0 * i as f64 + season[i % 4]), i as i64 * 60, None))
+            .collect();
+
+        let result = SeasonalDecompositionTransformation::new(4).unwrap().apply_float(&metrics).unwrap();
+        assert_eq!(result.len(), 48);
+
+        let trend = component(&result, "trend");
+        let seasonal = component(&result, "seasonal");
+        let residual = component(&result, "residual");
+        for i in 2..14 {
+            assert!((trend[i].value - 2.0 * i as f64).abs() < 1e-9);
+            assert!(residual[i].value.abs() < 1e-9);
+        }
+        for (i, metric) in seasonal.iter().enumerate() {
+            assert_eq!(metric.timestamp, i as i64 * 60);
+            assert!((metric.value - season[i % 4]).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_components_sum_to_input() {
+        let metrics: Vec<Metric> = (0..12)
+            .map(|i| Metric::new([10, 20, 15][i % 3] + i as i64, 1000 + i as i64 * 10, None))
+            .collect();
+        let result = SeasonalDecompositionTransformation::new(3).unwrap().apply(&metrics).unwrap();
+        assert_eq!(result.len(), 36);
+        for i in 0..12 {
+            let total = result[i].value + result[12 + i].value + result[24 + i].value;
+            assert!((total - metrics[i].value).abs() <= 1);
+        }
+    }
+
+    #[test]
+    fn test_rejects_irregular_or_short_series() {
+        let short: Vec<Metric> = (0..5).map(|i| Metric::new(i, i * 60, None)).collect();
+        assert!(SeasonalDecompositionTransformation::new(4).unwrap().apply(&short).is_err());
+
+        let mut irregular: Vec<Metric> = (0..8).map(|i| Metric::new(i, i * 60, None)).collect();
+        irregular[7].timestamp = 1000;
+        assert!(SeasonalDecompositionTransformation::new(4).unwrap().apply(&irregular).is_err());
+    }
+
+    #[test]
+    fn test_rejects_short_period_up_front() {
+        assert!(SeasonalDecompositionTransformation::new(1).is_err());
+        assert!(SeasonalDecompositionTransformation::new(0).is_err());
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_spec_step() {
+        let metrics: Vec<Metric> = (0..12).map(|i| Metric::new([10, 20, 15][i % 3], i as i64 * 60, None)).collect();
+        let spec = crate::spec::PipelineSpec::from_json(r#"{"steps": [{"op": "decompose", "period": 3}]}"#).unwrap();
+        assert_eq!(spec.build(metrics).unwrap().run().unwrap().len(), 36);
+
+        let spec = crate::spec::PipelineSpec::from_json(r#"{"steps": [{"op": "decompose", "period": 1}]}"#).unwrap();
+        assert!(spec.build(Vec::new()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_forecast {
+    use super::*;
+    use crate::models::FloatMetric;
+    use crate::steps::{ForecastMethod, ForecastTransformation, SmoothingParams};
+
+    #[test]
+    fn test_holt_extends_linear_series() {
+        let metrics: Vec<Metric> = (0..10).map(|i| Metric::new(100 + 5 * i, 1000 + i * 60, None)).collect();
+        let result = ForecastTransformation::new(ForecastMethod::Holt, 3).apply(&metrics).unwrap();
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![150, 155, 160]);
+        assert_eq!(result.iter().map(|m| m.timestamp).collect::<Vec<_>>(), vec![1600, 1660, 1720]);
+        assert!(result.iter().all(|m| m.label.as_deref() == Some("forecast")));
+    }
+
+    #[test]
+    fn test_holt_winters_continues_season() {
+        let season = [5.0, -5.0, 2.0, -2.0];
+        let metrics: Vec<FloatMetric> = (0..16)
+            .map(|i| FloatMetric::new(Some(50.0 + season[i % 4]), i as i64 * 10, None))
+            .collect();
+        let result = ForecastTransformation::new(ForecastMethod::HoltWinters, 4)
+            .with_period(4)
+            .unwrap()
+            .apply_float(&metrics)
+            .unwrap();
+
+        for (h, metric) in result.iter().enumerate() {
+            assert!((metric.value - (50.0 + season[h % 4])).abs() < 1e-6);
+            assert_eq!(metric.timestamp, 160 + h as i64 * 10);
+        }
+    }
+
+    #[test]
+    fn test_holt_winters_requires_period() {
+        let metrics: Vec<Metric> = (0..8).map(|i| Metric::new(i, i * 60, None)).collect();
+        let err = ForecastTransformation::new(ForecastMethod::HoltWinters, 2).apply(&metrics).unwrap_err();
+        assert!(err.to_string().contains("period"));
+        assert!(ForecastTransformation::new(ForecastMethod::HoltWinters, 2).checked().is_err());
+        assert!(ForecastTransformation::new(ForecastMethod::Holt, 2).checked().is_ok());
+    }
+
+    #[test]
+    fn test_parameters_are_checked_up_front() {
+        let step = || ForecastTransformation::new(ForecastMethod::HoltWinters, 2);
+        assert!(step().with_period(1).is_err());
+        assert!(step().with_period(2).is_ok());
+        for (alpha, beta) in [(0.0, 0.1), (0.5, 0.0), (1.5, 0.1), (0.5, f64::NAN)] {
+            let params = SmoothingParams { alpha, beta, gamma: 0.1 };
+            assert!(step().with_params(params).is_err(), "{} {}", alpha, beta);
+        }
+        assert!(step().with_params(SmoothingParams { alpha: 1.0, beta: 1.0, gamma: 1.0 }).is_ok());
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_spec_step() {
+        let metrics: Vec<Metric> = (0..10).map(|i| Metric::new(100 + 5 * i, 1000 + i * 60, None)).collect();
+        let spec = crate::spec::PipelineSpec::from_json(
+            r#"{"steps": [{"op": "forecast", "horizon": 3, "method": "holt", "alpha": 0.5}]}"#,
+        )
+        .unwrap();
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![150, 155, 160]);
+
+        for bad in [
+            r#"{"steps": [{"op": "forecast", "horizon": 3}]}"#,
+            r#"{"steps": [{"op": "forecast", "horizon": 3, "period": 1}]}"#,
+            r#"{"steps": [{"op": "forecast", "horizon": 3, "period": 4, "beta": 0}]}"#,
+        ] {
+            let spec = crate::spec::PipelineSpec::from_json(bad).unwrap();
+            assert!(spec.build(Vec::new()).is_err(), "{}", bad);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_forecast_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_builders_reject_bad_parameters() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            py.run(
+                c"
+pipeline = MetricPipeline([Metric(i, i * 60) for i in range(8)])
+pipeline.forecast(2, period=4)
+for bad in [
+    lambda: pipeline.decompose(1),
+    lambda: pipeline.forecast(2),
+    lambda: pipeline.forecast(2, period=1),
+    lambda: pipeline.forecast(2, method='holt', alpha=0.0),
+    lambda: pipeline.forecast(2, method='holt', beta=1.5),
+]:
+    try:
+        bad()
+        raise AssertionError('invalid parameters accepted')
+    except ValueError:
+        pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_trend {
+    use super::*;
+    use crate::steps::{TrendOutput, TrendTransformation};
+
+    fn create_test_metrics() -> Vec<Metric> {
+        // Grows by 2 per minute from 100, with alternating noise
+        (0..10)
+            .map(|i| Metric::new(100 + 2 * i + if i % 2 == 0 { 1 } else { -1 }, 6000 + i * 60, None))
+            .collect()
+    }
+
+    #[test]
+    fn test_summary_reports_slope_and_intercept() {
+        let metrics: Vec<Metric> = (0..10).map(|i| Metric::new(100 + 2 * i, 6000 + i * 60, None)).collect();
+        let step = TrendTransformation::new(TrendOutput::Summary).with_slope_unit(3600).unwrap();
+        let result = step.apply(&metrics).unwrap();
+
+        assert_eq!(result.len(), 2);
+        assert_eq!(result[0].label.as_deref(), Some("slope"));
+        assert_eq!(result[0].value, 120);
+        assert_eq!(result[1].label.as_deref(), Some("intercept"));
+        assert_eq!(result[1].value, 100);
+        assert_eq!(result[1].timestamp, 6540);
+    }
+
+    #[test]
+    fn test_line_follows_input_timestamps() {
+        let mut metrics = create_test_metrics();
+        metrics.reverse();
+        let result = TrendTransformation::new(TrendOutput::Line).apply(&metrics).unwrap();
+
+        assert_eq!(result.len(), 10);
+        assert_eq!(result[0].timestamp, 6000);
+        assert!(result.windows(2).all(|pair| pair[1].value >= pair[0].value));
+        assert!(result.iter().all(|m| m.label.as_deref() == Some("trend")));
+    }
+
+    #[test]
+    fn test_single_timestamp_is_rejected() {
+        let metrics = vec![Metric::new(1, 100, None), Metric::new(2, 100, None)];
+        assert!(TrendTransformation::new(TrendOutput::Summary).apply(&metrics).is_err());
+    }
+
+    #[test]
+    fn test_slope_unit_is_checked_at_construction() {
+        assert!(TrendTransformation::new(TrendOutput::Line).with_slope_unit(0).is_err());
+        assert!(TrendTransformation::new(TrendOutput::Line).with_slope_unit(-60).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_correlation {
+    use super::*;
+    use crate::analysis::{correlation, rolling_correlation, CorrelationMethod};
+
+    fn create_test_metrics() -> Vec<Metric> {
+        let mut metrics = Vec::new();
+        for i in 0..8i64 {
+            // Request rate grows linearly; CPU grows quadratically, a few seconds later
+            metrics.push(Metric::new(10 * i, 60 * i, Some("requests".to_string())));
+            metrics.push(Metric::new(i * i, 60 * i + 5, Some("cpu".to_string())));
+        }
+        metrics
+    }
+
+    #[test]
+    fn test_pearson_and_spearman() {
+        let metrics = create_test_metrics();
+        let pearson = correlation(&metrics, "requests", "cpu", CorrelationMethod::Pearson, 60).unwrap();
+        let spearman = correlation(&metrics, "requests", "cpu", CorrelationMethod::Spearman, 60).unwrap();
+
+        assert!(pearson > 0.9 && pearson < 1.0);
+        assert!((spearman - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_exact_alignment_needs_shared_timestamps() {
+        let err = correlation(&create_test_metrics(), "requests", "cpu", CorrelationMethod::Pearson, 1);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn test_rolling_correlation() {
+        let result =
+            rolling_correlation(&create_test_metrics(), "requests", "cpu", CorrelationMethod::Spearman, 60, 4)
+                .unwrap();
+        assert_eq!(result.len(), 5);
+        assert_eq!(result[0].timestamp, 180);
+        assert!(result.iter().all(|m| (m.value - 1.0).abs() < 1e-12));
+    }
+
+    #[test]
+    fn test_scales_stale_markers_and_precision() {
+        use crate::settings::{Settings, TimestampPrecision};
+
+        // Millisecond timestamps; "cpu" is fixed-point at two different scales and
+        // ends with a staleness marker sharing a bucket with a real point
+        let mut metrics = Vec::new();
+        for i in 0..6i64 {
+            metrics.push(Metric::new(10 * i, 60_000 * i, Some("requests".to_string())));
+            let cpu = Metric::new(i * i, 60_000 * i + 5_000, Some("cpu".to_string()));
+            metrics.push(if i % 2 == 0 { cpu.with_scale(0) } else { Metric { value: i * i * 100, ..cpu.with_scale(2) } });
+        }
+        metrics.push(Metric::stale_marker(300_000 + 10_000, Some("cpu".to_string())));
+
+        let settings = Settings { timestamp_precision: TimestampPrecision::Milliseconds, ..Settings::DEFAULT };
+        let spearman = settings
+            .scope(|| correlation(&metrics, "requests", "cpu", CorrelationMethod::Spearman, 60))
+            .unwrap();
+        assert!((spearman - 1.0).abs() < 1e-12);
+
+        // Read as seconds, every point would land in its own bucket
+        assert!(correlation(&metrics, "requests", "cpu", CorrelationMethod::Spearman, 60).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_histogram {
+    use super::*;
+    use crate::steps::{HistogramBuckets, HistogramTransformation};
+
+    fn create_test_metrics() -> Vec<Metric> {
+        [1, 5, 10, 11, 50, 100, 250]
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| Metric::new(v, 1000 + i as i64, None))
+            .collect()
+    }
+
+    #[test]
+    fn test_explicit_bounds() {
+        let step = HistogramTransformation::new(HistogramBuckets::Bounds(vec![10.0, 100.0])).unwrap();
+        let result = step.apply(&create_test_metrics()).unwrap();
+
+        let labels: Vec<&str> = result.iter().map(|m| m.label.as_deref().unwrap()).collect();
+        assert_eq!(labels, vec!["le=10", "le=100", "le=+Inf"]);
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![3, 3, 1]);
+        assert!(result.iter().all(|m| m.timestamp == 1006));
+    }
+
+    #[test]
+    fn test_automatic_buckets_cover_range() {
+        let step = HistogramTransformation::new(HistogramBuckets::Auto(4)).unwrap();
+        let result = step.apply(&create_test_metrics()).unwrap();
+
+        assert_eq!(result.len(), 4);
+        assert_eq!(result.iter().map(|m| m.value).sum::<i64>(), 7);
+        assert_eq!(result[3].label.as_deref(), Some("le=250"));
+    }
+
+    #[test]
+    fn test_invalid_bounds() {
+        assert!(HistogramTransformation::new(HistogramBuckets::Bounds(vec![5.0, 1.0])).is_err());
+        assert!(HistogramTransformation::new(HistogramBuckets::Auto(0)).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_describe {
+    use super::*;
+    use crate::analysis::describe;
+
+    #[test]
+    fn test_pipeline_describe() {
+        let metrics: Vec<Metric> = (1..=10).map(|i| Metric::new(i * 10, i, None)).collect();
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(20)));
+
+        let summary = pipeline.describe_metrics(&[0.0, 0.5, 1.0]).unwrap();
+        assert_eq!(summary.count, 8);
+        assert_eq!(summary.min, 30.0);
+        assert_eq!(summary.max, 100.0);
+        assert_eq!(summary.mean, 65.0);
+        assert!((summary.stddev - 24.494897).abs() < 1e-6);
+        assert_eq!(summary.percentile(0.5), Some(65.0));
+        assert_eq!(summary.percentile(1.0), Some(100.0));
+        assert_eq!(summary.percentile(0.25), None);
+        assert_eq!((summary.earliest, summary.latest), (Some(3), Some(10)));
+    }
+
+    #[test]
+    fn test_describe_metrics_scales_and_skips_markers() {
+        let metrics = vec![
+            Metric::new(150, 40, None).with_scale(2),
+            Metric::stale_marker(99, None),
+            Metric::new(2, 7, None),
+        ];
+        let summary = crate::analysis::describe_metrics(&metrics, &[]).unwrap();
+        assert_eq!((summary.count, summary.min, summary.max), (2, 1.5, 2.0));
+        assert_eq!((summary.earliest, summary.latest), (Some(7), Some(40)));
+        assert_eq!(describe(&[1.0], &[]).unwrap().earliest, None);
+    }
+
+    #[test]
+    fn test_describe_interpolates_and_skips_missing() {
+        let summary = describe(&[4.0, f64::NAN, 1.0, 2.0, 3.0], &[0.9]).unwrap();
+        assert_eq!(summary.count, 4);
+        assert!((summary.percentiles[0].1 - 3.7).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_describe_rejects_empty_and_bad_quantiles() {
+        assert!(describe(&[], &[0.5]).is_err());
+        assert!(describe(&[1.0], &[50.0]).is_err());
+        assert!(describe(&[1.0], &[0.5]).unwrap().stddev.is_nan());
+    }
+}
+
+#[cfg(test)]
+mod test_crossings {
+    use super::*;
+    use crate::steps::{CrossingDirection, CrossingTransformation};
+
+    fn create_test_metrics() -> Vec<Metric> {
+        // Deliberately out of timestamp order
+        [(40, 300), (95, 100), (20, 0), (80, 200), (70, 400), (90, 500)]
+            .iter()
+            .map(|&(value, timestamp)| Metric::new(value, timestamp, None))
+            .collect()
+    }
+
+    fn crossings(direction: CrossingDirection) -> Vec<(i64, Option<String>)> {
+        CrossingTransformation::new(80.0, direction)
+            .apply(&create_test_metrics())
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.timestamp, m.label))
+            .collect()
+    }
+
+    #[test]
+    fn test_both_directions() {
+        let up = Some("crossing_up".to_string());
+        let down = Some("crossing_down".to_string());
+        assert_eq!(
+            crossings(CrossingDirection::Both),
+            vec![(100, up.clone()), (300, down), (500, up)]
+        );
+    }
+
+    #[test]
+    fn test_single_direction() {
+        assert_eq!(crossings(CrossingDirection::Up).len(), 2);
+        assert_eq!(crossings(CrossingDirection::Down), vec![(300, Some("crossing_down".to_string()))]);
+    }
+}
+
+#[cfg(test)]
+mod test_slo {
+    use super::*;
+    use crate::slo::{burn_rates, evaluate_burn_rate_rules, BurnRateRule};
+
+    /// One minute of traffic per metric pair; errors only in the last five minutes
+    fn create_test_metrics() -> Vec<Metric> {
+        let mut metrics = Vec::new();
+        for minute in 1..=60 {
+            let good = if minute > 55 { 900 } else { 1000 };
+            metrics.push(Metric::new(1000, minute * 60, Some("total".to_string())));
+            metrics.push(Metric::new(good, minute * 60, Some("good".to_string())));
+        }
+        metrics
+    }
+
+    #[test]
+    fn test_burn_rates_per_window() {
+        let result = burn_rates(&create_test_metrics(), "good", "total", 0.99, &[300, 3600], None).unwrap();
+
+        assert_eq!(result[0].label.as_deref(), Some("burn_rate_300s"));
+        assert!((result[0].value - 10.0).abs() < 1e-9);
+        assert!((result[1].value - 10.0 * 5.0 / 60.0).abs() < 1e-9);
+        assert!(result.iter().all(|m| m.timestamp == 3600));
+    }
+
+    #[test]
+    fn test_multiwindow_rules() {
+        let rules = [BurnRateRule::new(3600, 300, 14.4), BurnRateRule::new(3600, 300, 0.5)];
+        let alerts = evaluate_burn_rate_rules(&create_test_metrics(), "good", "total", 0.99, &rules, None).unwrap();
+
+        assert!(!alerts[0].firing);
+        assert!(alerts[1].firing);
+    }
+
+    #[test]
+    fn test_invalid_target() {
+        assert!(burn_rates(&create_test_metrics(), "good", "total", 1.0, &[300], None).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_pct_change {
+    use super::*;
+    use crate::models::FloatMetric;
+    use crate::steps::PercentChangeTransformation;
+
+    #[test]
+    fn test_hour_over_hour() {
+        let metrics = vec![
+            Metric::new(100, 0, Some("a".to_string())),
+            Metric::new(0, 0, Some("b".to_string())),
+            Metric::new(150, 3600, Some("a".to_string())),
+            Metric::new(50, 3600, Some("b".to_string())),
+            Metric::new(75, 7200, Some("a".to_string())),
+        ];
+        let result = PercentChangeTransformation::new(3600).unwrap().apply(&metrics).unwrap();
+
+        // "b" has a zero base and the first bucket has no predecessor
+        assert_eq!(result.len(), 2);
+        assert_eq!((result[0].value, result[0].timestamp), (50, 3600));
+        assert_eq!((result[1].value, result[1].timestamp), (-50, 7200));
+    }
+
+    #[test]
+    fn test_float_keeps_fraction() {
+        let metrics = vec![FloatMetric::new(Some(3.0), 0, None), FloatMetric::new(Some(4.0), 60, None)];
+        let result = PercentChangeTransformation::new(60).unwrap().apply_float(&metrics).unwrap();
+        assert!((result[0].value - 100.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_period_is_checked_at_construction() {
+        assert!(PercentChangeTransformation::new(0).is_err());
+        assert!(PercentChangeTransformation::new(-3600).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_rolling_percentile {
+    use super::*;
+    use crate::models::FloatMetric;
+    use crate::steps::{RollingPercentileTransformation, RollingWindow};
+
+    fn create_test_metrics() -> Vec<Metric> {
+        [5, 1, 9, 3, 7]
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| Metric::new(v, i as i64 * 10, Some("latency".to_string())))
+            .collect()
+    }
+
+    fn values(result: &[Metric]) -> Vec<i64> {
+        result.iter().map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_count_window_max() {
+        let step = RollingPercentileTransformation::new(1.0, RollingWindow::Count(3)).unwrap();
+        assert_eq!(values(&step.apply(&create_test_metrics()).unwrap()), vec![5, 5, 9, 9, 9]);
+    }
+
+    #[test]
+    fn test_duration_window_median() {
+        // A 20 second window holds the current point and the previous one
+        let step = RollingPercentileTransformation::new(0.5, RollingWindow::Duration(20)).unwrap();
+        assert_eq!(values(&step.apply(&create_test_metrics()).unwrap()), vec![5, 3, 5, 6, 5]);
+    }
+
+    #[test]
+    fn test_labels_roll_independently() {
+        let metrics = vec![
+            Metric::new(100, 0, Some("a".to_string())),
+            Metric::new(1, 5, Some("b".to_string())),
+            Metric::new(50, 10, Some("a".to_string())),
+        ];
+        let step = RollingPercentileTransformation::new(1.0, RollingWindow::Count(2)).unwrap();
+        assert_eq!(values(&step.apply(&metrics).unwrap()), vec![100, 1, 100]);
+    }
+
+    #[test]
+    fn test_tags_split_series_and_are_kept() {
+        let metrics = vec![
+            Metric::new(100, 0, None).with_tag("pod", "a"),
+            Metric::new(1, 5, None).with_tag("pod", "b"),
+            Metric::new(50, 10, None).with_tag("pod", "a"),
+        ];
+        let step = RollingPercentileTransformation::new(1.0, RollingWindow::Count(2)).unwrap();
+        let result = step.apply(&metrics).unwrap();
+        assert_eq!(values(&result), vec![100, 1, 100]);
+        let pods: Vec<&str> = result.iter().map(|m| m.tags["pod"].as_str()).collect();
+        assert_eq!(pods, vec!["a", "b", "a"]);
+    }
+
+    #[test]
+    fn test_large_windows_match_sorting_each_window() {
+        // Values with many repeats, at uneven timestamps
+        let mut state: u64 = 7;
+        let metrics: Vec<Metric> = (0..40_000)
+            .map(|i| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                Metric::new((state >> 33) as i64 % 1000 - 500, i * 3 + (state % 3) as i64, None)
+            })
+            .collect();
+        let floats: Vec<FloatMetric> = metrics.iter().map(FloatMetric::from).collect();
+
+        for (quantile, window) in [(0.9, RollingWindow::Count(10_000)), (0.37, RollingWindow::Duration(25_000))] {
+            let step = RollingPercentileTransformation::new(quantile, window).unwrap();
+            let result = step.apply(&metrics).unwrap();
+            let float_result = step.apply_float(&floats).unwrap();
+            for i in (0..metrics.len()).step_by(997) {
+                let start = match window {
+                    RollingWindow::Count(count) => (i + 1).saturating_sub(count),
+                    RollingWindow::Duration(seconds) => {
+                        metrics.iter().position(|m| m.timestamp > metrics[i].timestamp - seconds).unwrap()
+                    }
+                };
+                let mut sorted: Vec<i64> = metrics[start..=i].iter().map(|m| m.value).collect();
+                sorted.sort_unstable();
+                let rank = quantile * (sorted.len() - 1) as f64;
+                let (lower, upper) = (sorted[rank.floor() as usize] as f64, sorted[rank.ceil() as usize] as f64);
+                let expected = lower + (upper - lower) * rank.fract();
+                assert_eq!(result[i].value, expected.round() as i64, "point {}", i);
+                assert!((float_result[i].value - expected).abs() < 1e-9, "point {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn test_invalid_parameters() {
+        assert!(RollingPercentileTransformation::new(95.0, RollingWindow::Count(3)).is_err());
+        assert!(RollingPercentileTransformation::new(0.5, RollingWindow::Count(0)).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_derivative {
+    use super::*;
+    use crate::models::FloatMetric;
+    use crate::steps::DerivativeTransformation;
+
+    #[test]
+    fn test_acceleration_of_quadratic_growth() {
+        // value = t^2 / 60 sampled every minute: the rate grows by 2 per minute, per minute
+        let metrics: Vec<FloatMetric> = (0..5)
+            .map(|i| {
+                let t = i * 60;
+                FloatMetric::new(Some((t * t) as f64 / 60.0), t, None)
+            })
+            .collect();
+        let step = DerivativeTransformation::new(2).unwrap().with_time_unit(60).unwrap();
+        let result = step.apply_float(&metrics).unwrap();
+
+        assert_eq!(result.len(), 3);
+        assert_eq!(result[0].timestamp, 120);
+        assert!(result.iter().all(|m| (m.value - 120.0).abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_first_order_per_label() {
+        let metrics = vec![
+            Metric::new(0, 0, Some("a".to_string())),
+            Metric::new(100, 0, Some("b".to_string())),
+            Metric::new(30, 10, Some("a".to_string())),
+            Metric::new(50, 10, Some("b".to_string())),
+        ];
+        let result = DerivativeTransformation::new(1).unwrap().apply(&metrics).unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![3, -5]);
+    }
+
+    #[test]
+    fn test_first_order_per_tag_set() {
+        let metrics = vec![
+            Metric::new(0, 0, None).with_tag("pod", "a"),
+            Metric::new(100, 0, None).with_tag("pod", "b"),
+            Metric::new(30, 10, None).with_tag("pod", "a"),
+            Metric::new(50, 10, None).with_tag("pod", "b"),
+        ];
+        let result = DerivativeTransformation::new(1).unwrap().apply(&metrics).unwrap();
+        let points: Vec<(&str, i64)> = result.iter().map(|m| (m.tags["pod"].as_str(), m.value)).collect();
+        assert_eq!(points, vec![("a", 3), ("b", -5)]);
+    }
+
+    #[test]
+    fn test_rejects_duplicate_timestamps_and_bad_order() {
+        let metrics = vec![Metric::new(1, 10, None), Metric::new(2, 10, None)];
+        assert!(DerivativeTransformation::new(1).unwrap().apply(&metrics).is_err());
+        assert!(DerivativeTransformation::new(3).is_err());
+        assert!(DerivativeTransformation::new(2).unwrap().with_time_unit(0).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_normalize {
+    use super::*;
+    use crate::models::FloatMetric;
+    use crate::steps::{NormalizeMethod, NormalizeTransformation};
+
+    fn create_test_metrics() -> Vec<FloatMetric> {
+        vec![
+            FloatMetric::new(Some(10.0), 0, Some("small".to_string())),
+            FloatMetric::new(Some(1000.0), 0, Some("large".to_string())),
+            FloatMetric::new(Some(20.0), 60, Some("small".to_string())),
+            FloatMetric::new(Some(3000.0), 60, Some("large".to_string())),
+            FloatMetric::new(None, 120, Some("small".to_string())),
+        ]
+    }
+
+    fn values(result: &[FloatMetric]) -> Vec<f64> {
+        result.iter().map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_minmax_per_label() {
+        let step = NormalizeTransformation::new(NormalizeMethod::MinMax).per_label(true);
+        let result = step.apply_float(&create_test_metrics()).unwrap();
+        assert_eq!(values(&result)[..4], [0.0, 0.0, 1.0, 1.0]);
+        assert!(result[4].is_missing());
+    }
+
+    #[test]
+    fn test_zscore_across_stream() {
+        let metrics = vec![FloatMetric::new(Some(2.0), 0, None), FloatMetric::new(Some(4.0), 1, None)];
+        let result = NormalizeTransformation::new(NormalizeMethod::ZScore).apply_float(&metrics).unwrap();
+        assert_eq!(values(&result), vec![-1.0, 1.0]);
+    }
+
+    #[test]
+    fn test_constant_series_normalizes_to_zero() {
+        let metrics = vec![Metric::new(5, 0, None), Metric::new(5, 1, None)];
+        let result = NormalizeTransformation::new(NormalizeMethod::ZScore).apply(&metrics).unwrap();
+        assert!(result.iter().all(|m| m.value == 0));
+    }
+}
+
+#[cfg(all(test, feature = "ffi"))]
+mod test_ffi {
+    use crate::ffi::*;
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+
+    fn empty_result() -> MqResult {
+        MqResult { values: ptr::null_mut(), timestamps: ptr::null_mut(), len: 0 }
+    }
+
+    #[test]
+    fn test_filter_and_time_grouping() {
+        let gt = CString::new("gt").unwrap();
+        let hour = CString::new("hour").unwrap();
+        let sum = CString::new("sum").unwrap();
+        let values = [5_i64, 20, 30, 40];
+        let timestamps = [0_i64, 60, 120, 3600];
+
+        unsafe {
+            let pipeline = mq_pipeline_new();
+            assert_eq!(mq_pipeline_add_filter(pipeline, gt.as_ptr(), 10), MqStatus::Ok);
+            assert_eq!(mq_pipeline_add_time_grouping(pipeline, hour.as_ptr(), sum.as_ptr()), MqStatus::Ok);
+
+            let mut result = empty_result();
+            let status = mq_pipeline_execute(pipeline, values.as_ptr(), timestamps.as_ptr(), values.len(), &mut result);
+            assert_eq!(status, MqStatus::Ok);
+            assert!(mq_last_error().is_null());
+
+            let out_values = std::slice::from_raw_parts(result.values, result.len);
+            let out_timestamps = std::slice::from_raw_parts(result.timestamps, result.len);
+            let mut groups: Vec<(i64, i64)> = out_timestamps.iter().copied().zip(out_values.iter().copied()).collect();
+            groups.sort();
+            assert_eq!(groups, vec![(0, 50), (3600, 40)]);
+
+            mq_result_free(&mut result);
+            assert!(result.values.is_null());
+            mq_pipeline_free(pipeline);
+        }
+    }
+
+    #[test]
+    fn test_unknown_step_sets_last_error() {
+        let mode = CString::new("mode").unwrap();
+        unsafe {
+            let pipeline = mq_pipeline_new();
+            assert_eq!(mq_pipeline_add_aggregation(pipeline, mode.as_ptr()), MqStatus::Failed);
+            let message = CStr::from_ptr(mq_last_error()).to_str().unwrap();
+            assert!(message.contains("mode"), "{}", message);
+            mq_pipeline_free(pipeline);
+        }
+    }
+
+    #[test]
+    fn test_null_arguments_are_rejected() {
+        let mut result = empty_result();
+        unsafe {
+            assert_eq!(mq_pipeline_add_filter(ptr::null_mut(), ptr::null(), 0), MqStatus::InvalidArgument);
+            assert_eq!(
+                mq_pipeline_execute(ptr::null(), ptr::null(), ptr::null(), 0, &mut result),
+                MqStatus::InvalidArgument
+            );
+            mq_result_free(ptr::null_mut());
+            mq_pipeline_free(ptr::null_mut());
+        }
+    }
+}
+
+#[cfg(all(test, feature = "wasm"))]
+mod test_wasm {
+    use crate::wasm::WasmPipeline;
+
+    #[test]
+    fn test_group_by_time_over_arrays() {
+        let mut pipeline = WasmPipeline::new();
+        pipeline.filter("gt", 10.0).unwrap();
+        pipeline.group_by_time("hour", "avg").unwrap();
+
+        let result = pipeline.execute(&[5.0, 20.0, 30.0, 40.5], &[0.0, 60.0, 120.0, 3600.0], None).unwrap();
+        // Groups come back in hash order
+        let mut groups: Vec<(f64, f64)> = result.timestamps().into_iter().zip(result.values()).collect();
+        groups.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(result.length(), 2);
+        assert_eq!(groups, vec![(0.0, 25.0), (3600.0, 40.5)]);
+    }
+
+    #[test]
+    fn test_label_filter_uses_labels_column() {
+        let mut pipeline = WasmPipeline::new();
+        pipeline.filter_by_label("cpu".to_string()).unwrap();
+
+        let labels = vec!["cpu".to_string(), "memory".to_string(), "cpu".to_string()];
+        let result = pipeline.execute(&[1.0, 2.0, 3.0], &[0.0, 1.0, 2.0], Some(labels)).unwrap();
+        assert_eq!(result.values(), vec![1.0, 3.0]);
+    }
+}
+
+#[cfg(all(test, feature = "spec"))]
+mod test_spec {
+    use serde_json::{json, Map, Value};
+
+    use crate::errors::{MetricQueryError, MetricQueryResult};
+    use std::sync::Arc;
+
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{FnFilter, Threshold};
+    use crate::plugins::{with_registry_mut, FilterPlugin};
+    use crate::spec::{migrate, Migration, PipelineSpec, StepSpec, SPEC_VERSION};
+
+    #[test]
+    fn test_spec_builds_pipeline() {
+        let spec = PipelineSpec::from_json(
+            r#"{"steps": [
+                {"op": "filter_by_label", "label": "cpu"},
+                {"op": "filter", "type": "gt", "value": 10},
+                {"op": "group_by_time", "grouping": "hour", "aggregation": "sum"}
+            ]}"#,
+        )
+        .unwrap();
+        let metrics = vec![
+            Metric::new(5, 0, Some("cpu".to_string())),
+            Metric::new(20, 60, Some("cpu".to_string())),
+            Metric::new(30, 120, Some("cpu".to_string())),
+            Metric::new(99, 180, Some("memory".to_string())),
+        ];
+
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].value, 50);
+    }
+
+    #[test]
+    fn test_filter_step_takes_floats_and_registered_filters() {
+        let metrics: Vec<Metric> = (0..6).map(|i| Metric::new(i, i, None)).collect();
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "filter", "type": "gt", "value": 2.5}]}"#).unwrap();
+        let result = spec.build(metrics.clone()).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![3, 4, 5]);
+
+        with_registry_mut(|registry| {
+            registry.register_filter_factory(
+                "spec_multiple_of",
+                Arc::new(|value| match value {
+                    Threshold::Int(n) => Ok(Box::new(FnFilter::new("spec_multiple_of", move |m: &Metric| m.value % n == 0))
+                        as Box<dyn FilterPlugin>),
+                    Threshold::Float(_) => Err(MetricQueryError::InvalidFilter { reason: "whole numbers only".to_string() }),
+                }),
+            )
+        });
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "filter", "type": "spec_multiple_of", "value": 2}]}"#).unwrap();
+        let result = spec.build(metrics.clone()).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![0, 2, 4]);
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "filter", "type": "spec_multiple_of", "value": 0.5}]}"#).unwrap();
+        assert!(spec.build(metrics).is_err());
+    }
+
+    #[test]
+    fn test_time_range_step() {
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "filter_time_range", "start_ts": 60, "end_ts": 180}]}"#)
+            .unwrap();
+        let metrics: Vec<Metric> = (0..5).map(|i| Metric::new(i, i * 60, None)).collect();
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_between_step() {
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "between", "lo": 2, "hi": 4}]}"#).unwrap();
+        let metrics: Vec<Metric> = (0..6).map(|i| Metric::new(i, i, None)).collect();
+        let result = spec.build(metrics.clone()).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![2, 3, 4]);
+
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "between", "lo": 1.5, "hi": 3.5}]}"#).unwrap();
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_value_in_step() {
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "value_in", "values": [404, 500]}]}"#).unwrap();
+        let metrics = vec![Metric::new(200, 0, None), Metric::new(404, 1, None), Metric::new(500, 2, None)];
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![404, 500]);
+    }
+
+    #[test]
+    fn test_negative_label_steps() {
+        let metrics = vec![
+            Metric::new(1, 0, Some("cpu".to_string())),
+            Metric::new(2, 1, Some("memory".to_string())),
+            Metric::new(3, 2, None),
+        ];
+        let values = |json: &str| -> Vec<i64> {
+            let pipeline = PipelineSpec::from_json(json).unwrap().build(metrics.clone()).unwrap();
+            pipeline.run().unwrap().iter().map(|m| m.value).collect()
+        };
+        assert_eq!(values(r#"{"steps": [{"op": "label_ne", "label": "cpu"}]}"#), vec![2, 3]);
+        assert_eq!(
+            values(r#"{"steps": [{"op": "label_not_in", "labels": ["cpu"], "keep_unlabeled": false}]}"#),
+            vec![2]
+        );
+    }
+
+    #[test]
+    fn test_tag_step() {
+        let spec = PipelineSpec::from_json(
+            r#"{"steps": [{"op": "filter_by_tag", "key": "pod", "operator": "in", "value": ["a", "c"]}]}"#,
+        )
+        .unwrap();
+        let metrics: Vec<Metric> =
+            ["a", "b", "c"].iter().zip(0..).map(|(pod, i)| Metric::new(i, i, None).with_tag("pod", *pod)).collect();
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_expr_step() {
+        let spec = PipelineSpec::from_json(
+            r#"{"steps": [{"op": "filter_expr", "expr": "value >= 1 and tags.pod != 'b'"}]}"#,
+        )
+        .unwrap();
+        let metrics: Vec<Metric> =
+            ["a", "b", "c"].iter().zip(0..).map(|(pod, i)| Metric::new(i, i, None).with_tag("pod", *pod)).collect();
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![2]);
+
+        let invalid = PipelineSpec::from_json(r#"{"steps": [{"op": "filter_expr", "expr": "value >"}]}"#).unwrap();
+        assert!(invalid.build(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_timestamp_steps() {
+        // Monday 2024-01-01 00:00 UTC, then hourly
+        let metrics: Vec<Metric> = (0..24).map(|h| Metric::new(h, 1_704_067_200 + h * 3_600, None)).collect();
+        let spec = PipelineSpec::from_json(
+            r#"{"steps": [
+                {"op": "filter_weekdays", "days": ["mon"]},
+                {"op": "filter_hours", "start": 9, "end": 17},
+                {"op": "filter_timestamp", "operator": "before", "instant": 1704103200}
+            ]}"#,
+        )
+        .unwrap();
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![9]);
+    }
+
+    #[test]
+    fn test_sample_every_step() {
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "sample_every", "n": 4}]}"#).unwrap();
+        let metrics: Vec<Metric> = (0..10).map(|i| Metric::new(i, i, None)).collect();
+        let result = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![0, 4, 8]);
+
+        let zero = PipelineSpec::from_json(r#"{"steps": [{"op": "sample_every", "n": 0}]}"#).unwrap();
+        assert!(zero.build(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_sample_fraction_step() {
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "sample_fraction", "p": 0.5, "seed": 1}]}"#).unwrap();
+        let metrics: Vec<Metric> = (0..100).map(|i| Metric::new(i, i, None)).collect();
+        let first = spec.build(metrics.clone()).unwrap().run().unwrap();
+        let second = spec.build(metrics).unwrap().run().unwrap();
+        assert_eq!(first, second);
+        assert!(!first.is_empty() && first.len() < 100);
+
+        let unseeded = PipelineSpec::from_json(r#"{"steps": [{"op": "sample_fraction", "p": 1.0}]}"#).unwrap();
+        assert_eq!(unseeded.build(vec![Metric::new(1, 1, None)]).unwrap().run().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_step_defaults_match_python() {
+        let spec = PipelineSpec::from_json(r#"{"steps": [{"op": "normalize"}]}"#).unwrap();
+        let metrics = vec![FloatMetric::new(Some(2.0), 0, None), FloatMetric::new(Some(4.0), 1, None)];
+        let result = spec.build(Vec::new()).unwrap().execute_float_metrics(&metrics).unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![0.0, 1.0]);
+    }
+
+    #[test]
+    fn test_invalid_specs_are_rejected() {
+        assert!(PipelineSpec::from_json(r#"{"steps": [{"op": "median"}]}"#).is_err());
+        assert!(PipelineSpec::from_json(r#"{"steps": [{"op": "pct_change", "period": 60, "extra": 1}]}"#).is_err());
+
+        let unknown_filter = PipelineSpec::from_json(r#"{"steps": [{"op": "filter", "type": "ne", "value": 1}]}"#);
+        assert!(unknown_filter.unwrap().build(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_specs_carry_a_version() {
+        let unversioned = PipelineSpec::from_json(r#"{"steps": [{"op": "latest"}]}"#).unwrap();
+        assert_eq!(unversioned.version, SPEC_VERSION);
+
+        let json = PipelineSpec::new(vec![StepSpec::Latest, StepSpec::PctChange { period: 60 }]).to_json();
+        assert!(json.starts_with(r#"{"version":1,"steps":[{"op":"latest"}"#), "{}", json);
+        let reloaded = PipelineSpec::from_json(&json).unwrap();
+        assert_eq!(reloaded.to_json(), json);
+
+        let newer = PipelineSpec::from_json(r#"{"version": 99, "steps": []}"#).unwrap_err();
+        assert!(newer.to_string().contains("newer than the supported version"), "{}", newer);
+        assert!(PipelineSpec::from_json(r#"{"version": 0, "steps": []}"#).is_err());
+        assert!(PipelineSpec::from_json(r#"{"version": "1", "steps": []}"#).is_err());
+        assert!(PipelineSpec::from_json(r#"[]"#).is_err());
+    }
+
+    #[test]
+    fn test_migrations_run_in_order_from_the_stored_version() {
+        fn rename_period(spec: &mut Map<String, Value>) -> MetricQueryResult<()> {
+            for step in spec["steps"].as_array_mut().into_iter().flatten() {
+                if let Some(seconds) = step.as_object_mut().and_then(|step| step.remove("seconds")) {
+                    step["period"] = seconds;
+                }
+            }
+            Ok(())
+        }
+        fn refuse(_: &mut Map<String, Value>) -> MetricQueryResult<()> {
+            Err(MetricQueryError::OperationFailed { operation: "test".to_string(), reason: "unsupported".to_string() })
+        }
+        let migrations: [Migration; 2] = [rename_period, |_| Ok(())];
+
+        let mut old = json!({"steps": [{"op": "pct_change", "seconds": 60}]});
+        migrate(&mut old, &migrations).unwrap();
+        assert_eq!(old, json!({"version": 3, "steps": [{"op": "pct_change", "period": 60}]}));
+
+        // Already past the rename, so only the second migration runs
+        let mut recent = json!({"version": 2, "steps": [{"op": "pct_change", "seconds": 60}]});
+        migrate(&mut recent, &migrations).unwrap();
+        assert_eq!(recent["steps"][0]["seconds"], 60);
+
+        assert!(migrate(&mut json!({"steps": []}), &[refuse]).is_err());
+        let mut current = json!({"version": 2, "steps": []});
+        migrate(&mut current, &[refuse]).unwrap();
+    }
+}
+
+#[cfg(all(test, feature = "cli"))]
+mod test_io {
+    use crate::io::{read_csv, read_ndjson, write_csv, write_ndjson, Format};
+
+    #[test]
+    fn test_csv_round_trip_keeps_missing_values() {
+        let input = "value,timestamp,label\n1.5,0,cpu\n,60,\n";
+        let metrics = read_csv(input.as_bytes()).unwrap();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].label.as_deref(), Some("cpu"));
+        assert!(metrics[1].is_missing());
+        assert_eq!(metrics[1].label, None);
+
+        let mut output = Vec::new();
+        write_csv(&mut output, &metrics).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+    }
+
+    #[test]
+    fn test_ndjson_round_trip() {
+        let input = "{\"value\":2.0,\"timestamp\":60,\"label\":\"cpu\"}\n\n{\"value\":null,\"timestamp\":120}\n";
+        let metrics = read_ndjson(input.as_bytes()).unwrap();
+        assert_eq!(metrics.len(), 2);
+        assert!(metrics[1].is_missing());
+
+        let mut output = Vec::new();
+        write_ndjson(&mut output, &metrics).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "{\"value\":2.0,\"timestamp\":60,\"label\":\"cpu\"}\n{\"value\":null,\"timestamp\":120,\"label\":null}\n"
+        );
+    }
+
+    #[test]
+    fn test_bad_ndjson_reports_line() {
+        let err = read_ndjson("{\"value\":1,\"timestamp\":0}\n{oops}\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("line 2"), "{}", err);
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_read_parquet_with_integer_values() {
+        use arrow_array::{ArrayRef, Int64Array, RecordBatch, StringArray};
+        use parquet::arrow::ArrowWriter;
+        use std::sync::Arc;
+
+        let batch = RecordBatch::try_from_iter([
+            ("value", Arc::new(Int64Array::from(vec![Some(10), None])) as ArrayRef),
+            ("timestamp", Arc::new(Int64Array::from(vec![0, 60])) as ArrayRef),
+            ("label", Arc::new(StringArray::from(vec![Some("cpu"), None])) as ArrayRef),
+        ])
+        .unwrap();
+        let path = std::env::temp_dir().join(format!("metric-query-test-{}.parquet", std::process::id()));
+        let mut writer = ArrowWriter::try_new(std::fs::File::create(&path).unwrap(), batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let metrics = crate::io::read_parquet(std::fs::File::open(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(metrics[0].value, 10.0);
+        assert_eq!(metrics[0].label.as_deref(), Some("cpu"));
+        assert!(metrics[1].is_missing());
+        assert_eq!(metrics[1].timestamp, 60);
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(Format::from_path("metrics.jsonl".as_ref()), Some(Format::Ndjson));
+        assert_eq!(Format::from_path("metrics.parquet".as_ref()), Some(Format::Parquet));
+        assert_eq!(Format::from_path("metrics".as_ref()), None);
+    }
+}
+
+#[cfg(all(test, feature = "server"))]
+mod test_server {
+    use std::io::Read;
+
+    use tiny_http::Method;
+
+    use crate::server::{handle_query, route, route_guarded, MAX_BODY};
+
+    #[test]
+    fn test_query_returns_metrics() {
+        let (status, body) = handle_query(
+            r#"{"pipeline": {"steps": [{"op": "filter_by_label", "label": "cpu"}]},
+                "metrics": [{"value": 1.5, "timestamp": 0, "label": "cpu"},
+                            {"value": 9.0, "timestamp": 0, "label": "memory"}]}"#,
+        );
+        assert_eq!(status, 200);
+        assert_eq!(body, r#"{"metrics":[{"label":"cpu","timestamp":0,"value":1.5}]}"#);
+    }
+
+    #[test]
+    fn test_malformed_body_is_bad_request() {
+        let (status, body) = handle_query(r#"{"pipeline": {"steps": []}}"#);
+        assert_eq!(status, 400);
+        assert!(body.contains("missing field `metrics`"), "{}", body);
+    }
+
+    #[test]
+    fn test_pipeline_error_reports_code_and_step() {
+        let (status, body) = handle_query(r#"{"pipeline": {"steps": [{"op": "aggregate", "type": "sum"}]}, "metrics": []}"#);
+        assert_eq!(status, 422);
+        assert!(body.contains(r#""code":"empty_metric_stream""#), "{}", body);
+        assert!(body.contains(r#""step":0"#), "{}", body);
+    }
+
+    #[test]
+    fn test_query_string_is_ignored() {
+        let query = r#"{"pipeline": {"steps": []}, "metrics": []}"#;
+        assert_eq!(route(&Method::Post, "/query?trace=1", query.as_bytes()).0, 200);
+        assert_eq!(route(&Method::Get, "/query?trace=1", query.as_bytes()).0, 405);
+        assert_eq!(route(&Method::Post, "/queryx", query.as_bytes()).0, 404);
+    }
+
+    #[test]
+    fn test_oversized_body_is_refused() {
+        let (status, body) = route(&Method::Post, "/query", std::io::repeat(b' ').take(MAX_BODY + 1));
+        assert_eq!(status, 413);
+        assert!(body.contains("payload_too_large"), "{}", body);
+    }
+
+    struct PanickingBody;
+
+    impl Read for PanickingBody {
+        fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+            panic!("body reader failed")
+        }
+    }
+
+    #[test]
+    fn test_panic_is_answered_with_500() {
+        let (status, body) = route_guarded(&Method::Post, "/query", PanickingBody);
+        assert_eq!(status, 500);
+        assert!(body.contains("internal_error"), "{}", body);
+        let query = r#"{"pipeline": {"steps": []}, "metrics": []}"#;
+        assert_eq!(route_guarded(&Method::Post, "/query", query.as_bytes()).0, 200);
+    }
+}
+
+#[cfg(test)]
+mod test_metric_builder {
+    use crate::errors::MetricQueryError;
+    use crate::validation::{MetricBuilder, TimestampRules};
+
+    #[test]
+    fn test_build_many_from_parallel_arrays() {
+        let labels = vec![Some("cpu".to_string()), None];
+        let metrics = MetricBuilder::new().build_many(&[1, 2], &[60, 120], Some(&labels)).unwrap();
+        assert_eq!(metrics.len(), 2);
+        assert_eq!(metrics[0].label.as_deref(), Some("cpu"));
+        assert_eq!(metrics[1].value, 2);
+    }
+
+    #[test]
+    fn test_value_out_of_range_is_rejected() {
+        let builder = MetricBuilder::new().with_value_range(Some(0), Some(100));
+        let err = builder.build_many(&[50, 101], &[60, 120], None).unwrap_err();
+        assert_eq!(err.code(), "invalid_metric");
+        assert_eq!(err.metric_index(), Some(1));
+        assert!(err.to_string().contains("outside [0, 100]"), "{}", err);
+    }
+
+    #[test]
+    fn test_timestamp_errors_count_offenders() {
+        let err = MetricBuilder::new().build_many(&[1, 2, 3], &[60, 0, 0], None).unwrap_err();
+        match err {
+            MetricQueryError::InvalidTimestamp { index, offenders, .. } => {
+                assert_eq!(index, 1);
+                assert_eq!(offenders, 2);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+
+        let lenient = MetricBuilder::new()
+            .with_timestamp_rules(TimestampRules { allow_negative: true, allow_zero: true, max_future_seconds: None });
+        assert!(lenient.build(1, -60, None).is_ok());
+    }
+
+    #[test]
+    fn test_label_format() {
+        let builder = MetricBuilder::new().with_max_label_length(8);
+        assert!(builder.build(1, 60, Some("cpu.user".to_string())).is_ok());
+        assert!(builder.build(1, 60, Some(String::new())).is_err());
+        assert!(builder.build(1, 60, Some("cpu user".to_string())).is_err());
+        assert!(builder.build(1, 60, Some("cpu.system".to_string())).is_err());
+        assert!(builder.require_label(true).build(1, 60, None).is_err());
+    }
+
+    #[test]
+    fn test_mismatched_lengths_are_rejected() {
+        assert!(MetricBuilder::new().build_many(&[1, 2], &[60], None).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_time_range {
+    use crate::time_range::{parse_timezone, TimeRange};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_trailing_window_includes_end() {
+        let range = TimeRange::trailing(3600, 10_000).unwrap();
+        assert_eq!(range, TimeRange { start: 6_400, end: 10_001 });
+        assert!(range.contains(6_400));
+        assert!(range.contains(10_000));
+        assert!(!range.contains(10_001));
+    }
+
+    #[test]
+    fn test_day_containing_respects_timezone() {
+        // 2024-03-10 12:00 UTC; New York switched to daylight time at 02:00 local that day
+        let noon = Utc.with_ymd_and_hms(2024, 3, 10, 12, 0, 0).unwrap().timestamp();
+
+        let utc_day = TimeRange::day_containing(noon, parse_timezone("UTC").unwrap()).unwrap();
+        assert_eq!(utc_day.start, Utc.with_ymd_and_hms(2024, 3, 10, 0, 0, 0).unwrap().timestamp());
+        assert_eq!(utc_day.duration_seconds(), 86_400);
+
+        let ny_day = TimeRange::day_containing(noon, parse_timezone("America/New_York").unwrap()).unwrap();
+        assert_eq!(ny_day.start, Utc.with_ymd_and_hms(2024, 3, 10, 5, 0, 0).unwrap().timestamp());
+        assert_eq!(ny_day.duration_seconds(), 23 * 3600);
+    }
+
+    #[test]
+    fn test_between_and_validation() {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+        assert_eq!(TimeRange::between(start, end).unwrap().duration_seconds(), 86_400);
+        assert!(TimeRange::between(end, start).is_err());
+        assert!(TimeRange::today("Mars/Olympus_Mons").is_err());
+    }
+
+    #[test]
+    fn test_last_hours_ends_now() {
+        let now = Utc::now().timestamp();
+        let range = TimeRange::last_hours(24).unwrap();
+        assert!(range.contains(now));
+        assert!(range.contains(now - 86_400 + 60));
+        assert!(!range.contains(now - 86_400 - 60));
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_python_accepts_datetimes_and_ints() {
+        use crate::time_range::extract_timestamp;
+        use pyo3::prelude::*;
+        use pyo3::types::PyDict;
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new(py);
+            py.run(
+                c"import datetime\naware = datetime.datetime(2024, 1, 1, 1, tzinfo=datetime.timezone(datetime.timedelta(hours=1)))\nnaive = datetime.datetime(2024, 1, 1)",
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+            let midnight = 1_704_067_200;
+            assert_eq!(extract_timestamp(&locals.get_item("aware").unwrap().unwrap()).unwrap(), midnight);
+            assert_eq!(extract_timestamp(&locals.get_item("naive").unwrap().unwrap()).unwrap(), midnight);
+            assert_eq!(extract_timestamp(&midnight.into_pyobject(py).unwrap()).unwrap(), midnight);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_run_stats {
+    use super::*;
+    use crate::models::FloatMetric;
+
+    fn pipeline() -> MetricPipeline {
+        let metrics = (0..10).map(|i| Metric::new(i, 60 * i, None)).collect();
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(3)));
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        pipeline
+    }
+
+    #[test]
+    fn test_run_records_counts_per_step() {
+        let pipeline = pipeline();
+        assert!(pipeline.last_run_stats().is_none());
+
+        pipeline.run().unwrap();
+        let stats = pipeline.last_run_stats().unwrap();
+        assert_eq!((stats.input_count, stats.output_count, stats.dropped), (10, 1, 9));
+        assert_eq!(stats.steps.len(), 2);
+        assert_eq!((stats.steps[0].input_count, stats.steps[0].output_count), (10, 6));
+        assert_eq!(stats.steps[0].dropped, 4);
+        assert_eq!((stats.steps[1].input_count, stats.steps[1].output_count), (6, 1));
+        assert!(stats.wall_time_seconds >= stats.steps.iter().map(|s| s.elapsed_seconds).sum::<f64>());
+    }
+
+    #[test]
+    fn test_columnar_and_float_runs_record_stats() {
+        let pipeline = pipeline();
+        pipeline.execute_columns(&[5, 6], &[0, 60]).unwrap();
+        assert_eq!(pipeline.last_run_stats().unwrap().input_count, 2);
+
+        let floats = vec![FloatMetric::new(Some(1.0), 0, None); 3];
+        pipeline.execute_float_metrics(&floats).unwrap_err();
+        // A failed run keeps the previous stats
+        assert_eq!(pipeline.last_run_stats().unwrap().input_count, 2);
+    }
+}
+
+#[cfg(test)]
+mod test_tap {
+    use super::*;
+    use crate::errors::MetricQueryError;
+    use crate::steps::{TapBatch, TapTransformation};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_tap_samples_without_altering_stream() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let recorder = Arc::clone(&seen);
+        let tap = TapTransformation::new(move |batch| {
+            if let TapBatch::Metrics(metrics) = batch {
+                recorder.lock().unwrap().extend(metrics.iter().map(|m| m.value));
+            }
+            Ok(())
+        })
+        .with_sample(Some(2));
+
+        let mut pipeline = MetricPipeline::new((1..=5).map(|i| Metric::new(i, i, None)).collect());
+        pipeline.add_strategy(Box::new(tap));
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(3)));
+
+        let result = pipeline.run().unwrap();
+        assert_eq!(result.len(), 2);
+        assert_eq!(*seen.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_callback_error_fails_pipeline() {
+        let tap = TapTransformation::new(|_| {
+            Err(MetricQueryError::OperationFailed { operation: "tap".to_string(), reason: "stop".to_string() })
+        });
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 1, None)]);
+        pipeline.add_strategy(Box::new(tap));
+        assert_eq!(pipeline.run().unwrap_err().plugin_name(), Some("tap"));
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_python_callback_receives_metrics() {
+        use pyo3::prelude::*;
+        use pyo3::types::PyList;
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let calls = PyList::empty(py);
+            let callback = calls.getattr("append").unwrap().unbind();
+
+            let mut pipeline = MetricPipeline::new((1..=3).map(|i| Metric::new(i, i, None)).collect());
+            pipeline.tap(callback, Some(2)).unwrap();
+            assert_eq!(pipeline.execute().unwrap().len(), 3);
+
+            let sample = calls.get_item(0).unwrap();
+            let values: Vec<i64> = sample
+                .try_iter()
+                .unwrap()
+                .map(|m| m.unwrap().getattr("value").unwrap().extract().unwrap())
+                .collect();
+            assert_eq!(values, vec![1, 2]);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_assertion {
+    use super::*;
+    use crate::errors::MetricQueryError;
+    use crate::models::FloatMetric;
+    use crate::steps::{Assertion, AssertionTransformation, TapBatch};
+    use std::sync::Arc;
+
+    fn run_with(assertion: Assertion, metrics: Vec<Metric>) -> crate::errors::MetricQueryResult<Vec<Metric>> {
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_strategy(Box::new(AssertionTransformation::new(assertion)));
+        pipeline.run()
+    }
+
+    #[test]
+    fn test_builtin_assertions_pass_valid_data() {
+        let metrics: Vec<Metric> = (1..=3).map(|i| Metric::new(i * 10, i, None)).collect();
+        assert_eq!(run_with(Assertion::NonEmpty, metrics.clone()).unwrap().len(), 3);
+        assert_eq!(run_with(Assertion::Sorted, metrics.clone()).unwrap().len(), 3);
+        assert!(run_with(Assertion::parse("within_range", Some(10.0), Some(30.0)).unwrap(), metrics).is_ok());
+    }
+
+    #[test]
+    fn test_failure_reports_offending_index() {
+        let metrics = vec![Metric::new(1, 10, None), Metric::new(2, 20, None), Metric::new(3, 15, None)];
+        let err = run_with(Assertion::Sorted, metrics).unwrap_err();
+        assert_eq!(err.code(), "assertion_failed");
+        assert_eq!(err.metric_index(), Some(2));
+
+        let err = run_with(Assertion::NonEmpty, Vec::new()).unwrap_err();
+        assert_eq!(err.code(), "assertion_failed");
+    }
+
+    #[test]
+    fn test_custom_message_and_float_path() {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_strategy(Box::new(
+            AssertionTransformation::new(Assertion::NoMissing).with_message("gaps in ingest"),
+        ));
+        let metrics = vec![FloatMetric::new(Some(1.0), 1, None), FloatMetric::new(None, 2, None)];
+        let err = pipeline.execute_float_metrics(&metrics).unwrap_err();
+        assert!(err.to_string().contains("gaps in ingest"));
+        assert_eq!(err.metric_index(), Some(1));
+    }
+
+    #[test]
+    fn test_custom_predicate() {
+        let predicate: crate::steps::AssertionPredicate =
+            Arc::new(|batch| Ok(matches!(batch, TapBatch::Metrics(metrics) if metrics.len() > 1)));
+        assert!(run_with(Assertion::Custom(predicate.clone()), vec![Metric::new(1, 1, None)]).is_err());
+        assert!(run_with(Assertion::Custom(predicate), vec![Metric::new(1, 1, None); 2]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_and_unbounded() {
+        assert!(matches!(Assertion::parse("bogus", None, None), Err(MetricQueryError::OperationFailed { .. })));
+        assert!(Assertion::parse("within_range", None, None).is_err());
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_python_callable_predicate() {
+        use pyo3::prelude::*;
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let predicate = py.eval(pyo3::ffi::c_str!("lambda ms: len(ms) > 1"), None, None).unwrap();
+            let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 1, None)]);
+            pipeline.assert_that(&predicate, Some("need two".to_string()), None, None).unwrap();
+            let err = pipeline.execute().unwrap_err();
+            assert!(err.to_string().contains("need two"));
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_add_metrics {
+    use super::*;
+
+    #[test]
+    fn test_batches_accumulate_before_execution() {
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 1, None)]);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(1)));
+        pipeline.add_metrics(vec![Metric::new(2, 2, None), Metric::new(3, 3, None)]);
+        pipeline.add_metrics(std::iter::once(Metric::new(4, 4, None)));
+
+        let values: Vec<i64> = pipeline.run().unwrap().iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn test_extend_from_copies_metrics_not_steps() {
+        let mut other = MetricPipeline::new(vec![Metric::new(5, 5, None)]);
+        other.add_filter(Box::new(GreaterThanFilter::new(100)));
+
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 1, None)]);
+        pipeline.extend_from(&other);
+        assert_eq!(pipeline.run().unwrap().len(), 2);
+        assert_eq!(other.run().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_appends_leave_the_shared_input_alone() {
+        let shared: std::sync::Arc<[Metric]> = vec![Metric::new(1, 1, None)].into();
+        let mut pipeline = MetricPipeline::from_shared(std::sync::Arc::clone(&shared));
+        for i in 2..100 {
+            pipeline.add_metrics(std::iter::once(Metric::new(i, i, None)));
+        }
+        // Appends are buffered; the pipeline still holds the original input rather than copies
+        assert_eq!(std::sync::Arc::strong_count(&shared), 2);
+        assert_eq!(pipeline.run().unwrap().len(), 99);
+
+        pipeline.freeze_input();
+        let frozen = pipeline.shared_metrics();
+        assert_eq!(frozen.len(), 99);
+        assert!(std::sync::Arc::ptr_eq(&frozen, &pipeline.shared_metrics()));
+        assert_eq!(shared.len(), 1);
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_python_extend_with_itself() {
+        use pyo3::prelude::*;
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let pipeline = Bound::new(py, MetricPipeline::new(vec![Metric::new(1, 1, None)])).unwrap();
+            pipeline.call_method1("extend", (pipeline.clone(),)).unwrap();
+            assert_eq!(pipeline.borrow().run().unwrap().len(), 2);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_grouped_results {
+    use super::*;
+
+    #[test]
+    fn test_run_grouped_partitions_by_label() {
+        let pipeline = MetricPipeline::new(vec![
+            Metric::new(1, 1, Some("web".to_string())),
+            Metric::new(2, 2, None),
+            Metric::new(3, 3, Some("db".to_string())),
+            Metric::new(4, 4, Some("web".to_string())),
+        ]);
+
+        let groups = pipeline.run_grouped().unwrap();
+        let keys: Vec<Option<&str>> = groups.keys().map(|k| k.as_deref()).collect();
+        assert_eq!(keys, vec![None, Some("db"), Some("web")]);
+        let web: Vec<i64> = groups[&Some("web".to_string())].iter().map(|m| m.value).collect();
+        assert_eq!(web, vec![1, 4]);
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_execute_grouped_returns_dict() {
+        use pyo3::prelude::*;
+        use pyo3::types::PyDict;
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let pipeline = MetricPipeline::new(vec![
+                Metric::new(1, 1, Some("web".to_string())),
+                Metric::new(2, 2, None),
+            ]);
+            let groups = pipeline.execute_grouped().unwrap().into_pyobject(py).unwrap();
+            let groups = groups.downcast::<PyDict>().unwrap();
+            assert_eq!(groups.len(), 2);
+            assert!(groups.contains(py.None()).unwrap());
+            assert!(groups.contains("web").unwrap());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_result_summary {
+    use super::*;
+    use crate::analysis::ResultSummary;
+    use crate::errors::{MetricQueryError, MetricQueryResult};
+    use crate::transformations::TransformationStrategy;
+    use crate::warnings::WarningSink;
+
+    #[test]
+    fn test_run_summary_reports_headline_numbers() {
+        let mut pipeline = MetricPipeline::new((1..=5).map(|i| Metric::new(i * 10, i, None)).collect());
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(15)));
+
+        let summary = pipeline.run_summary().unwrap();
+        assert_eq!(summary.count, 4);
+        assert_eq!(summary.sum, 140);
+        assert_eq!((summary.min, summary.max), (Some(20), Some(50)));
+        assert_eq!(summary.mean, Some(35.0));
+    }
+
+    /// Emits its input from `apply_into` only, so a run that builds its output fails
+    #[derive(Clone)]
+    struct EmitOnly;
+
+    impl TransformationStrategy for EmitOnly {
+        fn apply(&self, _metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+            Err(MetricQueryError::OperationFailed { operation: "emit_only".to_string(), reason: "built".to_string() })
+        }
+
+        fn apply_into(
+            &self,
+            metrics: &[Metric],
+            _warnings: &mut WarningSink,
+            sink: &mut dyn FnMut(&Metric) -> MetricQueryResult<()>,
+        ) -> MetricQueryResult<usize> {
+            metrics.iter().try_for_each(sink)?;
+            Ok(metrics.len())
+        }
+    }
+
+    #[test]
+    fn test_last_step_folds_into_the_summary() {
+        let mut pipeline = MetricPipeline::new((1..=5).map(|i| Metric::new(i, i, None)).collect());
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(1)));
+        pipeline.add_strategy(Box::new(EmitOnly));
+        assert!(pipeline.run().is_err());
+        let summary = pipeline.run_summary().unwrap();
+        assert_eq!((summary.count, summary.sum), (4, 14));
+        assert_eq!(pipeline.last_run_stats().unwrap().output_count, 4);
+
+        let summary = MetricPipeline::new(vec![Metric::new(7, 1, None)]).run_summary().unwrap();
+        assert_eq!((summary.count, summary.sum), (1, 7));
+    }
+
+    #[test]
+    fn test_filter_summary_matches_the_result() {
+        let mut metrics: Vec<Metric> = (1..=6).map(|i| Metric::new(i * 10, i, None)).collect();
+        metrics.push(Metric::stale_marker(7, None));
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(25)));
+        assert_eq!(pipeline.run_summary().unwrap(), ResultSummary::of(&pipeline.run().unwrap()).unwrap());
+
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 1, None)]);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(100)));
+        let (summary, warnings) = pipeline.run_summary_with_warnings().unwrap();
+        assert_eq!(summary.count, 0);
+        assert_eq!(warnings[0].code, "filtered_to_empty");
+    }
+
+    #[test]
+    fn test_empty_result_has_zero_count() {
+        let summary = ResultSummary::of(&[]).unwrap();
+        assert_eq!(summary.count, 0);
+        assert_eq!(summary.min, None);
+        assert_eq!(summary.mean, None);
+    }
+
+    #[test]
+    fn test_sum_overflow_errors() {
+        let metrics = vec![Metric::new(i64::MAX, 1, None), Metric::new(1, 2, None)];
+        assert!(matches!(ResultSummary::of(&metrics), Err(MetricQueryError::ArithmeticOverflow { .. })));
+    }
+
+    #[test]
+    fn test_stale_markers_and_scales() {
+        let metrics = vec![
+            Metric::new(150, 1, None).with_scale(2),
+            Metric::new(2, 2, None),
+            Metric::stale_marker(3, None),
+            Metric::new(1250, 4, None).with_scale(3),
+        ];
+        let summary = ResultSummary::of(&metrics).unwrap();
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.scale, Some(3));
+        assert_eq!(summary.sum, 4750);
+        assert_eq!((summary.min, summary.max), (Some(1250), Some(2000)));
+        assert!((summary.mean.unwrap() - 4.75 / 3.0).abs() < 1e-12);
+    }
+}
+
+#[cfg(test)]
+mod test_histogram_metric {
+    use crate::models::HistogramMetric;
+    use crate::plugin_impls::HourGrouping;
+
+    fn latency(counts: Vec<u64>, timestamp: i64) -> HistogramMetric {
+        HistogramMetric::new(vec![0.1, 0.5, 1.0], counts, 0.0, timestamp, Some("api".to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_new_validates_shape() {
+        assert!(HistogramMetric::new(vec![1.0, 0.5], vec![0, 0, 0], 0.0, 0, None).is_err());
+        assert!(HistogramMetric::new(vec![1.0], vec![1], 0.0, 0, None).is_err());
+    }
+
+    #[test]
+    fn test_quantile_interpolates_within_bucket() {
+        let histogram = latency(vec![0, 10, 10, 0], 0);
+        assert_eq!(histogram.count(), 20);
+        assert!((histogram.quantile(0.5).unwrap() - 0.5).abs() < 1e-9);
+        assert!((histogram.quantile(0.75).unwrap() - 0.75).abs() < 1e-9);
+        assert!(histogram.quantile(1.5).is_err());
+
+        // Anything in the +Inf bucket reports the highest finite bound
+        assert_eq!(latency(vec![0, 0, 0, 5], 0).quantile(0.99).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn test_group_by_time_merges_buckets() {
+        let histograms = vec![latency(vec![1, 2, 0, 0], 10), latency(vec![0, 3, 1, 1], 3500), latency(vec![4, 0, 0, 0], 3700)];
+        let grouped = HistogramMetric::group_by_time(&histograms, &HourGrouping).unwrap();
+
+        assert_eq!(grouped.len(), 2);
+        assert_eq!(grouped[0].timestamp, 0);
+        assert_eq!(grouped[0].counts, vec![1, 5, 1, 1]);
+        assert_eq!(grouped[1].timestamp, 3600);
+        assert_eq!(grouped[1].counts, vec![4, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_merge_rejects_different_bounds() {
+        let mut histogram = latency(vec![1, 0, 0, 0], 0);
+        let other = HistogramMetric::new(vec![1.0], vec![1, 0], 0.0, 0, None).unwrap();
+        assert!(histogram.merge(&other).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_sketch_metric {
+    use super::*;
+    use crate::errors::MetricQueryError;
+    use crate::models::{FloatMetric, SketchMetric};
+    use crate::plugin_impls::HourGrouping;
+
+    fn within(estimate: f64, actual: f64, accuracy: f64) -> bool {
+        (estimate - actual).abs() <= actual.abs() * accuracy + 1e-9
+    }
+
+    #[test]
+    fn test_quantiles_within_relative_accuracy() {
+        let mut sketch = SketchMetric::new(0.01, 0, None).unwrap();
+        (1..=1000).for_each(|v| sketch.add(v as f64));
+        assert_eq!(sketch.count, 1000);
+        assert!(within(sketch.quantile(0.5).unwrap(), 500.0, 0.01));
+        assert!(within(sketch.quantile(0.99).unwrap(), 990.0, 0.01));
+        assert_eq!(sketch.quantile(1.0).unwrap(), 1000.0);
+    }
+
+    #[test]
+    fn test_merge_matches_single_sketch() {
+        let mut left = SketchMetric::new(0.02, 0, None).unwrap();
+        let mut right = SketchMetric::new(0.02, 0, None).unwrap();
+        let mut whole = SketchMetric::new(0.02, 0, None).unwrap();
+        for v in -50..50 {
+            let value = v as f64 * 1.5;
+            if v % 2 == 0 { left.add(value) } else { right.add(value) }
+            whole.add(value);
+        }
+        left.merge(&right).unwrap();
+        for q in [0.0, 0.1, 0.5, 0.9, 1.0] {
+            assert_eq!(left.quantile(q).unwrap(), whole.quantile(q).unwrap());
+        }
+        assert!(left.merge(&SketchMetric::new(0.05, 0, None).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_merge_overflow_leaves_sketch_unchanged() {
+        let mut sketch = SketchMetric::new(0.01, 0, None).unwrap();
+        sketch.add(1.0);
+        let mut full = sketch.clone();
+        full.count = u64::MAX;
+        let before = sketch.clone();
+        assert!(matches!(sketch.merge(&full), Err(MetricQueryError::ArithmeticOverflow { .. })));
+        assert_eq!(sketch, before);
+    }
+
+    #[test]
+    fn test_pipeline_builds_sketches_per_group() {
+        let metrics: Vec<Metric> = (0..10)
+            .map(|i| Metric::new(i, i * 600, Some("api".to_string())))
+            .collect();
+        let sketches = MetricPipeline::new(metrics).run_sketches(&HourGrouping, 0.01).unwrap();
+        assert_eq!(sketches.len(), 2);
+        assert_eq!((sketches[0].timestamp, sketches[0].count), (0, 6));
+        assert_eq!((sketches[1].timestamp, sketches[1].count), (3600, 4));
+
+        let merged = SketchMetric::group_by_time(&sketches, &crate::plugin_impls::DayGrouping).unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].count, 10);
+        assert!(SketchMetric::from_metrics(&[FloatMetric::new(None, 0, None)], &HourGrouping, 0.01).unwrap()[0]
+            .quantile(0.5)
+            .is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_exemplars {
+    use super::*;
+    use crate::models::Exemplar;
+    use crate::plugin_impls::{HourGrouping, MaxAggregation};
+
+    fn traced(value: i64, timestamp: i64, trace: &str) -> Metric {
+        Metric::new(value, timestamp, Some("latency".to_string()))
+            .with_exemplar(Exemplar::new(trace.to_string(), value as f64, timestamp))
+    }
+
+    #[test]
+    fn test_time_grouping_keeps_worst_exemplar_per_bucket() {
+        let metrics = vec![
+            traced(120, 10, "a"),
+            traced(900, 20, "slow"),
+            Metric::new(300, 30, Some("latency".to_string())),
+            traced(50, 3700, "b"),
+            Metric::new(10, 3800, None),
+        ];
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(MaxAggregation));
+
+        let mut result = pipeline.run().unwrap();
+        result.sort_by_key(|m| (m.label.clone(), m.timestamp));
+        let traces: Vec<Option<&str>> =
+            result.iter().map(|m| m.exemplar.as_ref().map(|e| e.trace_id.as_str())).collect();
+        assert_eq!(traces, vec![None, Some("slow"), Some("b")]);
+    }
+
+    #[test]
+    fn test_aggregation_and_filters_carry_exemplars() {
+        let mut pipeline = MetricPipeline::new(vec![traced(5, 1, "x"), traced(7, 2, "y")]);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(4)));
+        pipeline.add_aggregation(Box::new(MaxAggregation));
+
+        let result = pipeline.run().unwrap();
+        assert_eq!(result[0].exemplar.as_ref().unwrap().trace_id, "y");
+    }
+}
+
+#[cfg(test)]
+mod test_multi_field_metrics {
+    use super::*;
+    use std::collections::BTreeMap;
+    use crate::models::Fields;
+    use crate::plugin_impls::{HourGrouping, OverflowPolicy, SumAggregation};
+    use crate::steps::{FieldFilter, SelectFieldTransformation};
+
+    fn traffic(bytes_in: i64, bytes_out: i64, timestamp: i64) -> Metric {
+        Metric::new(0, timestamp, Some("eth0".to_string()))
+            .with_field("bytes_in", bytes_in)
+            .with_field("bytes_out", bytes_out)
+    }
+
+    #[test]
+    fn test_aggregate_selected_field() {
+        let mut pipeline = MetricPipeline::new(vec![traffic(10, 1, 0), traffic(20, 2, 60), traffic(5, 3, 3600)]);
+        pipeline.add_strategy(Box::new(SelectFieldTransformation::new("bytes_in")));
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::new(OverflowPolicy::Error)));
+
+        let mut result = pipeline.run().unwrap();
+        result.sort_by_key(|m| m.timestamp);
+        let sums: Vec<i64> = result.iter().map(|m| m.value).collect();
+        assert_eq!(sums, vec![30, 5]);
+    }
+
+    #[test]
+    fn test_field_filter_keeps_all_fields() {
+        let mut pipeline = MetricPipeline::new(vec![traffic(10, 1, 0), traffic(20, 2, 60)]);
+        pipeline.add_filter(Box::new(FieldFilter::new("bytes_in", Box::new(GreaterThanFilter::new(15)))));
+
+        let result = pipeline.run().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].field("bytes_out"), Some(2));
+    }
+
+    #[test]
+    fn test_missing_field_is_dropped_with_warning() {
+        let mut pipeline = MetricPipeline::new(vec![traffic(10, 1, 0), Metric::new(7, 60, None)]);
+        pipeline.add_strategy(Box::new(SelectFieldTransformation::new("bytes_out")));
+
+        let (result, warnings) = pipeline.run_with_warnings().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].value, 1);
+        assert_eq!(warnings[0].code, "missing_field");
+    }
+
+    #[test]
+    fn test_fields_are_unallocated_until_set() {
+        assert_eq!(std::mem::size_of::<Fields>(), std::mem::size_of::<usize>());
+        assert!(Metric::new(1, 0, None).fields.is_empty());
+        // An empty map is the same as no fields at all
+        assert_eq!(Fields::from(BTreeMap::new()), Fields::default());
+
+        let metric = traffic(10, 1, 0);
+        assert_eq!(metric.fields.iter().collect::<Vec<_>>(), vec![("bytes_in", 10), ("bytes_out", 1)]);
+        assert_eq!(metric.field("bytes_in"), Some(10));
+        assert_eq!(metric.field("packets"), None);
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod test_tracing {
+    use super::*;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::FmtSpan;
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_steps_emit_spans_with_counts() {
+        let captured = Captured::default();
+        let writer = captured.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_span_events(FmtSpan::CLOSE)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let mut pipeline = MetricPipeline::new((1..=4).map(|i| Metric::new(i, i, None)).collect());
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(2)));
+        tracing::subscriber::with_default(subscriber, || pipeline.run().unwrap());
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("pipeline_step"), "{}", output);
+        assert!(output.contains("output_count=2"), "{}", output);
+        assert!(output.contains("pipeline_run"), "{}", output);
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_python_logging {
+    use crate::logging::{py_set_log_level, PythonLogger};
+    use log::Log;
+    use pyo3::prelude::*;
+
+    #[test]
+    fn test_records_reach_python_logger() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let code = pyo3::ffi::c_str!(
+                "import logging\n\
+                 class Capture(logging.Handler):\n    \
+                     def __init__(self):\n        super().__init__()\n        self.records = []\n    \
+                     def emit(self, record):\n        self.records.append((record.name, record.levelno, record.getMessage()))\n\
+                 capture = Capture()\n\
+                 logging.getLogger('metric_query_library').addHandler(capture)\n\
+                 logging.getLogger('metric_query_library').setLevel(logging.DEBUG)\n"
+            );
+            let globals = pyo3::types::PyDict::new(py);
+            py.run(code, Some(&globals), None).unwrap();
+
+            // The process-wide level gates records before they cross into Python
+            log::set_max_level(log::LevelFilter::Debug);
+            PythonLogger.log(
+                &log::Record::builder()
+                    .level(log::Level::Debug)
+                    .target("metric_query_library::stats")
+                    .args(format_args!("step 0 (gt): 4 -> 2 metrics"))
+                    .build(),
+            );
+
+            let records: Vec<(String, u8, String)> =
+                globals.get_item("capture").unwrap().unwrap().getattr("records").unwrap().extract().unwrap();
+            assert_eq!(
+                records,
+                vec![("metric_query_library.stats".to_string(), 10, "step 0 (gt): 4 -> 2 metrics".to_string())]
+            );
+        });
+    }
+
+    #[test]
+    fn test_python_levels_are_cached_until_set_log_level() {
+        pyo3::prepare_freethreaded_python();
+        let debug = || {
+            PythonLogger.log(
+                &log::Record::builder()
+                    .level(log::Level::Debug)
+                    .target("metric_query_library::cached")
+                    .args(format_args!("probe"))
+                    .build(),
+            )
+        };
+        let globals = Python::with_gil(|py| {
+            let code = pyo3::ffi::c_str!(
+                "import logging\n\
+                 class Count(logging.Handler):\n    \
+                     def __init__(self):\n        super().__init__()\n        self.count = 0\n    \
+                     def emit(self, record):\n        self.count += 1\n\
+                 counter = Count()\n\
+                 cached = logging.getLogger('metric_query_library.cached')\n\
+                 cached.addHandler(counter)\n\
+                 cached.setLevel(logging.WARNING)\n"
+            );
+            let globals = pyo3::types::PyDict::new(py);
+            py.run(code, Some(&globals), None).unwrap();
+            globals.unbind()
+        });
+        let count = || -> usize {
+            Python::with_gil(|py| {
+                globals.bind(py).get_item("counter").unwrap().unwrap().getattr("count").unwrap().extract().unwrap()
+            })
+        };
+
+        log::set_max_level(log::LevelFilter::Debug);
+        debug();
+        // The cached WARNING level keeps dropping debug records without asking Python
+        Python::with_gil(|py| py.run(pyo3::ffi::c_str!("cached.setLevel(logging.DEBUG)"), Some(globals.bind(py)), None))
+            .unwrap();
+        debug();
+        assert_eq!(count(), 0);
+
+        py_set_log_level("debug").unwrap();
+        debug();
+        assert_eq!(count(), 1);
+    }
+}
+
+#[cfg(test)]
+mod test_compare_results {
+    use crate::compare::compare_results;
+    use crate::models::FloatMetric;
+
+    fn point(value: f64, timestamp: i64, label: &str) -> FloatMetric {
+        FloatMetric::new(Some(value), timestamp, Some(label.to_string()))
+    }
+
+    #[test]
+    fn test_identical_sets_in_any_order_match() {
+        let a = vec![point(1.0, 0, "a"), point(2.0, 60, "a"), FloatMetric::new(None, 0, None)];
+        let b = vec![FloatMetric::new(None, 0, None), point(2.0, 60, "a"), point(1.0, 0, "a")];
+        assert!(compare_results(&a, &b, 0.0, 0).is_empty());
+    }
+
+    #[test]
+    fn test_reports_missing_unexpected_and_mismatched() {
+        let expected = vec![point(1.0, 0, "a"), point(2.0, 60, "a"), point(3.0, 0, "b")];
+        let actual = vec![point(1.05, 1, "a"), point(9.0, 60, "a"), point(3.0, 0, "c")];
+
+        let diff = compare_results(&expected, &actual, 0.1, 1);
+        assert_eq!(diff.missing, vec![point(3.0, 0, "b")]);
+        assert_eq!(diff.unexpected, vec![point(3.0, 0, "c")]);
+        assert_eq!(diff.mismatched.len(), 1);
+        assert_eq!((diff.mismatched[0].expected, diff.mismatched[0].actual), (2.0, 9.0));
+    }
+
+    #[test]
+    fn test_each_actual_point_matches_once() {
+        let expected = vec![point(1.0, 10, "a"), point(1.0, 11, "a")];
+        let actual = vec![point(1.0, 10, "a")];
+        let diff = compare_results(&expected, &actual, 0.0, 5);
+        assert_eq!(diff.missing, vec![point(1.0, 11, "a")]);
+        assert!(diff.unexpected.is_empty());
+    }
+}
+
+#[cfg(test)]
+mod test_clone_pipeline {
+    use super::*;
+    use crate::plugin_impls::{HourGrouping, LabelFilter, MaxAggregation};
+
+    #[test]
+    fn test_clone_can_be_specialised_independently() {
+        let metrics = vec![
+            Metric::new(5, 0, Some("tenant-a".to_string())),
+            Metric::new(50, 60, Some("tenant-b".to_string())),
+            Metric::new(7, 120, Some("tenant-a".to_string())),
+        ];
+        let mut base = MetricPipeline::new(metrics);
+        base.add_filter(Box::new(GreaterThanFilter::new(1)));
+
+        let mut tenant = base.clone_pipeline();
+        tenant.add_filter(Box::new(LabelFilter::new("tenant-a".to_string())));
+        tenant.add_time_grouping(Box::new(HourGrouping), Box::new(MaxAggregation));
+
+        assert_eq!(base.run().unwrap().len(), 3);
+        let result = tenant.run().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].value, 7);
+        assert!(tenant.last_run_stats().is_some());
+        assert_eq!(base.clone().last_run_stats(), None);
+    }
+
+    #[cfg(feature = "python")]
+    #[test]
+    fn test_python_deepcopy() {
+        use pyo3::prelude::*;
+
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut base = MetricPipeline::new(vec![Metric::new(1, 1, None), Metric::new(9, 2, None)]);
+            base.add_filter(Box::new(GreaterThanFilter::new(5)));
+            let base = Bound::new(py, base).unwrap();
+
+            let copy = py.import("copy").unwrap().call_method1("deepcopy", (&base,)).unwrap();
+            let copy = copy.downcast::<MetricPipeline>().unwrap();
+            copy.borrow_mut().select_field("missing".to_string());
+
+            assert_eq!(base.borrow().run().unwrap().len(), 1);
+            assert_eq!(copy.borrow().run().unwrap().len(), 0);
+        });
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_live_registry {
+    use crate::models::Metric;
+    use crate::plugin_impls::{init_registry, Threshold};
+    use crate::plugins::{BoundFilter, FilterArg, TransformationRegistry};
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    fn run_python(code: &std::ffi::CStr) -> Py<PyDict> {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("registry", Py::new(py, TransformationRegistry).unwrap()).unwrap();
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            py.run(code, Some(&globals), None).unwrap();
+            globals.unbind()
+        })
+    }
+
+    #[test]
+    fn test_view_sees_new_plugins_without_refresh() {
+        let globals = run_python(c"
+filters = registry.filters
+registry.register_filter('view_even', lambda m: m.value % 2 == 0)
+before = any(f.name == 'view_even' for f in filters)
+after = any(f.name == 'view_even' for f in registry.filters)
+has_even = registry.has_filter('view_even')
+");
+        Python::with_gil(|py| {
+            let globals = globals.bind(py);
+            assert!(!globals.get_item("before").unwrap().unwrap().extract::<bool>().unwrap());
+            assert!(globals.get_item("after").unwrap().unwrap().extract::<bool>().unwrap());
+            assert!(globals.get_item("has_even").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_registered_plugins_are_usable_in_pipelines() {
+        run_python(c"
+registry.register_filter('even', lambda m: m.value % 2 == 0)
+registry.register_aggregation('spread', lambda values: max(values) - min(values))
+registry.register_time_grouping('ten', lambda ts: ts - ts % 10)
+");
+        Python::with_gil(|py| {
+            let metrics = (0..20).map(|i| Metric::new(i, i, None)).collect();
+            let mut pipeline = MetricPipeline::new(metrics);
+            pipeline.filter("even".into(), None, None).unwrap();
+            pipeline
+                .group_by_time(py, "ten", "spread", None, None, None, None, None, None, None)
+                .unwrap();
+            let result = pipeline.run().unwrap();
+            let mut values: Vec<(i64, i64)> = result.iter().map(|m| (m.timestamp, m.value)).collect();
+            values.sort_unstable();
+            assert_eq!(values, vec![(0, 8), (10, 8)]);
+        });
+    }
+
+    #[test]
+    fn test_raising_aggregation_fails_the_run() {
+        run_python(c"registry.register_aggregation('broken', lambda values: 1 / 0)");
+        Python::with_gil(|py| {
+            let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 0, None)]);
+            pipeline.aggregate(py, "broken", None, None, None, None, "first", None, None).unwrap();
+            assert!(pipeline.run().is_err());
+        });
+    }
+
+    #[test]
+    fn test_misplaced_option_is_named() {
+        run_python(c"");
+        Python::with_gil(|py| {
+            let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 0, None)]);
+            let error = pipeline
+                .aggregate(py, "sum", Some("error"), Some("round"), None, None, "first", None, None)
+                .unwrap_err();
+            assert!(error.to_string().contains("Rounding mode only applies to 'avg', not 'sum'"), "{}", error);
+            let error = pipeline
+                .aggregate(py, "avg", Some("error"), Some("round"), None, None, "first", None, None)
+                .unwrap_err();
+            assert!(error.to_string().contains("Overflow policy only applies to 'sum', not 'avg'"), "{}", error);
+        });
+    }
+
+    #[test]
+    fn test_filters_are_instantiated_with_their_value() {
+        let globals = run_python(c"
+registry.register_filter_factory('multiple_of', lambda n: lambda m: m.value % n == 0)
+gt25 = registry.create_filter('gt', 25)
+checks = (gt25(Metric(30, 0)), gt25(Metric(20, 0)))
+");
+        Python::with_gil(|py| {
+            let globals = globals.bind(py);
+            let checks: (bool, bool) = globals.get_item("checks").unwrap().unwrap().extract().unwrap();
+            assert_eq!(checks, (true, false));
+
+            let metrics: Vec<Metric> = (0..10).map(|i| Metric::new(i * 10, i, None)).collect();
+            let mut pipeline = MetricPipeline::new(metrics.clone());
+            pipeline.filter("gt".into(), Some(25.into()), None).unwrap();
+            let values: Vec<i64> = pipeline.run().unwrap().iter().map(|m| m.value).collect();
+            assert_eq!(values, vec![30, 40, 50, 60, 70, 80, 90]);
+
+            let bound: BoundFilter = globals.get_item("gt25").unwrap().unwrap().extract().unwrap();
+            let mut pipeline = MetricPipeline::new(metrics.clone());
+            pipeline.filter(FilterArg::Bound(bound), None, None).unwrap();
+            pipeline.filter("multiple_of".into(), Some(20.into()), None).unwrap();
+            let values: Vec<i64> = pipeline.run().unwrap().iter().map(|m| m.value).collect();
+            assert_eq!(values, vec![40, 60, 80]);
+
+            let mut pipeline = MetricPipeline::new(metrics.clone());
+            pipeline.filter("gt".into(), Some(Threshold::Float(25.5)), None).unwrap();
+            pipeline.filter("multiple_of".into(), Some(Threshold::Float(2.5)), None).unwrap();
+            let values: Vec<i64> = pipeline.run().unwrap().iter().map(|m| m.value).collect();
+            assert_eq!(values, vec![30, 40, 50, 60, 70, 80, 90]);
+
+            let bound: BoundFilter = globals.get_item("gt25").unwrap().unwrap().extract().unwrap();
+            let mut pipeline = MetricPipeline::new(metrics);
+            assert!(pipeline.filter("gt".into(), None, None).is_err());
+            assert!(pipeline.filter("nope".into(), Some(1.into()), None).is_err());
+            // Values are never silently dropped
+            assert!(pipeline.filter("label_eq".into(), Some(1.into()), None).is_err());
+            assert!(pipeline.filter(FilterArg::Bound(bound), Some(1.into()), None).is_err());
+        });
+    }
+
+    #[test]
+    fn test_plugins_are_shared_across_threads() {
+        run_python(c"
+def shared_factory(n):
+    registry.register_filter('shared_registered_by_factory', lambda m: True)
+    return lambda m: m.value >= n
+registry.register_filter_factory('shared_at_least', shared_factory)
+");
+        let values = std::thread::spawn(|| {
+            let mut pipeline = MetricPipeline::new((0..5).map(|i| Metric::new(i, i, None)).collect());
+            // The factory registers a filter while the pipeline resolves it
+            pipeline.filter("shared_at_least".into(), Some(3.into()), None).unwrap();
+            pipeline.run().unwrap().iter().map(|m| m.value).collect::<Vec<i64>>()
+        })
+        .join()
+        .unwrap();
+        assert_eq!(values, vec![3, 4]);
+        assert!(TransformationRegistry.has_filter("shared_registered_by_factory"));
+    }
+
+    #[test]
+    fn test_register_rejects_non_callables() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let registry = TransformationRegistry;
+            assert!(registry.register_filter("bad".to_string(), 3i64.into_pyobject(py).unwrap().as_any(), "skip").is_err());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_units {
+    use crate::errors::MetricQueryError;
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{HourGrouping, SumAggregation};
+    use crate::steps::ConvertUnitTransformation;
+    use crate::transformations::{
+        AggregationTransformation, MetricPipeline, TimeGroupingTransformation, TransformationStrategy,
+    };
+    use crate::units::conversion_factor;
+
+    #[test]
+    fn test_conversion_table() {
+        assert_eq!(conversion_factor("s", "ms").unwrap(), 1000.0);
+        assert_eq!(conversion_factor("h", "min").unwrap(), 60.0);
+        assert_eq!(conversion_factor("MiB", "KiB").unwrap(), 1024.0);
+        assert_eq!(conversion_factor("bytes", "bits").unwrap(), 8.0);
+        assert_eq!(conversion_factor("Tbit", "kbit").unwrap(), 1e9);
+        assert_eq!(conversion_factor("kW", "W").unwrap(), 1000.0);
+        assert_eq!(conversion_factor("mV", "V").unwrap(), 0.001);
+        assert!(conversion_factor("ms", "bytes").is_err());
+        assert!(conversion_factor("W", "V").is_err());
+    }
+
+    #[test]
+    fn test_convert_unit_rescales_and_tags() {
+        let metrics = vec![
+            Metric::new(3_145_728, 0, None).with_unit("bytes"),
+            Metric::new(2_097_152, 1, None),
+            Metric::new(5, 2, None).with_unit("MiB"),
+        ];
+        let step = ConvertUnitTransformation::new("bytes", "MiB").unwrap();
+        assert!(step.apply(&metrics).is_err());
+        let step = step.with_assume_unit(true);
+        let result = step.apply(&metrics).unwrap();
+        let values: Vec<i64> = result.iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![3, 2, 5]);
+        assert!(result.iter().all(|m| m.unit.as_deref() == Some("MiB")));
+
+        let floats = vec![FloatMetric::new(Some(1_572_864.0), 0, None).with_unit("bytes")];
+        assert_eq!(step.apply_float(&floats).unwrap()[0].value, 1.5);
+    }
+
+    #[test]
+    fn test_convert_unit_rejects_other_units() {
+        let step = ConvertUnitTransformation::new("ms", "s").unwrap();
+        let error = step.apply(&[Metric::new(1, 0, None).with_unit("bytes")]).unwrap_err();
+        assert!(matches!(error, MetricQueryError::OperationFailed { .. }));
+        assert!(ConvertUnitTransformation::new("ms", "MiB").is_err());
+    }
+
+    #[test]
+    fn test_aggregation_keeps_unit_and_refuses_mixed_units() {
+        let sum = AggregationTransformation::new(Box::new(SumAggregation::default()));
+        let same = vec![Metric::new(1, 0, None).with_unit("ms"), Metric::new(2, 1, None)];
+        assert_eq!(sum.apply(&same).unwrap()[0].unit.as_deref(), Some("ms"));
+
+        let mixed = vec![Metric::new(1, 0, None).with_unit("ms"), Metric::new(2, 1, None).with_unit("s")];
+        let error = sum.apply(&mixed).unwrap_err().to_string();
+        assert!(error.contains("convert_unit('s', 'ms')"), "{}", error);
+
+        let unrelated = vec![Metric::new(1, 0, None).with_unit("ms"), Metric::new(2, 1, None).with_unit("bytes")];
+        assert!(sum.apply(&unrelated).unwrap_err().to_string().contains("different quantities"));
+    }
+
+    #[test]
+    fn test_unitless_metrics_warn() {
+        let metrics = vec![
+            Metric::new(1, 0, Some("a".to_string())).with_unit("ms"),
+            Metric::new(2, 1, Some("a".to_string())),
+            Metric::new(3, 2, Some("b".to_string())),
+        ];
+        let codes = |step: Box<dyn TransformationStrategy>, metrics: Vec<Metric>| {
+            let mut pipeline = MetricPipeline::new(metrics);
+            pipeline.add_strategy(step);
+            let (_, warnings) = pipeline.run_with_warnings().unwrap();
+            warnings.iter().map(|warning| warning.code).collect::<Vec<_>>()
+        };
+        let sum = || Box::new(AggregationTransformation::new(Box::new(SumAggregation::default())));
+        let hourly = || Box::new(TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(SumAggregation::default())));
+
+        assert_eq!(codes(sum(), metrics.clone()), vec!["unitless_metrics"]);
+        assert_eq!(codes(hourly(), metrics.clone()), vec!["unitless_metrics"]);
+        // Series are grouped separately, so a unitless series beside one with a unit is fine
+        assert!(codes(hourly(), vec![metrics[0].clone(), metrics[2].clone()]).is_empty());
+    }
+
+    #[test]
+    fn test_mixed_units_group_after_conversion() {
+        let metrics = vec![
+            Metric::new(500, 0, None).with_unit("ms"),
+            Metric::new(2, 60, None).with_unit("s"),
+        ];
+        let mut pipeline = MetricPipeline::new(metrics.clone());
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        assert!(pipeline.run().is_err());
+
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_strategy(Box::new(ConvertUnitTransformation::new("s", "ms").unwrap()));
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        let result = pipeline.run().unwrap();
+        assert_eq!(result[0].value, 2500);
+        assert_eq!(result[0].unit.as_deref(), Some("ms"));
+    }
+}
+
+#[cfg(test)]
+mod test_timezone_shift {
+    use crate::models::{FloatMetric, Metric};
+    use crate::steps::{TimezoneDirection, TimezoneShiftTransformation};
+    use crate::time_range::parse_timezone;
+    use crate::transformations::TransformationStrategy;
+
+    // 2024-01-15 12:00:00 UTC and 2024-07-15 12:00:00 UTC
+    const WINTER: i64 = 1_705_320_000;
+    const SUMMER: i64 = 1_721_044_800;
+
+    fn shift(tz: &str, direction: TimezoneDirection, timestamps: &[i64]) -> Vec<i64> {
+        let step = TimezoneShiftTransformation::new(parse_timezone(tz).unwrap(), direction);
+        let metrics: Vec<Metric> = timestamps.iter().map(|&ts| Metric::new(1, ts, None)).collect();
+        step.apply(&metrics).unwrap().iter().map(|m| m.timestamp).collect()
+    }
+
+    #[test]
+    fn test_to_local_follows_dst() {
+        let shifted = shift("Europe/Berlin", TimezoneDirection::ToLocal, &[WINTER, SUMMER]);
+        assert_eq!(shifted, vec![WINTER + 3600, SUMMER + 7200]);
+        assert_eq!(shift("UTC", TimezoneDirection::ToLocal, &[WINTER]), vec![WINTER]);
+    }
+
+    #[test]
+    fn test_from_local_round_trips() {
+        let local = shift("America/New_York", TimezoneDirection::ToLocal, &[WINTER, SUMMER]);
+        assert_eq!(shift("America/New_York", TimezoneDirection::FromLocal, &local), vec![WINTER, SUMMER]);
+    }
+
+    #[test]
+    fn test_from_local_handles_dst_edges() {
+        // 2024-03-31 02:30 does not exist in Berlin; the pre-change offset (+1h) applies
+        let skipped = 1_711_852_200;
+        assert_eq!(shift("Europe/Berlin", TimezoneDirection::FromLocal, &[skipped]), vec![skipped - 3600]);
+        // 2024-10-27 02:30 happens twice in Berlin; the earlier (+2h) instant is used
+        let repeated = 1_729_996_200;
+        assert_eq!(shift("Europe/Berlin", TimezoneDirection::FromLocal, &[repeated]), vec![repeated - 7200]);
+    }
+
+    #[test]
+    fn test_float_path_and_parsing() {
+        let step = TimezoneShiftTransformation::new(parse_timezone("Asia/Tokyo").unwrap(), TimezoneDirection::ToLocal);
+        let result = step.apply_float(&[FloatMetric::new(Some(1.5), WINTER, None)]).unwrap();
+        assert_eq!(result[0].timestamp, WINTER + 9 * 3600);
+        assert_eq!(result[0].value, 1.5);
+        assert!(TimezoneDirection::parse("sideways").is_err());
+        assert!(parse_timezone("Mars/Olympus").is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_latest {
+    use crate::models::{FloatMetric, Metric};
+    use crate::steps::LatestTransformation;
+    use crate::transformations::TransformationStrategy;
+
+    fn labeled(value: i64, timestamp: i64, label: Option<&str>) -> Metric {
+        Metric::new(value, timestamp, label.map(str::to_string))
+    }
+
+    #[test]
+    fn test_keeps_most_recent_per_label() {
+        let metrics = vec![
+            labeled(1, 30, Some("cpu")),
+            labeled(2, 10, Some("mem")),
+            labeled(3, 10, Some("cpu")),
+            labeled(4, 20, None),
+            labeled(5, 40, Some("mem")),
+        ];
+        let result = LatestTransformation::new().apply(&metrics).unwrap();
+        let kept: Vec<(i64, i64)> = result.iter().map(|m| (m.value, m.timestamp)).collect();
+        assert_eq!(kept, vec![(1, 30), (4, 20), (5, 40)]);
+    }
+
+    #[test]
+    fn test_ties_keep_last_and_empty_is_empty() {
+        let metrics = vec![labeled(1, 10, Some("cpu")), labeled(2, 10, Some("cpu"))];
+        assert_eq!(LatestTransformation::new().apply(&metrics).unwrap()[0].value, 2);
+        assert!(LatestTransformation::new().apply(&[]).unwrap().is_empty());
+
+        let floats = vec![FloatMetric::new(Some(1.5), 10, None), FloatMetric::new(None, 5, None)];
+        let result = LatestTransformation::new().apply_float(&floats).unwrap();
+        assert_eq!(result, vec![FloatMetric::new(Some(1.5), 10, None)]);
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_latest_spec_step() {
+        let spec = crate::spec::PipelineSpec::from_json(r#"{"steps": [{"op": "latest"}]}"#).unwrap();
+        let result = spec.build(vec![labeled(1, 0, None), labeled(2, 5, None)]).unwrap().run().unwrap();
+        assert_eq!(result.len(), 1);
+        assert_eq!(result[0].value, 2);
+    }
+}
+
+#[cfg(test)]
+mod test_resample {
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{AvgAggregation, SumAggregation};
+    use crate::steps::{ResampleFill, ResampleTransformation};
+    use crate::transformations::TransformationStrategy;
+
+    fn resample(fill: ResampleFill) -> ResampleTransformation {
+        ResampleTransformation::new(60, Box::new(AvgAggregation::default()), fill)
+    }
+
+    fn irregular() -> Vec<FloatMetric> {
+        vec![
+            FloatMetric::new(Some(1.0), 5, None),
+            FloatMetric::new(Some(3.0), 50, None),
+            FloatMetric::new(Some(8.0), 190, None),
+        ]
+    }
+
+    fn points(metrics: &[FloatMetric]) -> Vec<(i64, f64)> {
+        metrics.iter().map(|m| (m.timestamp, m.value)).collect()
+    }
+
+    #[test]
+    fn test_one_point_per_step_with_previous_fill() {
+        let result = resample(ResampleFill::Previous).apply_float(&irregular()).unwrap();
+        assert_eq!(points(&result), vec![(0, 2.0), (60, 2.0), (120, 2.0), (180, 8.0)]);
+    }
+
+    #[test]
+    fn test_linear_and_null_fill() {
+        let linear = resample(ResampleFill::Linear).apply_float(&irregular()).unwrap();
+        assert_eq!(points(&linear), vec![(0, 2.0), (60, 4.0), (120, 6.0), (180, 8.0)]);
+
+        let null = resample(ResampleFill::Null).apply_float(&irregular()).unwrap();
+        assert_eq!(null.len(), 4);
+        assert!(null[1].is_missing() && null[2].is_missing());
+
+        // Integer output has no missing values, so null steps become staleness markers
+        let metrics: Vec<Metric> = irregular().iter().map(FloatMetric::to_metric).collect();
+        let null = resample(ResampleFill::Null).apply(&metrics).unwrap();
+        let stale: Vec<(i64, bool)> = null.iter().map(|m| (m.timestamp, m.stale)).collect();
+        assert_eq!(stale, vec![(0, false), (60, true), (120, true), (180, false)]);
+    }
+
+    #[test]
+    fn test_labels_are_resampled_separately() {
+        let metrics = vec![
+            Metric::new(1, 0, Some("a".to_string())),
+            Metric::new(5, -30, Some("b".to_string())),
+            Metric::new(2, 10, Some("a".to_string())),
+            Metric::new(4, 130, Some("a".to_string())),
+        ];
+        let step = ResampleTransformation::new(60, Box::new(SumAggregation::default()), ResampleFill::Previous);
+        let result = step.apply(&metrics).unwrap();
+        let kept: Vec<(Option<&str>, i64, i64)> =
+            result.iter().map(|m| (m.label.as_deref(), m.timestamp, m.value)).collect();
+        assert_eq!(kept, vec![(Some("a"), 0, 3), (Some("a"), 60, 3), (Some("a"), 120, 4), (Some("b"), -60, 5)]);
+    }
+
+    #[test]
+    fn test_tag_sets_are_resampled_separately() {
+        let metrics = vec![
+            Metric::new(1, 0, None).with_tag("pod", "a"),
+            Metric::new(5, 10, None).with_tag("pod", "b"),
+            Metric::new(4, 70, None).with_tag("pod", "a"),
+        ];
+        let step = ResampleTransformation::new(60, Box::new(SumAggregation::default()), ResampleFill::Previous);
+        let result = step.apply(&metrics).unwrap();
+        let kept: Vec<(&str, i64, i64)> = result.iter().map(|m| (m.tags["pod"].as_str(), m.timestamp, m.value)).collect();
+        assert_eq!(kept, vec![("a", 0, 1), ("a", 60, 4), ("b", 0, 5)]);
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        let step = ResampleTransformation::new(0, Box::new(SumAggregation::default()), ResampleFill::Previous);
+        assert!(step.apply(&[Metric::new(1, 0, None)]).is_err());
+        assert!(ResampleFill::parse("nearest").is_err());
+
+        let sparse = vec![Metric::new(1, 0, None), Metric::new(1, i64::MAX / 2, None)];
+        assert!(resample(ResampleFill::Previous).apply(&sparse).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_closure_adapters {
+    use crate::models::Metric;
+    use crate::plugin_impls::{FnAggregation, FnFilter, FnTimeGrouping};
+    use crate::plugins::AggregationPlugin;
+    use crate::transformations::MetricPipeline;
+
+    #[test]
+    fn test_closures_run_as_plugins() {
+        let metrics = (0..10).map(|i| Metric::new(i, i * 10, None)).collect();
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(FnFilter::new("even", |m| m.value % 2 == 0)));
+        pipeline.add_time_grouping(
+            Box::new(FnTimeGrouping::new("half_minute", |ts| ts - ts.rem_euclid(30))),
+            Box::new(FnAggregation::new("count", |ms| ms.len() as i64)),
+        );
+
+        let mut result: Vec<(i64, i64)> = pipeline.run().unwrap().iter().map(|m| (m.timestamp, m.value)).collect();
+        result.sort_unstable();
+        assert_eq!(result, vec![(0, 2), (30, 1), (60, 2)]);
+    }
+
+    #[test]
+    fn test_adapters_clone_and_guard_empty_groups() {
+        let threshold = 5;
+        let filter = FnFilter::new("above", move |m| m.value > threshold);
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(3, 0, None), Metric::new(7, 1, None)]);
+        pipeline.add_filter(Box::new(filter));
+        assert_eq!(pipeline.clone_pipeline().run().unwrap().len(), 1);
+
+        let sum = FnAggregation::new("sum", |ms| ms.iter().map(|m| m.value).sum());
+        assert_eq!(sum.apply_values(&[1, 2, 3]).unwrap(), 6);
+        assert!(sum.apply(&[]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_batch_filters {
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{
+        create_filter, EqualFilter, FnFilter, GreaterThanFilter, GreaterThanOrEqualFilter, LessThanFilter,
+        LessThanOrEqualFilter, Threshold,
+    };
+    use crate::plugins::FilterPlugin;
+    use crate::transformations::{FilterTransformation, TransformationStrategy};
+
+    /// Only implements the batch path, so the test fails if the per-metric path is used
+    #[derive(Clone)]
+    struct BatchOnly;
+
+    impl FilterPlugin for BatchOnly {
+        fn name(&self) -> &str {
+            "batch_only"
+        }
+
+        fn apply(&self, _metric: &Metric) -> bool {
+            panic!("per-metric path used")
+        }
+
+        fn apply_batch(&self, metrics: &[Metric], out: &mut Vec<bool>) {
+            out.clear();
+            out.extend((0..metrics.len()).map(|index| index % 2 == 0));
+        }
+    }
+
+    #[test]
+    fn test_filter_transformation_uses_batch() {
+        let metrics: Vec<Metric> = (10..15).map(|i| Metric::new(i, i, None)).collect();
+        let result = FilterTransformation::new(Box::new(BatchOnly)).apply(&metrics).unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![10, 12, 14]);
+    }
+
+    #[test]
+    fn test_builtin_batches_match_per_metric() {
+        let metrics: Vec<Metric> = (-3..4).map(|i| Metric::new(i, 0, None)).collect();
+        let filters: Vec<Box<dyn FilterPlugin>> = vec![
+            Box::new(GreaterThanFilter::new(0)),
+            Box::new(LessThanFilter::new(0)),
+            Box::new(GreaterThanOrEqualFilter::new(0)),
+            Box::new(LessThanOrEqualFilter::new(0)),
+            Box::new(EqualFilter::new(0)),
+            create_filter("gt", 2).unwrap(),
+        ];
+        // Stale contents must be replaced, not appended to
+        let mut out = vec![true; 20];
+        for filter in filters {
+            filter.apply_batch(&metrics, &mut out);
+            let expected: Vec<bool> = metrics.iter().map(|m| filter.apply(m)).collect();
+            assert_eq!(out, expected, "{}", filter.name());
+        }
+    }
+
+    #[test]
+    fn test_thresholds_compare_without_rounding() {
+        let above = GreaterThanFilter::from_threshold(Threshold::Float(2.5));
+        let ints: Vec<Metric> = (0..5).map(|i| Metric::new(i, 0, None)).collect();
+        let kept: Vec<i64> = ints.iter().filter(|m| above.apply(m)).map(|m| m.value).collect();
+        assert_eq!(kept, vec![3, 4]);
+        assert!(above.apply_float(&FloatMetric::new(Some(2.6), 0, None)));
+        assert!(!above.apply_float(&FloatMetric::new(Some(2.5), 0, None)));
+
+        let equal = EqualFilter::from_threshold(Threshold::Float(2.5));
+        assert!(ints.iter().all(|m| !equal.apply(m)));
+
+        // 0.4 used to round to 0 and fail `> 0`
+        assert!(GreaterThanFilter::new(0).apply_float(&FloatMetric::new(Some(0.4), 0, None)));
+
+        let huge = LessThanFilter::from_threshold(Threshold::Float(1e300));
+        assert!(huge.apply(&Metric::new(i64::MAX, 0, None)));
+        let nan = GreaterThanOrEqualFilter::from_threshold(Threshold::Float(f64::NAN));
+        assert!(!nan.apply(&Metric::new(0, 0, None)));
+        assert!(!LessThanOrEqualFilter::from_threshold(Threshold::Float(f64::NAN)).apply(&Metric::new(0, 0, None)));
+    }
+
+    #[test]
+    fn test_value_filters_refuse_to_round_floats() {
+        let filter = FnFilter::new("positive", |m: &Metric| m.value > 0);
+        let metrics = vec![FloatMetric::new(Some(0.4), 0, None)];
+        assert!(FilterTransformation::new(Box::new(filter)).apply_float(&metrics).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_metric_aware_grouping {
+    use crate::errors::MetricQueryResult;
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{HourGrouping, SumAggregation};
+    use crate::plugins::{GroupPoint, TimeGroupingPlugin};
+    use crate::transformations::{TimeGroupingTransformation, TransformationStrategy};
+
+    /// Hourly buckets, except tenant "shifted" whose hours start at half past
+    #[derive(Clone)]
+    struct TenantHours;
+
+    impl TenantHours {
+        fn bucket(timestamp: i64, label: Option<&str>) -> i64 {
+            let offset = if label == Some("shifted") { 1800 } else { 0 };
+            timestamp - (timestamp - offset).rem_euclid(3600)
+        }
+    }
+
+    impl TimeGroupingPlugin for TenantHours {
+        fn name(&self) -> &str {
+            "tenant_hours"
+        }
+
+        fn get_group_timestamp(&self, timestamp: i64) -> MetricQueryResult<i64> {
+            Ok(Self::bucket(timestamp, None))
+        }
+
+        fn get_point_group_timestamp(&self, point: &GroupPoint<'_>) -> MetricQueryResult<i64> {
+            Ok(Self::bucket(point.timestamp, point.label))
+        }
+    }
+
+    fn grouped(result: &[(Option<String>, i64, f64)]) -> Vec<(Option<String>, i64, f64)> {
+        let mut result = result.to_vec();
+        result.sort_by(|a, b| (&a.0, a.1).cmp(&(&b.0, b.1)));
+        result
+    }
+
+    #[test]
+    fn test_grouping_sees_label() {
+        let label = |l: &str| Some(l.to_string());
+        let metrics = vec![
+            Metric::new(1, 1000, label("plain")),
+            Metric::new(2, 2000, label("plain")),
+            Metric::new(3, 1000, label("shifted")),
+            Metric::new(4, 2000, label("shifted")),
+        ];
+        let step = TimeGroupingTransformation::new(Box::new(TenantHours), Box::new(SumAggregation::default()));
+
+        let ints: Vec<_> =
+            step.apply(&metrics).unwrap().into_iter().map(|m| (m.label, m.timestamp, m.value as f64)).collect();
+        let expected = vec![(label("plain"), 0, 3.0), (label("shifted"), -1800, 3.0), (label("shifted"), 1800, 4.0)];
+        assert_eq!(grouped(&ints), expected);
+
+        let floats: Vec<FloatMetric> = metrics.iter().map(FloatMetric::from).collect();
+        let floats: Vec<_> =
+            step.apply_float(&floats).unwrap().into_iter().map(|m| (m.label, m.timestamp, m.value)).collect();
+        assert_eq!(grouped(&floats), expected);
+    }
+
+    #[test]
+    fn test_timestamp_only_plugins_use_the_default() {
+        let metric = Metric::new(1, 7_250, Some("any".to_string()));
+        assert_eq!(HourGrouping.get_point_group_timestamp(&GroupPoint::from(&metric)).unwrap(), 7_200);
+        assert_eq!(HourGrouping.get_point_group_timestamp(&GroupPoint::from(&FloatMetric::from(&metric))).unwrap(), 7_200);
+    }
+
+    /// Groups by the sign of the value, and fails if anything bypasses the point hook
+    #[derive(Clone)]
+    struct SignGroups;
+
+    impl TimeGroupingPlugin for SignGroups {
+        fn name(&self) -> &str {
+            "sign_groups"
+        }
+
+        fn get_group_timestamp(&self, _timestamp: i64) -> MetricQueryResult<i64> {
+            panic!("point hook bypassed")
+        }
+
+        fn get_point_group_timestamp(&self, point: &GroupPoint<'_>) -> MetricQueryResult<i64> {
+            Ok(if point.value.is_some_and(|value| value < 0.0) { -1 } else { 1 })
+        }
+    }
+
+    #[test]
+    fn test_every_path_uses_the_point_hook() {
+        let step = TimeGroupingTransformation::new(Box::new(SignGroups), Box::new(SumAggregation::default()));
+        let metrics: Vec<Metric> = [-3, 4, -5, 6].iter().enumerate().map(|(i, &v)| Metric::new(v, i as i64, None)).collect();
+
+        let mut ints: Vec<(i64, i64)> = step.apply(&metrics).unwrap().iter().map(|m| (m.timestamp, m.value)).collect();
+        ints.sort();
+        assert_eq!(ints, vec![(-1, -8), (1, 10)]);
+
+        let floats: Vec<FloatMetric> = metrics.iter().map(FloatMetric::from).collect();
+        let mut floats: Vec<(i64, f64)> =
+            step.apply_float(&floats).unwrap().iter().map(|m| (m.timestamp, m.value)).collect();
+        floats.sort_by_key(|&(timestamp, _)| timestamp);
+        assert_eq!(floats, vec![(-1, -8.0), (1, 10.0)]);
+
+        let values: Vec<i64> = metrics.iter().map(|m| m.value).collect();
+        let timestamps: Vec<i64> = metrics.iter().map(|m| m.timestamp).collect();
+        let (values, timestamps) = step.apply_columns(&values, &timestamps).unwrap();
+        let mut columns: Vec<(i64, i64)> = timestamps.into_iter().zip(values).collect();
+        columns.sort();
+        assert_eq!(columns, vec![(-1, -8), (1, 10)]);
+    }
+}
+
+#[cfg(test)]
+mod test_compiled_pipeline {
+    use crate::compiled::CompiledPipeline;
+    use crate::models::Metric;
+    use crate::plugin_impls::{GreaterThanFilter, SumAggregation};
+    use crate::transformations::MetricPipeline;
+
+    fn metrics() -> Vec<Metric> {
+        (1..=10).map(|i| Metric::new(i, i * 60, None)).collect()
+    }
+
+    #[test]
+    fn test_snapshot_ignores_later_steps() {
+        let mut pipeline = MetricPipeline::new(metrics());
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(5)));
+        let compiled = CompiledPipeline::new(&pipeline);
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
+
+        assert_eq!(compiled.run().unwrap().len(), 5);
+        assert_eq!(pipeline.run().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_threads_share_one_compiled_pipeline() {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(5)));
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
+        let compiled = CompiledPipeline::new(&pipeline);
+
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..8)
+                .map(|offset| {
+                    let compiled = &compiled;
+                    scope.spawn(move || {
+                        let input: Vec<Metric> =
+                            metrics().into_iter().map(|m| Metric::new(m.value + offset, m.timestamp, None)).collect();
+                        compiled.run_metrics_with_warnings(&input).unwrap().0[0].value
+                    })
+                })
+                .collect();
+            for (offset, worker) in workers.into_iter().enumerate() {
+                // Values above 5 after shifting every input by `offset`
+                let expected: i64 = (1..=10).map(|i| i + offset as i64).filter(|&v| v > 5).sum();
+                assert_eq!(worker.join().unwrap(), expected);
+            }
+        });
+        assert_eq!(compiled.last_run_stats().unwrap().output_count, 1);
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_compiled_pipeline_python {
+    use crate::models::Metric;
+    use crate::plugin_impls::init_registry;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_worker_pool_executes_concurrently() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let parity = |i: i64| Some(if i % 2 == 0 { "even" } else { "odd" }.to_string());
+            let metrics: Vec<Metric> = (1..=10).map(|i| Metric::new(i, i * 60, parity(i))).collect();
+            let globals = PyDict::new(py);
+            globals.set_item("pipeline", Py::new(py, MetricPipeline::new(metrics)).unwrap()).unwrap();
+            py.run(
+                c"
+from concurrent.futures import ThreadPoolExecutor
+pipeline.filter_by_label('label_eq', 'even')
+compiled = pipeline.compile()
+pipeline.latest()
+with ThreadPoolExecutor(4) as pool:
+    results = list(pool.map(lambda _: [m.value for m in compiled.execute()], range(16)))
+first = compiled.execute(compiled.execute()[:2])
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+
+            let results: Vec<Vec<i64>> = globals.get_item("results").unwrap().unwrap().extract().unwrap();
+            assert_eq!(results.len(), 16);
+            assert!(results.iter().all(|values| values == &vec![2, 4, 6, 8, 10]));
+            let first: Vec<Metric> = globals.get_item("first").unwrap().unwrap().extract().unwrap();
+            assert_eq!(first.iter().map(|m| m.value).collect::<Vec<_>>(), vec![2, 4]);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_shared_input {
+    use std::sync::Arc;
+
+    use crate::models::{Metric, MetricDataset};
+    use crate::plugin_impls::{GreaterThanFilter, SumAggregation};
+
+    fn dataset() -> MetricDataset {
+        MetricDataset::new((1..=10).map(|i| Metric::new(i, i * 60, None)).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_pipelines_share_one_input() {
+        let dataset = dataset();
+        let mut total = dataset.pipeline();
+        total.add_aggregation(Box::new(SumAggregation::default()));
+        let mut large = dataset.pipeline();
+        large.add_filter(Box::new(GreaterThanFilter::new(8)));
+
+        assert!(Arc::ptr_eq(&total.shared_metrics(), &dataset.shared()));
+        assert!(Arc::ptr_eq(&large.shared_metrics(), &dataset.shared()));
+        assert_eq!(total.run().unwrap()[0].value, 55);
+        assert_eq!(large.run().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_adding_metrics_leaves_other_pipelines_alone() {
+        let dataset = dataset();
+        let mut grown = dataset.pipeline();
+        grown.add_metrics(vec![Metric::new(11, 660, None)]);
+        let untouched = dataset.pipeline();
+
+        assert_eq!(grown.run().unwrap().len(), 11);
+        assert_eq!(untouched.run().unwrap().len(), 10);
+        assert_eq!(dataset.len(), 10);
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_shared_input_python {
+    use crate::models::{Metric, MetricDataset};
+    use crate::plugin_impls::init_registry;
+    use crate::python::create_pipeline;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+
+    #[test]
+    fn test_pipelines_accept_datasets_and_lists() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let metrics: Vec<Metric> = (1..=4).map(|i| Metric::new(i, i * 60, None)).collect();
+            let dataset = Py::new(py, MetricDataset::new(metrics.clone())).unwrap();
+
+            let shared = create_pipeline(dataset.bind(py).extract().unwrap());
+            assert!(std::sync::Arc::ptr_eq(&shared.shared_metrics(), &dataset.get().shared()));
+            let copied = create_pipeline(metrics.into_pyobject(py).unwrap().extract().unwrap());
+            assert_eq!(copied.run().unwrap().len(), 4);
+
+            let class = py.get_type::<MetricPipeline>();
+            let pipeline = class.call1((dataset.bind(py),)).unwrap();
+            assert_eq!(pipeline.call_method0("execute").unwrap().len().unwrap(), 4);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_validate_metrics {
+    use crate::models::Metric;
+    use crate::validation::{validate_metrics, StreamRules};
+
+    fn metric(value: i64, timestamp: i64, label: &str) -> Metric {
+        Metric::new(value, timestamp, Some(label.to_string()))
+    }
+
+    fn kinds(metrics: &[Metric], rules: &StreamRules) -> Vec<(usize, String)> {
+        validate_metrics(metrics, rules).issues.into_iter().map(|issue| (issue.index, issue.kind)).collect()
+    }
+
+    #[test]
+    fn test_clean_stream_is_valid() {
+        let metrics: Vec<Metric> = (0..10).map(|i| metric(i, i * 60, "cpu")).collect();
+        let report = validate_metrics(&metrics, &StreamRules { counter_resets: true, ..StreamRules::default() });
+        assert!(report.is_valid());
+        assert_eq!((report.checked, report.series), (10, 1));
+    }
+
+    #[test]
+    fn test_ordering_duplicates_and_resets_per_series() {
+        let metrics = vec![
+            metric(5, 60, "requests"),
+            metric(1, 60, "latency"),
+            metric(9, 120, "requests"),
+            metric(2, 0, "latency"),
+            metric(3, 120, "requests"),
+            metric(7, 180, "requests"),
+        ];
+        let rules = StreamRules { counter_resets: true, ..StreamRules::default() };
+        // In time order "latency" goes from 2 to 1, so it also looks like a reset
+        assert_eq!(
+            kinds(&metrics, &rules),
+            vec![(1, "counter_reset".to_string()), (3, "unsorted".to_string()), (4, "duplicate".to_string())]
+        );
+
+        let metrics = vec![metric(5, 0, "c"), metric(9, 60, "c"), metric(2, 120, "c"), metric(4, 180, "c")];
+        assert_eq!(kinds(&metrics, &rules), vec![(2, "counter_reset".to_string())]);
+        assert!(validate_metrics(&metrics, &StreamRules::default()).is_valid());
+    }
+
+    #[test]
+    fn test_gaps_relative_to_median_or_fixed() {
+        let timestamps = [0, 60, 120, 180, 900, 960];
+        let metrics: Vec<Metric> = timestamps.iter().map(|&t| metric(1, t, "cpu")).collect();
+        let report = validate_metrics(&metrics, &StreamRules::default());
+        assert_eq!(report.count("gap"), 1);
+        assert_eq!(report.issues[0].index, 4);
+        assert_eq!(report.issues[0].detail, "720s since the previous metric");
+
+        let fixed = StreamRules { max_gap_seconds: Some(30), ..StreamRules::default() };
+        assert_eq!(validate_metrics(&metrics, &fixed).count("gap"), 5);
+    }
+
+    #[test]
+    fn test_label_anomalies() {
+        let metrics = vec![metric(1, 60, "cpu"), Metric::new(1, 60, None), metric(1, 60, "bad label"), metric(1, 60, "")];
+        assert_eq!(
+            kinds(&metrics, &StreamRules::default()),
+            vec![(1, "label".to_string()), (2, "label".to_string()), (3, "label".to_string())]
+        );
+    }
+}
+
+#[cfg(test)]
+mod test_pipeline_graph {
+    use crate::graph::NodeKind;
+    use crate::models::Metric;
+    use crate::plugin_impls::{GreaterThanFilter, SumAggregation};
+    use crate::transformations::MetricPipeline;
+
+    fn pipeline() -> MetricPipeline {
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 60, None), Metric::new(2, 120, None)]);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(1)));
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
+        pipeline
+    }
+
+    #[test]
+    fn test_graph_chains_input_steps_and_output() {
+        let graph = pipeline().to_graph();
+        let kinds: Vec<NodeKind> = graph.nodes.iter().map(|node| node.kind).collect();
+        assert_eq!(kinds, vec![NodeKind::Input, NodeKind::Step, NodeKind::Step, NodeKind::Output]);
+        assert_eq!(graph.nodes[0].label, "input (2 metrics)");
+        let edges: Vec<(&str, &str)> = graph.edges.iter().map(|e| (e.from.as_str(), e.to.as_str())).collect();
+        assert_eq!(edges, vec![("input", "step0"), ("step0", "step1"), ("step1", "output")]);
+    }
+
+    #[test]
+    fn test_dot_output() {
+        let dot = pipeline().to_dot();
+        assert!(dot.starts_with("digraph pipeline {\n    rankdir=LR;\n"));
+        assert!(dot.contains("    step0 [label=\"gt\", shape=box];\n"));
+        assert!(dot.contains("    step1 -> output;\n"));
+        assert!(dot.ends_with("}\n"));
+
+        let empty = MetricPipeline::new(Vec::new()).to_dot();
+        assert!(empty.contains("    input -> output;\n"));
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_json_graph() {
+        let json: serde_json::Value = serde_json::from_str(&pipeline().to_graph().to_json()).unwrap();
+        assert_eq!(json["nodes"][1]["kind"], "step");
+        assert_eq!(json["edges"][0]["from"], "input");
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_pipeline_graph_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_graph_dict_is_json_ready() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("pipeline", Py::new(py, MetricPipeline::new(vec![Metric::new(1, 60, None)])).unwrap()).unwrap();
+            py.run(
+                c"
+import json
+pipeline.latest()
+graph = json.loads(json.dumps(pipeline.to_graph()))
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            let graph = globals.get_item("graph").unwrap().unwrap();
+            let kinds: Vec<String> = py
+                .eval(c"[node['kind'] for node in graph['nodes']]", Some(&globals), None)
+                .unwrap()
+                .extract()
+                .unwrap();
+            assert_eq!(kinds, vec!["input", "step", "output"]);
+            assert_eq!(graph.get_item("edges").unwrap().len().unwrap(), 2);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_result_cache {
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::cache::{fingerprint, ResultCache};
+    use crate::compiled::CompiledPipeline;
+    use crate::models::{Exemplar, Fields, Metric, MetricType};
+    use crate::plugin_impls::{DayGrouping, SumAggregation};
+    use crate::settings::Settings;
+    use crate::steps::TapTransformation;
+    use crate::time_range::parse_timezone;
+    use crate::transformations::MetricPipeline;
+
+    fn metrics(n: i64) -> Vec<Metric> {
+        (1..=n).map(|i| Metric::new(i, i * 60, None)).collect()
+    }
+
+    /// A summing pipeline that counts how often its steps actually run
+    fn counting_pipeline() -> (CompiledPipeline, Arc<AtomicUsize>) {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&runs);
+        let mut pipeline = MetricPipeline::new(metrics(4));
+        pipeline.add_strategy(Box::new(TapTransformation::new(move |_| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        })));
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
+        (CompiledPipeline::new(&pipeline), runs)
+    }
+
+    #[test]
+    fn test_repeated_queries_are_memoized() {
+        let (compiled, runs) = counting_pipeline();
+        let cache = ResultCache::new(Duration::from_secs(60), 8);
+
+        for _ in 0..3 {
+            assert_eq!(compiled.run_cached(None, &cache).unwrap().0[0].value, 10);
+        }
+        let other = metrics(3);
+        assert_eq!(compiled.run_cached(Some(&other), &cache).unwrap().0[0].value, 6);
+        assert_eq!(compiled.run_cached(Some(&other), &cache).unwrap().0[0].value, 6);
+
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (3, 2, 2));
+    }
+
+    #[test]
+    fn test_entries_expire_and_are_bounded() {
+        let (compiled, runs) = counting_pipeline();
+        let expired = ResultCache::new(Duration::ZERO, 8);
+        compiled.run_cached(None, &expired).unwrap();
+        compiled.run_cached(None, &expired).unwrap();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+
+        let bounded = ResultCache::new(Duration::from_secs(60), 2);
+        for n in 1..=3 {
+            compiled.run_cached(Some(&metrics(n)), &bounded).unwrap();
+        }
+        assert_eq!(bounded.len(), 2);
+    }
+
+    #[test]
+    fn test_keys_separate_pipelines_and_inputs() {
+        let (first, _) = counting_pipeline();
+        let (second, _) = counting_pipeline();
+        assert_ne!(first.id(), second.id());
+        assert_eq!(first.clone().id(), first.id());
+
+        let mut labeled = metrics(2);
+        assert_eq!(fingerprint(&labeled), fingerprint(&metrics(2)));
+        labeled[0].label = Some("cpu".to_string());
+        assert_ne!(fingerprint(&labeled), fingerprint(&metrics(2)));
+    }
+
+    #[test]
+    fn test_hits_need_an_equal_input() {
+        let cache = ResultCache::new(Duration::from_secs(60), 8);
+        let output = |value| move || Ok((vec![Metric::new(value, 0, None)], Vec::new()));
+        // Same key, as after a fingerprint collision, but a different input
+        assert_eq!(cache.get_or_run((0, 0), &metrics(1), output(1)).unwrap().0[0].value, 1);
+        assert_eq!(cache.get_or_run((0, 0), &metrics(2), output(2)).unwrap().0[0].value, 2);
+        assert_eq!(cache.get_or_run((0, 0), &metrics(2), output(3)).unwrap().0[0].value, 2);
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+
+    #[test]
+    fn test_compiling_freezes_the_settings() {
+        let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 1_704_067_200 + 3_600, None)]);
+        pipeline.add_time_grouping(Box::new(DayGrouping), Box::new(SumAggregation::default()));
+        // A pipeline following the global settings keeps the ones current at compile time
+        assert_eq!(CompiledPipeline::new(&pipeline).settings(), Settings::global());
+
+        let kolkata = Settings { timezone: Some(parse_timezone("Asia/Kolkata").unwrap()), ..Settings::DEFAULT };
+        pipeline.set_settings(Some(kolkata));
+        let compiled = CompiledPipeline::new(&pipeline);
+        let cache = ResultCache::new(Duration::from_secs(60), 8);
+        let first = compiled.run_cached(None, &cache).unwrap().0;
+
+        pipeline.set_settings(None);
+        assert_eq!(compiled.settings(), kolkata);
+        assert_eq!(compiled.run_cached(None, &cache).unwrap().0, first);
+        assert_ne!(CompiledPipeline::new(&pipeline).run().unwrap(), first);
+    }
+
+    #[test]
+    fn test_fingerprint_covers_every_field() {
+        // Built without `..` so a new field has to be added here, along with a change below
+        let base = Metric {
+            value: 1,
+            timestamp: 60,
+            label: None,
+            exemplar: None,
+            fields: Fields::default(),
+            unit: None,
+            metric_type: None,
+            scale: None,
+            source: None,
+            description: None,
+            stale: false,
+            tags: BTreeMap::new(),
+        };
+        let changes: Vec<fn(&mut Metric)> = vec![
+            |m| m.value = 2,
+            |m| m.timestamp = 120,
+            |m| m.label = Some("cpu".to_string()),
+            |m| m.exemplar = Some(Exemplar::new("abc".to_string(), 1.0, 60)),
+            |m| {
+                m.fields.insert("bytes_in".to_string(), 1);
+            },
+            |m| m.unit = Some("ms".to_string()),
+            |m| m.metric_type = Some(MetricType::Gauge),
+            |m| m.scale = Some(2),
+            |m| m.source = Some("host-a".to_string()),
+            |m| m.description = Some("note".to_string()),
+            |m| m.stale = true,
+            |m| {
+                m.tags.insert("pod".to_string(), "a".to_string());
+            },
+        ];
+        let unchanged = fingerprint(std::slice::from_ref(&base));
+        for (index, change) in changes.iter().enumerate() {
+            let mut changed = base.clone();
+            change(&mut changed);
+            assert_ne!(fingerprint(&[changed]), unchanged, "change {} isn't hashed", index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_rolling_reduce {
+    use crate::models::{FloatMetric, Metric};
+    use crate::steps::{RollingPercentileTransformation, RollingReduceTransformation, RollingReduction, RollingWindow};
+    use crate::time_range::parse_duration;
+    use crate::transformations::TransformationStrategy;
+
+    fn values(step: &RollingReduceTransformation, metrics: &[Metric]) -> Vec<i64> {
+        step.apply(metrics).unwrap().into_iter().map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_duration_window_sum_and_mean() {
+        let metrics: Vec<Metric> = [(1, 0), (2, 60), (3, 120), (4, 300), (5, 360)]
+            .into_iter()
+            .map(|(value, timestamp)| Metric::new(value, timestamp, None))
+            .collect();
+        // A 5m window ending at t covers (t - 300, t]
+        let sum = RollingReduceTransformation::new(RollingReduction::Sum, RollingWindow::Duration(300)).unwrap();
+        assert_eq!(values(&sum, &metrics), vec![1, 3, 6, 9, 12]);
+
+        let floats: Vec<FloatMetric> = metrics.iter().map(FloatMetric::from).collect();
+        let mean = RollingReduceTransformation::new(RollingReduction::Mean, RollingWindow::Duration(300)).unwrap();
+        let means: Vec<f64> = mean.apply_float(&floats).unwrap().into_iter().map(|m| m.value).collect();
+        assert_eq!(means, vec![1.0, 1.5, 2.0, 3.0, 4.0]);
+    }
+
+    #[test]
+    fn test_min_max_match_brute_force() {
+        // Deterministic pseudo-random walk with repeated values
+        let mut state = 7u64;
+        let metrics: Vec<Metric> = (0..500)
+            .map(|i| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                Metric::new((state >> 60) as i64, i * 10, Some(if i % 3 == 0 { "a" } else { "b" }.to_string()))
+            })
+            .collect();
+
+        for (reduction, pick) in [(RollingReduction::Min, i64::min as fn(i64, i64) -> i64), (RollingReduction::Max, i64::max)] {
+            let step = RollingReduceTransformation::new(reduction, RollingWindow::Count(7)).unwrap();
+            let result = step.apply(&metrics).unwrap();
+            for (position, metric) in result.iter().enumerate() {
+                assert_eq!(metric.timestamp, metrics[position].timestamp);
+                let series: Vec<i64> = metrics[..=position]
+                    .iter()
+                    .filter(|m| m.label == metric.label)
+                    .map(|m| m.value)
+                    .collect();
+                let expected = series[series.len().saturating_sub(7)..].iter().copied().reduce(pick).unwrap();
+                assert_eq!(metric.value, expected, "{:?} at {}", reduction, position);
+            }
+        }
+    }
+
+    #[test]
+    fn test_integers_stay_exact_past_2_pow_53() {
+        let big = (1 << 53) + 1;
+        let metrics: Vec<Metric> = (0..3).map(|i| Metric::new(big + i, i, None)).collect();
+        let window = RollingWindow::Count(2);
+        let step = |reduction| RollingReduceTransformation::new(reduction, window).unwrap();
+        assert_eq!(values(&step(RollingReduction::Sum), &metrics), vec![big, 2 * big + 1, 2 * big + 3]);
+        assert_eq!(values(&step(RollingReduction::Mean), &metrics), vec![big, big + 1, big + 2]);
+        assert_eq!(values(&step(RollingReduction::Min), &metrics), vec![big, big, big + 1]);
+
+        let median = RollingPercentileTransformation::new(0.5, RollingWindow::Count(3)).unwrap();
+        let medians: Vec<i64> = median.apply(&metrics).unwrap().into_iter().map(|m| m.value).collect();
+        assert_eq!(medians, vec![big, big + 1, big + 1]);
+
+        // Window sums past i64 fail, while the means of the same windows still fit
+        let peaks = vec![Metric::new(i64::MAX, 0, None), Metric::new(i64::MAX, 1, None), Metric::new(-5, 2, None)];
+        assert!(step(RollingReduction::Sum).apply(&peaks).is_err());
+        assert_eq!(values(&step(RollingReduction::Mean), &peaks), vec![i64::MAX, i64::MAX, i64::MAX / 2 - 2]);
+    }
+
+    #[test]
+    fn test_invalid_arguments() {
+        assert!(RollingReduction::parse("median").is_err());
+        assert!(RollingReduceTransformation::new(RollingReduction::Sum, RollingWindow::Count(0)).is_err());
+        assert_eq!(parse_duration("5m").unwrap(), 300);
+        assert_eq!(parse_duration("90").unwrap(), 90);
+        assert_eq!(parse_duration("1w").unwrap(), 604_800);
+        assert!(parse_duration("5 minutes").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_spec_step() {
+        let spec = crate::spec::PipelineSpec::from_json(r#"{"steps": [{"op": "rolling", "how": "sum", "window": "1m"}]}"#)
+            .unwrap();
+        let metrics = vec![Metric::new(1, 0, None), Metric::new(2, 30, None), Metric::new(4, 60, None)];
+        let result: Vec<i64> = spec.build(metrics).unwrap().run().unwrap().into_iter().map(|m| m.value).collect();
+        assert_eq!(result, vec![1, 3, 6]);
+        assert!(crate::spec::PipelineSpec::from_json(r#"{"steps": [{"op": "rolling", "how": "sum"}]}"#)
+            .unwrap()
+            .build(Vec::new())
+            .is_err());
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_rolling_reduce_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_window_accepts_durations_and_seconds() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let metrics: Vec<Metric> = (0..4).map(|i| Metric::new(i + 1, i * 60, None)).collect();
+            let globals = PyDict::new(py);
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            globals.set_item("metrics", metrics).unwrap();
+            py.run(
+                c"
+by_text = MetricPipeline(metrics)
+by_text.rolling('sum', window='2m')
+by_seconds = MetricPipeline(metrics)
+by_seconds.rolling('max', window=120)
+sums = [m.value for m in by_text.execute()]
+maxes = [m.value for m in by_seconds.execute()]
+try:
+    MetricPipeline(metrics).rolling('sum')
+    missing = False
+except ValueError:
+    missing = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            let get = |name: &str| globals.get_item(name).unwrap().unwrap();
+            assert_eq!(get("sums").extract::<Vec<i64>>().unwrap(), vec![1, 3, 5, 7]);
+            assert_eq!(get("maxes").extract::<Vec<i64>>().unwrap(), vec![1, 2, 3, 4]);
+            assert!(get("missing").extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_topk_series {
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{MaxAggregation, MissingValuePolicy, SumAggregation};
+    use crate::settings::Settings;
+    use crate::steps::TopKSeriesTransformation;
+    use crate::transformations::TransformationStrategy;
+
+    fn host(value: i64, timestamp: i64, host: &str) -> Metric {
+        Metric::new(value, timestamp, Some(host.to_string()))
+    }
+
+    fn metrics() -> Vec<Metric> {
+        vec![
+            host(5, 0, "a"),
+            host(1, 0, "b"),
+            host(9, 0, "c"),
+            host(5, 60, "a"),
+            host(2, 60, "b"),
+            host(1, 60, "c"),
+            host(4, 0, "d"),
+        ]
+    }
+
+    fn labels(result: &[Metric]) -> Vec<&str> {
+        result.iter().map(|m| m.label.as_deref().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_keeps_every_point_of_the_top_series() {
+        let step = TopKSeriesTransformation::new(2, Box::new(SumAggregation::default())).unwrap();
+        // Sums: a=10, b=3, c=10, d=4; the a/c tie doesn't matter with k=2
+        assert_eq!(labels(&step.apply(&metrics()).unwrap()), vec!["a", "c", "a", "c"]);
+
+        let by_peak = TopKSeriesTransformation::new(1, Box::new(MaxAggregation)).unwrap();
+        assert_eq!(labels(&by_peak.apply(&metrics()).unwrap()), vec!["c", "c"]);
+    }
+
+    #[test]
+    fn test_ties_go_to_the_first_series() {
+        let step = TopKSeriesTransformation::new(1, Box::new(SumAggregation::default())).unwrap();
+        assert_eq!(labels(&step.apply(&metrics()).unwrap()), vec!["a", "a"]);
+
+        let large_k = TopKSeriesTransformation::new(10, Box::new(SumAggregation::default())).unwrap();
+        assert_eq!(large_k.apply(&metrics()).unwrap().len(), 7);
+        assert!(TopKSeriesTransformation::new(0, Box::new(SumAggregation::default())).is_err());
+    }
+
+    #[test]
+    fn test_float_missing_values_follow_the_settings_policy() {
+        let metrics = vec![
+            FloatMetric::new(None, 0, Some("empty".to_string())),
+            FloatMetric::new(Some(-3.0), 0, Some("negative".to_string())),
+            FloatMetric::new(None, 60, Some("negative".to_string())),
+        ];
+        let step = TopKSeriesTransformation::new(1, Box::new(SumAggregation::default())).unwrap();
+        let top = |missing| {
+            let settings = Settings { missing: Some(missing), ..Settings::DEFAULT };
+            let result = settings.scope(|| step.apply_float(&metrics)).unwrap();
+            result[0].label.clone().unwrap()
+        };
+
+        // A series whose aggregate is missing ranks last
+        assert_eq!(top(MissingValuePolicy::Skip), "negative");
+        assert_eq!(top(MissingValuePolicy::Substitute(0.0)), "empty");
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_spec_step() {
+        let spec = crate::spec::PipelineSpec::from_json(r#"{"steps": [{"op": "topk_series", "k": 1}]}"#).unwrap();
+        let result = spec.build(metrics()).unwrap().run().unwrap();
+        assert_eq!(labels(&result), vec!["a", "a"]);
+    }
+}
+
+#[cfg(test)]
+mod test_settings {
+    use crate::errors::MetricQueryError;
+    use crate::models::Metric;
+    use crate::plugin_impls::{DayGrouping, HourGrouping, MinuteGrouping, OverflowPolicy, SumAggregation};
+    use crate::plugins::TimeGroupingPlugin;
+    use crate::settings::{EmptyStreamPolicy, OutputOrdering, Settings};
+    use crate::transformations::MetricPipeline;
+
+    fn summed_by_hour(metrics: Vec<Metric>, settings: Settings) -> MetricPipeline {
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        pipeline.set_settings(Some(settings));
+        pipeline
+    }
+
+    #[test]
+    fn test_empty_stream_policy() {
+        let failing = summed_by_hour(Vec::new(), Settings::DEFAULT);
+        assert!(matches!(failing.run().unwrap_err().root(), MetricQueryError::EmptyMetricStream));
+
+        let settings = Settings { empty_stream: EmptyStreamPolicy::Empty, ..Settings::DEFAULT };
+        assert!(summed_by_hour(Vec::new(), settings).run().unwrap().is_empty());
+
+        let mut aggregated = MetricPipeline::new(Vec::new());
+        aggregated.add_aggregation(Box::new(SumAggregation::default()));
+        aggregated.set_settings(Some(settings));
+        assert!(aggregated.run().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sorted_ordering() {
+        let metrics: Vec<Metric> = (0..48).rev().map(|hour| Metric::new(hour, hour * 3600, None)).collect();
+        let settings = Settings { ordering: OutputOrdering::Sorted, ..Settings::DEFAULT };
+        let result = summed_by_hour(metrics, settings).run().unwrap();
+        let timestamps: Vec<i64> = result.iter().map(|m| m.timestamp).collect();
+        assert_eq!(timestamps, (0..48).map(|hour| hour * 3600).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_sum_overflow_follows_settings() {
+        let metrics = vec![Metric::new(i64::MAX, 0, None), Metric::new(1, 60, None)];
+        assert!(summed_by_hour(metrics.clone(), Settings::DEFAULT).run().is_err());
+
+        let saturating = Settings { overflow: OverflowPolicy::Saturate, ..Settings::DEFAULT };
+        assert_eq!(summed_by_hour(metrics.clone(), saturating).run().unwrap()[0].value, i64::MAX);
+
+        // An explicit policy wins over the settings
+        let mut explicit = MetricPipeline::new(metrics);
+        explicit.add_aggregation(Box::new(SumAggregation::new(OverflowPolicy::Error)));
+        explicit.set_settings(Some(saturating));
+        assert!(explicit.run().is_err());
+    }
+
+    #[test]
+    fn test_sequential_matches_parallel() {
+        let metrics: Vec<Metric> = (0..50_000).map(|i| Metric::new(i % 7, i * 13, None)).collect();
+        let sorted = Settings { ordering: OutputOrdering::Sorted, ..Settings::DEFAULT };
+        let parallel = summed_by_hour(metrics.clone(), sorted).run().unwrap();
+        let sequential = summed_by_hour(metrics, Settings { parallel: false, ..sorted }).run().unwrap();
+        let pairs = |result: Vec<Metric>| result.into_iter().map(|m| (m.timestamp, m.value)).collect::<Vec<_>>();
+        assert_eq!(pairs(parallel), pairs(sequential));
+    }
+
+    #[test]
+    fn test_timezone_moves_bucket_boundaries() {
+        let kolkata = Settings {
+            timezone: Some(chrono_tz::Asia::Kolkata),
+            ordering: OutputOrdering::Sorted,
+            ..Settings::DEFAULT
+        };
+        // 00:00 UTC is 05:30 in Kolkata, so hours start on the half hour
+        let metrics = vec![Metric::new(1, 0, None), Metric::new(2, 1700, None), Metric::new(4, 1900, None)];
+        let hours = summed_by_hour(metrics.clone(), kolkata).run().unwrap();
+        assert_eq!(hours.iter().map(|m| (m.timestamp, m.value)).collect::<Vec<_>>(), vec![(-1800, 3), (1800, 4)]);
+
+        // Kolkata days start at 18:30 UTC
+        let mut days = MetricPipeline::new(vec![Metric::new(1, 0, None), Metric::new(2, 70_000, None)]);
+        days.add_time_grouping(Box::new(DayGrouping), Box::new(SumAggregation::default()));
+        days.set_settings(Some(kolkata));
+        let result = days.run().unwrap();
+        assert_eq!(result.iter().map(|m| (m.timestamp, m.value)).collect::<Vec<_>>(), vec![(-19_800, 1), (66_600, 2)]);
+
+        // Without the settings, the same run buckets by UTC day
+        days.set_settings(None);
+        Settings::DEFAULT.scope(|| assert_eq!(days.run().unwrap().len(), 1));
+    }
+
+    #[test]
+    fn test_timezone_moves_minute_boundaries() {
+        // Liberia kept UTC-0:44:30 until 1972, so its minutes start on the half minute
+        let monrovia = Settings { timezone: Some(chrono_tz::Africa::Monrovia), ..Settings::DEFAULT };
+        let minute = |timestamp| monrovia.scope(|| MinuteGrouping.get_group_timestamp(timestamp)).unwrap();
+        assert_eq!((minute(0), minute(29), minute(30)), (-30, -30, 30));
+        assert_eq!(Settings::DEFAULT.scope(|| MinuteGrouping.get_group_timestamp(29)).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_pipeline_settings_beat_scoped_ones() {
+        let empty_ok = Settings { empty_stream: EmptyStreamPolicy::Empty, ..Settings::DEFAULT };
+        let mut pipeline = summed_by_hour(Vec::new(), Settings::DEFAULT);
+        Settings::scope(&empty_ok, || assert!(pipeline.run().is_err()));
+
+        pipeline.set_settings(None);
+        assert_eq!(pipeline.settings(), Settings::global());
+        assert!(EmptyStreamPolicy::parse("skip").is_err());
+        assert_eq!(OutputOrdering::parse("sorted").unwrap(), OutputOrdering::Sorted);
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_settings_python {
+    use crate::models::Metric;
+    use crate::plugin_impls::{HourGrouping, SumAggregation};
+    use crate::settings::Settings;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_settings_from_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Settings", py.get_type::<Settings>()).unwrap();
+            let mut pipeline = MetricPipeline::new(Vec::<Metric>::new());
+            pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+            globals.set_item("pipeline", Py::new(py, pipeline).unwrap()).unwrap();
+            py.run(
+                c"
+settings = Settings(timezone='Asia/Kolkata', empty_stream='empty', ordering='sorted')
+assert settings.timezone == 'Asia/Kolkata'
+assert settings.overflow == 'error' and settings.parallel
+assert settings == Settings(timezone='Asia/Kolkata', empty_stream='empty', ordering='sorted')
+assert 'sorted' in repr(settings)
+assert settings.timestamp_precision == 's'
+assert Settings(timestamp_precision='ns').timestamp_precision == 'ns'
+assert settings.missing is None
+filled = Settings(missing='substitute', fill_value=0.0)
+assert filled.missing == 'substitute' and filled.fill_value == 0.0
+
+pipeline.settings = settings
+assert pipeline.settings == settings
+assert pipeline.execute() == []
+
+try:
+    Settings(ordering='random')
+    raise AssertionError('expected an error')
+except ValueError:
+    pass
+try:
+    Settings(missing='substitute')
+    raise AssertionError('expected an error')
+except ValueError:
+    pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_golden {
+    use crate::golden::{assert_pipeline_result, check_golden, read_golden, record_golden, write_golden};
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{HourGrouping, SumAggregation};
+    use crate::transformations::MetricPipeline;
+
+    fn hourly_sums() -> MetricPipeline {
+        let metrics = vec![
+            Metric::new(1, 0, Some("a".to_string())),
+            Metric::new(2, 1800, Some("a".to_string())),
+            Metric::new(5, 3600, Some("a".to_string())),
+        ];
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        pipeline
+    }
+
+    fn point(value: f64, timestamp: i64) -> FloatMetric {
+        FloatMetric::new(Some(value), timestamp, Some("a".to_string()))
+    }
+
+    #[test]
+    fn test_format_round_trips() {
+        let metrics = vec![
+            FloatMetric::new(Some(0.1 + 0.2), 60, Some("tab\there\\".to_string())),
+            FloatMetric::new(None, -5, None),
+            FloatMetric::new(Some(-1e300), 0, Some(String::new())),
+        ];
+        let mut text = Vec::new();
+        write_golden(&mut text, &metrics).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.starts_with("# metric-query golden v1\n-5\tNaN\n"));
+
+        let read = read_golden(text.as_bytes()).unwrap();
+        assert_eq!(read.len(), 3);
+        assert!(read[0].is_missing() && read[0].label.is_none());
+        assert_eq!(read[1].label.as_deref(), Some(""));
+        assert_eq!(read[2].value, 0.1 + 0.2);
+        assert_eq!(read[2].label.as_deref(), Some("tab\there\\"));
+
+        assert!(read_golden("0\t1\n".as_bytes()).is_err());
+        assert!(read_golden("# metric-query golden v1\n0\tlots\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_assert_pipeline_result_ignores_order() {
+        assert_pipeline_result(&hourly_sums(), &[point(5.0, 3600), point(3.0, 0)], 0.0);
+        assert_pipeline_result(&hourly_sums(), &[point(3.4, 0), point(5.0, 3600)], 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "a@0: expected 4, got 3")]
+    fn test_assert_pipeline_result_reports_drift() {
+        assert_pipeline_result(&hourly_sums(), &[point(4.0, 0), point(5.0, 3600)], 0.0);
+    }
+
+    #[test]
+    fn test_golden_file_detects_changes() {
+        let path = std::env::temp_dir().join(format!("metric-query-golden-{}.txt", std::process::id()));
+        assert!(check_golden(&hourly_sums(), &path, 0.0).is_err());
+
+        record_golden(&hourly_sums(), &path).unwrap();
+        assert!(check_golden(&hourly_sums(), &path, 0.0).unwrap().is_empty());
+
+        let mut changed = hourly_sums();
+        changed.add_metrics(vec![Metric::new(10, 7200, Some("a".to_string()))]);
+        let diff = check_golden(&changed, &path, 0.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(diff.unexpected.len(), 1);
+        assert!(diff.describe().contains("unexpected (1):\n  a@7200 = 10"));
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_golden_python {
+    use crate::golden::{py_assert_golden, py_assert_pipeline_result};
+    use crate::models::Metric;
+    use crate::plugin_impls::{HourGrouping, SumAggregation};
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_golden_helpers_from_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let mut pipeline = MetricPipeline::new(vec![Metric::new(1, 0, None), Metric::new(2, 60, None)]);
+            pipeline.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+            let path = std::env::temp_dir().join(format!("metric-query-golden-py-{}.txt", std::process::id()));
+
+            let globals = PyDict::new(py);
+            globals.set_item("pipeline", Py::new(py, pipeline).unwrap()).unwrap();
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("path", &path).unwrap();
+            globals.set_item("assert_pipeline_result", wrap_pyfunction!(py_assert_pipeline_result, py).unwrap()).unwrap();
+            globals.set_item("assert_golden", wrap_pyfunction!(py_assert_golden, py).unwrap()).unwrap();
+            py.run(
+                c"
+assert_pipeline_result(pipeline, [Metric(3, 0)])
+try:
+    assert_pipeline_result(pipeline, [Metric(4, 0)])
+    raise RuntimeError('expected a mismatch')
+except AssertionError as e:
+    assert 'expected 4, got 3' in str(e)
+
+assert_golden(pipeline, path, update=True)
+assert_golden(pipeline, path, update=False)
+pipeline.add_metrics([Metric(1, 120)])
+try:
+    assert_golden(pipeline, path, update=False)
+    raise RuntimeError('expected a mismatch')
+except AssertionError:
+    pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            std::fs::remove_file(&path).unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_timestamp_range {
+    use chrono::{DateTime, Utc};
+
+    use crate::analysis::{correlation, CorrelationMethod};
+    use crate::errors::MetricQueryError;
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{DayGrouping, HourGrouping, MinuteGrouping, SumAggregation};
+    use crate::plugins::TimeGroupingPlugin;
+    use crate::settings::Settings;
+    use crate::steps::{
+        DerivativeTransformation, ResampleFill, ResampleTransformation, RollingReduceTransformation, RollingReduction,
+        RollingWindow, TrendOutput, TrendTransformation,
+    };
+    use crate::time_range::TimeRange;
+    use crate::transformations::TransformationStrategy;
+
+    /// 0001-01-01T00:00:00Z
+    const YEAR_ONE: i64 = -62_135_596_800;
+
+    #[test]
+    fn test_groupings_before_1970() {
+        assert_eq!(MinuteGrouping.get_group_timestamp(-1).unwrap(), -60);
+        assert_eq!(HourGrouping.get_group_timestamp(-1).unwrap(), -3_600);
+        assert_eq!(DayGrouping.get_group_timestamp(-1).unwrap(), -86_400);
+        assert_eq!(DayGrouping.get_group_timestamp(YEAR_ONE + 86_399).unwrap(), YEAR_ONE);
+
+        let kolkata = Settings { timezone: Some(chrono_tz::Asia::Kolkata), ..Settings::DEFAULT };
+        kolkata.scope(|| {
+            // 1969-12-31T23:59:59Z is 05:29:59 local; that hour started at 04:30Z
+            assert_eq!(HourGrouping.get_group_timestamp(-1).unwrap(), -1_800);
+            assert_eq!(DayGrouping.get_group_timestamp(-1).unwrap(), -19_800);
+        });
+    }
+
+    #[test]
+    fn test_groupings_error_only_beyond_chrono() {
+        let first = DateTime::<Utc>::MIN_UTC.timestamp();
+        let last = DateTime::<Utc>::MAX_UTC.timestamp();
+        assert_eq!(DayGrouping.get_group_timestamp(first).unwrap(), first);
+        assert_eq!(MinuteGrouping.get_group_timestamp(last).unwrap(), last - 59);
+
+        for timestamp in [i64::MIN, first - 1, last + 1, i64::MAX] {
+            for grouping in [&HourGrouping as &dyn TimeGroupingPlugin, &MinuteGrouping, &DayGrouping] {
+                let error = grouping.get_group_timestamp(timestamp).unwrap_err();
+                assert!(matches!(error, MetricQueryError::InvalidTimeGrouping { .. }));
+                assert!(error.to_string().contains("outside the supported range"));
+            }
+        }
+    }
+
+    #[test]
+    fn test_steps_handle_extreme_timestamps() {
+        let extremes = vec![Metric::new(0, i64::MIN, None), Metric::new(10, 0, None), Metric::new(20, i64::MAX, None)];
+
+        let floats: Vec<FloatMetric> = extremes.iter().map(FloatMetric::from).collect();
+        let rates = DerivativeTransformation::new(1).unwrap().apply_float(&floats).unwrap();
+        assert!(rates.iter().all(|m| m.value > 0.0 && m.value.is_finite()));
+        assert!(TrendTransformation::new(TrendOutput::Line).apply(&extremes).is_ok());
+
+        let resample = ResampleTransformation::new(i64::MAX, Box::new(SumAggregation::default()), ResampleFill::Linear);
+        let spread = vec![Metric::new(0, -i64::MAX, None), Metric::new(10, 0, None), Metric::new(20, i64::MAX, None)];
+        let grid: Vec<i64> = resample.apply(&spread).unwrap().iter().map(|m| m.timestamp).collect();
+        assert_eq!(grid, vec![i64::MIN + 1, 0, i64::MAX]);
+
+        // A window ending near i64::MIN still holds its newest point
+        let rolling = RollingReduceTransformation::new(RollingReduction::Max, RollingWindow::Duration(60)).unwrap();
+        let near_min = vec![Metric::new(1, i64::MIN, None), Metric::new(2, i64::MIN + 30, None)];
+        let values: Vec<i64> = rolling.apply(&near_min).unwrap().iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_ranges_and_buckets_at_the_edges() {
+        assert_eq!(TimeRange::new(i64::MIN, i64::MAX).unwrap().duration_seconds(), i64::MAX);
+        assert_eq!(TimeRange::new(-120, -60).unwrap().duration_seconds(), 60);
+
+        let metrics = vec![Metric::new(1, i64::MIN + 1, Some("a".to_string())), Metric::new(1, i64::MIN + 1, Some("b".to_string()))];
+        let error = correlation(&metrics, "a", "b", CorrelationMethod::Pearson, 60).unwrap_err();
+        assert!(matches!(error, MetricQueryError::ArithmeticOverflow { .. }));
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_plugin_sandbox {
+    use crate::errors::MetricQueryError;
+    use crate::models::Metric;
+    use crate::plugin_impls::init_registry;
+    use crate::plugins::TransformationRegistry;
+    use crate::settings::Settings;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+    use std::time::{Duration, Instant};
+
+    fn register(code: &std::ffi::CStr) {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("registry", Py::new(py, TransformationRegistry).unwrap()).unwrap();
+            py.run(code, Some(&globals), None).unwrap();
+        });
+    }
+
+    fn with_timeout(pipeline: &mut MetricPipeline, timeout: Duration) {
+        pipeline.set_settings(Some(Settings { plugin_timeout: Some(timeout), ..Settings::DEFAULT }));
+    }
+
+    fn metrics(count: i64) -> Vec<Metric> {
+        (0..count).map(|i| Metric::new(i, i, None)).collect()
+    }
+
+    #[test]
+    fn test_hanging_aggregation_times_out() {
+        register(c"
+def sandbox_spin(values):
+    while True:
+        pass
+registry.register_aggregation('sandbox_spin', sandbox_spin)
+");
+        let mut pipeline = MetricPipeline::new(metrics(3));
+        Python::with_gil(|py| pipeline.aggregate(py, "sandbox_spin", None, None, None, None, "first", None, None).unwrap());
+        with_timeout(&mut pipeline, Duration::from_millis(100));
+
+        let started = Instant::now();
+        let error = pipeline.run().unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(error.step_index(), Some(0));
+        assert!(matches!(error.root(), MetricQueryError::PluginTimeout { plugin, .. } if plugin == "sandbox_spin"));
+    }
+
+    #[test]
+    fn test_fast_plugins_run_under_a_timeout() {
+        register(c"registry.register_aggregation('sandbox_total', lambda values: sum(values))");
+        let mut pipeline = MetricPipeline::new(metrics(4));
+        Python::with_gil(|py| pipeline.aggregate(py, "sandbox_total", None, None, None, None, "first", None, None).unwrap());
+        with_timeout(&mut pipeline, Duration::from_secs(5));
+        assert_eq!(pipeline.run().unwrap()[0].value, 6);
+    }
+
+    #[test]
+    fn test_trusted_only_refuses_python_plugins() {
+        register(c"registry.register_time_grouping('sandbox_ten', lambda ts: ts - ts % 10)");
+        let mut pipeline = MetricPipeline::new(metrics(3));
+        Python::with_gil(|py| {
+            pipeline
+                .group_by_time(py, "sandbox_ten", "sum", None, None, None, None, None, None, None)
+                .unwrap()
+        });
+        pipeline.set_settings(Some(Settings { trusted_only: true, ..Settings::DEFAULT }));
+        let error = pipeline.run().unwrap_err();
+        assert!(matches!(error.root(), MetricQueryError::UntrustedPlugin { plugin } if plugin == "sandbox_ten"));
+
+        Python::with_gil(|py| {
+            let error: PyErr = error.into();
+            assert!(error.is_instance_of::<pyo3::exceptions::PyPermissionError>(py));
+        });
+    }
+
+    #[test]
+    fn test_pipeline_settings_cannot_lift_global_trusted_only() {
+        register(c"registry.register_aggregation('sandbox_global_total', lambda values: sum(values))");
+        let mut pipeline = MetricPipeline::new(metrics(3));
+        Python::with_gil(|py| {
+            pipeline.aggregate(py, "sandbox_global_total", None, None, None, None, "first", None, None).unwrap()
+        });
+        pipeline.set_settings(Some(Settings { trusted_only: false, ..Settings::DEFAULT }));
+
+        // Kept as short as possible, since other tests run under the global settings
+        let global = Settings::global();
+        Settings::set_global(Settings { trusted_only: true, ..global });
+        let result = pipeline.run();
+        Settings::set_global(global);
+        let error = result.unwrap_err();
+        assert!(matches!(error.root(), MetricQueryError::UntrustedPlugin { plugin } if plugin == "sandbox_global_total"));
+    }
+
+    #[test]
+    fn test_exceptions_carry_location_and_step() {
+        register(c"
+def sandbox_divide(values):
+    return values[0] / 0
+registry.register_aggregation('sandbox_divide', sandbox_divide)
+");
+        let mut pipeline = MetricPipeline::new(metrics(2));
+        Python::with_gil(|py| pipeline.aggregate(py, "sandbox_divide", None, None, None, None, "first", None, None).unwrap());
+        let error = pipeline.run().unwrap_err();
+        assert_eq!(error.step_index(), Some(0));
+        match error.root() {
+            MetricQueryError::PluginFailed { plugin, reason } => {
+                assert_eq!(plugin, "sandbox_divide");
+                assert!(reason.starts_with("ZeroDivisionError"), "{}", reason);
+                assert!(reason.contains(":3)"), "{}", reason);
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_filter_exceptions_skip_or_raise() {
+        register(c"
+registry.register_filter('sandbox_skip', lambda m: 1 / m.value > 0)
+registry.register_filter('sandbox_raise', lambda m: 1 / m.value > 0, on_error='raise')
+");
+        Python::with_gil(|py| {
+            let mut pipeline = MetricPipeline::new(metrics(3));
+            pipeline.filter("sandbox_skip".into(), None, None).unwrap();
+            assert_eq!(pipeline.run().unwrap().len(), 2);
+
+            let mut pipeline = MetricPipeline::new(metrics(3));
+            pipeline.filter("sandbox_raise".into(), None, None).unwrap();
+            let error = pipeline.run().unwrap_err();
+            assert!(matches!(error.root(), MetricQueryError::PluginFailed { .. }));
+
+            let registry = TransformationRegistry;
+            let predicate = py.eval(c"lambda m: True", None, None).unwrap();
+            assert!(registry.register_filter("sandbox_bad".to_string(), &predicate, "ignore").is_err());
+        });
+    }
+
+    #[test]
+    fn test_timed_out_filter_stops_the_batch() {
+        register(c"
+import time
+calls = []
+def sandbox_slow(m):
+    calls.append(m.timestamp)
+    time.sleep(0.2)
+    return True
+registry.register_filter('sandbox_slow', sandbox_slow)
+");
+        let mut pipeline = MetricPipeline::new(metrics(50));
+        pipeline.filter("sandbox_slow".into(), None, None).unwrap();
+        with_timeout(&mut pipeline, Duration::from_millis(20));
+
+        let started = Instant::now();
+        let error = pipeline.run().unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(matches!(error.root(), MetricQueryError::PluginTimeout { .. }));
+
+        // The pending error doesn't leak into the next filter step on this thread
+        let mut pipeline = MetricPipeline::new(metrics(3));
+        pipeline.filter("gt".into(), Some(0.into()), None).unwrap();
+        assert!(pipeline.run().is_ok());
+    }
+
+    #[test]
+    fn test_tap_and_assert_callables_run_in_the_sandbox() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let spin = py.eval(c"lambda ms: [0 for _ in iter(int, 1)]", None, None).unwrap();
+            let mut pipeline = MetricPipeline::new(metrics(3));
+            pipeline.tap(spin.unbind(), None).unwrap();
+            with_timeout(&mut pipeline, Duration::from_millis(100));
+            let error = py.allow_threads(|| pipeline.run().unwrap_err());
+            assert!(matches!(error.root(), MetricQueryError::PluginTimeout { plugin, .. } if plugin == "tap"));
+
+            let predicate = py.eval(c"lambda ms: 1 / 0", None, None).unwrap();
+            let mut pipeline = MetricPipeline::new(metrics(3));
+            pipeline.assert_that(&predicate, None, None, None).unwrap();
+            assert!(pipeline.thread_bound());
+            match pipeline.run().unwrap_err().root() {
+                MetricQueryError::PluginFailed { plugin, reason } => {
+                    assert_eq!(plugin, "assert_that");
+                    assert!(reason.starts_with("ZeroDivisionError"), "{}", reason);
+                }
+                other => panic!("unexpected error {:?}", other),
+            }
+
+            pipeline.set_settings(Some(Settings { trusted_only: true, ..Settings::DEFAULT }));
+            let error = pipeline.run().unwrap_err();
+            assert!(matches!(error.root(), MetricQueryError::UntrustedPlugin { plugin } if plugin == "assert_that"));
+        });
+    }
+}
+
+#[cfg(all(test, feature = "gpu"))]
+mod test_gpu {
+    use crate::errors::MetricQueryError;
+    use crate::gpu::{reduce_groups, Partial, Reduction, GPU_GROUPING_THRESHOLD, SEGMENT_LEN};
+    use crate::models::Metric;
+    use crate::plugin_impls::{HourGrouping, OverflowPolicy, RoundingMode, SumAggregation};
+    use crate::transformations::{TimeGroupingTransformation, TransformationStrategy};
+
+    #[test]
+    fn test_reduce_groups_matches_per_group_partials() {
+        // Interleaved groups: one spanning many segments, one tiny, one at the i64 extremes
+        let mut values = Vec::new();
+        let mut groups = Vec::new();
+        for i in 0..(SEGMENT_LEN as i64 * 3 + 17) {
+            values.push(i - 5000);
+            groups.push(0);
+            if i % 1000 == 0 {
+                values.push(i64::MAX - i);
+                groups.push(2);
+                values.push(i64::MIN + i);
+                groups.push(2);
+            }
+        }
+        values.push(42);
+        groups.push(1);
+
+        let partials = reduce_groups(&values, &groups, 3);
+        for (group, partial) in partials.iter().enumerate() {
+            let members: Vec<i64> = values.iter().zip(&groups).filter(|(_, &g)| g == group).map(|(&v, _)| v).collect();
+            assert_eq!(*partial, Partial::of(&members).unwrap(), "group {}", group);
+        }
+        assert_eq!(partials[2].min, i64::MIN);
+        assert_eq!(partials[2].max, i64::MAX);
+    }
+
+    #[test]
+    fn test_finish_applies_policies() {
+        let big = Partial::of(&[i64::MAX, i64::MAX, -3]).unwrap();
+        assert!(matches!(
+            Reduction::Sum(OverflowPolicy::Error).finish(&big),
+            Err(MetricQueryError::ArithmeticOverflow { .. })
+        ));
+        assert_eq!(Reduction::Sum(OverflowPolicy::Saturate).finish(&big).unwrap(), i64::MAX);
+        assert_eq!(Reduction::Avg(RoundingMode::Truncate).finish(&big).unwrap(), ((2 * i64::MAX as i128 - 3) / 3) as i64);
+
+        let small = Partial::of(&[1, 2, 4]).unwrap();
+        assert_eq!(Reduction::Sum(OverflowPolicy::Error).finish(&small).unwrap(), 7);
+        assert_eq!(Reduction::Avg(RoundingMode::Round).finish(&small).unwrap(), 2);
+        assert_eq!(Reduction::Avg(RoundingMode::Ceil).finish(&small).unwrap(), 3);
+        assert_eq!(Reduction::Min.finish(&small).unwrap(), 1);
+        assert_eq!(Reduction::Max.finish(&small).unwrap(), 4);
+    }
+
+    #[test]
+    fn test_sums_agree_on_both_sides_of_the_threshold() {
+        let grouped = |len: usize, head: &[i64]| {
+            let metrics: Vec<Metric> =
+                (0..len).map(|i| Metric::new(head.get(i).copied().unwrap_or(0), i as i64 % 60, None)).collect();
+            TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(SumAggregation::default())).apply(&metrics)
+        };
+        for len in [GPU_GROUPING_THRESHOLD - 1, GPU_GROUPING_THRESHOLD] {
+            // The running sum overflows, the total doesn't
+            assert_eq!(grouped(len, &[i64::MAX, 10, -20]).unwrap()[0].value, i64::MAX - 10, "{} metrics", len);
+            assert!(
+                matches!(grouped(len, &[i64::MAX, 1]), Err(MetricQueryError::ArithmeticOverflow { .. })),
+                "{} metrics",
+                len
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_batch {
+    use crate::batch::BatchExecutor;
+    use crate::compiled::CompiledPipeline;
+    use crate::errors::MetricQueryError;
+    use crate::models::Metric;
+    use crate::plugin_impls::{FnFilter, GreaterThanFilter, MaxAggregation, SumAggregation};
+    use crate::plugins::FilterPlugin;
+    use crate::transformations::MetricPipeline;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::ThreadId;
+
+    fn metrics() -> Vec<Metric> {
+        (1..=10).map(|i| Metric::new(i, i * 60, None)).collect()
+    }
+
+    fn compiled(build: impl FnOnce(&mut MetricPipeline)) -> CompiledPipeline {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        build(&mut pipeline);
+        CompiledPipeline::new(&pipeline)
+    }
+
+    #[test]
+    fn test_shared_steps_run_once_for_all_pipelines() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let mut shared = MetricPipeline::new(Vec::new());
+        shared.add_filter(Box::new(FnFilter::new("counted_gt_5", move |m| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            m.value > 5
+        })));
+
+        let executor = BatchExecutor::new(vec![
+            compiled(|p| p.add_aggregation(Box::new(SumAggregation::default()))),
+            compiled(|p| p.add_aggregation(Box::new(MaxAggregation))),
+            compiled(|_| {}),
+        ])
+        .with_shared_steps(&shared);
+        let results = executor.run(&metrics()).unwrap();
+
+        assert_eq!(calls.load(Ordering::Relaxed), 10);
+        let values: Vec<Vec<i64>> =
+            results.into_iter().map(|r| r.unwrap().0.iter().map(|m| m.value).collect()).collect();
+        assert_eq!(values, vec![vec![40], vec![10], vec![6, 7, 8, 9, 10]]);
+    }
+
+    #[test]
+    fn test_results_match_individual_runs() {
+        let pipelines: Vec<CompiledPipeline> = (0..20)
+            .map(|threshold| {
+                compiled(|p| {
+                    p.add_filter(Box::new(GreaterThanFilter::new(threshold % 10)));
+                    p.add_aggregation(Box::new(SumAggregation::default()));
+                })
+            })
+            .collect();
+        let input = metrics();
+        let results = BatchExecutor::new(pipelines.clone()).run(&input).unwrap();
+
+        assert_eq!(results.len(), 20);
+        for (pipeline, result) in pipelines.iter().zip(results) {
+            let expected = pipeline.run_metrics_with_warnings(&input).unwrap().0;
+            assert_eq!(result.unwrap().0[0].value, expected[0].value);
+        }
+    }
+
+    #[test]
+    fn test_failing_pipeline_keeps_the_others() {
+        let executor = BatchExecutor::new(vec![
+            compiled(|p| p.add_filter(Box::new(GreaterThanFilter::new(100)))),
+            compiled(|p| {
+                p.add_filter(Box::new(GreaterThanFilter::new(100)));
+                p.add_aggregation(Box::new(SumAggregation::default()));
+            }),
+        ]);
+        let results = executor.run(&metrics()).unwrap();
+        assert!(results[0].as_ref().unwrap().0.is_empty());
+        assert!(matches!(results[1].as_ref().unwrap_err().root(), MetricQueryError::EmptyMetricStream));
+
+        // A failing shared stage fails the batch, with the failing step reported
+        let mut shared = MetricPipeline::new(Vec::new());
+        shared.add_aggregation(Box::new(SumAggregation::default()));
+        let error = executor.with_shared_steps(&shared).run(&[]).unwrap_err();
+        assert_eq!(error.step_index(), Some(0));
+    }
+
+    /// Keeps every metric, recording the threads it runs on
+    #[derive(Clone)]
+    struct ThreadRecorder(Arc<Mutex<Vec<ThreadId>>>);
+
+    impl FilterPlugin for ThreadRecorder {
+        fn name(&self) -> &str {
+            "thread_recorder"
+        }
+
+        fn apply(&self, _metric: &Metric) -> bool {
+            self.0.lock().unwrap().push(std::thread::current().id());
+            true
+        }
+
+        fn thread_bound(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn test_thread_bound_pipelines_run_on_the_calling_thread() {
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let bound = compiled(|p| p.add_filter(Box::new(ThreadRecorder(Arc::clone(&threads)))));
+        let free = compiled(|p| p.add_aggregation(Box::new(SumAggregation::default())));
+        assert!(bound.thread_bound() && !free.thread_bound());
+
+        let results = BatchExecutor::new(vec![free.clone(), bound, free]).run(&metrics()).unwrap();
+        assert!(results.iter().all(Result::is_ok));
+        let caller = std::thread::current().id();
+        let threads = threads.lock().unwrap();
+        assert_eq!(threads.len(), 10);
+        assert!(threads.iter().all(|&thread| thread == caller));
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_batch_python {
+    use crate::batch::BatchExecutor;
+    use crate::models::Metric;
+    use crate::plugin_impls::{init_registry, GreaterThanFilter};
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_execute_returns_one_result_per_pipeline() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let metrics: Vec<Metric> = (1..=10).map(|i| Metric::new(i, i * 60, None)).collect();
+            let globals = PyDict::new(py);
+            globals.set_item("metrics", metrics).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            globals.set_item("BatchExecutor", py.get_type::<BatchExecutor>()).unwrap();
+            let mut shared = MetricPipeline::new(Vec::new());
+            shared.add_filter(Box::new(GreaterThanFilter::new(5)));
+            globals.set_item("shared", Py::new(py, shared).unwrap()).unwrap();
+            py.run(
+                c"
+total = MetricPipeline([])
+total.aggregate('sum')
+largest = MetricPipeline([])
+largest.aggregate('max')
+executor = BatchExecutor([total, largest.compile()], shared=shared)
+values = [[m.value for m in result] for result in executor.execute(metrics)]
+size = len(executor)
+
+empty = BatchExecutor([total, MetricPipeline([])])
+gathered = empty.execute([], return_exceptions=True)
+try:
+    empty.execute([])
+    raised = False
+except ValueError:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+
+            let values: Vec<Vec<i64>> = globals.get_item("values").unwrap().unwrap().extract().unwrap();
+            assert_eq!(values, vec![vec![40], vec![10]]);
+            assert_eq!(globals.get_item("size").unwrap().unwrap().extract::<usize>().unwrap(), 2);
+            let gathered = globals.get_item("gathered").unwrap().unwrap();
+            assert!(gathered.get_item(0).unwrap().is_instance_of::<pyo3::exceptions::PyValueError>());
+            assert_eq!(gathered.get_item(1).unwrap().len().unwrap(), 0);
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_streaming {
+    use crate::compiled::CompiledPipeline;
+    use crate::models::Metric;
+    use crate::plugin_impls::SumAggregation;
+    use crate::settings::{Settings, TimestampPrecision};
+    use crate::steps::LatestTransformation;
+    use crate::streaming::{SeriesChange, SeriesId, StreamingProcessor, WindowUpdate};
+    use crate::transformations::MetricPipeline;
+    use std::collections::BTreeMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+
+    fn labeled(value: i64, timestamp: i64, label: &str) -> Metric {
+        Metric::new(value, timestamp, Some(label.to_string()))
+    }
+
+    fn series(label: &str) -> SeriesId {
+        SeriesId { label: Some(label.to_string()), tags: BTreeMap::new() }
+    }
+
+    fn latest_per_label() -> CompiledPipeline {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_strategy(Box::new(LatestTransformation::new()));
+        CompiledPipeline::new(&pipeline)
+    }
+
+    #[test]
+    fn test_windows_close_when_a_later_metric_arrives() {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
+        let mut processor = StreamingProcessor::new(CompiledPipeline::new(&pipeline), 60).unwrap();
+        let pushed = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&pushed);
+        processor.subscribe(move |update| {
+            sink.lock().unwrap().push(update.clone());
+            Ok(())
+        });
+
+        assert!(processor.push(vec![Metric::new(1, 0, None), Metric::new(2, 30, None)]).unwrap().is_empty());
+        let updates = processor.push(vec![Metric::new(4, 60, None), Metric::new(8, 150, None)]).unwrap();
+        assert_eq!(updates.iter().map(|u| (u.start, u.end)).collect::<Vec<_>>(), vec![(0, 60), (60, 120)]);
+        assert_eq!(updates[0].changed[0].value, 3);
+        assert_eq!(updates[1].changed[0].delta, Some(1));
+        assert_eq!(*pushed.lock().unwrap(), updates);
+
+        // Metrics for a closed window are dropped
+        processor.push(vec![Metric::new(100, 90, None)]).unwrap();
+        assert_eq!(processor.late_metrics(), 1);
+        let flushed = processor.flush().unwrap();
+        assert_eq!((flushed[0].start, flushed[0].changed[0].value), (120, 8));
+        assert!(processor.flush().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_updates_are_delta_encoded_per_series() {
+        let mut processor = StreamingProcessor::new(latest_per_label(), 10).unwrap();
+        processor.push(vec![labeled(1, 0, "a"), labeled(5, 1, "b")]).unwrap();
+        let updates = processor.push(vec![labeled(1, 10, "a"), labeled(7, 20, "c")]).unwrap();
+
+        assert_eq!(updates[0].changed.len(), 2);
+        // "a" kept its value, "b" went away
+        assert_eq!(
+            updates[1],
+            WindowUpdate { start: 10, end: 20, changed: Vec::new(), removed: vec![series("b")] }
+        );
+        let flushed = processor.flush().unwrap();
+        assert_eq!(flushed[0].removed, vec![series("a")]);
+        assert_eq!(
+            flushed[0].changed,
+            vec![SeriesChange {
+                label: Some("c".to_string()),
+                tags: BTreeMap::new(),
+                value: 7,
+                timestamp: 20,
+                delta: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_series_are_told_apart_by_tags() {
+        let pipeline = CompiledPipeline::new(&MetricPipeline::new(Vec::new()));
+        let mut processor = StreamingProcessor::new(pipeline, 10).unwrap();
+        let pod = |value, timestamp, pod: &str| labeled(value, timestamp, "cpu").with_tag("pod", pod);
+        processor.push(vec![pod(1, 0, "a"), pod(2, 1, "b")]).unwrap();
+        let updates = processor.push(vec![pod(1, 10, "a"), pod(5, 11, "b"), pod(0, 20, "a")]).unwrap();
+
+        assert_eq!(updates[0].changed.len(), 2);
+        // Only pod "b" changed, by its own previous value
+        let changed = &updates[1].changed;
+        assert_eq!(changed.len(), 1);
+        assert_eq!((changed[0].tags["pod"].as_str(), changed[0].delta), ("b", Some(3)));
+    }
+
+    #[test]
+    fn test_failed_window_stays_open_for_a_retry() {
+        let mut processor = StreamingProcessor::new(latest_per_label(), 10).unwrap();
+        let fail = Arc::new(AtomicBool::new(false));
+        let failing = Arc::clone(&fail);
+        processor.subscribe(move |update| match failing.load(Ordering::SeqCst) && update.start == 10 {
+            true => Err(crate::errors::MetricQueryError::OperationFailed {
+                operation: "dashboard".to_string(),
+                reason: "disconnected".to_string(),
+            }),
+            false => Ok(()),
+        });
+
+        assert!(processor.push(vec![labeled(1, 0, "a")]).unwrap().is_empty());
+        fail.store(true, Ordering::SeqCst);
+        // Window 0 closes, then window 10 fails
+        assert!(processor.push(vec![labeled(2, 10, "a"), labeled(3, 25, "a")]).is_err());
+        fail.store(false, Ordering::SeqCst);
+
+        // The next push reports window 0, and window 10 still takes metrics
+        let updates = processor.push(vec![labeled(4, 12, "a")]).unwrap();
+        assert_eq!(updates.iter().map(|u| u.start).collect::<Vec<_>>(), vec![0]);
+        assert_eq!(processor.late_metrics(), 0);
+        let flushed = processor.flush().unwrap();
+        assert_eq!(flushed.iter().map(|u| u.start).collect::<Vec<_>>(), vec![10, 20]);
+        assert_eq!((flushed[0].changed[0].value, flushed[0].changed[0].delta), (4, Some(3)));
+    }
+
+    #[test]
+    fn test_full_updates_without_delta_encoding() {
+        let mut processor = StreamingProcessor::new(latest_per_label(), 10).unwrap().with_delta_encoding(false);
+        processor.push(vec![labeled(1, -5, "a")]).unwrap();
+        let updates = processor.push(vec![labeled(1, 5, "a")]).unwrap();
+        assert_eq!((updates[0].start, updates[0].end), (-10, 0));
+        let flushed = processor.flush().unwrap();
+        assert_eq!(flushed[0].changed[0].delta, Some(0));
+        assert!(StreamingProcessor::new(latest_per_label(), 0).is_err());
+    }
+
+    #[test]
+    fn test_window_follows_timestamp_precision() {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_strategy(Box::new(LatestTransformation::new()));
+        pipeline.set_settings(Some(Settings { timestamp_precision: TimestampPrecision::Milliseconds, ..Settings::default() }));
+        let mut processor = StreamingProcessor::new(CompiledPipeline::new(&pipeline), 60).unwrap();
+        // Both metrics fall in the first minute
+        assert!(processor.push(vec![labeled(1, 0, "a"), labeled(2, 59_999, "a")]).unwrap().is_empty());
+        let updates = processor.push(vec![labeled(3, 60_000, "a")]).unwrap();
+        assert_eq!((updates[0].start, updates[0].end), (0, 60_000));
+        assert_eq!(updates[0].changed[0].value, 2);
+    }
+
+    #[test]
+    fn test_subscriber_error_is_returned_from_push() {
+        let mut processor = StreamingProcessor::new(latest_per_label(), 10).unwrap();
+        processor.subscribe(|_| {
+            Err(crate::errors::MetricQueryError::OperationFailed {
+                operation: "dashboard".to_string(),
+                reason: "disconnected".to_string(),
+            })
+        });
+        processor.push(vec![labeled(1, 0, "a")]).unwrap();
+        assert!(processor.push(vec![labeled(1, 10, "a")]).is_err());
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_update_json() {
+        let update = WindowUpdate {
+            start: 0,
+            end: 60,
+            changed: vec![SeriesChange {
+                label: Some("cpu".to_string()),
+                tags: BTreeMap::from([("pod".to_string(), "a".to_string())]),
+                value: 3,
+                timestamp: 30,
+                delta: Some(-1),
+            }],
+            removed: vec![SeriesId { label: None, tags: BTreeMap::new() }],
+        };
+        assert_eq!(
+            update.to_json(),
+            r#"{"start":0,"end":60,"changed":[{"label":"cpu","tags":{"pod":"a"},"value":3,"timestamp":30,"delta":-1}],"removed":[{"label":null}]}"#
+        );
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_streaming_python {
+    use crate::models::Metric;
+    use crate::plugin_impls::init_registry;
+    use crate::settings::Settings;
+    use crate::streaming::StreamingProcessor;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_subscribers_receive_window_updates() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            globals.set_item("StreamingProcessor", py.get_type::<StreamingProcessor>()).unwrap();
+            py.run(
+                c"
+pipeline = MetricPipeline([])
+pipeline.aggregate('sum')
+processor = StreamingProcessor(pipeline, '1m')
+received = []
+processor.subscribe(lambda update: received.append((update.start, [c.value for c in update.changed])))
+processor.push([Metric(1, 0), Metric(2, 30)])
+returned = processor.push([Metric(4, 60)])
+processor.flush()
+
+def fail(update):
+    raise RuntimeError('gone')
+processor.subscribe(fail)
+try:
+    processor.push([Metric(1, 120), Metric(1, 180)])
+    raised = False
+except Exception:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+
+            let received: Vec<(i64, Vec<i64>)> = globals.get_item("received").unwrap().unwrap().extract().unwrap();
+            assert_eq!(received, vec![(0, vec![3]), (60, vec![4]), (120, vec![1])]);
+            assert_eq!(globals.get_item("returned").unwrap().unwrap().len().unwrap(), 1);
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+
+    #[test]
+    fn test_subscribers_run_in_the_plugin_sandbox() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            globals.set_item("Settings", py.get_type::<Settings>()).unwrap();
+            globals.set_item("StreamingProcessor", py.get_type::<StreamingProcessor>()).unwrap();
+            py.run(
+                c"
+pipeline = MetricPipeline([])
+pipeline.settings = Settings(plugin_timeout=0.1)
+processor = StreamingProcessor(pipeline, 10)
+def spin(update):
+    while True:
+        pass
+processor.subscribe(spin)
+try:
+    processor.push([Metric(1, 0), Metric(1, 10)])
+    raise AssertionError('hanging subscriber not stopped')
+except TimeoutError:
+    pass
+
+processor = StreamingProcessor(MetricPipeline([]), 10)
+def fail(update):
+    raise RuntimeError('gone')
+processor.subscribe(fail)
+try:
+    processor.push([Metric(1, 0), Metric(1, 10)])
+    raise AssertionError('exception swallowed')
+except ValueError as error:
+    assert error.code == 'plugin_failed' and 'RuntimeError: gone' in str(error), error
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_accuracy {
+    use crate::accuracy::{Accuracy, HyperLogLog, AUTO_APPROXIMATE_THRESHOLD};
+    use crate::errors::MetricQueryError;
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{
+        create_aggregation, with_accuracy, DistinctCountAggregation, GreaterThanFilter, HourGrouping,
+        PercentileAggregation, SumAggregation,
+    };
+    use crate::plugins::AggregationPlugin;
+    use crate::transformations::{
+        AggregationTransformation, FilterTransformation, MetricPipeline, TimeGroupingTransformation,
+    };
+
+    fn percentile(quantile: f64, accuracy: Accuracy) -> PercentileAggregation {
+        PercentileAggregation::new(quantile, accuracy).unwrap()
+    }
+
+    #[test]
+    fn test_exact_and_approximate_percentiles() {
+        let values: Vec<i64> = (1..=100).collect();
+        assert_eq!(percentile(0.9, Accuracy::Exact).apply_values(&values).unwrap(), 90);
+        let approximate = percentile(0.9, Accuracy::Approximate).apply_values(&values).unwrap();
+        assert!((89..=91).contains(&approximate), "p90 estimate {}", approximate);
+
+        let floats = [1.0, 2.0, f64::NAN, 4.0];
+        assert_eq!(percentile(0.5, Accuracy::Exact).apply_float_values(&floats).unwrap(), 2.0);
+        assert!(matches!(percentile(0.5, Accuracy::Exact).apply_values(&[]), Err(MetricQueryError::EmptyMetricStream)));
+        assert!(PercentileAggregation::new(1.5, Accuracy::Exact).is_err());
+    }
+
+    #[test]
+    fn test_percentile_names() {
+        assert_eq!(create_aggregation("median").unwrap().name(), "median");
+        assert_eq!(create_aggregation("p99").unwrap().name(), "p99");
+        assert_eq!(create_aggregation("p99.9").unwrap().name(), "p99.9");
+        assert!(create_aggregation("p101").is_err());
+        assert!(create_aggregation("pct").is_err());
+    }
+
+    #[test]
+    fn test_distinct_counts() {
+        let values: Vec<i64> = (0..1000).map(|i| i % 37).collect();
+        assert_eq!(DistinctCountAggregation::new(Accuracy::Exact).apply_values(&values).unwrap(), 37);
+        assert_eq!(DistinctCountAggregation::default().apply_float_values(&[0.0, -0.0, 1.5, f64::NAN]).unwrap(), 2.0);
+
+        let many: Vec<i64> = (0..50_000).collect();
+        let estimate = DistinctCountAggregation::new(Accuracy::Approximate).apply_values(&many).unwrap();
+        assert!((estimate - 50_000).abs() < 1_500, "distinct estimate {}", estimate);
+
+        let mut counter = HyperLogLog::new();
+        (0..10).for_each(|i| counter.add(i % 3));
+        assert_eq!(counter.estimate(), 3);
+    }
+
+    #[test]
+    fn test_accuracy_only_applies_to_sketchable_aggregations() {
+        assert!(with_accuracy(Box::new(SumAggregation::default()), Accuracy::Approximate).is_err());
+        let aggregation = with_accuracy(create_aggregation("p50").unwrap(), Accuracy::Auto).unwrap();
+        assert_eq!(aggregation.accuracy(), Some(Accuracy::Auto));
+        assert!(Accuracy::parse("fast").is_err());
+        assert_eq!(Accuracy::Auto.resolve(AUTO_APPROXIMATE_THRESHOLD), Accuracy::Exact);
+        assert_eq!(Accuracy::Auto.resolve(AUTO_APPROXIMATE_THRESHOLD + 1), Accuracy::Approximate);
+    }
+
+    #[test]
+    fn test_auto_accuracy_is_resolved_per_run_and_reported() {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_strategy(Box::new(AggregationTransformation::new(Box::new(
+            DistinctCountAggregation::new(Accuracy::Auto),
+        ))));
+        pipeline.add_strategy(Box::new(AggregationTransformation::new(Box::new(SumAggregation::default()))));
+
+        let small: Vec<Metric> = (0..10).map(|i| Metric::new(i, i, None)).collect();
+        assert_eq!(pipeline.run_metrics_with_warnings(&small).unwrap().0[0].value, 10);
+        let stats = pipeline.last_run_stats().unwrap();
+        assert_eq!(stats.steps[0].accuracy.as_deref(), Some("exact"));
+        assert_eq!(stats.steps[1].accuracy, None);
+
+        let large: Vec<Metric> = (0..=AUTO_APPROXIMATE_THRESHOLD as i64).map(|i| Metric::new(i, i, None)).collect();
+        let estimate = pipeline.run_metrics_with_warnings(&large).unwrap().0[0].value;
+        assert!((estimate - large.len() as i64).abs() < 3_000, "distinct estimate {}", estimate);
+        assert_eq!(pipeline.last_run_stats().unwrap().steps[0].accuracy.as_deref(), Some("approximate"));
+    }
+
+    #[test]
+    fn test_integer_percentiles_stay_exact_past_f64_precision() {
+        let values = [i64::MAX - 2, i64::MAX - 1, i64::MAX];
+        assert_eq!(percentile(0.5, Accuracy::Exact).apply_values(&values).unwrap(), i64::MAX - 1);
+        assert_eq!(percentile(0.75, Accuracy::Exact).apply_values(&[1 << 60, (1 << 60) + 3]).unwrap(), (1 << 60) + 2);
+    }
+
+    #[test]
+    fn test_modes_agree_on_all_missing_values() {
+        for accuracy in [Accuracy::Exact, Accuracy::Approximate] {
+            assert!(percentile(0.5, accuracy).apply_float_values(&[f64::NAN, f64::NAN]).unwrap().is_nan());
+        }
+    }
+
+    #[test]
+    fn test_auto_accuracy_is_planned_from_the_run_input() {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_strategy(Box::new(FilterTransformation::new(Box::new(GreaterThanFilter::new(
+            AUTO_APPROXIMATE_THRESHOLD as i64 - 10,
+        )))));
+        pipeline.add_strategy(Box::new(AggregationTransformation::new(Box::new(percentile(0.5, Accuracy::Auto)))));
+
+        // The filter leaves only a few metrics, but the mode was chosen for the whole input
+        let large: Vec<Metric> = (0..=AUTO_APPROXIMATE_THRESHOLD as i64).map(|i| Metric::new(i, i, None)).collect();
+        pipeline.run_metrics_with_warnings(&large).unwrap();
+        let stats = pipeline.last_run_stats().unwrap();
+        assert_eq!(stats.steps[1].input_count, 10);
+        assert_eq!(stats.steps[1].accuracy.as_deref(), Some("approximate"));
+    }
+
+    #[test]
+    fn test_grouped_percentiles_report_accuracy() {
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_strategy(Box::new(TimeGroupingTransformation::new(
+            Box::new(HourGrouping),
+            Box::new(percentile(0.5, Accuracy::Approximate)),
+        )));
+        let metrics: Vec<FloatMetric> = (0..3).map(|i| FloatMetric::new(Some(i as f64 * 10.0), i, None)).collect();
+        let result = pipeline.execute_float_metrics(&metrics).unwrap();
+        assert!((result[0].value - 10.0).abs() <= 0.1);
+        assert_eq!(pipeline.last_run_stats().unwrap().steps[0].accuracy.as_deref(), Some("approximate"));
+    }
+
+    #[cfg(feature = "spec")]
+    #[test]
+    fn test_spec_accuracy() {
+        use crate::spec::PipelineSpec;
+
+        let spec = PipelineSpec::from_json(
+            r#"{"version": 1, "steps": [{"op": "aggregate", "type": "count_distinct", "accuracy": "approximate"}]}"#,
+        );
+        let pipeline = spec.unwrap().build((0..5).map(|i| Metric::new(i, i, None)).collect()).unwrap();
+        assert_eq!(pipeline.run().unwrap()[0].value, 5);
+        assert_eq!(pipeline.last_run_stats().unwrap().steps[0].accuracy.as_deref(), Some("approximate"));
+
+        let invalid = PipelineSpec::from_json(
+            r#"{"version": 1, "steps": [{"op": "aggregate", "type": "sum", "accuracy": "approximate"}]}"#,
+        );
+        assert!(invalid.unwrap().build(Vec::new()).is_err());
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_accuracy_python {
+    use crate::models::Metric;
+    use crate::plugin_impls::init_registry;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_aggregate_accuracy_option() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let metrics: Vec<Metric> = (1..=100).map(|i| Metric::new(i, i * 60, None)).collect();
+            let globals = PyDict::new(py);
+            globals.set_item("metrics", metrics).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+pipeline = MetricPipeline(metrics)
+pipeline.aggregate('p99.9', accuracy='approximate')
+value = pipeline.execute()[0].value
+mode = pipeline.last_run_stats().steps[0].accuracy
+
+grouped = MetricPipeline(metrics)
+grouped.group_by_time('hour', 'count_distinct', accuracy='auto')
+counts = [m.value for m in grouped.execute()]
+grouped_mode = grouped.last_run_stats().steps[0].accuracy
+
+try:
+    MetricPipeline(metrics).aggregate('sum', accuracy='exact')
+    raised = False
+except ValueError:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+
+            let value: i64 = globals.get_item("value").unwrap().unwrap().extract().unwrap();
+            assert!((99..=101).contains(&value), "p99.9 estimate {}", value);
+            assert_eq!(globals.get_item("mode").unwrap().unwrap().extract::<String>().unwrap(), "approximate");
+            let mut counts: Vec<i64> = globals.get_item("counts").unwrap().unwrap().extract().unwrap();
+            counts.sort();
+            assert_eq!(counts, vec![41, 59]);
+            assert_eq!(globals.get_item("grouped_mode").unwrap().unwrap().extract::<String>().unwrap(), "exact");
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_metric_types {
+    use crate::models::{Metric, MetricType};
+    use crate::plugin_impls::{HourGrouping, SumAggregation};
+    use crate::steps::DerivativeTransformation;
+    use crate::transformations::{
+        AggregationTransformation, MetricPipeline, TimeGroupingTransformation, TransformationStrategy,
+    };
+
+    fn typed(value: i64, timestamp: i64, label: &str, metric_type: MetricType) -> Metric {
+        Metric::new(value, timestamp, Some(label.to_string())).with_type(metric_type)
+    }
+
+    fn warning_codes(pipeline: &MetricPipeline) -> Vec<&'static str> {
+        let (_, warnings) = pipeline.run_with_warnings().unwrap();
+        warnings.iter().map(|warning| warning.code).collect()
+    }
+
+    #[test]
+    fn test_parse_metric_type() {
+        assert_eq!(MetricType::parse("counter").unwrap(), MetricType::Counter);
+        assert_eq!(MetricType::parse("histogram").unwrap().as_str(), "histogram");
+        assert!(MetricType::parse("summary").is_err());
+        assert_eq!(Metric::new(1, 0, None).metric_type, None);
+    }
+
+    #[test]
+    fn test_aggregate_keeps_common_type() {
+        let metrics = vec![typed(1, 0, "a", MetricType::Gauge), typed(3, 60, "b", MetricType::Gauge)];
+        let result = AggregationTransformation::new(Box::new(SumAggregation::default())).apply(&metrics).unwrap();
+        assert_eq!(result[0].metric_type, Some(MetricType::Gauge));
+
+        let mut mixed = metrics.clone();
+        mixed.push(typed(5, 120, "c", MetricType::Counter));
+        let result = AggregationTransformation::new(Box::new(SumAggregation::default())).apply(&mixed).unwrap();
+        assert_eq!(result[0].metric_type, None);
+
+        let mut pipeline = MetricPipeline::new(mixed);
+        pipeline.add_strategy(Box::new(AggregationTransformation::new(Box::new(SumAggregation::default()))));
+        assert_eq!(warning_codes(&pipeline), vec!["mixed_metric_types"]);
+    }
+
+    #[test]
+    fn test_summing_counter_samples_warns() {
+        let counters = vec![typed(10, 0, "requests", MetricType::Counter), typed(15, 60, "requests", MetricType::Counter)];
+        let mut pipeline = MetricPipeline::new(counters.clone());
+        pipeline.add_strategy(Box::new(AggregationTransformation::new(Box::new(SumAggregation::default()))));
+        assert_eq!(warning_codes(&pipeline), vec!["counter_sum"]);
+
+        // One sample per counter is a legitimate cross-series sum
+        let latest = vec![typed(10, 0, "a", MetricType::Counter), typed(15, 0, "b", MetricType::Counter)];
+        let mut pipeline = MetricPipeline::new(latest);
+        pipeline.add_strategy(Box::new(AggregationTransformation::new(Box::new(SumAggregation::default()))));
+        assert!(warning_codes(&pipeline).is_empty());
+
+        let mut pipeline = MetricPipeline::new(counters);
+        pipeline.add_strategy(Box::new(TimeGroupingTransformation::new(
+            Box::new(HourGrouping),
+            Box::new(SumAggregation::default()),
+        )));
+        assert_eq!(warning_codes(&pipeline), vec!["counter_sum"]);
+    }
+
+    #[test]
+    fn test_group_by_time_keeps_series_type() {
+        let metrics = vec![
+            typed(1, 0, "requests", MetricType::Counter),
+            typed(7, 0, "temperature", MetricType::Gauge),
+            Metric::new(2, 0, Some("untyped".to_string())),
+        ];
+        let grouping = TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        let result = grouping.apply(&metrics).unwrap();
+        let type_of = |label: &str| result.iter().find(|m| m.label.as_deref() == Some(label)).unwrap().metric_type;
+        assert_eq!(type_of("requests"), Some(MetricType::Counter));
+        assert_eq!(type_of("temperature"), Some(MetricType::Gauge));
+        assert_eq!(type_of("untyped"), None);
+    }
+
+    #[test]
+    fn test_counter_derivative_handles_resets() {
+        let metrics = vec![
+            typed(100, 0, "requests", MetricType::Counter),
+            typed(160, 60, "requests", MetricType::Counter),
+            typed(30, 120, "requests", MetricType::Counter),
+        ];
+        let rate = DerivativeTransformation::new(1).unwrap().with_time_unit(60).unwrap();
+        let result = rate.apply(&metrics).unwrap();
+        assert_eq!(result.iter().map(|m| m.value).collect::<Vec<_>>(), vec![60, 30]);
+        assert!(result.iter().all(|m| m.metric_type == Some(MetricType::Gauge)));
+
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_strategy(Box::new(rate));
+        assert_eq!(warning_codes(&pipeline), vec!["counter_reset"]);
+
+        let gauges = vec![typed(100, 0, "t", MetricType::Gauge), typed(40, 60, "t", MetricType::Gauge)];
+        let result = DerivativeTransformation::new(1).unwrap().with_time_unit(60).unwrap().apply(&gauges).unwrap();
+        assert_eq!(result[0].value, -60);
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_metric_types_python {
+    use crate::models::Metric;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_metric_type_keyword() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            py.run(
+                c"
+counter = Metric(5, 0, 'requests', metric_type='counter')
+kind = counter.metric_type
+untyped = Metric(5, 0).metric_type
+try:
+    Metric(5, 0, metric_type='summary')
+    raised = False
+except ValueError:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            assert_eq!(globals.get_item("kind").unwrap().unwrap().extract::<String>().unwrap(), "counter");
+            assert!(globals.get_item("untyped").unwrap().unwrap().is_none());
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_timestamp_precision {
+    use crate::models::Metric;
+    use crate::plugin_impls::{create_time_grouping, HourGrouping, SecondGrouping, SumAggregation};
+    use crate::plugins::TimeGroupingPlugin;
+    use crate::settings::{Settings, TimestampPrecision};
+    use crate::steps::{DerivativeTransformation, RollingReduceTransformation, RollingReduction, RollingWindow};
+    use crate::transformations::{MetricPipeline, TransformationStrategy};
+
+    fn at(precision: TimestampPrecision) -> Settings {
+        Settings { timestamp_precision: precision, ..Settings::DEFAULT }
+    }
+
+    #[test]
+    fn test_parse_precision() {
+        assert_eq!(TimestampPrecision::parse("ms").unwrap().ticks_per_second(), 1_000);
+        assert_eq!(TimestampPrecision::Nanoseconds.as_str(), "ns");
+        assert!(TimestampPrecision::parse("ps").is_err());
+        assert_eq!(TimestampPrecision::Milliseconds.split(-1), (-1, 999));
+        assert!(TimestampPrecision::Nanoseconds.from_seconds(i64::MAX / 10).is_err());
+    }
+
+    #[test]
+    fn test_groupings_keep_sub_second_metrics_apart() {
+        let metrics: Vec<Metric> = (0..4).map(|i| Metric::new(1, 1_700_000_000_000 + i * 400, None)).collect();
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_time_grouping(Box::new(SecondGrouping), Box::new(SumAggregation::default()));
+        pipeline.set_settings(Some(at(TimestampPrecision::Milliseconds)));
+        let mut seconds: Vec<(i64, i64)> = pipeline.run().unwrap().iter().map(|m| (m.timestamp, m.value)).collect();
+        seconds.sort();
+        assert_eq!(seconds, vec![(1_700_000_000_000, 3), (1_700_000_001_000, 1)]);
+
+        at(TimestampPrecision::Nanoseconds).scope(|| {
+            let hour = HourGrouping.get_group_timestamp(5_400_000_000_001).unwrap();
+            assert_eq!(hour, 3_600_000_000_000);
+            assert_eq!(create_time_grouping("day").unwrap().get_group_timestamp(-1).unwrap(), -86_400_000_000_000);
+        });
+        assert_eq!(SecondGrouping.get_group_timestamp(1_234).unwrap(), 1_234);
+    }
+
+    #[test]
+    fn test_rates_and_windows_use_seconds() {
+        let metrics = vec![Metric::new(0, 0, None), Metric::new(5, 500, None), Metric::new(10, 1_000, None)];
+        at(TimestampPrecision::Milliseconds).scope(|| {
+            let rates = DerivativeTransformation::new(1).unwrap().apply(&metrics).unwrap();
+            assert_eq!(rates.iter().map(|m| m.value).collect::<Vec<_>>(), vec![10, 10]);
+
+            let rolling = RollingReduceTransformation::new(RollingReduction::Sum, RollingWindow::Duration(1)).unwrap();
+            let sums = rolling.apply(&metrics).unwrap();
+            assert_eq!(sums.iter().map(|m| m.value).collect::<Vec<_>>(), vec![0, 5, 15]);
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_metric_series {
+    use crate::compiled::CompiledPipeline;
+    use crate::models::{Metric, MetricSeries};
+    use crate::plugin_impls::{HourGrouping, SumAggregation};
+    use crate::transformations::MetricPipeline;
+
+    fn labeled(value: i64, timestamp: i64, label: &str) -> Metric {
+        Metric::new(value, timestamp, Some(label.to_string()))
+    }
+
+    #[test]
+    fn test_series_keep_their_identity() {
+        let mut series = MetricSeries::from_metrics(vec![labeled(1, 0, "cpu"), labeled(2, 0, "mem"), labeled(3, 60, "cpu")]);
+        series.insert(Some("disk".to_string()), vec![Metric::new(4, 0, None)]);
+
+        assert_eq!(series.names().collect::<Vec<_>>(), vec![Some("cpu"), Some("disk"), Some("mem")]);
+        assert_eq!(series.get(Some("cpu")).unwrap().iter().map(|m| m.value).collect::<Vec<_>>(), vec![1, 3]);
+        assert_eq!(series.get(Some("disk")).unwrap()[0].label.as_deref(), Some("disk"));
+        assert_eq!(series.to_metrics().len(), 4);
+        assert_eq!(series.remove(Some("mem")).unwrap().len(), 1);
+        assert!(series.get(Some("mem")).is_none());
+    }
+
+    #[test]
+    fn test_apply_runs_each_series_separately() {
+        let series = MetricSeries::from_metrics(vec![labeled(1, 0, "cpu"), labeled(2, 0, "mem"), labeled(3, 60, "cpu")]);
+        let mut pipeline = MetricPipeline::new(Vec::new());
+        pipeline.add_aggregation(Box::new(SumAggregation::default()));
+        let (sums, _) = series.apply(&CompiledPipeline::new(&pipeline)).unwrap();
+        assert_eq!(sums.get(Some("cpu")).unwrap()[0].value, 4);
+        assert_eq!(sums.get(Some("mem")).unwrap()[0].value, 2);
+
+        let mut grouped = series.pipeline();
+        grouped.add_time_grouping(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        assert_eq!(grouped.run().unwrap().len(), 2);
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_metric_series_python {
+    use crate::models::{Metric, MetricSeries};
+    use crate::plugin_impls::init_registry;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_series_from_python() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricSeries", py.get_type::<MetricSeries>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+series = MetricSeries({'cpu': [Metric(1, 0), Metric(3, 60)], 'mem': [Metric(2, 0)]})
+series['disk'] = [Metric(4, 0)]
+assert len(series) == 3 and 'disk' in series
+assert series['cpu'][0].label == 'cpu'
+assert series.names() == ['cpu', 'disk', 'mem']
+
+total = MetricPipeline([])
+total.aggregate('sum')
+sums = series.apply(total)
+assert sums['cpu'][0].value == 4 and sums['disk'][0].value == 4
+
+pipeline = MetricPipeline(series)
+pipeline.group_by_time('hour', 'sum')
+assert sorted(m.label for m in pipeline.execute()) == ['cpu', 'disk', 'mem']
+
+del series['disk']
+try:
+    series['disk']
+    raised = False
+except KeyError:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_metric_arrays {
+    use crate::models::Metric;
+
+    #[test]
+    fn test_arrays_round_trip() {
+        let labels = vec![Some("a".to_string()), None];
+        let metrics = Metric::from_arrays(&[1, 2], &[10, 20], Some(labels.clone())).unwrap();
+        assert_eq!(metrics[1].timestamp, 20);
+        assert_eq!(metrics[0].label.as_deref(), Some("a"));
+        assert_eq!(Metric::to_arrays(&metrics), (vec![1, 2], vec![10, 20], labels));
+
+        assert!(Metric::from_arrays(&[1, 2], &[10], None).is_err());
+        assert!(Metric::from_arrays(&[1], &[10], Some(Vec::new())).is_err());
+        assert!(Metric::from_arrays(&[], &[], None).unwrap().is_empty());
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_metric_arrays_python {
+    use crate::models::Metric;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_from_arrays_in_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            py.run(
+                c"
+metrics = Metric.from_arrays(list(range(1000)), [i * 60 for i in range(1000)], ['cpu'] * 1000)
+assert len(metrics) == 1000 and metrics[999].timestamp == 59940 and metrics[0].label == 'cpu'
+values, timestamps, labels = Metric.to_arrays(metrics[:2])
+assert values == [0, 1] and timestamps == [0, 60] and labels == ['cpu', 'cpu']
+assert Metric.from_arrays([1], [2])[0].label is None
+try:
+    Metric.from_arrays([1, 2], [3])
+    raised = False
+except ValueError:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_metric_inputs_python {
+    use crate::python::create_pipeline;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_tuples_and_dicts_as_metrics() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("create_pipeline", pyo3::wrap_pyfunction!(create_pipeline, py).unwrap()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+import json
+payload = json.loads('[{\"value\": 5, \"timestamp\": 60, \"label\": \"cpu\", \"unit\": \"ms\"}, {\"value\": 7, \"timestamp\": 120}]')
+metrics = create_pipeline(payload).metrics
+assert (metrics[0].value, metrics[0].label, metrics[0].unit) == (5, 'cpu', 'ms')
+assert metrics[1].label is None
+
+mixed = MetricPipeline([(1, 0), (2, 60, 'mem'), {'value': 3, 'timestamp': 0, 'metric_type': 'gauge'}]).metrics
+assert [m.value for m in mixed] == [1, 2, 3] and mixed[1].label == 'mem' and mixed[2].metric_type == 'gauge'
+
+errors = []
+for bad in [[{'value': 1}], [(1,)], ['1,2']]:
+    try:
+        create_pipeline(bad)
+    except (TypeError, ValueError) as e:
+        errors.append(type(e).__name__)
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            let errors: Vec<String> = globals.get_item("errors").unwrap().unwrap().extract().unwrap();
+            assert_eq!(errors.len(), 3);
+        });
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_metric_dunders_python {
+    use crate::models::{LabeledMetric, Metric};
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_compare_hash_and_print_metrics() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("LabeledMetric", py.get_type::<LabeledMetric>()).unwrap();
+            py.run(
+                c"
+a = Metric(5, 60, 'cpu', unit='ms')
+assert a == Metric(5, 60, 'cpu', unit='ms') and a != Metric(5, 60, 'cpu')
+assert a != 'metric'
+assert len({a, Metric(5, 60, 'cpu', unit='ms'), Metric(6, 60, 'cpu')}) == 2
+assert sorted([Metric(1, 120), Metric(2, 60, 'b'), Metric(3, 60, 'a')]) == [Metric(3, 60, 'a'), Metric(2, 60, 'b'), Metric(1, 120)]
+assert repr(a) == \"Metric(value=5, timestamp=60, label='cpu', unit='ms')\"
+assert repr(Metric(1, 0, fields={'in': 2})) == \"Metric(value=1, timestamp=0, fields={'in': 2})\"
+d = a.to_dict()
+assert d['value'] == 5 and d['label'] == 'cpu' and d['metric_type'] is None and d['fields'] == {} and d['exemplar'] is None
+
+l = LabeledMetric('cpu', 1, 0)
+assert l == LabeledMetric('cpu', 1, 0) and hash(l) == hash(LabeledMetric('cpu', 1, 0))
+assert LabeledMetric('mem', 1, 0) < LabeledMetric('cpu', 1, 60)
+assert l.to_dict() == {'label': 'cpu', 'value': 1, 'timestamp': 0}
+assert repr(l) == \"LabeledMetric(label='cpu', value=1, timestamp=0)\"
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_labeled_pipeline_python {
+    use crate::models::{LabeledMetric, Metric};
+    use crate::plugin_impls::init_registry;
+    use crate::python::create_labeled_pipeline;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_labeled_metrics_run_through_pipelines() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("LabeledMetric", py.get_type::<LabeledMetric>()).unwrap();
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            globals
+                .set_item("create_labeled_pipeline", pyo3::wrap_pyfunction!(create_labeled_pipeline, py).unwrap())
+                .unwrap();
+            py.run(
+                c"
+metrics = [LabeledMetric('cpu', 1, 0), LabeledMetric('mem', 5, 0), LabeledMetric('cpu', 3, 60)]
+pipeline = create_labeled_pipeline(metrics)
+pipeline.group_by_time('hour', 'sum')
+result = sorted(LabeledMetric.from_metric(m) for m in pipeline.execute())
+assert result == [LabeledMetric('cpu', 4, 0), LabeledMetric('mem', 5, 0)]
+
+assert LabeledMetric('cpu', 1, 0).to_metric() == Metric(1, 0, 'cpu')
+assert len(MetricPipeline(metrics).metrics) == 3
+try:
+    LabeledMetric.from_metric(Metric(1, 0))
+    raised = False
+except ValueError:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_iso_timestamps {
+    use crate::models::Metric;
+    use crate::settings::{Settings, TimestampPrecision};
+
+    #[test]
+    fn test_from_iso() {
+        let metric = Metric::from_iso(5, "2024-03-01T10:15:30Z", Some("cpu".to_string())).unwrap();
+        assert_eq!(metric.timestamp, 1_709_288_130);
+        assert_eq!(metric.label.as_deref(), Some("cpu"));
+        assert_eq!(Metric::from_iso(5, "2024-03-01T12:15:30.9+02:00", None).unwrap().timestamp, 1_709_288_130);
+        assert_eq!(Metric::from_iso(5, "2024-03-01T10:15:30", None).unwrap().timestamp, 1_709_288_130);
+        assert!(Metric::from_iso(5, "yesterday", None).is_err());
+        assert_eq!(metric.timestamp_iso().unwrap(), "2024-03-01T10:15:30Z");
+    }
+
+    #[test]
+    fn test_iso_at_millisecond_precision() {
+        let settings = Settings { timestamp_precision: TimestampPrecision::Milliseconds, ..Settings::DEFAULT };
+        settings.scope(|| {
+            let metric = Metric::from_iso(1, "2024-03-01T10:15:30.25Z", None).unwrap();
+            assert_eq!(metric.timestamp, 1_709_288_130_250);
+            assert_eq!(metric.timestamp_iso().unwrap(), "2024-03-01T10:15:30.250Z");
+            assert_eq!(Metric::new(1, -1, None).timestamp_iso().unwrap(), "1969-12-31T23:59:59.999Z");
+        });
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_iso_timestamps_python {
+    use crate::models::Metric;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_from_iso_in_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            py.run(
+                c"
+metric = Metric.from_iso(5, '2024-03-01T10:15:30Z', label='cpu')
+assert metric.timestamp == 1709288130 and metric.label == 'cpu'
+assert metric.timestamp_iso == '2024-03-01T10:15:30Z'
+assert Metric(1, 0).timestamp_iso == '1970-01-01T00:00:00Z'
+try:
+    Metric.from_iso(5, 'not a time')
+    raised = False
+except ValueError:
+    raised = True
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+            assert!(globals.get_item("raised").unwrap().unwrap().extract::<bool>().unwrap());
+        });
+    }
+}
+
+#[cfg(all(test, feature = "spec"))]
+mod test_serde {
+    use crate::errors::MetricQueryError;
+    use crate::models::{Exemplar, LabeledMetric, Metric, MetricType};
+
+    #[test]
+    fn test_metric_round_trip() {
+        let metric = Metric::new(5, 100, Some("cpu".to_string()))
+            .with_unit("ms")
+            .with_type(MetricType::Gauge)
+            .with_field("bytes_in", 7)
+            .with_exemplar(Exemplar::new("abc".to_string(), 5.0, 100));
+        let json = serde_json::to_string(&metric).unwrap();
+        assert!(json.contains(r#""metric_type":"gauge""#));
+        assert_eq!(serde_json::from_str::<Metric>(&json).unwrap(), metric);
+
+        let minimal: Metric = serde_json::from_str(r#"{"value": 3, "timestamp": 60}"#).unwrap();
+        assert_eq!(minimal, Metric::new(3, 60, None));
+
+        let labeled = LabeledMetric::new("memory".to_string(), 9, 120);
+        let json = serde_json::to_string(&labeled).unwrap();
+        assert_eq!(serde_json::from_str::<LabeledMetric>(&json).unwrap(), labeled);
+    }
+
+    #[test]
+    fn test_error_round_trip() {
+        let err = MetricQueryError::InvalidFilter { reason: "bad".to_string() }.at_step(2, "gt");
+        let json = serde_json::to_string(&err).unwrap();
+        let back: MetricQueryError = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.to_string(), err.to_string());
+        assert_eq!(back.code(), "invalid_filter");
+    }
+}
+
+#[cfg(test)]
+mod test_decimal {
+    use crate::decimal::{format_decimal, parse_decimal, rescale};
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{
+        AvgAggregation, DistinctCountAggregation, FnAggregation, HourGrouping, RoundingMode, SumAggregation,
+    };
+    use crate::steps::{
+        DerivativeTransformation, RescaleTransformation, ResampleFill, ResampleTransformation,
+        RollingPercentileTransformation, RollingReduceTransformation, RollingReduction, RollingWindow,
+        TopKSeriesTransformation,
+    };
+    use crate::transformations::{AggregationTransformation, TimeGroupingTransformation, TransformationStrategy};
+
+    #[test]
+    fn test_parse_and_format() {
+        assert_eq!(parse_decimal("12.34").unwrap(), (1234, 2));
+        assert_eq!(parse_decimal("-0.05").unwrap(), (-5, 2));
+        assert_eq!(parse_decimal("7").unwrap(), (7, 0));
+        assert!(parse_decimal("1.2.3").is_err());
+        assert!(parse_decimal("").is_err());
+        assert!(parse_decimal("99999999999999999999").is_err());
+        assert_eq!(format_decimal(1234, 2), "12.34");
+        assert_eq!(format_decimal(-5, 2), "-0.05");
+        assert_eq!(format_decimal(7, 0), "7");
+        assert_eq!(rescale(1234, 2, 4, RoundingMode::Truncate).unwrap(), 123_400);
+        assert_eq!(rescale(1235, 2, 1, RoundingMode::Round).unwrap(), 124);
+        assert!(rescale(i64::MAX, 0, 2, RoundingMode::Round).is_err());
+    }
+
+    #[test]
+    fn test_sum_aligns_scales_exactly() {
+        let metrics = vec![
+            Metric::from_decimal("0.10", 0, None).unwrap(),
+            Metric::from_decimal("0.2", 1, None).unwrap(),
+            Metric::new(1, 2, None),
+        ];
+        let result = AggregationTransformation::new(Box::new(SumAggregation::default())).apply(&metrics).unwrap();
+        assert_eq!(result[0].scale, Some(2));
+        assert_eq!(result[0].decimal_string(), "1.30");
+        assert_eq!(FloatMetric::from(&result[0]).value, 1.3);
+    }
+
+    #[test]
+    fn test_avg_keeps_digits_after_rescale() {
+        let metrics: Vec<Metric> =
+            ["0.01", "0.02"].iter().map(|d| Metric::from_decimal(d, 0, None).unwrap()).collect();
+        let avg = AggregationTransformation::new(Box::new(AvgAggregation::new(RoundingMode::Round)));
+        assert_eq!(avg.apply(&metrics).unwrap()[0].decimal_string(), "0.02");
+
+        let rescaled = RescaleTransformation::new(4, RoundingMode::Round).unwrap().apply(&metrics).unwrap();
+        assert_eq!(avg.apply(&rescaled).unwrap()[0].decimal_string(), "0.0150");
+        assert!(RescaleTransformation::new(19, RoundingMode::Round).is_err());
+    }
+
+    #[test]
+    fn test_grouping_keeps_scale_except_for_counts() {
+        let metrics = vec![
+            Metric::from_decimal("1.5", 0, None).unwrap(),
+            Metric::from_decimal("2.25", 60, None).unwrap(),
+        ];
+        let sum = TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        let result = sum.apply(&metrics).unwrap();
+        assert_eq!((result[0].value, result[0].scale), (375, Some(2)));
+
+        let distinct = TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(DistinctCountAggregation::default()));
+        let result = distinct.apply(&metrics).unwrap();
+        assert_eq!((result[0].value, result[0].scale), (2, None));
+
+        // Custom aggregations are counts unless they say otherwise
+        let count = FnAggregation::new("count", |ms| ms.len() as i64);
+        let result = TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(count)).apply(&metrics).unwrap();
+        assert_eq!((result[0].value, result[0].scale), (2, None));
+        let total = FnAggregation::new("total", |ms| ms.iter().map(|m| m.value).sum()).keeping_scale();
+        let result = TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(total)).apply(&metrics).unwrap();
+        assert_eq!((result[0].value, result[0].scale), (375, Some(2)));
+    }
+
+    #[test]
+    fn test_series_steps_align_and_keep_scale() {
+        let metrics = vec![
+            Metric::from_decimal("1.5", 0, Some("a".to_string())).unwrap(),
+            Metric::from_decimal("2.25", 60, Some("a".to_string())).unwrap(),
+            Metric::from_decimal("0.5", 0, Some("b".to_string())).unwrap(),
+            Metric::from_decimal("3.5", 60, Some("b".to_string())).unwrap(),
+        ];
+        let points = |result: Vec<Metric>| -> Vec<String> { result.iter().map(Metric::decimal_string).collect() };
+
+        let rolling = RollingReduceTransformation::new(RollingReduction::Sum, RollingWindow::Count(2)).unwrap();
+        assert_eq!(points(rolling.apply(&metrics).unwrap()), vec!["1.50", "0.50", "3.75", "4.00"]);
+        let p100 = RollingPercentileTransformation::new(1.0, RollingWindow::Count(2)).unwrap();
+        assert_eq!(points(p100.apply(&metrics).unwrap()), vec!["1.50", "0.50", "2.25", "3.50"]);
+        let resample = ResampleTransformation::new(60, Box::new(SumAggregation::default()), ResampleFill::Previous);
+        assert_eq!(points(resample.apply(&metrics).unwrap()), vec!["1.50", "2.25", "0.50", "3.50"]);
+        let count = ResampleTransformation::new(120, Box::new(DistinctCountAggregation::default()), ResampleFill::Previous);
+        assert_eq!(count.apply(&metrics).unwrap()[0].scale, None);
+        // 0.75 per minute for "a", 3.00 per minute for "b"
+        let rate = DerivativeTransformation::new(1).unwrap().with_time_unit(60).unwrap();
+        assert_eq!(points(rate.apply(&metrics).unwrap()), vec!["0.75", "3.00"]);
+
+        // 1.5 at scale 1 outranks 0.25 at scale 2
+        let mixed = vec![
+            Metric::new(25, 0, Some("a".to_string())).with_scale(2),
+            Metric::new(15, 0, Some("b".to_string())).with_scale(1),
+        ];
+        let top = TopKSeriesTransformation::new(1, Box::new(SumAggregation::default())).unwrap();
+        let result = top.apply(&mixed).unwrap();
+        assert_eq!((result[0].label.as_deref(), result[0].value, result[0].scale), (Some("b"), 15, Some(1)));
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_decimal_python {
+    use crate::models::Metric;
+    use crate::plugin_impls::init_registry;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_decimal_metrics_in_python() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+from decimal import Decimal
+metrics = [Metric.from_decimal(Decimal('19.99'), 0), Metric.from_decimal('0.01', 1)]
+assert metrics[0].value == 1999 and metrics[0].scale == 2
+pipeline = MetricPipeline(metrics)
+pipeline.rescale(4)
+pipeline.aggregate('avg')
+result = pipeline.execute()
+assert result[0].decimal == Decimal('10.0000'), result[0].decimal
+assert Metric(5, 0, scale=1).decimal == Decimal('0.5')
+assert Metric(5, 0).decimal == Decimal(5)
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_metadata {
+    use crate::models::{MetadataMerge, Metric};
+    use crate::plugin_impls::{FnTimeGrouping, GreaterThanFilter, HourGrouping, SumAggregation};
+    use crate::settings::Settings;
+    use crate::transformations::{
+        AggregationTransformation, FilterTransformation, TimeGroupingTransformation, TransformationStrategy,
+    };
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn sample() -> Vec<Metric> {
+        vec![
+            Metric::new(1, 0, None).with_source("host-a").with_description("request count"),
+            Metric::new(2, 60, None).with_source("host-b"),
+            Metric::new(3, 120, None).with_source("host-a"),
+            Metric::new(4, 7200, None),
+        ]
+    }
+
+    #[test]
+    fn test_filter_keeps_metadata() {
+        let result = FilterTransformation::new(Box::new(GreaterThanFilter::new(1))).apply(&sample()).unwrap();
+        assert_eq!(result[0].source.as_deref(), Some("host-b"));
+    }
+
+    #[test]
+    fn test_merge_modes() {
+        let values = [Some("a"), None, Some("b"), Some("a")];
+        assert_eq!(MetadataMerge::First.merge(values).as_deref(), Some("a"));
+        assert_eq!(MetadataMerge::Concat.merge(values).as_deref(), Some("a; b"));
+        assert_eq!(MetadataMerge::Concat.merge([None, None]), None);
+        assert!(MetadataMerge::parse("last").is_err());
+    }
+
+    #[test]
+    fn test_aggregation_merges_per_settings() {
+        let sum = AggregationTransformation::new(Box::new(SumAggregation::default()));
+        let result = sum.apply(&sample()).unwrap();
+        assert_eq!(result[0].source.as_deref(), Some("host-a"));
+        assert_eq!(result[0].description.as_deref(), Some("request count"));
+
+        let concat = Settings { metadata: MetadataMerge::Concat, ..Settings::DEFAULT };
+        concat.scope(|| {
+            let result = sum.apply(&sample()).unwrap();
+            assert_eq!(result[0].source.as_deref(), Some("host-a; host-b"));
+        });
+    }
+
+    #[test]
+    fn test_grouping_merges_per_group() {
+        let concat = Settings { metadata: MetadataMerge::Concat, ..Settings::DEFAULT };
+        let mut result = concat.scope(|| {
+            TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(SumAggregation::default()))
+                .apply(&sample())
+                .unwrap()
+        });
+        result.sort_by_key(|m| m.timestamp);
+        assert_eq!(result[0].source.as_deref(), Some("host-a; host-b"));
+        assert_eq!(result[0].description.as_deref(), Some("request count"));
+        assert_eq!(result[1].source, None);
+    }
+
+    #[test]
+    fn test_grouping_merges_metadata_in_one_pass() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&calls);
+        let hourly = FnTimeGrouping::new("counted_hour", move |timestamp| {
+            counter.fetch_add(1, Ordering::Relaxed);
+            timestamp - timestamp.rem_euclid(3600)
+        });
+        let result = TimeGroupingTransformation::new(Box::new(hourly), Box::new(SumAggregation::default()))
+            .apply(&sample())
+            .unwrap();
+        assert_eq!(calls.load(Ordering::Relaxed), sample().len());
+        assert!(result.iter().any(|m| m.source.as_deref() == Some("host-a")));
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_metadata_python {
+    use crate::models::Metric;
+    use crate::settings::Settings;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_metadata_from_python() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("Settings", py.get_type::<Settings>()).unwrap();
+            py.run(
+                c"
+metric = Metric(1, 0, source='host-a', description='requests')
+assert metric.source == 'host-a' and metric.description == 'requests'
+assert metric.to_dict()['source'] == 'host-a'
+assert Settings(metadata='concat').metadata == 'concat'
+assert Settings().metadata == 'first'
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_counter_increase {
+    use crate::models::{FloatMetric, Metric, MetricType};
+    use crate::steps::CounterIncreaseTransformation;
+    use crate::transformations::TransformationStrategy;
+    use crate::warnings::WarningSink;
+
+    fn restarted() -> Vec<Metric> {
+        // The process restarts between 20 and 30
+        vec![
+            Metric::new(10, 0, None),
+            Metric::new(15, 10, None),
+            Metric::new(22, 20, None),
+            Metric::new(4, 30, None),
+            Metric::new(9, 40, None),
+        ]
+    }
+
+    fn values(metrics: &[Metric]) -> Vec<i64> {
+        metrics.iter().map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_increases_across_reset() {
+        let mut warnings = WarningSink::new();
+        let result = CounterIncreaseTransformation::new().apply_with_warnings(&restarted(), &mut warnings).unwrap();
+        assert_eq!(values(&result), vec![5, 7, 4, 5]);
+        assert!(result.iter().all(|m| m.metric_type == Some(MetricType::Gauge)));
+        assert_eq!(warnings.warnings()[0].code, "counter_reset");
+    }
+
+    #[test]
+    fn test_cumulative_is_monotonic() {
+        let result = CounterIncreaseTransformation::new().cumulative(true).apply(&restarted()).unwrap();
+        assert_eq!(values(&result), vec![0, 5, 12, 16, 21]);
+        assert!(result.iter().all(|m| m.metric_type == Some(MetricType::Counter)));
+    }
+
+    #[test]
+    fn test_labels_are_independent() {
+        let metrics = vec![
+            Metric::new(5, 0, Some("a".to_string())),
+            Metric::new(100, 0, Some("b".to_string())),
+            Metric::new(7, 10, Some("a".to_string())),
+            Metric::new(90, 10, Some("b".to_string())),
+        ];
+        let result = CounterIncreaseTransformation::new().apply(&metrics).unwrap();
+        let pairs: Vec<(Option<&str>, i64)> = result.iter().map(|m| (m.label.as_deref(), m.value)).collect();
+        assert_eq!(pairs, vec![(Some("a"), 2), (Some("b"), 90)]);
+
+        let floats = vec![FloatMetric::new(Some(1.5), 0, None), FloatMetric::new(Some(0.5), 10, None)];
+        let result = CounterIncreaseTransformation::new().apply_float(&floats).unwrap();
+        assert_eq!(result[0].value, 0.5);
+    }
+}
+
+#[cfg(test)]
+mod test_gaps {
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{GreaterThanFilter, HourGrouping, LabelFilter, SumAggregation};
+    use crate::steps::{DerivativeTransformation, GapAction, GapDetectionTransformation};
+    use crate::transformations::{AggregationTransformation, MetricPipeline, TimeGroupingTransformation, TransformationStrategy};
+
+    fn reporting() -> Vec<Metric> {
+        vec![
+            Metric::new(1, 0, Some("a".to_string())),
+            Metric::new(2, 60, Some("a".to_string())),
+            Metric::new(3, 600, Some("a".to_string())),
+            Metric::new(4, 0, Some("b".to_string())),
+        ]
+    }
+
+    #[test]
+    fn test_marks_gaps() {
+        let step = GapDetectionTransformation::new(120, GapAction::Mark).unwrap();
+        let result = step.apply(&reporting()).unwrap();
+        let markers: Vec<(Option<&str>, i64)> =
+            result.iter().filter(|m| m.stale).map(|m| (m.label.as_deref(), m.timestamp)).collect();
+        assert_eq!(markers, vec![(Some("a"), 180)]);
+        assert_eq!(result.len(), 5);
+    }
+
+    #[test]
+    fn test_heartbeat_and_error() {
+        let step = GapDetectionTransformation::new(120, GapAction::Mark).unwrap().with_heartbeat(700);
+        let result = step.apply(&reporting()).unwrap();
+        let markers: Vec<(Option<&str>, i64)> =
+            result.iter().filter(|m| m.stale).map(|m| (m.label.as_deref(), m.timestamp)).collect();
+        assert_eq!(markers, vec![(Some("b"), 120), (Some("a"), 180)]);
+
+        let step = GapDetectionTransformation::new(120, GapAction::Error).unwrap();
+        assert!(step.apply(&reporting()).is_err());
+        assert!(GapDetectionTransformation::new(0, GapAction::Mark).is_err());
+        assert!(GapAction::parse("ignore").is_err());
+
+        // A missing float value would read as missing data, not as the series ending
+        let floats = vec![FloatMetric::new(Some(1.0), 0, None), FloatMetric::new(Some(2.0), 500, None)];
+        let step = GapDetectionTransformation::new(120, GapAction::Mark).unwrap();
+        assert!(step.apply_float(&floats).is_err());
+        assert_eq!(step.apply_float(&floats[..1]).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_aggregations_skip_markers() {
+        let metrics = vec![Metric::new(5, 0, None), Metric::stale_marker(60, None), Metric::stale_marker(7200, None)];
+        let sum = AggregationTransformation::new(Box::new(SumAggregation::default()));
+        let result = sum.apply(&metrics).unwrap();
+        assert_eq!((result[0].value, result[0].stale), (5, false));
+        let result = sum.apply(&metrics[1..]).unwrap();
+        assert!(result[0].stale);
+
+        let grouped = TimeGroupingTransformation::new(Box::new(HourGrouping), Box::new(SumAggregation::default()));
+        let mut result = grouped.apply(&metrics).unwrap();
+        result.sort_by_key(|m| m.timestamp);
+        let points: Vec<(i64, i64, bool)> = result.iter().map(|m| (m.timestamp, m.value, m.stale)).collect();
+        assert_eq!(points, vec![(0, 5, false), (7200, 0, true)]);
+    }
+
+    #[test]
+    fn test_value_steps_never_see_markers() {
+        let metrics = vec![
+            Metric::new(10, 0, None),
+            Metric::new(20, 10, None),
+            Metric::stale_marker(20, None),
+            Metric::new(40, 30, None),
+        ];
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_strategy(Box::new(DerivativeTransformation::new(1).unwrap()));
+        let rates: Vec<(i64, i64)> = pipeline.run().unwrap().iter().map(|m| (m.timestamp, m.value)).collect();
+        assert_eq!(rates, vec![(10, 1), (30, 1)]);
+    }
+
+    #[test]
+    fn test_filters_keep_markers_of_kept_series() {
+        let metrics = vec![
+            Metric::new(10, 0, Some("a".to_string())),
+            Metric::stale_marker(60, Some("a".to_string())),
+            Metric::new(10, 0, Some("b".to_string())),
+            Metric::stale_marker(60, Some("b".to_string())),
+        ];
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(GreaterThanFilter::new(5)));
+        pipeline.add_filter(Box::new(LabelFilter::new("a".to_string())));
+        let result = pipeline.run().unwrap();
+        let points: Vec<(i64, bool)> = result.iter().map(|m| (m.timestamp, m.stale)).collect();
+        assert_eq!(points, vec![(0, false), (60, true)]);
+    }
+}
+
+#[cfg(test)]
+mod test_timestamp_unit {
+    use crate::errors::MetricQueryError;
+    use crate::models::Metric;
+    use crate::settings::{Settings, TimestampPrecision};
+    use crate::steps::TimestampUnitTransformation;
+    use crate::transformations::TransformationStrategy;
+
+    #[test]
+    fn test_converts_to_settings_precision() {
+        let step = TimestampUnitTransformation::new(TimestampPrecision::Milliseconds);
+        let metrics = vec![Metric::new(1, 1_700_000_000_999, None), Metric::new(2, -1_000_000_000_001, None)];
+        let result = step.apply(&metrics).unwrap();
+        let timestamps: Vec<i64> = result.iter().map(|m| m.timestamp).collect();
+        assert_eq!(timestamps, vec![1_700_000_000, -1_000_000_001]);
+
+        let settings = Settings { timestamp_precision: TimestampPrecision::Microseconds, ..Settings::DEFAULT };
+        let (_, timestamps) = settings.scope(|| step.apply_columns(&[1], &[1_700_000_000_999])).unwrap();
+        assert_eq!(timestamps, vec![1_700_000_000_999_000]);
+        assert!(TimestampPrecision::Seconds.convert(i64::MAX / 10, TimestampPrecision::Nanoseconds).is_err());
+    }
+
+    #[test]
+    fn test_rejects_implausible_magnitudes() {
+        let step = TimestampUnitTransformation::new(TimestampPrecision::Seconds);
+        let metrics = vec![Metric::new(1, 1_700_000_000, None), Metric::new(2, 1_700_000_000_000, None)];
+        match step.apply(&metrics).unwrap_err() {
+            MetricQueryError::InvalidTimestamp { index, reason, offenders, .. } => {
+                assert_eq!((index, offenders), (1, 1));
+                assert!(reason.contains("'ms'"), "{}", reason);
+            }
+            other => panic!("unexpected error: {}", other),
+        }
+        assert_eq!(TimestampPrecision::guess(1_700_000_000_000_000), Some(TimestampPrecision::Microseconds));
+        assert!(TimestampPrecision::parse("minutes").is_err());
+    }
+
+    #[test]
+    fn test_rejects_seconds_read_as_a_finer_unit() {
+        let step = TimestampUnitTransformation::new(TimestampPrecision::Milliseconds);
+        match step.apply(&[Metric::new(1, 1_700_000_000, None)]).unwrap_err() {
+            MetricQueryError::InvalidTimestamp { reason, .. } => assert!(reason.contains("'s'"), "{}", reason),
+            other => panic!("unexpected error: {}", other),
+        }
+        assert!(!TimestampPrecision::Nanoseconds.plausible(1_700_000_000));
+        assert!(TimestampPrecision::Seconds.plausible(60));
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_describe_python {
+    use crate::analysis::py_describe;
+    use crate::models::Metric;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+    use pyo3::wrap_pyfunction;
+
+    #[test]
+    fn test_describe_metric_collection() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("describe", wrap_pyfunction!(py_describe, py).unwrap()).unwrap();
+            py.run(
+                c"
+summary = describe([Metric(4, 30), Metric(2, 10), Metric(6, 20)], percentiles=[0.5])
+assert (summary.count, summary.min, summary.max, summary.mean) == (3, 2.0, 6.0, 4.0)
+assert summary.stddev == 2.0
+assert (summary.earliest, summary.latest) == (10, 30)
+assert summary.percentile(0.5) == 4.0
+try:
+    describe([])
+    raise AssertionError('empty collection accepted')
+except ValueError:
+    pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_frozen_metric {
+    use crate::models::{FrozenMetric, Metric};
+    use crate::plugin_impls::init_registry;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_frozen_metrics_are_immutable_and_hashable() {
+        pyo3::prepare_freethreaded_python();
+        init_registry();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("FrozenMetric", py.get_type::<FrozenMetric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+frozen = FrozenMetric(5, 10, 'cpu', unit='ms')
+try:
+    frozen.value = 6
+    raise AssertionError('frozen metric was mutated')
+except AttributeError:
+    pass
+assert frozen.value == 5 and frozen.unit == 'ms'
+assert Metric(5, 10, 'cpu', unit='ms').freeze() == frozen
+assert hash(frozen) == hash(frozen.thaw())
+assert len({frozen, Metric(5, 10, 'cpu', unit='ms').freeze(), FrozenMetric(6, 10)}) == 2
+
+thawed = frozen.thaw()
+thawed.value = 7
+assert frozen.value == 5
+assert repr(frozen).startswith('FrozenMetric(value=5')
+
+pipeline = MetricPipeline([frozen, FrozenMetric(1, 20)])
+pipeline.aggregate('sum')
+assert [m.value for m in pipeline.execute()] == [6]
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_time_range_filter {
+    use crate::plugin_impls::TimestampRangeFilter;
+    use crate::plugins::FilterPlugin;
+    use crate::models::{FloatMetric, Metric};
+    use crate::transformations::MetricPipeline;
+
+    #[test]
+    fn test_keeps_half_open_window() {
+        let metrics: Vec<Metric> = (0..10).map(|i| Metric::new(i, i * 10, None)).collect();
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(TimestampRangeFilter::new(20, 50).unwrap()));
+        let timestamps: Vec<i64> = pipeline.run().unwrap().iter().map(|m| m.timestamp).collect();
+        assert_eq!(timestamps, vec![20, 30, 40]);
+
+        let filter = TimestampRangeFilter::new(20, 50).unwrap();
+        assert!(filter.apply_parts(0, 49) && !filter.apply_parts(0, 50));
+        assert!(filter.apply_float(&FloatMetric::new(Some(1.0), 20, None)));
+        assert!(TimestampRangeFilter::new(5, 4).is_err());
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_time_range_filter_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_filter_time_range_takes_ints_and_datetimes() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+from datetime import datetime, timezone
+metrics = [Metric(i, 1_700_000_000 + i * 60) for i in range(10)]
+pipeline = MetricPipeline(metrics)
+pipeline.filter_time_range(1_700_000_060, 1_700_000_180)
+assert [m.value for m in pipeline.execute()] == [1, 2]
+
+start = datetime.fromtimestamp(1_700_000_240, timezone.utc)
+pipeline = MetricPipeline(metrics)
+pipeline.filter_time_range(start, start.replace(tzinfo=None).replace(minute=start.minute + 2))
+assert [m.value for m in pipeline.execute()] == [4, 5]
+try:
+    pipeline.filter_time_range(10, 5)
+    raise AssertionError('inverted range accepted')
+except ValueError:
+    pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_between {
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{BetweenFilter, GreaterThanFilter, LessThanFilter};
+    use crate::plugins::FilterPlugin;
+    use crate::transformations::MetricPipeline;
+
+    fn kept(filter: BetweenFilter) -> Vec<i64> {
+        let metrics: Vec<Metric> = (0..8).map(|i| Metric::new(i, i, None)).collect();
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(filter));
+        pipeline.run().unwrap().iter().map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_bounds_inclusive_and_exclusive() {
+        assert_eq!(kept(BetweenFilter::new(2, 5, true).unwrap()), vec![2, 3, 4, 5]);
+        assert_eq!(kept(BetweenFilter::new(2, 5, false).unwrap()), vec![3, 4]);
+        assert_eq!(kept(BetweenFilter::new(3, 3, true).unwrap()), vec![3]);
+        assert!(BetweenFilter::new(5, 2, true).is_err());
+
+        let filter = BetweenFilter::new(2, 5, false).unwrap();
+        assert!(filter.apply_float(&FloatMetric::new(Some(4.9), 0, None)));
+        assert!(!filter.apply_float(&FloatMetric::new(Some(5.0), 0, None)));
+        assert!(!filter.apply_float(&FloatMetric::new(None, 0, None)));
+    }
+
+    #[test]
+    fn test_float_bounds_and_fixed_point_values() {
+        assert_eq!(kept(BetweenFilter::new(1.5, 4, true).unwrap()), vec![2, 3, 4]);
+        assert_eq!(kept(BetweenFilter::new(2, 4.5, false).unwrap()), vec![3, 4]);
+        assert!(BetweenFilter::new(2.5, 2, true).is_err());
+        assert!(BetweenFilter::new(f64::NAN, 2, true).is_err());
+
+        // 12.34 and 5.00 at scale 2
+        let prices = [Metric::new(1234, 0, None).with_scale(2), Metric::new(500, 1, None).with_scale(2)];
+        let filter = BetweenFilter::new(10, 12.34, true).unwrap();
+        assert_eq!(prices.iter().map(|m| filter.apply(m)).collect::<Vec<_>>(), vec![true, false]);
+        let filter = BetweenFilter::new(5, 12.34, false).unwrap();
+        assert_eq!(prices.iter().map(|m| filter.apply(m)).collect::<Vec<_>>(), vec![false, false]);
+        assert!(BetweenFilter::new(4.99, 5.01, false).unwrap().apply(&prices[1]));
+        // The same reading as the single-bound filters
+        assert!(!GreaterThanFilter::new(100).apply(&prices[0]));
+        assert!(LessThanFilter::from_threshold(12.35.into()).apply(&prices[0]));
+    }
+}
+
+#[cfg(test)]
+mod test_value_in {
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::ValueInFilter;
+    use crate::plugins::FilterPlugin;
+    use crate::transformations::MetricPipeline;
+
+    #[test]
+    fn test_keeps_values_in_set() {
+        let metrics: Vec<Metric> =
+            [200, 404, 200, 500, 503].iter().zip(0..).map(|(&code, i)| Metric::new(code, i, None)).collect();
+        let mut pipeline = MetricPipeline::new(metrics);
+        pipeline.add_filter(Box::new(ValueInFilter::new([500, 503, 404])));
+        let values: Vec<i64> = pipeline.run().unwrap().iter().map(|m| m.value).collect();
+        assert_eq!(values, vec![404, 500, 503]);
+
+        let filter = ValueInFilter::new([1]);
+        assert!(filter.apply_float(&FloatMetric::new(Some(1.0), 0, None)));
+        assert!(!filter.apply_float(&FloatMetric::new(Some(1.5), 0, None)));
+        assert!(!filter.apply_float(&FloatMetric::new(None, 0, None)));
+        assert!(!ValueInFilter::new([]).apply_parts(0, 0));
+    }
+}
+
+#[cfg(test)]
+mod test_negative_label_filters {
+    use crate::models::Metric;
+    use crate::plugin_impls::{create_label_filter, create_label_in_filter, LabelFilter, LabelInFilter};
+    use crate::plugins::FilterPlugin;
+
+    fn metrics() -> Vec<Metric> {
+        vec![
+            Metric::new(1, 0, Some("cpu".to_string())),
+            Metric::new(2, 1, Some("memory".to_string())),
+            Metric::new(3, 2, Some("disk".to_string())),
+            Metric::new(4, 3, None),
+        ]
+    }
+
+    fn kept(filter: &dyn FilterPlugin) -> Vec<i64> {
+        metrics().iter().filter(|m| filter.apply(m)).map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_label_ne() {
+        assert_eq!(kept(&LabelFilter::not_equal("cpu".to_string())), vec![2, 3, 4]);
+        assert_eq!(kept(&LabelFilter::not_equal("cpu".to_string()).keep_unlabeled(false)), vec![2, 3]);
+        assert_eq!(kept(&LabelFilter::new("cpu".to_string())), vec![1]);
+        assert_eq!(kept(&LabelFilter::new("cpu".to_string()).keep_unlabeled(true)), vec![1, 4]);
+        assert_eq!(create_label_filter("label_ne", "cpu".to_string()).unwrap().name(), "label_ne");
+    }
+
+    #[test]
+    fn test_label_not_in() {
+        let labels = vec!["cpu".to_string(), "disk".to_string()];
+        assert_eq!(kept(&LabelInFilter::not_in(labels.clone())), vec![2, 4]);
+        assert_eq!(kept(&LabelInFilter::not_in(labels.clone()).keep_unlabeled(false)), vec![2]);
+        assert_eq!(kept(&LabelInFilter::new(labels.clone())), vec![1, 3]);
+        assert_eq!(create_label_in_filter("label_not_in", labels).unwrap().name(), "label_not_in");
+        assert!(create_label_in_filter("label_out", Vec::new()).is_err());
+    }
+}
+
+#[cfg(test)]
+mod test_tag_filter {
+    use crate::models::Metric;
+    use crate::plugin_impls::{TagFilter, TagMatch, TagValue};
+    use crate::plugins::FilterPlugin;
+
+    fn metrics() -> Vec<Metric> {
+        vec![
+            Metric::new(1, 0, None).with_tag("namespace", "prod").with_tag("pod", "api-7f9c"),
+            Metric::new(2, 0, None).with_tag("namespace", "staging").with_tag("pod", "api-1b2d"),
+            Metric::new(3, 0, None).with_tag("namespace", "prod").with_tag("pod", "worker-0"),
+            Metric::new(4, 0, None),
+        ]
+    }
+
+    fn kept(key: &str, op: &str, value: TagValue) -> Vec<i64> {
+        let filter = TagFilter::new(key, TagMatch::parse(op, value).unwrap());
+        metrics().iter().filter(|m| filter.apply(m)).map(|m| m.value).collect()
+    }
+
+    fn one(value: &str) -> TagValue {
+        TagValue::One(value.to_string())
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(kept("namespace", "eq", one("prod")), vec![1, 3]);
+        assert_eq!(kept("namespace", "ne", one("prod")), vec![2, 4]);
+        let namespaces = TagValue::Many(vec!["staging".to_string(), "dev".to_string()]);
+        assert_eq!(kept("namespace", "in", namespaces), vec![2]);
+        assert_eq!(kept("zone", "eq", one("eu")), Vec::<i64>::new());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex() {
+        assert_eq!(kept("pod", "regex", one("api-.*")), vec![1, 2]);
+        // Regexes match the whole value
+        assert_eq!(kept("pod", "regex", one("api")), Vec::<i64>::new());
+        assert!(TagMatch::parse("regex", one("(")).is_err());
+    }
+
+    #[cfg(not(feature = "regex"))]
+    #[test]
+    fn test_regex_needs_the_feature() {
+        assert!(TagMatch::parse("regex", one("api-.*")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_operators() {
+        assert!(TagMatch::parse("like", one("a")).is_err());
+        assert!(TagMatch::parse("eq", TagValue::Many(Vec::new())).is_err());
+    }
+}
+
+#[cfg(all(test, feature = "python", feature = "regex"))]
+mod test_tag_filter_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_filter_by_tag() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+metrics = [
+    Metric(1, 0, tags={'namespace': 'prod', 'pod': 'api-0'}),
+    Metric(2, 0, tags={'namespace': 'dev', 'pod': 'api-1'}),
+    {'value': 3, 'timestamp': 0, 'tags': {'namespace': 'prod', 'pod': 'db-0'}},
+]
+pipeline = MetricPipeline(metrics)
+pipeline.filter_by_tag('namespace', 'in', ['prod', 'qa'])
+pipeline.filter_by_tag('pod', 'regex', 'api-\\\\d+')
+result = pipeline.execute()
+assert [m.value for m in result] == [1], result
+assert result[0].tags == {'namespace': 'prod', 'pod': 'api-0'}
+assert result[0].to_dict()['tags']['pod'] == 'api-0'
+assert hash(Metric(1, 0, tags={'a': 'b'})) != hash(Metric(1, 0))
+try:
+    pipeline.filter_by_tag('pod', 'eq', ['a', 'b'])
+    raise AssertionError('list accepted for eq')
+except ValueError:
+    pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_expr {
+    use crate::errors::MetricQueryError;
+    use crate::expr::{Expr, MAX_EXPR_DEPTH};
+    use crate::models::{FloatMetric, Metric};
+
+    fn metrics() -> Vec<Metric> {
+        let mut with_field = Metric::new(50, 3, Some("mem".to_string()));
+        with_field.fields.insert("bytes_in".to_string(), 7);
+        vec![
+            Metric::new(5, 0, Some("cpu".to_string())),
+            Metric::new(20, 1, Some("cpu".to_string())).with_tag("host", "a"),
+            Metric::new(200, 2, Some("cpu".to_string())),
+            with_field,
+            Metric::new(60, 4, None).with_tag("host", "b"),
+        ]
+    }
+
+    fn kept(source: &str) -> Vec<i64> {
+        let expr = Expr::parse(source).unwrap();
+        metrics().iter().filter(|m| expr.matches(m)).map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_comparisons_and_connectives() {
+        assert_eq!(kept("value > 10 and value <= 100 and label == 'cpu'"), vec![20]);
+        assert_eq!(kept("value < 10 or value >= 200"), vec![5, 200]);
+        assert_eq!(kept("not (label == \"cpu\") and value != 60"), vec![50]);
+        // `and` binds tighter than `or`
+        assert_eq!(kept("label == 'mem' or label == 'cpu' and value > 100"), vec![200, 50]);
+        assert_eq!(kept("timestamp >= 3"), vec![50, 60]);
+        assert_eq!(kept("value > 19.5 and value < 20.5"), vec![20]);
+        assert_eq!(kept("(value) > (100) or ((label) == 'mem')"), vec![200, 50]);
+    }
+
+    #[test]
+    fn test_nesting_is_limited() {
+        let nested = |depth: usize| format!("{}value > 1{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(Expr::parse(&nested(MAX_EXPR_DEPTH)).is_ok());
+        assert!(Expr::parse(&format!("{}value > 1", "not ".repeat(MAX_EXPR_DEPTH))).is_ok());
+
+        // Deep enough to overflow the stack without the limit
+        for source in [nested(100_000), format!("{}value > 1", "not ".repeat(100_000)), "(".repeat(100_000)] {
+            match Expr::parse(&source).unwrap_err() {
+                MetricQueryError::InvalidFilter { reason } => assert!(reason.contains("nests deeper"), "{}", reason),
+                other => panic!("unexpected error {:?}", other),
+            }
+        }
+
+        // Long flat chains aren't nesting and evaluate without deep recursion
+        let chain = vec!["value == 1"; 100_000].join(" or ");
+        assert_eq!(kept(&chain), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn test_membership_tags_and_fields() {
+        assert_eq!(kept("label in ('mem', 'disk')"), vec![50]);
+        assert_eq!(kept("value not in (5, 200)"), vec![20, 50, 60]);
+        assert_eq!(kept("tags.host == 'b'"), vec![60]);
+        assert_eq!(kept("tags.host != 'b'"), vec![5, 20, 200, 50]);
+        assert_eq!(kept("fields.bytes_in > 0"), vec![50]);
+    }
+
+    #[test]
+    fn test_missing_attributes_are_none() {
+        assert_eq!(kept("label == None"), vec![60]);
+        assert_eq!(kept("tags.host != None and value > 30"), vec![60]);
+        // Ordering comparisons with a missing attribute are false
+        assert_eq!(kept("label < 'z'"), vec![5, 20, 200, 50]);
+
+        let expr = Expr::parse("value == None or value > 1.5").unwrap();
+        assert!(expr.matches_float(&FloatMetric::new(None, 0, None)));
+        assert!(expr.matches_float(&FloatMetric::new(Some(2.0), 0, None)));
+        assert!(!expr.matches_float(&FloatMetric::new(Some(1.0), 0, None)));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        for source in [
+            "",
+            "value >",
+            "value = 3",
+            "value > 'a'",
+            "label > 3",
+            "value < None",
+            "colour == 'red'",
+            "value > 1 and",
+            "(value > 1",
+            "value > 1 value",
+            "label in ('a', 2)",
+            "label == 'unterminated",
+            "value",
+            "(value > 1) > 2",
+            "value > (label == 'a')",
+        ] {
+            let error = Expr::parse(source).unwrap_err();
+            assert!(matches!(error, MetricQueryError::InvalidFilter { .. }), "{}: {}", source, error);
+        }
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_expr_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_filter_expr() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+metrics = [Metric(5, 0, 'cpu'), Metric(20, 1, 'cpu'), Metric(50, 2, 'mem'), Metric(500, 3, 'cpu')]
+pipeline = MetricPipeline(metrics)
+pipeline.filter_expr(\"value > 10 and value <= 100 and label == 'cpu'\")
+assert [m.value for m in pipeline.execute()] == [20]
+try:
+    pipeline.filter_expr('label > 3')
+    raise AssertionError('type error accepted')
+except ValueError as error:
+    assert 'Cannot compare' in str(error), error
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(test)]
+mod test_timestamp_filters {
+    use crate::models::{FloatMetric, Metric};
+    use crate::plugin_impls::{HourOfDayFilter, TimestampComparison, TimestampFilter, WeekdayFilter};
+    use crate::plugins::FilterPlugin;
+    use crate::settings::Settings;
+    use crate::time_range::parse_timezone;
+
+    // Monday 2024-01-01 00:00 UTC
+    const MONDAY: i64 = 1_704_067_200;
+    const HOUR: i64 = 3_600;
+
+    fn kept(filter: &dyn FilterPlugin, timestamps: &[i64]) -> Vec<i64> {
+        timestamps.iter().copied().filter(|&ts| filter.apply(&Metric::new(0, ts, None))).collect()
+    }
+
+    #[test]
+    fn test_comparisons() {
+        let timestamps = [10, 20, 30];
+        let filter = |op| TimestampFilter::new(TimestampComparison::parse(op).unwrap(), 20);
+        assert_eq!(kept(&filter("before"), &timestamps), vec![10]);
+        assert_eq!(kept(&filter("after"), &timestamps), vec![30]);
+        assert_eq!(kept(&filter("at"), &timestamps), vec![20]);
+        assert_eq!(filter("after").name(), "after");
+        assert!(filter("at").apply_float(&FloatMetric::new(None, 20, None)));
+        assert!(TimestampComparison::parse("since").is_err());
+    }
+
+    #[test]
+    fn test_weekdays_and_business_hours() {
+        let week: Vec<i64> = (0..7).map(|day| MONDAY + day * 24 * HOUR + 10 * HOUR).collect();
+        let weekdays = WeekdayFilter::parse(&["mon", "Tuesday", "fri"]).unwrap();
+        assert_eq!(kept(&weekdays, &week), vec![week[0], week[1], week[4]]);
+        assert!(WeekdayFilter::parse(&["someday"]).is_err());
+
+        let hours: Vec<i64> = [0, 8, 9, 16, 17, 23].iter().map(|h| MONDAY + h * HOUR).collect();
+        let business = HourOfDayFilter::new(9, 17).unwrap();
+        assert_eq!(kept(&business, &hours), vec![hours[2], hours[3]]);
+        let night = HourOfDayFilter::new(22, 6).unwrap();
+        assert_eq!(kept(&night, &hours), vec![hours[0], hours[5]]);
+        assert_eq!(kept(&HourOfDayFilter::new(0, 24).unwrap(), &hours), hours);
+        assert!(HourOfDayFilter::new(24, 1).is_err());
+        assert!(HourOfDayFilter::new(0, 25).is_err());
+    }
+
+    #[test]
+    fn test_uses_settings_timezone() {
+        // Monday 00:00 UTC is Sunday 19:00 in New York
+        let settings = Settings { timezone: Some(parse_timezone("America/New_York").unwrap()), ..Settings::DEFAULT };
+        settings.scope(|| {
+            assert_eq!(kept(&WeekdayFilter::parse(&["sun"]).unwrap(), &[MONDAY]), vec![MONDAY]);
+            assert_eq!(kept(&HourOfDayFilter::new(19, 20).unwrap(), &[MONDAY]), vec![MONDAY]);
+        });
+        assert!(kept(&WeekdayFilter::parse(&["sun"]).unwrap(), &[MONDAY]).is_empty());
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_timestamp_filters_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_business_hours() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+import datetime
+monday = datetime.datetime(2024, 1, 1, tzinfo=datetime.timezone.utc)
+metrics = [Metric(i, int((monday + datetime.timedelta(hours=h)).timestamp())) for i, h in enumerate([8, 10, 16, 17, 24 * 5 + 10])]
+pipeline = MetricPipeline(metrics)
+pipeline.filter_weekdays(['mon', 'tue', 'wed', 'thu', 'fri'])
+pipeline.filter_hours(9, 17)
+assert [m.value for m in pipeline.execute()] == [1, 2]
+
+pipeline = MetricPipeline(metrics)
+pipeline.filter_timestamp('after', monday + datetime.timedelta(hours=16))
+assert [m.value for m in pipeline.execute()] == [3, 4]
+for bad in [lambda: pipeline.filter_weekdays(['caturday']), lambda: pipeline.filter_hours(9, 25),
+            lambda: pipeline.filter_timestamp('during', 0)]:
+    try:
+        bad()
+        raise AssertionError('invalid filter accepted')
+    except ValueError:
+        pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_filter_py {
+    use crate::models::{FloatMetric, Metric};
+    use crate::settings::Settings;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+    use std::time::Duration;
+
+    #[test]
+    fn test_filter_py() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+metrics = [Metric(i, i, 'even' if i % 2 == 0 else None) for i in range(2500)]
+calls = []
+def multiple_of_seven(m):
+    calls.append(m.value)
+    return m.value % 7 == 0
+pipeline = MetricPipeline(metrics)
+pipeline.filter_py(multiple_of_seven)
+pipeline.filter_py(lambda m: m.label == 'even')
+assert [m.value for m in pipeline.execute()] == list(range(0, 2500, 14))
+assert calls == list(range(2500))
+
+pipeline = MetricPipeline(metrics[:4])
+pipeline.filter_py(lambda m: 1 / m.value > 0)
+assert [m.value for m in pipeline.execute()] == [1, 2, 3]
+
+calls.clear()
+def failing(m):
+    calls.append(m.value)
+    return 1 / (m.value - 2) > 0
+pipeline = MetricPipeline(metrics[:6])
+pipeline.filter_py(failing, on_error='raise')
+try:
+    pipeline.execute()
+    raise AssertionError('exception swallowed')
+except Exception as error:
+    assert 'ZeroDivisionError' in str(error), error
+assert calls == [0, 1, 2], calls
+
+for bad in [lambda: pipeline.filter_py(3), lambda: pipeline.filter_py(failing, on_error='ignore')]:
+    try:
+        bad()
+        raise AssertionError('invalid filter accepted')
+    except (TypeError, ValueError):
+        pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_filter_py_on_float_metrics() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("FloatMetric", py.get_type::<FloatMetric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+seen = []
+def positive(m):
+    seen.append(type(m).__name__)
+    return m.value > 0
+pipeline = MetricPipeline([])
+pipeline.filter_py(positive)
+metrics = [FloatMetric(v, i) for i, v in enumerate([0.4, -0.4, 0.0] * 1000)]
+assert [m.value for m in pipeline.execute_float(metrics)] == [0.4] * 1000
+assert seen == ['FloatMetric'] * 3000
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+
+    #[test]
+    fn test_filter_py_under_a_timeout() {
+        pyo3::prepare_freethreaded_python();
+        let mut pipeline = MetricPipeline::new((0..5).map(|i| Metric::new(i, i, None)).collect());
+        Python::with_gil(|py| {
+            let predicate = py.eval(c"lambda m: m.value > 2", None, None).unwrap();
+            pipeline.filter_py(&predicate, "skip").unwrap();
+        });
+        pipeline.set_settings(Some(Settings { plugin_timeout: Some(Duration::from_secs(5)), ..Settings::DEFAULT }));
+        assert_eq!(pipeline.run().unwrap().iter().map(|m| m.value).collect::<Vec<_>>(), vec![3, 4]);
+    }
+}
+
+#[cfg(test)]
+mod test_sample_every {
+    use crate::models::{FloatMetric, Metric};
+    use crate::steps::SampleEveryTransformation;
+    use crate::transformations::{MetricPipeline, TransformationStrategy};
+
+    #[test]
+    fn test_keeps_every_nth_from_the_first() {
+        let metrics: Vec<Metric> = (0..10).map(|i| Metric::new(i, i * 10, None)).collect();
+        let step = SampleEveryTransformation::new(3).unwrap();
+        assert_eq!(step.apply(&metrics).unwrap().iter().map(|m| m.value).collect::<Vec<_>>(), vec![0, 3, 6, 9]);
+        assert_eq!(step.name(), "sample_every(3)");
+
+        let values: Vec<i64> = (0..10).collect();
+        let timestamps: Vec<i64> = values.iter().map(|v| v * 10).collect();
+        assert_eq!(step.apply_columns(&values, &timestamps).unwrap(), (vec![0, 3, 6, 9], vec![0, 30, 60, 90]));
+
+        let floats: Vec<FloatMetric> = (0..4).map(|i| FloatMetric::new(Some(i as f64), i, None)).collect();
+        assert_eq!(step.apply_float(&floats).unwrap().len(), 2);
+
+        let all = SampleEveryTransformation::new(1).unwrap();
+        assert_eq!(all.apply(&metrics).unwrap().len(), 10);
+        assert!(SampleEveryTransformation::new(0).is_err());
+    }
+
+    #[test]
+    fn test_samples_before_aggregating() {
+        let mut pipeline = MetricPipeline::new((0..1000).map(|i| Metric::new(i, i, None)).collect());
+        pipeline.add_strategy(Box::new(SampleEveryTransformation::new(100).unwrap()));
+        let values: Vec<i64> = pipeline.run().unwrap().iter().map(|m| m.value).collect();
+        assert_eq!(values, (0..1000).step_by(100).collect::<Vec<_>>());
+    }
+}
+
+#[cfg(test)]
+mod test_sample_fraction {
+    use crate::models::{FloatMetric, Metric};
+    use crate::steps::SampleFractionTransformation;
+    use crate::transformations::TransformationStrategy;
+
+    fn metrics(count: i64) -> Vec<Metric> {
+        (0..count).map(|i| Metric::new(i, i, None)).collect()
+    }
+
+    fn kept(step: &SampleFractionTransformation, count: i64) -> Vec<i64> {
+        step.apply(&metrics(count)).unwrap().iter().map(|m| m.value).collect()
+    }
+
+    #[test]
+    fn test_seeded_samples_are_reproducible() {
+        let step = SampleFractionTransformation::new(0.3, Some(42)).unwrap();
+        let sample = kept(&step, 10_000);
+        assert_eq!(kept(&step, 10_000), sample);
+        assert!((2_700..3_300).contains(&sample.len()), "{}", sample.len());
+        assert_ne!(kept(&SampleFractionTransformation::new(0.3, Some(43)).unwrap(), 10_000), sample);
+
+        // Every execution mode keeps the same positions
+        let values: Vec<i64> = (0..10_000).collect();
+        assert_eq!(step.apply_columns(&values, &values).unwrap().0, sample);
+        let floats: Vec<FloatMetric> = (0..10_000).map(|i| FloatMetric::new(Some(i as f64), i, None)).collect();
+        let float_sample: Vec<i64> = step.apply_float(&floats).unwrap().iter().map(|m| m.timestamp).collect();
+        assert_eq!(float_sample, sample);
+    }
+
+    #[test]
+    fn test_bounds_and_unseeded_runs() {
+        assert!(kept(&SampleFractionTransformation::new(0.0, None).unwrap(), 100).is_empty());
+        assert_eq!(kept(&SampleFractionTransformation::new(1.0, None).unwrap(), 100).len(), 100);
+        for p in [-0.1, 1.5, f64::NAN] {
+            assert!(SampleFractionTransformation::new(p, None).is_err());
+        }
+
+        let unseeded = SampleFractionTransformation::new(0.5, None).unwrap();
+        assert_ne!(kept(&unseeded, 1_000), kept(&unseeded, 1_000));
+    }
+}
+
+#[cfg(all(test, feature = "python"))]
+mod test_sampling_python {
+    use crate::models::Metric;
+    use crate::transformations::MetricPipeline;
+    use pyo3::prelude::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_sample_every_and_fraction() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let globals = PyDict::new(py);
+            globals.set_item("Metric", py.get_type::<Metric>()).unwrap();
+            globals.set_item("MetricPipeline", py.get_type::<MetricPipeline>()).unwrap();
+            py.run(
+                c"
+metrics = [Metric(i, i) for i in range(1000)]
+pipeline = MetricPipeline(metrics)
+pipeline.sample_every(250)
+assert [m.value for m in pipeline.execute()] == [0, 250, 500, 750]
+
+def sample(seed):
+    pipeline = MetricPipeline(metrics)
+    pipeline.sample_fraction(0.1, seed=seed)
+    return [m.value for m in pipeline.execute()]
+assert sample(7) == sample(7)
+assert 50 < len(sample(7)) < 150
+for bad in [lambda: pipeline.sample_every(0), lambda: pipeline.sample_fraction(2.0)]:
+    try:
+        bad()
+        raise AssertionError('invalid sampling accepted')
+    except ValueError:
+        pass
+",
+                Some(&globals),
+                None,
+            )
+            .unwrap();
+        });
+    }
+}
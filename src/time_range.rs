@@ -0,0 +1,240 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use chrono::{DateTime, NaiveTime, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::settings::Settings;
+
+/// A half-open window of Unix timestamps, `start <= t < end`.
+///
+/// Built with helpers such as `last_hours(24)`, `today("Europe/Berlin")` or
+/// `between(start, end)` so callers don't hand-compute epoch boundaries.
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeRange {
+    /// First timestamp in the range (inclusive)
+    pub start: i64,
+    /// End of the range (exclusive)
+    pub end: i64,
+}
+
+/// Parse an IANA timezone name such as "UTC" or "America/New_York"
+pub fn parse_timezone(tz: &str) -> MetricQueryResult<Tz> {
+    tz.parse::<Tz>().map_err(|_| MetricQueryError::OperationFailed {
+        operation: "timezone".to_string(),
+        reason: format!("Unknown timezone: {}", tz),
+    })
+}
+
+/// Parse a duration such as "30s", "5m", "2h", "1d" or "1w" into seconds; a bare number is seconds
+pub fn parse_duration(duration: &str) -> MetricQueryResult<i64> {
+    let invalid = || MetricQueryError::OperationFailed {
+        operation: "duration".to_string(),
+        reason: format!("Invalid duration: {:?}. Expected e.g. '30s', '5m', '2h', '1d' or '1w'", duration),
+    };
+    let trimmed = duration.trim();
+    let split = trimmed.find(|c: char| !c.is_ascii_digit()).unwrap_or(trimmed.len());
+    let (amount, unit) = trimmed.split_at(split);
+    let amount: i64 = amount.parse().map_err(|_| invalid())?;
+    let multiplier = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 3_600,
+        "d" => 86_400,
+        "w" => 604_800,
+        _ => return Err(invalid()),
+    };
+    amount.checked_mul(multiplier).ok_or_else(invalid)
+}
+
+/// Parse an ISO-8601 instant such as "2024-03-01T10:15:30Z" or "2024-03-01T11:15:30.5+01:00"
+/// into a timestamp at the `Settings` timestamp precision, dropping finer fractions.
+/// Without an offset the time is taken to be UTC.
+pub fn parse_iso_timestamp(iso: &str) -> MetricQueryResult<i64> {
+    let invalid = |reason: String| MetricQueryError::OperationFailed { operation: "timestamp".to_string(), reason };
+    let instant = match DateTime::parse_from_rfc3339(iso) {
+        Ok(aware) => aware.with_timezone(&Utc),
+        Err(_) => chrono::NaiveDateTime::parse_from_str(iso, "%Y-%m-%dT%H:%M:%S%.f")
+            .map_err(|e| invalid(format!("Invalid ISO-8601 timestamp {:?}: {}", iso, e)))?
+            .and_utc(),
+    };
+    let ticks = Settings::current().timestamp_precision.ticks_per_second();
+    instant
+        .timestamp()
+        .checked_mul(ticks)
+        .and_then(|whole| whole.checked_add(i64::from(instant.timestamp_subsec_nanos()) / (1_000_000_000 / ticks)))
+        .ok_or_else(|| invalid(format!("{} is out of range at the timestamp precision", iso)))
+}
+
+/// Format a timestamp at the `Settings` timestamp precision as an RFC 3339 instant in UTC,
+/// with as many fractional digits as it needs
+pub fn format_iso_timestamp(timestamp: i64) -> MetricQueryResult<String> {
+    let precision = Settings::current().timestamp_precision;
+    let (seconds, ticks) = precision.split(timestamp);
+    let nanos = ticks * (1_000_000_000 / precision.ticks_per_second());
+    DateTime::<Utc>::from_timestamp(seconds, nanos as u32)
+        .map(|instant| instant.to_rfc3339_opts(SecondsFormat::AutoSi, true))
+        .ok_or_else(|| MetricQueryError::OperationFailed {
+            operation: "timestamp".to_string(),
+            reason: format!("timestamp {} is out of range", timestamp),
+        })
+}
+
+impl TimeRange {
+    /// Create a range from `start` (inclusive) to `end` (exclusive)
+    pub fn new(start: i64, end: i64) -> MetricQueryResult<Self> {
+        if start > end {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "time_range".to_string(),
+                reason: format!("start {} is after end {}", start, end),
+            });
+        }
+        Ok(Self { start, end })
+    }
+
+    /// The `seconds` leading up to (and including) `end`
+    pub fn trailing(seconds: i64, end: i64) -> MetricQueryResult<Self> {
+        Self::new(end.saturating_sub(seconds), end.saturating_add(1))
+    }
+
+    /// The last `seconds` seconds up to now
+    pub fn last_seconds(seconds: i64) -> MetricQueryResult<Self> {
+        Self::trailing(seconds, Utc::now().timestamp())
+    }
+
+    /// The last `minutes` minutes up to now
+    pub fn last_minutes(minutes: i64) -> MetricQueryResult<Self> {
+        Self::last_seconds(minutes.saturating_mul(60))
+    }
+
+    /// The last `hours` hours up to now
+    pub fn last_hours(hours: i64) -> MetricQueryResult<Self> {
+        Self::last_seconds(hours.saturating_mul(3600))
+    }
+
+    /// The last `days` days up to now
+    pub fn last_days(days: i64) -> MetricQueryResult<Self> {
+        Self::last_seconds(days.saturating_mul(86_400))
+    }
+
+    /// The calendar day in `tz` that contains `timestamp`, from local midnight to the next
+    pub fn day_containing(timestamp: i64, tz: Tz) -> MetricQueryResult<Self> {
+        let instant = DateTime::from_timestamp(timestamp, 0).ok_or_else(|| MetricQueryError::OperationFailed {
+            operation: "time_range".to_string(),
+            reason: format!("timestamp {} is out of range", timestamp),
+        })?;
+        let date = instant.with_timezone(&tz).date_naive();
+        let local_midnight = |date: chrono::NaiveDate| {
+            let midnight = date.and_time(NaiveTime::MIN);
+            // Where a DST change skips midnight, the day starts at the first valid instant
+            tz.from_local_datetime(&midnight)
+                .earliest()
+                .unwrap_or_else(|| tz.from_utc_datetime(&midnight))
+                .timestamp()
+        };
+        let next = date.succ_opt().unwrap_or(date);
+        Self::new(local_midnight(date), local_midnight(next))
+    }
+
+    /// Today in the named timezone
+    pub fn today(tz: &str) -> MetricQueryResult<Self> {
+        Self::day_containing(Utc::now().timestamp(), parse_timezone(tz)?)
+    }
+
+    /// The window between two instants, `start` inclusive and `end` exclusive
+    pub fn between<A: TimeZone, B: TimeZone>(start: DateTime<A>, end: DateTime<B>) -> MetricQueryResult<Self> {
+        Self::new(start.timestamp(), end.timestamp())
+    }
+
+    /// Whether `timestamp` falls in the range
+    pub fn contains(&self, timestamp: i64) -> bool {
+        self.start <= timestamp && timestamp < self.end
+    }
+
+    /// Length of the range in seconds, saturating at `i64::MAX` for ranges wider than that
+    pub fn duration_seconds(&self) -> i64 {
+        self.end.saturating_sub(self.start)
+    }
+}
+
+/// Read a Python `int` (Unix seconds) or `datetime` as a Unix timestamp.
+/// Naive datetimes are taken to be UTC.
+#[cfg(feature = "python")]
+pub(crate) fn extract_timestamp(value: &Bound<'_, PyAny>) -> PyResult<i64> {
+    if let Ok(timestamp) = value.extract::<i64>() {
+        return Ok(timestamp);
+    }
+    if let Ok(aware) = value.extract::<DateTime<chrono::FixedOffset>>() {
+        return Ok(aware.timestamp());
+    }
+    let naive = value.extract::<chrono::NaiveDateTime>().map_err(|_| {
+        pyo3::exceptions::PyTypeError::new_err("expected an int timestamp or a datetime")
+    })?;
+    Ok(naive.and_utc().timestamp())
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl TimeRange {
+    #[new]
+    fn py_new(start: &Bound<'_, PyAny>, end: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Ok(Self::new(extract_timestamp(start)?, extract_timestamp(end)?)?)
+    }
+
+    /// The last `seconds` seconds up to now
+    #[staticmethod]
+    #[pyo3(name = "last_seconds")]
+    fn py_last_seconds(seconds: i64) -> PyResult<Self> {
+        Ok(Self::last_seconds(seconds)?)
+    }
+
+    /// The last `minutes` minutes up to now
+    #[staticmethod]
+    #[pyo3(name = "last_minutes")]
+    fn py_last_minutes(minutes: i64) -> PyResult<Self> {
+        Ok(Self::last_minutes(minutes)?)
+    }
+
+    /// The last `hours` hours up to now
+    #[staticmethod]
+    #[pyo3(name = "last_hours")]
+    fn py_last_hours(hours: i64) -> PyResult<Self> {
+        Ok(Self::last_hours(hours)?)
+    }
+
+    /// The last `days` days up to now
+    #[staticmethod]
+    #[pyo3(name = "last_days")]
+    fn py_last_days(days: i64) -> PyResult<Self> {
+        Ok(Self::last_days(days)?)
+    }
+
+    /// Today, from local midnight to the next, in the named timezone
+    #[staticmethod]
+    #[pyo3(name = "today", signature = (tz="UTC"))]
+    fn py_today(tz: &str) -> PyResult<Self> {
+        Ok(Self::today(tz)?)
+    }
+
+    /// The window between two datetimes (or int timestamps), `end` exclusive
+    #[staticmethod]
+    #[pyo3(name = "between")]
+    fn py_between(start: &Bound<'_, PyAny>, end: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Self::py_new(start, end)
+    }
+
+    /// Whether `timestamp` falls in the range
+    #[pyo3(name = "contains")]
+    fn py_contains(&self, timestamp: i64) -> bool {
+        self.contains(timestamp)
+    }
+
+    fn __contains__(&self, timestamp: i64) -> bool {
+        self.contains(timestamp)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("TimeRange(start={}, end={})", self.start, self.end)
+    }
+}
@@ -1,21 +1,196 @@
-use pyo3::prelude::*;
-use std::collections::HashMap;
+use smallvec::SmallVec;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
 
+use crate::accuracy::Accuracy;
+use crate::analysis::{describe_metrics, MetricSummary, ResultSummary, SummaryBuilder};
+use crate::decimal::align_scales;
 use crate::errors::{MetricQueryError, MetricQueryResult};
-use crate::models::Metric;
+use crate::graph::PipelineGraph;
+use crate::settings::Settings;
+#[cfg(feature = "python")]
+use crate::settings::TimestampPrecision;
+use crate::models::{Exemplar, FloatMetric, Metric, MetricType, SketchMetric};
+use crate::plugin_impls::aggregate_float_values;
+use crate::plugins::{take_filter_error, FilterPlugin, AggregationPlugin, GroupPoint, TimeGroupingPlugin};
+use crate::stats::{RunStats, StatsRecorder};
+use crate::units::{common_unit, partly_unitless};
+use crate::warnings::{PipelineWarning, WarningSink};
+use std::sync::{Mutex, PoisonError};
+
+// Everything below is only needed by the Python-facing builder methods
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use pyo3::types::{PyDict, PyList};
+#[cfg(feature = "python")]
+use crate::analysis::DEFAULT_PERCENTILES;
+#[cfg(feature = "python")]
 use crate::plugins::{
-    FilterPlugin, AggregationPlugin, TimeGroupingPlugin,
-    with_registry
+    create_registered_filter, raise_on_error, require_callable, with_registry, FilterArg, PyCallableFilter,
+};
+#[cfg(feature = "python")]
+use crate::sandbox::call_plugin;
+#[cfg(feature = "python")]
+use crate::warnings::emit_python_warnings;
+#[cfg(feature = "python")]
+use crate::compiled::CompiledPipeline;
+#[cfg(feature = "python")]
+use crate::models::dataset::PipelineInput;
+#[cfg(feature = "python")]
+use crate::time_range::{extract_timestamp, parse_duration, parse_timezone};
+#[cfg(feature = "python")]
+use crate::validation::{
+    TimestampRules, TimestampValidationMode, TimestampValidationTransformation,
+    DEFAULT_MAX_FUTURE_SECONDS,
 };
-use crate::plugin_impls::{LabelFilter, LabelInFilter};
+#[cfg(feature = "python")]
+use crate::steps::{
+    AnomalyDetectionTransformation, AnomalyMethod, AnomalyOutput, Assertion, AssertionTransformation,
+    ConvertUnitTransformation, CounterIncreaseTransformation, CrossingDirection,
+    CrossingTransformation, DeduplicateTransformation, DerivativeTransformation,
+    DuplicateStrategy, FieldFilter, ForecastMethod, ForecastTransformation, GapAction, GapDetectionTransformation,
+    HistogramBuckets,
+    HistogramTransformation, LatestTransformation, NormalizeMethod, NormalizeTransformation,
+    PercentChangeTransformation, RescaleTransformation, ResampleFill, ResampleTransformation, RollingPercentileTransformation, RollingReduceTransformation,
+    RollingReduction, RollingWindow, SampleEveryTransformation, SampleFractionTransformation, TopKSeriesTransformation,
+    resample_aggregation_name, SeasonalDecompositionTransformation, SelectFieldTransformation, SmoothingParams, TapBatch, TapTransformation, TimezoneDirection,
+    TimestampUnitTransformation, TimezoneShiftTransformation, TrendOutput, TrendTransformation,
+};
+#[cfg(feature = "python")]
+use crate::plugin_impls::{
+    with_accuracy, AvgAggregation, BetweenFilter, ExprFilter, LabelFilter, LabelInFilter, MissingValueAggregation, MissingValuePolicy,
+    HourOfDayFilter, OverflowPolicy, PercentileAggregation, RoundingMode, SumAggregation, TagFilter, TagMatch, Threshold,
+    TagValue, TimestampComparison, TimestampFilter, TimestampRangeFilter, ValueInFilter, WeekdayFilter,
+};
+
+/// Clones a step behind a `Box<dyn TransformationStrategy>`, so pipelines can be
+/// copied; implemented for every `Clone` step
+pub trait TransformationStrategyClone {
+    fn clone_box(&self) -> Box<dyn TransformationStrategy>;
+}
+
+impl<T: TransformationStrategy + Clone + 'static> TransformationStrategyClone for T {
+    fn clone_box(&self) -> Box<dyn TransformationStrategy> {
+        Box::new(self.clone())
+    }
+}
 
 /// Trait for transformation strategies
-pub trait TransformationStrategy: Send + Sync {
+pub trait TransformationStrategy: TransformationStrategyClone + Send + Sync {
+    /// Name of the step, reported in errors raised while it runs
+    fn name(&self) -> String {
+        "custom".to_string()
+    }
+    
     /// Apply the transformation to a collection of metrics
     fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>>;
+    
+    /// Apply the transformation, reporting non-fatal issues to `warnings`.
+    ///
+    /// The default never warns; strategies that drop or skip data override it.
+    fn apply_with_warnings(&self, metrics: &[Metric], _warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        self.apply(metrics)
+    }
+    
+    /// Apply the transformation, handing each output metric to `sink` instead of
+    /// returning them, and return how many were handed over. `run_summary` runs its
+    /// last step this way to fold the output without keeping it.
+    ///
+    /// The default builds the output with `apply_with_warnings` first; steps that
+    /// select from their input, like filters, override it to skip that copy.
+    fn apply_into(
+        &self,
+        metrics: &[Metric],
+        warnings: &mut WarningSink,
+        sink: &mut dyn FnMut(&Metric) -> MetricQueryResult<()>,
+    ) -> MetricQueryResult<usize> {
+        let result = self.apply_with_warnings(metrics, warnings)?;
+        result.iter().try_for_each(sink)?;
+        Ok(result.len())
+    }
+    
+    /// Apply the transformation to parallel value/timestamp columns.
+    ///
+    /// The default materializes unlabeled metrics and delegates to `apply`; the
+    /// built-in strategies override it to work on the columns directly.
+    fn apply_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        let metrics: Vec<Metric> = values
+            .iter()
+            .zip(timestamps)
+            .map(|(&value, &timestamp)| Metric::new(value, timestamp, None))
+            .collect();
+        
+        Ok(self.apply(&metrics)?.into_iter().map(|m| (m.value, m.timestamp)).unzip())
+    }
+    
+    /// Apply the transformation to float metrics.
+    ///
+    /// Strategies that only make sense for integer values keep this default, which
+    /// reports the step as unsupported instead of silently rounding.
+    fn apply_float(&self, _metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        Err(MetricQueryError::OperationFailed {
+            operation: "apply_float".to_string(),
+            reason: "this transformation does not support float metrics".to_string(),
+        })
+    }
+    
+    /// Whether the step's aggregation is computed exactly or from a sketch; `None` for
+    /// steps without that choice
+    fn accuracy(&self) -> Option<Accuracy> {
+        None
+    }
+    
+    /// The step with an `Accuracy::Auto` resolved for a run over `input_count` metrics,
+    /// or `None` when there is nothing to resolve. Called for every step when a run is
+    /// planned, before any step runs.
+    fn plan(&self, _input_count: usize) -> Option<Box<dyn TransformationStrategy>> {
+        None
+    }
+    
+    /// Whether the step understands staleness markers (`Metric::stale`).
+    ///
+    /// A marker's value of 0 is not a sample, so pipelines strip markers from the
+    /// input of every step that keeps this default.
+    fn handles_stale(&self) -> bool {
+        false
+    }
+    
+    /// Whether the step must run on the calling thread because one of its plugins does
+    /// (e.g. it calls into Python), which keeps batches from running its pipeline on
+    /// worker threads
+    fn thread_bound(&self) -> bool {
+        false
+    }
+}
+
+/// `metrics` as `strategy` gets them: without staleness markers unless it handles them
+fn step_input<'a>(strategy: &dyn TransformationStrategy, metrics: &'a [Metric]) -> Cow<'a, [Metric]> {
+    if strategy.handles_stale() || !metrics.iter().any(|m| m.stale) {
+        Cow::Borrowed(metrics)
+    } else {
+        Cow::Owned(metrics.iter().filter(|m| !m.stale).cloned().collect())
+    }
+}
+
+/// `aggregation` with an `Accuracy::Auto` resolved for `input_count` metrics
+fn plan_aggregation(aggregation: &dyn AggregationPlugin, input_count: usize) -> Option<Box<dyn AggregationPlugin>> {
+    match aggregation.accuracy()? {
+        Accuracy::Auto => aggregation.with_accuracy(Accuracy::Auto.resolve(input_count)),
+        _ => None,
+    }
+}
+
+// Enable cloning of boxed strategies
+impl Clone for Box<dyn TransformationStrategy> {
+    fn clone(&self) -> Self {
+        self.clone_box()
+    }
 }
 
 /// Filter transformation strategy
+#[derive(Clone)]
 pub struct FilterTransformation {
     filter: Box<dyn FilterPlugin>,
 }
@@ -25,46 +200,245 @@ impl FilterTransformation {
     pub fn new(filter: Box<dyn FilterPlugin>) -> Self {
         Self { filter }
     }
+    
+    /// Which of `metrics` pass the filter, or the error a filter reported
+    fn keep_flags(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<bool>> {
+        // One batch call instead of a virtual call per metric
+        let mut keep = Vec::with_capacity(metrics.len());
+        take_filter_error();
+        if metrics.iter().any(|m| m.stale) {
+            self.keep_with_markers(metrics, &mut keep);
+        } else {
+            self.filter.apply_batch(metrics, &mut keep);
+        }
+        match take_filter_error() {
+            Some(error) => Err(error),
+            None => Ok(keep),
+        }
+    }
+    
+    /// Keep flags for input holding staleness markers: only samples go through the
+    /// batch, and markers are judged by the filter only if it doesn't read values
+    fn keep_with_markers(&self, metrics: &[Metric], keep: &mut Vec<bool>) {
+        let live: Vec<Metric> = metrics.iter().filter(|m| !m.stale).cloned().collect();
+        let mut kept_live = Vec::with_capacity(live.len());
+        self.filter.apply_batch(&live, &mut kept_live);
+        let mut kept_live = kept_live.into_iter();
+        keep.clear();
+        keep.extend(metrics.iter().map(|metric| {
+            if !metric.stale {
+                kept_live.next().unwrap_or(false)
+            } else {
+                self.filter.reads_value() || self.filter.apply(metric)
+            }
+        }));
+    }
 }
 
 impl TransformationStrategy for FilterTransformation {
+    fn name(&self) -> String {
+        self.filter.name().to_string()
+    }
+    
     fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
-        // Pre-allocate with an estimated capacity to avoid multiple reallocations
-        // For filters, we'll estimate half the metrics might pass (a reasonable heuristic)
-        let estimated_capacity = metrics.len() / 2;
-        let mut result = Vec::with_capacity(estimated_capacity);
+        let keep = self.keep_flags(metrics)?;
         
         // Only clone metrics that pass the filter
-        for metric in metrics {
-            if self.filter.apply(metric) {
+        let kept = keep.iter().filter(|&&keep| keep).count();
+        let mut result = Vec::with_capacity(kept);
+        for (metric, keep) in metrics.iter().zip(keep) {
+            if keep {
                 result.push(metric.clone());
             }
         }
         
         Ok(result)
     }
+    
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        let result = self.apply(metrics)?;
+        warn_if_emptied(warnings, metrics.len(), result.len());
+        Ok(result)
+    }
+    
+    fn apply_into(
+        &self,
+        metrics: &[Metric],
+        warnings: &mut WarningSink,
+        sink: &mut dyn FnMut(&Metric) -> MetricQueryResult<()>,
+    ) -> MetricQueryResult<usize> {
+        let keep = self.keep_flags(metrics)?;
+        let mut kept = 0;
+        for (metric, keep) in metrics.iter().zip(keep) {
+            if keep {
+                sink(metric)?;
+                kept += 1;
+            }
+        }
+        warn_if_emptied(warnings, metrics.len(), kept);
+        Ok(kept)
+    }
+    
+    fn apply_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        let estimated_capacity = values.len() / 2;
+        let mut out_values = Vec::with_capacity(estimated_capacity);
+        let mut out_timestamps = Vec::with_capacity(estimated_capacity);
+        
+        take_filter_error();
+        for (&value, &timestamp) in values.iter().zip(timestamps) {
+            if self.filter.apply_parts(value, timestamp) {
+                out_values.push(value);
+                out_timestamps.push(timestamp);
+            }
+        }
+        if let Some(error) = take_filter_error() {
+            return Err(error);
+        }
+        
+        Ok((out_values, out_timestamps))
+    }
+    
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let mut keep = Vec::with_capacity(metrics.len());
+        take_filter_error();
+        self.filter.apply_float_batch(metrics, &mut keep);
+        if let Some(error) = take_filter_error() {
+            return Err(error);
+        }
+        Ok(metrics.iter().zip(keep).filter(|(_, keep)| *keep).map(|(metric, _)| metric.clone()).collect())
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.filter.thread_bound()
+    }
+}
+
+fn warn_if_emptied(warnings: &mut WarningSink, input_count: usize, kept: usize) {
+    if kept == 0 && input_count > 0 {
+        warnings.warn("filtered_to_empty", format!("filter removed all {} metrics", input_count));
+    }
+}
+
+/// Which timestamp an aggregate result is stamped with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TimestampPolicy {
+    /// Timestamp of the first metric in input order
+    #[default]
+    First,
+    /// Timestamp of the last metric in input order
+    Last,
+    /// Earliest timestamp
+    Min,
+    /// Latest timestamp
+    Max,
+    /// Halfway between the earliest and latest timestamps
+    Midpoint,
+}
+
+impl TimestampPolicy {
+    /// Parse a policy name ("first", "last", "min", "max" or "midpoint")
+    pub fn parse(policy: &str) -> MetricQueryResult<Self> {
+        match policy {
+            "first" => Ok(Self::First),
+            "last" => Ok(Self::Last),
+            "min" => Ok(Self::Min),
+            "max" => Ok(Self::Max),
+            "midpoint" => Ok(Self::Midpoint),
+            _ => Err(MetricQueryError::InvalidAggregation {
+                reason: format!(
+                    "Unknown timestamp policy: {}. Expected one of: first, last, min, max, midpoint",
+                    policy
+                ),
+            }),
+        }
+    }
+    
+    /// Pick the representative timestamp from a non-empty sequence of timestamps
+    pub fn select<I>(self, timestamps: I) -> Option<i64>
+    where
+        I: IntoIterator<Item = i64>,
+    {
+        let mut timestamps = timestamps.into_iter();
+        match self {
+            Self::First => timestamps.next(),
+            Self::Last => timestamps.last(),
+            Self::Min => timestamps.min(),
+            Self::Max => timestamps.max(),
+            Self::Midpoint => {
+                let first = timestamps.next()?;
+                let (min, max) = timestamps.fold((first, first), |(lo, hi), t| (lo.min(t), hi.max(t)));
+                // Computed in i128 so extreme timestamps cannot overflow
+                Some(((i128::from(min) + i128::from(max)) / 2) as i64)
+            }
+        }
+    }
 }
 
 /// Aggregation transformation strategy
+#[derive(Clone)]
 pub struct AggregationTransformation {
     aggregation: Box<dyn AggregationPlugin>,
+    timestamp_policy: TimestampPolicy,
 }
 
 impl AggregationTransformation {
-    /// Create a new aggregation transformation
+    /// Create a new aggregation transformation stamped with the first input timestamp
     pub fn new(aggregation: Box<dyn AggregationPlugin>) -> Self {
-        Self { aggregation }
+        Self { aggregation, timestamp_policy: TimestampPolicy::default() }
+    }
+    
+    /// Choose which input timestamp the aggregate result carries
+    pub fn with_timestamp_policy(mut self, timestamp_policy: TimestampPolicy) -> Self {
+        self.timestamp_policy = timestamp_policy;
+        self
     }
 }
 
 impl TransformationStrategy for AggregationTransformation {
+    fn name(&self) -> String {
+        self.aggregation.name().to_string()
+    }
+    
+    fn accuracy(&self) -> Option<Accuracy> {
+        self.aggregation.accuracy()
+    }
+    
+    fn plan(&self, input_count: usize) -> Option<Box<dyn TransformationStrategy>> {
+        let aggregation = plan_aggregation(&*self.aggregation, input_count)?;
+        Some(Box::new(Self { aggregation, timestamp_policy: self.timestamp_policy }))
+    }
+    
     fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
         if metrics.is_empty() {
-            return Err(MetricQueryError::EmptyMetricStream);
+            return Settings::current().empty_stream_result();
+        }
+        
+        // Staleness markers carry no value; only a stream of nothing but markers stays stale
+        if metrics.iter().any(|m| m.stale) {
+            let live: Vec<Metric> = metrics.iter().filter(|m| !m.stale).cloned().collect();
+            if live.is_empty() {
+                let timestamp = self
+                    .timestamp_policy
+                    .select(metrics.iter().map(|m| m.timestamp))
+                    .ok_or(MetricQueryError::EmptyMetricStream)?;
+                return Ok(vec![Metric::stale_marker(timestamp, metrics[0].label.clone())]);
+            }
+            return self.apply(&live);
         }
         
-        // Use the first timestamp as a representative timestamp
-        let timestamp = metrics[0].timestamp;
+        // Fixed-point inputs are combined at their largest scale
+        let (metrics, scale) = align_scales(metrics)?;
+        let metrics = &*metrics;
+        
+        // Pick the representative timestamp according to the configured policy
+        let timestamp = self
+            .timestamp_policy
+            .select(metrics.iter().map(|m| m.timestamp))
+            .ok_or(MetricQueryError::EmptyMetricStream)?;
         
         // Apply the aggregation to get a single value
         let value = self.aggregation.apply(metrics)?;
@@ -73,13 +447,204 @@ impl TransformationStrategy for AggregationTransformation {
         let mut result = Vec::with_capacity(1);
         // Preserve label if present in first metric
         let label = metrics[0].label.clone();
-        result.push(Metric { value, timestamp, label });
+        let exemplar = Exemplar::worst(metrics.iter().map(|m| m.exemplar.as_ref())).cloned();
+        let unit = common_unit(metrics.iter().map(|m| m.unit.as_deref()), "aggregate")?.map(str::to_string);
+        let metric_type = MetricType::common(metrics.iter().map(|m| m.metric_type)).unwrap_or(None);
+        let scale = scale.filter(|_| self.aggregation.keeps_scale());
+        let merge = Settings::current().metadata;
+        let source = merge.merge(metrics.iter().map(|m| m.source.as_deref()));
+        let description = merge.merge(metrics.iter().map(|m| m.description.as_deref()));
+        result.push(Metric {
+            exemplar,
+            unit,
+            metric_type,
+            scale,
+            source,
+            description,
+            ..Metric::new(value, timestamp, label)
+        });
         
         Ok(result)
     }
+    
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        if let Err((a, b)) = MetricType::common(metrics.iter().map(|m| m.metric_type)) {
+            warn_mixed_types(warnings, a, b);
+        }
+        warn_unitless(warnings, metrics, false);
+        if self.aggregation.name() == "sum" {
+            let counts = counter_samples_per_label(metrics);
+            if let Some((label, _)) = counts.iter().find(|(_, &count)| count > 1) {
+                warn_counter_sum(warnings, *label);
+            }
+        }
+        self.apply(metrics)
+    }
+    
+    fn apply_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        if values.is_empty() {
+            Settings::current().empty_stream_result::<i64>()?;
+            return Ok((Vec::new(), Vec::new()));
+        }
+        
+        let value = self.aggregation.apply_values(values)?;
+        let timestamp = self
+            .timestamp_policy
+            .select(timestamps.iter().copied())
+            .ok_or(MetricQueryError::EmptyMetricStream)?;
+        Ok((vec![value], vec![timestamp]))
+    }
+    
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        if metrics.is_empty() {
+            return Settings::current().empty_stream_result();
+        }
+        
+        let values: Vec<f64> = metrics.iter().map(|m| m.value).collect();
+        let value = aggregate_float_values(&*self.aggregation, &values)?;
+        
+        let timestamp = self
+            .timestamp_policy
+            .select(metrics.iter().map(|m| m.timestamp))
+            .ok_or(MetricQueryError::EmptyMetricStream)?;
+        
+        Ok(vec![FloatMetric {
+            value,
+            timestamp,
+            label: metrics[0].label.clone(),
+            unit: common_unit(metrics.iter().map(|m| m.unit.as_deref()), "aggregate")?.map(str::to_string),
+        }])
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.aggregation.thread_bound()
+    }
+}
+
+/// Values collected for a single time bucket.
+///
+/// Most buckets only hold a handful of points, so they are stored inline and
+/// only spill to the heap for dense buckets.
+type BucketValues = SmallVec<[i64; 8]>;
+
+/// A time group within one label's series: (label, group timestamp)
+type GroupKey<'a> = (Option<&'a str>, i64);
+
+/// Highest-valued exemplar seen in each group; only groups with exemplars have an entry
+type GroupExemplars<'a> = HashMap<GroupKey<'a>, &'a Exemplar>;
+
+/// Record `exemplar` for `key` if it beats the one already kept
+fn keep_worst_exemplar<'a>(exemplars: &mut GroupExemplars<'a>, key: GroupKey<'a>, exemplar: Option<&'a Exemplar>) {
+    if let Some(exemplar) = exemplar {
+        let kept = exemplars.entry(key).or_insert(exemplar);
+        *kept = Exemplar::worst([Some(*kept), Some(exemplar)]).unwrap_or(exemplar);
+    }
+}
+
+/// Unit of each group; only groups whose members carry a unit have an entry
+type GroupUnits<'a> = HashMap<GroupKey<'a>, &'a str>;
+
+/// Record `unit` for `key`, failing if the group already holds a different unit
+fn keep_unit<'a>(units: &mut GroupUnits<'a>, key: GroupKey<'a>, unit: Option<&'a str>) -> MetricQueryResult<()> {
+    if let Some(unit) = unit {
+        let kept = units.entry(key).or_insert(unit);
+        common_unit([Some(*kept), Some(unit)], "group_by_time")?;
+    }
+    Ok(())
+}
+
+/// `source` and `description` values of each group's members, in input order; only
+/// groups whose members carry metadata have an entry
+type GroupMetadata<'a> = HashMap<GroupKey<'a>, (Vec<&'a str>, Vec<&'a str>)>;
+
+/// Record the metadata `metric` carries, if any, for `key`
+fn keep_metadata<'a>(metadata: &mut GroupMetadata<'a>, key: GroupKey<'a>, metric: &'a Metric) {
+    if metric.source.is_some() || metric.description.is_some() {
+        let (sources, descriptions) = metadata.entry(key).or_default();
+        sources.extend(metric.source.as_deref());
+        descriptions.extend(metric.description.as_deref());
+    }
+}
+
+/// Two different metric types found combined, if any
+type MixedTypes = Option<(MetricType, MetricType)>;
+
+/// Type of each label's series, leaving out labels whose metrics are untyped or mix
+/// types, and the first two types found mixed within a label
+fn series_types(metrics: &[Metric]) -> (HashMap<Option<&str>, MetricType>, MixedTypes) {
+    let mut types = HashMap::new();
+    let mut mixed = None;
+    let mut mixed_labels = Vec::new();
+    for metric in metrics {
+        let Some(metric_type) = metric.metric_type else { continue };
+        let kept = *types.entry(metric.label.as_deref()).or_insert(metric_type);
+        if kept != metric_type {
+            mixed.get_or_insert((kept, metric_type));
+            mixed_labels.push(metric.label.as_deref());
+        }
+    }
+    for label in mixed_labels {
+        types.remove(&label);
+    }
+    (types, mixed)
+}
+
+/// Number of counter samples in each label's series
+fn counter_samples_per_label(metrics: &[Metric]) -> HashMap<Option<&str>, usize> {
+    let mut counts = HashMap::new();
+    for metric in metrics.iter().filter(|m| m.metric_type == Some(MetricType::Counter)) {
+        *counts.entry(metric.label.as_deref()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn warn_mixed_types(warnings: &mut WarningSink, a: MetricType, b: MetricType) {
+    warnings.warn(
+        "mixed_metric_types",
+        format!("combined {} and {} metrics; the result is untyped", a.as_str(), b.as_str()),
+    );
+}
+
+/// Warn when a label's series mixes metrics with and without a unit
+fn warn_unitless(warnings: &mut WarningSink, metrics: &[Metric], by_label: bool) {
+    let mut series: HashMap<Option<&str>, Vec<Option<&str>>> = HashMap::new();
+    for metric in metrics {
+        let label = if by_label { metric.label.as_deref() } else { None };
+        series.entry(label).or_default().push(metric.unit.as_deref());
+    }
+    if let Some((unit, unitless)) = series.into_values().find_map(partly_unitless) {
+        warnings.warn(
+            "unitless_metrics",
+            format!("combined {} metric(s) without a unit with metrics in '{}', assuming '{}'", unitless, unit, unit),
+        );
+    }
+}
+
+fn warn_counter_sum(warnings: &mut WarningSink, label: Option<&str>) {
+    warnings.warn(
+        "counter_sum",
+        format!(
+            "summed several samples of counter {}, counting earlier totals again; use 'max' or a derivative instead",
+            label.map_or("(unlabeled)".to_string(), |label| format!("'{}'", label))
+        ),
+    );
 }
 
 /// Time grouping transformation strategy
+///
+/// Each label's series is bucketed separately and its groups keep the label;
+/// unlabeled metrics form a series of their own. Each group carries the
+/// highest-valued exemplar of its members, if any had one, their unit and the
+/// type of their series; a group mixing units is an error. Fixed-point inputs
+/// are brought to the largest scale among them before grouping, and members'
+/// `source` and `description` are merged per the `Settings` metadata mode.
+/// Staleness markers are left out of the values; a group holding nothing else
+/// becomes a staleness marker itself.
+#[derive(Clone)]
 pub struct TimeGroupingTransformation {
     time_grouping: Box<dyn TimeGroupingPlugin>,
     aggregation: Box<dyn AggregationPlugin>,
@@ -90,182 +655,1610 @@ impl TimeGroupingTransformation {
     pub fn new(time_grouping: Box<dyn TimeGroupingPlugin>, aggregation: Box<dyn AggregationPlugin>) -> Self {
         Self { time_grouping, aggregation }
     }
-}
 
-impl TransformationStrategy for TimeGroupingTransformation {
-    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+    /// Aggregate the values collected for a single group into one metric
+    fn aggregate_group(
+        &self,
+        key: GroupKey<'_>,
+        values: BucketValues,
+        exemplar: Option<&Exemplar>,
+        unit: Option<&str>,
+        metadata: Option<&(Vec<&str>, Vec<&str>)>,
+    ) -> MetricQueryResult<Metric> {
+        // Timestamps don't matter for the aggregation itself, so aggregate the bare values
+        let value = self.aggregation.apply_values(&values)?;
+        Ok(Self::finish_group(key, value, exemplar, unit, metadata))
+    }
+
+    /// The metric for one group, carrying its exemplar, unit and merged metadata
+    fn finish_group(
+        key: GroupKey<'_>,
+        value: i64,
+        exemplar: Option<&Exemplar>,
+        unit: Option<&str>,
+        metadata: Option<&(Vec<&str>, Vec<&str>)>,
+    ) -> Metric {
+        let (label, timestamp) = key;
+        let (source, description) = match metadata {
+            Some((sources, descriptions)) => {
+                let merge = Settings::current().metadata;
+                (merge.merge(sources.iter().copied().map(Some)), merge.merge(descriptions.iter().copied().map(Some)))
+            }
+            None => (None, None),
+        };
+        Metric {
+            exemplar: exemplar.cloned(),
+            unit: unit.map(str::to_string),
+            source,
+            description,
+            ..Metric::new(value, timestamp, label.map(str::to_string))
+        }
+    }
+
+    /// Group and aggregate with whichever backend suits the input
+    fn group(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let settings = Settings::current();
         if metrics.is_empty() {
-            return Err(MetricQueryError::EmptyMetricStream);
+            return settings.empty_stream_result();
         }
-        
+
+        #[cfg(feature = "gpu")]
+        if metrics.len() >= crate::gpu::GPU_GROUPING_THRESHOLD {
+            if let Some(reduction) = self.aggregation.gpu_reduction() {
+                log::debug!("grouping {} metrics with GPU reductions", metrics.len());
+                return self.apply_gpu(metrics, reduction);
+            }
+        }
+
+        // Plugins that call into Python cannot run on worker threads while the caller holds the GIL
+        #[cfg(feature = "rayon")]
+        if metrics.len() >= PARALLEL_GROUPING_THRESHOLD
+            && settings.parallel
+            && !self.time_grouping.thread_bound()
+            && !self.aggregation.thread_bound()
+        {
+            log::debug!("grouping {} metrics with sharded parallel aggregation", metrics.len());
+            return self.apply_sharded(metrics, settings);
+        }
+
+        log::debug!("grouping {} metrics sequentially", metrics.len());
+        self.apply_sequential(metrics)
+    }
+
+    /// Group `metrics`, giving each group the type of its label's series; also returns
+    /// the first two types found mixed within a label
+    fn apply_typed(&self, metrics: &[Metric]) -> MetricQueryResult<(Vec<Metric>, MixedTypes)> {
+        if metrics.iter().any(|m| m.stale) {
+            return self.apply_with_markers(metrics);
+        }
+        let (aligned, scale) = align_scales(metrics)?;
+        let mut result = self.group(&aligned)?;
+        let scale = scale.filter(|_| self.aggregation.keeps_scale());
+        let (types, mixed) = series_types(metrics);
+        if !types.is_empty() || scale.is_some() {
+            for metric in &mut result {
+                metric.metric_type = types.get(&metric.label.as_deref()).copied();
+                metric.scale = scale;
+            }
+        }
+        Ok((result, mixed))
+    }
+
+    /// Group the samples among `metrics`, then mark as stale every group that only held
+    /// staleness markers
+    fn apply_with_markers(&self, metrics: &[Metric]) -> MetricQueryResult<(Vec<Metric>, MixedTypes)> {
+        let (markers, live): (Vec<&Metric>, Vec<&Metric>) = metrics.iter().partition(|m| m.stale);
+        let live: Vec<Metric> = live.into_iter().cloned().collect();
+        let (mut result, mixed) = if live.is_empty() { (Vec::new(), None) } else { self.apply_typed(&live)? };
+        let mut groups: HashSet<(Option<String>, i64)> = result.iter().map(|m| (m.label.clone(), m.timestamp)).collect();
+        for marker in markers {
+            let group_timestamp = self.time_grouping.get_point_group_timestamp(&GroupPoint::from(marker))?;
+            if groups.insert((marker.label.clone(), group_timestamp)) {
+                result.push(Metric::stale_marker(group_timestamp, marker.label.clone()));
+            }
+        }
+        Ok((result, mixed))
+    }
+
+    /// Sequential hash aggregation over the whole input
+    fn apply_sequential(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
         // Performance optimization: Instead of cloning each metric into groups,
-        // just collect their values by timestamp groups
-        let mut group_values: HashMap<i64, Vec<i64>> = HashMap::new();
-        
+        // just collect their values by label and timestamp group
+        let mut group_values: HashMap<GroupKey<'_>, BucketValues> = HashMap::new();
+        let mut exemplars = GroupExemplars::new();
+        let mut units = GroupUnits::new();
+        let mut metadata = GroupMetadata::new();
+
         for metric in metrics {
             // Get the group timestamp for this metric
-            let group_timestamp = self.time_grouping.get_group_timestamp(metric.timestamp)?;
-            
+            let group_timestamp = self.time_grouping.get_point_group_timestamp(&GroupPoint::from(metric))?;
+            let key = (metric.label.as_deref(), group_timestamp);
+
             // Store just the value in the appropriate group (avoids cloning the entire Metric)
-            group_values
-                .entry(group_timestamp)
-                .or_insert_with(Vec::new)
-                .push(metric.value);
+            group_values.entry(key).or_default().push(metric.value);
+            keep_worst_exemplar(&mut exemplars, key, metric.exemplar.as_ref());
+            keep_unit(&mut units, key, metric.unit.as_deref())?;
+            keep_metadata(&mut metadata, key, metric);
         }
-        
+
         // Apply aggregation to each group
         let mut result = Vec::with_capacity(group_values.len());
-        
-        for (timestamp, values) in group_values {
-            // Create temporary metrics for the aggregation
-            let group_metrics: Vec<Metric> = values
-                .into_iter()
-                .map(|value| Metric { value, timestamp: 0, label: None }) // Timestamp doesn't matter for aggregation
-                .collect();
-            
-            let value = self.aggregation.apply(&group_metrics)?;
-            // For grouped metrics, we don't have a meaningful label to preserve
-            result.push(Metric { value, timestamp, label: None });
+
+        for (key, values) in group_values {
+            let exemplar = exemplars.get(&key).copied();
+            let unit = units.get(&key).copied();
+            result.push(self.aggregate_group(key, values, exemplar, unit, metadata.get(&key))?);
         }
-        
+
         Ok(result)
     }
+
+    /// Hash grouping on the CPU with the group reductions offloaded to the GPU backend
+    #[cfg(feature = "gpu")]
+    fn apply_gpu(&self, metrics: &[Metric], reduction: crate::gpu::Reduction) -> MetricQueryResult<Vec<Metric>> {
+        let mut group_ids: HashMap<GroupKey<'_>, usize> = HashMap::new();
+        let mut keys = Vec::new();
+        let mut values = Vec::with_capacity(metrics.len());
+        let mut groups = Vec::with_capacity(metrics.len());
+        let mut exemplars = GroupExemplars::new();
+        let mut units = GroupUnits::new();
+        let mut metadata = GroupMetadata::new();
+
+        for metric in metrics {
+            let group_timestamp = self.time_grouping.get_point_group_timestamp(&GroupPoint::from(metric))?;
+            let key = (metric.label.as_deref(), group_timestamp);
+            let group = *group_ids.entry(key).or_insert_with(|| {
+                keys.push(key);
+                keys.len() - 1
+            });
+            values.push(metric.value);
+            groups.push(group);
+            keep_worst_exemplar(&mut exemplars, key, metric.exemplar.as_ref());
+            keep_unit(&mut units, key, metric.unit.as_deref())?;
+            keep_metadata(&mut metadata, key, metric);
+        }
+
+        let partials = crate::gpu::reduce_groups(&values, &groups, keys.len());
+        keys.into_iter()
+            .zip(partials)
+            .map(|(key, partial)| {
+                let value = reduction.finish(&partial)?;
+                let (exemplar, unit) = (exemplars.get(&key).copied(), units.get(&key).copied());
+                Ok(Self::finish_group(key, value, exemplar, unit, metadata.get(&key)))
+            })
+            .collect()
+    }
+
+    /// Sharded parallel hash aggregation.
+    ///
+    /// Input chunks are bucketed in parallel and partitioned by a hash of the group
+    /// timestamp, so every group lives in exactly one shard. Each shard is then
+    /// aggregated independently and the shard outputs are concatenated.
+    ///
+    /// Workers run with the caller's `settings`, which are otherwise only visible on its thread.
+    #[cfg(feature = "rayon")]
+    fn apply_sharded(&self, metrics: &[Metric], settings: Settings) -> MetricQueryResult<Vec<Metric>> {
+        use rayon::prelude::*;
+
+        let shard_count = rayon::current_num_threads().max(1);
+        let chunk_size = metrics.len().div_ceil(shard_count).max(1);
+
+        // Partition phase: each chunk splits its (group, value) pairs across shards
+        let partitions: Vec<Vec<Vec<(GroupKey<'_>, &Metric)>>> = metrics
+            .par_chunks(chunk_size)
+            .map(|chunk| settings.scope(|| {
+                let mut shards: Vec<Vec<(GroupKey<'_>, &Metric)>> = vec![Vec::new(); shard_count];
+                for metric in chunk {
+                    let group_timestamp = self.time_grouping.get_point_group_timestamp(&GroupPoint::from(metric))?;
+                    shards[shard_for(group_timestamp, shard_count)]
+                        .push(((metric.label.as_deref(), group_timestamp), metric));
+                }
+                Ok(shards)
+            }))
+            .collect::<MetricQueryResult<_>>()?;
+
+        // Aggregation phase: each shard owns a disjoint set of groups
+        let shard_results: Vec<Vec<Metric>> = (0..shard_count)
+            .into_par_iter()
+            .map(|shard| settings.scope(|| {
+                let mut group_values: HashMap<GroupKey<'_>, BucketValues> = HashMap::new();
+                let mut exemplars = GroupExemplars::new();
+                let mut units = GroupUnits::new();
+                let mut metadata = GroupMetadata::new();
+                for partition in &partitions {
+                    for &(key, metric) in &partition[shard] {
+                        group_values.entry(key).or_default().push(metric.value);
+                        keep_worst_exemplar(&mut exemplars, key, metric.exemplar.as_ref());
+                        keep_unit(&mut units, key, metric.unit.as_deref())?;
+                        keep_metadata(&mut metadata, key, metric);
+                    }
+                }
+
+                group_values
+                    .into_iter()
+                    .map(|(key, values)| {
+                        let (exemplar, unit) = (exemplars.get(&key).copied(), units.get(&key).copied());
+                        self.aggregate_group(key, values, exemplar, unit, metadata.get(&key))
+                    })
+                    .collect::<MetricQueryResult<Vec<Metric>>>()
+            }))
+            .collect::<MetricQueryResult<_>>()?;
+
+        // Merge phase: shards are disjoint, so concatenation is enough
+        Ok(shard_results.into_iter().flatten().collect())
+    }
 }
 
-/// Pipeline for chaining transformations
-#[pyclass]
-pub struct MetricPipeline {
-    #[pyo3(get)]
-    metrics: Vec<Metric>,
-    // We'll use an internal Vec for strategies
-    strategies: Vec<Box<dyn TransformationStrategy>>,
+/// Inputs smaller than this are grouped sequentially even in rayon mode
+#[cfg(feature = "rayon")]
+pub const PARALLEL_GROUPING_THRESHOLD: usize = 16_384;
+
+/// Pick the shard that owns a group timestamp
+#[cfg(feature = "rayon")]
+fn shard_for(group_timestamp: i64, shard_count: usize) -> usize {
+    // Fibonacci hashing spreads regularly spaced bucket timestamps evenly across shards
+    let hash = (group_timestamp as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    ((hash >> 32) as usize) % shard_count
 }
 
-#[pymethods]
-impl MetricPipeline {
-    /// Create a new pipeline with the given metrics
-    #[new]
-    pub fn new(metrics: Vec<Metric>) -> Self {
-        // Estimate initial capacity for strategies
-        // Most pipelines have 2-5 transformations, so 5 is a reasonable starting point
-        Self {
-            metrics,
-            strategies: Vec::with_capacity(5),
-        }
+impl TransformationStrategy for TimeGroupingTransformation {
+    fn name(&self) -> String {
+        format!("{}/{}", self.time_grouping.name(), self.aggregation.name())
     }
     
-    /// Add a filter transformation to the pipeline
-    pub fn filter(&mut self, _py: Python<'_>, filter_type: &str, _filter_value: i64) -> PyResult<()> {
-        with_registry(|registry| {
-            // Find the filter
-            if let Some(filter) = registry.get_filter(filter_type) {
-                self.strategies.push(Box::new(FilterTransformation::new(filter.clone())));
-                Ok(())
-            } else {
-                Err(pyo3::exceptions::PyValueError::new_err(
-                    format!("Unknown filter type: {}", filter_type)
-                ))
-            }
-        })
+    fn accuracy(&self) -> Option<Accuracy> {
+        self.aggregation.accuracy()
     }
     
-    /// Add an aggregation transformation to the pipeline
-    pub fn aggregate(&mut self, _py: Python<'_>, agg_type: &str) -> PyResult<()> {
-        with_registry(|registry| {
-            // Find the aggregation
-            if let Some(aggregation) = registry.get_aggregation(agg_type) {
-                self.strategies.push(Box::new(AggregationTransformation::new(aggregation.clone())));
-                Ok(())
-            } else {
-                Err(pyo3::exceptions::PyValueError::new_err(
-                    format!("Unknown aggregation type: {}", agg_type)
-                ))
-            }
-        })
+    fn plan(&self, input_count: usize) -> Option<Box<dyn TransformationStrategy>> {
+        let aggregation = plan_aggregation(&*self.aggregation, input_count)?;
+        Some(Box::new(Self::new(self.time_grouping.clone(), aggregation)))
     }
     
-    /// Add a time grouping transformation with an aggregation to the pipeline
-    pub fn group_by_time(
-        &mut self,
-        _py: Python<'_>,
-        time_grouping_type: &str,
-        agg_type: &str,
-    ) -> PyResult<()> {
-        with_registry(|registry| {
-            // Find the time grouping and aggregation
-            let time_grouping = registry.get_time_grouping(time_grouping_type)
-                .ok_or_else(|| pyo3::exceptions::PyValueError::new_err(
-                    format!("Unknown time grouping type: {}", time_grouping_type)
-                ))?;
-            
-            let aggregation = registry.get_aggregation(agg_type)
-                .ok_or_else(|| pyo3::exceptions::PyValueError::new_err(
-                    format!("Unknown aggregation type: {}", agg_type)
-                ))?;
-            
-            self.strategies.push(Box::new(TimeGroupingTransformation::new(
-                time_grouping.clone(),
-                aggregation.clone(),
-            )));
-            
-            Ok(())
-        })
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        Ok(self.apply_typed(metrics)?.0)
     }
     
-    /// Add a label filter transformation to the pipeline
-    pub fn filter_by_label(&mut self, _py: Python<'_>, filter_type: &str, label: String) -> PyResult<()> {
-        if filter_type == "label_eq" {
-            // Create a new label filter directly
-            let filter_box: Box<dyn FilterPlugin> = Box::new(LabelFilter::new(label));
-            self.strategies.push(Box::new(FilterTransformation::new(filter_box)));
-            Ok(())
-        } else {
-            Err(pyo3::exceptions::PyValueError::new_err(
-                format!("Invalid label filter type: {}. Expected 'label_eq'", filter_type)
-            ))
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        let (result, mixed) = self.apply_typed(metrics)?;
+        if let Some((a, b)) = mixed {
+            warn_mixed_types(warnings, a, b);
+        }
+        warn_unitless(warnings, metrics, true);
+        if self.aggregation.name() == "sum" {
+            let mut groups = HashMap::new();
+            for metric in &result {
+                *groups.entry(metric.label.as_deref()).or_insert(0) += 1;
+            }
+            // Fewer groups than samples means some group summed several samples of the counter
+            let merged = counter_samples_per_label(metrics)
+                .into_iter()
+                .find(|(label, count)| *count > groups.get(label).copied().unwrap_or(0));
+            if let Some((label, _)) = merged {
+                warn_counter_sum(warnings, label);
+            }
         }
+        Ok(result)
     }
     
-    /// Add a label inclusion filter transformation to the pipeline
-    pub fn filter_by_labels(&mut self, _py: Python<'_>, filter_type: &str, labels: Vec<String>) -> PyResult<()> {
-        if filter_type == "label_in" {
-            // Create a new label_in filter directly
-            let filter_box: Box<dyn FilterPlugin> = Box::new(LabelInFilter::new(labels));
-            self.strategies.push(Box::new(FilterTransformation::new(filter_box)));
-            Ok(())
-        } else {
-            Err(pyo3::exceptions::PyValueError::new_err(
-                format!("Invalid label filter type: {}. Expected 'label_in'", filter_type)
-            ))
+    fn apply_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        if values.is_empty() {
+            Settings::current().empty_stream_result::<i64>()?;
+            return Ok((Vec::new(), Vec::new()));
         }
+        
+        let mut group_values: HashMap<i64, BucketValues> = HashMap::new();
+        for (&value, &timestamp) in values.iter().zip(timestamps) {
+            let point = GroupPoint { timestamp, label: None, value: Some(value as f64) };
+            let group_timestamp = self.time_grouping.get_point_group_timestamp(&point)?;
+            group_values.entry(group_timestamp).or_default().push(value);
+        }
+        
+        let mut out_values = Vec::with_capacity(group_values.len());
+        let mut out_timestamps = Vec::with_capacity(group_values.len());
+        for (timestamp, values) in group_values {
+            out_values.push(self.aggregation.apply_values(&values)?);
+            out_timestamps.push(timestamp);
+        }
+        
+        Ok((out_values, out_timestamps))
     }
     
-    /// Execute the pipeline and return the result
-    pub fn execute(&self) -> PyResult<Vec<Metric>> {
-        // Only clone the metrics once at the end if no transformations are applied
-        // This avoids unnecessary cloning during intermediate steps
-        if self.strategies.is_empty() {
-            return Ok(self.metrics.clone());
+    fn apply_float(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        if metrics.is_empty() {
+            return Settings::current().empty_stream_result();
+        }
+        
+        let mut group_values: HashMap<GroupKey<'_>, SmallVec<[f64; 8]>> = HashMap::new();
+        let mut units = GroupUnits::new();
+        for metric in metrics {
+            let group_timestamp = self.time_grouping.get_point_group_timestamp(&GroupPoint::from(metric))?;
+            let key = (metric.label.as_deref(), group_timestamp);
+            group_values.entry(key).or_default().push(metric.value);
+            keep_unit(&mut units, key, metric.unit.as_deref())?;
         }
         
-        // Apply the first transformation directly on the original metrics
-        let mut result = match self.strategies[0].apply(&self.metrics) {
-            Ok(transformed) => transformed,
-            Err(e) => return Err(pyo3::exceptions::PyValueError::new_err(
-                format!("Error executing transformation: {:?}", e)
-            )),
+        let mut result = Vec::with_capacity(group_values.len());
+        for (key, values) in group_values {
+            let value = aggregate_float_values(&*self.aggregation, &values)?;
+            let (label, timestamp) = key;
+            result.push(FloatMetric {
+                value,
+                timestamp,
+                label: label.map(str::to_string),
+                unit: units.get(&key).map(|unit| unit.to_string()),
+            });
+        }
+        
+        Ok(result)
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+
+    fn thread_bound(&self) -> bool {
+        self.time_grouping.thread_bound() || self.aggregation.thread_bound()
+    }
+}
+
+/// A step's output in `run_summary`: kept for the next step, or folded by the last one
+enum SummaryStep {
+    Metrics(Vec<Metric>),
+    /// The summary so far and how many metrics went into it
+    Folded(SummaryBuilder, usize),
+    /// The pipeline has no steps
+    Unchanged,
+}
+
+impl SummaryStep {
+    fn len(&self) -> usize {
+        match self {
+            Self::Metrics(metrics) => metrics.len(),
+            Self::Folded(_, count) => *count,
+            Self::Unchanged => 0,
+        }
+    }
+}
+
+/// Optional knobs accepted by `aggregate` and `group_by_time`
+#[cfg(feature = "python")]
+#[derive(Default)]
+struct AggregationOptions<'a> {
+    /// Overflow policy for "sum"
+    overflow: Option<&'a str>,
+    /// Rounding mode for "avg"
+    rounding: Option<&'a str>,
+    /// Missing-value policy for float execution
+    missing: Option<&'a str>,
+    /// Fill value used by the "substitute" missing-value policy
+    fill_value: Option<f64>,
+    /// Accuracy mode for percentiles and "count_distinct"
+    accuracy: Option<&'a str>,
+}
+
+/// Look up an aggregation by name and apply the given options to it
+#[cfg(feature = "python")]
+fn resolve_aggregation(agg_type: &str, options: &AggregationOptions) -> PyResult<Box<dyn AggregationPlugin>> {
+    if options.overflow.is_some() && agg_type != "sum" {
+        return Err(MetricQueryError::InvalidAggregation {
+            reason: format!("Overflow policy only applies to 'sum', not '{}'", agg_type),
+        }
+        .into());
+    }
+    if options.rounding.is_some() && agg_type != "avg" {
+        return Err(MetricQueryError::InvalidAggregation {
+            reason: format!("Rounding mode only applies to 'avg', not '{}'", agg_type),
+        }
+        .into());
+    }
+    let aggregation: Box<dyn AggregationPlugin> = match (agg_type, options.overflow, options.rounding) {
+        ("sum", Some(policy), _) => Box::new(SumAggregation::new(OverflowPolicy::parse(policy)?)),
+        ("avg", _, Some(mode)) => Box::new(AvgAggregation::new(RoundingMode::parse(mode)?)),
+        _ => with_registry(|registry| {
+            registry
+                .get_aggregation(agg_type)
+                .map(|aggregation| aggregation.clone_box())
+                // Percentiles beyond the registered ones, such as "p99.9"
+                .or_else(|| PercentileAggregation::parse(agg_type).map(|p| Box::new(p) as Box<dyn AggregationPlugin>))
+                .ok_or_else(|| pyo3::exceptions::PyValueError::new_err(
+                    format!("Unknown aggregation type: {}", agg_type)
+                ))
+        })?,
+    };
+    let aggregation = match options.accuracy {
+        Some(mode) => with_accuracy(aggregation, Accuracy::parse(mode)?)?,
+        None => aggregation,
+    };
+    
+    match options.missing {
+        Some(policy) => {
+            let policy = MissingValuePolicy::parse(policy, options.fill_value)?;
+            Ok(Box::new(MissingValueAggregation::new(aggregation, policy)))
+        }
+        None => Ok(aggregation),
+    }
+}
+
+/// An int timestamp as given, or a datetime converted to the settings' timestamp precision
+#[cfg(feature = "python")]
+fn pipeline_timestamp(value: &Bound<'_, PyAny>) -> PyResult<i64> {
+    if let Ok(timestamp) = value.extract::<i64>() {
+        return Ok(timestamp);
+    }
+    Ok(Settings::current().timestamp_precision.from_seconds(extract_timestamp(value)?)?)
+}
+
+/// Call a tap or assertion callable with a copy of `batch`, as plugin `plugin` in the sandbox
+#[cfg(feature = "python")]
+fn call_with_batch(plugin: &str, callable: &Arc<PyObject>, batch: TapBatch<'_>) -> MetricQueryResult<PyObject> {
+    enum Owned {
+        Metrics(Vec<Metric>),
+        Floats(Vec<FloatMetric>),
+    }
+    let batch = match batch {
+        TapBatch::Metrics(metrics) => Owned::Metrics(metrics.to_vec()),
+        TapBatch::Floats(metrics) => Owned::Floats(metrics.to_vec()),
+    };
+    let callable = Arc::clone(callable);
+    call_plugin(plugin, move |py| {
+        let batch = match batch {
+            Owned::Metrics(metrics) => metrics.into_pyobject(py)?,
+            Owned::Floats(metrics) => metrics.into_pyobject(py)?,
         };
+        callable.call1(py, (batch,))
+    })
+}
+
+fn group_by_label(metrics: Vec<Metric>) -> BTreeMap<Option<String>, Vec<Metric>> {
+    let mut groups: BTreeMap<Option<String>, Vec<Metric>> = BTreeMap::new();
+    for metric in metrics {
+        groups.entry(metric.label.clone()).or_default().push(metric);
+    }
+    groups
+}
+
+/// Pipeline for chaining transformations
+#[cfg_attr(feature = "python", pyclass)]
+pub struct MetricPipeline {
+    // Shared, so pipelines over the same dataset don't each hold a copy
+    metrics: Arc<[Metric]>,
+    // Appended after construction; kept apart so appends don't copy the shared input
+    appended: Vec<Metric>,
+    // We'll use an internal Vec for strategies
+    strategies: Vec<Box<dyn TransformationStrategy>>,
+    // Overrides the process-wide settings for this pipeline's runs
+    settings: Option<Settings>,
+    last_run_stats: Mutex<Option<RunStats>>,
+}
+
+impl Clone for MetricPipeline {
+    /// Share the metrics and copy the configured steps; the copy starts without run stats.
+    /// Callback steps (`tap`, custom `assert_that`) share their callback with the original.
+    fn clone(&self) -> Self {
+        Self {
+            metrics: Arc::clone(&self.metrics),
+            appended: self.appended.clone(),
+            strategies: self.strategies.clone(),
+            settings: self.settings,
+            last_run_stats: Mutex::new(None),
+        }
+    }
+}
+
+// Rust-side builders that take plugin instances directly instead of registry names
+impl MetricPipeline {
+    /// Create a new pipeline with the given metrics
+    pub fn new(metrics: Vec<Metric>) -> Self {
+        Self::from_shared(metrics.into())
+    }
+
+    /// Create a new pipeline reading metrics shared with other pipelines, without copying them
+    pub fn from_shared(metrics: Arc<[Metric]>) -> Self {
+        // Estimate initial capacity for strategies
+        // Most pipelines have 2-5 transformations, so 5 is a reasonable starting point
+        Self {
+            metrics,
+            appended: Vec::new(),
+            strategies: Vec::with_capacity(5),
+            settings: None,
+            last_run_stats: Mutex::new(None),
+        }
+    }
+
+    /// The pipeline's input metrics, shared rather than copied unless metrics were
+    /// appended since the input was last frozen
+    pub fn shared_metrics(&self) -> Arc<[Metric]> {
+        match self.input() {
+            Cow::Borrowed(_) => Arc::clone(&self.metrics),
+            Cow::Owned(metrics) => metrics.into(),
+        }
+    }
+
+    /// The input metrics as one slice, copied only when metrics were appended
+    fn input(&self) -> Cow<'_, [Metric]> {
+        if self.appended.is_empty() {
+            Cow::Borrowed(&self.metrics)
+        } else {
+            Cow::Owned(self.metrics.iter().chain(&self.appended).cloned().collect())
+        }
+    }
+
+    /// Fold appended metrics into the shared input, so later runs and snapshots don't
+    /// copy them again
+    pub fn freeze_input(&mut self) {
+        if !self.appended.is_empty() {
+            self.metrics = self.shared_metrics();
+            self.appended.clear();
+        }
+    }
+
+    /// Copy the pipeline's steps, sharing its metrics, so it can be specialised separately
+    pub fn clone_pipeline(&self) -> Self {
+        self.clone()
+    }
+
+    /// Append more input metrics; steps added so far apply to them too.
+    ///
+    /// Appends go to a buffer of the pipeline's own, leaving other pipelines sharing the
+    /// input untouched; the buffer is joined to the input once per run, or for good by
+    /// `freeze_input`.
+    pub fn add_metrics(&mut self, metrics: impl IntoIterator<Item = Metric>) {
+        self.appended.extend(metrics);
+    }
+
+    /// Append another pipeline's input metrics (its steps are not copied)
+    pub fn extend_from(&mut self, other: &MetricPipeline) {
+        if self.metrics.is_empty() && self.appended.is_empty() {
+            self.metrics = other.shared_metrics();
+        } else {
+            self.add_metrics(other.input().iter().cloned());
+        }
+    }
+
+    /// Give this pipeline its own settings, or with `None` follow the process-wide ones again
+    pub fn set_settings(&mut self, settings: Option<Settings>) {
+        self.settings = settings;
+    }
+
+    /// The settings runs use: the pipeline's own, else the process-wide ones at the time of asking
+    pub fn settings(&self) -> Settings {
+        self.settings.unwrap_or_else(Settings::global)
+    }
+
+    /// Whether any step must run on the calling thread (e.g. it calls into Python)
+    pub fn thread_bound(&self) -> bool {
+        self.strategies.iter().any(|strategy| strategy.thread_bound())
+    }
+
+    /// Names of the configured steps, in order
+    pub fn step_names(&self) -> Vec<String> {
+        self.strategies.iter().map(|strategy| strategy.name()).collect()
+    }
+
+    /// Nodes and edges describing how metrics flow from the input through each step
+    pub fn to_graph(&self) -> PipelineGraph {
+        PipelineGraph::linear(self.metrics.len() + self.appended.len(), &self.step_names())
+    }
+
+    /// Graphviz DOT source of the pipeline graph
+    pub fn to_dot(&self) -> String {
+        self.to_graph().to_dot()
+    }
+
+    /// Add an arbitrary transformation step to the pipeline
+    pub fn add_strategy(&mut self, strategy: Box<dyn TransformationStrategy>) {
+        self.strategies.push(strategy);
+    }
+
+    /// Add a filter plugin instance to the pipeline
+    pub fn add_filter(&mut self, filter: Box<dyn FilterPlugin>) {
+        self.strategies.push(Box::new(FilterTransformation::new(filter)));
+    }
+
+    /// Add an aggregation plugin instance to the pipeline
+    pub fn add_aggregation(&mut self, aggregation: Box<dyn AggregationPlugin>) {
+        self.strategies.push(Box::new(AggregationTransformation::new(aggregation)));
+    }
+
+    /// Add a time grouping plugin instance with an aggregation to the pipeline
+    pub fn add_time_grouping(
+        &mut self,
+        time_grouping: Box<dyn TimeGroupingPlugin>,
+        aggregation: Box<dyn AggregationPlugin>,
+    ) {
+        self.strategies.push(Box::new(TimeGroupingTransformation::new(time_grouping, aggregation)));
+    }
+
+    /// Execute the pipeline over its own metrics.
+    ///
+    /// Errors are annotated with the index and name of the step that raised them.
+    pub fn run(&self) -> MetricQueryResult<Vec<Metric>> {
+        self.run_with_warnings().map(|(result, _)| result)
+    }
+
+    /// Execute the pipeline and partition the result by label, keeping each series in order.
+    ///
+    /// Unlabeled metrics are keyed by `None`, which sorts before every label.
+    pub fn run_grouped(&self) -> MetricQueryResult<BTreeMap<Option<String>, Vec<Metric>>> {
+        Ok(group_by_label(self.run()?))
+    }
+
+    /// Execute the pipeline and fold the result into one quantile sketch per label and time group
+    pub fn run_sketches(
+        &self,
+        grouping: &dyn TimeGroupingPlugin,
+        relative_accuracy: f64,
+    ) -> MetricQueryResult<Vec<SketchMetric>> {
+        let result: Vec<FloatMetric> = self.run()?.iter().map(FloatMetric::from).collect();
+        SketchMetric::from_metrics(&result, grouping, relative_accuracy)
+    }
+
+    /// Execute the pipeline and summarise the resulting values
+    pub fn describe_metrics(&self, percentiles: &[f64]) -> MetricQueryResult<MetricSummary> {
+        describe_metrics(&self.run()?, percentiles)
+    }
+
+    /// Execute the pipeline and reduce the result to count, sum, min, max and mean.
+    ///
+    /// The last step hands its output straight to the summary instead of returning
+    /// it, so a pipeline ending in a filter, or without steps, never copies its
+    /// result. Other last steps still build their output once before it is folded.
+    pub fn run_summary(&self) -> MetricQueryResult<ResultSummary> {
+        self.run_summary_with_warnings().map(|(summary, _)| summary)
+    }
+
+    /// `run_summary`, also returning non-fatal warnings
+    pub fn run_summary_with_warnings(&self) -> MetricQueryResult<(ResultSummary, Vec<PipelineWarning>)> {
+        let input = self.input();
+        self.settings().scope(|| self.summarise_steps(&input))
+    }
+
+    fn summarise_steps(&self, metrics: &[Metric]) -> MetricQueryResult<(ResultSummary, Vec<PipelineWarning>)> {
+        let mut warnings = WarningSink::new();
+        let last = self.strategies.len().saturating_sub(1);
+        let output = self.run_recorded(
+            metrics.len(),
+            SummaryStep::len,
+            || SummaryStep::Unchanged,
+            |index, name, strategy, previous| {
+                warnings.enter_step(index, name.to_string());
+                let input = match previous {
+                    Some(SummaryStep::Metrics(previous)) => previous.as_slice(),
+                    _ => metrics,
+                };
+                let input = step_input(strategy, input);
+                if index < last {
+                    return strategy.apply_with_warnings(&input, &mut warnings).map(SummaryStep::Metrics);
+                }
+                let mut summary = SummaryBuilder::default();
+                let count = strategy.apply_into(&input, &mut warnings, &mut |metric| summary.push(metric))?;
+                Ok(SummaryStep::Folded(summary, count))
+            },
+        )?;
+        let summary = match output {
+            SummaryStep::Folded(summary, _) => summary.finish(),
+            SummaryStep::Metrics(result) => ResultSummary::of(&result)?,
+            SummaryStep::Unchanged => ResultSummary::of(metrics)?,
+        };
+        Ok((summary, warnings.into_warnings()))
+    }
+
+    /// Counts and timings from the most recent successful run, if any
+    pub fn last_run_stats(&self) -> Option<RunStats> {
+        self.last_run_stats.lock().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn store_run_stats(&self, stats: RunStats) {
+        *self.last_run_stats.lock().unwrap_or_else(PoisonError::into_inner) = Some(stats);
+    }
+
+    /// Execute the pipeline over its own metrics, also returning non-fatal warnings
+    pub fn run_with_warnings(&self) -> MetricQueryResult<(Vec<Metric>, Vec<PipelineWarning>)> {
+        self.run_metrics_with_warnings(&self.input())
+    }
+
+    /// Execute the configured steps over borrowed metrics instead of the pipeline's own,
+    /// also returning non-fatal warnings
+    pub fn run_metrics_with_warnings(&self, metrics: &[Metric]) -> MetricQueryResult<(Vec<Metric>, Vec<PipelineWarning>)> {
+        let settings = self.settings();
+        let (mut result, warnings) = settings.scope(|| self.run_steps(metrics))?;
+        settings.order_metrics(&mut result);
+        Ok((result, warnings))
+    }
+
+    fn run_steps(&self, metrics: &[Metric]) -> MetricQueryResult<(Vec<Metric>, Vec<PipelineWarning>)> {
+        let mut warnings = WarningSink::new();
+        let result = self.run_recorded(
+            metrics.len(),
+            Vec::len,
+            || metrics.to_vec(),
+            |index, name, strategy, previous| {
+                warnings.enter_step(index, name.to_string());
+                // The first step reads the borrowed metrics; later steps read the previous result
+                let input = previous.map_or(metrics, Vec::as_slice);
+                strategy.apply_with_warnings(&step_input(strategy, input), &mut warnings)
+            },
+        )?;
+        Ok((result, warnings.into_warnings()))
+    }
+
+    /// Run every step in order, recording its counts and timing for `last_run_stats`.
+    ///
+    /// `step` receives `None` for the first step, which should read the caller's
+    /// borrowed input, and the previous step's output after that. `unchanged` builds
+    /// the result when the pipeline has no steps.
+    fn run_recorded<T>(
+        &self,
+        input_count: usize,
+        len: impl Fn(&T) -> usize,
+        unchanged: impl FnOnce() -> T,
+        mut step: impl FnMut(usize, &str, &dyn TransformationStrategy, Option<&T>) -> MetricQueryResult<T>,
+    ) -> MetricQueryResult<T> {
+        let mut stats = StatsRecorder::start(input_count);
+        // Every step's mode is settled before the first one runs
+        let planned: Vec<_> = self.strategies.iter().map(|strategy| strategy.plan(input_count)).collect();
+        let mut result: Option<T> = None;
+        let mut count = input_count;
+        for (index, (strategy, planned)) in self.strategies.iter().zip(&planned).enumerate() {
+            let strategy = planned.as_deref().unwrap_or(strategy.as_ref());
+            let name = strategy.name();
+            let timer = stats.begin_step(index, &name, count);
+            let output = step(index, &name, strategy, result.as_ref()).map_err(|e| e.at_step(index, name.clone()))?;
+            let output_count = len(&output);
+            stats.record_step(index, name, count, output_count, strategy.accuracy(), timer);
+            result = Some(output);
+            count = output_count;
+        }
         
-        // Apply remaining transformations sequentially
-        for strategy in &self.strategies[1..] {
-            result = match strategy.apply(&result) {
-                Ok(transformed) => transformed,
-                Err(e) => return Err(pyo3::exceptions::PyValueError::new_err(
-                    format!("Error executing transformation: {:?}", e)
-                )),
-            };
+        self.store_run_stats(stats.finish(count));
+        Ok(result.unwrap_or_else(unchanged))
+    }
+
+    /// Execute the configured steps over borrowed value/timestamp columns.
+    ///
+    /// The pipeline's own metrics are ignored, so one configured pipeline can be
+    /// reused across many array batches without building `Metric` structs.
+    pub fn execute_columns(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        let settings = self.settings();
+        let result = settings.scope(|| self.run_column_steps(values, timestamps))?;
+        Ok(settings.order_columns(result))
+    }
+
+    fn run_column_steps(&self, values: &[i64], timestamps: &[i64]) -> MetricQueryResult<(Vec<i64>, Vec<i64>)> {
+        if values.len() != timestamps.len() {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "execute_columns".to_string(),
+                reason: format!(
+                    "values and timestamps differ in length ({} vs {})",
+                    values.len(),
+                    timestamps.len()
+                ),
+            });
         }
         
+        self.run_recorded(
+            values.len(),
+            |columns: &(Vec<i64>, Vec<i64>)| columns.0.len(),
+            || (values.to_vec(), timestamps.to_vec()),
+            |_, _, strategy, previous| match previous {
+                None => strategy.apply_columns(values, timestamps),
+                Some((values, timestamps)) => strategy.apply_columns(values, timestamps),
+            },
+        )
+    }
+
+    /// Execute the configured steps over float metrics.
+    ///
+    /// Like `execute_columns`, the pipeline's own metrics are ignored.
+    pub fn execute_float_metrics(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        let settings = self.settings();
+        let mut result = settings.scope(|| self.run_float_steps(metrics))?;
+        settings.order_float_metrics(&mut result);
         Ok(result)
     }
+
+    fn run_float_steps(&self, metrics: &[FloatMetric]) -> MetricQueryResult<Vec<FloatMetric>> {
+        self.run_recorded(metrics.len(), Vec::len, || metrics.to_vec(), |_, _, strategy, previous| {
+            strategy.apply_float(previous.map_or(metrics, Vec::as_slice))
+        })
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl MetricPipeline {
+    /// Create a new pipeline with the given metrics (`Metric`s, tuples or dicts), or over
+    /// a `MetricDataset` without copying it
+    #[new]
+    fn py_new(metrics: PipelineInput) -> Self {
+        Self::from_shared(metrics.into_shared())
+    }
+    
+    /// The pipeline's input metrics
+    #[getter]
+    fn metrics(&self) -> Vec<Metric> {
+        self.input().into_owned()
+    }
+    
+    fn __copy__(&self) -> Self {
+        self.clone_pipeline()
+    }
+    
+    /// Steps hold no Python objects apart from callbacks, which are shared as with `copy`
+    fn __deepcopy__(&self, _memo: &Bound<'_, PyAny>) -> Self {
+        self.clone_pipeline()
+    }
+    
+    /// Freeze the current metrics, steps and settings into a `CompiledPipeline` that
+    /// threads can share
+    fn compile(&self) -> CompiledPipeline {
+        CompiledPipeline::new(self)
+    }
+    
+    /// The `Settings` this pipeline runs with: its own, or the process-wide ones
+    #[getter(settings)]
+    fn py_settings(&self) -> Settings {
+        self.settings()
+    }
+    
+    /// Give this pipeline its own `Settings`; `None` follows the process-wide ones again
+    #[setter(settings)]
+    fn py_set_settings(&mut self, settings: Option<Settings>) {
+        self.set_settings(settings);
+    }
+    
+    /// Graphviz DOT source showing the input, each step and the output
+    #[pyo3(name = "to_dot")]
+    fn py_to_dot(&self) -> String {
+        self.to_dot()
+    }
+    
+    /// The pipeline graph as `{"nodes": [{"id", "label", "kind"}], "edges": [{"from", "to"}]}`,
+    /// ready for `json.dumps`
+    #[pyo3(name = "to_graph")]
+    fn py_to_graph<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let graph = self.to_graph();
+        let nodes = PyList::empty(py);
+        for node in &graph.nodes {
+            let entry = PyDict::new(py);
+            entry.set_item("id", &node.id)?;
+            entry.set_item("label", &node.label)?;
+            entry.set_item("kind", node.kind.as_str())?;
+            nodes.append(entry)?;
+        }
+        let edges = PyList::empty(py);
+        for edge in &graph.edges {
+            let entry = PyDict::new(py);
+            entry.set_item("from", &edge.from)?;
+            entry.set_item("to", &edge.to)?;
+            edges.append(entry)?;
+        }
+        let result = PyDict::new(py);
+        result.set_item("nodes", nodes)?;
+        result.set_item("edges", edges)?;
+        Ok(result)
+    }
+    
+    /// Append a batch of metrics to the pipeline's input
+    #[pyo3(name = "add_metrics")]
+    fn py_add_metrics(&mut self, metrics: Vec<Metric>) {
+        self.add_metrics(metrics);
+    }
+    
+    /// Append another pipeline's input metrics; its steps are not copied
+    fn extend(slf: &Bound<'_, Self>, other: &Bound<'_, Self>) {
+        // Take the shared input first so `pipeline.extend(pipeline)` doesn't hold two borrows at once
+        let metrics = other.borrow().shared_metrics();
+        slf.borrow_mut().add_metrics(metrics.iter().cloned());
+    }
+    
+    /// Add a filter transformation to the pipeline
+    ///
+    /// `filter_type` names a registered filter, instantiated with `filter_value`
+    /// (e.g. `filter("gt", 25)` or `filter("gt", 2.5)`), or is a `BoundFilter` from
+    /// `registry.create_filter`, which already carries its value.
+    /// With `field`, the filter tests that named field of multi-field metrics and
+    /// keeps the whole metric when it matches.
+    #[pyo3(signature = (filter_type, filter_value=None, field=None))]
+    pub fn filter(&mut self, filter_type: FilterArg, filter_value: Option<Threshold>, field: Option<String>) -> PyResult<()> {
+        let filter = match (filter_type, filter_value) {
+            (FilterArg::Name(name), value) => create_registered_filter(&name, value)?,
+            (FilterArg::Bound(bound), None) => bound.filter,
+            (FilterArg::Bound(bound), Some(_)) => {
+                return Err(MetricQueryError::InvalidFilter {
+                    reason: format!("Filter '{}' is already bound to its value", bound.filter.name()),
+                }
+                .into())
+            }
+        };
+        let filter: Box<dyn FilterPlugin> = match field {
+            Some(field) => Box::new(FieldFilter::new(field, filter)),
+            None => filter,
+        };
+        self.strategies.push(Box::new(FilterTransformation::new(filter)));
+        Ok(())
+    }
+    
+    /// Add an aggregation transformation to the pipeline
+    ///
+    /// `overflow` sets the overflow policy ("error" or "saturate") for "sum";
+    /// `rounding` sets the rounding mode ("truncate", "round", "floor" or "ceil") for "avg";
+    /// `missing` sets how missing float values are handled ("skip", "propagate" or
+    /// "substitute", which replaces them with `fill_value`); `timestamp` picks the result's
+    /// timestamp ("first", "last", "min", "max" or "midpoint"). `field` aggregates a
+    /// named field of multi-field metrics, as if `select_field(field)` came first.
+    /// `accuracy` ("exact", "approximate" or "auto") chooses between exact and sketch-based
+    /// computation for percentiles ("median", "p90", "p99.9", ...) and "count_distinct";
+    /// "auto" goes approximate on large inputs, and `last_run_stats()` reports the choice.
+    #[pyo3(signature = (agg_type, overflow=None, rounding=None, missing=None, fill_value=None, timestamp="first", field=None, accuracy=None))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn aggregate(
+        &mut self,
+        _py: Python<'_>,
+        agg_type: &str,
+        overflow: Option<&str>,
+        rounding: Option<&str>,
+        missing: Option<&str>,
+        fill_value: Option<f64>,
+        timestamp: &str,
+        field: Option<String>,
+        accuracy: Option<&str>,
+    ) -> PyResult<()> {
+        let options = AggregationOptions { overflow, rounding, missing, fill_value, accuracy };
+        let aggregation = resolve_aggregation(agg_type, &options)?;
+        let timestamp_policy = TimestampPolicy::parse(timestamp)?;
+        if let Some(field) = field {
+            self.strategies.push(Box::new(SelectFieldTransformation::new(field)));
+        }
+        self.strategies.push(Box::new(
+            AggregationTransformation::new(aggregation).with_timestamp_policy(timestamp_policy),
+        ));
+        Ok(())
+    }
+    
+    /// Add a time grouping transformation with an aggregation to the pipeline
+    ///
+    /// Each label is grouped separately and keeps its label. Takes the same
+    /// aggregation options as `aggregate`. When `deduplicate` is given,
+    /// metrics sharing a timestamp and label are collapsed with that strategy first.
+    /// `field` groups a named field of multi-field metrics.
+    #[pyo3(signature = (time_grouping_type, agg_type, overflow=None, rounding=None, missing=None, fill_value=None, deduplicate=None, field=None, accuracy=None))]
+    #[allow(clippy::too_many_arguments)]
+    pub fn group_by_time(
+        &mut self,
+        _py: Python<'_>,
+        time_grouping_type: &str,
+        agg_type: &str,
+        overflow: Option<&str>,
+        rounding: Option<&str>,
+        missing: Option<&str>,
+        fill_value: Option<f64>,
+        deduplicate: Option<&str>,
+        field: Option<String>,
+        accuracy: Option<&str>,
+    ) -> PyResult<()> {
+        let options = AggregationOptions { overflow, rounding, missing, fill_value, accuracy };
+        let aggregation = resolve_aggregation(agg_type, &options)?;
+        let deduplication = deduplicate.map(DuplicateStrategy::parse).transpose()?;
+        
+        with_registry(|registry| {
+            // Find the time grouping
+            let time_grouping = registry.get_time_grouping(time_grouping_type)
+                .ok_or_else(|| pyo3::exceptions::PyValueError::new_err(
+                    format!("Unknown time grouping type: {}", time_grouping_type)
+                ))?;
+            
+            if let Some(field) = field {
+                self.strategies.push(Box::new(SelectFieldTransformation::new(field)));
+            }
+            if let Some(strategy) = deduplication {
+                self.strategies.push(Box::new(DeduplicateTransformation::new(strategy)));
+            }
+            self.strategies.push(Box::new(TimeGroupingTransformation::new(
+                time_grouping.clone_box(),
+                aggregation,
+            )));
+            
+            Ok(())
+        })
+    }
+    
+    /// Add a step that checks for negative, zero or far-future timestamps
+    ///
+    /// In "reject" mode the pipeline fails on the first offender; in "drop" mode
+    /// offenders are removed and the remaining metrics continue.
+    #[pyo3(signature = (mode="reject", max_future_seconds=Some(DEFAULT_MAX_FUTURE_SECONDS), allow_zero=false, allow_negative=false))]
+    pub fn validate_timestamps(
+        &mut self,
+        mode: &str,
+        max_future_seconds: Option<i64>,
+        allow_zero: bool,
+        allow_negative: bool,
+    ) -> PyResult<()> {
+        let mode = TimestampValidationMode::parse(mode)?;
+        let rules = TimestampRules { allow_negative, allow_zero, max_future_seconds };
+        self.strategies.push(Box::new(TimestampValidationTransformation::new(rules, mode)));
+        Ok(())
+    }
+    
+    /// Add a step that collapses metrics sharing a timestamp and label
+    ///
+    /// `strategy` is one of "keep_first", "keep_last", "sum", "average" or "error".
+    #[pyo3(signature = (strategy="keep_last"))]
+    pub fn deduplicate(&mut self, strategy: &str) -> PyResult<()> {
+        let strategy = DuplicateStrategy::parse(strategy)?;
+        self.strategies.push(Box::new(DeduplicateTransformation::new(strategy)));
+        Ok(())
+    }
+    
+    /// Add a step that flags metrics deviating strongly from their baseline
+    ///
+    /// `method` is "zscore" or "mad"; a metric is anomalous when its score exceeds
+    /// `threshold`. With `window`, each metric is scored against the preceding `window`
+    /// metrics instead of the whole series. `output="flag"` returns every metric with
+    /// anomalies relabeled "anomaly" (or "<label>:anomaly"); "extract" returns only anomalies.
+    #[pyo3(signature = (method="zscore", threshold=3.0, window=None, output="flag"))]
+    pub fn detect_anomalies(
+        &mut self,
+        method: &str,
+        threshold: f64,
+        window: Option<usize>,
+        output: &str,
+    ) -> PyResult<()> {
+        let mut step = AnomalyDetectionTransformation::new(AnomalyMethod::parse(method)?, threshold)?
+            .with_output(AnomalyOutput::parse(output)?);
+        if let Some(window) = window {
+            step = step.with_window(window)?;
+        }
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a step that splits the series into trend, seasonal and residual components
+    ///
+    /// `period` is the number of points in one season (e.g. 24 for hourly data with a
+    /// daily cycle). The series must be regularly spaced; the result holds three series
+    /// labeled "trend", "seasonal" and "residual". `period` must be at least 2.
+    pub fn decompose(&mut self, period: usize) -> PyResult<()> {
+        self.strategies.push(Box::new(SeasonalDecompositionTransformation::new(period)?));
+        Ok(())
+    }
+    
+    /// Add a step that replaces the series with `horizon` predicted future points
+    ///
+    /// `method` is "holt_winters" (needs `period`, the number of points per season) or
+    /// "holt" for series without seasonality. The series must be regularly spaced;
+    /// predictions continue at the same spacing and are labeled "forecast". `period`
+    /// must be at least 2 and the smoothing factors in (0, 1].
+    #[pyo3(signature = (horizon, method="holt_winters", period=None, alpha=0.5, beta=0.1, gamma=0.1))]
+    pub fn forecast(
+        &mut self,
+        horizon: usize,
+        method: &str,
+        period: Option<usize>,
+        alpha: f64,
+        beta: f64,
+        gamma: f64,
+    ) -> PyResult<()> {
+        let mut step = ForecastTransformation::new(ForecastMethod::parse(method)?, horizon)
+            .with_params(SmoothingParams { alpha, beta, gamma })?;
+        if let Some(period) = period {
+            step = step.with_period(period)?;
+        }
+        self.strategies.push(Box::new(step.checked()?));
+        Ok(())
+    }
+    
+    /// Add a step that fits a least-squares line of value over time
+    ///
+    /// `output="line"` returns the fitted value at each input timestamp; "summary"
+    /// returns "slope" (per `per_seconds` seconds) and "intercept" (the fitted value
+    /// at the first timestamp). Use `execute_as_float` to keep fractional slopes.
+    #[pyo3(signature = (output="line", per_seconds=1))]
+    pub fn trend(&mut self, output: &str, per_seconds: i64) -> PyResult<()> {
+        let step = TrendTransformation::new(TrendOutput::parse(output)?).with_slope_unit(per_seconds)?;
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a terminal step that replaces the stream with value counts per bucket
+    ///
+    /// With `bounds`, buckets end at each (inclusive) upper bound plus a final "+Inf"
+    /// bucket; otherwise `buckets` equal-width buckets span the observed range. Each
+    /// count is labeled "le=<upper bound>".
+    #[pyo3(signature = (buckets=10, bounds=None))]
+    pub fn histogram(&mut self, buckets: usize, bounds: Option<Vec<f64>>) -> PyResult<()> {
+        let buckets = match bounds {
+            Some(bounds) => HistogramBuckets::Bounds(bounds),
+            None => HistogramBuckets::Auto(buckets),
+        };
+        self.strategies.push(Box::new(HistogramTransformation::new(buckets)?));
+        Ok(())
+    }
+    
+    /// Add a step that emits a metric wherever the series crosses `threshold`
+    ///
+    /// `direction` is "up", "down" or "both". Each emitted metric is the first one on
+    /// the far side of the threshold, labeled "crossing_up" or "crossing_down".
+    #[pyo3(signature = (threshold, direction="both"))]
+    pub fn crossings(&mut self, threshold: f64, direction: &str) -> PyResult<()> {
+        let direction = CrossingDirection::parse(direction)?;
+        self.strategies.push(Box::new(CrossingTransformation::new(threshold, direction)));
+        Ok(())
+    }
+    
+    /// Add a step emitting the percent change from the same label `period` seconds earlier
+    ///
+    /// Use after `group_by_time` (e.g. `period=3600` for hour-over-hour). Integer results
+    /// are rounded to whole percent; use `execute_as_float` for fractional changes.
+    pub fn pct_change(&mut self, period: i64) -> PyResult<()> {
+        self.strategies.push(Box::new(PercentChangeTransformation::new(period)?));
+        Ok(())
+    }
+    
+    /// Add a step replacing each value with a percentile of its rolling window
+    ///
+    /// `quantile` is in [0, 1] (0.95 for a moving p95). Give exactly one of `count`
+    /// (the last N metrics) or `seconds` (metrics within the last N seconds). Each
+    /// label is rolled independently.
+    #[pyo3(signature = (quantile, count=None, seconds=None))]
+    pub fn rolling_percentile(&mut self, quantile: f64, count: Option<usize>, seconds: Option<i64>) -> PyResult<()> {
+        let window = match (count, seconds) {
+            (Some(count), None) => RollingWindow::Count(count),
+            (None, Some(seconds)) => RollingWindow::Duration(seconds),
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "Specify exactly one of 'count' or 'seconds' for the rolling window",
+                ))
+            }
+        };
+        self.strategies.push(Box::new(RollingPercentileTransformation::new(quantile, window)?));
+        Ok(())
+    }
+    
+    /// Add a step replacing each value with the "sum", "mean", "min" or "max" of its rolling window
+    ///
+    /// Give exactly one of `window`, a duration such as "5m" or a number of seconds, or
+    /// `count` (the last N metrics). Each label is rolled independently, in O(n).
+    #[pyo3(signature = (how, window=None, count=None))]
+    pub fn rolling(&mut self, how: &str, window: Option<&Bound<'_, PyAny>>, count: Option<usize>) -> PyResult<()> {
+        let window = match (window, count) {
+            (Some(window), None) => match window.extract::<i64>() {
+                Ok(seconds) => RollingWindow::Duration(seconds),
+                Err(_) => RollingWindow::Duration(parse_duration(window.extract()?)?),
+            },
+            (None, Some(count)) => RollingWindow::Count(count),
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(
+                    "Specify exactly one of 'window' or 'count' for the rolling window",
+                ))
+            }
+        };
+        self.strategies.push(Box::new(RollingReduceTransformation::new(RollingReduction::parse(how)?, window)?));
+        Ok(())
+    }
+    
+    /// Add a step finding series that went quiet for more than `max_gap_seconds`
+    ///
+    /// With `on_gap="mark"` (the default) a staleness marker (`Metric.stale`) is added
+    /// where each gap begins, `max_gap_seconds` after the last sample, so "not
+    /// reporting" can be told apart from zero. Value filters keep markers, aggregations
+    /// and time grouping skip them, and value steps such as `derivative` never see
+    /// them; float runs can't carry markers and fail on a gap. With `on_gap="error"`
+    /// the run fails instead. Give `now` to also catch series whose last heartbeat is
+    /// too old.
+    #[pyo3(signature = (max_gap_seconds, on_gap="mark", now=None))]
+    pub fn detect_gaps(&mut self, max_gap_seconds: i64, on_gap: &str, now: Option<i64>) -> PyResult<()> {
+        let step = GapDetectionTransformation::new(max_gap_seconds, GapAction::parse(on_gap)?)?;
+        self.strategies.push(Box::new(match now {
+            Some(now) => step.with_heartbeat(now),
+            None => step,
+        }));
+        Ok(())
+    }
+    
+    /// Add a step turning each label's counter samples into reset-aware increases
+    ///
+    /// A drop in value is taken as a restart from zero, so the increase across it is
+    /// the value after the reset instead of a negative delta; each reset adds a
+    /// `counter_reset` warning. By default every sample but the first becomes the
+    /// increase since the previous one; with `cumulative=True` every sample becomes the
+    /// total increase since the start of the series, a monotonic counter from zero.
+    #[pyo3(signature = (cumulative=false))]
+    pub fn counter_increase(&mut self, cumulative: bool) {
+        self.strategies.push(Box::new(CounterIncreaseTransformation::new().cumulative(cumulative)));
+    }
+    
+    /// Add a step declaring the unit of the input timestamps: "s", "ms", "us" or "ns"
+    ///
+    /// Timestamps are converted to the settings' `timestamp_precision`, so epoch
+    /// milliseconds bucket correctly in a pipeline counting seconds. Put it first.
+    /// A timestamp implausible in `unit` (beyond the year 5138, or before 1973 for a
+    /// sub-second unit) fails the run with the unit it more likely is.
+    pub fn timestamp_unit(&mut self, unit: &str) -> PyResult<()> {
+        self.strategies.push(Box::new(TimestampUnitTransformation::new(TimestampPrecision::parse(unit)?)));
+        Ok(())
+    }
+    
+    /// Add a step emitting the change of the rate (second derivative) of each label's series
+    ///
+    /// Both the rate and its change are expressed per `per_seconds` seconds, so with
+    /// `per_seconds=60` the result is change-per-minute, per minute.
+    #[pyo3(signature = (per_seconds=1))]
+    pub fn acceleration(&mut self, per_seconds: i64) -> PyResult<()> {
+        let step = DerivativeTransformation::new(2)?.with_time_unit(per_seconds)?;
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a step rescaling values with "minmax" (to [0, 1]) or "zscore"
+    ///
+    /// With `per_label`, each label is rescaled independently. Normalized values are
+    /// fractional, so run the pipeline with `execute_as_float` or `execute_float`.
+    #[pyo3(signature = (method="minmax", per_label=false))]
+    pub fn normalize(&mut self, method: &str, per_label: bool) -> PyResult<()> {
+        let step = NormalizeTransformation::new(NormalizeMethod::parse(method)?).per_label(per_label);
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a debug step that passes a sample of the stream to `callback` unchanged
+    ///
+    /// `callback` receives a list of the first `sample` metrics (all of them when
+    /// `sample=None`) as `Metric` or, under `execute_float`, `FloatMetric` objects.
+    /// An exception raised by the callback fails the pipeline at this step. The
+    /// callback runs as plugin "tap", so `trusted_only` and `plugin_timeout` apply.
+    #[pyo3(signature = (callback, sample=Some(100)))]
+    pub fn tap(&mut self, callback: PyObject, sample: Option<usize>) -> PyResult<()> {
+        let callback = Arc::new(callback);
+        let step = TapTransformation::new(move |batch| call_with_batch("tap", &callback, batch).map(|_| ()))
+        .with_sample(sample)
+        .with_thread_bound(true);
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a guardrail step that fails the pipeline when the stream violates `predicate`
+    ///
+    /// `predicate` is "non_empty", "sorted" (timestamps never decrease), "no_missing",
+    /// "within_range" (every value within `min..=max`; give at least one bound), or a
+    /// callable taking the list of metrics and returning a truthy value when the data
+    /// is acceptable. The raised error carries `code="assertion_failed"` and `message`.
+    /// A callable runs as plugin "assert_that", under `trusted_only` and `plugin_timeout`.
+    #[pyo3(signature = (predicate, message=None, min=None, max=None))]
+    pub fn assert_that(
+        &mut self,
+        predicate: &Bound<'_, PyAny>,
+        message: Option<String>,
+        min: Option<f64>,
+        max: Option<f64>,
+    ) -> PyResult<()> {
+        let assertion = if let Ok(name) = predicate.extract::<&str>() {
+            Assertion::parse(name, min, max)?
+        } else if predicate.is_callable() {
+            let predicate = Arc::new(predicate.clone().unbind());
+            Assertion::Custom(Arc::new(move |batch| {
+                let verdict = call_with_batch("assert_that", &predicate, batch)?;
+                Python::with_gil(|py| verdict.is_truthy(py)).map_err(|e| MetricQueryError::OperationFailed {
+                    operation: "assert_that".to_string(),
+                    reason: format!("predicate result has no truth value: {}", e),
+                })
+            }))
+        } else {
+            return Err(pyo3::exceptions::PyTypeError::new_err(
+                "predicate must be an assertion name or a callable",
+            ));
+        };
+        
+        let thread_bound = matches!(assertion, Assertion::Custom(_));
+        let mut step = AssertionTransformation::new(assertion).with_thread_bound(thread_bound);
+        if let Some(message) = message {
+            step = step.with_message(message);
+        }
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a step making the named field of multi-field metrics their value
+    ///
+    /// Metrics without the field are dropped with a `missing_field` warning.
+    pub fn select_field(&mut self, field: String) {
+        self.strategies.push(Box::new(SelectFieldTransformation::new(field)));
+    }
+    
+    /// Add a step converting values from unit `from` to unit `to`, e.g. ("bytes", "MiB")
+    ///
+    /// Supports time units ("ns" to "d"), byte and bit sizes ("KB", "MiB", ...) and
+    /// SI prefixes on any other unit ("kW" to "W"). Metrics in another unit fail the
+    /// run, and so do metrics without a unit unless `assume_unit` says they are in
+    /// `from`. Use `execute_as_float` to keep fractional results.
+    #[pyo3(signature = (from, to, assume_unit=false))]
+    pub fn convert_unit(&mut self, from: String, to: String, assume_unit: bool) -> PyResult<()> {
+        self.strategies.push(Box::new(ConvertUnitTransformation::new(from, to)?.with_assume_unit(assume_unit)));
+        Ok(())
+    }
+    
+    /// Add a step bringing fixed-point values to `scale` decimal places
+    ///
+    /// Rescale to more places before `aggregate("avg")` to keep more digits of the
+    /// mean; dropping places rounds with `rounding` ("truncate", "round", "floor" or
+    /// "ceil"). Plain integer metrics are treated as having no decimal places.
+    #[pyo3(signature = (scale, rounding="round"))]
+    pub fn rescale(&mut self, scale: u32, rounding: &str) -> PyResult<()> {
+        self.strategies.push(Box::new(RescaleTransformation::new(scale, RoundingMode::parse(rounding)?)?));
+        Ok(())
+    }
+    
+    /// Add a step shifting timestamps between UTC and the IANA timezone `tz`
+    ///
+    /// With `direction="to_local"` (the default), UTC timestamps become `tz`'s
+    /// wall-clock time, so exported results match a consumer's local clock. Use
+    /// "from_local" on ingest to turn local wall-clock timestamps into UTC.
+    #[pyo3(signature = (tz, direction="to_local"))]
+    pub fn convert_timezone(&mut self, tz: &str, direction: &str) -> PyResult<()> {
+        let step = TimezoneShiftTransformation::new(parse_timezone(tz)?, TimezoneDirection::parse(direction)?);
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a step re-projecting each label's series onto a fixed grid of `interval` seconds
+    ///
+    /// Produces exactly one point per grid step between each series' first and last
+    /// metric. Steps with metrics are combined with the `how` aggregation ("mean",
+    /// "sum", "min", "max" or any registered one); empty steps are filled with the
+    /// `previous` value, `linear`ly interpolated, or left missing with "null" (float
+    /// execution yields NaN; integer execution emits a staleness marker).
+    #[pyo3(signature = (interval, how="mean", fill="previous"))]
+    pub fn resample(&mut self, interval: i64, how: &str, fill: &str) -> PyResult<()> {
+        let aggregation = resolve_aggregation(resample_aggregation_name(how), &AggregationOptions::default())?;
+        let step = ResampleTransformation::new(interval, aggregation, ResampleFill::parse(fill)?);
+        self.strategies.push(Box::new(step));
+        Ok(())
+    }
+    
+    /// Add a step keeping only the most recent metric of each label
+    ///
+    /// Gives the current value of every series; on a timestamp tie the metric that
+    /// comes last wins.
+    pub fn latest(&mut self) {
+        self.strategies.push(Box::new(LatestTransformation::new()));
+    }
+    
+    /// Add a step keeping only the `k` label series with the highest `agg` of their values
+    ///
+    /// `agg` is "sum", "avg", "max" or any registered aggregation; all points of the
+    /// selected series are kept. Ties go to the series that appears first.
+    #[pyo3(signature = (k, agg="sum"))]
+    pub fn topk_series(&mut self, k: usize, agg: &str) -> PyResult<()> {
+        let aggregation = resolve_aggregation(agg, &AggregationOptions::default())?;
+        self.strategies.push(Box::new(TopKSeriesTransformation::new(k, aggregation)?));
+        Ok(())
+    }
+    
+    /// Add a step keeping the first metric and every `n`th one after it
+    ///
+    /// Deterministic, for downsizing a huge stream before expensive steps; positions
+    /// count across the whole input rather than per label.
+    pub fn sample_every(&mut self, n: usize) -> PyResult<()> {
+        self.strategies.push(Box::new(SampleEveryTransformation::new(n)?));
+        Ok(())
+    }
+    
+    /// Add a step keeping each metric with probability `p`
+    ///
+    /// Pass `seed` for a reproducible sample: the same seed keeps the same positions
+    /// on every run. Without one each run samples afresh.
+    #[pyo3(signature = (p, seed=None))]
+    pub fn sample_fraction(&mut self, p: f64, seed: Option<u64>) -> PyResult<()> {
+        self.strategies.push(Box::new(SampleFractionTransformation::new(p, seed)?));
+        Ok(())
+    }
+    
+    /// Add a filter keeping values from `lo` to `hi`, bounds included unless
+    /// `inclusive=False`; one step instead of chained "gt" and "lt" filters.
+    /// Bounds are ints or floats, compared with fixed-point values at their scale.
+    #[pyo3(signature = (lo, hi, inclusive=true))]
+    pub fn between(&mut self, lo: Threshold, hi: Threshold, inclusive: bool) -> PyResult<()> {
+        let filter = BetweenFilter::new(lo, hi, inclusive)?;
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a filter keeping metrics whose value is one of `values`, e.g. status codes
+    pub fn value_in(&mut self, values: Vec<i64>) {
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(ValueInFilter::new(values)))));
+    }
+    
+    /// Add a filter keeping metrics with `start_ts <= timestamp < end_ts`
+    ///
+    /// Bounds are int timestamps in the pipeline's units or datetimes (naive ones are
+    /// UTC), so a window can be sliced out before aggregating.
+    pub fn filter_time_range(&mut self, start_ts: &Bound<'_, PyAny>, end_ts: &Bound<'_, PyAny>) -> PyResult<()> {
+        let filter = TimestampRangeFilter::new(pipeline_timestamp(start_ts)?, pipeline_timestamp(end_ts)?)?;
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a filter keeping metrics timestamped "before", "after" or "at" `instant`
+    ///
+    /// `instant` is an int timestamp in the pipeline's units or a datetime (naive
+    /// ones are UTC); "before" and "after" exclude the instant itself.
+    pub fn filter_timestamp(&mut self, op: &str, instant: &Bound<'_, PyAny>) -> PyResult<()> {
+        let filter = TimestampFilter::new(TimestampComparison::parse(op)?, pipeline_timestamp(instant)?);
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a filter keeping metrics taken on the given days, e.g. `["mon", "tue"]`,
+    /// in the settings' timezone
+    pub fn filter_weekdays(&mut self, days: Vec<String>) -> PyResult<()> {
+        let filter = WeekdayFilter::parse(&days)?;
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a filter keeping metrics taken from hour `start` up to (not including) hour
+    /// `end`, in the settings' timezone
+    ///
+    /// `filter_hours(9, 17)` with `filter_weekdays` selects business hours; a `start`
+    /// after `end` wraps past midnight.
+    pub fn filter_hours(&mut self, start: u32, end: u32) -> PyResult<()> {
+        let filter = HourOfDayFilter::new(start, end)?;
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a label filter transformation to the pipeline ("label_eq" or "label_ne")
+    pub fn filter_by_label(&mut self, _py: Python<'_>, filter_type: &str, label: String) -> PyResult<()> {
+        let filter_box: Box<dyn FilterPlugin> = match filter_type {
+            "label_eq" => Box::new(LabelFilter::new(label)),
+            "label_ne" => Box::new(LabelFilter::not_equal(label)),
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Invalid label filter type: {}. Expected 'label_eq' or 'label_ne'",
+                    filter_type
+                )))
+            }
+        };
+        self.strategies.push(Box::new(FilterTransformation::new(filter_box)));
+        Ok(())
+    }
+    
+    /// Add a label inclusion filter transformation to the pipeline ("label_in" or "label_not_in")
+    pub fn filter_by_labels(&mut self, _py: Python<'_>, filter_type: &str, labels: Vec<String>) -> PyResult<()> {
+        let filter_box: Box<dyn FilterPlugin> = match filter_type {
+            "label_in" => Box::new(LabelInFilter::new(labels)),
+            "label_not_in" => Box::new(LabelInFilter::not_in(labels)),
+            _ => {
+                return Err(pyo3::exceptions::PyValueError::new_err(format!(
+                    "Invalid label filter type: {}. Expected 'label_in' or 'label_not_in'",
+                    filter_type
+                )))
+            }
+        };
+        self.strategies.push(Box::new(FilterTransformation::new(filter_box)));
+        Ok(())
+    }
+    
+    /// Add a filter on the tag `key`, with `op` one of "eq", "ne", "regex" (matching
+    /// the whole value) or "in" (with a list of values)
+    ///
+    /// Metrics without the tag only pass "ne".
+    pub fn filter_by_tag(&mut self, key: String, op: &str, value: TagValue) -> PyResult<()> {
+        let filter = TagFilter::new(key, TagMatch::parse(op, value)?);
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a filter keeping metrics that satisfy `expr`, e.g.
+    /// `"value > 10 and value <= 100 and label == 'cpu'"`
+    ///
+    /// See the `expr` module for the attributes and operators; syntax and type
+    /// errors raise here rather than when the pipeline runs.
+    pub fn filter_expr(&mut self, expr: &str) -> PyResult<()> {
+        let filter = ExprFilter::new(expr)?;
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a filter keeping metrics for which `predicate(metric)` is truthy, for
+    /// one-off conditions that don't warrant a registered plugin
+    ///
+    /// `on_error` works as for `TransformationRegistry.register_filter`: "skip" logs
+    /// an exception and drops the metric, "raise" fails the step.
+    #[pyo3(signature = (predicate, on_error="skip"))]
+    pub fn filter_py(&mut self, predicate: &Bound<'_, PyAny>, on_error: &str) -> PyResult<()> {
+        let filter = PyCallableFilter::new("filter_py", require_callable(predicate)?).raise_errors(raise_on_error(on_error)?);
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+        Ok(())
+    }
+    
+    /// Add a filter dropping metrics labeled `label`
+    ///
+    /// Unlabeled metrics are kept; pass `keep_unlabeled=False` to drop them too.
+    #[pyo3(signature = (label, keep_unlabeled=true))]
+    pub fn label_ne(&mut self, label: String, keep_unlabeled: bool) {
+        let filter = LabelFilter::not_equal(label).keep_unlabeled(keep_unlabeled);
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+    }
+    
+    /// Add a filter dropping metrics whose label is one of `labels`
+    ///
+    /// Unlabeled metrics are kept; pass `keep_unlabeled=False` to drop them too.
+    #[pyo3(signature = (labels, keep_unlabeled=true))]
+    pub fn label_not_in(&mut self, labels: Vec<String>, keep_unlabeled: bool) {
+        let filter = LabelInFilter::not_in(labels).keep_unlabeled(keep_unlabeled);
+        self.strategies.push(Box::new(FilterTransformation::new(Box::new(filter))));
+    }
+    
+    /// Execute the pipeline and return the result
+    ///
+    /// Non-fatal issues are reported through Python's `warnings` module as `MetricQueryWarning`.
+    pub fn execute(&self) -> PyResult<Vec<Metric>> {
+        let (result, warnings) = self.run_with_warnings()?;
+        if !warnings.is_empty() {
+            Python::with_gil(|py| emit_python_warnings(py, &warnings))?;
+        }
+        Ok(result)
+    }
+    
+    /// Execute the pipeline and return only count, sum, min, max and mean of the result
+    ///
+    /// No result metrics are converted to Python objects, and the last step folds its
+    /// output into the summary as it goes (see `run_summary`).
+    pub fn execute_summary(&self) -> PyResult<ResultSummary> {
+        let (summary, warnings) = self.run_summary_with_warnings()?;
+        if !warnings.is_empty() {
+            Python::with_gil(|py| emit_python_warnings(py, &warnings))?;
+        }
+        Ok(summary)
+    }
+    
+    /// Execute the pipeline and return one `SketchMetric` per label and "minute", "hour"
+    /// or "day" group, ready to merge with sketches from other nodes
+    #[pyo3(signature = (grouping, relative_accuracy=0.01))]
+    pub fn execute_sketch(&self, grouping: &str, relative_accuracy: f64) -> PyResult<Vec<SketchMetric>> {
+        let grouping = crate::plugin_impls::create_time_grouping(grouping)?;
+        let result: Vec<FloatMetric> = self.execute()?.iter().map(FloatMetric::from).collect();
+        Ok(SketchMetric::from_metrics(&result, grouping.as_ref(), relative_accuracy)?)
+    }
+    
+    /// Execute the pipeline and return `{label: [Metric, ...]}`, with unlabeled metrics under `None`
+    pub fn execute_grouped(&self) -> PyResult<BTreeMap<Option<String>, Vec<Metric>>> {
+        Ok(group_by_label(self.execute()?))
+    }
+    
+    /// Execute the pipeline and return count, min, max, mean, stddev, percentiles and the
+    /// earliest and latest timestamps of the result
+    ///
+    /// `percentiles` are quantiles in [0, 1]; the default is 0.5, 0.9 and 0.99.
+    #[pyo3(signature = (percentiles=None))]
+    pub fn describe(&self, percentiles: Option<Vec<f64>>) -> PyResult<MetricSummary> {
+        let percentiles = percentiles.unwrap_or_else(|| DEFAULT_PERCENTILES.to_vec());
+        Ok(self.describe_metrics(&percentiles)?)
+    }
+    
+    /// Execute the pipeline's steps over float metrics instead of the pipeline's own metrics
+    pub fn execute_float(&self, metrics: Vec<FloatMetric>) -> PyResult<Vec<FloatMetric>> {
+        Ok(self.execute_float_metrics(&metrics)?)
+    }
+    
+    /// Execute the pipeline's own metrics as floats, so averages come back exact
+    pub fn execute_as_float(&self) -> PyResult<Vec<FloatMetric>> {
+        let metrics: Vec<FloatMetric> = self.input().iter().map(FloatMetric::from).collect();
+        self.execute_float(metrics)
+    }
+    
+    /// Execute the pipeline over parallel value/timestamp arrays, returning `(values, timestamps)`
+    pub fn execute_arrays(&self, values: Vec<i64>, timestamps: Vec<i64>) -> PyResult<(Vec<i64>, Vec<i64>)> {
+        Ok(self.execute_columns(&values, &timestamps)?)
+    }
+    
+    /// Input/output counts, per-step counts and wall time of the most recent
+    /// successful execution, or `None` if the pipeline hasn't run yet
+    #[pyo3(name = "last_run_stats")]
+    fn py_last_run_stats(&self) -> Option<RunStats> {
+        self.last_run_stats()
+    }
 }
\ No newline at end of file
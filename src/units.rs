@@ -0,0 +1,144 @@
+//! Unit conversion table used by the `convert_unit` step.
+//!
+//! Known units are time ("ns", "us", "ms", "s", "min", "h", "d") and data
+//! sizes, in decimal ("KB", "MB", ...) and binary ("KiB", "MiB", ...) multiples
+//! of bytes or bits. Any other unit may carry an SI prefix ("kW" to "W",
+//! "mV" to "V"), so it converts to the same unit with a different prefix.
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+
+/// Physical quantity a unit measures; only units of the same dimension convert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Dimension {
+    Time,
+    Data,
+}
+
+/// Known units with their size in the dimension's base unit (seconds or bytes)
+const UNITS: &[(&str, Dimension, f64)] = &[
+    ("ns", Dimension::Time, 1e-9),
+    ("us", Dimension::Time, 1e-6),
+    ("µs", Dimension::Time, 1e-6),
+    ("ms", Dimension::Time, 1e-3),
+    ("s", Dimension::Time, 1.0),
+    ("min", Dimension::Time, 60.0),
+    ("h", Dimension::Time, 3_600.0),
+    ("d", Dimension::Time, 86_400.0),
+    ("bit", Dimension::Data, 0.125),
+    ("bits", Dimension::Data, 0.125),
+    ("kbit", Dimension::Data, 125.0),
+    ("Kbit", Dimension::Data, 125.0),
+    ("Mbit", Dimension::Data, 125e3),
+    ("Gbit", Dimension::Data, 125e6),
+    ("Tbit", Dimension::Data, 125e9),
+    ("B", Dimension::Data, 1.0),
+    ("bytes", Dimension::Data, 1.0),
+    ("KB", Dimension::Data, 1e3),
+    ("kB", Dimension::Data, 1e3),
+    ("MB", Dimension::Data, 1e6),
+    ("GB", Dimension::Data, 1e9),
+    ("TB", Dimension::Data, 1e12),
+    ("PB", Dimension::Data, 1e15),
+    ("KiB", Dimension::Data, 1_024.0),
+    ("MiB", Dimension::Data, 1_048_576.0),
+    ("GiB", Dimension::Data, 1_073_741_824.0),
+    ("TiB", Dimension::Data, 1_099_511_627_776.0),
+    ("PiB", Dimension::Data, 1_125_899_906_842_624.0),
+];
+
+/// SI prefixes for units outside the table
+const SI_PREFIXES: &[(&str, f64)] = &[
+    ("P", 1e15),
+    ("T", 1e12),
+    ("G", 1e9),
+    ("M", 1e6),
+    ("k", 1e3),
+    ("m", 1e-3),
+    ("u", 1e-6),
+    ("µ", 1e-6),
+    ("n", 1e-9),
+];
+
+fn known_unit(unit: &str) -> Option<(Dimension, f64)> {
+    UNITS.iter().find(|(name, _, _)| *name == unit).map(|&(_, dimension, size)| (dimension, size))
+}
+
+/// Split a unit into its base and SI multiplier; an unprefixed unit has multiplier 1
+fn split_prefix(unit: &str) -> impl Iterator<Item = (&str, f64)> + '_ {
+    let prefixed = SI_PREFIXES.iter().filter_map(move |&(prefix, multiplier)| {
+        unit.strip_prefix(prefix).filter(|base| !base.is_empty()).map(|base| (base, multiplier))
+    });
+    std::iter::once((unit, 1.0)).chain(prefixed)
+}
+
+/// Factor that turns a value in `from` into a value in `to`
+pub fn conversion_factor(from: &str, to: &str) -> MetricQueryResult<f64> {
+    if from == to {
+        return Ok(1.0);
+    }
+    let incompatible = || MetricQueryError::OperationFailed {
+        operation: "convert_unit".to_string(),
+        reason: format!("cannot convert '{}' to '{}'", from, to),
+    };
+
+    match (known_unit(from), known_unit(to)) {
+        (Some((from_dimension, from_size)), Some((to_dimension, to_size))) => {
+            if from_dimension == to_dimension {
+                Ok(from_size / to_size)
+            } else {
+                Err(incompatible())
+            }
+        }
+        (None, None) => split_prefix(from)
+            .find_map(|(from_base, from_multiplier)| {
+                split_prefix(to)
+                    .find(|&(to_base, _)| to_base == from_base)
+                    .map(|(_, to_multiplier)| from_multiplier / to_multiplier)
+            })
+            .ok_or_else(incompatible),
+        _ => Err(incompatible()),
+    }
+}
+
+/// The unit shared by a set of metrics, ignoring metrics without one.
+///
+/// Fails when two metrics carry different units, since combining them would
+/// silently mix scales (e.g. summing milliseconds with seconds). The error names
+/// the `convert_unit` call that fixes it when the units convert into each other.
+pub fn common_unit<'a>(
+    units: impl IntoIterator<Item = Option<&'a str>>,
+    operation: &str,
+) -> MetricQueryResult<Option<&'a str>> {
+    let mut common = None;
+    for unit in units.into_iter().flatten() {
+        match common {
+            None => common = Some(unit),
+            Some(seen) if seen != unit => {
+                let hint = match conversion_factor(unit, seen) {
+                    Ok(_) => format!("convert_unit('{}', '{}') first", unit, seen),
+                    Err(_) => "they measure different quantities".to_string(),
+                };
+                return Err(MetricQueryError::OperationFailed {
+                    operation: operation.to_string(),
+                    reason: format!("cannot combine metrics in '{}' and '{}'; {}", seen, unit, hint),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(common)
+}
+
+/// A unit found among `units` when some of them are `None`, with the number of
+/// `None`s; metrics without a unit are assumed to share it
+pub fn partly_unitless<'a>(units: impl IntoIterator<Item = Option<&'a str>>) -> Option<(&'a str, usize)> {
+    let mut unit = None;
+    let mut unitless = 0;
+    for metric_unit in units {
+        match metric_unit {
+            Some(metric_unit) => unit = unit.or(Some(metric_unit)),
+            None => unitless += 1,
+        }
+    }
+    unit.filter(|_| unitless > 0).map(|unit| (unit, unitless))
+}
@@ -0,0 +1,599 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+use chrono::Utc;
+use std::collections::HashMap;
+
+use crate::errors::{MetricQueryError, MetricQueryResult};
+use crate::models::Metric;
+use crate::transformations::TransformationStrategy;
+use crate::warnings::WarningSink;
+
+/// Default allowance for clock skew before a timestamp counts as "in the future"
+pub const DEFAULT_MAX_FUTURE_SECONDS: i64 = 24 * 60 * 60;
+
+/// Why a timestamp was flagged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampProblem {
+    /// Timestamp is before the Unix epoch
+    Negative,
+    /// Timestamp is exactly zero, usually an unset field
+    Zero,
+    /// Timestamp is further in the future than the allowed skew
+    FarFuture,
+}
+
+impl TimestampProblem {
+    /// Short machine-readable name of the problem
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Negative => "negative",
+            Self::Zero => "zero",
+            Self::FarFuture => "far_future",
+        }
+    }
+}
+
+/// Rules deciding which timestamps are plausible
+#[derive(Debug, Clone)]
+pub struct TimestampRules {
+    /// Accept timestamps before 1970
+    pub allow_negative: bool,
+    /// Accept a timestamp of exactly 0
+    pub allow_zero: bool,
+    /// Maximum number of seconds a timestamp may lie ahead of "now" (`None` disables the check)
+    pub max_future_seconds: Option<i64>,
+}
+
+impl Default for TimestampRules {
+    fn default() -> Self {
+        Self {
+            allow_negative: false,
+            allow_zero: false,
+            max_future_seconds: Some(DEFAULT_MAX_FUTURE_SECONDS),
+        }
+    }
+}
+
+impl TimestampRules {
+    /// Check a single timestamp against the rules, relative to `now`
+    pub fn check(&self, timestamp: i64, now: i64) -> Option<TimestampProblem> {
+        if timestamp < 0 && !self.allow_negative {
+            return Some(TimestampProblem::Negative);
+        }
+        if timestamp == 0 && !self.allow_zero {
+            return Some(TimestampProblem::Zero);
+        }
+        if let Some(max_future) = self.max_future_seconds {
+            if timestamp > now.saturating_add(max_future) {
+                return Some(TimestampProblem::FarFuture);
+            }
+        }
+        None
+    }
+}
+
+/// A single metric whose timestamp failed validation
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone)]
+pub struct TimestampIssue {
+    /// Position of the metric in the validated input
+    pub index: usize,
+    /// The offending timestamp
+    pub timestamp: i64,
+    /// Problem name: "negative", "zero" or "far_future"
+    pub reason: String,
+}
+
+/// Structured report of every metric with an implausible timestamp
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone)]
+pub struct TimestampReport {
+    /// Number of metrics that were checked
+    pub checked: usize,
+    /// Every offending metric, in input order
+    pub issues: Vec<TimestampIssue>,
+}
+
+#[cfg_attr(feature = "python", pymethods)]
+impl TimestampReport {
+    /// True when no timestamp was flagged
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Validate the timestamps of a metric stream relative to `now`
+pub fn validate_timestamps(metrics: &[Metric], rules: &TimestampRules, now: i64) -> TimestampReport {
+    let issues = metrics
+        .iter()
+        .enumerate()
+        .filter_map(|(index, metric)| {
+            rules.check(metric.timestamp, now).map(|problem| TimestampIssue {
+                index,
+                timestamp: metric.timestamp,
+                reason: problem.as_str().to_string(),
+            })
+        })
+        .collect();
+
+    TimestampReport { checked: metrics.len(), issues }
+}
+
+/// What the validation step does with offending metrics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampValidationMode {
+    /// Fail the pipeline on the first offender
+    Reject,
+    /// Drop offenders and continue with the remaining metrics
+    Drop,
+}
+
+impl TimestampValidationMode {
+    /// Parse a mode name ("reject" or "drop")
+    pub fn parse(mode: &str) -> MetricQueryResult<Self> {
+        match mode {
+            "reject" => Ok(Self::Reject),
+            "drop" => Ok(Self::Drop),
+            _ => Err(MetricQueryError::OperationFailed {
+                operation: "validate_timestamps".to_string(),
+                reason: format!("Unknown validation mode: {}. Expected 'reject' or 'drop'", mode),
+            }),
+        }
+    }
+}
+
+/// Pipeline step that checks timestamps before later transformations see them
+#[derive(Clone)]
+pub struct TimestampValidationTransformation {
+    rules: TimestampRules,
+    mode: TimestampValidationMode,
+}
+
+impl TimestampValidationTransformation {
+    /// Create a new timestamp validation step
+    pub fn new(rules: TimestampRules, mode: TimestampValidationMode) -> Self {
+        Self { rules, mode }
+    }
+}
+
+impl TransformationStrategy for TimestampValidationTransformation {
+    fn name(&self) -> String {
+        "validate_timestamps".to_string()
+    }
+
+    fn apply(&self, metrics: &[Metric]) -> MetricQueryResult<Vec<Metric>> {
+        let now = Utc::now().timestamp();
+
+        match self.mode {
+            TimestampValidationMode::Reject => {
+                let report = validate_timestamps(metrics, &self.rules, now);
+                match report.issues.first() {
+                    Some(first) => Err(MetricQueryError::InvalidTimestamp {
+                        index: first.index,
+                        timestamp: first.timestamp,
+                        reason: first.reason.clone(),
+                        offenders: report.issues.len(),
+                    }),
+                    None => Ok(metrics.to_vec()),
+                }
+            }
+            TimestampValidationMode::Drop => Ok(metrics
+                .iter()
+                .filter(|metric| self.rules.check(metric.timestamp, now).is_none())
+                .cloned()
+                .collect()),
+        }
+    }
+
+    fn apply_with_warnings(&self, metrics: &[Metric], warnings: &mut WarningSink) -> MetricQueryResult<Vec<Metric>> {
+        let result = self.apply(metrics)?;
+        let dropped = metrics.len() - result.len();
+        if dropped > 0 {
+            warnings.warn(
+                "dropped_invalid_timestamps",
+                format!("dropped {} metric(s) with implausible timestamps", dropped),
+            );
+        }
+        Ok(result)
+    }
+
+    fn handles_stale(&self) -> bool {
+        true
+    }
+}
+
+/// Default upper bound on label length accepted by `MetricBuilder`
+pub const DEFAULT_MAX_LABEL_LENGTH: usize = 128;
+
+/// Why a label is unusable: empty, too long, or containing whitespace or control characters
+fn label_problem(label: &str, max_label_length: usize) -> Option<String> {
+    if label.is_empty() {
+        Some("label is empty".to_string())
+    } else if label.chars().count() > max_label_length {
+        Some(format!("label is longer than {} characters", max_label_length))
+    } else if label.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Some(format!("label {:?} contains whitespace or control characters", label))
+    } else {
+        None
+    }
+}
+
+/// Default multiple of a series' median interval above which a gap counts as suspicious
+pub const DEFAULT_GAP_FACTOR: f64 = 3.0;
+
+/// What `validate_metrics` found wrong with a metric
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamProblem {
+    /// Timestamp is earlier than the previous metric of the same label
+    Unsorted,
+    /// Another metric of the same label has the same timestamp
+    Duplicate,
+    /// Value is lower than the one before it in time, for counters
+    CounterReset,
+    /// Label is empty, too long or malformed, or missing among labeled metrics
+    Label,
+    /// Time since the previous metric of the same label is suspiciously long
+    Gap,
+}
+
+impl StreamProblem {
+    /// Short machine-readable name of the problem
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unsorted => "unsorted",
+            Self::Duplicate => "duplicate",
+            Self::CounterReset => "counter_reset",
+            Self::Label => "label",
+            Self::Gap => "gap",
+        }
+    }
+}
+
+/// What `validate_metrics` checks beyond ordering, duplicates and labels
+#[derive(Debug, Clone)]
+pub struct StreamRules {
+    /// Treat values as counters and report every decrease as a reset
+    pub counter_resets: bool,
+    /// Flag gaps longer than this many seconds; `None` uses `gap_factor`
+    pub max_gap_seconds: Option<i64>,
+    /// Without `max_gap_seconds`, flag gaps longer than this multiple of the series' median interval
+    pub gap_factor: f64,
+    /// Maximum label length in characters
+    pub max_label_length: usize,
+}
+
+impl Default for StreamRules {
+    fn default() -> Self {
+        Self {
+            counter_resets: false,
+            max_gap_seconds: None,
+            gap_factor: DEFAULT_GAP_FACTOR,
+            max_label_length: DEFAULT_MAX_LABEL_LENGTH,
+        }
+    }
+}
+
+/// A single problem found by `validate_metrics`
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone)]
+pub struct StreamIssue {
+    /// Position of the offending metric in the validated input
+    pub index: usize,
+    pub timestamp: i64,
+    pub label: Option<String>,
+    /// Problem name: "unsorted", "duplicate", "counter_reset", "label" or "gap"
+    pub kind: String,
+    /// Human-readable explanation
+    pub detail: String,
+}
+
+/// Structured report of everything `validate_metrics` found suspicious
+#[cfg_attr(feature = "python", pyclass(get_all))]
+#[derive(Debug, Clone)]
+pub struct StreamReport {
+    /// Number of metrics that were checked
+    pub checked: usize,
+    /// Number of distinct labels (unlabeled metrics count as one series)
+    pub series: usize,
+    /// Every problem, ordered by metric position
+    pub issues: Vec<StreamIssue>,
+}
+
+#[cfg_attr(feature = "python", pymethods)]
+impl StreamReport {
+    /// True when nothing was flagged
+    pub fn is_valid(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Number of issues of one kind, e.g. "gap"
+    pub fn count(&self, kind: &str) -> usize {
+        self.issues.iter().filter(|issue| issue.kind == kind).count()
+    }
+}
+
+/// Check a metric stream for problems that would make pipeline output untrustworthy.
+///
+/// Each label's series is checked for out-of-order and duplicate timestamps, gaps and,
+/// with `counter_resets`, decreasing values; every label is checked for malformed names.
+/// Gaps and resets are judged in timestamp order, so unsorted input is still checked.
+pub fn validate_metrics(metrics: &[Metric], rules: &StreamRules) -> StreamReport {
+    let mut found: Vec<(usize, StreamProblem, String)> = Vec::new();
+
+    let mut series: HashMap<Option<&str>, Vec<usize>> = HashMap::new();
+    for (index, metric) in metrics.iter().enumerate() {
+        series.entry(metric.label.as_deref()).or_default().push(index);
+    }
+
+    // Mixing labeled and unlabeled metrics usually means a field went missing upstream
+    let has_labeled = series.keys().any(Option::is_some);
+    for (index, metric) in metrics.iter().enumerate() {
+        match metric.label.as_deref() {
+            Some(label) => {
+                if let Some(problem) = label_problem(label, rules.max_label_length) {
+                    found.push((index, StreamProblem::Label, problem));
+                }
+            }
+            None if has_labeled => {
+                found.push((index, StreamProblem::Label, "unlabeled metric among labeled ones".to_string()))
+            }
+            None => {}
+        }
+    }
+
+    for indices in series.values() {
+        for pair in indices.windows(2) {
+            let (previous, current) = (&metrics[pair[0]], &metrics[pair[1]]);
+            if current.timestamp < previous.timestamp {
+                let detail = format!("timestamp {} comes after {}", current.timestamp, previous.timestamp);
+                found.push((pair[1], StreamProblem::Unsorted, detail));
+            }
+        }
+
+        // Stable, so metrics sharing a timestamp stay in input order
+        let mut by_time = indices.clone();
+        by_time.sort_by_key(|&index| metrics[index].timestamp);
+
+        let mut intervals = Vec::new();
+        for pair in by_time.windows(2) {
+            let (previous, current) = (&metrics[pair[0]], &metrics[pair[1]]);
+            if current.timestamp == previous.timestamp {
+                found.push((pair[1], StreamProblem::Duplicate, format!("repeats the timestamp of metric {}", pair[0])));
+                continue;
+            }
+            if rules.counter_resets && current.value < previous.value {
+                let detail = format!("value dropped from {} to {}", previous.value, current.value);
+                found.push((pair[1], StreamProblem::CounterReset, detail));
+            }
+            intervals.push((pair[1], current.timestamp.saturating_sub(previous.timestamp)));
+        }
+
+        let threshold = match rules.max_gap_seconds {
+            Some(max_gap) => Some(max_gap as f64),
+            // A median needs a few intervals before a long one stands out
+            None if intervals.len() >= 2 => {
+                let mut lengths: Vec<i64> = intervals.iter().map(|&(_, length)| length).collect();
+                lengths.sort_unstable();
+                Some(lengths[lengths.len() / 2] as f64 * rules.gap_factor)
+            }
+            None => None,
+        };
+        if let Some(threshold) = threshold {
+            for &(index, length) in &intervals {
+                if length as f64 > threshold {
+                    found.push((index, StreamProblem::Gap, format!("{}s since the previous metric", length)));
+                }
+            }
+        }
+    }
+
+    found.sort_by_key(|&(index, problem, _)| (index, problem as u8));
+    let issues = found
+        .into_iter()
+        .map(|(index, problem, detail)| StreamIssue {
+            index,
+            timestamp: metrics[index].timestamp,
+            label: metrics[index].label.clone(),
+            kind: problem.as_str().to_string(),
+            detail,
+        })
+        .collect();
+
+    StreamReport { checked: metrics.len(), series: series.len(), issues }
+}
+
+/// Builds metrics, rejecting implausible values, timestamps and labels up front.
+///
+/// Values must lie within the optional inclusive range, timestamps must pass the
+/// `TimestampRules`, and labels must be non-empty, at most `max_label_length`
+/// characters and free of whitespace and control characters.
+#[cfg_attr(feature = "python", pyclass)]
+#[derive(Debug, Clone)]
+pub struct MetricBuilder {
+    min_value: Option<i64>,
+    max_value: Option<i64>,
+    timestamp_rules: TimestampRules,
+    max_label_length: usize,
+    require_label: bool,
+}
+
+impl Default for MetricBuilder {
+    fn default() -> Self {
+        Self {
+            min_value: None,
+            max_value: None,
+            timestamp_rules: TimestampRules::default(),
+            max_label_length: DEFAULT_MAX_LABEL_LENGTH,
+            require_label: false,
+        }
+    }
+}
+
+impl MetricBuilder {
+    /// Create a builder with default timestamp rules and no value range
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only accept values within `min..=max`; `None` leaves that side open
+    pub fn with_value_range(mut self, min: Option<i64>, max: Option<i64>) -> Self {
+        self.min_value = min;
+        self.max_value = max;
+        self
+    }
+
+    /// Replace the timestamp rules
+    pub fn with_timestamp_rules(mut self, rules: TimestampRules) -> Self {
+        self.timestamp_rules = rules;
+        self
+    }
+
+    /// Set the maximum label length in characters
+    pub fn with_max_label_length(mut self, max_label_length: usize) -> Self {
+        self.max_label_length = max_label_length;
+        self
+    }
+
+    /// Reject metrics without a label
+    pub fn require_label(mut self, require_label: bool) -> Self {
+        self.require_label = require_label;
+        self
+    }
+
+    /// Why the value or label at `index` is invalid, if it is
+    fn check_value_and_label(&self, index: usize, value: i64, label: Option<&str>) -> MetricQueryResult<()> {
+        let invalid = |reason: String| Err(MetricQueryError::InvalidMetric { index, reason });
+
+        if self.min_value.is_some_and(|min| value < min) || self.max_value.is_some_and(|max| value > max) {
+            let bound = |b: Option<i64>| b.map_or("unbounded".to_string(), |b| b.to_string());
+            return invalid(format!(
+                "value {} is outside [{}, {}]",
+                value,
+                bound(self.min_value),
+                bound(self.max_value)
+            ));
+        }
+
+        match label {
+            None if self.require_label => invalid("label is required".to_string()),
+            None => Ok(()),
+            Some(label) => match label_problem(label, self.max_label_length) {
+                Some(problem) => invalid(problem),
+                None => Ok(()),
+            },
+        }
+    }
+
+    /// Build and validate a single metric
+    pub fn build(&self, value: i64, timestamp: i64, label: Option<String>) -> MetricQueryResult<Metric> {
+        self.build_many(&[value], &[timestamp], Some(&[label])).map(|mut metrics| metrics.remove(0))
+    }
+
+    /// Build and validate metrics from parallel arrays.
+    ///
+    /// `labels`, when given, must match `values` in length. Fails on the first invalid
+    /// metric; timestamp errors report how many timestamps failed in total.
+    pub fn build_many(
+        &self,
+        values: &[i64],
+        timestamps: &[i64],
+        labels: Option<&[Option<String>]>,
+    ) -> MetricQueryResult<Vec<Metric>> {
+        if values.len() != timestamps.len() || labels.is_some_and(|labels| labels.len() != values.len()) {
+            return Err(MetricQueryError::OperationFailed {
+                operation: "build_metrics".to_string(),
+                reason: "values, timestamps and labels must have the same length".to_string(),
+            });
+        }
+
+        let now = Utc::now().timestamp();
+        let mut metrics = Vec::with_capacity(values.len());
+        for (index, (&value, &timestamp)) in values.iter().zip(timestamps).enumerate() {
+            let label = labels.and_then(|labels| labels[index].clone());
+
+            if let Some(problem) = self.timestamp_rules.check(timestamp, now) {
+                let offenders = timestamps.iter().filter(|&&t| self.timestamp_rules.check(t, now).is_some()).count();
+                return Err(MetricQueryError::InvalidTimestamp {
+                    index,
+                    timestamp,
+                    reason: problem.as_str().to_string(),
+                    offenders,
+                });
+            }
+            self.check_value_and_label(index, value, label.as_deref())?;
+
+            metrics.push(Metric::new(value, timestamp, label));
+        }
+        Ok(metrics)
+    }
+}
+
+#[cfg(feature = "python")]
+#[pymethods]
+impl MetricBuilder {
+    #[new]
+    #[pyo3(signature = (min_value=None, max_value=None, max_future_seconds=Some(DEFAULT_MAX_FUTURE_SECONDS), allow_zero=false, allow_negative=false, max_label_length=DEFAULT_MAX_LABEL_LENGTH, require_label=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn py_new(
+        min_value: Option<i64>,
+        max_value: Option<i64>,
+        max_future_seconds: Option<i64>,
+        allow_zero: bool,
+        allow_negative: bool,
+        max_label_length: usize,
+        require_label: bool,
+    ) -> Self {
+        Self::new()
+            .with_value_range(min_value, max_value)
+            .with_timestamp_rules(TimestampRules { allow_negative, allow_zero, max_future_seconds })
+            .with_max_label_length(max_label_length)
+            .require_label(require_label)
+    }
+
+    /// Build and validate a single metric
+    #[pyo3(name = "build", signature = (value, timestamp, label=None))]
+    fn py_build(&self, value: i64, timestamp: i64, label: Option<String>) -> MetricQueryResult<Metric> {
+        self.build(value, timestamp, label)
+    }
+
+    /// Build and validate metrics from parallel lists of values, timestamps and (optionally) labels
+    #[pyo3(name = "build_many", signature = (values, timestamps, labels=None))]
+    fn py_build_many(
+        &self,
+        values: Vec<i64>,
+        timestamps: Vec<i64>,
+        labels: Option<Vec<Option<String>>>,
+    ) -> MetricQueryResult<Vec<Metric>> {
+        self.build_many(&values, &timestamps, labels.as_deref())
+    }
+}
+
+/// Check metric timestamps without running a pipeline and return a report of offenders
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(signature = (metrics, max_future_seconds=Some(DEFAULT_MAX_FUTURE_SECONDS), allow_zero=false, allow_negative=false))]
+pub fn check_timestamps(
+    metrics: Vec<Metric>,
+    max_future_seconds: Option<i64>,
+    allow_zero: bool,
+    allow_negative: bool,
+) -> TimestampReport {
+    let rules = TimestampRules { allow_negative, allow_zero, max_future_seconds };
+    validate_timestamps(&metrics, &rules, Utc::now().timestamp())
+}
+
+/// Check a metric stream for unsorted or duplicate timestamps, counter resets,
+/// malformed labels and suspicious gaps, and return a report of every problem
+#[cfg(feature = "python")]
+#[pyfunction]
+#[pyo3(name = "validate_metrics", signature = (metrics, counters=false, max_gap_seconds=None, gap_factor=DEFAULT_GAP_FACTOR, max_label_length=DEFAULT_MAX_LABEL_LENGTH))]
+pub fn py_validate_metrics(
+    metrics: Vec<Metric>,
+    counters: bool,
+    max_gap_seconds: Option<i64>,
+    gap_factor: f64,
+    max_label_length: usize,
+) -> StreamReport {
+    let rules = StreamRules { counter_resets: counters, max_gap_seconds, gap_factor, max_label_length };
+    validate_metrics(&metrics, &rules)
+}
@@ -0,0 +1,90 @@
+#[cfg(feature = "python")]
+use pyo3::prelude::*;
+#[cfg(feature = "python")]
+use std::ffi::CString;
+
+/// A non-fatal issue reported while executing a pipeline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PipelineWarning {
+    /// Index of the step that reported the warning
+    pub step: usize,
+    /// Name of the step that reported the warning
+    pub plugin: String,
+    /// Stable machine-readable warning code
+    pub code: &'static str,
+    /// Human-readable description
+    pub message: String,
+}
+
+impl std::fmt::Display for PipelineWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Step {} ('{}'): {} [{}]", self.step, self.plugin, self.message, self.code)
+    }
+}
+
+/// Collects warnings emitted by transformation steps.
+///
+/// The pipeline points the sink at the current step before running it, so
+/// strategies only have to supply a code and a message.
+#[derive(Debug, Default)]
+pub struct WarningSink {
+    warnings: Vec<PipelineWarning>,
+    step: usize,
+    plugin: String,
+}
+
+impl WarningSink {
+    /// Create an empty sink
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Attribute subsequent warnings to the given step
+    pub fn enter_step(&mut self, step: usize, plugin: String) {
+        self.step = step;
+        self.plugin = plugin;
+    }
+
+    /// Record a warning for the current step
+    pub fn warn(&mut self, code: &'static str, message: impl Into<String>) {
+        let message = message.into();
+        log::info!("step {} ({}): {}: {}", self.step, self.plugin, code, message);
+        self.warnings.push(PipelineWarning {
+            step: self.step,
+            plugin: self.plugin.clone(),
+            code,
+            message,
+        });
+    }
+
+    /// Warnings recorded so far
+    pub fn warnings(&self) -> &[PipelineWarning] {
+        &self.warnings
+    }
+
+    /// Consume the sink and return its warnings
+    pub fn into_warnings(self) -> Vec<PipelineWarning> {
+        self.warnings
+    }
+}
+
+#[cfg(feature = "python")]
+pyo3::create_exception!(
+    metric_query_library,
+    MetricQueryWarning,
+    pyo3::exceptions::PyUserWarning,
+    "Non-fatal issue reported by a pipeline step."
+);
+
+/// Route pipeline warnings to Python's `warnings` module as `MetricQueryWarning`
+#[cfg(feature = "python")]
+pub fn emit_python_warnings(py: Python<'_>, warnings: &[PipelineWarning]) -> PyResult<()> {
+    let category = py.get_type::<MetricQueryWarning>();
+    for warning in warnings {
+        // Messages are built from our own strings; strip NULs rather than fail on them
+        let message = CString::new(warning.to_string().replace('\0', ""))
+            .expect("NUL bytes were removed");
+        PyErr::warn(py, &category, &message, 1)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,144 @@
+//! Browser bindings, enabled with the `wasm` feature.
+//!
+//! JavaScript numbers are doubles, so execution runs the float path: values and
+//! timestamps arrive as `Float64Array`s and results come back the same way.
+//! Timestamps are Unix seconds and are truncated to whole seconds on the way in.
+
+use wasm_bindgen::prelude::*;
+
+use crate::errors::MetricQueryResult;
+use crate::models::FloatMetric;
+use crate::plugin_impls::{create_aggregation, create_filter, create_label_filter, create_time_grouping};
+use crate::transformations::MetricPipeline;
+
+fn js_result<T>(result: MetricQueryResult<T>) -> Result<T, JsError> {
+    result.map_err(|e| JsError::new(&e.to_string()))
+}
+
+/// Pipeline that can be configured once and executed over many downloaded batches
+#[wasm_bindgen(js_name = MetricPipeline)]
+pub struct WasmPipeline {
+    pipeline: MetricPipeline,
+}
+
+#[wasm_bindgen(js_class = MetricPipeline)]
+impl WasmPipeline {
+    /// Create an empty pipeline
+    #[wasm_bindgen(constructor)]
+    #[allow(clippy::new_without_default)]
+    pub fn new() -> Self {
+        Self { pipeline: MetricPipeline::new(Vec::new()) }
+    }
+
+    /// Add a value filter ("gt", "lt", "ge", "le" or "eq"); `value` may be fractional
+    pub fn filter(&mut self, filter_type: &str, value: f64) -> Result<(), JsError> {
+        if value.is_nan() {
+            return Err(JsError::new("filter value must be a number, got NaN"));
+        }
+        let filter = js_result(create_filter(filter_type, value))?;
+        self.pipeline.add_filter(filter);
+        Ok(())
+    }
+
+    /// Keep only metrics with the given label
+    #[wasm_bindgen(js_name = filterByLabel)]
+    pub fn filter_by_label(&mut self, label: String) -> Result<(), JsError> {
+        let filter = js_result(create_label_filter("label_eq", label))?;
+        self.pipeline.add_filter(filter);
+        Ok(())
+    }
+
+    /// Add an aggregation ("sum", "avg", "min" or "max") over the whole batch
+    pub fn aggregate(&mut self, agg_type: &str) -> Result<(), JsError> {
+        let aggregation = js_result(create_aggregation(agg_type))?;
+        self.pipeline.add_aggregation(aggregation);
+        Ok(())
+    }
+
+    /// Group by time ("minute", "hour" or "day") and aggregate each group
+    #[wasm_bindgen(js_name = groupByTime)]
+    pub fn group_by_time(&mut self, time_grouping_type: &str, agg_type: &str) -> Result<(), JsError> {
+        let time_grouping = js_result(create_time_grouping(time_grouping_type))?;
+        let aggregation = js_result(create_aggregation(agg_type))?;
+        self.pipeline.add_time_grouping(time_grouping, aggregation);
+        Ok(())
+    }
+
+    /// Execute the pipeline over a batch; `labels`, when given, must match `values` in length
+    pub fn execute(
+        &self,
+        values: &[f64],
+        timestamps: &[f64],
+        labels: Option<Vec<String>>,
+    ) -> Result<WasmResult, JsError> {
+        if values.len() != timestamps.len() || labels.as_ref().is_some_and(|l| l.len() != values.len()) {
+            return Err(JsError::new("values, timestamps and labels must have the same length"));
+        }
+
+        let mut labels = labels.map(Vec::into_iter);
+        let metrics: Vec<FloatMetric> = values
+            .iter()
+            .zip(timestamps)
+            .map(|(&value, &timestamp)| FloatMetric {
+                value,
+                timestamp: timestamp as i64,
+                label: labels.as_mut().and_then(Iterator::next),
+                unit: None,
+            })
+            .collect();
+
+        let result = js_result(self.pipeline.execute_float_metrics(&metrics))?;
+        Ok(WasmResult::from_metrics(result))
+    }
+}
+
+/// Columns produced by `MetricPipeline.execute`
+#[wasm_bindgen(js_name = PipelineResult)]
+pub struct WasmResult {
+    values: Vec<f64>,
+    timestamps: Vec<f64>,
+    labels: Vec<Option<String>>,
+}
+
+impl WasmResult {
+    fn from_metrics(metrics: Vec<FloatMetric>) -> Self {
+        let mut result = Self {
+            values: Vec::with_capacity(metrics.len()),
+            timestamps: Vec::with_capacity(metrics.len()),
+            labels: Vec::with_capacity(metrics.len()),
+        };
+        for metric in metrics {
+            result.values.push(metric.value);
+            result.timestamps.push(metric.timestamp as f64);
+            result.labels.push(metric.label);
+        }
+        result
+    }
+}
+
+#[wasm_bindgen(js_class = PipelineResult)]
+impl WasmResult {
+    /// Result values; missing values are NaN
+    #[wasm_bindgen(getter)]
+    pub fn values(&self) -> Vec<f64> {
+        self.values.clone()
+    }
+
+    /// Result timestamps in Unix seconds
+    #[wasm_bindgen(getter)]
+    pub fn timestamps(&self) -> Vec<f64> {
+        self.timestamps.clone()
+    }
+
+    /// Result labels; unlabeled metrics are `undefined`
+    #[wasm_bindgen(getter)]
+    pub fn labels(&self) -> Vec<JsValue> {
+        self.labels.iter().map(|label| label.as_deref().map_or(JsValue::UNDEFINED, JsValue::from)).collect()
+    }
+
+    /// Number of result metrics
+    #[wasm_bindgen(getter)]
+    pub fn length(&self) -> usize {
+        self.values.len()
+    }
+}